@@ -0,0 +1,118 @@
+//! Intake acknowledgements written by the model, so the reply to an answer
+//! can pick up what the user actually said instead of cycling through
+//! `CopyKey::ACKS`. The model's text is only used when it is one short
+//! sentence the safety rules have nothing to say about; otherwise, and
+//! offline, the static acknowledgements stand.
+//!
+//! The acknowledgement is not streamed: the safety rules have to see the
+//! whole sentence before any of it is shown. Instead the call gets
+//! `ACK_DEADLINE`, so a slow model costs the intake reply at most that
+//! much before the static acknowledgement is used.
+
+use std::time::Duration;
+
+use crate::model::ChatMessage;
+use crate::safety::SafetyEngine;
+
+use super::{assistant_role, language};
+
+/// Output cap of the acknowledgement call.
+pub const ACK_MAX_TOKENS: u32 = 48;
+/// Longest the intake reply waits for the acknowledgement, including the
+/// wait for a model call slot.
+pub const ACK_DEADLINE: Duration = Duration::from_millis(1500);
+/// Longest acknowledgement kept, in characters, for Chinese and for other
+/// languages.
+const MAX_CHARS_CHINESE: usize = 40;
+const MAX_CHARS_OTHER: usize = 120;
+/// Characters of the answer shown to the model; long answers are cut.
+const ANSWER_EXCERPT_CHARS: usize = 200;
+
+/// Prompt for acknowledging `answer` to `question` of a `scenario` intake
+/// in `locale`.
+pub fn prompt(scenario: &str, question: &str, answer: &str, locale: &str) -> Vec<ChatMessage> {
+    let excerpt = answer
+        .trim()
+        .chars()
+        .take(ANSWER_EXCERPT_CHARS)
+        .collect::<String>();
+    vec![
+        ChatMessage {
+            role: "system".to_owned(),
+            content: format!(
+                "你是{}，正在逐条询问用户的案情。用一句{}简短确认用户刚才的回答，\
+                 可以复述其中的关键信息，语气温和自然。不要提问，不要给法律意见或判断，\
+                 不超过{}个字，只输出这句话。",
+                assistant_role(scenario),
+                language::language_name(locale),
+                max_chars(locale)
+            ),
+        },
+        ChatMessage {
+            role: "user".to_owned(),
+            content: format!("问题：{question}\n回答：{excerpt}"),
+        },
+    ]
+}
+
+/// `raw` model output as an acknowledgement, or `None` when it is not one
+/// short line, asks something, or trips a safety rule.
+pub fn accept(raw: &str, locale: &str, safety: &SafetyEngine) -> Option<String> {
+    let ack = raw
+        .trim()
+        .trim_matches(|ch: char| matches!(ch, '"' | '\'' | '“' | '”' | '「' | '」'))
+        .trim();
+    let well_formed = !ack.is_empty()
+        && !ack.contains('\n')
+        && !ack.contains(['?', '？', '【'])
+        && ack.chars().count() <= max_chars(locale);
+    if !well_formed || !safety.check(ack).issues.is_empty() {
+        return None;
+    }
+    Some(ack.to_owned())
+}
+
+fn max_chars(locale: &str) -> usize {
+    if language::is_chinese(locale) {
+        MAX_CHARS_CHINESE
+    } else {
+        MAX_CHARS_OTHER
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{accept, prompt};
+    use crate::safety::SafetyEngine;
+
+    #[test]
+    fn only_short_safe_statements_are_kept() {
+        let safety = SafetyEngine::default();
+        assert_eq!(
+            accept("“好的，你在深圳上班，已经记下了。”\n", "zh-CN", &safety).as_deref(),
+            Some("好的，你在深圳上班，已经记下了。")
+        );
+        assert_eq!(accept("", "zh-CN", &safety), None);
+        assert_eq!(accept("记下了。你入职多久了？", "zh-CN", &safety), None);
+        assert_eq!(accept("记下了。\n另外……", "zh-CN", &safety), None);
+        assert_eq!(accept("记下了，这个案子你包赢。", "zh-CN", &safety), None);
+        assert_eq!(accept(&"记".repeat(41), "zh-CN", &safety), None);
+        assert!(accept(
+            "Got it, you have been working in Shenzhen since 2021.",
+            "en",
+            &safety
+        )
+        .is_some());
+
+        let messages = prompt("labor", "你在哪里上班？", &"深圳".repeat(200), "zh-CN");
+        assert!(messages[0].content.contains("简体中文"));
+        assert!(messages[0].content.starts_with("你是劳动法咨询助手"));
+        assert_eq!(
+            messages[1].content.chars().count(),
+            "问题：你在哪里上班？\n回答：".chars().count() + 200
+        );
+        let messages = prompt("rental", "房子在哪个城市？", "深圳", "en");
+        assert!(messages[0].content.starts_with("你是房屋租赁法律咨询助手"));
+        assert!(messages[0].content.contains("英文"));
+    }
+}
@@ -0,0 +1,472 @@
+use crate::error::{CoreError, CoreResult};
+use crate::storage::SqliteStorage;
+
+/// Locale used when the configured one has no built-in copy. Built-in copy
+/// exists for `zh-CN` and `en`.
+pub const DEFAULT_LOCALE: &str = "zh-CN";
+
+/// User-facing agent copy. Templates use `{name}` placeholders, filled by
+/// `CopyCatalog::render`; the placeholders each key receives are listed on
+/// the variant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CopyKey {
+    /// `{total}`, `{question}`
+    IntakeIntro,
+    /// `{hint}`, `{current}`, `{total}`, `{question}`
+    IntakeReask,
+    /// `{ack}`, `{current}`, `{total}`, `{question}`
+    IntakeNext,
+    IntakeFallbackQuestion,
+    IntakeFallbackNextQuestion,
+    AckSkipped,
+    Ack1,
+    Ack2,
+    Ack3,
+    Ack4,
+    /// `{count}`, `{report}`
+    ReviewIntercepted,
+    /// `{count}`, `{labels}`
+    FollowupOpenQuestions,
+    /// `{completed}`, `{total}`, `{percent}`
+    ProgressDrafting,
+    /// `{percent}`
+    ProgressReviewing,
+    ProgressDone,
+    /// `{calls}`, `{tokens}`, `{seconds}`
+    DraftEstimate,
+    /// `{current}`, `{total}`, `{question}`
+    IntakeReset,
+    ReportPendingReview,
+    /// Answer of a required intake question left open.
+    FactNotProvided,
+    /// Answer of an optional intake question left open.
+    FactOptional,
+    LegalAnalysisEmpty,
+    /// `{references}`
+    LegalAnalysis,
+    /// `{preview}`, `{file_name}`, `{chars}`, `{digest}`
+    LongTextStub,
+    /// `{changes}`, `{facts}`, `{analysis}`, `{disclaimer}`
+    WhatIfAnswer,
+    /// `{facts}`: labels of the intake answers a footnote points at.
+    FootnoteFacts,
+    /// `{chunks}`: ids of the KB chunks a footnote points at.
+    FootnoteProvisions,
+    /// Appended to a legal claim no retrieved KB chunk backs.
+    CitationHedge,
+    /// `{level}`, `{reason}`: the confidence line under the conclusion.
+    ConfidenceLine,
+    ConfidenceHigh,
+    ConfidenceMedium,
+    ConfidenceLow,
+    /// `{contradictions}`
+    ConfidenceContradicted,
+    /// `{labels}`: required intake questions left open.
+    ConfidenceMissing,
+    ConfidenceNoSources,
+    /// `{count}`, `{outdated}`: current sources closely matching the case.
+    ConfidenceFewSources,
+    /// Filled into `{outdated}` when some sources may be out of date.
+    ConfidenceOutdatedSources,
+    /// `{count}`
+    ConfidenceBacked,
+    /// `{label}`: an answer marked unconfirmed.
+    ConfidenceUnconfirmed,
+    ConfidenceHiredInFuture,
+    ConfidenceHiredAfterDispute,
+    ConfidenceDisputeInFuture,
+    ConfidenceArrearsOverPay,
+}
+
+impl CopyKey {
+    pub const ALL: [Self; 42] = [
+        Self::IntakeIntro,
+        Self::IntakeReask,
+        Self::IntakeNext,
+        Self::IntakeFallbackQuestion,
+        Self::IntakeFallbackNextQuestion,
+        Self::AckSkipped,
+        Self::Ack1,
+        Self::Ack2,
+        Self::Ack3,
+        Self::Ack4,
+        Self::ReviewIntercepted,
+        Self::FollowupOpenQuestions,
+        Self::ProgressDrafting,
+        Self::ProgressReviewing,
+        Self::ProgressDone,
+        Self::DraftEstimate,
+        Self::IntakeReset,
+        Self::ReportPendingReview,
+        Self::FactNotProvided,
+        Self::FactOptional,
+        Self::LegalAnalysisEmpty,
+        Self::LegalAnalysis,
+        Self::LongTextStub,
+        Self::WhatIfAnswer,
+        Self::FootnoteFacts,
+        Self::FootnoteProvisions,
+        Self::CitationHedge,
+        Self::ConfidenceLine,
+        Self::ConfidenceHigh,
+        Self::ConfidenceMedium,
+        Self::ConfidenceLow,
+        Self::ConfidenceContradicted,
+        Self::ConfidenceMissing,
+        Self::ConfidenceNoSources,
+        Self::ConfidenceFewSources,
+        Self::ConfidenceOutdatedSources,
+        Self::ConfidenceBacked,
+        Self::ConfidenceUnconfirmed,
+        Self::ConfidenceHiredInFuture,
+        Self::ConfidenceHiredAfterDispute,
+        Self::ConfidenceDisputeInFuture,
+        Self::ConfidenceArrearsOverPay,
+    ];
+
+    /// Acknowledgements rotated through after each intake answer.
+    pub const ACKS: [Self; 4] = [Self::Ack1, Self::Ack2, Self::Ack3, Self::Ack4];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::IntakeIntro => "intake.intro",
+            Self::IntakeReask => "intake.reask",
+            Self::IntakeNext => "intake.next",
+            Self::IntakeFallbackQuestion => "intake.fallback_question",
+            Self::IntakeFallbackNextQuestion => "intake.fallback_next_question",
+            Self::AckSkipped => "intake.ack.skipped",
+            Self::Ack1 => "intake.ack.1",
+            Self::Ack2 => "intake.ack.2",
+            Self::Ack3 => "intake.ack.3",
+            Self::Ack4 => "intake.ack.4",
+            Self::ReviewIntercepted => "review.intercepted",
+            Self::FollowupOpenQuestions => "followup.open_questions",
+            Self::ProgressDrafting => "progress.drafting",
+            Self::ProgressReviewing => "progress.reviewing",
+            Self::ProgressDone => "progress.done",
+            Self::DraftEstimate => "draft.estimate",
+            Self::IntakeReset => "intake.reset",
+            Self::ReportPendingReview => "review.pending",
+            Self::FactNotProvided => "facts.not_provided",
+            Self::FactOptional => "facts.optional",
+            Self::LegalAnalysisEmpty => "analysis.empty",
+            Self::LegalAnalysis => "analysis.references",
+            Self::LongTextStub => "message.long_text",
+            Self::WhatIfAnswer => "whatif.answer",
+            Self::FootnoteFacts => "footnote.facts",
+            Self::FootnoteProvisions => "footnote.provisions",
+            Self::CitationHedge => "citation.hedge",
+            Self::ConfidenceLine => "confidence.line",
+            Self::ConfidenceHigh => "confidence.high",
+            Self::ConfidenceMedium => "confidence.medium",
+            Self::ConfidenceLow => "confidence.low",
+            Self::ConfidenceContradicted => "confidence.contradicted",
+            Self::ConfidenceMissing => "confidence.missing",
+            Self::ConfidenceNoSources => "confidence.no_sources",
+            Self::ConfidenceFewSources => "confidence.few_sources",
+            Self::ConfidenceOutdatedSources => "confidence.outdated_sources",
+            Self::ConfidenceBacked => "confidence.backed",
+            Self::ConfidenceUnconfirmed => "confidence.unconfirmed",
+            Self::ConfidenceHiredInFuture => "confidence.hired_in_future",
+            Self::ConfidenceHiredAfterDispute => "confidence.hired_after_dispute",
+            Self::ConfidenceDisputeInFuture => "confidence.dispute_in_future",
+            Self::ConfidenceArrearsOverPay => "confidence.arrears_over_pay",
+        }
+    }
+
+    pub fn parse(raw: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|key| key.as_str() == raw)
+    }
+
+    fn builtin(self, locale: &str) -> &'static str {
+        match locale {
+            "en" => self.builtin_en(),
+            _ => self.builtin_zh_cn(),
+        }
+    }
+
+    fn builtin_zh_cn(self) -> &'static str {
+        match self {
+            Self::IntakeIntro => {
+                "我先帮你把案情梳理清楚，接下来会问你 {total} 个小问题。\n你按知道的回答就可以，不确定也可以说“暂不清楚”。\n\n进度：1/{total}\n\n第 1 题：{question}"
+            }
+            Self::IntakeReask => "{hint}\n\n进度：{current}/{total}\n\n第 {current} 题：{question}",
+            Self::IntakeNext => "{ack}\n\n进度：{current}/{total}\n\n下一题：{question}",
+            Self::IntakeFallbackQuestion => "请描述您的情况",
+            Self::IntakeFallbackNextQuestion => "请继续补充信息",
+            Self::AckSkipped => "好的，这题先记为待补充，不影响我们继续往下走。",
+            Self::Ack1 => "收到，这条信息很有帮助。",
+            Self::Ack2 => "明白了，我已经记下这一点。",
+            Self::Ack3 => "好的，信息很关键，继续下一题。",
+            Self::Ack4 => "了解，感谢补充，我们再确认下一项。",
+            Self::ReviewIntercepted => {
+                "【安全审查】\n检测到 {count} 处高风险表述，已自动拦截并改写。\n\n{report}"
+            }
+            Self::FollowupOpenQuestions => {
+                "你之前有{count}个问题没答，补上可以让报告更准确：{labels}"
+            }
+            Self::ProgressDrafting => "正在撰写报告，已完成 {completed}/{total} 部分，约 {percent}%",
+            Self::ProgressReviewing => "正在进行安全审查，约 {percent}%",
+            Self::ProgressDone => "报告已生成",
+            Self::DraftEstimate => {
+                "信息已收集完毕。生成报告预计调用模型 {calls} 次，约 {tokens} 个 token，用时约 {seconds} 秒。确认后我再开始撰写。"
+            }
+            Self::IntakeReset => {
+                "好的，我们从第 {current} 题重新开始，之后的回答已清空。\n\n进度：{current}/{total}\n\n第 {current} 题：{question}"
+            }
+            Self::ReportPendingReview => {
+                "报告已写好，正在由专业人员审核，审核通过后会发送给你。"
+            }
+            Self::FactNotProvided => "未提供",
+            Self::FactOptional => "可补充",
+            Self::LegalAnalysisEmpty => {
+                "当前未检索到足够的法规条文。建议补充案情细节（时间、金额、证据）后再生成一次分析。"
+            }
+            Self::LegalAnalysis => {
+                "结合知识库中的条文信息，现阶段可以先这样理解：\n{references}\n\n以上为通用分析，最终判断仍要结合当地裁审口径和证据完整度。"
+            }
+            Self::LongTextStub => {
+                "{preview}…\n\n【长文本已存为附件】{file_name}，共 {chars} 字。要点：{digest}"
+            }
+            Self::WhatIfAnswer => {
+                "【假设分析】\n以下内容仅为假设情形下的推演，不会修改已记录的案情，也不代表对您实际情况的结论。\n\n【假设变化】\n{changes}\n\n【假设情形下的事实摘要】\n{facts}\n\n【假设情形下的法律分析】\n{analysis}\n\n【对比提示】\n与当前记录的情况相比，上述变化可能影响需要准备的证据和可以主张的请求。如果实际情况确实如此，请更新对应回答后重新生成正式报告。\n\n【免责声明】\n{disclaimer}"
+            }
+            Self::FootnoteFacts => "事实：{facts}",
+            Self::FootnoteProvisions => "条文：{chunks}",
+            Self::CitationHedge => "（此说法未在知识库中找到出处，仅供参考，请向专业人士核实）",
+            Self::ConfidenceLine => "结论把握：{level}。{reason}",
+            Self::ConfidenceHigh => "高",
+            Self::ConfidenceMedium => "中",
+            Self::ConfidenceLow => "低",
+            Self::ConfidenceContradicted => "{contradictions}，结论可能随之变化。",
+            Self::ConfidenceMissing => "尚缺「{labels}」，结论仅供初步参考。",
+            Self::ConfidenceNoSources => "知识库中未检索到可引用的条文，结论缺少依据。",
+            Self::ConfidenceFewSources => {
+                "关键事实齐全，但与案情紧密相关的现行条文只有 {count} 条{outdated}。"
+            }
+            Self::ConfidenceOutdatedSources => "，部分条文可能已失效",
+            Self::ConfidenceBacked => "关键事实齐全，并有 {count} 条现行条文支持。",
+            Self::ConfidenceUnconfirmed => "「{label}」待确认",
+            Self::ConfidenceHiredInFuture => "入职时间晚于今天",
+            Self::ConfidenceHiredAfterDispute => "入职时间晚于争议发生日期",
+            Self::ConfidenceDisputeInFuture => "争议发生日期晚于今天",
+            Self::ConfidenceArrearsOverPay => "拖欠金额超过入职以来的工资总额",
+        }
+    }
+
+    fn builtin_en(self) -> &'static str {
+        match self {
+            Self::IntakeIntro => {
+                "Let's get the facts of your case straight first. I'll ask you {total} short questions.\nAnswer what you know; \"not sure\" is fine too.\n\nProgress: 1/{total}\n\nQuestion 1: {question}"
+            }
+            Self::IntakeReask => {
+                "{hint}\n\nProgress: {current}/{total}\n\nQuestion {current}: {question}"
+            }
+            Self::IntakeNext => "{ack}\n\nProgress: {current}/{total}\n\nNext question: {question}",
+            Self::IntakeFallbackQuestion => "Please describe your situation.",
+            Self::IntakeFallbackNextQuestion => "Please tell me a bit more.",
+            Self::AckSkipped => {
+                "OK, I'll mark this one as to be filled in later. We can keep going."
+            }
+            Self::Ack1 => "Got it, that helps.",
+            Self::Ack2 => "Understood, I've noted that.",
+            Self::Ack3 => "Thanks, that's an important detail. On to the next one.",
+            Self::Ack4 => "Thanks for adding that. Let's check the next item.",
+            Self::ReviewIntercepted => {
+                "[Safety review]\n{count} high-risk statement(s) were intercepted and rewritten.\n\n{report}"
+            }
+            Self::FollowupOpenQuestions => {
+                "You left {count} question(s) unanswered earlier. Answering them makes the report more accurate: {labels}"
+            }
+            Self::ProgressDrafting => {
+                "Writing the report: {completed} of {total} parts done, about {percent}%"
+            }
+            Self::ProgressReviewing => "Running the safety review, about {percent}%",
+            Self::ProgressDone => "The report is ready",
+            Self::DraftEstimate => {
+                "I have what I need. Writing the report should take about {calls} model call(s), roughly {tokens} tokens and {seconds} seconds. I'll start once you confirm."
+            }
+            Self::IntakeReset => {
+                "OK, let's start again from question {current}; the answers after it are cleared.\n\nProgress: {current}/{total}\n\nQuestion {current}: {question}"
+            }
+            Self::ReportPendingReview => {
+                "Your report is written and is being checked by a reviewer. You'll receive it once it's approved."
+            }
+            Self::FactNotProvided => "Not provided",
+            Self::FactOptional => "Can be added later",
+            Self::LegalAnalysisEmpty => {
+                "Not enough statutes were found yet. Add details of the case (dates, amounts, evidence) and run the analysis again."
+            }
+            Self::LegalAnalysis => {
+                "Based on the statutes in the knowledge base, for now it can be understood like this:\n{references}\n\nThis is a general analysis; the final assessment depends on local practice and how complete the evidence is."
+            }
+            Self::LongTextStub => {
+                "{preview}…\n\n[Long text saved as an attachment] {file_name}, {chars} characters. Key points: {digest}"
+            }
+            Self::WhatIfAnswer => {
+                "[What-if analysis]\nThis only explores a hypothetical situation. It does not change the recorded facts and is not a conclusion about your actual case.\n\n[Changes assumed]\n{changes}\n\n[Facts in this scenario]\n{facts}\n\n[Legal analysis in this scenario]\n{analysis}\n\n[Comparison]\nCompared with what is on record, these changes may affect the evidence to prepare and the claims you can make. If this is what actually happened, update the matching answers and generate the report again.\n\n[Disclaimer]\n{disclaimer}"
+            }
+            Self::FootnoteFacts => "Facts: {facts}",
+            Self::FootnoteProvisions => "Provisions: {chunks}",
+            Self::CitationHedge => {
+                " (no source for this was found in the knowledge base; for reference only, please check with a professional)"
+            }
+            Self::ConfidenceLine => "Confidence: {level}. {reason}",
+            Self::ConfidenceHigh => "high",
+            Self::ConfidenceMedium => "medium",
+            Self::ConfidenceLow => "low",
+            Self::ConfidenceContradicted => {
+                "{contradictions}; the conclusion may change with them."
+            }
+            Self::ConfidenceMissing => {
+                "Still missing: {labels}. The conclusion is a first reading only."
+            }
+            Self::ConfidenceNoSources => {
+                "No citable provision was found in the knowledge base; the conclusion lacks a basis."
+            }
+            Self::ConfidenceFewSources => {
+                "The key facts are complete, but only {count} current provision(s) closely match the case{outdated}."
+            }
+            Self::ConfidenceOutdatedSources => ", and some may be out of date",
+            Self::ConfidenceBacked => {
+                "The key facts are complete and {count} current provision(s) support the conclusion."
+            }
+            Self::ConfidenceUnconfirmed => "\"{label}\" is unconfirmed",
+            Self::ConfidenceHiredInFuture => "the hire date is after today",
+            Self::ConfidenceHiredAfterDispute => "the hire date is after the dispute started",
+            Self::ConfidenceDisputeInFuture => "the dispute start date is after today",
+            Self::ConfidenceArrearsOverPay => "the unpaid amount exceeds all pay since the hire date",
+        }
+    }
+}
+
+fn override_key(locale: &str, key: CopyKey) -> String {
+    format!("copy:{locale}:{}", key.as_str())
+}
+
+/// Copy for one locale. Overrides stored with `set_copy_override` win over
+/// the built-in text, which falls back to `DEFAULT_LOCALE` for locales
+/// without built-in copy.
+pub struct CopyCatalog<'a> {
+    storage: &'a SqliteStorage,
+    locale: &'a str,
+}
+
+impl<'a> CopyCatalog<'a> {
+    pub fn new(storage: &'a SqliteStorage, locale: &'a str) -> Self {
+        Self { storage, locale }
+    }
+
+    /// The template for `key`. A failed override lookup is logged and the
+    /// built-in text used, so copy never fails a task.
+    pub fn text(&self, key: CopyKey) -> String {
+        match self.storage.get_setting(&override_key(self.locale, key)) {
+            Ok(Some(text)) => return text,
+            Ok(None) => {}
+            Err(err) => tracing::warn!("copy override lookup failed: {err}"),
+        }
+        key.builtin(self.locale).to_owned()
+    }
+
+    pub fn locale(&self) -> &str {
+        self.locale
+    }
+
+    pub fn render(&self, key: CopyKey, values: &[(&str, &str)]) -> String {
+        values.iter().fold(self.text(key), |text, (name, value)| {
+            text.replace(&format!("{{{name}}}"), value)
+        })
+    }
+}
+
+/// Copy written into reports, what-if answers and other texts that are
+/// composed in Chinese and translated afterwards.
+pub fn report_copy(storage: &SqliteStorage) -> CopyCatalog<'_> {
+    CopyCatalog::new(storage, DEFAULT_LOCALE)
+}
+
+/// Whether `key` has an override for `locale`.
+pub fn has_copy_override(storage: &SqliteStorage, locale: &str, key: &str) -> CoreResult<bool> {
+    let key =
+        CopyKey::parse(key).ok_or_else(|| CoreError::Config(format!("unknown copy key {key}")))?;
+    Ok(storage.get_setting(&override_key(locale, key))?.is_some())
+}
+
+/// Store (or with `None`, remove) the override of `key` for `locale`.
+pub fn set_copy_override(
+    storage: &SqliteStorage,
+    locale: &str,
+    key: &str,
+    text: Option<&str>,
+) -> CoreResult<()> {
+    let key =
+        CopyKey::parse(key).ok_or_else(|| CoreError::Config(format!("unknown copy key {key}")))?;
+    if locale.trim().is_empty() {
+        return Err(CoreError::Config(
+            "copy locale must not be empty".to_owned(),
+        ));
+    }
+    match text {
+        Some(text) => storage.set_setting(&override_key(locale, key), text),
+        None => storage.delete_setting(&override_key(locale, key)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tempfile::TempDir;
+
+    use super::{set_copy_override, CopyCatalog, CopyKey};
+    use crate::clock::SystemClock;
+    use crate::storage::SqliteStorage;
+
+    #[test]
+    fn overrides_win_per_locale_and_fall_back_to_builtin() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let storage = SqliteStorage::new(temp_dir.path().join("core.db"), Arc::new(SystemClock))
+            .expect("storage");
+
+        let zh = CopyCatalog::new(&storage, "zh-CN");
+        assert_eq!(
+            zh.render(
+                CopyKey::IntakeNext,
+                &[
+                    ("ack", "收到"),
+                    ("current", "2"),
+                    ("total", "6"),
+                    ("question", "Q")
+                ]
+            ),
+            "收到\n\n进度：2/6\n\n下一题：Q"
+        );
+        assert_eq!(
+            CopyCatalog::new(&storage, "fr").text(CopyKey::Ack1),
+            zh.text(CopyKey::Ack1)
+        );
+
+        set_copy_override(&storage, "en", "intake.ack.1", Some("Noted!")).expect("override");
+        assert_eq!(
+            CopyCatalog::new(&storage, "en").text(CopyKey::Ack1),
+            "Noted!"
+        );
+        assert_eq!(zh.text(CopyKey::Ack1), "收到，这条信息很有帮助。");
+
+        set_copy_override(&storage, "en", "intake.ack.1", None).expect("clear");
+        assert_eq!(
+            CopyCatalog::new(&storage, "en").text(CopyKey::Ack1),
+            "Got it, that helps."
+        );
+        assert!(set_copy_override(&storage, "en", "intake.nope", Some("x")).is_err());
+    }
+
+    #[test]
+    fn every_key_has_builtin_copy_in_both_locales() {
+        for key in CopyKey::ALL {
+            assert_eq!(CopyKey::parse(key.as_str()), Some(key));
+            assert!(!key.builtin_zh_cn().is_empty());
+            assert!(!key.builtin_en().is_empty());
+        }
+    }
+}
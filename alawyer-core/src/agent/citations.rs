@@ -0,0 +1,218 @@
+//! Review-phase guardrail against made-up law: every line of a report that
+//! makes a legal claim must name a KB source the task actually retrieved.
+//! A line naming a statute or article is only backed by a chunk of that
+//! statute whose text holds the article. Unsourced claims naming a statute
+//! or article are removed, since those are what a model invents; other
+//! unsourced claims are kept with the `citation.hedge` copy appended.
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::retrieval::stopwords::arabic_legal_numbers;
+use crate::retrieval::SearchResult;
+
+/// Sections holding the user's own account or fixed text, not claims.
+const EXEMPT_SECTIONS: [&str; 6] = [
+    "事实摘要",
+    "证据摘要",
+    "引用",
+    "办理地点",
+    "免责声明",
+    "名词解释",
+];
+
+/// Statute names and article numbers: specific enough to be checked.
+static SPECIFIC_CLAIM: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"《[^》]{2,}》|第[0-9０-９零〇一二三四五六七八九十百千两]+条")
+        .expect("specific claim pattern")
+});
+/// Statute names, captured without the brackets.
+static STATUTE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"《([^》]{2,})》").expect("statute pattern"));
+/// Article numbers, in Arabic digits after `arabic_legal_numbers`.
+static ARTICLE: Lazy<Regex> = Lazy::new(|| Regex::new(r"第[0-9]+条").expect("article pattern"));
+/// Appeals to the law in general.
+static GENERAL_CLAIM: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?:法律|法规|司法解释|条例)(?:规定|明确|要求)").expect("general claim pattern")
+});
+
+/// A KB chunk the task retrieved, which a claim may cite by title, path,
+/// chunk id or the statute it is from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifiedSource {
+    pub title: String,
+    pub file_path: String,
+    pub chunk_id: String,
+    /// The chunk's text, for checking the articles a claim names.
+    pub text: String,
+    /// Past the KB's `stale_after_days`.
+    pub outdated: bool,
+    /// The search score it was retrieved with.
+    pub score: f32,
+}
+
+impl From<&SearchResult> for VerifiedSource {
+    fn from(result: &SearchResult) -> Self {
+        Self {
+            title: result.title.trim().to_owned(),
+            file_path: result.file_path.clone(),
+            chunk_id: result.chunk_id.clone(),
+            text: result.snippet.clone(),
+            outdated: result.outdated_warning.is_some(),
+            score: result.score,
+        }
+    }
+}
+
+impl VerifiedSource {
+    /// Whether `line` points at this chunk and every article it names is
+    /// in the chunk's text. A statute name points at the chunk when it is
+    /// (part of) the chunk's title or appears in its path or text.
+    fn cited_in(&self, line: &str) -> bool {
+        let named = (!self.file_path.is_empty() && line.contains(&self.file_path))
+            || (!self.chunk_id.is_empty() && line.contains(&self.chunk_id))
+            || STATUTE.captures_iter(line).any(|statute| {
+                let statute = &statute[1];
+                (!self.title.is_empty() && self.title.contains(statute))
+                    || self.file_path.contains(statute)
+                    || self.text.contains(statute)
+            });
+        if !named {
+            return false;
+        }
+        let line = arabic_legal_numbers(line);
+        let text = arabic_legal_numbers(&self.text);
+        ARTICLE
+            .find_iter(&line)
+            .all(|article| text.contains(article.as_str()))
+    }
+}
+
+/// How the legal claims of a report fared in review.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, uniffi::Record)]
+pub struct CitationCoverage {
+    /// Lines making a legal claim.
+    pub claims: u32,
+    /// Claims naming a verified source.
+    pub cited: u32,
+    pub hedged: u32,
+    pub removed: u32,
+}
+
+/// `report` with unsourced legal claims hedged with `hedge` or removed, and
+/// the counts. Each non-empty line outside `EXEMPT_SECTIONS` and headings
+/// is one paragraph.
+pub fn enforce_citations(
+    report: &str,
+    sources: &[VerifiedSource],
+    hedge: &str,
+) -> (String, CitationCoverage) {
+    let mut coverage = CitationCoverage::default();
+    let mut exempt = false;
+    let mut kept = Vec::new();
+    for line in report.lines() {
+        let trimmed = line.trim();
+        if let Some(title) = trimmed
+            .strip_prefix('【')
+            .and_then(|rest| rest.strip_suffix('】'))
+        {
+            exempt = EXEMPT_SECTIONS.contains(&title);
+            kept.push(line.to_owned());
+            continue;
+        }
+        let specific = SPECIFIC_CLAIM.is_match(trimmed);
+        if exempt || !(specific || GENERAL_CLAIM.is_match(trimmed)) {
+            kept.push(line.to_owned());
+            continue;
+        }
+
+        coverage.claims += 1;
+        if sources.iter().any(|source| source.cited_in(trimmed)) {
+            coverage.cited += 1;
+            kept.push(line.to_owned());
+        } else if specific {
+            coverage.removed += 1;
+        } else {
+            coverage.hedged += 1;
+            kept.push(format!("{}{hedge}", line.trim_end()));
+        }
+    }
+
+    let mut checked = kept.join("\n");
+    if report.ends_with('\n') {
+        checked.push('\n');
+    }
+    (checked, coverage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{enforce_citations, CitationCoverage, VerifiedSource};
+
+    const HEDGE_NOTE: &str = "（仅供参考）";
+
+    #[test]
+    fn unsourced_claims_are_hedged_or_removed() {
+        let sources = [VerifiedSource {
+            title: "劳动仲裁".to_owned(),
+            file_path: "labor/law.md".to_owned(),
+            chunk_id: "labor/law.md#L1-L4".to_owned(),
+            text: "拖欠工资可申请劳动仲裁。".to_owned(),
+            outdated: false,
+            score: 1.0,
+        }];
+        let report = "【事实摘要】\n- 依据《劳动合同法》第30条签了合同\n\n\
+                      【法律分析】\n\
+                      1. 《劳动仲裁》提到：拖欠工资可申请劳动仲裁。\n\
+                      2. 依据《劳动合同法》第八十五条，可要求加付赔偿金。\n\
+                      法律规定用人单位应按时足额支付工资。\n\
+                      建议尽快整理证据。\n\n\
+                      【引用】\n- labor/law.md:1-4\n";
+
+        let (checked, coverage) = enforce_citations(report, &sources, HEDGE_NOTE);
+        assert_eq!(
+            coverage,
+            CitationCoverage {
+                claims: 3,
+                cited: 1,
+                hedged: 1,
+                removed: 1,
+            }
+        );
+        assert_eq!(
+            checked,
+            format!(
+                "【事实摘要】\n- 依据《劳动合同法》第30条签了合同\n\n\
+                 【法律分析】\n\
+                 1. 《劳动仲裁》提到：拖欠工资可申请劳动仲裁。\n\
+                 法律规定用人单位应按时足额支付工资。{HEDGE_NOTE}\n\
+                 建议尽快整理证据。\n\n\
+                 【引用】\n- labor/law.md:1-4\n"
+            )
+        );
+    }
+
+    #[test]
+    fn articles_must_be_in_the_cited_statute() {
+        let sources = [VerifiedSource {
+            title: "中华人民共和国劳动合同法".to_owned(),
+            file_path: "labor/contract.md".to_owned(),
+            chunk_id: "labor/contract.md#L10-L12".to_owned(),
+            text: "第八十二条 用人单位自用工之日起超过一个月不满一年未与劳动者订立书面劳动合同的，应当向劳动者每月支付二倍的工资。".to_owned(),
+            outdated: false,
+            score: 1.0,
+        }];
+        let report = "【法律分析】\n\
+                      依据《劳动合同法》第82条，可主张二倍工资。\n\
+                      依据《劳动合同法》第八十五条，可要求加付赔偿金。\n\
+                      依据《劳动法》第八十二条，可主张二倍工资。\n";
+
+        let (checked, coverage) = enforce_citations(report, &sources, HEDGE_NOTE);
+        assert_eq!((coverage.cited, coverage.removed), (1, 2));
+        assert_eq!(
+            checked,
+            "【法律分析】\n依据《劳动合同法》第82条，可主张二倍工资。\n"
+        );
+    }
+}
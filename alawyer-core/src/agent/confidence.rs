@@ -0,0 +1,449 @@
+//! How firmly the 【先说结论】 section may state its path. The conclusion
+//! text is fixed per tone, so a report built on half the facts and no
+//! sources used to read as sure as one with everything in place; the level
+//! here weighs the intake answers, how closely the KB sources behind the
+//! legal analysis match the case, and answers that disagree with each
+//! other, with the case's dates or with the calendar.
+
+use chrono::{Datelike, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+use crate::storage::CaseMetadata;
+use crate::tools::{is_unknown_answer, IntakeQuestion};
+
+use super::catalog::{CopyCatalog, CopyKey};
+use super::citations::VerifiedSource;
+use super::language::is_chinese;
+use super::CaseFact;
+
+/// Current sources, each matching the case as closely as the best one,
+/// that back the analysis fully; less backing scales the retrieval share
+/// down.
+const STRONG_SOURCE_COUNT: u32 = 3;
+/// Share of the best source's score a source needs to count as closely
+/// matching the case.
+const CLOSE_MATCH_SHARE: f32 = 0.5;
+/// Score lost per contradiction.
+const CONTRADICTION_PENALTY: u32 = 25;
+const HIGH_SCORE: u32 = 75;
+const MEDIUM_SCORE: u32 = 45;
+/// Monthly pay below this is more likely a count (of years, of months)
+/// than a wage, and is not compared with the arrears.
+const MIN_MONTHLY_PAY: f64 = 500.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, uniffi::Enum)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfidenceLevel {
+    High,
+    Medium,
+    Low,
+}
+
+impl ConfidenceLevel {
+    /// Catalog key of its badge text.
+    fn copy_key(self) -> CopyKey {
+        match self {
+            Self::High => CopyKey::ConfidenceHigh,
+            Self::Medium => CopyKey::ConfidenceMedium,
+            Self::Low => CopyKey::ConfidenceLow,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, uniffi::Record)]
+pub struct ConclusionConfidence {
+    pub level: ConfidenceLevel,
+    /// 0–100: half fact completeness, half retrieval strength, less
+    /// `CONTRADICTION_PENALTY` per contradiction.
+    pub score: u32,
+    /// One line on what the level rests on, in the catalog's locale.
+    pub reason: String,
+}
+
+impl ConclusionConfidence {
+    /// The line appended to the conclusion section, in `copy`'s locale.
+    pub fn render(&self, copy: &CopyCatalog) -> String {
+        copy.render(
+            CopyKey::ConfidenceLine,
+            &[
+                ("level", &copy.text(self.level.copy_key())),
+                ("reason", &self.reason),
+            ],
+        )
+    }
+}
+
+/// Confidence in the conclusion drawn from `facts` (answers to
+/// `questions`, in order), the case's `metadata` and the `sources` the
+/// legal analysis rests on, with its reason in `copy`'s locale. Any
+/// contradiction caps the level at medium.
+pub fn assess_conclusion(
+    questions: &[IntakeQuestion],
+    facts: &[CaseFact],
+    metadata: &CaseMetadata,
+    sources: &[VerifiedSource],
+    today: NaiveDate,
+    copy: &CopyCatalog,
+) -> ConclusionConfidence {
+    let chinese = is_chinese(copy.locale());
+    let answered = |fact: &CaseFact| {
+        fact.value
+            .as_deref()
+            .is_some_and(|value| !is_unknown_answer(value))
+    };
+    let required = questions
+        .iter()
+        .zip(facts)
+        .filter(|(question, _)| question.required)
+        .collect::<Vec<_>>();
+    let missing = required
+        .iter()
+        .filter(|(_, fact)| !answered(fact))
+        .map(|(question, _)| question.label_in(copy.locale()))
+        .collect::<Vec<_>>();
+    let completeness = if required.is_empty() {
+        100
+    } else {
+        (required.len() - missing.len()) as u32 * 100 / required.len() as u32
+    };
+
+    // Each source counts by its score against the best one, so a tail of
+    // weak matches does not read as backing; an outdated one counts half.
+    let best = sources
+        .iter()
+        .map(|source| source.score)
+        .fold(0.0_f32, f32::max);
+    let relevance = |source: &VerifiedSource| {
+        if best > 0.0 {
+            (source.score / best).clamp(0.0, 1.0)
+        } else {
+            1.0
+        }
+    };
+    let backing = sources
+        .iter()
+        .map(|source| relevance(source) * if source.outdated { 0.5 } else { 1.0 })
+        .sum::<f32>()
+        .min(STRONG_SOURCE_COUNT as f32);
+    let retrieval = (backing * 100.0 / STRONG_SOURCE_COUNT as f32).round() as u32;
+    let close = sources
+        .iter()
+        .filter(|source| !source.outdated && relevance(source) >= CLOSE_MATCH_SHARE)
+        .count() as u32;
+    let outdated = sources.iter().any(|source| source.outdated);
+
+    let contradictions = contradictions(questions, facts, metadata, today, copy);
+    let score = (completeness / 2 + retrieval / 2)
+        .saturating_sub(contradictions.len() as u32 * CONTRADICTION_PENALTY);
+    let level = if score >= HIGH_SCORE && contradictions.is_empty() {
+        ConfidenceLevel::High
+    } else if score >= MEDIUM_SCORE {
+        ConfidenceLevel::Medium
+    } else {
+        ConfidenceLevel::Low
+    };
+
+    let reason = if !contradictions.is_empty() {
+        copy.render(
+            CopyKey::ConfidenceContradicted,
+            &[(
+                "contradictions",
+                &contradictions.join(if chinese { "；" } else { "; " }),
+            )],
+        )
+    } else if !missing.is_empty() {
+        copy.render(
+            CopyKey::ConfidenceMissing,
+            &[("labels", &missing.join(if chinese { "」「" } else { ", " }))],
+        )
+    } else if sources.is_empty() {
+        copy.text(CopyKey::ConfidenceNoSources)
+    } else if close < STRONG_SOURCE_COUNT {
+        let outdated = if outdated {
+            copy.text(CopyKey::ConfidenceOutdatedSources)
+        } else {
+            String::new()
+        };
+        copy.render(
+            CopyKey::ConfidenceFewSources,
+            &[("count", &close.to_string()), ("outdated", &outdated)],
+        )
+    } else {
+        copy.render(CopyKey::ConfidenceBacked, &[("count", &close.to_string())])
+    };
+
+    ConclusionConfidence {
+        level,
+        score,
+        reason,
+    }
+}
+
+/// Answers that cannot all be right: ones marked unconfirmed after failing
+/// validation or a merge, a hire or dispute date after `today`, a hire
+/// date after the dispute started, and arrears above the pay for every
+/// month since the hire date.
+fn contradictions(
+    questions: &[IntakeQuestion],
+    facts: &[CaseFact],
+    metadata: &CaseMetadata,
+    today: NaiveDate,
+    copy: &CopyCatalog,
+) -> Vec<String> {
+    let mut found = questions
+        .iter()
+        .zip(facts)
+        .filter(|(_, fact)| fact.value.is_some() && !fact.confirmed)
+        .map(|(question, _)| {
+            copy.render(
+                CopyKey::ConfidenceUnconfirmed,
+                &[("label", &question.label_in(copy.locale()))],
+            )
+        })
+        .collect::<Vec<_>>();
+    let normalized = |field: &str| {
+        facts
+            .iter()
+            .find(|fact| fact.field == field && fact.confirmed)
+            .and_then(|fact| fact.normalized_value.as_deref())
+    };
+    let amount = |field: &str| normalized(field).and_then(|value| value.parse::<f64>().ok());
+    // `YYYY` or `YYYY-MM`; a year alone is read as its first month, the
+    // reading least likely to contradict anything.
+    let hired = normalized("hire_date").and_then(|hired| {
+        let (year, month) = hired.split_once('-').unwrap_or((hired, "01"));
+        NaiveDate::from_ymd_opt(year.parse().ok()?, month.parse().ok()?, 1)
+    });
+    let dispute = metadata
+        .dispute_started_on
+        .as_deref()
+        .and_then(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok());
+    let month_start = |date: NaiveDate| date.with_day(1).unwrap_or(date);
+
+    if hired.is_some_and(|hired| hired > month_start(today)) {
+        found.push(copy.text(CopyKey::ConfidenceHiredInFuture));
+    }
+    if dispute.is_some_and(|dispute| dispute > today) {
+        found.push(copy.text(CopyKey::ConfidenceDisputeInFuture));
+    }
+    if let (Some(hired), Some(dispute)) = (hired, dispute) {
+        if hired > month_start(dispute) {
+            found.push(copy.text(CopyKey::ConfidenceHiredAfterDispute));
+        }
+    }
+    if let (Some(hired), Some(pay), Some(arrears)) =
+        (hired, amount("job_salary"), amount("arrears"))
+    {
+        let end = dispute.unwrap_or(today).min(today);
+        let months =
+            (end.year() - hired.year()) * 12 + end.month() as i32 - hired.month() as i32 + 1;
+        if pay >= MIN_MONTHLY_PAY && months > 0 && arrears > pay * f64::from(months) {
+            found.push(copy.text(CopyKey::ConfidenceArrearsOverPay));
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::NaiveDate;
+    use tempfile::TempDir;
+
+    use super::{assess_conclusion, ConfidenceLevel};
+    use crate::agent::catalog::CopyCatalog;
+    use crate::agent::citations::VerifiedSource;
+    use crate::agent::CaseFact;
+    use crate::clock::SystemClock;
+    use crate::storage::{CaseMetadata, SqliteStorage};
+    use crate::tools::intake_questions_for_scenario;
+
+    fn facts(answers: &[(&str, &str)]) -> Vec<CaseFact> {
+        intake_questions_for_scenario("labor")
+            .into_iter()
+            .map(|question| {
+                let value = answers
+                    .iter()
+                    .find(|(field, _)| *field == question.field)
+                    .map(|(_, value)| (*value).to_owned());
+                CaseFact {
+                    normalized_value: value.as_deref().and_then(|value| {
+                        question
+                            .validator
+                            .as_ref()
+                            .and_then(|validator| validator.normalize(value))
+                    }),
+                    field: question.field,
+                    label: question.label,
+                    value,
+                    confirmed: true,
+                    source_message_id: None,
+                }
+            })
+            .collect()
+    }
+
+    fn source(outdated: bool, score: f32) -> VerifiedSource {
+        VerifiedSource {
+            title: "劳动合同法".to_owned(),
+            file_path: "labor/law.md".to_owned(),
+            chunk_id: "labor/law.md#L1-L20".to_owned(),
+            text: "用人单位应当按时足额支付劳动报酬。".to_owned(),
+            outdated,
+            score,
+        }
+    }
+
+    fn complete() -> Vec<CaseFact> {
+        facts(&[
+            ("region", "广东深圳"),
+            ("hire_date", "2024年3月"),
+            ("job_salary", "月薪8000元"),
+            ("goal", "补发工资"),
+        ])
+    }
+
+    #[test]
+    fn level_follows_facts_sources_and_contradictions() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let storage = SqliteStorage::new(temp_dir.path().join("core.db"), Arc::new(SystemClock))
+            .expect("storage");
+        let zh = CopyCatalog::new(&storage, "zh-CN");
+        let questions = intake_questions_for_scenario("labor");
+        let today = NaiveDate::from_ymd_opt(2026, 5, 10).expect("date");
+        let none = CaseMetadata::default();
+        let sources = [source(false, 2.0), source(false, 2.0), source(false, 1.8)];
+
+        let high = assess_conclusion(&questions, &complete(), &none, &sources, today, &zh);
+        assert_eq!((high.level, high.score), (ConfidenceLevel::High, 98));
+        assert_eq!(
+            high.render(&zh),
+            "结论把握：高。关键事实齐全，并有 3 条现行条文支持。"
+        );
+        let en = CopyCatalog::new(&storage, "en");
+        let high_en = assess_conclusion(&questions, &complete(), &none, &sources, today, &en);
+        assert_eq!(
+            high_en.render(&en),
+            "Confidence: high. The key facts are complete and 3 current provision(s) support the conclusion."
+        );
+
+        let thin = assess_conclusion(
+            &questions,
+            &complete(),
+            &none,
+            &[source(true, 1.0)],
+            today,
+            &zh,
+        );
+        assert_eq!(thin.level, ConfidenceLevel::Medium);
+        assert!(thin.reason.contains("部分条文可能已失效"));
+
+        // One close match and a tail of weak ones is thin backing.
+        let weak_tail = [source(false, 10.0), source(false, 1.0), source(false, 1.0)];
+        let weak = assess_conclusion(&questions, &complete(), &none, &weak_tail, today, &zh);
+        assert_eq!((weak.level, weak.score), (ConfidenceLevel::Medium, 70));
+        assert_eq!(
+            weak.reason,
+            "关键事实齐全，但与案情紧密相关的现行条文只有 1 条。"
+        );
+
+        let sparse = facts(&[("region", "深圳"), ("hire_date", "不清楚")]);
+        let low = assess_conclusion(&questions, &sparse, &none, &[], today, &zh);
+        assert_eq!(low.level, ConfidenceLevel::Low);
+        assert!(low
+            .reason
+            .starts_with("尚缺「入职时间与合同」「岗位与月薪」「期望结果」"));
+
+        let mut future = facts(&[
+            ("region", "广东深圳"),
+            ("hire_date", "2027年1月"),
+            ("job_salary", "月薪8000元"),
+            ("goal", "补发工资"),
+        ]);
+        future[2].confirmed = false;
+        let disputed = assess_conclusion(&questions, &future, &none, &sources, today, &zh);
+        assert_eq!(
+            (disputed.level, disputed.score),
+            (ConfidenceLevel::Medium, 48)
+        );
+        assert_eq!(
+            disputed.reason,
+            "「岗位与月薪」待确认；入职时间晚于今天，结论可能随之变化。"
+        );
+    }
+
+    #[test]
+    fn dates_and_amounts_that_disagree_are_contradictions() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let storage = SqliteStorage::new(temp_dir.path().join("core.db"), Arc::new(SystemClock))
+            .expect("storage");
+        let zh = CopyCatalog::new(&storage, "zh-CN");
+        let questions = intake_questions_for_scenario("labor");
+        let today = NaiveDate::from_ymd_opt(2026, 5, 10).expect("date");
+        let sources = [source(false, 1.0), source(false, 1.0), source(false, 1.0)];
+        let started = |date: &str| CaseMetadata {
+            dispute_started_on: Some(date.to_owned()),
+            ..CaseMetadata::default()
+        };
+
+        let before_hire = assess_conclusion(
+            &questions,
+            &complete(),
+            &started("2023-12-01"),
+            &sources,
+            today,
+            &zh,
+        );
+        assert_eq!(before_hire.level, ConfidenceLevel::Medium);
+        assert_eq!(
+            before_hire.reason,
+            "入职时间晚于争议发生日期，结论可能随之变化。"
+        );
+        let ahead = assess_conclusion(
+            &questions,
+            &complete(),
+            &started("2026-09-01"),
+            &sources,
+            today,
+            &zh,
+        );
+        assert_eq!(ahead.reason, "争议发生日期晚于今天，结论可能随之变化。");
+        let same_month = assess_conclusion(
+            &questions,
+            &complete(),
+            &started("2024-03-20"),
+            &sources,
+            today,
+            &zh,
+        );
+        assert_eq!(same_month.level, ConfidenceLevel::High);
+
+        // Three months of 8000 at most, from March to May 2024.
+        let mut owed = complete();
+        owed[3].value = Some("拖欠3万元".to_owned());
+        owed[3].normalized_value = Some("30000".to_owned());
+        let over = assess_conclusion(
+            &questions,
+            &owed,
+            &started("2024-05-31"),
+            &sources,
+            today,
+            &zh,
+        );
+        assert_eq!(
+            over.reason,
+            "拖欠金额超过入职以来的工资总额，结论可能随之变化。"
+        );
+        owed[3].normalized_value = Some("24000".to_owned());
+        let within = assess_conclusion(
+            &questions,
+            &owed,
+            &started("2024-05-31"),
+            &sources,
+            today,
+            &zh,
+        );
+        assert_eq!(within.level, ConfidenceLevel::High);
+    }
+}
@@ -0,0 +1,247 @@
+//! What drafting a report is expected to cost, worked out after intake so
+//! users on metered models know the model calls, tokens and time before
+//! the report is written. Sessions with `CONFIRM_DRAFT_PREFERENCE` on stop
+//! at the estimate until `Core::confirm_draft`.
+
+use crate::error::CoreResult;
+use crate::model::{estimate_tokens, ModelPhase, ModelPhaseUsage};
+use crate::storage::SqliteStorage;
+
+use super::timing::{PhaseTimingStats, TimedPhase};
+use super::{session_preference, CONFIRM_DRAFT_PREFERENCE};
+
+/// Tokens of the templated report the style pass and the translation
+/// rewrite: the legal context budget plus the fixed sections.
+const REPORT_TOKENS: u32 = 2_500;
+/// System prompt of a drafting call.
+const INSTRUCTION_TOKENS: u32 = 80;
+/// One-sentence evidence summary.
+const EVIDENCE_SUMMARY_TOKENS: u32 = 60;
+/// Translated retrieval query, both ways.
+const QUERY_TRANSLATION_TOKENS: u32 = 120;
+/// Without history: time to the first token of a call, and output speed.
+const CALL_LATENCY_MS: u64 = 1_500;
+const COMPLETION_TOKENS_PER_SEC: u64 = 25;
+/// Without history: retrieval, review and storage around the model calls.
+const LOCAL_DRAFT_MS: u64 = 500;
+
+/// `session:{id}:draft_confirmation` values.
+const AWAITING: &str = "awaiting";
+const CONFIRMED: &str = "confirmed";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DraftEstimate {
+    pub model_calls: u32,
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub duration_ms: u64,
+    /// Token or time figures come from earlier drafts rather than only
+    /// the template.
+    pub from_history: bool,
+}
+
+/// The model calls drafting will make for one session.
+#[derive(Debug, Clone, Default)]
+pub struct DraftPlan {
+    pub model_configured: bool,
+    /// Extracted text of attachments still to be summarized.
+    pub unsummarized_evidence: Vec<String>,
+    /// The session's tone asks for a style pass.
+    pub style_pass: bool,
+    /// The session is not in Chinese: the retrieval query and the report
+    /// are translated.
+    pub translated: bool,
+}
+
+/// Calls as the template makes them, with per-call token averages of
+/// earlier drafts in `usage` and the phase times in `timings` replacing the
+/// template's guesses where there are any.
+pub fn estimate_draft(
+    plan: &DraftPlan,
+    usage: &[ModelPhaseUsage],
+    timings: &[PhaseTimingStats],
+) -> DraftEstimate {
+    let mut calls = Vec::new();
+    if plan.model_configured {
+        for text in &plan.unsummarized_evidence {
+            calls.push((
+                INSTRUCTION_TOKENS + estimate_tokens(text),
+                EVIDENCE_SUMMARY_TOKENS,
+            ));
+        }
+        if plan.translated {
+            calls.push((QUERY_TRANSLATION_TOKENS, QUERY_TRANSLATION_TOKENS));
+        }
+        if plan.style_pass {
+            calls.push((INSTRUCTION_TOKENS + REPORT_TOKENS, REPORT_TOKENS));
+        }
+        if plan.translated {
+            calls.push((INSTRUCTION_TOKENS + REPORT_TOKENS, REPORT_TOKENS));
+        }
+    }
+    let model_calls = calls.len() as u32;
+
+    let (mut prompt_tokens, mut completion_tokens) =
+        calls.iter().fold((0, 0), |(prompt, completion), call| {
+            (prompt + call.0, completion + call.1)
+        });
+    let history = average_call(usage);
+    if let Some((prompt, completion)) = history.filter(|_| model_calls > 0) {
+        prompt_tokens = prompt * model_calls;
+        completion_tokens = completion * model_calls;
+    }
+
+    let timed = timings
+        .iter()
+        .filter(|stats| {
+            stats.tasks > 0
+                && [TimedPhase::Draft, TimedPhase::Review]
+                    .iter()
+                    .any(|phase| phase.as_str() == stats.phase)
+        })
+        .map(|stats| stats.average_ms)
+        .collect::<Vec<_>>();
+    let duration_ms = if timed.is_empty() {
+        LOCAL_DRAFT_MS
+            + u64::from(model_calls) * CALL_LATENCY_MS
+            + u64::from(completion_tokens) * 1_000 / COMPLETION_TOKENS_PER_SEC
+    } else {
+        timed.iter().sum()
+    };
+
+    DraftEstimate {
+        model_calls,
+        prompt_tokens,
+        completion_tokens,
+        duration_ms,
+        from_history: (history.is_some() && model_calls > 0) || !timed.is_empty(),
+    }
+}
+
+/// Prompt tokens per draft or review call so far, and completion tokens
+/// per successful one; failed calls sent a prompt but got nothing back.
+fn average_call(usage: &[ModelPhaseUsage]) -> Option<(u32, u32)> {
+    let drafting = [ModelPhase::Draft.as_str(), ModelPhase::Review.as_str()];
+    let (calls, answered, prompt, completion) = usage
+        .iter()
+        .filter(|usage| drafting.contains(&usage.phase.as_str()))
+        .fold(
+            (0, 0, 0, 0),
+            |(calls, answered, prompt, completion), usage| {
+                (
+                    calls + usage.calls,
+                    answered + usage.calls - usage.failures,
+                    prompt + usage.prompt_tokens,
+                    completion + usage.completion_tokens,
+                )
+            },
+        );
+    if answered == 0 {
+        return None;
+    }
+    Some(((prompt / calls) as u32, (completion / answered) as u32))
+}
+
+fn confirmation_key(session_id: &str) -> String {
+    format!("session:{session_id}:draft_confirmation")
+}
+
+pub fn confirmation_required(storage: &SqliteStorage, session_id: &str) -> CoreResult<bool> {
+    Ok(session_preference(storage, session_id, CONFIRM_DRAFT_PREFERENCE)?.as_deref() == Some("on"))
+}
+
+pub fn await_confirmation(storage: &SqliteStorage, session_id: &str) -> CoreResult<()> {
+    storage.set_setting(&confirmation_key(session_id), AWAITING)
+}
+
+/// Mark the estimate the session is stopped at as accepted. `false` when
+/// it is not waiting for one.
+pub fn confirm(storage: &SqliteStorage, session_id: &str) -> CoreResult<bool> {
+    let key = confirmation_key(session_id);
+    if storage.get_setting(&key)?.as_deref() != Some(AWAITING) {
+        return Ok(false);
+    }
+    storage.set_setting(&key, CONFIRMED)?;
+    Ok(true)
+}
+
+/// Forget an estimate waiting for confirmation or accepted, e.g. when
+/// intake starts over.
+pub fn cancel_confirmation(storage: &SqliteStorage, session_id: &str) -> CoreResult<()> {
+    storage.delete_setting(&confirmation_key(session_id))
+}
+
+/// Whether the user accepted the estimate, consuming the acceptance so the
+/// next draft asks again.
+pub fn take_confirmation(storage: &SqliteStorage, session_id: &str) -> CoreResult<bool> {
+    let key = confirmation_key(session_id);
+    let confirmed = storage.get_setting(&key)?.as_deref() == Some(CONFIRMED);
+    if confirmed {
+        storage.delete_setting(&key)?;
+    }
+    Ok(confirmed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{estimate_draft, DraftPlan};
+    use crate::agent::timing::PhaseTimingStats;
+    use crate::model::ModelPhaseUsage;
+
+    #[test]
+    fn template_counts_calls_and_history_replaces_the_guesses() {
+        let offline = estimate_draft(
+            &DraftPlan {
+                unsummarized_evidence: vec!["工资条".to_owned()],
+                style_pass: true,
+                ..DraftPlan::default()
+            },
+            &[],
+            &[],
+        );
+        assert_eq!((offline.model_calls, offline.completion_tokens), (0, 0));
+
+        let plan = DraftPlan {
+            model_configured: true,
+            unsummarized_evidence: vec!["2024年3月工资条，应发8000元".to_owned()],
+            style_pass: true,
+            translated: true,
+        };
+        let template = estimate_draft(&plan, &[], &[]);
+        assert_eq!(template.model_calls, 4);
+        assert!(template.completion_tokens > 2 * 2_500);
+        assert!(template.duration_ms > 4 * 1_500);
+        assert!(!template.from_history);
+
+        let usage = ModelPhaseUsage {
+            phase: "draft".to_owned(),
+            model_name: "m".to_owned(),
+            calls: 3,
+            failures: 1,
+            prompt_tokens: 3_000,
+            completion_tokens: 1_000,
+        };
+        let timings = [
+            PhaseTimingStats {
+                phase: "draft".to_owned(),
+                tasks: 2,
+                average_ms: 9_000,
+            },
+            PhaseTimingStats {
+                phase: "review".to_owned(),
+                tasks: 2,
+                average_ms: 1_000,
+            },
+        ];
+        let history = estimate_draft(&plan, &[usage], &timings);
+        assert_eq!(
+            (
+                history.prompt_tokens,
+                history.completion_tokens,
+                history.duration_ms
+            ),
+            (4_000, 2_000, 10_000)
+        );
+        assert!(history.from_history);
+    }
+}
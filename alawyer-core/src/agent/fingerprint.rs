@@ -0,0 +1,214 @@
+//! Tamper evidence for exported reports. Every export ends in a footer with
+//! the SHA-256 of the rest of the file and what the report was generated
+//! from; the footer is also stored with the session, so a copy brought
+//! back in a dispute can be checked against what the app actually issued.
+//!
+//! The hash covers the export exactly as written, footer lines excluded,
+//! with `\r\n` read as `\n` so a copy saved on Windows still matches.
+
+use chrono::DateTime;
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{CoreError, CoreResult};
+use crate::region::UserTimeZone;
+use crate::storage::SqliteStorage;
+
+use super::share::{escape_html, ShareFormat};
+use super::ReportKbSnapshot;
+
+const FOOTER_START: &str = "—— 报告指纹 ——";
+const FOOTER_END: &str = "—— 指纹结束 ——";
+const HASH_LABEL: &str = "SHA-256：";
+const SESSION_LABEL: &str = "会话：";
+
+/// What an export was generated from, as issued.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, uniffi::Record)]
+pub struct ReportFingerprint {
+    /// Hex SHA-256 of the export without its footer.
+    pub content_sha256: String,
+    pub session_id: String,
+    /// The report message exported.
+    pub message_id: String,
+    pub generated_at: i64,
+    pub exported_at: i64,
+    /// `KnowledgeInfo::updated_at` when the report was generated.
+    pub kb_updated_at: i64,
+    pub kb_file_count: u32,
+    /// `None` for reports built from templates.
+    pub model: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, uniffi::Enum)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportIntegrity {
+    /// The content matches its footer and the footer was issued here.
+    Intact,
+    /// The content no longer matches its footer.
+    Modified,
+    /// The content matches its footer, but no such export was issued, or
+    /// its session has been deleted.
+    Unrecognized,
+    /// No footer, or one too damaged to read.
+    Missing,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct ReportVerification {
+    pub integrity: ReportIntegrity,
+    /// The fingerprint as issued, when the footer names one on record.
+    pub fingerprint: Option<ReportFingerprint>,
+    /// The session has a newer report than the one exported, or no longer
+    /// has it.
+    pub superseded: bool,
+}
+
+fn fingerprint_key(session_id: &str, sha256: &str) -> String {
+    format!("session:{session_id}:fingerprint:{sha256}")
+}
+
+/// `content` with its footer appended, and the fingerprint stored for it.
+pub fn stamp_report(
+    storage: &SqliteStorage,
+    content: &str,
+    format: ShareFormat,
+    session_id: &str,
+    snapshot: &ReportKbSnapshot,
+    exported_at: i64,
+    zone: &UserTimeZone,
+) -> CoreResult<String> {
+    // The parts around the footer; the hash covers both.
+    let (before, after) = match format {
+        ShareFormat::Text => (format!("{content}\n"), String::new()),
+        ShareFormat::Markdown => (format!("{content}\n```\n"), "```\n".to_owned()),
+        ShareFormat::Html => {
+            let body_end = content.rfind("</body>").unwrap_or(content.len());
+            (
+                format!(
+                    "{}<pre class=\"report-fingerprint\">\n",
+                    &content[..body_end]
+                ),
+                format!("</pre>\n{}", &content[body_end..]),
+            )
+        }
+    };
+    let fingerprint = ReportFingerprint {
+        content_sha256: content_hash(&format!("{before}{after}")),
+        session_id: session_id.to_owned(),
+        message_id: snapshot.message_id.clone(),
+        generated_at: snapshot.generated_at,
+        exported_at,
+        kb_updated_at: snapshot.kb_updated_at,
+        kb_file_count: snapshot.kb_file_count,
+        model: snapshot.model.clone(),
+    };
+    let raw = serde_json::to_string(&fingerprint)
+        .map_err(|e| CoreError::Unknown(format!("serialize report fingerprint failed: {e}")))?;
+    storage.set_setting(
+        &fingerprint_key(session_id, &fingerprint.content_sha256),
+        &raw,
+    )?;
+
+    let mut footer = footer(&fingerprint, zone);
+    if format == ShareFormat::Html {
+        footer = escape_html(&footer);
+    }
+    Ok(format!("{before}{footer}{after}"))
+}
+
+/// Check `text`, an export, against its footer and the fingerprints on
+/// record. `superseded` is left for the caller, which knows the session's
+/// reports.
+pub fn verify_report(storage: &SqliteStorage, text: &str) -> CoreResult<ReportVerification> {
+    let text = text.replace("\r\n", "\n");
+    let unverified = |integrity| ReportVerification {
+        integrity,
+        fingerprint: None,
+        superseded: false,
+    };
+    let Some((rest, footer)) = split_footer(&text) else {
+        return Ok(unverified(ReportIntegrity::Missing));
+    };
+    let field = |label: &str| {
+        footer
+            .lines()
+            .find_map(|line| line.trim().strip_prefix(label))
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+    };
+    let (Some(claimed), Some(session_id)) = (field(HASH_LABEL), field(SESSION_LABEL)) else {
+        return Ok(unverified(ReportIntegrity::Missing));
+    };
+
+    let fingerprint = storage
+        .get_setting(&fingerprint_key(session_id, claimed))?
+        .and_then(|raw| serde_json::from_str::<ReportFingerprint>(&raw).ok());
+    let integrity = if content_hash(&rest) != claimed {
+        ReportIntegrity::Modified
+    } else if fingerprint.is_some() {
+        ReportIntegrity::Intact
+    } else {
+        ReportIntegrity::Unrecognized
+    };
+    Ok(ReportVerification {
+        integrity,
+        fingerprint,
+        superseded: false,
+    })
+}
+
+fn footer(fingerprint: &ReportFingerprint, zone: &UserTimeZone) -> String {
+    let time = |timestamp: i64| {
+        DateTime::from_timestamp(timestamp, 0)
+            .unwrap_or_default()
+            .with_timezone(&zone.offset())
+            .format("%Y-%m-%d %H:%M")
+            .to_string()
+    };
+    format!(
+        "{FOOTER_START}\n\
+         {HASH_LABEL}{}\n\
+         {SESSION_LABEL}{}\n\
+         报告生成：{}（{}）\n\
+         知识库版本：{}，{} 个文件\n\
+         模型：{}\n\
+         导出时间：{}\n\
+         导出后如有改动，核验将不能通过。\n\
+         {FOOTER_END}\n",
+        fingerprint.content_sha256,
+        fingerprint.session_id,
+        time(fingerprint.generated_at),
+        zone.label(),
+        time(fingerprint.kb_updated_at),
+        fingerprint.kb_file_count,
+        fingerprint
+            .model
+            .as_deref()
+            .unwrap_or("未使用模型（模板生成）"),
+        time(fingerprint.exported_at),
+    )
+}
+
+/// `text` without its last footer, and the footer. The line break after
+/// the end marker belongs to the footer.
+fn split_footer(text: &str) -> Option<(String, &str)> {
+    let start = text.rfind(FOOTER_START)?;
+    let end = start + text[start..].find(FOOTER_END)? + FOOTER_END.len();
+    let end = if text[end..].starts_with('\n') {
+        end + 1
+    } else {
+        end
+    };
+    Some((
+        format!("{}{}", &text[..start], &text[end..]),
+        &text[start..end],
+    ))
+}
+
+fn content_hash(text: &str) -> String {
+    digest(&SHA256, text.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
@@ -0,0 +1,247 @@
+//! Language of a session. Migrant workers often write in English, or in
+//! English with Chinese terms mixed in; the session's language is detected
+//! from its first message and picks the intake copy, question wording and
+//! report translation. The KB stays Chinese, so retrieval queries are
+//! translated the other way.
+
+use crate::error::CoreResult;
+use crate::storage::SqliteStorage;
+
+use super::{session_preference, set_session_preference};
+
+/// Session preference holding the language, `zh-CN` or `en`. Detected
+/// once; the host may set it to correct the guess.
+pub const LANGUAGE_PREFERENCE: &str = "language";
+pub const CHINESE: &str = "zh-CN";
+pub const ENGLISH: &str = "en";
+pub const LANGUAGES: [&str; 2] = [CHINESE, ENGLISH];
+
+/// A Han character carries about as much as a short English word, so it
+/// weighs this many Latin letters when scripts are mixed.
+const HAN_WEIGHT: usize = 3;
+
+/// English words or word stems, and the Chinese KB terms they stand for,
+/// per scenario. Used to search the KB when no model is configured to
+/// translate.
+const LABOR_TERMS: &[(&str, &str)] = &[
+    ("unpaid", "拖欠工资"),
+    ("owe", "拖欠工资"),
+    ("arrears", "拖欠工资"),
+    ("wage", "工资 劳动报酬"),
+    ("salary", "工资 劳动报酬"),
+    ("overtime", "加班费"),
+    ("contract", "劳动合同"),
+    ("fired", "解除劳动合同 辞退"),
+    ("dismiss", "解除劳动合同 辞退"),
+    ("laid off", "解除劳动合同 裁员"),
+    ("terminat", "解除劳动合同"),
+    ("compensation", "经济补偿 赔偿金"),
+    ("arbitration", "劳动仲裁"),
+    ("evidence", "证据"),
+    ("social insurance", "社会保险"),
+    ("injur", "工伤"),
+    ("deadline", "仲裁时效"),
+    ("resign", "离职 解除劳动合同"),
+];
+
+const RENTAL_TERMS: &[(&str, &str)] = &[
+    ("rent", "租金"),
+    ("deposit", "押金"),
+    ("landlord", "出租人 房东"),
+    ("tenant", "承租人"),
+    ("lease", "租赁合同"),
+    ("contract", "租赁合同"),
+    ("evict", "解除租赁合同"),
+    ("terminat", "解除租赁合同"),
+    ("repair", "维修义务"),
+    ("sublet", "转租"),
+];
+
+const CONSUMER_TERMS: &[(&str, &str)] = &[
+    ("refund", "退款 退货"),
+    ("return", "退货"),
+    ("defect", "质量问题"),
+    ("broken", "质量问题"),
+    ("fake", "欺诈 假货"),
+    ("counterfeit", "欺诈 假货"),
+    ("fraud", "欺诈"),
+    ("warranty", "三包 修理 更换"),
+    ("seller", "经营者"),
+    ("shop", "经营者"),
+    ("online", "网络购物 七日无理由退货"),
+    ("complain", "投诉"),
+];
+
+const FAMILY_TERMS: &[(&str, &str)] = &[
+    ("divorce", "离婚"),
+    ("custody", "抚养权"),
+    ("child support", "抚养费"),
+    ("property", "夫妻共同财产"),
+    ("debt", "夫妻共同债务"),
+    ("alimony", "离婚经济帮助"),
+    ("domestic violence", "家庭暴力"),
+    ("abuse", "家庭暴力"),
+    ("inherit", "继承"),
+];
+
+const TRAFFIC_TERMS: &[(&str, &str)] = &[
+    ("accident", "交通事故"),
+    ("crash", "交通事故"),
+    ("injur", "人身损害赔偿"),
+    ("insurance", "交强险 保险"),
+    ("liability", "事故责任认定"),
+    ("fault", "事故责任认定"),
+    ("medical", "医疗费"),
+    ("police", "交警 事故认定书"),
+    ("hit and run", "逃逸"),
+];
+
+/// Terms every scenario shares; a scenario's own entry for the same word
+/// wins.
+const COMMON_TERMS: &[(&str, &str)] = &[
+    ("contract", "合同"),
+    ("compensation", "赔偿"),
+    ("evidence", "证据"),
+    ("sue", "起诉"),
+    ("lawsuit", "起诉 诉讼"),
+    ("court", "人民法院"),
+    ("deadline", "诉讼时效"),
+];
+
+fn scenario_terms(scenario: &str) -> &'static [(&'static str, &'static str)] {
+    match scenario {
+        "labor" => LABOR_TERMS,
+        "rental" => RENTAL_TERMS,
+        "consumer" => CONSUMER_TERMS,
+        "family" => FAMILY_TERMS,
+        "traffic" => TRAFFIC_TERMS,
+        _ => &[],
+    }
+}
+
+/// `zh-CN` or `en` by the script most of `text` is written in; `None`
+/// when it has neither, e.g. only digits or emoji.
+pub fn detect_language(text: &str) -> Option<&'static str> {
+    let han = text.chars().filter(|ch| is_han(*ch)).count();
+    let latin = text.chars().filter(char::is_ascii_alphabetic).count();
+    if han == 0 && latin == 0 {
+        None
+    } else if latin > han * HAN_WEIGHT {
+        Some(ENGLISH)
+    } else {
+        Some(CHINESE)
+    }
+}
+
+pub fn session_language(storage: &SqliteStorage, session_id: &str) -> CoreResult<Option<String>> {
+    session_preference(storage, session_id, LANGUAGE_PREFERENCE)
+}
+
+/// The stored language of the session, detecting and storing it from
+/// `first_message` when there is none yet.
+pub fn resolve_session_language(
+    storage: &SqliteStorage,
+    session_id: &str,
+    first_message: &str,
+) -> CoreResult<Option<String>> {
+    if let Some(language) = session_language(storage, session_id)? {
+        return Ok(Some(language));
+    }
+    let Some(language) = detect_language(first_message) else {
+        return Ok(None);
+    };
+    set_session_preference(storage, session_id, LANGUAGE_PREFERENCE, language)?;
+    Ok(Some(language.to_owned()))
+}
+
+pub fn is_chinese(language: &str) -> bool {
+    language.starts_with("zh")
+}
+
+/// Name of `language` for model prompts.
+pub fn language_name(language: &str) -> &'static str {
+    if is_chinese(language) {
+        "简体中文"
+    } else {
+        "英文"
+    }
+}
+
+/// Chinese KB terms of `scenario` for the English words in `text`, or
+/// `text` itself when none is known. A rough stand-in for model
+/// translation.
+pub fn fallback_query(scenario: &str, text: &str) -> String {
+    let lower = text.to_lowercase();
+    let words = lower
+        .split(|ch: char| !ch.is_ascii_alphabetic())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>();
+    let own = scenario_terms(scenario);
+    let common = COMMON_TERMS
+        .iter()
+        .filter(|(word, _)| !own.iter().any(|(own_word, _)| own_word == word));
+    let mut terms = Vec::new();
+    for &(word, term) in own.iter().chain(common) {
+        // Single words match as prefixes, so "dismissed" finds "dismiss".
+        let found = if word.contains(' ') {
+            lower.contains(word)
+        } else {
+            words.iter().any(|candidate| candidate.starts_with(word))
+        };
+        if found && !terms.contains(&term) {
+            terms.push(term);
+        }
+    }
+    if terms.is_empty() {
+        text.to_owned()
+    } else {
+        terms.join(" ")
+    }
+}
+
+fn is_han(ch: char) -> bool {
+    matches!(ch, '\u{4E00}'..='\u{9FFF}' | '\u{3400}'..='\u{4DBF}')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{detect_language, fallback_query, CHINESE, ENGLISH};
+
+    #[test]
+    fn dominant_script_decides_and_terms_map_to_the_kb() {
+        assert_eq!(detect_language("公司拖欠了我三个月工资"), Some(CHINESE));
+        assert_eq!(
+            detect_language("My boss has not paid my salary for 3 months"),
+            Some(ENGLISH)
+        );
+        // English with a Chinese term stays English; a Chinese sentence
+        // with one English word stays Chinese.
+        assert_eq!(
+            detect_language("The factory owes me 工资 since March"),
+            Some(ENGLISH)
+        );
+        assert_eq!(
+            detect_language("老板不给我发加班费，还说我不是full time"),
+            Some(CHINESE)
+        );
+        assert_eq!(detect_language("8000 !!"), None);
+
+        assert_eq!(
+            fallback_query("labor", "I was fired and my wages are unpaid"),
+            "拖欠工资 工资 劳动报酬 解除劳动合同 辞退"
+        );
+        assert_eq!(fallback_query("labor", "hello"), "hello");
+        // Words mean what they mean in the session's scenario.
+        assert_eq!(
+            fallback_query(
+                "rental",
+                "The landlord keeps my deposit and ended the contract"
+            ),
+            "押金 出租人 房东 租赁合同"
+        );
+        assert_eq!(
+            fallback_query("traffic", "I was injured, what compensation can I get"),
+            "人身损害赔偿 赔偿"
+        );
+    }
+}
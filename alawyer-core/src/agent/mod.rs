@@ -1,6 +1,40 @@
-use crate::error::CoreResult;
-use crate::storage::SqliteStorage;
-use crate::tools::{intake_questions_for_scenario, IntakeQuestion};
+use std::panic;
+use std::thread;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::error::{CoreError, CoreResult};
+use crate::model::{CallPriority, ModelPhase};
+use crate::region::{DeploymentRegion, UserTimeZone};
+use crate::retrieval::{trim_to_token_budget, CaseDates, RetrievalEngine, SearchResult};
+use crate::safety::SafetyEdit;
+use crate::storage::{CaseMetadata, Session, SqliteStorage};
+use crate::tools::{
+    intake_questions_for_scenario, is_unknown_answer, summarize_evidence_text, AnswerValidator,
+    IntakeQuestion,
+};
+use catalog::{CopyCatalog, CopyKey};
+
+pub mod acknowledgement;
+pub mod catalog;
+pub mod citations;
+pub mod confidence;
+pub mod estimate;
+pub mod fingerprint;
+pub mod language;
+pub mod onboarding;
+pub mod persona;
+pub mod planner;
+pub mod progress;
+pub mod provenance;
+pub mod quick;
+pub mod review_gate;
+pub mod share;
+pub mod stepping;
+pub mod timing;
+pub mod topics;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AgentPhase {
@@ -17,13 +51,540 @@ impl AgentPhase {
             Self::Review => "reviewing",
         }
     }
+    /// Queue class for model calls made in this phase by a task of
+    /// `priority`. Planning covers intake, where the user is waiting on the
+    /// next question.
+    pub fn call_priority(self, priority: TaskPriority) -> CallPriority {
+        match (priority, self) {
+            (TaskPriority::Interactive, _) | (TaskPriority::Normal, Self::Plan) => {
+                CallPriority::Interactive
+            }
+            (TaskPriority::Normal, Self::Draft | Self::Review) => CallPriority::Normal,
+            (TaskPriority::Background, _) => CallPriority::Background,
+        }
+    }
+
+    pub fn model_phase(self) -> ModelPhase {
+        match self {
+            Self::Plan => ModelPhase::Plan,
+            Self::Draft => ModelPhase::Draft,
+            Self::Review => ModelPhase::Review,
+        }
+    }
+}
+
+/// How urgently the result of a task is wanted. It orders the task in its
+/// session's queue and its model calls against those of other tasks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, uniffi::Enum)]
+pub enum TaskPriority {
+    /// The user is watching: every model call goes first.
+    Interactive,
+    /// Intake calls go first, drafting after interactive work.
+    Normal,
+    /// Nobody is waiting, e.g. a report regenerated after a KB update. Goes
+    /// last and leaves a model slot free for other tasks.
+    Background,
+}
+
+impl TaskPriority {
+    /// Class of the task in its session's queue.
+    pub fn queue_priority(self) -> CallPriority {
+        match self {
+            Self::Interactive => CallPriority::Interactive,
+            Self::Normal => CallPriority::Normal,
+            Self::Background => CallPriority::Background,
+        }
+    }
+}
+
+/// Upper bound on section generators running at once during drafting.
+/// Their model calls still queue on the shared model scheduler.
+pub const MAX_PARALLEL_SECTIONS: usize = 4;
+
+/// Report sections that depend only on the (already fixed) facts, so they can
+/// be drafted concurrently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DraftSection {
+    LegalAnalysis,
+    Evidence,
+    Venue,
+    RiskNotice,
+}
+
+impl DraftSection {
+    pub fn report_section(self) -> ReportSection {
+        match self {
+            Self::LegalAnalysis => ReportSection::LegalAnalysis,
+            Self::Evidence => ReportSection::Evidence,
+            Self::Venue => ReportSection::Venue,
+            Self::RiskNotice => ReportSection::RiskNotice,
+        }
+    }
+}
+
+pub const PARALLEL_DRAFT_SECTIONS: [DraftSection; 4] = [
+    DraftSection::LegalAnalysis,
+    DraftSection::Evidence,
+    DraftSection::Venue,
+    DraftSection::RiskNotice,
+];
+
+pub const PROCESS_PATH: &str = "1. 先把证据按时间线整理：合同/考勤/工资流水/沟通记录尽量对应到具体日期。\n2. 准备并提交仲裁申请：写清诉求、金额和事实经过，向有管辖权的仲裁委递交。\n3. 参加调解或开庭：围绕劳动关系、欠薪事实、金额计算这三点陈述，并按要求补充材料。";
+
+pub const DEFAULT_RISK_NOTICE: &str =
+    "本回答基于你当前提供的信息，存在不确定性；若金额较大或争议复杂，建议尽快咨询执业律师。";
+
+pub const TONE_PREFERENCE: &str = "tone";
+/// Session preference switching the report's 【名词解释】 section, `on`
+/// (default) or `off`.
+pub const GLOSSARY_PREFERENCE: &str = "glossary";
+/// Session preference that stops each draft at its cost estimate until the
+/// user confirms, `on` or `off` (default).
+pub const CONFIRM_DRAFT_PREFERENCE: &str = "confirm_draft";
+/// Session preference overriding the configured zone of dates shown to the
+/// user; see `UserTimeZone::parse`.
+pub const TIME_ZONE_PREFERENCE: &str = "timezone";
+
+/// Characters a single message may hold before the text is moved to an
+/// attachment; see `long_text_stub`.
+pub const DEFAULT_MAX_MESSAGE_CHARS: u32 = 4_000;
+
+/// Leading characters of an oversize message kept in the message itself.
+const LONG_TEXT_PREVIEW_CHARS: usize = 300;
+
+/// Session status set when the last agent task failed and can be retried.
+pub const NEEDS_RETRY_STATUS: &str = "needs_retry";
+
+/// Reference to the last failed task of a session, kept so the task can be
+/// re-run from the stored user message.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct FailedTask {
+    pub task_id: String,
+    pub user_message_id: String,
+    pub error: String,
+    pub failed_at: i64,
+}
+
+fn failed_task_key(session_id: &str) -> String {
+    format!("session:{session_id}:failed_task")
+}
+
+pub fn record_failed_task(
+    storage: &SqliteStorage,
+    session_id: &str,
+    failed: &FailedTask,
+) -> CoreResult<()> {
+    let raw = serde_json::to_string(failed)
+        .map_err(|e| CoreError::Unknown(format!("serialize failed task failed: {e}")))?;
+    storage.set_setting(&failed_task_key(session_id), &raw)?;
+    // An archived session stays archived; the failure is still retryable.
+    match storage.get_session(session_id)? {
+        Some(session) if session.status == "archived" => Ok(()),
+        _ => storage.update_session_status(session_id, NEEDS_RETRY_STATUS),
+    }
+}
+
+pub fn failed_task(storage: &SqliteStorage, session_id: &str) -> CoreResult<Option<FailedTask>> {
+    Ok(storage
+        .get_setting(&failed_task_key(session_id))?
+        .and_then(|raw| serde_json::from_str(&raw).ok()))
+}
+
+/// Forget a recorded failure and put a `needs_retry` session back to active.
+pub fn clear_failed_task(storage: &SqliteStorage, session_id: &str) -> CoreResult<()> {
+    if failed_task(storage, session_id)?.is_none() {
+        return Ok(());
+    }
+    storage.delete_setting(&failed_task_key(session_id))?;
+    match storage.get_session(session_id)? {
+        Some(session) if session.status == NEEDS_RETRY_STATUS => {
+            storage.update_session_status(session_id, "active")
+        }
+        _ => Ok(()),
+    }
+}
+
+/// State of the KB the session's latest report was generated against.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ReportKbSnapshot {
+    pub message_id: String,
+    pub generated_at: i64,
+    /// `KnowledgeInfo::updated_at` at generation time.
+    pub kb_updated_at: i64,
+    pub kb_file_count: u32,
+    /// Default model of the connector that wrote it; `None` for reports
+    /// built from templates.
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+fn report_snapshot_key(session_id: &str) -> String {
+    format!("session:{session_id}:report_kb")
+}
+
+pub fn record_report_snapshot(
+    storage: &SqliteStorage,
+    session_id: &str,
+    snapshot: &ReportKbSnapshot,
+) -> CoreResult<()> {
+    let raw = serde_json::to_string(snapshot)
+        .map_err(|e| CoreError::Unknown(format!("serialize report snapshot failed: {e}")))?;
+    storage.set_setting(&report_snapshot_key(session_id), &raw)
+}
+
+pub fn report_snapshot(
+    storage: &SqliteStorage,
+    session_id: &str,
+) -> CoreResult<Option<ReportKbSnapshot>> {
+    Ok(storage
+        .get_setting(&report_snapshot_key(session_id))?
+        .and_then(|raw| serde_json::from_str(&raw).ok()))
+}
+
+/// Sections of the session's latest report as drafted, before the tone,
+/// the style pass and the review, for a `CitationsOnly` regeneration to
+/// keep. Sealed like message content, since they restate the facts.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct DraftedSections {
+    pub facts_summary: String,
+    pub evidence_summary: String,
+    pub venue: String,
+    pub risk_notice: String,
+}
+
+fn drafted_sections_key(session_id: &str) -> String {
+    format!("session:{session_id}:report_sections")
+}
+
+pub fn record_drafted_sections(
+    storage: &SqliteStorage,
+    session_id: &str,
+    sections: &DraftedSections,
+) -> CoreResult<()> {
+    let raw = serde_json::to_string(sections)
+        .map_err(|e| CoreError::Unknown(format!("serialize report sections failed: {e}")))?;
+    storage.set_sealed_setting(&drafted_sections_key(session_id), &raw)
+}
+
+pub fn drafted_sections(
+    storage: &SqliteStorage,
+    session_id: &str,
+) -> CoreResult<Option<DraftedSections>> {
+    Ok(storage
+        .get_sealed_setting(&drafted_sections_key(session_id))?
+        .and_then(|raw| serde_json::from_str(&raw).ok()))
+}
+
+/// Safety rewrites the review phase made in the session's latest report.
+/// Sealed like message content, since the edits quote the report.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, uniffi::Record)]
+pub struct ReportReview {
+    pub message_id: String,
+    /// Offsets are characters into the stored report message.
+    pub edits: Vec<SafetyEdit>,
+}
+
+fn report_review_key(session_id: &str) -> String {
+    format!("session:{session_id}:report_review")
+}
+
+pub fn record_report_review(
+    storage: &SqliteStorage,
+    session_id: &str,
+    review: &ReportReview,
+) -> CoreResult<()> {
+    let raw = serde_json::to_string(review)
+        .map_err(|e| CoreError::Unknown(format!("serialize report review failed: {e}")))?;
+    storage.set_sealed_setting(&report_review_key(session_id), &raw)
+}
+
+pub fn report_review(
+    storage: &SqliteStorage,
+    session_id: &str,
+) -> CoreResult<Option<ReportReview>> {
+    Ok(storage
+        .get_sealed_setting(&report_review_key(session_id))?
+        .and_then(|raw| serde_json::from_str(&raw).ok()))
+}
+
+/// Register of the generated report, selected per session.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReportTone {
+    Plain,
+    #[default]
+    Standard,
+    Formal,
+}
+
+impl ReportTone {
+    pub fn parse(raw: &str) -> Option<Self> {
+        match raw {
+            "plain" => Some(Self::Plain),
+            "standard" => Some(Self::Standard),
+            "formal" => Some(Self::Formal),
+            _ => None,
+        }
+    }
+
+    fn conclusion(self) -> &'static str {
+        match self {
+            Self::Plain => "简单说：您这种情况一般可以先去申请劳动仲裁。先把证据按时间顺序理清楚，再一步一步来。",
+            Self::Standard => "从您目前提供的信息看，这类争议通常可以先走劳动仲裁路径；建议尽快把证据按时间线整理好，再按步骤推进。",
+            Self::Formal => "经初步梳理，本案争议宜先行通过劳动仲裁程序解决；建议申请人按时间顺序整理并固定证据后，依程序推进。",
+        }
+    }
+
+    fn facts_intro(self) -> &'static str {
+        match self {
+            Self::Plain => "我把您说的情况整理了一下：",
+            Self::Standard => "我先把您提供的信息整理如下：",
+            Self::Formal => "根据申请人陈述，案件基本事实整理如下：",
+        }
+    }
+
+    fn process_intro(self) -> &'static str {
+        match self {
+            Self::Plain => "分三步走就行：先准备、再提交、再跟进：",
+            Self::Standard => "建议按“先准备、再提交、再跟进”的顺序推进：",
+            Self::Formal => "建议按以下程序依次推进：",
+        }
+    }
+
+    /// Style instruction for the optional model rewrite pass. `Standard`
+    /// needs no rewrite.
+    pub fn style_instruction(self) -> Option<&'static str> {
+        match self {
+            Self::Plain => Some("通俗易懂、像朋友解释一样的口语化表达，避免专业术语堆砌"),
+            Self::Standard => None,
+            Self::Formal => Some("正式、严谨、适合提交给仲裁机构或律师阅读的书面语"),
+        }
+    }
+}
+
+fn session_preference_key(session_id: &str, key: &str) -> String {
+    format!("session:{session_id}:pref:{key}")
+}
+
+/// Validate and store a per-session preference.
+pub fn set_session_preference(
+    storage: &SqliteStorage,
+    session_id: &str,
+    key: &str,
+    value: &str,
+) -> CoreResult<()> {
+    match key {
+        TONE_PREFERENCE => {
+            if ReportTone::parse(value).is_none() {
+                return Err(CoreError::Config(format!(
+                    "invalid tone {value}; expected plain, standard or formal"
+                )));
+            }
+        }
+        GLOSSARY_PREFERENCE => {
+            if !matches!(value, "on" | "off") {
+                return Err(CoreError::Config(format!(
+                    "invalid glossary {value}; expected on or off"
+                )));
+            }
+        }
+        CONFIRM_DRAFT_PREFERENCE => {
+            if !matches!(value, "on" | "off") {
+                return Err(CoreError::Config(format!(
+                    "invalid confirm_draft {value}; expected on or off"
+                )));
+            }
+        }
+        TIME_ZONE_PREFERENCE => {
+            if UserTimeZone::parse(value).is_none() {
+                return Err(CoreError::Config(format!(
+                    "invalid timezone {value}; expected an offset such as +08:00 or a zone \
+                     without daylight saving time such as Asia/Shanghai"
+                )));
+            }
+        }
+        language::LANGUAGE_PREFERENCE => {
+            if !language::LANGUAGES.contains(&value) {
+                return Err(CoreError::Config(format!(
+                    "invalid language {value}; expected {}",
+                    language::LANGUAGES.join(" or ")
+                )));
+            }
+        }
+        _ => {
+            return Err(CoreError::Config(format!(
+                "unknown session preference {key}"
+            )))
+        }
+    }
+
+    storage.set_setting(&session_preference_key(session_id, key), value)
+}
+
+pub fn session_preference(
+    storage: &SqliteStorage,
+    session_id: &str,
+    key: &str,
+) -> CoreResult<Option<String>> {
+    storage.get_setting(&session_preference_key(session_id, key))
+}
+
+pub fn report_tone(storage: &SqliteStorage, session_id: &str) -> CoreResult<ReportTone> {
+    Ok(session_preference(storage, session_id, TONE_PREFERENCE)?
+        .and_then(|raw| ReportTone::parse(&raw))
+        .unwrap_or_default())
+}
+
+/// Digit runs in report text: amounts, dates, article and case numbers.
+static FIGURE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\d+(?:[.,]\d+)*").expect("valid regex"));
+
+/// Whether a style rewrite kept every amount, date and other figure of
+/// `draft` and introduced none. Thousands separators and leading zeros
+/// are ignored, so "12,000" may become "12000" and "01月" may become "1月".
+pub fn keeps_figures(draft: &str, rewrite: &str) -> bool {
+    let figures = |text: &str| {
+        FIGURE
+            .find_iter(text)
+            .map(|figure| {
+                let digits = figure.as_str().replace(',', "");
+                match digits.trim_start_matches('0') {
+                    "" => "0".to_owned(),
+                    trimmed if trimmed.starts_with('.') => format!("0{trimmed}"),
+                    trimmed => trimmed.to_owned(),
+                }
+            })
+            .collect::<std::collections::BTreeSet<_>>()
+    };
+    figures(draft) == figures(rewrite)
+}
+
+pub fn glossary_enabled(storage: &SqliteStorage, session_id: &str) -> CoreResult<bool> {
+    Ok(session_preference(storage, session_id, GLOSSARY_PREFERENCE)?.as_deref() != Some("off"))
+}
+
+/// The session's `TIME_ZONE_PREFERENCE`, else `default`.
+pub fn session_time_zone(
+    storage: &SqliteStorage,
+    session_id: &str,
+    default: &UserTimeZone,
+) -> CoreResult<UserTimeZone> {
+    Ok(
+        session_preference(storage, session_id, TIME_ZONE_PREFERENCE)?
+            .and_then(|raw| UserTimeZone::parse(&raw))
+            .unwrap_or_else(|| default.clone()),
+    )
+}
+
+/// Disclaimer section body for `region`, dated with the report's
+/// generation time in `zone`.
+pub fn report_disclaimer(
+    region: DeploymentRegion,
+    generated_at: DateTime<Utc>,
+    zone: &UserTimeZone,
+) -> String {
+    format!(
+        "{}\n报告生成日期：{}",
+        region.disclaimer(),
+        region.format_date(generated_at, zone)
+    )
+}
+
+/// Longest opposing party or jurisdiction accepted, in characters.
+const MAX_CASE_NAME_CHARS: usize = 100;
+
+/// `metadata` trimmed, with blank fields cleared, or why it cannot be
+/// stored: names over `MAX_CASE_NAME_CHARS`, an amount without one or a
+/// start date that is not `YYYY-MM-DD`.
+pub fn normalize_case_metadata(metadata: CaseMetadata) -> CoreResult<CaseMetadata> {
+    let clean = |value: Option<String>| {
+        value
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty())
+    };
+    let name = |field: &str, value: Option<String>| match clean(value) {
+        Some(value) if value.chars().count() > MAX_CASE_NAME_CHARS => Err(CoreError::InvalidState(
+            format!("{field} is longer than {MAX_CASE_NAME_CHARS} characters"),
+        )),
+        value => Ok(value),
+    };
+
+    let claim_amount = clean(metadata.claim_amount);
+    if let Some(amount) = &claim_amount {
+        if AnswerValidator::Money.normalize(amount).is_none() {
+            return Err(CoreError::InvalidState(format!(
+                "invalid claim_amount {amount}; expected an amount such as 30000 or 3万元"
+            )));
+        }
+    }
+    let dispute_started_on = clean(metadata.dispute_started_on);
+    if let Some(date) = &dispute_started_on {
+        if NaiveDate::parse_from_str(date, "%Y-%m-%d").is_err() {
+            return Err(CoreError::InvalidState(format!(
+                "invalid dispute_started_on {date}; expected YYYY-MM-DD"
+            )));
+        }
+    }
+
+    Ok(CaseMetadata {
+        opposing_party: name("opposing_party", metadata.opposing_party)?,
+        claim_amount,
+        dispute_started_on,
+        jurisdiction: name("jurisdiction", metadata.jurisdiction)?,
+    })
+}
+
+/// Lines of the facts section stating the set fields of `metadata`, in the
+/// `format_facts_summary` style; empty when none is set.
+pub fn format_case_metadata(metadata: &CaseMetadata) -> String {
+    [
+        ("对方当事人", &metadata.opposing_party),
+        ("争议金额", &metadata.claim_amount),
+        ("争议发生日期", &metadata.dispute_started_on),
+        ("管辖机构", &metadata.jurisdiction),
+    ]
+    .into_iter()
+    .filter_map(|(label, value)| value.as_ref().map(|value| format!("- {label}：{value}")))
+    .collect::<Vec<_>>()
+    .join("\n")
 }
 
-pub const DISCLAIMER: &str = r#"【免责声明】
-1. 本报告由AI生成，仅供参考，不构成法律意见或律师建议
-2. 案件具体情况可能影响法律适用，建议咨询执业律师
-3. 法规可能存在时效性，请以最新颁布版本为准
-4. 本报告不保证准确性、完整性或适用性"#;
+/// The span of the case's key dates, for leaving out laws not in force
+/// during it: the date facts of the intake up to the day the dispute
+/// started, or up to `today` while that day is not set. `None` when the
+/// case has no dates at all.
+pub fn case_dates(
+    metadata: &CaseMetadata,
+    facts: &[CaseFact],
+    scenario: &str,
+    today: NaiveDate,
+) -> Option<CaseDates> {
+    let date_fields = intake_questions_for_scenario(scenario)
+        .into_iter()
+        .filter(|question| question.validator == Some(AnswerValidator::Date))
+        .map(|question| question.field)
+        .collect::<Vec<_>>();
+    // `YYYY-MM` or `YYYY`; see `AnswerValidator::normalize`.
+    let fact_dates = facts
+        .iter()
+        .filter(|fact| date_fields.contains(&fact.field))
+        .filter_map(|fact| fact.normalized_value.as_deref())
+        .filter_map(|value| match value.len() {
+            4 => NaiveDate::parse_from_str(&format!("{value}-01-01"), "%Y-%m-%d").ok(),
+            _ => NaiveDate::parse_from_str(&format!("{value}-01"), "%Y-%m-%d").ok(),
+        })
+        .collect::<Vec<_>>();
+    let dispute_started_on = metadata
+        .dispute_started_on
+        .as_deref()
+        .and_then(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok());
+    if fact_dates.is_empty() && dispute_started_on.is_none() {
+        return None;
+    }
+    CaseDates::spanning(
+        fact_dates
+            .into_iter()
+            .chain([dispute_started_on.unwrap_or(today)]),
+    )
+}
 
 pub fn intake_state(
     storage: &SqliteStorage,
@@ -59,16 +620,358 @@ pub fn mark_intake_done(storage: &SqliteStorage, session_id: &str) -> CoreResult
     storage.set_setting(&format!("intake:{session_id}:done"), "1")
 }
 
+/// Saving an answer clears any earlier "待确认" mark on the question, and
+/// marks it open when the answer is "暂不清楚" or a skip, all in one
+/// transaction.
+/// `source_message_id` is the user message the answer came from; manual
+/// corrections have none.
 pub fn save_answer(
     storage: &SqliteStorage,
     session_id: &str,
     question_index: usize,
     answer: &str,
+    source_message_id: Option<&str>,
 ) -> CoreResult<()> {
-    storage.set_setting(
-        &format!("intake:{session_id}:answer:{question_index}"),
-        answer,
-    )
+    storage.with_tx(|| {
+        storage.set_sealed_setting(&answer_key(session_id, question_index), answer)?;
+        let source_key = answer_source_key(session_id, question_index);
+        match source_message_id {
+            Some(message_id) => storage.set_setting(&source_key, message_id)?,
+            None => storage.delete_setting(&source_key)?,
+        }
+        let open_key = open_question_key(session_id, question_index);
+        if is_unknown_answer(answer) {
+            storage.set_setting(&open_key, "1")?;
+        } else {
+            storage.delete_setting(&open_key)?;
+        }
+        storage.delete_setting(&unconfirmed_key(session_id, question_index))
+    })
+}
+
+/// Sealed while content encryption is on; read with `get_sealed_setting`.
+fn answer_key(session_id: &str, question_index: usize) -> String {
+    format!("intake:{session_id}:answer:{question_index}")
+}
+
+fn answer_source_key(session_id: &str, question_index: usize) -> String {
+    format!("intake:{session_id}:answer_source:{question_index}")
+}
+
+fn open_question_key(session_id: &str, question_index: usize) -> String {
+    format!("intake:{session_id}:open:{question_index}")
+}
+
+/// Holds the index of the open question asked again with
+/// `reask_open_question`, until the user's next message answers it.
+fn pending_reask_key(session_id: &str) -> String {
+    format!("intake:{session_id}:reask_pending")
+}
+
+/// An intake question the user answered with "暂不清楚" or skipped.
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct OpenQuestion {
+    pub field: String,
+    pub label: String,
+    pub question: String,
+    /// What the user said instead of an answer.
+    pub answer: String,
+}
+
+/// Open questions of a session, in question order and the session's
+/// language.
+pub fn open_questions(
+    storage: &SqliteStorage,
+    session_id: &str,
+    scenario: &str,
+) -> CoreResult<Vec<OpenQuestion>> {
+    let locale = language::session_language(storage, session_id)?.unwrap_or_default();
+    let mut open = Vec::new();
+    for (idx, question) in intake_questions_for_scenario(scenario)
+        .into_iter()
+        .enumerate()
+    {
+        if storage
+            .get_setting(&open_question_key(session_id, idx))?
+            .is_none()
+        {
+            continue;
+        }
+        open.push(OpenQuestion {
+            label: question.label_in(&locale),
+            question: question.question_in(&locale),
+            field: question.field,
+            answer: storage
+                .get_sealed_setting(&answer_key(session_id, idx))?
+                .unwrap_or_default(),
+        });
+    }
+    Ok(open)
+}
+
+/// Mark the open question `field` as asked again, so the next user message
+/// is taken as its answer. Returns the question.
+pub fn start_reask(
+    storage: &SqliteStorage,
+    session_id: &str,
+    scenario: &str,
+    field: &str,
+) -> CoreResult<IntakeQuestion> {
+    let (index, question) = intake_questions_for_scenario(scenario)
+        .into_iter()
+        .enumerate()
+        .find(|(_, question)| question.field == field)
+        .ok_or_else(|| {
+            CoreError::Config(format!(
+                "unknown fact field {field} for scenario {scenario}"
+            ))
+        })?;
+    if storage
+        .get_setting(&open_question_key(session_id, index))?
+        .is_none()
+    {
+        return Err(CoreError::InvalidState(format!(
+            "question {field} is already answered"
+        )));
+    }
+    storage.set_setting(&pending_reask_key(session_id), &index.to_string())?;
+    Ok(question)
+}
+
+/// Message phase for the follow-up offer to answer open questions.
+pub const OPEN_QUESTIONS_PHASE: &str = "open_questions";
+
+/// Index of the question asked again with `start_reask`, clearing it.
+pub fn take_pending_reask(storage: &SqliteStorage, session_id: &str) -> CoreResult<Option<usize>> {
+    let key = pending_reask_key(session_id);
+    storage.with_tx(|| {
+        let Some(raw) = storage.get_setting(&key)? else {
+            return Ok(None);
+        };
+        storage.delete_setting(&key)?;
+        Ok(raw.parse().ok())
+    })
+}
+
+/// One intake fact as the host renders it on the case card.
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct CaseFact {
+    pub field: String,
+    pub label: String,
+    /// `None` until the question is answered.
+    pub value: Option<String>,
+    /// See `AnswerValidator::normalize`.
+    pub normalized_value: Option<String>,
+    /// `false` when the answer never passed its question's validator.
+    pub confirmed: bool,
+    pub source_message_id: Option<String>,
+}
+
+/// Typed view of the intake answers, in question order.
+pub fn case_facts(
+    storage: &SqliteStorage,
+    session_id: &str,
+    scenario: &str,
+) -> CoreResult<Vec<CaseFact>> {
+    intake_questions_for_scenario(scenario)
+        .into_iter()
+        .enumerate()
+        .map(|(idx, question)| {
+            let value = storage
+                .get_sealed_setting(&answer_key(session_id, idx))?
+                .filter(|value| !value.trim().is_empty());
+            let normalized_value = value.as_deref().and_then(|value| {
+                question
+                    .validator
+                    .as_ref()
+                    .and_then(|validator| validator.normalize(value))
+            });
+            Ok(CaseFact {
+                field: question.field,
+                label: question.label,
+                normalized_value,
+                confirmed: storage
+                    .get_setting(&unconfirmed_key(session_id, idx))?
+                    .is_none(),
+                source_message_id: storage.get_setting(&answer_source_key(session_id, idx))?,
+                value,
+            })
+        })
+        .collect()
+}
+
+/// Manually correct one fact. The next report uses the new value.
+pub fn set_case_fact(
+    storage: &SqliteStorage,
+    session_id: &str,
+    scenario: &str,
+    field: &str,
+    value: &str,
+) -> CoreResult<()> {
+    if value.trim().is_empty() {
+        return Err(CoreError::Config(format!("fact {field} value is empty")));
+    }
+    let index = intake_questions_for_scenario(scenario)
+        .iter()
+        .position(|question| question.field == field)
+        .ok_or_else(|| {
+            CoreError::Config(format!(
+                "unknown fact field {field} for scenario {scenario}"
+            ))
+        })?;
+    save_answer(storage, session_id, index, value.trim(), None)
+}
+
+/// How `merge_intake` settles a question both sessions answered differently.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, uniffi::Enum)]
+pub enum FactMergePolicy {
+    /// Keep the answer given last; ties keep the target's.
+    #[default]
+    PreferNewer,
+    /// Keep the target's answer and report the conflict, so the host can
+    /// ask the user and apply their choice with `set_fact`.
+    Prompt,
+}
+
+/// An intake question both merged sessions answered differently.
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct FactConflict {
+    pub field: String,
+    pub label: String,
+    pub source_value: String,
+    pub target_value: String,
+    /// Whether the source's answer replaced the target's.
+    pub took_source: bool,
+}
+
+/// A setting to write, or to delete when the value is `None`.
+pub type SettingWrite = (String, Option<String>);
+
+/// Settings writes that fold `source`'s intake answers and progress into
+/// `target`. Questions only one side answered keep that answer; differing
+/// answers are settled by `policy` and returned. Nothing is written here,
+/// so the caller can apply the writes in the same transaction that moves
+/// the session's records.
+pub fn merge_intake(
+    storage: &SqliteStorage,
+    source: &Session,
+    target: &Session,
+    policy: FactMergePolicy,
+) -> CoreResult<(Vec<FactConflict>, Vec<SettingWrite>)> {
+    let answer = |session_id: &str, idx: usize| -> CoreResult<Option<String>> {
+        Ok(storage
+            .get_sealed_setting(&answer_key(session_id, idx))?
+            .filter(|value| !value.trim().is_empty()))
+    };
+
+    let mut conflicts = Vec::new();
+    let mut writes = Vec::new();
+    for (idx, question) in intake_questions_for_scenario(&target.scenario)
+        .into_iter()
+        .enumerate()
+    {
+        let Some(source_value) = answer(&source.id, idx)? else {
+            continue;
+        };
+        let take_source = match answer(&target.id, idx)? {
+            None => true,
+            Some(target_value) if target_value.trim() == source_value.trim() => false,
+            Some(target_value) => {
+                let took_source = policy == FactMergePolicy::PreferNewer
+                    && answered_at(storage, source, idx)? > answered_at(storage, target, idx)?;
+                conflicts.push(FactConflict {
+                    field: question.field,
+                    label: question.label,
+                    source_value: source_value.clone(),
+                    target_value,
+                    took_source,
+                });
+                took_source
+            }
+        };
+        if take_source {
+            let unconfirmed = storage
+                .get_setting(&unconfirmed_key(&source.id, idx))?
+                .map(|_| "1".to_owned());
+            let target_key = answer_key(&target.id, idx);
+            writes.push((
+                target_key.clone(),
+                Some(storage.sealed_setting_value(&target_key, &source_value)?),
+            ));
+            writes.push((
+                answer_source_key(&target.id, idx),
+                storage.get_setting(&answer_source_key(&source.id, idx))?,
+            ));
+            writes.push((unconfirmed_key(&target.id, idx), unconfirmed));
+        }
+    }
+
+    let source_state = intake_state(storage, &source.id, &source.scenario)?;
+    let target_state = intake_state(storage, &target.id, &target.scenario)?;
+    if source_state.current_index > target_state.current_index {
+        writes.push((
+            format!("intake:{}:idx", target.id),
+            Some(source_state.current_index.to_string()),
+        ));
+    }
+    if source_state.done && !target_state.done {
+        writes.push((format!("intake:{}:done", target.id), Some("1".to_owned())));
+    }
+    Ok((conflicts, writes))
+}
+
+/// When an answer was given: its source message's time, or the session's
+/// last update for manual corrections.
+fn answered_at(
+    storage: &SqliteStorage,
+    session: &Session,
+    question_index: usize,
+) -> CoreResult<i64> {
+    let message = match storage.get_setting(&answer_source_key(&session.id, question_index))? {
+        Some(message_id) => storage.get_message(&message_id)?,
+        None => None,
+    };
+    Ok(message.map_or(session.updated_at, |message| message.created_at))
+}
+
+/// Re-asks allowed per question before an invalid answer is kept as-is.
+pub const MAX_INTAKE_REASKS: u32 = 2;
+
+/// Shown next to answers that never passed their question's validator.
+pub const UNCONFIRMED_ANSWER_MARK: &str = "待确认";
+
+fn unconfirmed_key(session_id: &str, question_index: usize) -> String {
+    format!("intake:{session_id}:unconfirmed:{question_index}")
+}
+
+/// Count an invalid answer to `question_index`. Returns `true` while the
+/// question should be asked again; once the re-asks are used up it returns
+/// `false` and the caller keeps the answer, marked as unconfirmed.
+pub fn note_invalid_answer(
+    storage: &SqliteStorage,
+    session_id: &str,
+    question_index: usize,
+) -> CoreResult<bool> {
+    let key = format!("intake:{session_id}:reasks:{question_index}");
+    storage.with_tx(|| {
+        let reasks = storage
+            .get_setting(&key)?
+            .and_then(|raw| raw.parse::<u32>().ok())
+            .unwrap_or(0);
+        if reasks < MAX_INTAKE_REASKS {
+            storage.set_setting(&key, &(reasks + 1).to_string())?;
+            return Ok(true);
+        }
+        Ok(false)
+    })
+}
+
+pub fn mark_answer_unconfirmed(
+    storage: &SqliteStorage,
+    session_id: &str,
+    question_index: usize,
+) -> CoreResult<()> {
+    storage.set_setting(&unconfirmed_key(session_id, question_index), "1")
 }
 
 pub fn advance_intake_index(
@@ -79,6 +982,165 @@ pub fn advance_intake_index(
     storage.set_setting(&format!("intake:{session_id}:idx"), &next.to_string())
 }
 
+/// Longest checkpoint name accepted, in characters.
+const MAX_CHECKPOINT_NAME_CHARS: usize = 40;
+
+fn checkpoint_name_key(session_id: &str, question_index: usize) -> String {
+    format!("intake:{session_id}:checkpoint:{question_index}")
+}
+
+/// A question intake can be rolled back to with `reset_intake`.
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct IntakeCheckpoint {
+    pub question_index: u32,
+    pub field: String,
+    /// In the session's language.
+    pub label: String,
+    /// `None` for the question waiting for its answer.
+    pub answer: Option<String>,
+    /// Given with `name_intake_checkpoint`, e.g. "换成租房纠纷之前".
+    pub name: Option<String>,
+}
+
+/// Questions asked so far, in order; each is a checkpoint.
+pub fn intake_checkpoints(
+    storage: &SqliteStorage,
+    session_id: &str,
+    scenario: &str,
+) -> CoreResult<Vec<IntakeCheckpoint>> {
+    let state = intake_state(storage, session_id, scenario)?;
+    let locale = language::session_language(storage, session_id)?.unwrap_or_default();
+    state
+        .questions
+        .iter()
+        .take(state.current_index)
+        .enumerate()
+        .map(|(idx, question)| {
+            Ok(IntakeCheckpoint {
+                question_index: idx as u32,
+                field: question.field.clone(),
+                label: question.label_in(&locale),
+                answer: storage.get_sealed_setting(&answer_key(session_id, idx))?,
+                name: storage.get_sealed_setting(&checkpoint_name_key(session_id, idx))?,
+            })
+        })
+        .collect()
+}
+
+/// Name the checkpoint at question `index`, or clear its name with `None`
+/// or a blank one. Names are unique within the session.
+pub fn name_intake_checkpoint(
+    storage: &SqliteStorage,
+    session_id: &str,
+    scenario: &str,
+    index: usize,
+    name: Option<&str>,
+) -> CoreResult<()> {
+    let checkpoints = intake_checkpoints(storage, session_id, scenario)?;
+    if index >= checkpoints.len() {
+        return Err(CoreError::InvalidState(format!(
+            "intake has not reached question {} yet",
+            index + 1
+        )));
+    }
+    let key = checkpoint_name_key(session_id, index);
+    let Some(name) = name.map(str::trim).filter(|name| !name.is_empty()) else {
+        return storage.delete_setting(&key);
+    };
+    if name.chars().count() > MAX_CHECKPOINT_NAME_CHARS {
+        return Err(CoreError::Config(format!(
+            "checkpoint name is longer than {MAX_CHECKPOINT_NAME_CHARS} characters"
+        )));
+    }
+    if let Some(taken) = checkpoints.iter().find(|checkpoint| {
+        checkpoint.question_index as usize != index && checkpoint.name.as_deref() == Some(name)
+    }) {
+        return Err(CoreError::Config(format!(
+            "checkpoint name {name} is already used for question {}",
+            taken.question_index + 1
+        )));
+    }
+    storage.set_sealed_setting(&key, name)
+}
+
+/// Question index of the checkpoint named `name`.
+pub fn named_checkpoint(
+    storage: &SqliteStorage,
+    session_id: &str,
+    scenario: &str,
+    name: &str,
+) -> CoreResult<usize> {
+    intake_checkpoints(storage, session_id, scenario)?
+        .into_iter()
+        .find(|checkpoint| checkpoint.name.as_deref() == Some(name.trim()))
+        .map(|checkpoint| checkpoint.question_index as usize)
+        .ok_or_else(|| CoreError::NotFound(format!("intake checkpoint {name}")))
+}
+
+/// Roll intake back so question `to_index` is the one waiting for an
+/// answer: its answer and every later one are dropped, with their marks
+/// and re-ask counts, and intake is no longer done. Names of checkpoints
+/// after `to_index` go with them. Messages stay. Returns the number of
+/// answers dropped.
+pub fn reset_intake(
+    storage: &SqliteStorage,
+    session_id: &str,
+    scenario: &str,
+    to_index: usize,
+) -> CoreResult<u32> {
+    storage.with_tx(|| {
+        let state = intake_state(storage, session_id, scenario)?;
+        if to_index >= state.current_index {
+            return Err(CoreError::InvalidState(format!(
+                "intake has not reached question {} yet",
+                to_index + 1
+            )));
+        }
+        let mut dropped = 0;
+        for idx in to_index..state.questions.len() {
+            let key = answer_key(session_id, idx);
+            if storage.get_setting(&key)?.is_some() {
+                dropped += 1;
+            }
+            storage.delete_setting(&key)?;
+            storage.delete_setting(&answer_source_key(session_id, idx))?;
+            storage.delete_setting(&open_question_key(session_id, idx))?;
+            storage.delete_setting(&unconfirmed_key(session_id, idx))?;
+            storage.delete_setting(&format!("intake:{session_id}:reasks:{idx}"))?;
+            if idx > to_index {
+                storage.delete_setting(&checkpoint_name_key(session_id, idx))?;
+            }
+        }
+        storage.delete_setting(&pending_reask_key(session_id))?;
+        storage.delete_setting(&format!("intake:{session_id}:done"))?;
+        estimate::cancel_confirmation(storage, session_id)?;
+        advance_intake_index(storage, session_id, to_index + 1)?;
+        Ok(dropped)
+    })
+}
+
+/// What the user wrote in the session, intake answers and messages, for
+/// telling their quoted words from anyone else's; see
+/// `SafetyEngine::check_quoting`.
+pub fn user_words(
+    storage: &SqliteStorage,
+    session_id: &str,
+    scenario: &str,
+) -> CoreResult<Vec<String>> {
+    let mut words = Vec::new();
+    for idx in 0..intake_questions_for_scenario(scenario).len() {
+        words.extend(storage.get_sealed_setting(&answer_key(session_id, idx))?);
+    }
+    words.extend(
+        storage
+            .get_messages(session_id)?
+            .into_iter()
+            .filter(|message| message.role == "user")
+            .map(|message| message.content),
+    );
+    Ok(words)
+}
+
 /// Collect answered facts in question-order (stable output).
 pub fn collect_facts(
     storage: &SqliteStorage,
@@ -87,18 +1149,28 @@ pub fn collect_facts(
 ) -> CoreResult<Vec<(String, String)>> {
     let questions = intake_questions_for_scenario(scenario);
     let mut facts = Vec::with_capacity(questions.len());
+    let copy = catalog::report_copy(storage);
 
     for (idx, question) in questions.iter().enumerate() {
-        let key = format!("intake:{session_id}:answer:{idx}");
+        let unconfirmed = storage
+            .get_setting(&unconfirmed_key(session_id, idx))?
+            .is_some();
         let answer = storage
-            .get_setting(&key)?
+            .get_sealed_setting(&answer_key(session_id, idx))?
             .filter(|value| !value.trim().is_empty())
-            .unwrap_or_else(|| {
-                if question.required {
-                    "未提供".to_owned()
+            .map(|value| {
+                if unconfirmed {
+                    format!("{value}（{UNCONFIRMED_ANSWER_MARK}）")
                 } else {
-                    "可补充".to_owned()
+                    value
                 }
+            })
+            .unwrap_or_else(|| {
+                copy.text(if question.required {
+                    CopyKey::FactNotProvided
+                } else {
+                    CopyKey::FactOptional
+                })
             });
         facts.push((question.question.clone(), answer));
     }
@@ -106,29 +1178,546 @@ pub fn collect_facts(
     Ok(facts)
 }
 
+/// Who the model is told it is, by scenario: prompts open with
+/// `你是{role}`.
+pub fn assistant_role(scenario: &str) -> &'static str {
+    match scenario {
+        "labor" => "劳动法咨询助手",
+        "rental" => "房屋租赁法律咨询助手",
+        "consumer" => "消费者权益法律咨询助手",
+        "family" => "婚姻家庭法律咨询助手",
+        "traffic" => "交通事故法律咨询助手",
+        _ => "法律咨询助手",
+    }
+}
+
+/// One line of the facts summary.
+pub fn fact_line(question: &str, answer: &str) -> String {
+    format!("- {question}：{answer}")
+}
+
 pub fn format_facts_summary(facts: &[(String, String)]) -> String {
     facts
         .iter()
-        .map(|(question, answer)| format!("- {}：{}", question, answer))
+        .map(|(question, answer)| fact_line(question, answer))
         .collect::<Vec<_>>()
         .join("\n")
 }
 
-pub fn build_report(
-    facts_summary: &str,
-    legal_analysis: &str,
-    process_path: &str,
-    risk_notice: &str,
-) -> String {
-    format!(
-        "【先说结论】\n从您目前提供的信息看，这类争议通常可以先走劳动仲裁路径；建议尽快把证据按时间线整理好，再按步骤推进。\n\n【事实摘要】\n我先把您提供的信息整理如下：\n{}\n\n【法律分析】\n{}\n\n【办事路径】\n建议按“先准备、再提交、再跟进”的顺序推进：\n{}\n\n【风险提示】\n{}\n\n{}",
-        facts_summary, legal_analysis, process_path, risk_notice, DISCLAIMER
+pub fn format_legal_analysis(copy: &CopyCatalog, search_results: &[SearchResult]) -> String {
+    if search_results.is_empty() {
+        return copy.text(CopyKey::LegalAnalysisEmpty);
+    }
+    let references = legal_references(search_results).join("\n");
+    copy.render(CopyKey::LegalAnalysis, &[("references", &references)])
+}
+
+/// The reference lines of the legal analysis, one per result it quotes.
+pub fn legal_references(search_results: &[SearchResult]) -> Vec<String> {
+    search_results
+        .iter()
+        .take(3)
+        .enumerate()
+        .map(|(idx, item)| {
+            let warning = [&item.inapplicable_warning, &item.outdated_warning]
+                .into_iter()
+                .flatten()
+                .map(|warning| format!("（{warning}）"))
+                .collect::<String>();
+            format!(
+                "{}. 《{}》提到：{}{}",
+                idx + 1,
+                item.title.trim(),
+                item.snippet.replace('\n', " "),
+                warning
+            )
+        })
+        .collect()
+}
+
+/// Run `f` over `items` on scoped threads, at most `limit` at a time.
+/// Results come back in input order regardless of completion order, so the
+/// merged report is deterministic.
+pub fn run_bounded<T, R, F>(items: &[T], limit: usize, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let mut results = Vec::with_capacity(items.len());
+    for batch in items.chunks(limit.max(1)) {
+        thread::scope(|scope| {
+            let handles = batch
+                .iter()
+                .map(|item| scope.spawn(|| f(item)))
+                .collect::<Vec<_>>();
+            for handle in handles {
+                match handle.join() {
+                    Ok(result) => results.push(result),
+                    Err(payload) => panic::resume_unwind(payload),
+                }
+            }
+        });
+    }
+    results
+}
+
+/// Sections of the final report, in document order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportSection {
+    Conclusion,
+    Facts,
+    /// Digests of attached evidence; left out when nothing is attached.
+    Evidence,
+    LegalAnalysis,
+    ProcessPath,
+    /// Arbitration commission for the intake region, or where to ask.
+    Venue,
+    RiskNotice,
+    Disclaimer,
+}
+
+impl ReportSection {
+    pub const ALL: [Self; 8] = [
+        Self::Conclusion,
+        Self::Facts,
+        Self::Evidence,
+        Self::LegalAnalysis,
+        Self::ProcessPath,
+        Self::Venue,
+        Self::RiskNotice,
+        Self::Disclaimer,
+    ];
+
+    pub fn id(self) -> &'static str {
+        match self {
+            Self::Conclusion => "conclusion",
+            Self::Facts => "facts",
+            Self::Evidence => "evidence",
+            Self::LegalAnalysis => "legal_analysis",
+            Self::ProcessPath => "process_path",
+            Self::Venue => "venue",
+            Self::RiskNotice => "risk_notice",
+            Self::Disclaimer => "disclaimer",
+        }
+    }
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Self::Conclusion => "先说结论",
+            Self::Facts => "事实摘要",
+            Self::Evidence => "证据摘要",
+            Self::LegalAnalysis => "法律分析",
+            Self::ProcessPath => "办事路径",
+            Self::Venue => "办理地点",
+            Self::RiskNotice => "风险提示",
+            Self::Disclaimer => "免责声明",
+        }
+    }
+
+    /// Zero-based position in the report.
+    pub fn order(self) -> u32 {
+        Self::ALL
+            .iter()
+            .position(|section| *section == self)
+            .unwrap_or_default() as u32
+    }
+}
+
+impl ReportTone {
+    /// Body of `section` under its heading. `content` is the drafted text
+    /// for facts, evidence, legal analysis, process path and risk notice, the
+    /// looked-up venue, and the regional disclaimer. The conclusion is fixed
+    /// per tone, followed by `content` as its confidence line when there is
+    /// one.
+    pub fn section_body(self, section: ReportSection, content: &str) -> String {
+        match section {
+            ReportSection::Conclusion if content.is_empty() => self.conclusion().to_owned(),
+            ReportSection::Conclusion => format!("{}\n{}", self.conclusion(), content),
+            ReportSection::Facts => format!("{}\n{}", self.facts_intro(), content),
+            ReportSection::ProcessPath => format!("{}\n{}", self.process_intro(), content),
+            ReportSection::Evidence
+            | ReportSection::LegalAnalysis
+            | ReportSection::Venue
+            | ReportSection::RiskNotice
+            | ReportSection::Disclaimer => content.to_owned(),
+        }
+    }
+}
+
+/// Drafted content of each report section; see `ReportTone::section_body`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReportContent<'a> {
+    /// `ConclusionConfidence::render`, or empty.
+    pub confidence: &'a str,
+    pub facts_summary: &'a str,
+    /// Empty when nothing is attached, which leaves the section out.
+    pub evidence_summary: &'a str,
+    pub legal_analysis: &'a str,
+    pub process_path: &'a str,
+    pub venue: &'a str,
+    pub risk_notice: &'a str,
+    pub disclaimer: &'a str,
+}
+
+pub fn build_report(tone: ReportTone, content: &ReportContent<'_>) -> String {
+    ReportSection::ALL
+        .iter()
+        .filter_map(|section| {
+            let content = match section {
+                ReportSection::Facts => content.facts_summary,
+                ReportSection::Evidence if content.evidence_summary.trim().is_empty() => {
+                    return None
+                }
+                ReportSection::Evidence => content.evidence_summary,
+                ReportSection::LegalAnalysis => content.legal_analysis,
+                ReportSection::ProcessPath => content.process_path,
+                ReportSection::Venue => content.venue,
+                ReportSection::RiskNotice => content.risk_notice,
+                ReportSection::Disclaimer => content.disclaimer,
+                ReportSection::Conclusion => content.confidence,
+            };
+            Some(format!(
+                "【{}】\n{}",
+                section.title(),
+                tone.section_body(*section, content)
+            ))
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Message body standing in for oversize `content` that was stored as the
+/// attachment `file_name`: a preview plus a digest of the whole text.
+pub fn long_text_stub(copy: &CopyCatalog, content: &str, file_name: &str) -> String {
+    let preview = match content.char_indices().nth(LONG_TEXT_PREVIEW_CHARS) {
+        Some((cut, _)) => &content[..cut],
+        None => content,
+    };
+    copy.render(
+        CopyKey::LongTextStub,
+        &[
+            ("preview", preview.trim_end()),
+            ("file_name", file_name),
+            ("chars", &content.chars().count().to_string()),
+            ("digest", &summarize_evidence_text(content)),
+        ],
     )
 }
 
+/// Message phase for hypothetical answers; they never count as the report.
+pub const WHAT_IF_PHASE: &str = "whatif";
+
+/// Message phase for a report translated into the session's language; the
+/// Chinese report before it stays the record.
+pub const TRANSLATION_PHASE: &str = "translation";
+
+/// Token budget for retrieved snippets quoted in a what-if analysis.
+const WHAT_IF_CONTEXT_TOKEN_BUDGET: u32 = 1_500;
+
+/// A replacement answer for one intake question, used only for a what-if run.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, uniffi::Record)]
+pub struct FactOverride {
+    pub question_index: u32,
+    pub answer: String,
+}
+
+/// Re-run the analysis on the stored facts with `overrides` applied and
+/// return a comparative answer. Stored facts are left untouched.
+pub fn run_what_if(
+    storage: &SqliteStorage,
+    retrieval: &RetrievalEngine,
+    session_id: &str,
+    scenario: &str,
+    overrides: &[FactOverride],
+    region: DeploymentRegion,
+) -> CoreResult<String> {
+    if overrides.is_empty() {
+        return Err(CoreError::Config(
+            "what_if needs at least one changed fact".to_owned(),
+        ));
+    }
+
+    let facts = collect_facts(storage, session_id, scenario)?;
+    let mut hypothetical = facts.clone();
+    let mut changes = Vec::with_capacity(overrides.len());
+    for fact in overrides {
+        let slot = hypothetical
+            .get_mut(fact.question_index as usize)
+            .ok_or_else(|| {
+                CoreError::Config(format!("unknown intake question {}", fact.question_index))
+            })?;
+        changes.push(format!("- {}：{} → {}", slot.0, slot.1, fact.answer.trim()));
+        slot.1 = fact.answer.trim().to_owned();
+    }
+
+    let query = overrides
+        .iter()
+        .map(|fact| fact.answer.trim())
+        .collect::<Vec<_>>()
+        .join(" ");
+    let results = retrieval.search(&format!("劳动仲裁 {query}"), scenario, 3)?;
+    let results = trim_to_token_budget(results, WHAT_IF_CONTEXT_TOKEN_BUDGET);
+
+    let copy = catalog::report_copy(storage);
+    Ok(copy.render(
+        CopyKey::WhatIfAnswer,
+        &[
+            ("changes", &changes.join("\n")),
+            ("facts", &format_facts_summary(&hypothetical)),
+            ("analysis", &format_legal_analysis(&copy, &results)),
+            ("disclaimer", region.disclaimer()),
+        ],
+    ))
+}
+
 #[derive(Debug, Clone)]
 pub struct IntakeState {
     pub questions: Vec<IntakeQuestion>,
     pub current_index: usize,
     pub done: bool,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    use std::fs;
+    use std::sync::Arc;
+
+    use tempfile::TempDir;
+
+    use chrono::NaiveDate;
+
+    use super::{
+        build_report, case_dates, case_facts, clear_failed_task, collect_facts, failed_task,
+        keeps_figures, mark_answer_unconfirmed, record_failed_task, run_bounded, run_what_if,
+        save_answer, set_case_fact, FactOverride, FailedTask, ReportContent, ReportTone,
+    };
+    use crate::clock::SystemClock;
+    use crate::region::DeploymentRegion;
+    use crate::retrieval::{RetrievalConfig, RetrievalEngine};
+    use crate::storage::{CaseMetadata, SqliteStorage, DEFAULT_PROFILE_ID};
+
+    #[test]
+    fn run_bounded_keeps_order_and_limit() {
+        let running = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        let items = [5_u64, 1, 4, 2, 3];
+
+        let results = run_bounded(&items, 2, |delay| {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            peak.fetch_max(now, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(delay * 10));
+            running.fetch_sub(1, Ordering::SeqCst);
+            delay * 2
+        });
+
+        assert_eq!(results, vec![10, 2, 8, 4, 6]);
+        assert!(peak.load(Ordering::SeqCst) <= 2);
+    }
+
+    #[test]
+    fn failures_keep_archived_sessions_archived() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let storage = SqliteStorage::new(temp_dir.path().join("core.db"), Arc::new(SystemClock))
+            .expect("storage");
+        let session = storage
+            .create_session("labor", None, DEFAULT_PROFILE_ID)
+            .expect("session");
+        storage
+            .update_session_status(&session.id, "archived")
+            .expect("archive");
+
+        let failed = FailedTask {
+            task_id: "task".to_owned(),
+            user_message_id: "message".to_owned(),
+            error: "boom".to_owned(),
+            failed_at: 0,
+        };
+        record_failed_task(&storage, &session.id, &failed).expect("record");
+        clear_failed_task(&storage, &session.id).expect("clear");
+        record_failed_task(&storage, &session.id, &failed).expect("record");
+
+        let status = storage
+            .get_session(&session.id)
+            .expect("get")
+            .expect("session")
+            .status;
+        assert_eq!(status, "archived");
+        assert_eq!(
+            failed_task(&storage, &session.id).expect("failed"),
+            Some(failed)
+        );
+    }
+
+    #[test]
+    fn style_rewrites_must_keep_amounts_and_dates() {
+        let draft = "2024年01月05日入职，月薪12,000元，拖欠0.5个月工资。";
+        assert!(keeps_figures(
+            draft,
+            "您于2024年1月5日入职，月薪为12000元，被拖欠0.5个月的工资。"
+        ));
+        assert!(!keeps_figures(
+            draft,
+            "您于2024年1月5日入职，月薪为15000元，被拖欠0.5个月的工资。"
+        ));
+        assert!(!keeps_figures(
+            draft,
+            "您于2024年1月入职，月薪12000元，被拖欠0.5个月工资。"
+        ));
+    }
+
+    #[test]
+    fn case_facts_are_typed_and_correctable() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let storage = SqliteStorage::new(temp_dir.path().join("core.db"), Arc::new(SystemClock))
+            .expect("storage");
+        let session = storage
+            .create_session("labor", None, DEFAULT_PROFILE_ID)
+            .expect("session");
+        save_answer(&storage, &session.id, 1, "2022年3月入职", Some("msg-1")).expect("answer");
+        save_answer(&storage, &session.id, 2, "销售，月薪1.2万", Some("msg-2")).expect("answer");
+        mark_answer_unconfirmed(&storage, &session.id, 2).expect("unconfirmed");
+
+        let facts = case_facts(&storage, &session.id, "labor").expect("facts");
+        assert_eq!(facts.len(), 6);
+        assert_eq!(facts[0].value, None);
+        assert_eq!(facts[1].field, "hire_date");
+        assert_eq!(facts[1].normalized_value.as_deref(), Some("2022-03"));
+        assert_eq!(facts[1].source_message_id.as_deref(), Some("msg-1"));
+        assert_eq!(facts[2].normalized_value.as_deref(), Some("12000"));
+        assert!(!facts[2].confirmed);
+
+        set_case_fact(&storage, &session.id, "labor", "job_salary", "月薪9000元").expect("correct");
+        let corrected = &case_facts(&storage, &session.id, "labor").expect("facts")[2];
+        assert_eq!(corrected.normalized_value.as_deref(), Some("9000"));
+        assert!(corrected.confirmed);
+        assert_eq!(corrected.source_message_id, None);
+        assert_eq!(
+            collect_facts(&storage, &session.id, "labor").expect("facts")[2].1,
+            "月薪9000元"
+        );
+        assert!(set_case_fact(&storage, &session.id, "labor", "unknown", "x").is_err());
+    }
+
+    #[test]
+    fn case_dates_span_date_facts_up_to_the_dispute() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let storage = SqliteStorage::new(temp_dir.path().join("core.db"), Arc::new(SystemClock))
+            .expect("storage");
+        let session = storage
+            .create_session("labor", None, DEFAULT_PROFILE_ID)
+            .expect("session");
+        let date = |value: &str| NaiveDate::parse_from_str(value, "%Y-%m-%d").expect("date");
+        let today = date("2026-05-10");
+        let mut metadata = CaseMetadata::default();
+
+        // Salaries are amounts, not dates.
+        save_answer(&storage, &session.id, 2, "销售，月薪2019元", None).expect("answer");
+        let facts = case_facts(&storage, &session.id, "labor").expect("facts");
+        assert_eq!(case_dates(&metadata, &facts, "labor", today), None);
+
+        save_answer(&storage, &session.id, 1, "2019年6月入职", None).expect("answer");
+        let facts = case_facts(&storage, &session.id, "labor").expect("facts");
+        let ongoing = case_dates(&metadata, &facts, "labor", today).expect("dates");
+        assert_eq!(
+            (ongoing.earliest, ongoing.latest),
+            (date("2019-06-01"), today)
+        );
+
+        metadata.dispute_started_on = Some("2022-03-01".to_owned());
+        let settled = case_dates(&metadata, &facts, "labor", today).expect("dates");
+        assert_eq!(
+            (settled.earliest, settled.latest),
+            (date("2019-06-01"), date("2022-03-01"))
+        );
+    }
+
+    #[test]
+    fn tone_changes_template_but_keeps_sections() {
+        let content = ReportContent {
+            facts_summary: "- a",
+            legal_analysis: "b",
+            process_path: "c",
+            venue: "v",
+            risk_notice: "d",
+            disclaimer: "e",
+            ..ReportContent::default()
+        };
+        let plain = build_report(ReportTone::Plain, &content);
+        let formal = build_report(
+            ReportTone::Formal,
+            &ReportContent {
+                evidence_summary: "- 工资流水.pdf：3月未入账",
+                ..content
+            },
+        );
+
+        assert_ne!(plain, formal);
+        assert!(formal.contains("申请人"));
+        for report in [&plain, &formal] {
+            assert!(report.contains("【事实摘要】"));
+            assert!(report.contains("【免责声明】"));
+            assert!(report.contains("【办理地点】\nv"));
+        }
+        assert!(!plain.contains("【证据摘要】"));
+        assert!(formal.contains("【证据摘要】\n- 工资流水.pdf：3月未入账"));
+        assert_eq!(ReportTone::parse("casual"), None);
+    }
+
+    #[test]
+    fn what_if_is_hypothetical_and_leaves_facts_alone() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let labor = temp_dir.path().join("kb").join("labor");
+        fs::create_dir_all(&labor).expect("create kb");
+        fs::write(
+            labor.join("law.md"),
+            "# 劳动合同\n未签订书面劳动合同的，可主张二倍工资。",
+        )
+        .expect("write kb");
+        let retrieval =
+            RetrievalEngine::new(temp_dir.path().join("kb"), RetrievalConfig::default());
+        let storage = SqliteStorage::new(temp_dir.path().join("core.db"), Arc::new(SystemClock))
+            .expect("storage");
+        let session = storage
+            .create_session("labor", None, DEFAULT_PROFILE_ID)
+            .expect("session");
+        save_answer(&storage, &session.id, 1, "2023年入职，签了合同", None).expect("answer");
+
+        let answer = run_what_if(
+            &storage,
+            &retrieval,
+            &session.id,
+            "labor",
+            &[FactOverride {
+                question_index: 1,
+                answer: "2023年入职，没有签劳动合同".to_owned(),
+            }],
+            DeploymentRegion::HongKong,
+        )
+        .expect("what if");
+
+        assert!(answer.starts_with("【假设分析】"));
+        assert!(answer.contains("2023年入职，签了合同 → 2023年入职，没有签劳动合同"));
+        assert!(answer.contains("【免责声明】"));
+        assert!(answer.contains(DeploymentRegion::HongKong.disclaimer()));
+        let facts = collect_facts(&storage, &session.id, "labor").expect("facts");
+        assert_eq!(facts[1].1, "2023年入职，签了合同");
+
+        assert!(run_what_if(
+            &storage,
+            &retrieval,
+            &session.id,
+            "labor",
+            &[FactOverride {
+                question_index: 99,
+                answer: "x".to_owned(),
+            }],
+            DeploymentRegion::Mainland,
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,291 @@
+//! First launch: a starter KB, the deployment's default tool permissions,
+//! copy and persona, and an optional demo session, done once per install.
+
+use std::fs;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use walkdir::WalkDir;
+
+use super::persona::PersonaConfig;
+use super::{
+    advance_intake_index, build_report, catalog, collect_facts, format_facts_summary,
+    format_legal_analysis, mark_intake_done, report_disclaimer, report_tone, save_answer,
+    ReportContent, DEFAULT_RISK_NOTICE, PROCESS_PATH,
+};
+use crate::error::{CoreError, CoreResult};
+use crate::region::{DeploymentRegion, UserTimeZone};
+use crate::retrieval::RetrievalEngine;
+use crate::storage::SqliteStorage;
+use crate::tools::venue::venue_fallback;
+
+/// Unix seconds `initialize_first_run` finished at. Not scoped to a profile.
+pub const ONBOARDING_COMPLETE_KEY: &str = "onboarding:completed_at";
+
+/// Scenario of the demo session when the host names none.
+pub const DEFAULT_DEMO_SCENARIO: &str = "labor";
+const DEMO_SEARCH_LIMIT: usize = 3;
+
+/// The sample case a demo session of one scenario is filled with.
+pub struct DemoCase {
+    pub scenario: &'static str,
+    pub title: &'static str,
+    pub question: &'static str,
+    /// Intake answers in question order; empty for scenarios without an
+    /// intake, whose facts are the question itself.
+    pub answers: &'static [&'static str],
+    search_query: &'static str,
+}
+
+pub const DEMO_CASES: [DemoCase; 5] = [
+    DemoCase {
+        scenario: "labor",
+        title: "示例：拖欠工资",
+        question: "公司拖欠了我三个月工资，我该怎么办？",
+        answers: &[
+            "广东深圳",
+            "2022年3月入职，签了劳动合同",
+            "做销售，月薪8000元",
+            "拖欠三个月，一共24000元",
+            "补发工资",
+            "劳动合同和工资流水",
+        ],
+        search_query: "拖欠工资 劳动仲裁",
+    },
+    DemoCase {
+        scenario: "rental",
+        title: "示例：押金不退",
+        question: "退租时房东以墙面有划痕为由扣下了全部押金3000元，我该怎么办？",
+        answers: &[],
+        search_query: "押金 退还 租赁",
+    },
+    DemoCase {
+        scenario: "consumer",
+        title: "示例：网购退货",
+        question: "网购的耳机七天内申请无理由退货，商家拒绝退款，我该怎么办？",
+        answers: &[],
+        search_query: "七日无理由退货 消费者",
+    },
+    DemoCase {
+        scenario: "family",
+        title: "示例：离婚抚养",
+        question: "准备协议离婚，孩子三岁，抚养权和抚养费应该怎么约定？",
+        answers: &[],
+        search_query: "离婚 子女抚养 抚养费",
+    },
+    DemoCase {
+        scenario: "traffic",
+        title: "示例：交通事故赔偿",
+        question: "骑电动车被汽车撞伤，交警认定对方全责，医药费和误工费怎么索赔？",
+        answers: &[],
+        search_query: "交通事故 损害赔偿 责任",
+    },
+];
+
+/// The demo case of `scenario`.
+pub fn demo_case(scenario: &str) -> CoreResult<&'static DemoCase> {
+    DEMO_CASES
+        .iter()
+        .find(|case| case.scenario == scenario)
+        .ok_or_else(|| CoreError::Config(format!("no demo case for scenario {scenario}")))
+}
+
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct ToolPermissionSeed {
+    pub tool_name: String,
+    /// `allow`, `ask` or `deny`.
+    pub permission: String,
+}
+
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct CopySeed {
+    /// Copy key, e.g. `intake.ack.1`; see `list_copy_keys`.
+    pub key: String,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, uniffi::Record)]
+pub struct FirstRunOptions {
+    /// Directory whose files are copied into `kb_path`; files already
+    /// there are kept.
+    #[uniffi(default = None)]
+    pub starter_kb_path: Option<String>,
+    /// Default tool permissions, below the permission policy: what the
+    /// user sets with `set_tool_permission` still wins.
+    #[uniffi(default = [])]
+    pub tool_permissions: Vec<ToolPermissionSeed>,
+    /// Copy in the configured locale, for keys without an override yet.
+    #[uniffi(default = [])]
+    pub copy_overrides: Vec<CopySeed>,
+    /// The assistant's persona, unless one is set already; see
+    /// `set_persona_config`.
+    #[uniffi(default = None)]
+    pub persona: Option<PersonaConfig>,
+    /// Add a session with a sample case and report.
+    #[uniffi(default = false)]
+    pub create_demo_session: bool,
+    /// Scenario of the demo session; defaults to `labor`.
+    #[uniffi(default = None)]
+    pub demo_scenario: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, uniffi::Record)]
+pub struct FirstRunResult {
+    /// Onboarding had already been done; nothing was changed.
+    pub already_completed: bool,
+    /// KB paths copied, relative to `kb_path`.
+    pub kb_files_copied: Vec<String>,
+    pub permissions_seeded: u32,
+    pub copy_overrides_seeded: u32,
+    pub persona_seeded: bool,
+    pub demo_session_id: Option<String>,
+}
+
+pub fn onboarding_completed_at(storage: &SqliteStorage) -> CoreResult<Option<i64>> {
+    Ok(storage
+        .get_setting(ONBOARDING_COMPLETE_KEY)?
+        .and_then(|raw| raw.parse().ok()))
+}
+
+/// Copy the files under `source` into `kb_root`, keeping the layout and
+/// any file `kb_root` already has. Returns the copied relative paths.
+pub fn copy_starter_kb(source: &Path, kb_root: &Path) -> CoreResult<Vec<String>> {
+    if !source.is_dir() {
+        return Err(CoreError::Config(format!(
+            "starter KB {} is not a directory",
+            source.display()
+        )));
+    }
+    let mut copied = Vec::new();
+    for entry in WalkDir::new(source).sort_by_file_name() {
+        let entry = entry.map_err(|e| CoreError::Storage(format!("read starter KB: {e}")))?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative = entry
+            .path()
+            .strip_prefix(source)
+            .map_err(|e| CoreError::Unknown(e.to_string()))?;
+        let target = kb_root.join(relative);
+        if target.exists() {
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| CoreError::Storage(format!("create {}: {e}", parent.display())))?;
+        }
+        fs::copy(entry.path(), &target)
+            .map_err(|e| CoreError::Storage(format!("copy {}: {e}", relative.display())))?;
+        copied.push(
+            relative
+                .components()
+                .map(|part| part.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/"),
+        );
+    }
+    Ok(copied)
+}
+
+/// Answer the demo session's intake with the case's answers and mark it
+/// done.
+pub fn seed_demo_intake(
+    storage: &SqliteStorage,
+    session_id: &str,
+    case: &DemoCase,
+) -> CoreResult<()> {
+    storage.with_tx(|| {
+        for (idx, answer) in case.answers.iter().enumerate() {
+            save_answer(storage, session_id, idx, answer, None)?;
+        }
+        advance_intake_index(storage, session_id, case.answers.len())?;
+        mark_intake_done(storage, session_id)
+    })
+}
+
+/// Facts summary of the demo session: its intake answers, or the question
+/// for scenarios without an intake.
+pub fn demo_facts_summary(
+    storage: &SqliteStorage,
+    session_id: &str,
+    case: &DemoCase,
+) -> CoreResult<String> {
+    let facts = collect_facts(storage, session_id, case.scenario)?;
+    if facts.is_empty() {
+        return Ok(format_facts_summary(&[(
+            "咨询问题".to_owned(),
+            case.question.to_owned(),
+        )]));
+    }
+    Ok(format_facts_summary(&facts))
+}
+
+/// Report of the demo session, written from its answers and the KB
+/// without a model.
+pub fn demo_report(
+    storage: &SqliteStorage,
+    retrieval: &RetrievalEngine,
+    session_id: &str,
+    case: &DemoCase,
+    region: DeploymentRegion,
+    zone: &UserTimeZone,
+    now: DateTime<Utc>,
+) -> CoreResult<String> {
+    let results = retrieval.search(case.search_query, case.scenario, DEMO_SEARCH_LIMIT)?;
+    Ok(build_report(
+        report_tone(storage, session_id)?,
+        &ReportContent {
+            confidence: "",
+            facts_summary: &demo_facts_summary(storage, session_id, case)?,
+            evidence_summary: "",
+            legal_analysis: &format_legal_analysis(&catalog::report_copy(storage), &results),
+            process_path: PROCESS_PATH,
+            venue: venue_fallback(case.scenario),
+            risk_notice: DEFAULT_RISK_NOTICE,
+            disclaimer: &report_disclaimer(region, now, zone),
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::{copy_starter_kb, demo_case, DEMO_CASES};
+    use crate::tools::intake_questions_for_scenario;
+
+    #[test]
+    fn starter_kb_fills_gaps_without_overwriting() {
+        let source = TempDir::new().expect("source");
+        std::fs::create_dir_all(source.path().join("labor")).expect("mkdir");
+        std::fs::write(source.path().join("labor/wage.md"), "# 欠薪\n拖欠工资").expect("write");
+        std::fs::write(source.path().join("README.md"), "starter").expect("write");
+        let kb = TempDir::new().expect("kb");
+        std::fs::write(kb.path().join("README.md"), "mine").expect("write");
+
+        let copied = copy_starter_kb(source.path(), kb.path()).expect("copy");
+        assert_eq!(copied, ["labor/wage.md"]);
+        assert_eq!(
+            std::fs::read_to_string(kb.path().join("README.md")).expect("read"),
+            "mine"
+        );
+        assert!(copy_starter_kb(source.path(), kb.path())
+            .expect("copy again")
+            .is_empty());
+        assert!(copy_starter_kb(&source.path().join("missing"), kb.path()).is_err());
+    }
+
+    #[test]
+    fn demo_cases_fit_their_scenario_intake() {
+        for case in &DEMO_CASES {
+            let questions = intake_questions_for_scenario(case.scenario);
+            assert!(
+                case.answers.is_empty() || case.answers.len() == questions.len(),
+                "{} answers do not match its intake",
+                case.scenario
+            );
+        }
+        assert!(demo_case("rental").is_ok());
+        assert!(demo_case("tax").is_err());
+    }
+}
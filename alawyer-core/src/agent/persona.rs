@@ -0,0 +1,237 @@
+//! The assistant's persona as a deployment configures it: the tone it
+//! writes in, what it must never do, and phrases it may use. Stored in the
+//! `persona:config` setting and added to the system prompts of the model
+//! calls whose output the user reads: intake acknowledgements, quick
+//! answers, what-if answers, the report style pass and report
+//! translations.
+//!
+//! The persona cannot loosen the safety layer: tone and signature phrases
+//! the safety rules would rewrite are refused when the persona is set, and
+//! so are prohibitions that would drop a safeguard the core adds.
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{CoreError, CoreResult};
+use crate::model::ChatMessage;
+use crate::safety::SafetyEngine;
+use crate::storage::SqliteStorage;
+
+const PERSONA_KEY: &str = "persona:config";
+/// Entries per list, and characters per entry.
+const MAX_ENTRIES: usize = 10;
+const MAX_ENTRY_CHARS: usize = 100;
+/// What the core adds to keep users safe; a `forbidden` entry naming one,
+/// such as `不要附免责声明`, would tell the model to leave it out.
+const SAFEGUARDS: [&str; 8] = [
+    "免责声明",
+    "风险提示",
+    "安全审查",
+    "求助",
+    "热线",
+    "法律援助",
+    "12348",
+    "报警",
+];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, uniffi::Record)]
+pub struct PersonaConfig {
+    /// How replies should read, e.g. `温和体贴` or `简明扼要`.
+    #[serde(default)]
+    pub style: Vec<String>,
+    /// Things the assistant must never do, e.g. `不推测当事人的刑事责任`.
+    #[serde(default)]
+    pub forbidden: Vec<String>,
+    /// Phrases the assistant may use, e.g. a greeting or a sign-off.
+    #[serde(default)]
+    pub signature_phrases: Vec<String>,
+}
+
+impl PersonaConfig {
+    pub fn is_empty(&self) -> bool {
+        self.style.is_empty() && self.forbidden.is_empty() && self.signature_phrases.is_empty()
+    }
+
+    /// The instructions added to a system prompt; empty for an empty
+    /// persona.
+    pub fn instructions(&self) -> String {
+        let mut lines = Vec::new();
+        if !self.style.is_empty() {
+            lines.push(format!("语气要求：{}。", self.style.join("、")));
+        }
+        if !self.forbidden.is_empty() {
+            lines.push(format!("始终遵守：{}。", self.forbidden.join("；")));
+        }
+        if !self.signature_phrases.is_empty() {
+            lines.push(format!(
+                "合适时可以使用这些表达：{}。",
+                self.signature_phrases
+                    .iter()
+                    .map(|phrase| format!("“{phrase}”"))
+                    .collect::<Vec<_>>()
+                    .join("、")
+            ));
+        }
+        lines.join("\n")
+    }
+}
+
+/// `config` trimmed and deduplicated, or why it cannot be used: entries
+/// must be single short lines, tone and signature phrases must pass the
+/// safety rules unchanged, and prohibitions may not name a safeguard. The
+/// safety rules do not read prohibitions, which quote what they forbid.
+pub fn normalize(config: PersonaConfig, safety: &SafetyEngine) -> CoreResult<PersonaConfig> {
+    let config = PersonaConfig {
+        style: normalize_entries("style", config.style)?,
+        forbidden: normalize_entries("forbidden", config.forbidden)?,
+        signature_phrases: normalize_entries("signature_phrases", config.signature_phrases)?,
+    };
+    for entry in config
+        .style
+        .iter()
+        .chain(&config.forbidden)
+        .chain(&config.signature_phrases)
+    {
+        if entry.contains('【') {
+            return Err(CoreError::Config(format!(
+                "persona entry {entry} looks like a report section heading"
+            )));
+        }
+    }
+    for entry in &config.forbidden {
+        if let Some(safeguard) = SAFEGUARDS.iter().find(|word| entry.contains(*word)) {
+            return Err(CoreError::Safety(format!(
+                "persona entry {entry} would suppress the {safeguard} the core adds"
+            )));
+        }
+    }
+    for entry in config.style.iter().chain(&config.signature_phrases) {
+        let check = safety.check(entry);
+        if let Some(issue) = check.issues.first() {
+            return Err(CoreError::Safety(format!(
+                "persona entry {entry} conflicts with safety rule {}",
+                issue.rule_name
+            )));
+        }
+    }
+    Ok(config)
+}
+
+fn normalize_entries(field: &str, entries: Vec<String>) -> CoreResult<Vec<String>> {
+    let mut normalized: Vec<String> = Vec::new();
+    for entry in entries {
+        let entry = entry.trim();
+        if entry.is_empty() || normalized.iter().any(|kept| kept == entry) {
+            continue;
+        }
+        if entry.contains('\n') || entry.chars().count() > MAX_ENTRY_CHARS {
+            return Err(CoreError::Config(format!(
+                "persona {field} entries must be one line of at most {MAX_ENTRY_CHARS} characters"
+            )));
+        }
+        normalized.push(entry.to_owned());
+    }
+    if normalized.len() > MAX_ENTRIES {
+        return Err(CoreError::Config(format!(
+            "persona {field} has more than {MAX_ENTRIES} entries"
+        )));
+    }
+    Ok(normalized)
+}
+
+pub fn load(storage: &SqliteStorage) -> CoreResult<PersonaConfig> {
+    let Some(raw) = storage.get_setting(PERSONA_KEY)? else {
+        return Ok(PersonaConfig::default());
+    };
+    serde_json::from_str(&raw)
+        .map_err(|e| CoreError::Config(format!("invalid persona config: {e}")))
+}
+
+/// Store `config`, already normalized; an empty one clears the persona.
+pub fn save(storage: &SqliteStorage, config: &PersonaConfig) -> CoreResult<()> {
+    if config.is_empty() {
+        return storage.delete_setting(PERSONA_KEY);
+    }
+    let raw = serde_json::to_string(config)
+        .map_err(|e| CoreError::Unknown(format!("serialize persona config failed: {e}")))?;
+    storage.set_setting(PERSONA_KEY, &raw)
+}
+
+/// Add the stored persona to the system prompt of `messages`. A persona
+/// that cannot be read is logged and left out; the call goes ahead.
+pub fn apply(storage: &SqliteStorage, messages: &mut [ChatMessage]) {
+    let instructions = match load(storage) {
+        Ok(config) => config.instructions(),
+        Err(err) => {
+            tracing::warn!("persona not applied: {err}");
+            return;
+        }
+    };
+    if instructions.is_empty() {
+        return;
+    }
+    if let Some(system) = messages.iter_mut().find(|message| message.role == "system") {
+        system.content = format!("{}\n\n{instructions}", system.content);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize, PersonaConfig};
+    use crate::error::CoreError;
+    use crate::safety::SafetyEngine;
+
+    #[test]
+    fn persona_is_normalized_and_checked_against_safety_rules() {
+        let safety = SafetyEngine::default();
+        let config = normalize(
+            PersonaConfig {
+                style: vec![
+                    " 温和体贴 ".to_owned(),
+                    "温和体贴".to_owned(),
+                    String::new(),
+                ],
+                forbidden: vec!["不推测当事人的刑事责任".to_owned()],
+                signature_phrases: vec!["祝您顺利".to_owned()],
+            },
+            &safety,
+        )
+        .expect("valid persona");
+        assert_eq!(config.style, ["温和体贴"]);
+        let instructions = config.instructions();
+        assert!(instructions.contains("语气要求：温和体贴。"));
+        assert!(instructions.contains("不推测当事人的刑事责任"));
+        assert!(instructions.contains("“祝您顺利”"));
+        assert!(PersonaConfig::default().instructions().is_empty());
+
+        let unsafe_phrase = PersonaConfig {
+            signature_phrases: vec!["保证胜诉".to_owned()],
+            ..PersonaConfig::default()
+        };
+        assert!(matches!(
+            normalize(unsafe_phrase, &safety),
+            Err(CoreError::Safety(_))
+        ));
+        let multiline = PersonaConfig {
+            style: vec!["简明\n扼要".to_owned()],
+            ..PersonaConfig::default()
+        };
+        assert!(matches!(
+            normalize(multiline, &safety),
+            Err(CoreError::Config(_))
+        ));
+        let dropped_safeguard = PersonaConfig {
+            forbidden: vec!["不要附免责声明".to_owned()],
+            ..PersonaConfig::default()
+        };
+        assert!(matches!(
+            normalize(dropped_safeguard, &safety),
+            Err(CoreError::Safety(_))
+        ));
+        // Prohibitions quote what they forbid.
+        let prohibition = PersonaConfig {
+            forbidden: vec!["不说保证胜诉".to_owned()],
+            ..PersonaConfig::default()
+        };
+        assert!(normalize(prohibition, &safety).is_ok());
+    }
+}
@@ -0,0 +1,282 @@
+//! Retrieval planning for the legal analysis section: instead of one coarse
+//! query, derive a few targeted ones from the intake facts.
+
+use super::CaseFact;
+
+/// Queries sent per report, the coarse query included.
+pub const MAX_PLANNED_QUERIES: usize = 5;
+
+/// A legal question worth its own KB query. It applies when one of
+/// `fields` is answered, or when a keyword shows up in the user's text or
+/// in any answer.
+struct RetrievalTopic {
+    name: &'static str,
+    query: &'static str,
+    fields: &'static [&'static str],
+    keywords: &'static [&'static str],
+}
+
+/// Results asked of the KB per planned query, so a longer plan does not
+/// thin out each topic's hits.
+pub const RESULTS_PER_QUERY: usize = 3;
+
+/// Labor topics, in priority order; the first ones win when more apply
+/// than fit.
+const LABOR_TOPICS: [RetrievalTopic; 6] = [
+    RetrievalTopic {
+        name: "欠薪",
+        query: "拖欠工资 劳动报酬 足额支付",
+        fields: &["arrears"],
+        keywords: &["拖欠", "欠薪", "欠工资", "克扣"],
+    },
+    RetrievalTopic {
+        name: "经济补偿",
+        query: "解除劳动合同 经济补偿 赔偿金",
+        fields: &[],
+        keywords: &["补偿", "赔偿", "解除", "辞退", "开除", "裁员", "N+1"],
+    },
+    RetrievalTopic {
+        name: "仲裁时效",
+        query: "劳动争议 仲裁时效 一年",
+        fields: &["hire_date", "arrears"],
+        keywords: &["时效", "离职", "多久"],
+    },
+    RetrievalTopic {
+        name: "举证责任",
+        query: "举证责任 证据材料 用人单位",
+        fields: &["evidence"],
+        keywords: &["证据", "举证", "录音", "聊天记录"],
+    },
+    RetrievalTopic {
+        name: "未签合同",
+        query: "未签订书面劳动合同 二倍工资",
+        fields: &[],
+        keywords: &["没签", "未签", "没有签", "没有合同"],
+    },
+    RetrievalTopic {
+        name: "加班费",
+        query: "加班费 计算标准",
+        fields: &[],
+        keywords: &["加班"],
+    },
+];
+
+const RENTAL_TOPICS: [RetrievalTopic; 3] = [
+    RetrievalTopic {
+        name: "押金",
+        query: "租赁合同 押金 返还",
+        fields: &[],
+        keywords: &["押金", "定金", "退租"],
+    },
+    RetrievalTopic {
+        name: "解除租约",
+        query: "租赁合同 解除 违约金",
+        fields: &[],
+        keywords: &["解约", "违约", "赶走", "提前"],
+    },
+    RetrievalTopic {
+        name: "维修义务",
+        query: "出租人 维修义务 租赁物",
+        fields: &[],
+        keywords: &["维修", "漏水", "损坏"],
+    },
+];
+
+const CONSUMER_TOPICS: [RetrievalTopic; 3] = [
+    RetrievalTopic {
+        name: "无理由退货",
+        query: "网络购买商品 七日无理由退货",
+        fields: &[],
+        keywords: &["退货", "七天无理由", "网购"],
+    },
+    RetrievalTopic {
+        name: "惩罚性赔偿",
+        query: "经营者 欺诈 惩罚性赔偿 三倍",
+        fields: &[],
+        keywords: &["假货", "欺诈", "赔偿", "三倍"],
+    },
+    RetrievalTopic {
+        name: "质量问题",
+        query: "商品质量 修理 更换 退货",
+        fields: &[],
+        keywords: &["质量", "坏了", "售后", "维修"],
+    },
+];
+
+const FAMILY_TOPICS: [RetrievalTopic; 3] = [
+    RetrievalTopic {
+        name: "抚养",
+        query: "离婚 子女抚养 抚养费",
+        fields: &[],
+        keywords: &["抚养", "孩子", "探视"],
+    },
+    RetrievalTopic {
+        name: "财产分割",
+        query: "夫妻共同财产 分割",
+        fields: &[],
+        keywords: &["财产", "房子", "存款", "债务"],
+    },
+    RetrievalTopic {
+        name: "彩礼",
+        query: "彩礼 返还",
+        fields: &[],
+        keywords: &["彩礼"],
+    },
+];
+
+const TRAFFIC_TOPICS: [RetrievalTopic; 3] = [
+    RetrievalTopic {
+        name: "责任认定",
+        query: "交通事故 责任认定 过错",
+        fields: &[],
+        keywords: &["责任认定", "全责", "主责", "交警"],
+    },
+    RetrievalTopic {
+        name: "保险赔付",
+        query: "机动车 交强险 赔偿 限额",
+        fields: &[],
+        keywords: &["保险", "交强险", "理赔"],
+    },
+    RetrievalTopic {
+        name: "人身损害",
+        query: "人身损害 医疗费 误工费 赔偿",
+        fields: &[],
+        keywords: &["受伤", "医疗费", "误工", "住院"],
+    },
+];
+
+/// The coarse query prefix and topics of `scenario`; unknown scenarios get
+/// the coarse query alone.
+fn scenario_topics(scenario: &str) -> (&'static str, &'static [RetrievalTopic]) {
+    match scenario {
+        "labor" => ("劳动仲裁", &LABOR_TOPICS),
+        "rental" => ("房屋租赁纠纷", &RENTAL_TOPICS),
+        "consumer" => ("消费者权益保护", &CONSUMER_TOPICS),
+        "family" => ("婚姻家庭纠纷", &FAMILY_TOPICS),
+        "traffic" => ("道路交通事故赔偿", &TRAFFIC_TOPICS),
+        _ => ("法律咨询", &[]),
+    }
+}
+
+/// One KB query of a retrieval plan.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedQuery {
+    /// Short name for traces, e.g. `仲裁时效`.
+    pub topic: &'static str,
+    pub query: String,
+    /// Fact fields the topic follows; empty for the coarse query.
+    pub fields: &'static [&'static str],
+}
+
+/// The coarse query (`劳动仲裁 + user text` for labor) first, then the
+/// `scenario` topics the facts point at, at most `MAX_PLANNED_QUERIES` in
+/// all.
+pub fn plan_queries(scenario: &str, facts: &[CaseFact], user_content: &str) -> Vec<PlannedQuery> {
+    let (coarse, topics) = scenario_topics(scenario);
+    let user_content = user_content.trim();
+    let mut plan = vec![PlannedQuery {
+        topic: "综合",
+        query: if user_content.is_empty() {
+            coarse.to_owned()
+        } else {
+            format!("{coarse} {user_content}")
+        },
+        fields: &[],
+    }];
+
+    let answered = facts
+        .iter()
+        .filter_map(|fact| Some((fact.field.as_str(), fact.value.as_deref()?)))
+        .collect::<Vec<_>>();
+    let mentions = |keyword: &str| {
+        user_content.contains(keyword) || answered.iter().any(|(_, value)| value.contains(keyword))
+    };
+    plan.extend(
+        topics
+            .iter()
+            .filter(|topic| {
+                topic
+                    .fields
+                    .iter()
+                    .any(|field| answered.iter().any(|(answered, _)| answered == field))
+                    || topic.keywords.iter().any(|keyword| mentions(keyword))
+            })
+            .map(|topic| PlannedQuery {
+                topic: topic.name,
+                query: topic.query.to_owned(),
+                fields: topic.fields,
+            }),
+    );
+    plan.truncate(MAX_PLANNED_QUERIES);
+    plan
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{plan_queries, MAX_PLANNED_QUERIES};
+    use crate::agent::CaseFact;
+
+    fn fact(field: &str, value: Option<&str>) -> CaseFact {
+        CaseFact {
+            field: field.to_owned(),
+            label: field.to_owned(),
+            value: value.map(ToOwned::to_owned),
+            normalized_value: None,
+            confirmed: true,
+            source_message_id: None,
+        }
+    }
+
+    #[test]
+    fn topics_follow_the_facts() {
+        let topics = |facts: &[CaseFact], text: &str| {
+            plan_queries("labor", facts, text)
+                .into_iter()
+                .map(|query| query.topic)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(topics(&[], ""), ["综合"]);
+        assert_eq!(plan_queries("labor", &[], "")[0].query, "劳动仲裁");
+        assert_eq!(
+            topics(
+                &[
+                    fact("hire_date", Some("2022年3月入职，没签合同")),
+                    fact("arrears", None),
+                    fact("goal", Some("补发工资")),
+                ],
+                "",
+            ),
+            ["综合", "仲裁时效", "未签合同"]
+        );
+        assert_eq!(
+            topics(
+                &[
+                    fact("arrears", Some("拖欠三个月")),
+                    fact("goal", Some("经济补偿")),
+                    fact("evidence", Some("工资流水")),
+                ],
+                "周末加班也没给加班费",
+            )
+            .len(),
+            MAX_PLANNED_QUERIES
+        );
+    }
+
+    #[test]
+    fn topics_follow_the_scenario() {
+        let plan = plan_queries("rental", &[], "房东不退押金");
+        assert_eq!(plan[0].query, "房屋租赁纠纷 房东不退押金");
+        assert_eq!(
+            plan.iter().map(|query| query.topic).collect::<Vec<_>>(),
+            ["综合", "押金"]
+        );
+        assert_eq!(
+            plan_queries("traffic", &[fact("region", Some("拖欠工资"))], "")
+                .into_iter()
+                .map(|query| query.topic)
+                .collect::<Vec<_>>(),
+            ["综合"]
+        );
+    }
+}
@@ -0,0 +1,102 @@
+//! Progress of report generation as announced in `task_progress` events.
+//! Sections take unequal time, so the estimate is coarse: each section sent
+//! counts as one step and the safety review as the last one.
+
+use super::catalog::{CopyCatalog, CopyKey};
+use super::AgentPhase;
+
+/// Where a report task is, in steps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReportProgress {
+    pub phase: AgentPhase,
+    pub completed: u32,
+    pub total: u32,
+}
+
+impl ReportProgress {
+    /// `sections_done` of a report with `sections` sections drafted.
+    pub fn drafting(sections_done: usize, sections: usize) -> Self {
+        Self {
+            phase: AgentPhase::Draft,
+            completed: sections_done.min(sections) as u32,
+            total: sections as u32 + 1,
+        }
+    }
+
+    /// All `sections` drafted, the review running.
+    pub fn reviewing(sections: usize) -> Self {
+        Self {
+            phase: AgentPhase::Review,
+            completed: sections as u32,
+            total: sections as u32 + 1,
+        }
+    }
+
+    pub fn done(sections: usize) -> Self {
+        Self {
+            phase: AgentPhase::Review,
+            completed: sections as u32 + 1,
+            total: sections as u32 + 1,
+        }
+    }
+
+    /// Rounded down, so 100 only once the report is done.
+    pub fn percent(self) -> u32 {
+        self.completed.min(self.total) * 100 / self.total.max(1)
+    }
+
+    pub fn label(self, copy: &CopyCatalog<'_>) -> String {
+        let percent = self.percent().to_string();
+        if self.completed >= self.total {
+            return copy.text(CopyKey::ProgressDone);
+        }
+        match self.phase {
+            AgentPhase::Review => copy.render(CopyKey::ProgressReviewing, &[("percent", &percent)]),
+            AgentPhase::Plan | AgentPhase::Draft => copy.render(
+                CopyKey::ProgressDrafting,
+                &[
+                    ("completed", &self.completed.to_string()),
+                    // Sections only; the review step is not a "part".
+                    ("total", &(self.total - 1).to_string()),
+                    ("percent", &percent),
+                ],
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tempfile::TempDir;
+
+    use super::ReportProgress;
+    use crate::agent::catalog::CopyCatalog;
+    use crate::clock::SystemClock;
+    use crate::storage::SqliteStorage;
+
+    #[test]
+    fn percent_counts_the_review_as_a_step() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let storage = SqliteStorage::new(temp_dir.path().join("core.db"), Arc::new(SystemClock))
+            .expect("storage");
+        let copy = CopyCatalog::new(&storage, "zh-CN");
+
+        let drafting = ReportProgress::drafting(3, 7);
+        assert_eq!(drafting.percent(), 37);
+        assert_eq!(
+            drafting.label(&copy),
+            "正在撰写报告，已完成 3/7 部分，约 37%"
+        );
+        let reviewing = ReportProgress::reviewing(7);
+        assert_eq!(reviewing.percent(), 87);
+        assert_eq!(reviewing.label(&copy), "正在进行安全审查，约 87%");
+        assert_eq!(ReportProgress::done(7).percent(), 100);
+        assert_eq!(ReportProgress::done(7).label(&copy), "报告已生成");
+        assert_eq!(
+            ReportProgress::drafting(1, 7).label(&CopyCatalog::new(&storage, "en")),
+            "Writing the report: 1 of 7 parts done, about 12%"
+        );
+    }
+}
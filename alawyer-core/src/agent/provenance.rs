@@ -0,0 +1,251 @@
+//! Which intake answers and KB chunks each section of a report, and each
+//! statement drafted from a single source, was written from, so a reader
+//! can check a claim against its source.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::catalog::{CopyCatalog, CopyKey};
+use super::ReportSection;
+use crate::error::{CoreError, CoreResult};
+use crate::storage::SqliteStorage;
+
+/// Sources of one report section.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, uniffi::Record)]
+pub struct SectionProvenance {
+    /// `ReportSection` id, e.g. `legal_analysis`.
+    pub section: String,
+    pub title: String,
+    /// `CaseFact::field` of each intake answer the section draws on.
+    pub fact_fields: Vec<String>,
+    /// KB chunks the section cites; see `get_knowledge_chunk`.
+    pub chunk_ids: Vec<String>,
+    /// Lines of the section drawn from one answer or one chunk, in report
+    /// order. Empty in records written before statements were tracked.
+    #[serde(default)]
+    pub statements: Vec<StatementProvenance>,
+}
+
+/// One line of a report section and the sources it restates.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, uniffi::Record)]
+pub struct StatementProvenance {
+    /// The line as drafted, before the style pass and the safety review.
+    pub text: String,
+    pub fact_fields: Vec<String>,
+    pub chunk_ids: Vec<String>,
+}
+
+impl SectionProvenance {
+    pub fn new(section: ReportSection, fact_fields: Vec<String>, chunk_ids: Vec<String>) -> Self {
+        Self {
+            section: section.id().to_owned(),
+            title: section.title().to_owned(),
+            fact_fields,
+            chunk_ids,
+            statements: Vec::new(),
+        }
+    }
+
+    pub fn with_statements(mut self, statements: Vec<StatementProvenance>) -> Self {
+        self.statements = statements;
+        self
+    }
+
+    fn is_empty(&self) -> bool {
+        self.fact_fields.is_empty() && self.chunk_ids.is_empty() && self.statements.is_empty()
+    }
+}
+
+/// Sources of the session's latest report, in report order. Sections
+/// written from neither facts nor the KB are left out. Sealed like message
+/// content, since statements quote the draft.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, uniffi::Record)]
+pub struct ReportProvenance {
+    pub message_id: String,
+    pub sections: Vec<SectionProvenance>,
+}
+
+impl ReportProvenance {
+    pub fn new(message_id: String, sections: Vec<SectionProvenance>) -> Self {
+        Self {
+            message_id,
+            sections: sections
+                .into_iter()
+                .filter(|section| !section.is_empty())
+                .collect(),
+        }
+    }
+}
+
+fn report_provenance_key(session_id: &str) -> String {
+    format!("session:{session_id}:report_provenance")
+}
+
+pub fn record_report_provenance(
+    storage: &SqliteStorage,
+    session_id: &str,
+    provenance: &ReportProvenance,
+) -> CoreResult<()> {
+    let raw = serde_json::to_string(provenance)
+        .map_err(|e| CoreError::Unknown(format!("serialize report provenance failed: {e}")))?;
+    storage.set_sealed_setting(&report_provenance_key(session_id), &raw)
+}
+
+pub fn report_provenance(
+    storage: &SqliteStorage,
+    session_id: &str,
+) -> CoreResult<Option<ReportProvenance>> {
+    Ok(storage
+        .get_sealed_setting(&report_provenance_key(session_id))?
+        .and_then(|raw| serde_json::from_str(&raw).ok()))
+}
+
+/// `report` with a `[^n]` marker after the heading of each section in
+/// `provenance` and after each of its statements still in the report, and
+/// the matching footnotes at the end. `labels` names fact fields; unknown
+/// fields appear as is. Sections whose heading the report no longer has get
+/// no footnote, nor do statements the style pass or the safety review
+/// rewrote.
+pub fn add_footnotes(
+    report: &str,
+    provenance: &ReportProvenance,
+    labels: &HashMap<String, String>,
+    copy: &CopyCatalog,
+) -> String {
+    let mut annotated = report.trim_end().to_owned();
+    let mut footnotes = Vec::new();
+    for section in &provenance.sections {
+        let heading = format!("【{}】", section.title);
+        let Some(at) = annotated.find(&heading) else {
+            continue;
+        };
+        let mut cursor = at + heading.len();
+        if !section.fact_fields.is_empty() || !section.chunk_ids.is_empty() {
+            let marker = format!("[^{}]", footnotes.len() + 1);
+            annotated.insert_str(cursor, &marker);
+            cursor += marker.len();
+            footnotes.push(sources(
+                &section.fact_fields,
+                &section.chunk_ids,
+                labels,
+                copy,
+            ));
+        }
+        for statement in &section.statements {
+            let Some(offset) = annotated[cursor..].find(&statement.text) else {
+                continue;
+            };
+            let marker = format!("[^{}]", footnotes.len() + 1);
+            cursor += offset + statement.text.len();
+            annotated.insert_str(cursor, &marker);
+            cursor += marker.len();
+            footnotes.push(sources(
+                &statement.fact_fields,
+                &statement.chunk_ids,
+                labels,
+                copy,
+            ));
+        }
+    }
+    if footnotes.is_empty() {
+        return report.to_owned();
+    }
+    let footnotes = footnotes
+        .iter()
+        .enumerate()
+        .map(|(idx, sources)| format!("[^{}]: {sources}", idx + 1))
+        .collect::<Vec<_>>();
+    format!("{annotated}\n\n{}\n", footnotes.join("\n"))
+}
+
+/// Footnote text naming `fact_fields` and `chunk_ids`.
+fn sources(
+    fact_fields: &[String],
+    chunk_ids: &[String],
+    labels: &HashMap<String, String>,
+    copy: &CopyCatalog,
+) -> String {
+    let mut sources = Vec::new();
+    if !fact_fields.is_empty() {
+        let facts = fact_fields
+            .iter()
+            .map(|field| labels.get(field).unwrap_or(field).as_str())
+            .collect::<Vec<_>>();
+        sources.push(copy.render(CopyKey::FootnoteFacts, &[("facts", &facts.join("、"))]));
+    }
+    if !chunk_ids.is_empty() {
+        sources.push(copy.render(
+            CopyKey::FootnoteProvisions,
+            &[("chunks", &chunk_ids.join("、"))],
+        ));
+    }
+    sources.join("；")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use tempfile::TempDir;
+
+    use super::{add_footnotes, ReportProvenance, SectionProvenance, StatementProvenance};
+    use crate::agent::catalog::{set_copy_override, CopyCatalog};
+    use crate::agent::ReportSection;
+    use crate::clock::SystemClock;
+    use crate::storage::SqliteStorage;
+
+    #[test]
+    fn footnotes_follow_section_headings_and_statements() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let storage = SqliteStorage::new(temp_dir.path().join("core.db"), Arc::new(SystemClock))
+            .expect("storage");
+        let statement = |text: &str, field: &str| StatementProvenance {
+            text: text.to_owned(),
+            fact_fields: vec![field.to_owned()],
+            chunk_ids: Vec::new(),
+        };
+        let provenance = ReportProvenance::new(
+            "m1".to_owned(),
+            vec![
+                SectionProvenance::new(ReportSection::Facts, Vec::new(), Vec::new())
+                    .with_statements(vec![
+                        statement("- 入职：2022年", "hire_date"),
+                        statement("- 欠薪：三个月", "arrears"),
+                    ]),
+                SectionProvenance::new(ReportSection::Evidence, Vec::new(), Vec::new()),
+                SectionProvenance::new(
+                    ReportSection::LegalAnalysis,
+                    vec!["arrears".to_owned()],
+                    vec!["labor/law.md#L1-L4".to_owned()],
+                ),
+                SectionProvenance::new(ReportSection::Venue, vec!["region".to_owned()], Vec::new()),
+            ],
+        );
+        assert_eq!(provenance.sections.len(), 3);
+
+        let labels = HashMap::from([
+            ("hire_date".to_owned(), "入职时间".to_owned()),
+            ("arrears".to_owned(), "欠薪情况".to_owned()),
+        ]);
+        let report = "【事实摘要】\n- 入职：2022年\n\n【法律分析】\n应足额支付。\n";
+        let copy = CopyCatalog::new(&storage, "zh-CN");
+        assert_eq!(
+            add_footnotes(report, &provenance, &labels, &copy),
+            "【事实摘要】\n- 入职：2022年[^1]\n\n【法律分析】[^2]\n应足额支付。\n\n\
+             [^1]: 事实：入职时间\n\
+             [^2]: 事实：欠薪情况；条文：labor/law.md#L1-L4\n"
+        );
+
+        set_copy_override(
+            &storage,
+            "zh-CN",
+            "footnote.provisions",
+            Some("依据：{chunks}"),
+        )
+        .expect("override");
+        assert!(add_footnotes(report, &provenance, &labels, &copy)
+            .ends_with("[^2]: 事实：欠薪情况；依据：labor/law.md#L1-L4\n"));
+    }
+}
@@ -0,0 +1,91 @@
+//! One-paragraph answers to short questions such as "仲裁要多久", outside
+//! the intake and report pipeline: one KB search and at most one model call.
+
+use crate::model::ChatMessage;
+use crate::retrieval::SearchResult;
+
+use super::assistant_role;
+
+/// Message phase of a quick question and its answer.
+pub const QUICK_PHASE: &str = "quick";
+
+/// Time budget of `quick_ask` when the host sets none.
+pub const DEFAULT_QUICK_ASK_BUDGET_MS: u64 = 5_000;
+
+/// Chunks searched for, and the token budget they are trimmed to.
+pub const QUICK_SEARCH_LIMIT: usize = 3;
+pub const QUICK_CONTEXT_TOKEN_BUDGET: u32 = 800;
+
+pub fn quick_prompt(scenario: &str, question: &str, results: &[SearchResult]) -> Vec<ChatMessage> {
+    let context = results
+        .iter()
+        .map(|result| format!("《{}》{}", result.title.trim(), result.snippet.trim()))
+        .collect::<Vec<_>>()
+        .join("\n");
+    vec![
+        ChatMessage {
+            role: "system".to_owned(),
+            content: format!(
+                "你是{}。请只依据下面的条文，用一段话（不超过150字）简要回答用户的问题；条文不足以回答时直接说明，不要编造。\n\n{context}",
+                assistant_role(scenario)
+            ),
+        },
+        ChatMessage {
+            role: "user".to_owned(),
+            content: question.to_owned(),
+        },
+    ]
+}
+
+/// Answer without a model: the best matching chunk, quoted.
+pub fn template_answer(results: &[SearchResult]) -> String {
+    match results.first() {
+        Some(result) => format!(
+            "《{}》提到：{}",
+            result.title.trim(),
+            result.snippet.replace('\n', " ").trim()
+        ),
+        None => "知识库中暂未找到与这个问题直接相关的内容。".to_owned(),
+    }
+}
+
+pub fn build_quick_answer(body: &str, disclaimer: &str) -> String {
+    format!(
+        "【快速回答】\n{}\n\n这只是简要参考，如需结合你的具体情况分析，可以继续对话生成完整报告。\n\n【免责声明】\n{}",
+        body.trim(),
+        disclaimer
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{build_quick_answer, quick_prompt, template_answer};
+    use crate::retrieval::SearchResult;
+
+    #[test]
+    fn template_quotes_the_best_match() {
+        let result = serde_json::from_value::<SearchResult>(json!({
+            "file_path": "labor/arbitration.md",
+            "title": "劳动仲裁时限",
+            "snippet": "仲裁庭应当自受理申请之日起\n四十五日内结束。",
+            "line_start": 1,
+            "line_end": 3,
+            "score": 1.0
+        }))
+        .expect("search result");
+        assert_eq!(
+            template_answer(std::slice::from_ref(&result)),
+            "《劳动仲裁时限》提到：仲裁庭应当自受理申请之日起 四十五日内结束。"
+        );
+        assert!(template_answer(&[]).contains("暂未找到"));
+        let prompt = quick_prompt("labor", "仲裁要多久", &[result]);
+        assert!(prompt[0].content.starts_with("你是劳动法咨询助手"));
+        assert!(prompt[0].content.contains("四十五日"));
+        assert!(quick_prompt("rental", "押金能退吗", &[])[0]
+            .content
+            .starts_with("你是房屋租赁法律咨询助手"));
+        assert!(build_quick_answer("四十五日。", "仅供参考").ends_with("【免责声明】\n仅供参考"));
+    }
+}
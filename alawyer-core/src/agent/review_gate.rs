@@ -0,0 +1,175 @@
+//! Human review of reports before the user sees them, for deployments with
+//! `CoreConfig::require_report_review`. A finished report is stored
+//! withheld, in `PENDING_REVIEW_PHASE`, and its session waits in
+//! `PENDING_REVIEW_STATUS` until a reviewer approves it, which releases it
+//! to the user, or requests changes, which has it redrafted with the
+//! reviewer's comment and submitted again. Withheld messages are left out
+//! of everything the user is shown.
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{CoreError, CoreResult};
+use crate::storage::{Message, SqliteStorage};
+
+use super::TRANSLATION_PHASE;
+
+/// Phase of a report waiting for review.
+pub const PENDING_REVIEW_PHASE: &str = "pending_review";
+/// Phase of the translation of a report waiting for review.
+pub const PENDING_TRANSLATION_PHASE: &str = "pending_translation";
+/// Phase of a report sent back with `request_changes`, kept for the record.
+pub const CHANGES_REQUESTED_PHASE: &str = "changes_requested";
+/// Session status while its report waits for review.
+pub const PENDING_REVIEW_STATUS: &str = "pending_review";
+
+const SETTING_SUFFIX: &str = ":pending_review";
+
+/// A report waiting for a reviewer.
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct PendingReview {
+    pub session_id: String,
+    pub message_id: String,
+    pub report: String,
+    pub submitted_at: i64,
+    /// What the reviewer asked to change in the previous draft, when this
+    /// one is its redraft.
+    pub previous_comment: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Submission {
+    message_id: String,
+    translation_message_id: Option<String>,
+    submitted_at: i64,
+    previous_comment: Option<String>,
+}
+
+fn submission_key(session_id: &str) -> String {
+    format!("session:{session_id}{SETTING_SUFFIX}")
+}
+
+fn comment_key(session_id: &str) -> String {
+    format!("session:{session_id}:review_comment")
+}
+
+/// Whether `message` is withheld from the user until review.
+pub fn is_withheld(message: &Message) -> bool {
+    matches!(
+        message.phase.as_deref(),
+        Some(PENDING_REVIEW_PHASE | PENDING_TRANSLATION_PHASE | CHANGES_REQUESTED_PHASE)
+    )
+}
+
+/// The reviewer's comment the next draft of the session has to address.
+pub fn changes_comment(storage: &SqliteStorage, session_id: &str) -> CoreResult<Option<String>> {
+    storage.get_setting(&comment_key(session_id))
+}
+
+/// Put the session's withheld report, and its translation, up for review.
+/// The comment it addressed moves onto the submission.
+pub fn submit(
+    storage: &SqliteStorage,
+    session_id: &str,
+    report: &Message,
+    translation: Option<&Message>,
+) -> CoreResult<()> {
+    let submission = Submission {
+        message_id: report.id.clone(),
+        translation_message_id: translation.map(|message| message.id.clone()),
+        submitted_at: report.created_at,
+        previous_comment: changes_comment(storage, session_id)?,
+    };
+    let raw = serde_json::to_string(&submission)
+        .map_err(|e| CoreError::Unknown(format!("serialize review submission failed: {e}")))?;
+    storage.with_tx(|| {
+        storage.set_setting(&submission_key(session_id), &raw)?;
+        storage.delete_setting(&comment_key(session_id))?;
+        storage.update_session_status(session_id, PENDING_REVIEW_STATUS)
+    })
+}
+
+/// Reports waiting for review across sessions, oldest first.
+pub fn pending(storage: &SqliteStorage) -> CoreResult<Vec<PendingReview>> {
+    let mut pending = Vec::new();
+    for setting in storage.list_settings("session:")? {
+        let Some(session_id) = setting
+            .key
+            .strip_prefix("session:")
+            .and_then(|rest| rest.strip_suffix(SETTING_SUFFIX))
+        else {
+            continue;
+        };
+        let Ok(submission) = serde_json::from_str::<Submission>(&setting.value) else {
+            continue;
+        };
+        // A session deleted meanwhile takes its report with it.
+        let Some(report) = storage.get_message(&submission.message_id)? else {
+            continue;
+        };
+        pending.push(PendingReview {
+            session_id: session_id.to_owned(),
+            message_id: submission.message_id,
+            report: report.content,
+            submitted_at: submission.submitted_at,
+            previous_comment: submission.previous_comment,
+        });
+    }
+    pending.sort_by_key(|review| review.submitted_at);
+    Ok(pending)
+}
+
+fn take_submission(storage: &SqliteStorage, session_id: &str) -> CoreResult<Submission> {
+    let key = submission_key(session_id);
+    let submission = storage
+        .get_setting(&key)?
+        .and_then(|raw| serde_json::from_str::<Submission>(&raw).ok())
+        .ok_or_else(|| {
+            CoreError::NotFound(format!("report pending review for session {session_id}"))
+        })?;
+    storage.delete_setting(&key)?;
+    Ok(submission)
+}
+
+/// Release the session's report to the user. Returns the report and the
+/// message the user reads: its translation when there is one, else the
+/// report itself.
+pub fn approve(storage: &SqliteStorage, session_id: &str) -> CoreResult<(Message, Message)> {
+    storage.with_tx(|| {
+        let submission = take_submission(storage, session_id)?;
+        storage.set_message_phase(&submission.message_id, "review")?;
+        if let Some(translation) = &submission.translation_message_id {
+            storage.set_message_phase(translation, TRANSLATION_PHASE)?;
+        }
+        storage.update_session_status(session_id, "active")?;
+        let message = |id: &str| {
+            storage
+                .get_message(id)?
+                .ok_or_else(|| CoreError::NotFound(format!("message {id}")))
+        };
+        let report = message(&submission.message_id)?;
+        let released = match &submission.translation_message_id {
+            Some(translation) => message(translation)?,
+            None => report.clone(),
+        };
+        Ok((report, released))
+    })
+}
+
+/// Send the session's report back with `comment` for the next draft to
+/// address. The report stays withheld.
+pub fn request_changes(storage: &SqliteStorage, session_id: &str, comment: &str) -> CoreResult<()> {
+    let comment = comment.trim();
+    if comment.is_empty() {
+        return Err(CoreError::Config("review comment is empty".to_owned()));
+    }
+    storage.with_tx(|| {
+        let submission = take_submission(storage, session_id)?;
+        for message_id in std::iter::once(&submission.message_id)
+            .chain(submission.translation_message_id.as_ref())
+        {
+            storage.set_message_phase(message_id, CHANGES_REQUESTED_PHASE)?;
+        }
+        storage.set_setting(&comment_key(session_id), comment)?;
+        storage.update_session_status(session_id, "active")
+    })
+}
@@ -0,0 +1,275 @@
+//! Reports packaged for sharing, e.g. with family: callers pick the
+//! sections to keep, and personal details in the kept ones are masked. The
+//! disclaimer always stays; a shared report without it reads as legal
+//! advice.
+
+use crate::error::{CoreError, CoreResult};
+use crate::redact::redact;
+use crate::tools::glossary::GLOSSARY_HEADING;
+
+use super::ReportSection;
+
+/// Section id of the 【名词解释】 section added after review.
+pub const GLOSSARY_SECTION: &str = "glossary";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, uniffi::Enum)]
+pub enum ShareFormat {
+    /// `## 标题` headings.
+    Markdown,
+    /// The report as shown in the app, with 【标题】 headings.
+    Text,
+    /// A standalone page.
+    Html,
+}
+
+impl ShareFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Markdown => "md",
+            Self::Text => "txt",
+            Self::Html => "html",
+        }
+    }
+
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            Self::Markdown => "text/markdown; charset=utf-8",
+            Self::Text => "text/plain; charset=utf-8",
+            Self::Html => "text/html; charset=utf-8",
+        }
+    }
+}
+
+/// Sections by id (`ReportSection::id`, or `glossary`). An empty `include`
+/// keeps every section; `exclude` then drops some.
+#[derive(Debug, Clone, Default, PartialEq, Eq, uniffi::Record)]
+pub struct ReportSectionFilter {
+    #[uniffi(default = [])]
+    pub include: Vec<String>,
+    #[uniffi(default = [])]
+    pub exclude: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct SharedReport {
+    pub file_name: String,
+    pub mime_type: String,
+    pub content: Vec<u8>,
+    /// Ids of the sections kept, in report order.
+    pub sections: Vec<String>,
+    /// Personal details masked in the kept sections.
+    pub redactions: u32,
+    /// Zone the report's dates are in, so the export reads the same
+    /// wherever it is opened.
+    pub time_zone: String,
+}
+
+/// One section of a stored report: its id, title and body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Section {
+    id: &'static str,
+    title: &'static str,
+    body: String,
+}
+
+/// `report` cut down to the sections `filter` keeps, rendered as `format`.
+/// With `redact`, personal details in the kept sections are masked, along
+/// with the `known` ones on record for the case. Returns the rendered text,
+/// the kept section ids and the number of masked details. Text before the
+/// first heading, such as the safety review notice, stays with the report.
+pub fn package_report(
+    report: &str,
+    filter: &ReportSectionFilter,
+    redact_details: bool,
+    known: &[&str],
+    format: ShareFormat,
+) -> CoreResult<(String, Vec<String>, u32)> {
+    for id in filter.include.iter().chain(&filter.exclude) {
+        if section_title(id).is_none() {
+            return Err(CoreError::Config(format!("unknown report section {id}")));
+        }
+    }
+    let (preamble, sections) = split_sections(report);
+    let kept = sections
+        .into_iter()
+        .filter(|section| {
+            section.id == ReportSection::Disclaimer.id()
+                || ((filter.include.is_empty() || filter.include.iter().any(|id| id == section.id))
+                    && !filter.exclude.iter().any(|id| id == section.id))
+        })
+        .collect::<Vec<_>>();
+
+    let mut redactions = 0;
+    let mut mask = |text: &str| {
+        if !redact_details {
+            return text.to_owned();
+        }
+        let (text, count) = redact(text, known);
+        redactions += count;
+        text
+    };
+    let preamble = mask(preamble.trim());
+    let kept = kept
+        .into_iter()
+        .map(|section| Section {
+            body: mask(section.body.trim()),
+            ..section
+        })
+        .collect::<Vec<_>>();
+
+    let ids = kept.iter().map(|section| section.id.to_owned()).collect();
+    Ok((render(&preamble, &kept, format), ids, redactions))
+}
+
+fn section_title(id: &str) -> Option<&'static str> {
+    if id == GLOSSARY_SECTION {
+        return Some(glossary_title());
+    }
+    ReportSection::ALL
+        .iter()
+        .find(|section| section.id() == id)
+        .map(|section| section.title())
+}
+
+fn glossary_title() -> &'static str {
+    GLOSSARY_HEADING
+        .trim_start_matches('【')
+        .trim_end_matches('】')
+}
+
+/// Split at heading lines of known sections only: 【引用】 and the like
+/// inside a section belong to its body.
+fn split_sections(report: &str) -> (String, Vec<Section>) {
+    let mut preamble = String::new();
+    let mut sections: Vec<Section> = Vec::new();
+    for line in report.lines() {
+        let heading = line
+            .trim()
+            .strip_prefix('【')
+            .and_then(|rest| rest.strip_suffix('】'))
+            .and_then(|title| {
+                ReportSection::ALL
+                    .iter()
+                    .map(|section| (section.id(), section.title()))
+                    .chain([(GLOSSARY_SECTION, glossary_title())])
+                    .find(|(_, known)| *known == title)
+            });
+        match (heading, sections.last_mut()) {
+            (Some((id, title)), _) => sections.push(Section {
+                id,
+                title,
+                body: String::new(),
+            }),
+            (None, Some(section)) => {
+                section.body.push_str(line);
+                section.body.push('\n');
+            }
+            (None, None) => {
+                preamble.push_str(line);
+                preamble.push('\n');
+            }
+        }
+    }
+    (preamble, sections)
+}
+
+fn render(preamble: &str, sections: &[Section], format: ShareFormat) -> String {
+    let mut blocks = Vec::new();
+    if !preamble.is_empty() {
+        blocks.push(match format {
+            ShareFormat::Html => paragraphs(preamble),
+            ShareFormat::Markdown | ShareFormat::Text => preamble.to_owned(),
+        });
+    }
+    for section in sections {
+        blocks.push(match format {
+            ShareFormat::Markdown => format!("## {}\n{}", section.title, section.body),
+            ShareFormat::Text => format!("【{}】\n{}", section.title, section.body),
+            ShareFormat::Html => format!(
+                "<h2>{}</h2>\n{}",
+                escape_html(section.title),
+                paragraphs(&section.body)
+            ),
+        });
+    }
+    match format {
+        ShareFormat::Markdown | ShareFormat::Text => blocks.join("\n\n") + "\n",
+        ShareFormat::Html => format!(
+            "<!DOCTYPE html>\n<html lang=\"zh-CN\">\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>法律咨询报告</title>\n</head>\n<body>\n{}\n</body>\n</html>\n",
+            blocks.join("\n")
+        ),
+    }
+}
+
+fn paragraphs(text: &str) -> String {
+    text.split("\n\n")
+        .filter(|paragraph| !paragraph.trim().is_empty())
+        .map(|paragraph| {
+            let lines = paragraph
+                .lines()
+                .map(escape_html)
+                .collect::<Vec<_>>()
+                .join("<br>\n");
+            format!("<p>{lines}</p>")
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub(super) fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{package_report, ReportSectionFilter, ShareFormat};
+
+    const REPORT: &str = "【安全审查】\n检测到 1 处高风险表述。\n\n【事实摘要】\n手机13800138000，身份证11010519900307123X，2024-03-15入职，月薪8000元。\n\n【法律分析】\n可申请仲裁。\n\n【引用】\n- labor/law.md\n\n【免责声明】\n仅供参考。";
+
+    #[test]
+    fn kept_sections_are_masked_and_the_disclaimer_stays() {
+        let filter = ReportSectionFilter {
+            include: vec!["facts".to_owned(), "legal_analysis".to_owned()],
+            exclude: Vec::new(),
+        };
+        let (text, sections, redactions) =
+            package_report(REPORT, &filter, true, &[], ShareFormat::Text).expect("package");
+        assert_eq!(sections, ["facts", "legal_analysis", "disclaimer"]);
+        assert_eq!(redactions, 2);
+        assert!(text.starts_with("【安全审查】"));
+        assert!(text.contains("手机***，身份证***，2024-03-15入职，月薪8000元。"));
+        assert!(text.contains("【引用】\n- labor/law.md"));
+
+        let without_facts = ReportSectionFilter {
+            include: Vec::new(),
+            exclude: vec!["facts".to_owned(), "disclaimer".to_owned()],
+        };
+        let (markdown, sections, redactions) =
+            package_report(REPORT, &without_facts, true, &[], ShareFormat::Markdown)
+                .expect("package");
+        assert_eq!(sections, ["legal_analysis", "disclaimer"]);
+        assert_eq!(redactions, 0);
+        assert!(!markdown.contains("13800138000"));
+        assert!(markdown.contains("## 法律分析\n可申请仲裁。"));
+
+        let (html, _, _) = package_report(
+            "【事实摘要】\n<b>老板</b>\n\n【免责声明】\n仅供参考。",
+            &ReportSectionFilter::default(),
+            false,
+            &[],
+            ShareFormat::Html,
+        )
+        .expect("package");
+        assert!(html.contains("<h2>事实摘要</h2>\n<p>&lt;b&gt;老板&lt;/b&gt;</p>"));
+
+        let unknown = ReportSectionFilter {
+            include: vec!["contacts".to_owned()],
+            exclude: Vec::new(),
+        };
+        assert!(package_report(REPORT, &unknown, true, &[], ShareFormat::Text).is_err());
+    }
+}
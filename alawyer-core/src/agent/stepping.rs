@@ -0,0 +1,152 @@
+//! Step-through runs for prompt debugging. In a session with step-through
+//! on, a report task stops after each phase of the pipeline until the
+//! host calls `Core::advance_task`, and keeps what it has produced so far
+//! for `Core::get_task_intermediate`. Intake turns are not paused.
+
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+
+use crate::retrieval::SearchResult;
+
+use super::CaseFact;
+
+/// How often a paused task checks whether it was cancelled.
+const CANCEL_POLL: Duration = Duration::from_millis(300);
+
+/// What a step-through task has produced so far.
+#[derive(Debug, Clone, uniffi::Record)]
+pub struct TaskIntermediate {
+    pub task_id: String,
+    pub session_id: String,
+    /// Phase the task is paused after (`planning`, `drafting`,
+    /// `reviewing`); `None` while it runs.
+    pub paused_after: Option<String>,
+    /// Intake facts the report is drafted from.
+    pub facts: Vec<CaseFact>,
+    /// Facts section of the draft, with the case details.
+    pub facts_summary: Option<String>,
+    /// KB chunks retrieved for the legal analysis.
+    pub retrieved_chunks: Vec<SearchResult>,
+    /// The report before review, replaced by the reviewed report once the
+    /// review phase is done.
+    pub draft: Option<String>,
+}
+
+struct State {
+    intermediate: TaskIntermediate,
+    advance: bool,
+}
+
+/// Pause point and artifacts of one step-through task.
+pub struct Stepper {
+    state: Mutex<State>,
+    resumed: Condvar,
+}
+
+impl Stepper {
+    pub fn new(task_id: &str, session_id: &str) -> Self {
+        Self {
+            state: Mutex::new(State {
+                intermediate: TaskIntermediate {
+                    task_id: task_id.to_owned(),
+                    session_id: session_id.to_owned(),
+                    paused_after: None,
+                    facts: Vec::new(),
+                    facts_summary: None,
+                    retrieved_chunks: Vec::new(),
+                    draft: None,
+                },
+                advance: false,
+            }),
+            resumed: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn record(&self, update: impl FnOnce(&mut TaskIntermediate)) {
+        update(&mut self.lock().intermediate);
+    }
+
+    pub fn snapshot(&self) -> TaskIntermediate {
+        self.lock().intermediate.clone()
+    }
+
+    /// Wait after `phase` until `advance`. Returns `false` when
+    /// `cancelled` turned true first.
+    pub fn pause(&self, phase: &str, cancelled: impl Fn() -> bool) -> bool {
+        let mut state = self.lock();
+        state.intermediate.paused_after = Some(phase.to_owned());
+        state.advance = false;
+        while !state.advance {
+            if cancelled() {
+                state.intermediate.paused_after = None;
+                return false;
+            }
+            state = self
+                .resumed
+                .wait_timeout(state, CANCEL_POLL)
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .0;
+        }
+        state.intermediate.paused_after = None;
+        true
+    }
+
+    /// Let the paused task run its next phase. Returns `false` when it is
+    /// not paused.
+    pub fn advance(&self) -> bool {
+        let mut state = self.lock();
+        if state.intermediate.paused_after.is_none() || state.advance {
+            return false;
+        }
+        state.advance = true;
+        self.resumed.notify_all();
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::Stepper;
+
+    fn wait_until_paused(stepper: &Stepper) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while stepper.snapshot().paused_after.is_none() {
+            assert!(Instant::now() < deadline, "task never paused");
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn pause_waits_for_advance_or_cancel() {
+        let stepper = Arc::new(Stepper::new("task", "session"));
+        assert!(!stepper.advance());
+
+        let paused = stepper.clone();
+        let worker = thread::spawn(move || paused.pause("planning", || false));
+        wait_until_paused(&stepper);
+        assert_eq!(stepper.snapshot().paused_after.as_deref(), Some("planning"));
+        assert!(stepper.advance());
+        assert!(worker.join().expect("join"));
+        assert!(stepper.snapshot().paused_after.is_none());
+
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let paused = stepper.clone();
+        let flag = cancelled.clone();
+        let worker =
+            thread::spawn(move || paused.pause("drafting", || flag.load(Ordering::Relaxed)));
+        wait_until_paused(&stepper);
+        cancelled.store(true, Ordering::Relaxed);
+        assert!(!worker.join().expect("join"));
+    }
+}
@@ -0,0 +1,260 @@
+//! Wall-clock time an agent task spends in each phase, for the `completed`
+//! event, the task trace and `CoreMetrics`.
+
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use super::AgentPhase;
+use crate::clock::Clock;
+
+/// Phases as timed. Intake is part of planning for `agent_phase` events but
+/// is timed on its own, since it waits on intake tools.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimedPhase {
+    Plan,
+    Intake,
+    Draft,
+    Review,
+}
+
+impl TimedPhase {
+    pub const ALL: [Self; 4] = [Self::Plan, Self::Intake, Self::Draft, Self::Review];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Plan => "plan",
+            Self::Intake => "intake",
+            Self::Draft => "draft",
+            Self::Review => "review",
+        }
+    }
+}
+
+impl From<AgentPhase> for TimedPhase {
+    fn from(phase: AgentPhase) -> Self {
+        match phase {
+            AgentPhase::Plan => Self::Plan,
+            AgentPhase::Draft => Self::Draft,
+            AgentPhase::Review => Self::Review,
+        }
+    }
+}
+
+/// One stretch of a phase, in milliseconds since the task started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PhaseSpan {
+    pub phase: TimedPhase,
+    pub start_ms: u64,
+    pub end_ms: u64,
+}
+
+/// Time per phase of one task. A phase entered more than once (planning
+/// runs again after the last intake answer) adds up; phases the task never
+/// reached are `None`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PhaseTimings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub plan_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub intake_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub draft_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub review_ms: Option<u64>,
+    pub total_ms: u64,
+}
+
+impl PhaseTimings {
+    pub fn get(&self, phase: TimedPhase) -> Option<u64> {
+        match phase {
+            TimedPhase::Plan => self.plan_ms,
+            TimedPhase::Intake => self.intake_ms,
+            TimedPhase::Draft => self.draft_ms,
+            TimedPhase::Review => self.review_ms,
+        }
+    }
+
+    fn slot(&mut self, phase: TimedPhase) -> &mut Option<u64> {
+        match phase {
+            TimedPhase::Plan => &mut self.plan_ms,
+            TimedPhase::Intake => &mut self.intake_ms,
+            TimedPhase::Draft => &mut self.draft_ms,
+            TimedPhase::Review => &mut self.review_ms,
+        }
+    }
+}
+
+/// Stopwatch of one task, reading `clock`. Entering a phase ends the
+/// previous one. Time paused, such as a step-through task waiting for the
+/// host, counts towards no phase.
+pub struct PhaseTimer {
+    clock: Arc<dyn Clock>,
+    started: i64,
+    current: Option<(TimedPhase, u64)>,
+    /// The phase `resume` continues.
+    paused: Option<TimedPhase>,
+    spans: Vec<PhaseSpan>,
+}
+
+impl PhaseTimer {
+    pub fn start(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            started: clock.now().timestamp_millis(),
+            clock,
+            current: None,
+            paused: None,
+            spans: Vec::new(),
+        }
+    }
+
+    pub fn enter(&mut self, phase: TimedPhase) {
+        self.close_current();
+        self.current = Some((phase, self.elapsed_ms()));
+    }
+
+    /// Stop timing the running phase until `resume`.
+    pub fn pause(&mut self) {
+        self.paused = self.current.map(|(phase, _)| phase);
+        self.close_current();
+    }
+
+    /// Continue the phase `pause` stopped, in a new span.
+    pub fn resume(&mut self) {
+        if let Some(phase) = self.paused.take() {
+            self.enter(phase);
+        }
+    }
+
+    /// End the running phase and sum up the spans so far.
+    pub fn finish(&mut self) -> PhaseTimings {
+        self.close_current();
+        let mut timings = PhaseTimings {
+            total_ms: self.elapsed_ms(),
+            ..PhaseTimings::default()
+        };
+        for span in &self.spans {
+            *timings.slot(span.phase).get_or_insert(0) += span.end_ms - span.start_ms;
+        }
+        timings
+    }
+
+    pub fn spans(&self) -> &[PhaseSpan] {
+        &self.spans
+    }
+
+    fn close_current(&mut self) {
+        if let Some((phase, start_ms)) = self.current.take() {
+            self.spans.push(PhaseSpan {
+                phase,
+                start_ms,
+                end_ms: self.elapsed_ms(),
+            });
+        }
+    }
+
+    fn elapsed_ms(&self) -> u64 {
+        (self.clock.now().timestamp_millis() - self.started).max(0) as u64
+    }
+}
+
+/// Average time of one phase over the tasks that reached it.
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct PhaseTimingStats {
+    /// `plan`, `intake`, `draft` or `review`.
+    pub phase: String,
+    pub tasks: u64,
+    pub average_ms: u64,
+}
+
+/// Phase times of completed tasks since the core started.
+#[derive(Default)]
+pub struct PhaseTimingCollector {
+    totals: Mutex<[(u64, u64); 4]>,
+}
+
+impl PhaseTimingCollector {
+    pub fn record(&self, timings: &PhaseTimings) {
+        let mut totals = self
+            .totals
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        for (slot, phase) in totals.iter_mut().zip(TimedPhase::ALL) {
+            if let Some(ms) = timings.get(phase) {
+                slot.0 += 1;
+                slot.1 += ms;
+            }
+        }
+    }
+
+    pub fn snapshot(&self) -> Vec<PhaseTimingStats> {
+        let totals = *self
+            .totals
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        TimedPhase::ALL
+            .iter()
+            .zip(totals)
+            .map(|(phase, (tasks, total_ms))| PhaseTimingStats {
+                phase: phase.as_str().to_owned(),
+                tasks,
+                average_ms: total_ms.checked_div(tasks).unwrap_or(0),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::{PhaseTimer, PhaseTimingCollector, TimedPhase};
+    use crate::clock::ManualClock;
+
+    #[test]
+    fn repeated_phases_add_up_and_feed_averages() {
+        let clock = Arc::new(ManualClock::at_timestamp(1_700_000_000));
+        let mut timer = PhaseTimer::start(clock.clone());
+        clock.advance(Duration::from_millis(2));
+        timer.enter(TimedPhase::Plan);
+        clock.advance(Duration::from_millis(5));
+        timer.enter(TimedPhase::Intake);
+        clock.advance(Duration::from_millis(5));
+        timer.enter(TimedPhase::Plan);
+        clock.advance(Duration::from_millis(5));
+        let timings = timer.finish();
+
+        assert_eq!(timer.spans().len(), 3);
+        assert_eq!(timings.plan_ms, Some(10));
+        assert_eq!(timings.intake_ms, Some(5));
+        assert_eq!((timings.draft_ms, timings.review_ms), (None, None));
+        assert_eq!(timings.total_ms, 17);
+
+        let collector = PhaseTimingCollector::default();
+        collector.record(&timings);
+        let stats = collector.snapshot();
+        assert_eq!(stats[0].phase, "plan");
+        assert_eq!(stats[0].tasks, 1);
+        assert_eq!(stats[0].average_ms, timings.plan_ms.unwrap_or(0));
+        assert_eq!(stats[2].tasks, 0);
+    }
+
+    #[test]
+    fn paused_time_counts_towards_no_phase() {
+        let clock = Arc::new(ManualClock::at_timestamp(1_700_000_000));
+        let mut timer = PhaseTimer::start(clock.clone());
+        timer.enter(TimedPhase::Draft);
+        clock.advance(Duration::from_millis(5));
+        timer.pause();
+        clock.advance(Duration::from_secs(60));
+        timer.resume();
+        clock.advance(Duration::from_millis(5));
+        let timings = timer.finish();
+
+        assert_eq!(timer.spans().len(), 2);
+        assert_eq!(timings.draft_ms, Some(10));
+        assert_eq!(timings.total_ms, 60_010);
+    }
+}
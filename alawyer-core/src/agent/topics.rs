@@ -0,0 +1,258 @@
+//! Sessions that wander off their scenario. Users mix a wage dispute and a
+//! deposit their landlord kept in one conversation; the intake, retrieval
+//! and report then only serve one of them. User messages are sorted into
+//! topics by keyword, and once enough of them are about another scenario
+//! the host is offered a split (`session_split_suggested`), which
+//! `Core::split_session` carries out.
+
+use std::cmp::Reverse;
+
+use crate::error::CoreResult;
+use crate::storage::{Message, Session, SqliteStorage};
+use crate::tools::intake_questions_for_scenario;
+
+use super::{answer_key, answer_source_key, mark_answer_unconfirmed, save_answer, unconfirmed_key};
+
+/// Keywords of each scenario's disputes.
+const TOPIC_KEYWORDS: [(&str, &[&str]); 5] = [
+    (
+        "labor",
+        &[
+            "工资",
+            "欠薪",
+            "加班",
+            "劳动合同",
+            "辞退",
+            "开除",
+            "离职",
+            "入职",
+            "社保",
+            "试用期",
+            "经济补偿",
+            "工伤",
+            "劳动仲裁",
+        ],
+    ),
+    (
+        "rental",
+        &[
+            "房东", "租房", "房租", "租金", "押金", "退租", "租客", "中介", "租赁", "合租",
+        ],
+    ),
+    (
+        "consumer",
+        &[
+            "退货",
+            "退款",
+            "商家",
+            "网购",
+            "假货",
+            "七天无理由",
+            "消费者",
+            "售后",
+            "发票",
+        ],
+    ),
+    (
+        "family",
+        &[
+            "离婚",
+            "抚养",
+            "彩礼",
+            "婚姻",
+            "夫妻",
+            "财产分割",
+            "探视",
+            "继承",
+            "遗产",
+        ],
+    ),
+    (
+        "traffic",
+        &["交通事故", "车祸", "交警", "肇事", "责任认定", "交强险"],
+    ),
+];
+
+/// Messages about one other scenario before a split is suggested; a
+/// single aside is not a second dispute.
+const DRIFT_MIN_MESSAGES: usize = 2;
+
+/// User messages about another scenario, and the replies to them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopicDrift {
+    pub topic: String,
+    pub message_ids: Vec<String>,
+}
+
+/// The scenario `text` is about: the one with the most keywords in it,
+/// `None` without keywords or on a tie.
+pub fn message_topic(text: &str) -> Option<&'static str> {
+    let mut counts = TOPIC_KEYWORDS
+        .iter()
+        .map(|(topic, keywords)| {
+            let hits = keywords
+                .iter()
+                .filter(|keyword| text.contains(*keyword))
+                .count();
+            (*topic, hits)
+        })
+        .filter(|(_, hits)| *hits > 0)
+        .collect::<Vec<_>>();
+    counts.sort_by_key(|(_, hits)| Reverse(*hits));
+    match counts.as_slice() {
+        [(topic, _)] => Some(topic),
+        [(topic, first), (_, second), ..] if first > second => Some(topic),
+        _ => None,
+    }
+}
+
+/// The other scenario most of `messages`' off-topic user messages are
+/// about, once there are `DRIFT_MIN_MESSAGES` of them.
+pub fn detect_drift(scenario: &str, messages: &[Message]) -> Option<TopicDrift> {
+    let mut drifted: Vec<(&str, Vec<&str>)> = Vec::new();
+    for message in messages.iter().filter(|message| message.role == "user") {
+        let Some(topic) = message_topic(&message.content).filter(|topic| *topic != scenario) else {
+            continue;
+        };
+        match drifted.iter_mut().find(|(other, _)| *other == topic) {
+            Some((_, ids)) => ids.push(&message.id),
+            None => drifted.push((topic, vec![&message.id])),
+        }
+    }
+    let (topic, user_ids) = drifted
+        .into_iter()
+        .filter(|(_, ids)| ids.len() >= DRIFT_MIN_MESSAGES)
+        .max_by_key(|(_, ids)| ids.len())?;
+    let message_ids = messages
+        .iter()
+        .filter(|message| {
+            user_ids.contains(&message.id.as_str())
+                || message
+                    .reply_to_message_id
+                    .as_deref()
+                    .is_some_and(|reply_to| user_ids.contains(&reply_to))
+        })
+        .map(|message| message.id.clone())
+        .collect();
+    Some(TopicDrift {
+        topic: topic.to_owned(),
+        message_ids,
+    })
+}
+
+/// Topics a split was suggested for, comma separated.
+fn split_suggested_key(session_id: &str) -> String {
+    format!("session:{session_id}:split_suggested")
+}
+
+/// The drift of `session` worth suggesting a split for: each topic is
+/// suggested once per session.
+pub fn take_split_suggestion(
+    storage: &SqliteStorage,
+    session: &Session,
+) -> CoreResult<Option<TopicDrift>> {
+    let messages = storage.get_messages(&session.id)?;
+    let Some(drift) = detect_drift(&session.scenario, &messages) else {
+        return Ok(None);
+    };
+    let key = split_suggested_key(&session.id);
+    let suggested = storage.get_setting(&key)?.unwrap_or_default();
+    if suggested.split(',').any(|topic| topic == drift.topic) {
+        return Ok(None);
+    }
+    let suggested = if suggested.is_empty() {
+        drift.topic.clone()
+    } else {
+        format!("{suggested},{}", drift.topic)
+    };
+    storage.set_setting(&key, &suggested)?;
+    Ok(Some(drift))
+}
+
+/// What `split_intake` did with the answers taken from the split messages.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct IntakeSplit {
+    pub moved: u32,
+    pub discarded: u32,
+}
+
+/// Carry the intake answers `source` took from `message_ids` over to
+/// `target`. Answers only move between sessions of one scenario and are
+/// discarded otherwise; in `source` they are reopened either way, since
+/// they came from messages about another dispute.
+pub fn split_intake(
+    storage: &SqliteStorage,
+    source: &Session,
+    target: &Session,
+    message_ids: &[String],
+) -> CoreResult<IntakeSplit> {
+    storage.with_tx(|| {
+        let mut split = IntakeSplit::default();
+        for idx in 0..intake_questions_for_scenario(&source.scenario).len() {
+            let Some(message_id) = storage.get_setting(&answer_source_key(&source.id, idx))? else {
+                continue;
+            };
+            if !message_ids.contains(&message_id) {
+                continue;
+            }
+            let answer = storage.get_sealed_setting(&answer_key(&source.id, idx))?;
+            if let Some(answer) = answer.filter(|_| source.scenario == target.scenario) {
+                save_answer(storage, &target.id, idx, &answer, Some(&message_id))?;
+                if storage
+                    .get_setting(&unconfirmed_key(&source.id, idx))?
+                    .is_some()
+                {
+                    mark_answer_unconfirmed(storage, &target.id, idx)?;
+                }
+                split.moved += 1;
+            } else {
+                split.discarded += 1;
+            }
+            save_answer(storage, &source.id, idx, "暂不清楚", None)?;
+        }
+        Ok(split)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{detect_drift, message_topic};
+    use crate::storage::Message;
+
+    fn message(id: &str, role: &str, content: &str, reply_to: Option<&str>) -> Message {
+        Message {
+            id: id.to_owned(),
+            session_id: "s".to_owned(),
+            role: role.to_owned(),
+            content: content.to_owned(),
+            phase: None,
+            tool_calls: None,
+            created_at: 0,
+            reply_to_message_id: reply_to.map(ToOwned::to_owned),
+        }
+    }
+
+    #[test]
+    fn drift_needs_several_messages_about_one_other_scenario() {
+        assert_eq!(message_topic("公司拖欠工资三个月"), Some("labor"));
+        assert_eq!(message_topic("房东不退押金"), Some("rental"));
+        assert_eq!(message_topic("好的"), None);
+        // One keyword each is a tie.
+        assert_eq!(message_topic("押金和工资"), None);
+
+        let mut messages = vec![
+            message("1", "user", "公司拖欠工资三个月", None),
+            message("2", "assistant", "请问您在哪里工作？", Some("1")),
+            message("3", "user", "另外房东不退押金", None),
+            message("4", "assistant", "记下了。", Some("3")),
+        ];
+        assert_eq!(detect_drift("labor", &messages), None);
+
+        messages.push(message("5", "user", "退租时房东说要扣房租", None));
+        messages.push(message("6", "assistant", "好的。", Some("5")));
+        let drift = detect_drift("labor", &messages).expect("drift");
+        assert_eq!(drift.topic, "rental");
+        assert_eq!(drift.message_ids, ["3", "4", "5", "6"]);
+        assert_eq!(detect_drift("rental", &messages), None);
+    }
+}
@@ -0,0 +1,374 @@
+pub mod query_log;
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::error::{CoreError, CoreResult};
+use crate::storage::SqliteStorage;
+
+/// Settings key holding the opt-in flag. Analytics stays off until the host
+/// explicitly enables it.
+pub const ANALYTICS_ENABLED_KEY: &str = "analytics:enabled";
+
+/// Privacy budget ε of the published KB usage counts. Each count gets
+/// Laplace noise of scale 1/ε, so the numbers do not tell whether one
+/// report retrieved or cited a file.
+pub const KB_USAGE_EPSILON: f64 = 1.0;
+
+/// Funnel counters tracked by the collector. Only event names and counts are
+/// recorded — never session ids, message content or user answers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnalyticsEvent {
+    SessionStarted,
+    IntakeStarted,
+    IntakeCompleted,
+    ReportGenerated,
+    ReportExported,
+}
+
+impl AnalyticsEvent {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::SessionStarted => "sessions_started",
+            Self::IntakeStarted => "intake_started",
+            Self::IntakeCompleted => "intake_completed",
+            Self::ReportGenerated => "reports_generated",
+            Self::ReportExported => "reports_exported",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct AnalyticsCounter {
+    pub name: String,
+    pub value: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct AnalyticsBatch {
+    pub counters: Vec<AnalyticsCounter>,
+    /// intake_completed / intake_started, or 0 when no intake has started.
+    pub intake_completion_rate: f64,
+    pub exported_at: i64,
+}
+
+/// How often the agent used one KB file, summed over its chunks. Built from
+/// per-chunk counters only: no query text, session or user content. Counts
+/// carry differential-privacy noise (see `KB_USAGE_EPSILON`).
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct KbFileUsage {
+    /// Path relative to the KB root, always `/`-separated.
+    pub file_path: String,
+    /// Times a chunk of the file was retrieved for a report.
+    pub hits: i64,
+    /// Times a chunk of the file was cited in a report.
+    pub citations: i64,
+    /// Distinct chunks of the file that were retrieved or cited.
+    pub chunks_used: u32,
+}
+
+#[uniffi::export(callback_interface)]
+pub trait AnalyticsSink: Send + Sync {
+    fn on_batch(&self, batch: AnalyticsBatch);
+}
+
+pub struct AnalyticsCollector {
+    storage: Arc<SqliteStorage>,
+    sink: RwLock<Option<Arc<dyn AnalyticsSink>>>,
+}
+
+impl AnalyticsCollector {
+    pub fn new(storage: Arc<SqliteStorage>) -> Self {
+        Self {
+            storage,
+            sink: RwLock::new(None),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.storage
+            .get_setting(ANALYTICS_ENABLED_KEY)
+            .ok()
+            .flatten()
+            .map(|value| value == "1")
+            .unwrap_or(false)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) -> CoreResult<()> {
+        self.storage
+            .set_setting(ANALYTICS_ENABLED_KEY, if enabled { "1" } else { "0" })?;
+        if !enabled {
+            self.storage.reset_counters()?;
+            self.storage.reset_kb_usage()?;
+        }
+        Ok(())
+    }
+
+    /// Best-effort: analytics must never fail the operation being measured.
+    pub fn record(&self, event: AnalyticsEvent) {
+        if !self.is_enabled() {
+            return;
+        }
+        if let Err(err) = self.storage.increment_counter(event.as_str(), 1) {
+            tracing::warn!("analytics counter update failed: {err}");
+        }
+    }
+
+    /// Count retrieved and cited chunks by `chunk_id`. Best-effort and
+    /// opt-in, like `record`.
+    pub fn record_kb_usage(&self, hits: &[String], cited: &[String]) {
+        if !self.is_enabled() || (hits.is_empty() && cited.is_empty()) {
+            return;
+        }
+        if let Err(err) = self.storage.record_kb_usage(hits, cited) {
+            tracing::warn!("kb usage update failed: {err}");
+        }
+    }
+
+    /// KB usage per file, most cited first, with Laplace noise on every
+    /// count.
+    pub fn kb_usage(&self) -> CoreResult<Vec<KbFileUsage>> {
+        let rng = SystemRandom::new();
+        let mut failed = false;
+        let files = self.kb_usage_with_noise(|| {
+            laplace_noise(&rng, 1.0 / KB_USAGE_EPSILON).unwrap_or_else(|| {
+                failed = true;
+                0.0
+            })
+        })?;
+        if failed {
+            // Exact counts are never published.
+            return Err(CoreError::Unknown(
+                "no randomness for kb usage noise".to_owned(),
+            ));
+        }
+        Ok(files)
+    }
+
+    /// KB usage per file with `noise()` added to each count, rounded and
+    /// kept non-negative.
+    fn kb_usage_with_noise(&self, mut noise: impl FnMut() -> f64) -> CoreResult<Vec<KbFileUsage>> {
+        let mut files = BTreeMap::<String, KbFileUsage>::new();
+        for (chunk_id, hits, citations) in self.storage.list_kb_usage()? {
+            // `relative/path.md#L3-L9`
+            let file_path = chunk_id
+                .rsplit_once('#')
+                .map_or(chunk_id.as_str(), |(path, _)| path);
+            let usage = files
+                .entry(file_path.to_owned())
+                .or_insert_with(|| KbFileUsage {
+                    file_path: file_path.to_owned(),
+                    hits: 0,
+                    citations: 0,
+                    chunks_used: 0,
+                });
+            usage.hits += hits;
+            usage.citations += citations;
+            usage.chunks_used += 1;
+        }
+        let mut noisy = |count: i64| (count as f64 + noise()).round().max(0.0) as i64;
+        let mut files = files
+            .into_values()
+            .map(|usage| KbFileUsage {
+                hits: noisy(usage.hits),
+                citations: noisy(usage.citations),
+                chunks_used: noisy(i64::from(usage.chunks_used)) as u32,
+                ..usage
+            })
+            .collect::<Vec<_>>();
+        files.sort_by(|a, b| {
+            (b.citations, b.hits)
+                .cmp(&(a.citations, a.hits))
+                .then_with(|| a.file_path.cmp(&b.file_path))
+        });
+        Ok(files)
+    }
+
+    pub fn set_sink(&self, sink: Option<Arc<dyn AnalyticsSink>>) -> CoreResult<()> {
+        let mut slot = self
+            .sink
+            .write()
+            .map_err(|_| CoreError::InvalidState("analytics sink lock poisoned".to_owned()))?;
+        *slot = sink;
+        Ok(())
+    }
+
+    pub fn snapshot(&self) -> CoreResult<AnalyticsBatch> {
+        let counters = self
+            .storage
+            .list_counters()?
+            .into_iter()
+            .map(|(name, value)| AnalyticsCounter { name, value })
+            .collect::<Vec<_>>();
+
+        let count_of = |event: AnalyticsEvent| {
+            counters
+                .iter()
+                .find(|counter| counter.name == event.as_str())
+                .map(|counter| counter.value)
+                .unwrap_or(0)
+        };
+        let started = count_of(AnalyticsEvent::IntakeStarted);
+        let completed = count_of(AnalyticsEvent::IntakeCompleted);
+        let intake_completion_rate = if started > 0 {
+            completed as f64 / started as f64
+        } else {
+            0.0
+        };
+
+        Ok(AnalyticsBatch {
+            counters,
+            intake_completion_rate,
+            exported_at: self.storage.clock().timestamp(),
+        })
+    }
+
+    /// Hand the current batch to the registered sink and reset the local
+    /// counters. Without a sink the counters are kept so nothing is lost.
+    pub fn flush(&self) -> CoreResult<Option<AnalyticsBatch>> {
+        let sink = self
+            .sink
+            .read()
+            .map_err(|_| CoreError::InvalidState("analytics sink lock poisoned".to_owned()))?
+            .clone();
+        let Some(sink) = sink else {
+            return Ok(None);
+        };
+
+        let batch = self.snapshot()?;
+        self.storage.reset_counters()?;
+        sink.on_batch(batch.clone());
+        Ok(Some(batch))
+    }
+}
+
+/// A sample of the Laplace distribution centred on 0 with `scale`; `None`
+/// without randomness.
+fn laplace_noise(rng: &SystemRandom, scale: f64) -> Option<f64> {
+    let mut bytes = [0u8; 8];
+    rng.fill(&mut bytes).ok()?;
+    // Uniform in (-0.5, 0.5), both ends excluded.
+    let uniform = ((u64::from_le_bytes(bytes) >> 11) as f64 + 0.5) / (1u64 << 53) as f64 - 0.5;
+    Some(-scale * uniform.signum() * (1.0 - 2.0 * uniform.abs()).ln())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tempfile::TempDir;
+
+    use ring::rand::SystemRandom;
+
+    use super::{laplace_noise, AnalyticsBatch, AnalyticsCollector, AnalyticsEvent, AnalyticsSink};
+    use crate::clock::SystemClock;
+    use crate::storage::SqliteStorage;
+
+    fn make_collector() -> (TempDir, AnalyticsCollector) {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let storage = Arc::new(
+            SqliteStorage::new(temp_dir.path().join("core.db"), Arc::new(SystemClock))
+                .expect("storage"),
+        );
+        (temp_dir, AnalyticsCollector::new(storage))
+    }
+
+    struct CollectingSink {
+        batches: Arc<Mutex<Vec<AnalyticsBatch>>>,
+    }
+
+    impl AnalyticsSink for CollectingSink {
+        fn on_batch(&self, batch: AnalyticsBatch) {
+            self.batches.lock().expect("lock").push(batch);
+        }
+    }
+
+    #[test]
+    fn disabled_by_default_records_nothing() {
+        let (_temp_dir, collector) = make_collector();
+        collector.record(AnalyticsEvent::SessionStarted);
+
+        let batch = collector.snapshot().expect("snapshot");
+        assert!(batch.counters.is_empty());
+    }
+
+    #[test]
+    fn flush_delivers_batch_and_resets() {
+        let (_temp_dir, collector) = make_collector();
+        collector.set_enabled(true).expect("enable");
+        collector.record(AnalyticsEvent::IntakeStarted);
+        collector.record(AnalyticsEvent::IntakeStarted);
+        collector.record(AnalyticsEvent::IntakeCompleted);
+
+        let batches = Arc::new(Mutex::new(Vec::new()));
+        collector
+            .set_sink(Some(Arc::new(CollectingSink {
+                batches: batches.clone(),
+            })))
+            .expect("set sink");
+
+        let flushed = collector.flush().expect("flush").expect("batch");
+        assert!((flushed.intake_completion_rate - 0.5).abs() < f64::EPSILON);
+        assert_eq!(batches.lock().expect("lock").len(), 1);
+        assert!(collector.snapshot().expect("snapshot").counters.is_empty());
+    }
+
+    #[test]
+    fn kb_usage_is_opt_in_and_grouped_per_file() {
+        let (_temp_dir, collector) = make_collector();
+        let ids = |ids: &[&str]| ids.iter().map(|id| (*id).to_owned()).collect::<Vec<_>>();
+        collector.record_kb_usage(&ids(&["labor/wage.md#L1-L9"]), &[]);
+        assert!(collector.kb_usage().expect("usage").is_empty());
+
+        collector.set_enabled(true).expect("enable");
+        collector.record_kb_usage(
+            &ids(&[
+                "labor/wage.md#L1-L9",
+                "labor/wage.md#L10-L20",
+                "labor/leave.md#L1-L5",
+            ]),
+            &ids(&["labor/leave.md#L1-L5"]),
+        );
+        collector.record_kb_usage(&ids(&["labor/wage.md#L1-L9"]), &[]);
+
+        let usage = collector.kb_usage_with_noise(|| 0.0).expect("usage");
+        assert_eq!(
+            usage
+                .iter()
+                .map(|file| (
+                    file.file_path.as_str(),
+                    file.hits,
+                    file.citations,
+                    file.chunks_used
+                ))
+                .collect::<Vec<_>>(),
+            [("labor/leave.md", 1, 1, 1), ("labor/wage.md", 3, 0, 2)]
+        );
+        let noisy = collector.kb_usage_with_noise(|| -1.6).expect("noisy usage");
+        assert_eq!(
+            noisy
+                .iter()
+                .map(|file| (file.hits, file.citations, file.chunks_used))
+                .collect::<Vec<_>>(),
+            [(1, 0, 0), (0, 0, 0)]
+        );
+        assert_eq!(collector.kb_usage().expect("published").len(), 2);
+
+        collector.set_enabled(false).expect("disable");
+        assert!(collector.kb_usage().expect("usage").is_empty());
+    }
+
+    #[test]
+    fn laplace_noise_is_centred_on_zero() {
+        let rng = SystemRandom::new();
+        let samples = (0..20_000)
+            .map(|_| laplace_noise(&rng, 1.0).expect("sample"))
+            .collect::<Vec<_>>();
+        let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+        let mean_abs = samples.iter().map(|x| x.abs()).sum::<f64>() / samples.len() as f64;
+        assert!(mean.abs() < 0.1, "mean {mean}");
+        assert!((mean_abs - 1.0).abs() < 0.1, "mean |x| {mean_abs}");
+    }
+}
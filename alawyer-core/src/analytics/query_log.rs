@@ -0,0 +1,281 @@
+//! Opt-in local log of retrieval queries, for tuning search against real
+//! questions. Entries never leave the device unless the host exports them.
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+use ring::digest::{digest, SHA256};
+
+use crate::error::CoreResult;
+use crate::redact::redact;
+use crate::storage::{QueryLogEntry, SqliteStorage};
+
+/// Settings key holding the capture mode; unset means `Off`.
+pub const QUERY_LOG_CAPTURE_KEY: &str = "query_log:capture";
+
+/// Entries kept; older ones are dropped as new ones arrive.
+pub const QUERY_LOG_MAX_ENTRIES: u32 = 2_000;
+
+/// Entries older than this are dropped as new ones arrive.
+pub const QUERY_LOG_MAX_AGE_SECS: i64 = 30 * 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, uniffi::Enum)]
+pub enum QueryLogCapture {
+    Off,
+    /// Only a hash of each query: repeats are visible, the text is not.
+    Hashed,
+    /// The query text with personal details masked.
+    Redacted,
+}
+
+impl QueryLogCapture {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Hashed => "hashed",
+            Self::Redacted => "redacted",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "off" => Some(Self::Off),
+            "hashed" => Some(Self::Hashed),
+            "redacted" => Some(Self::Redacted),
+            _ => None,
+        }
+    }
+}
+
+/// Replaying logged queries that have used chunks against the current KB.
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct QueryLogEvaluation {
+    /// Entries with query text and at least one used chunk.
+    pub cases: u32,
+    /// Cases where a used chunk is among the results.
+    pub cases_hit: u32,
+    /// Share of used chunks found among the results, averaged over cases.
+    pub mean_recall: f64,
+}
+
+/// `query` with personal details masked; amounts and months stay, they
+/// matter for search.
+pub fn redact_query(query: &str) -> String {
+    redact(query, &[]).0
+}
+
+fn hash_query(query: &str) -> String {
+    digest(&SHA256, query.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Replay each case through `search` (query text, scenario) and measure how
+/// many of its used chunks come back.
+pub fn evaluate<F>(entries: &[QueryLogEntry], search: F) -> CoreResult<QueryLogEvaluation>
+where
+    F: Fn(&str, &str) -> CoreResult<Vec<String>>,
+{
+    let mut cases = 0;
+    let mut cases_hit = 0;
+    let mut recall_sum = 0.0;
+    for entry in entries {
+        let Some(query) = entry.query_text.as_deref() else {
+            continue;
+        };
+        if entry.used_chunk_ids.is_empty() {
+            continue;
+        }
+        let results = search(query, &entry.scenario)?
+            .into_iter()
+            .collect::<HashSet<_>>();
+        let found = entry
+            .used_chunk_ids
+            .iter()
+            .filter(|chunk_id| results.contains(*chunk_id))
+            .count();
+        cases += 1;
+        if found > 0 {
+            cases_hit += 1;
+        }
+        recall_sum += found as f64 / entry.used_chunk_ids.len() as f64;
+    }
+    Ok(QueryLogEvaluation {
+        cases,
+        cases_hit,
+        mean_recall: if cases == 0 {
+            0.0
+        } else {
+            recall_sum / f64::from(cases)
+        },
+    })
+}
+
+pub struct QueryLog {
+    storage: Arc<SqliteStorage>,
+    /// Entry id and result chunk ids of the last host search, so opening
+    /// one of its results counts as a click.
+    last_search: Mutex<Option<(i64, Vec<String>)>>,
+}
+
+impl QueryLog {
+    pub fn new(storage: Arc<SqliteStorage>) -> Self {
+        Self {
+            storage,
+            last_search: Mutex::new(None),
+        }
+    }
+
+    pub fn capture(&self) -> QueryLogCapture {
+        self.storage
+            .get_setting(QUERY_LOG_CAPTURE_KEY)
+            .ok()
+            .flatten()
+            .and_then(|value| QueryLogCapture::parse(&value))
+            .unwrap_or(QueryLogCapture::Off)
+    }
+
+    /// Turning capture off deletes the entries logged so far.
+    pub fn set_capture(&self, capture: QueryLogCapture) -> CoreResult<()> {
+        self.storage
+            .set_setting(QUERY_LOG_CAPTURE_KEY, capture.as_str())?;
+        if capture == QueryLogCapture::Off {
+            self.clear()?;
+        }
+        Ok(())
+    }
+
+    /// Log a query. Best-effort like analytics; `None` when capture is off
+    /// or the write failed.
+    pub fn record(
+        &self,
+        query: &str,
+        scenario: &str,
+        result_count: usize,
+        used_chunk_ids: &[String],
+    ) -> Option<i64> {
+        let text = match self.capture() {
+            QueryLogCapture::Off => return None,
+            QueryLogCapture::Hashed => None,
+            QueryLogCapture::Redacted => Some(redact_query(query)),
+        };
+        self.storage
+            .append_query_log(
+                &hash_query(query),
+                text.as_deref(),
+                scenario,
+                result_count as u32,
+                used_chunk_ids,
+            )
+            .and_then(|id| {
+                self.storage
+                    .trim_query_log(QUERY_LOG_MAX_ENTRIES, QUERY_LOG_MAX_AGE_SECS)?;
+                Ok(id)
+            })
+            .inspect_err(|err| tracing::warn!("query log write failed: {err}"))
+            .ok()
+    }
+
+    /// Log a host search, remembering its results for `record_click`.
+    pub fn record_search(&self, query: &str, scenario: &str, result_chunk_ids: Vec<String>) {
+        let entry = self
+            .record(query, scenario, result_chunk_ids.len(), &[])
+            .map(|id| (id, result_chunk_ids));
+        *self.lock_last_search() = entry;
+    }
+
+    /// Count `chunk_id` as used by the last host search when it was one of
+    /// its results.
+    pub fn record_click(&self, chunk_id: &str) {
+        let last_search = self.lock_last_search();
+        let Some((id, results)) = last_search.as_ref() else {
+            return;
+        };
+        if !results.iter().any(|result| result == chunk_id) {
+            return;
+        }
+        if let Err(err) = self.storage.mark_query_log_used(*id, chunk_id) {
+            tracing::warn!("query log click update failed: {err}");
+        }
+    }
+
+    pub fn export(&self, since: i64) -> CoreResult<Vec<QueryLogEntry>> {
+        self.storage.list_query_log(since)
+    }
+
+    pub fn clear(&self) -> CoreResult<()> {
+        *self.lock_last_search() = None;
+        self.storage.clear_query_log()
+    }
+
+    fn lock_last_search(&self) -> std::sync::MutexGuard<'_, Option<(i64, Vec<String>)>> {
+        self.last_search
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tempfile::TempDir;
+
+    use super::{evaluate, redact_query, QueryLog, QueryLogCapture};
+    use crate::clock::SystemClock;
+    use crate::storage::SqliteStorage;
+
+    #[test]
+    fn captures_only_when_opted_in_and_tracks_clicks() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let storage = Arc::new(
+            SqliteStorage::new(temp_dir.path().join("core.db"), Arc::new(SystemClock))
+                .expect("storage"),
+        );
+        let log = QueryLog::new(storage);
+        assert_eq!(log.capture(), QueryLogCapture::Off);
+        assert!(log.record("拖欠工资", "labor", 2, &[]).is_none());
+
+        assert_eq!(
+            redact_query("电话13800138000，邮箱a.b@example.com，欠薪3个月"),
+            "电话***，邮箱***，欠薪3个月"
+        );
+
+        log.set_capture(QueryLogCapture::Hashed).expect("hashed");
+        log.record("拖欠工资", "labor", 2, &["law.md#L1-L2".to_owned()]);
+        log.set_capture(QueryLogCapture::Redacted)
+            .expect("redacted");
+        log.record_search(
+            "加班费 13800138000",
+            "labor",
+            vec!["law.md#L1-L2".to_owned(), "law.md#L3-L4".to_owned()],
+        );
+        log.record_click("law.md#L3-L4");
+        log.record_click("law.md#L3-L4");
+        log.record_click("other.md#L1-L1");
+
+        let entries = log.export(0).expect("export");
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].query_text, None);
+        assert_eq!(entries[0].query_hash.len(), 64);
+        assert_eq!(entries[1].query_text.as_deref(), Some("加班费 ***"));
+        assert_eq!(entries[1].result_count, 2);
+        assert_eq!(entries[1].used_chunk_ids, ["law.md#L3-L4"]);
+
+        let evaluation = evaluate(&entries, |query, _| {
+            Ok(if query.contains("加班费") {
+                vec!["law.md#L3-L4".to_owned()]
+            } else {
+                Vec::new()
+            })
+        })
+        .expect("evaluate");
+        assert_eq!((evaluation.cases, evaluation.cases_hit), (1, 1));
+        assert_eq!(evaluation.mean_recall, 1.0);
+
+        log.set_capture(QueryLogCapture::Off).expect("off");
+        assert!(log.export(0).expect("export").is_empty());
+    }
+}
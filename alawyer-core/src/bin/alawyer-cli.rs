@@ -0,0 +1,289 @@
+//! Headless front end to `Core` for testers and KB curators: runs a
+//! session from the first message through intake and drafting in a
+//! terminal, printing events to stderr and the report to stdout.
+//!
+//! Intake answers come from stdin, one per line, or from a YAML script:
+//!
+//! ```yaml
+//! scenario: labor
+//! title: 拖欠工资
+//! message: 公司拖欠我三个月工资
+//! answers:
+//!   - 广东深圳
+//!   - 2022年3月入职，签了劳动合同
+//! ```
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+
+use alawyer_core::{Core, CoreConfig, CoreEvent, EventListener, ToolResponse};
+use clap::{Parser, Subcommand};
+use serde::Deserialize;
+use serde_json::Value;
+
+#[derive(Parser)]
+#[command(name = "alawyer-cli", about = "Run the Alawyer agent from a terminal")]
+struct Cli {
+    /// Knowledge base directory.
+    #[arg(long, default_value = "kb")]
+    kb: PathBuf,
+    /// SQLite database; created on first use.
+    #[arg(long, default_value = "alawyer.db")]
+    db: PathBuf,
+    /// Locale of the agent's copy, e.g. `en`; the configured default
+    /// otherwise.
+    #[arg(long)]
+    locale: Option<String>,
+    #[arg(long, default_value_t = 8)]
+    max_iterations: u32,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List sessions, most recent first.
+    Sessions,
+    /// Create a session, or continue one, and run it until the report is
+    /// written or the answers run out.
+    Run {
+        /// Continue this session instead of creating one.
+        #[arg(long)]
+        session: Option<String>,
+        #[arg(long, default_value = "labor")]
+        scenario: String,
+        #[arg(long)]
+        title: Option<String>,
+        /// YAML script with the first message and the intake answers;
+        /// read from stdin without one.
+        #[arg(long)]
+        script: Option<PathBuf>,
+        /// Only print the report, not the events.
+        #[arg(long)]
+        quiet: bool,
+        /// Also write the report as Markdown to this path.
+        #[arg(long)]
+        export: Option<PathBuf>,
+    },
+    /// Write a session's report as Markdown.
+    Export {
+        #[arg(long)]
+        session: String,
+        #[arg(long)]
+        out: PathBuf,
+        /// Mark each section with footnotes pointing at its sources.
+        #[arg(long)]
+        footnotes: bool,
+    },
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Script {
+    scenario: Option<String>,
+    title: Option<String>,
+    message: Option<String>,
+    #[serde(default)]
+    answers: Vec<String>,
+}
+
+/// Where the user's side of the conversation comes from.
+enum Input {
+    Script(VecDeque<String>),
+    Stdin,
+}
+
+impl Input {
+    /// The next line to send, after showing `prompt`; `None` once the
+    /// script or stdin is exhausted.
+    fn next(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        eprintln!("{prompt}");
+        match self {
+            Self::Script(lines) => {
+                let line = lines.pop_front();
+                if let Some(line) = &line {
+                    eprintln!("> {line}");
+                }
+                Ok(line)
+            }
+            Self::Stdin => {
+                eprint!("> ");
+                io::stderr().flush()?;
+                let mut line = String::new();
+                if io::stdin().lock().read_line(&mut line)? == 0 {
+                    return Ok(None);
+                }
+                Ok(Some(line.trim().to_owned()))
+            }
+        }
+    }
+}
+
+/// Hands events over to the main thread, which may call back into `Core`.
+struct Forwarder(Mutex<Sender<CoreEvent>>);
+
+impl EventListener for Forwarder {
+    fn on_event(&self, event: CoreEvent) {
+        if let Ok(sender) = self.0.lock() {
+            let _ = sender.send(event);
+        }
+    }
+}
+
+fn main() {
+    if let Err(err) = run(Cli::parse()) {
+        eprintln!("error: {err}");
+        std::process::exit(1);
+    }
+}
+
+fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
+    let core = Core::new(CoreConfig {
+        locale: cli.locale,
+        ..CoreConfig::new(
+            cli.kb.to_string_lossy().into_owned(),
+            cli.db.to_string_lossy().into_owned(),
+            cli.max_iterations,
+        )
+    })?;
+
+    match cli.command {
+        Command::Sessions => {
+            for session in core.list_sessions(None)? {
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    session.id,
+                    session.scenario,
+                    session.status,
+                    session.title.unwrap_or_default()
+                );
+            }
+            Ok(())
+        }
+        Command::Run {
+            session,
+            scenario,
+            title,
+            script,
+            quiet,
+            export,
+        } => {
+            let script = match script {
+                Some(path) => Some(serde_yaml::from_str::<Script>(&std::fs::read_to_string(
+                    path,
+                )?)?),
+                None => None,
+            };
+            let (sender, events) = mpsc::channel();
+            core.subscribe_events(Box::new(Forwarder(Mutex::new(sender))))?;
+
+            let session_id = match session {
+                Some(session_id) => session_id,
+                None => {
+                    let script = script.as_ref();
+                    core.create_session(
+                        script
+                            .and_then(|script| script.scenario.clone())
+                            .unwrap_or(scenario),
+                        script.and_then(|script| script.title.clone()).or(title),
+                    )?
+                }
+            };
+            eprintln!("session {session_id}");
+            let (first, input) = match script {
+                Some(script) => (script.message, Input::Script(script.answers.into())),
+                None => (None, Input::Stdin),
+            };
+
+            let Some(report) = converse(&core, &session_id, first, input, &events, quiet)? else {
+                return Err("stopped before the report was written".into());
+            };
+            println!("{report}");
+            if let Some(path) = export {
+                core.export_report_markdown(
+                    session_id,
+                    path.to_string_lossy().into_owned(),
+                    false,
+                )?;
+                eprintln!("report written to {}", path.display());
+            }
+            Ok(())
+        }
+        Command::Export {
+            session,
+            out,
+            footnotes,
+        } => {
+            core.export_report_markdown(session, out.to_string_lossy().into_owned(), footnotes)?;
+            Ok(())
+        }
+    }
+}
+
+/// Send `first` (or the first input line) and answer each intake question
+/// from `input` until a task completes with the report. Tool approvals are
+/// granted for the call, as a host would after asking.
+fn converse(
+    core: &Core,
+    session_id: &str,
+    first: Option<String>,
+    mut input: Input,
+    events: &Receiver<CoreEvent>,
+    quiet: bool,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let first = match first {
+        Some(first) => first,
+        None => match input.next("describe the case:")? {
+            Some(first) => first,
+            None => return Ok(None),
+        },
+    };
+    let mut task_id = core.send_message(session_id.to_owned(), first, None)?;
+    let mut awaiting_confirmation = false;
+
+    loop {
+        let event = events.recv()?;
+        if !quiet {
+            eprintln!("[{}] {}", event.kind, event.payload);
+        }
+        let payload = serde_json::from_str::<Value>(&event.payload)?;
+        let field = |name: &str| payload.get(name).and_then(Value::as_str);
+        if field("task_id") != Some(task_id.as_str()) {
+            continue;
+        }
+        match event.kind.as_str() {
+            "tool_call_request" => {
+                if let Some(request_id) = field("request_id") {
+                    core.respond_tool_call(
+                        request_id.to_owned(),
+                        ToolResponse::Allow { always: false },
+                    )?;
+                }
+            }
+            "estimate" => {
+                awaiting_confirmation = payload["awaiting_confirmation"].as_bool() == Some(true);
+            }
+            "error" => {
+                return Err(field("message").unwrap_or("task failed").into());
+            }
+            "cancelled" => return Ok(None),
+            "completed" => {
+                if let Some(report) = field("report") {
+                    return Ok(Some(report.to_owned()));
+                }
+                if std::mem::take(&mut awaiting_confirmation) {
+                    task_id = core.confirm_draft(session_id.to_owned())?;
+                    continue;
+                }
+                let Some(answer) = input.next(field("message").unwrap_or_default())? else {
+                    return Ok(None);
+                };
+                task_id = core.send_message(session_id.to_owned(), answer, None)?;
+            }
+            _ => {}
+        }
+    }
+}
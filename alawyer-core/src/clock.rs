@@ -0,0 +1,111 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+pub type SleepFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Source of wall-clock time and delays. Production code uses [`SystemClock`];
+/// tests swap in [`ManualClock`] so timestamps and backoff are deterministic.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+
+    fn sleep(&self, duration: Duration) -> SleepFuture;
+
+    fn timestamp(&self) -> i64 {
+        self.now().timestamp()
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> SleepFuture {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+#[cfg(any(test, feature = "test-clock"))]
+pub use manual::ManualClock;
+
+#[cfg(any(test, feature = "test-clock"))]
+mod manual {
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use chrono::{DateTime, TimeZone, Utc};
+
+    use super::{Clock, SleepFuture};
+
+    /// Clock that only moves when told to. `sleep` advances the clock by the
+    /// requested duration and resolves immediately.
+    pub struct ManualClock {
+        now: Mutex<DateTime<Utc>>,
+        slept: Mutex<Vec<Duration>>,
+    }
+
+    impl ManualClock {
+        pub fn at_timestamp(timestamp: i64) -> Self {
+            Self {
+                now: Mutex::new(
+                    Utc.timestamp_opt(timestamp, 0)
+                        .single()
+                        .expect("valid timestamp"),
+                ),
+                slept: Mutex::new(Vec::new()),
+            }
+        }
+
+        pub fn advance(&self, duration: Duration) {
+            let mut now = self.now.lock().expect("manual clock lock");
+            *now += chrono::Duration::from_std(duration).expect("duration in range");
+        }
+
+        /// Every duration passed to `sleep`, in call order.
+        pub fn sleeps(&self) -> Vec<Duration> {
+            self.slept.lock().expect("manual clock lock").clone()
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> DateTime<Utc> {
+            *self.now.lock().expect("manual clock lock")
+        }
+
+        fn sleep(&self, duration: Duration) -> SleepFuture {
+            self.advance(duration);
+            self.slept.lock().expect("manual clock lock").push(duration);
+            Box::pin(std::future::ready(()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Clock, ManualClock};
+
+    #[test]
+    fn manual_clock_advances_on_sleep() {
+        let clock = ManualClock::at_timestamp(1_700_000_000);
+        block_on(clock.sleep(Duration::from_secs(30)));
+        clock.advance(Duration::from_secs(30));
+
+        assert_eq!(clock.timestamp(), 1_700_000_060);
+        assert_eq!(clock.sleeps(), vec![Duration::from_secs(30)]);
+    }
+
+    fn block_on(future: super::SleepFuture) {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .expect("runtime")
+            .block_on(future);
+    }
+}
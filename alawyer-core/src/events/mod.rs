@@ -0,0 +1,392 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+use serde::Serialize;
+
+use crate::clock::Clock;
+use crate::error::{CoreError, CoreResult};
+use crate::storage::SqliteStorage;
+use crate::{CoreEvent, EventListener};
+
+pub mod payloads;
+
+use payloads::{EventPayload, EVENT_SCHEMA_VERSION};
+
+/// Kinds delivered even for a muted session: failures, escalations, and
+/// approvals the task is blocked on.
+pub const CRITICAL_EVENT_KINDS: [&str; 3] = ["error", "escalation_required", "tool_call_request"];
+
+/// Events held per muted session for `take_muted_events`; the oldest go
+/// first past this.
+pub const MAX_MUTED_EVENTS: usize = 200;
+
+/// Panics in a row after which a listener is unsubscribed.
+const MAX_LISTENER_FAILURES: u32 = 3;
+
+/// Listener panics caught since startup, and listeners dropped for them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ListenerFailureStats {
+    pub panics: u64,
+    pub removed: u64,
+}
+
+struct Subscriber {
+    listener: Arc<dyn EventListener>,
+    /// Panics since the last event it took without one.
+    failures: AtomicU32,
+}
+
+/// Fan-out point for every event the core emits. Timestamps come from the
+/// injected clock so tests can assert on them.
+pub struct EventHub {
+    listeners: Mutex<HashMap<u64, Arc<Subscriber>>>,
+    next_listener_id: AtomicU64,
+    listener_panics: AtomicU64,
+    listeners_removed: AtomicU64,
+    clock: Arc<dyn Clock>,
+    history: Option<EventHistory>,
+    /// Sessions whose non-critical events are held back instead of
+    /// delivered.
+    muted_sessions: RwLock<HashSet<String>>,
+    /// Events held back per muted session, independent of `history`.
+    muted_events: Mutex<HashMap<String, VecDeque<CoreEvent>>>,
+    /// Session of each running task, for payloads that only name the task.
+    task_sessions: Mutex<HashMap<String, String>>,
+}
+
+/// Ring of recent events in the database, so another process sharing it or a
+/// UI that reconnects can catch up with `get_recent_events`.
+struct EventHistory {
+    storage: Arc<SqliteStorage>,
+    keep: u32,
+}
+
+impl EventHub {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            listeners: Mutex::new(HashMap::new()),
+            next_listener_id: AtomicU64::new(1),
+            listener_panics: AtomicU64::new(0),
+            listeners_removed: AtomicU64::new(0),
+            clock,
+            history: None,
+            muted_sessions: RwLock::new(HashSet::new()),
+            muted_events: Mutex::new(HashMap::new()),
+            task_sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Also record every emitted event, keeping the newest `keep`.
+    pub fn with_history(mut self, storage: Arc<SqliteStorage>, keep: u32) -> Self {
+        self.history = Some(EventHistory { storage, keep });
+        self
+    }
+
+    pub fn set_session_muted(&self, session_id: &str, muted: bool) {
+        let mut sessions = self
+            .muted_sessions
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if muted {
+            sessions.insert(session_id.to_owned());
+        } else {
+            sessions.remove(session_id);
+        }
+    }
+
+    /// Drain the events held back while `session_id` was muted, oldest
+    /// first.
+    pub fn take_muted_events(&self, session_id: &str) -> Vec<CoreEvent> {
+        self.lock_muted_events()
+            .remove(session_id)
+            .map(Vec::from)
+            .unwrap_or_default()
+    }
+
+    /// Drop everything held for `session_id`, for a deleted session.
+    pub fn forget_session(&self, session_id: &str) {
+        self.set_session_muted(session_id, false);
+        self.lock_muted_events().remove(session_id);
+    }
+
+    /// Unmute every session and drop the events held for them, for erased
+    /// user data.
+    pub fn unmute_all_sessions(&self) {
+        self.muted_sessions
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clear();
+        self.lock_muted_events().clear();
+    }
+
+    pub fn is_session_muted(&self, session_id: &str) -> bool {
+        self.muted_sessions
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .contains(session_id)
+    }
+
+    /// Attribute events naming `task_id` to `session_id` until `untrack_task`.
+    pub fn track_task(&self, task_id: &str, session_id: &str) {
+        self.lock_task_sessions()
+            .insert(task_id.to_owned(), session_id.to_owned());
+    }
+
+    pub fn untrack_task(&self, task_id: &str) {
+        self.lock_task_sessions().remove(task_id);
+    }
+
+    /// Whether a task of `session_id` is queued or running.
+    pub fn has_session_tasks(&self, session_id: &str) -> bool {
+        self.lock_task_sessions()
+            .values()
+            .any(|tracked| tracked == session_id)
+    }
+
+    pub fn subscribe(&self, listener: Arc<dyn EventListener>) -> CoreResult<u64> {
+        let id = self.next_listener_id.fetch_add(1, Ordering::Relaxed);
+        let mut listeners = self
+            .listeners
+            .lock()
+            .map_err(|_| CoreError::InvalidState("event listener lock poisoned".to_owned()))?;
+        listeners.insert(
+            id,
+            Arc::new(Subscriber {
+                listener,
+                failures: AtomicU32::new(0),
+            }),
+        );
+        Ok(id)
+    }
+
+    pub fn unsubscribe(&self, subscription_id: u64) -> CoreResult<()> {
+        let mut listeners = self
+            .listeners
+            .lock()
+            .map_err(|_| CoreError::InvalidState("event listener lock poisoned".to_owned()))?;
+
+        if listeners.remove(&subscription_id).is_none() {
+            return Err(CoreError::NotFound(format!(
+                "subscription {subscription_id}"
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn emit<P: EventPayload>(&self, payload: &P) {
+        let kind = P::KIND;
+        let serialized = match versioned_payload(payload) {
+            Ok(serialized) => serialized,
+            Err(err) => {
+                tracing::warn!("failed to serialize event {kind}: {err}");
+                return;
+            }
+        };
+        let event = CoreEvent {
+            kind: kind.to_owned(),
+            payload: serialized,
+            timestamp: self.clock.timestamp(),
+        };
+
+        if let Some(history) = &self.history {
+            if let Err(err) = history.storage.append_event(&event, history.keep) {
+                tracing::warn!("failed to record event {kind}: {err}");
+            }
+        }
+
+        if !CRITICAL_EVENT_KINDS.contains(&kind) {
+            if let Some(session_id) = self.muted_session(payload) {
+                let mut held = self.lock_muted_events();
+                let queue = held.entry(session_id).or_default();
+                if queue.len() == MAX_MUTED_EVENTS {
+                    queue.pop_front();
+                }
+                queue.push_back(event);
+                return;
+            }
+        }
+
+        // Snapshot so listeners run without holding the lock (they may
+        // subscribe/unsubscribe from inside on_event).
+        let listeners_snapshot = self
+            .lock_listeners()
+            .iter()
+            .map(|(id, subscriber)| (*id, subscriber.clone()))
+            .collect::<Vec<_>>();
+
+        for (id, subscriber) in listeners_snapshot {
+            let delivered = panic::catch_unwind(AssertUnwindSafe(|| {
+                subscriber.listener.on_event(event.clone());
+            }));
+            match delivered {
+                Ok(()) => subscriber.failures.store(0, Ordering::Relaxed),
+                Err(_) => self.listener_panicked(id, &subscriber, kind),
+            }
+        }
+    }
+
+    pub fn listener_failure_stats(&self) -> ListenerFailureStats {
+        ListenerFailureStats {
+            panics: self.listener_panics.load(Ordering::Relaxed),
+            removed: self.listeners_removed.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Count a panic of listener `id` on a `kind` event, logging it, and
+    /// unsubscribe the listener once it has failed
+    /// `MAX_LISTENER_FAILURES` times in a row.
+    fn listener_panicked(&self, id: u64, subscriber: &Subscriber, kind: &str) {
+        self.listener_panics.fetch_add(1, Ordering::Relaxed);
+        let failures = subscriber.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        // Ids are never reused, so `id` is still this listener if present.
+        let removed =
+            failures >= MAX_LISTENER_FAILURES && self.lock_listeners().remove(&id).is_some();
+        let message = if removed {
+            self.listeners_removed.fetch_add(1, Ordering::Relaxed);
+            format!("event listener {id} unsubscribed after {failures} panics in a row")
+        } else {
+            format!("event listener {id} panicked on a {kind} event")
+        };
+        tracing::error!("{message}");
+        if let Some(history) = &self.history {
+            let _ = history.storage.append_log("error", &message, None);
+        }
+    }
+
+    /// The muted session `payload` belongs to, by its `session_id` or the
+    /// session of its `task_id`.
+    fn muted_session<P: EventPayload>(&self, payload: &P) -> Option<String> {
+        let muted = self
+            .muted_sessions
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if muted.is_empty() {
+            return None;
+        }
+        let session_id = match payload.session_id() {
+            Some(session_id) => session_id.to_owned(),
+            None => self.lock_task_sessions().get(payload.task_id()?).cloned()?,
+        };
+        muted.contains(&session_id).then_some(session_id)
+    }
+
+    fn lock_muted_events(&self) -> std::sync::MutexGuard<'_, HashMap<String, VecDeque<CoreEvent>>> {
+        self.muted_events
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn lock_listeners(&self) -> std::sync::MutexGuard<'_, HashMap<u64, Arc<Subscriber>>> {
+        self.listeners
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn lock_task_sessions(&self) -> std::sync::MutexGuard<'_, HashMap<String, String>> {
+        self.task_sessions
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[derive(Serialize)]
+struct Versioned<'a, P> {
+    schema_version: u32,
+    #[serde(flatten)]
+    payload: &'a P,
+}
+
+/// `payload` as a JSON object with `schema_version` first.
+pub(crate) fn versioned_payload<P: Serialize>(payload: &P) -> serde_json::Result<String> {
+    serde_json::to_string(&Versioned {
+        schema_version: EVENT_SCHEMA_VERSION,
+        payload,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    use super::payloads::{Cancelling, MessageCreated};
+    use super::{EventHub, ListenerFailureStats, MAX_LISTENER_FAILURES, MAX_MUTED_EVENTS};
+    use crate::clock::SystemClock;
+    use crate::{CoreEvent, EventListener};
+
+    #[derive(Default)]
+    struct Counting(AtomicU32);
+
+    impl EventListener for Counting {
+        fn on_event(&self, _event: CoreEvent) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    struct Panicking;
+
+    impl EventListener for Panicking {
+        fn on_event(&self, _event: CoreEvent) {
+            panic!("listener bug");
+        }
+    }
+
+    #[test]
+    fn panicking_listener_is_isolated_and_dropped() {
+        let hub = EventHub::new(Arc::new(SystemClock));
+        let healthy = Arc::new(Counting::default());
+        hub.subscribe(Arc::new(Panicking)).expect("subscribe");
+        hub.subscribe(healthy.clone()).expect("subscribe");
+        let emit = || {
+            hub.emit(&MessageCreated {
+                session_id: "s".to_owned(),
+                message_id: "m".to_owned(),
+            })
+        };
+
+        for _ in 0..MAX_LISTENER_FAILURES + 2 {
+            emit();
+        }
+
+        assert_eq!(healthy.0.load(Ordering::Relaxed), MAX_LISTENER_FAILURES + 2);
+        assert_eq!(
+            hub.listener_failure_stats(),
+            ListenerFailureStats {
+                panics: u64::from(MAX_LISTENER_FAILURES),
+                removed: 1,
+            }
+        );
+        assert_eq!(hub.lock_listeners().len(), 1);
+    }
+
+    #[test]
+    fn muted_events_are_held_without_history() {
+        let hub = EventHub::new(Arc::new(SystemClock));
+        let listener = Arc::new(Counting::default());
+        hub.subscribe(listener.clone()).expect("subscribe");
+        hub.set_session_muted("s", true);
+        hub.track_task("t", "s");
+
+        hub.emit(&Cancelling {
+            task_id: "t".to_owned(),
+        });
+        for _ in 0..MAX_MUTED_EVENTS {
+            hub.emit(&MessageCreated {
+                session_id: "s".to_owned(),
+                message_id: "m".to_owned(),
+            });
+        }
+        hub.emit(&MessageCreated {
+            session_id: "other".to_owned(),
+            message_id: "m".to_owned(),
+        });
+
+        assert_eq!(listener.0.load(Ordering::Relaxed), 1);
+        let held = hub.take_muted_events("s");
+        assert_eq!(held.len(), MAX_MUTED_EVENTS);
+        assert!(held.iter().all(|event| event.kind == "message_created"));
+        assert!(hub.take_muted_events("s").is_empty());
+    }
+}
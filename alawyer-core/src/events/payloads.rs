@@ -0,0 +1,704 @@
+//! Typed payloads of every event kind. Each payload is serialized as a JSON
+//! object with snake_case fields plus `schema_version`; `event_schema`
+//! describes them all as JSON Schema for client codegen.
+//!
+//! Changes from the unversioned payloads to version 1, for clients that
+//! parsed them before:
+//! - `cancelled` was the bare task id; it is now an object whose `task_id`
+//!   holds that id, with `session_id` and the partial draft added.
+//! - `subscribed`, `session_created` and `message_created` were
+//!   `key=value` strings; the same keys are now object fields.
+//! - `test` was the bare message; it is now the `message` field.
+//! - `model_updated`, `model_connection_ok` and `model_ping` were fixed
+//!   sentences; `model_updated` now names the `model`, the others are empty
+//!   objects.
+//! - Optional fields that were sent as `null`, such as `completed.report`
+//!   or `model_warmup_failed.status`, are now left out; read a missing
+//!   field as `null`.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::agent::citations::CitationCoverage;
+use crate::agent::confidence::ConclusionConfidence;
+use crate::agent::timing::PhaseTimings;
+use crate::safety::SafetyEdit;
+
+/// Bumped whenever a payload loses a field or a field changes type. Adding
+/// an optional field does not bump it.
+pub const EVENT_SCHEMA_VERSION: u32 = 1;
+
+/// A payload that `EventHub::emit` can send as `KIND`.
+pub trait EventPayload: Serialize {
+    const KIND: &'static str;
+
+    /// The `session_id` field, for payloads that have one.
+    fn session_id(&self) -> Option<&str>;
+
+    /// The `task_id` field, for payloads that have one.
+    fn task_id(&self) -> Option<&str>;
+}
+
+/// JSON Schema of a payload field type. `Option` fields are left out of
+/// the payload when `None` and are not required.
+pub trait JsonType {
+    const REQUIRED: bool = true;
+
+    fn schema() -> Value;
+
+    fn is_absent(&self) -> bool {
+        false
+    }
+
+    /// The value when it is a string, for `EventPayload::session_id`.
+    fn as_text(&self) -> Option<&str> {
+        None
+    }
+}
+
+impl JsonType for String {
+    fn schema() -> Value {
+        json!({"type": "string"})
+    }
+
+    fn as_text(&self) -> Option<&str> {
+        Some(self)
+    }
+}
+
+impl JsonType for bool {
+    fn schema() -> Value {
+        json!({"type": "boolean"})
+    }
+}
+
+macro_rules! integer_json_type {
+    ($($ty:ty),*) => {
+        $(impl JsonType for $ty {
+            fn schema() -> Value {
+                json!({"type": "integer", "minimum": 0})
+            }
+        })*
+    };
+}
+
+integer_json_type!(u16, u32, u64, usize);
+
+impl JsonType for Value {
+    fn schema() -> Value {
+        json!({})
+    }
+}
+
+impl<T: JsonType> JsonType for Vec<T> {
+    fn schema() -> Value {
+        json!({"type": "array", "items": T::schema()})
+    }
+}
+
+impl<T: JsonType> JsonType for Option<T> {
+    const REQUIRED: bool = false;
+
+    fn schema() -> Value {
+        T::schema()
+    }
+
+    fn is_absent(&self) -> bool {
+        self.is_none()
+    }
+
+    fn as_text(&self) -> Option<&str> {
+        self.as_ref().and_then(JsonType::as_text)
+    }
+}
+
+impl JsonType for PhaseTimings {
+    fn schema() -> Value {
+        let millis = <u64 as JsonType>::schema();
+        json!({
+            "type": "object",
+            "properties": {
+                "plan_ms": millis,
+                "intake_ms": millis,
+                "draft_ms": millis,
+                "review_ms": millis,
+                "total_ms": millis
+            },
+            "required": ["total_ms"],
+            "additionalProperties": false
+        })
+    }
+}
+
+impl JsonType for CitationCoverage {
+    fn schema() -> Value {
+        let count = <u32 as JsonType>::schema();
+        json!({
+            "type": "object",
+            "properties": {
+                "claims": count,
+                "cited": count,
+                "hedged": count,
+                "removed": count
+            },
+            "required": ["claims", "cited", "hedged", "removed"],
+            "additionalProperties": false
+        })
+    }
+}
+
+impl JsonType for ConclusionConfidence {
+    fn schema() -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "level": {"type": "string", "enum": ["high", "medium", "low"]},
+                "score": {"type": "integer", "minimum": 0, "maximum": 100},
+                "reason": <String as JsonType>::schema()
+            },
+            "required": ["level", "score", "reason"],
+            "additionalProperties": false
+        })
+    }
+}
+
+impl JsonType for SafetyEdit {
+    fn schema() -> Value {
+        let text = <String as JsonType>::schema();
+        json!({
+            "type": "object",
+            "properties": {
+                "rule": text,
+                "original": text,
+                "replacement": text,
+                "offset": <u32 as JsonType>::schema()
+            },
+            "required": ["rule", "original", "replacement", "offset"],
+            "additionalProperties": false
+        })
+    }
+}
+
+macro_rules! event_payloads {
+    ($(
+        $(#[doc = $doc:literal])*
+        $name:ident => $kind:literal {
+            $($(#[doc = $field_doc:literal])* $field:ident: $ty:ty),* $(,)?
+        }
+    )*) => {
+        $(
+            $(#[doc = $doc])*
+            #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+            pub struct $name {
+                $(
+                    $(#[doc = $field_doc])*
+                    #[serde(skip_serializing_if = "JsonType::is_absent")]
+                    pub $field: $ty,
+                )*
+            }
+
+            impl EventPayload for $name {
+                const KIND: &'static str = $kind;
+
+                fn session_id(&self) -> Option<&str> {
+                    named_text(&[$((stringify!($field), self.$field.as_text())),*], "session_id")
+                }
+
+                fn task_id(&self) -> Option<&str> {
+                    named_text(&[$((stringify!($field), self.$field.as_text())),*], "task_id")
+                }
+            }
+        )*
+
+        /// JSON Schema (draft 2020-12) of every payload, keyed by event kind.
+        pub fn event_schema() -> Value {
+            let mut events = Map::new();
+            $(
+                #[allow(unused_mut)]
+                let mut properties = Map::new();
+                properties.insert(
+                    "schema_version".to_owned(),
+                    json!({"const": EVENT_SCHEMA_VERSION}),
+                );
+                #[allow(unused_mut)]
+                let mut required = vec!["schema_version"];
+                $(
+                    let mut property = <$ty as JsonType>::schema();
+                    let field_doc = describe(&[$($field_doc),*]);
+                    if !field_doc.is_empty() {
+                        property["description"] = Value::String(field_doc);
+                    }
+                    properties.insert(stringify!($field).to_owned(), property);
+                    if <$ty as JsonType>::REQUIRED {
+                        required.push(stringify!($field));
+                    }
+                )*
+                events.insert(
+                    $kind.to_owned(),
+                    json!({
+                        "title": stringify!($name),
+                        "description": describe(&[$($doc),*]),
+                        "type": "object",
+                        "properties": properties,
+                        "required": required,
+                        "additionalProperties": false
+                    }),
+                );
+            )*
+            json!({
+                "$schema": "https://json-schema.org/draft/2020-12/schema",
+                "schema_version": EVENT_SCHEMA_VERSION,
+                "events": events
+            })
+        }
+    };
+}
+
+/// The text of the field called `name` among `fields`.
+fn named_text<'a>(fields: &[(&str, Option<&'a str>)], name: &str) -> Option<&'a str> {
+    fields
+        .iter()
+        .find(|(field, _)| *field == name)
+        .and_then(|(_, value)| *value)
+}
+
+/// Doc comment lines joined into one sentence-level string.
+fn describe(lines: &[&str]) -> String {
+    lines
+        .iter()
+        .map(|line| line.trim())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+event_payloads! {
+    /// A listener was registered.
+    Subscribed => "subscribed" {
+        subscription_id: u64,
+    }
+
+    /// Sent by `emit_test_event`, for checking the event path end to end.
+    TestEvent => "test" {
+        message: String,
+    }
+
+    SessionCreated => "session_created" {
+        session_id: String,
+        scenario: String,
+    }
+
+    /// `source_id` was folded into `target_id` and archived.
+    SessionsMerged => "sessions_merged" {
+        source_id: String,
+        target_id: String,
+        /// Intake answers the two sessions disagreed on.
+        conflict_count: usize,
+    }
+
+    /// Several of the session's messages are about another scenario;
+    /// `message_ids` are the ones `split_session` would move.
+    SessionSplitSuggested => "session_split_suggested" {
+        session_id: String,
+        topic: String,
+        message_ids: Vec<String>,
+    }
+
+    /// `split_session` moved messages of `source_id` into the new
+    /// session `target_id`.
+    SessionSplit => "session_split" {
+        source_id: String,
+        target_id: String,
+        message_count: usize,
+    }
+
+    /// `invalidate_settings_cache` dropped cached settings; `prefix` is
+    /// absent when every setting and tool permission was dropped.
+    SettingsCacheInvalidated => "settings_cache_invalidated" {
+        prefix: Option<String>,
+        entries: u32,
+    }
+
+    /// `update_core_config` applied a new configuration.
+    ConfigUpdated => "config_updated" {
+        kb_path: String,
+        max_iterations: u32,
+        /// A new retrieval engine replaced the old one.
+        kb_reloaded: bool,
+    }
+
+    ProfileSwitched => "profile_switched" {
+        profile_id: String,
+    }
+
+    MessageCreated => "message_created" {
+        session_id: String,
+        message_id: String,
+    }
+
+    /// A batch of messages was stored by `create_messages_bulk`; reload
+    /// the session's messages.
+    MessagesCreated => "messages_created" {
+        session_id: String,
+        count: usize,
+    }
+
+    /// A stored secret was replaced; the value itself is never sent.
+    SecretRotated => "secret_rotated" {
+        name: String,
+    }
+
+    ModelUpdated => "model_updated" {
+        model: String,
+    }
+
+    ModelConnectionOk => "model_connection_ok" {}
+
+    ModelPing => "model_ping" {}
+
+    /// The optional warm-up after a config update succeeded.
+    ModelReady => "model_ready" {
+        model: String,
+        latency_ms: u64,
+    }
+
+    ModelWarmupFailed => "model_warmup_failed" {
+        model: String,
+        /// `models` or `completion`.
+        stage: String,
+        /// HTTP status, when the endpoint answered.
+        status: Option<u16>,
+        message: String,
+    }
+
+    /// A message over the length limit was stored as an attachment.
+    LongTextStored => "long_text_stored" {
+        session_id: String,
+        attachment_id: String,
+        chars: usize,
+    }
+
+    /// A step-through task finished `phase` and waits for `advance_task`;
+    /// see `get_task_intermediate`.
+    TaskPaused => "task_paused" {
+        task_id: String,
+        session_id: String,
+        phase: String,
+    }
+
+    TaskRetrying => "task_retrying" {
+        session_id: String,
+        task_id: String,
+        failed_task_id: String,
+    }
+
+    /// Cancellation was requested; `cancelled` follows once the task stops.
+    Cancelling => "cancelling" {
+        task_id: String,
+    }
+
+    Cancelled => "cancelled" {
+        task_id: String,
+        session_id: String,
+        /// Message holding the report sections finished before the cancel.
+        draft_message_id: Option<String>,
+        partial_report: Option<String>,
+    }
+
+    /// The case looks high-risk enough to need a lawyer; delivered even for
+    /// muted sessions.
+    EscalationRequired => "escalation_required" {
+        task_id: String,
+        session_id: String,
+        message: String,
+    }
+
+    TaskError => "error" {
+        task_id: String,
+        session_id: String,
+        message: String,
+        /// Whether `retry_last_task` can rerun the task.
+        retryable: bool,
+    }
+
+    /// A task finished: `report` after drafting, `message` when intake asks
+    /// the next question.
+    Completed => "completed" {
+        task_id: String,
+        session_id: String,
+        report: Option<String>,
+        message: Option<String>,
+        /// Time spent per phase; phases the task did not reach are left out.
+        timings: PhaseTimings,
+        /// How the report's legal claims are backed by KB sources; only
+        /// with `report`.
+        citation_coverage: Option<CitationCoverage>,
+        /// How firmly the conclusion holds, for a badge; only with
+        /// `report`.
+        confidence: Option<ConclusionConfidence>,
+    }
+
+    AgentPhase => "agent_phase" {
+        task_id: String,
+        /// `planning`, `drafting` or `reviewing`.
+        phase: String,
+    }
+
+    IntakeProgress => "intake_progress" {
+        task_id: String,
+        current: u64,
+        total: u64,
+        question: String,
+        /// The previous answer was rejected and the question is asked again.
+        reask: bool,
+    }
+
+    /// Coarse progress of a report for status announcements: each report
+    /// section is one step and the safety review one more.
+    TaskProgress => "task_progress" {
+        task_id: String,
+        session_id: String,
+        /// `drafting` or `reviewing`.
+        phase: String,
+        completed: u32,
+        total: u32,
+        percent: u32,
+        /// The progress in words, in the configured locale.
+        label: String,
+    }
+
+    IntakeDone => "intake_done" {
+        task_id: String,
+        session_id: String,
+    }
+
+    /// Expected cost of the draft about to be written; with
+    /// `awaiting_confirmation` the task stopped until `confirm_draft`.
+    DraftEstimated => "estimate" {
+        task_id: String,
+        session_id: String,
+        model_calls: u32,
+        prompt_tokens: u32,
+        completion_tokens: u32,
+        duration_ms: u64,
+        from_history: bool,
+        awaiting_confirmation: bool,
+    }
+
+    /// Intake was rolled back with `reset_intake`; question
+    /// `question_index` (zero-based) waits for its answer again.
+    IntakeReset => "intake_reset" {
+        session_id: String,
+        question_index: u32,
+        answers_cleared: u32,
+        question: String,
+    }
+
+    FactsUpdated => "facts_updated" {
+        session_id: String,
+        field: String,
+    }
+
+    /// A follow-up report offered to ask the open intake questions again;
+    /// `fields` are their `CaseFact::field`s, for `reask_open_question`.
+    OpenQuestionsOffered => "open_questions_offered" {
+        task_id: String,
+        session_id: String,
+        fields: Vec<String>,
+    }
+
+    WhatIfCompleted => "whatif_completed" {
+        session_id: String,
+        message_id: String,
+    }
+
+    ReportRegenerating => "report_regenerating" {
+        session_id: String,
+    }
+
+    /// The KB changed after the session's report was generated.
+    ReportStale => "report_stale" {
+        session_id: String,
+        message_id: String,
+        /// KB-relative paths modified since the report.
+        changed_files: Vec<String>,
+        /// Regeneration mode to offer, e.g. `citations_only`.
+        suggested_mode: String,
+    }
+
+    /// A report is withheld from the user until a reviewer approves it;
+    /// see `list_pending_reviews`.
+    ReportPendingReview => "report_pending_review" {
+        task_id: String,
+        session_id: String,
+        message_id: String,
+    }
+
+    /// A reviewer approved the report; `report` is what the user reads.
+    ReportReleased => "report_released" {
+        session_id: String,
+        message_id: String,
+        report: String,
+    }
+
+    /// One report section is ready, in the order of the final report. A
+    /// section sent again for the same `order` replaces the earlier one:
+    /// the conclusion is resent with its confidence line once the legal
+    /// analysis is drafted. Not sent for reports withheld for review; see
+    /// `report_released`.
+    ReportSection => "report_section" {
+        task_id: String,
+        session_id: String,
+        section: String,
+        title: String,
+        order: u32,
+        total: usize,
+        content: String,
+    }
+
+    /// The review phase rewrote parts of the draft.
+    ReviewAdjusted => "review_adjusted" {
+        task_id: String,
+        session_id: String,
+        issue_count: usize,
+        critical_count: usize,
+        /// Each rewrite; offsets are characters into the report.
+        edits: Vec<SafetyEdit>,
+    }
+
+    /// The review phase found critical issues and replaced the draft.
+    ReviewIntercepted => "review_intercepted" {
+        task_id: String,
+        session_id: String,
+        issue_count: usize,
+        critical_count: usize,
+        /// Each rewrite; offsets are characters into the report.
+        edits: Vec<SafetyEdit>,
+    }
+
+    /// A tool needs the user's approval; answer with `respond_tool_call`.
+    ToolCallRequest => "tool_call_request" {
+        task_id: String,
+        request_id: String,
+        tool_name: String,
+        arguments: Value,
+    }
+
+    ToolCallResponse => "tool_call_response" {
+        request_id: String,
+        tool_name: String,
+        session_id: String,
+    }
+
+    /// Several approvals answered at once by `respond_tool_calls_bulk`;
+    /// stands in for one `tool_call_response` per request.
+    ToolCallsResponded => "tool_calls_responded" {
+        request_ids: Vec<String>,
+        /// Tool of each request, in the order of `request_ids`.
+        tool_names: Vec<String>,
+        session_ids: Vec<String>,
+    }
+
+    ToolCallSkipped => "tool_call_skipped" {
+        task_id: String,
+        request_id: String,
+        tool_name: String,
+        reason: String,
+    }
+
+    ToolCallResult => "tool_call_result" {
+        task_id: String,
+        tool_name: String,
+        result: Value,
+    }
+
+    /// An approval request is no longer pending, e.g. to dismiss its card.
+    ToolCallClosed => "tool_call_closed" {
+        request_id: String,
+        reason: String,
+    }
+
+    KbIntegrityFailed => "kb_integrity_failed" {
+        manifest_error: Option<String>,
+        /// KB-relative paths that failed verification.
+        files: Vec<String>,
+    }
+
+    KbChanged => "kb_changed" {
+        paths: Vec<String>,
+    }
+
+    RetentionPurged => "retention_purged" {
+        session_count: usize,
+        message_count: u32,
+        log_count: u32,
+        trace_count: u32,
+    }
+
+    /// `delete_all_user_data` finished; the counts of what it removed.
+    UserDataErased => "user_data_erased" {
+        session_count: u32,
+        message_count: u32,
+        attachment_count: u32,
+        fact_count: u32,
+        log_count: u32,
+        trace_count: u32,
+        record_count: u32,
+        file_count: u32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::{event_schema, Completed, PhaseTimings, EVENT_SCHEMA_VERSION};
+    use crate::events::versioned_payload;
+
+    #[test]
+    fn payloads_carry_version_and_match_schema() {
+        let payload = versioned_payload(&Completed {
+            task_id: "t1".to_owned(),
+            session_id: "s1".to_owned(),
+            report: Some("报告".to_owned()),
+            message: None,
+            timings: PhaseTimings {
+                draft_ms: Some(120),
+                total_ms: 150,
+                ..PhaseTimings::default()
+            },
+            citation_coverage: None,
+            confidence: None,
+        })
+        .expect("serialize");
+        let value = serde_json::from_str::<Value>(&payload).expect("json");
+        assert_eq!(
+            value,
+            json!({
+                "schema_version": EVENT_SCHEMA_VERSION,
+                "task_id": "t1",
+                "session_id": "s1",
+                "report": "报告",
+                "timings": {"draft_ms": 120, "total_ms": 150}
+            })
+        );
+
+        let schema = event_schema();
+        let completed = &schema["events"]["completed"];
+        assert_eq!(
+            completed["required"],
+            json!(["schema_version", "task_id", "session_id", "timings"])
+        );
+        let properties = completed["properties"].as_object().expect("properties");
+        assert!(value
+            .as_object()
+            .expect("object")
+            .keys()
+            .all(|key| properties.contains_key(key)));
+        assert_eq!(schema["events"]["error"]["title"], "TaskError");
+        assert_eq!(
+            schema["events"]["cancelled"]["properties"]["draft_message_id"]["type"],
+            "string"
+        );
+        assert_eq!(schema["events"].as_object().expect("events").len(), 51);
+    }
+}
@@ -0,0 +1,208 @@
+//! Fault injection for chaos tests of the pipeline, configured through
+//! `fault:*` settings. Only builds with the `fault-injection` feature act on
+//! them; elsewhere the hooks compile to nothing.
+//!
+//! - `fault:tool:{name}:fail_every` fails every Nth run of tool `name`
+//! - `fault:model:fail_every` fails every Nth model call as an HTTP 500
+//! - `fault:model:delay_ms` delays every model call
+//! - `fault:storage:fail_every` fails every Nth storage call as if the
+//!   connection lock were poisoned
+//!
+//! `0` or an unparsable value turns a fault off.
+
+/// Settings namespace of the fault rules. Not scoped to a profile.
+pub const FAULT_SETTING_PREFIX: &str = "fault:";
+
+#[cfg(not(feature = "fault-injection"))]
+pub use inert::FaultInjector;
+#[cfg(feature = "fault-injection")]
+pub use injector::FaultInjector;
+
+#[cfg(feature = "fault-injection")]
+mod injector {
+    use std::collections::HashMap;
+    use std::sync::{Mutex, MutexGuard};
+    use std::thread;
+    use std::time::Duration;
+
+    use super::FAULT_SETTING_PREFIX;
+    use crate::error::{CoreError, CoreResult};
+    use crate::storage::Setting;
+
+    #[derive(Default)]
+    struct Rules {
+        /// Rule value by key without the `fault:` prefix.
+        values: HashMap<String, u64>,
+        /// Calls seen per rule, for the every-Nth ones.
+        calls: HashMap<String, u64>,
+    }
+
+    #[derive(Default)]
+    pub struct FaultInjector {
+        rules: Mutex<Rules>,
+    }
+
+    impl FaultInjector {
+        /// Replace all rules with those in `settings`.
+        pub fn load(&self, settings: &[Setting]) {
+            let mut rules = self.lock();
+            *rules = Rules::default();
+            for setting in settings {
+                if let Some((rule, value)) = parse(&setting.key, &setting.value) {
+                    rules.values.insert(rule.to_owned(), value);
+                }
+            }
+        }
+
+        /// Apply a write of the setting `key`; `None` deletes it.
+        pub fn apply(&self, key: &str, value: Option<&str>) {
+            let Some(rule) = key.strip_prefix(FAULT_SETTING_PREFIX) else {
+                return;
+            };
+            let mut rules = self.lock();
+            rules.calls.remove(rule);
+            match value.and_then(|value| parse(key, value)) {
+                Some((_, value)) => {
+                    rules.values.insert(rule.to_owned(), value);
+                }
+                None => {
+                    rules.values.remove(rule);
+                }
+            }
+        }
+
+        /// Drop the rules whose setting key starts with `prefix`.
+        pub fn clear(&self, prefix: &str) {
+            let mut rules = self.lock();
+            let Rules { values, calls } = &mut *rules;
+            values.retain(|rule, _| !format!("{FAULT_SETTING_PREFIX}{rule}").starts_with(prefix));
+            calls.retain(|rule, _| values.contains_key(rule));
+        }
+
+        pub fn tool(&self, tool_name: &str) -> CoreResult<()> {
+            if self.hit(&format!("tool:{tool_name}:fail_every")) {
+                return Err(CoreError::Tool(format!(
+                    "tool {tool_name} failed (injected fault)"
+                )));
+            }
+            Ok(())
+        }
+
+        pub fn model(&self) -> CoreResult<()> {
+            let delay = self.lock().values.get("model:delay_ms").copied();
+            if let Some(delay) = delay {
+                thread::sleep(Duration::from_millis(delay));
+            }
+            if self.hit("model:fail_every") {
+                return Err(CoreError::Model(
+                    "HTTP 500 Internal Server Error (injected fault)".to_owned(),
+                ));
+            }
+            Ok(())
+        }
+
+        pub fn storage(&self) -> CoreResult<()> {
+            if self.hit("storage:fail_every") {
+                return Err(CoreError::Storage(
+                    "storage lock poisoned (injected fault)".to_owned(),
+                ));
+            }
+            Ok(())
+        }
+
+        /// Count a call under the every-Nth `rule`; true when this one fails.
+        fn hit(&self, rule: &str) -> bool {
+            let mut rules = self.lock();
+            let Some(every) = rules.values.get(rule).copied() else {
+                return false;
+            };
+            let calls = rules.calls.entry(rule.to_owned()).or_insert(0);
+            *calls += 1;
+            calls.is_multiple_of(every)
+        }
+
+        fn lock(&self) -> MutexGuard<'_, Rules> {
+            self.rules
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+        }
+    }
+
+    /// Rule name and value of a fault setting; `None` when it is off.
+    fn parse<'a>(key: &'a str, value: &str) -> Option<(&'a str, u64)> {
+        let rule = key.strip_prefix(FAULT_SETTING_PREFIX)?;
+        let value = value
+            .trim()
+            .parse::<u64>()
+            .ok()
+            .filter(|value| *value > 0)?;
+        Some((rule, value))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::FaultInjector;
+        use crate::storage::Setting;
+
+        #[test]
+        fn rules_fail_every_nth_call_until_cleared() {
+            let faults = FaultInjector::default();
+            faults.load(&[
+                Setting {
+                    key: "fault:tool:kb_search:fail_every".to_owned(),
+                    value: "2".to_owned(),
+                },
+                Setting {
+                    key: "fault:model:fail_every".to_owned(),
+                    value: "off".to_owned(),
+                },
+            ]);
+            let runs = (0..4)
+                .map(|_| faults.tool("kb_search").is_ok())
+                .collect::<Vec<_>>();
+            assert_eq!(runs, [true, false, true, false]);
+            assert!(faults.tool("cite").is_ok());
+            assert!(faults.model().is_ok());
+
+            faults.apply("fault:storage:fail_every", Some("1"));
+            assert!(faults.storage().is_err());
+            faults.clear("fault:");
+            assert!(faults.storage().is_ok());
+            assert!(faults.tool("kb_search").is_ok());
+        }
+    }
+}
+
+#[cfg(not(feature = "fault-injection"))]
+mod inert {
+    use crate::error::CoreResult;
+    use crate::storage::Setting;
+
+    #[derive(Default)]
+    pub struct FaultInjector {
+        _inert: (),
+    }
+
+    impl FaultInjector {
+        pub fn load(&self, _settings: &[Setting]) {}
+
+        pub fn apply(&self, _key: &str, _value: Option<&str>) {}
+
+        pub fn clear(&self, _prefix: &str) {}
+
+        #[inline]
+        pub fn tool(&self, _tool_name: &str) -> CoreResult<()> {
+            Ok(())
+        }
+
+        #[inline]
+        pub fn model(&self) -> CoreResult<()> {
+            Ok(())
+        }
+
+        #[inline]
+        pub fn storage(&self) -> CoreResult<()> {
+            Ok(())
+        }
+    }
+}
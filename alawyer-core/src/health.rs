@@ -0,0 +1,340 @@
+//! `Core::health_check`: one status per subsystem for a "系统状态" page in
+//! the host's settings. Every check is local, but the KB check hashes every
+//! KB file, so it costs about as much as reading the KB once: run it when
+//! the page opens, not on a timer. The model is judged by its recent calls
+//! rather than by a new request.
+
+use crate::model::CallOutcome;
+use crate::retrieval::KbIntegrityReport;
+use crate::storage::sqlite::SCHEMA_VERSION;
+
+/// Free space below which new messages and attachments may soon fail.
+const DISK_WARN_BYTES: u64 = 500 * 1024 * 1024;
+const DISK_FAIL_BYTES: u64 = 50 * 1024 * 1024;
+/// Queued tasks and model calls from which the queue is reported as backed
+/// up.
+const QUEUE_WARN_DEPTH: u32 = 5;
+/// Failed model calls in a row from which the model counts as down; fewer
+/// may be a blip and only warn.
+const MODEL_FAIL_STREAK: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, uniffi::Enum)]
+pub enum HealthLevel {
+    Ok,
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct StorageHealth {
+    pub level: HealthLevel,
+    pub writable: bool,
+    pub schema_version: u32,
+    pub expected_schema_version: u32,
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct KbHealth {
+    pub level: HealthLevel,
+    pub file_count: u32,
+    /// The KB has no files and searches answer from the bundled statutes;
+    /// see `retrieval::builtin`.
+    pub builtin_fallback: bool,
+    /// Every file passed the integrity check, so search sees the whole KB.
+    /// The index itself is rebuilt from the files on each search.
+    pub index_fresh: bool,
+    /// Unix seconds of the newest KB file.
+    pub updated_at: i64,
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct ModelHealth {
+    pub level: HealthLevel,
+    pub configured: bool,
+    /// Whether the last call got through; `None` before the first call.
+    pub reachable: Option<bool>,
+    pub last_error: Option<String>,
+    /// Unix seconds of the last call.
+    pub last_call_at: Option<i64>,
+    /// Calls in a row that failed; the model only counts as down from
+    /// `MODEL_FAIL_STREAK` on.
+    pub consecutive_failures: u32,
+    /// Unix seconds of the last call that got through.
+    pub last_success_at: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct TaskQueueHealth {
+    pub level: HealthLevel,
+    pub running_tasks: u32,
+    /// Tasks waiting for an earlier task of their session.
+    pub queued_tasks: u32,
+    /// Model calls waiting for a slot.
+    pub queued_model_calls: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct DiskHealth {
+    pub level: HealthLevel,
+    /// Of the volume holding the data directory; `None` when the platform
+    /// does not say.
+    pub available_bytes: Option<u64>,
+    pub total_bytes: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct HealthReport {
+    /// The worst level of the subsystems.
+    pub level: HealthLevel,
+    pub storage: StorageHealth,
+    pub knowledge_base: KbHealth,
+    pub model: ModelHealth,
+    pub tasks: TaskQueueHealth,
+    pub disk: DiskHealth,
+    pub checked_at: i64,
+}
+
+impl HealthReport {
+    pub fn new(
+        storage: StorageHealth,
+        knowledge_base: KbHealth,
+        model: ModelHealth,
+        tasks: TaskQueueHealth,
+        disk: DiskHealth,
+        checked_at: i64,
+    ) -> Self {
+        let level = [
+            storage.level,
+            knowledge_base.level,
+            model.level,
+            tasks.level,
+            disk.level,
+        ]
+        .into_iter()
+        .max()
+        .unwrap_or(HealthLevel::Ok);
+        Self {
+            level,
+            storage,
+            knowledge_base,
+            model,
+            tasks,
+            disk,
+            checked_at,
+        }
+    }
+}
+
+/// `probe` is the result of opening a write transaction, `None` for a
+/// read-only core, which is expected not to write.
+pub fn storage_health(probe: Option<Result<(), String>>, schema_version: u32) -> StorageHealth {
+    let (level, writable, message) = match probe {
+        Some(Err(err)) => (HealthLevel::Fail, false, Some(err)),
+        None => (
+            HealthLevel::Warn,
+            false,
+            Some("opened read-only".to_owned()),
+        ),
+        Some(Ok(())) if schema_version != SCHEMA_VERSION => (
+            HealthLevel::Warn,
+            true,
+            Some(format!(
+                "schema version {schema_version}, this build uses {SCHEMA_VERSION}"
+            )),
+        ),
+        Some(Ok(())) => (HealthLevel::Ok, true, None),
+    };
+    StorageHealth {
+        level,
+        writable,
+        schema_version,
+        expected_schema_version: SCHEMA_VERSION,
+        message,
+    }
+}
+
+/// `builtin_available` is whether this build bundles statutes to search
+/// while the KB is empty.
+pub fn kb_health(
+    file_count: u32,
+    builtin_available: bool,
+    updated_at: i64,
+    integrity: &KbIntegrityReport,
+) -> KbHealth {
+    let failed = integrity.files_checked - integrity.files_passed;
+    let index_fresh = integrity.manifest_error.is_none() && failed == 0;
+    let builtin_fallback = file_count == 0 && builtin_available;
+    let (level, message) = if builtin_fallback {
+        (
+            HealthLevel::Warn,
+            Some("the knowledge base has no files; searching the bundled statutes".to_owned()),
+        )
+    } else if file_count == 0 {
+        (
+            HealthLevel::Fail,
+            Some("the knowledge base has no files".to_owned()),
+        )
+    } else if let Some(err) = &integrity.manifest_error {
+        (
+            HealthLevel::Warn,
+            Some(format!("integrity manifest unreadable: {err}")),
+        )
+    } else if failed > 0 {
+        (
+            HealthLevel::Warn,
+            Some(format!("{failed} file(s) failed the integrity check")),
+        )
+    } else {
+        (HealthLevel::Ok, None)
+    };
+    KbHealth {
+        level,
+        file_count,
+        builtin_fallback,
+        index_fresh,
+        updated_at,
+        message,
+    }
+}
+
+/// `last_call` is `None` without a model (`configured` false) or before
+/// its first call. Running without a model is supported, so that only
+/// warns, as do failures until `MODEL_FAIL_STREAK` in a row.
+pub fn model_health(configured: bool, last_call: Option<CallOutcome>) -> ModelHealth {
+    let reachable = last_call.as_ref().map(|call| call.error.is_none());
+    let consecutive_failures = last_call
+        .as_ref()
+        .map_or(0, |call| call.consecutive_failures);
+    let level = match (configured, consecutive_failures) {
+        (false, _) => HealthLevel::Warn,
+        (true, 0) => HealthLevel::Ok,
+        (true, failures) if failures < MODEL_FAIL_STREAK => HealthLevel::Warn,
+        (true, _) => HealthLevel::Fail,
+    };
+    ModelHealth {
+        level,
+        configured,
+        reachable,
+        last_call_at: last_call.as_ref().map(|call| call.at),
+        consecutive_failures,
+        last_success_at: last_call.as_ref().and_then(|call| call.last_success_at),
+        last_error: last_call.and_then(|call| call.error),
+    }
+}
+
+pub fn task_queue_health(
+    running_tasks: u32,
+    queued_tasks: u32,
+    queued_model_calls: u32,
+) -> TaskQueueHealth {
+    let level = if queued_tasks + queued_model_calls >= QUEUE_WARN_DEPTH {
+        HealthLevel::Warn
+    } else {
+        HealthLevel::Ok
+    };
+    TaskQueueHealth {
+        level,
+        running_tasks,
+        queued_tasks,
+        queued_model_calls,
+    }
+}
+
+pub fn disk_health(available_bytes: Option<u64>, total_bytes: Option<u64>) -> DiskHealth {
+    let level = match available_bytes {
+        None => HealthLevel::Warn,
+        Some(bytes) if bytes < DISK_FAIL_BYTES => HealthLevel::Fail,
+        Some(bytes) if bytes < DISK_WARN_BYTES => HealthLevel::Warn,
+        Some(_) => HealthLevel::Ok,
+    };
+    DiskHealth {
+        level,
+        available_bytes,
+        total_bytes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        disk_health, kb_health, model_health, storage_health, task_queue_health, HealthLevel,
+        HealthReport, SCHEMA_VERSION,
+    };
+    use crate::model::CallOutcome;
+    use crate::retrieval::KbIntegrityReport;
+
+    #[test]
+    fn levels_follow_each_subsystem_and_the_worst_wins() {
+        let storage = storage_health(Some(Ok(())), SCHEMA_VERSION);
+        assert_eq!(storage.level, HealthLevel::Ok);
+        assert_eq!(
+            storage_health(None, SCHEMA_VERSION).level,
+            HealthLevel::Warn
+        );
+        assert_eq!(
+            storage_health(Some(Err("disk I/O error".to_owned())), SCHEMA_VERSION).level,
+            HealthLevel::Fail
+        );
+        assert_eq!(storage_health(Some(Ok(())), 0).level, HealthLevel::Warn);
+
+        let integrity = KbIntegrityReport {
+            files_checked: 3,
+            files_passed: 2,
+            ..KbIntegrityReport::default()
+        };
+        let kb = kb_health(3, true, 0, &integrity);
+        assert_eq!((kb.level, kb.index_fresh), (HealthLevel::Warn, false));
+        assert_eq!(
+            kb_health(0, false, 0, &KbIntegrityReport::default()).level,
+            HealthLevel::Fail
+        );
+        // The bundled statutes still answer searches.
+        let bundled = kb_health(0, true, 0, &KbIntegrityReport::default());
+        assert_eq!(
+            (bundled.level, bundled.builtin_fallback),
+            (HealthLevel::Warn, true)
+        );
+
+        let failing = |consecutive_failures| {
+            model_health(
+                true,
+                Some(CallOutcome {
+                    at: 100,
+                    error: Some("status 401".to_owned()),
+                    consecutive_failures,
+                    last_success_at: Some(40),
+                }),
+            )
+        };
+        // One failure after calls that got through may be a blip.
+        let blip = failing(1);
+        assert_eq!(
+            (blip.level, blip.reachable, blip.last_success_at),
+            (HealthLevel::Warn, Some(false), Some(40))
+        );
+        let failed = failing(3);
+        assert_eq!(
+            (failed.level, failed.reachable, failed.last_error.as_deref()),
+            (HealthLevel::Fail, Some(false), Some("status 401"))
+        );
+        assert_eq!(model_health(true, None).level, HealthLevel::Ok);
+        assert_eq!(model_health(false, None).level, HealthLevel::Warn);
+
+        assert_eq!(task_queue_health(2, 1, 1).level, HealthLevel::Ok);
+        assert_eq!(task_queue_health(2, 3, 2).level, HealthLevel::Warn);
+        assert_eq!(disk_health(Some(10 << 20), None).level, HealthLevel::Fail);
+        assert_eq!(disk_health(Some(100 << 30), None).level, HealthLevel::Ok);
+
+        let report = HealthReport::new(
+            storage,
+            kb_health(3, true, 0, &KbIntegrityReport::default()),
+            failed,
+            task_queue_health(0, 0, 0),
+            disk_health(Some(100 << 30), Some(200 << 30)),
+            0,
+        );
+        assert_eq!(report.level, HealthLevel::Fail);
+    }
+}
@@ -1,33 +1,128 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::path::Path;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use std::sync::{mpsc, Arc, Mutex, RwLock};
+use std::sync::{mpsc, Arc, Mutex, RwLock, Weak};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use chrono::Utc;
 use once_cell::sync::Lazy;
 use serde_json::{json, Value};
 use uuid::Uuid;
 
 mod agent;
+mod analytics;
+pub mod clock;
 mod error;
+mod events;
+mod faults;
+mod health;
+// Without `model-remote` the connector settings are accepted but unused.
+#[cfg_attr(not(feature = "model-remote"), allow(dead_code))]
 mod model;
+mod redact;
+mod region;
 mod retrieval;
 mod safety;
+// Only model calls resolve API keys.
+#[cfg_attr(not(feature = "model-remote"), allow(dead_code))]
+mod secrets;
 mod storage;
 mod tools;
-
+mod transcription;
+
+use agent::acknowledgement;
+use agent::catalog::{self, CopyCatalog, CopyKey};
+use agent::citations::{enforce_citations, CitationCoverage, VerifiedSource};
+use agent::confidence::{assess_conclusion, ConclusionConfidence};
+use agent::estimate::{self, estimate_draft, DraftEstimate, DraftPlan};
+use agent::fingerprint::{stamp_report, verify_report, ReportVerification};
+use agent::language::{self, resolve_session_language};
+use agent::onboarding::{
+    copy_starter_kb, demo_case, demo_facts_summary, demo_report, onboarding_completed_at,
+    seed_demo_intake, DemoCase, FirstRunOptions, FirstRunResult, DEFAULT_DEMO_SCENARIO,
+    ONBOARDING_COMPLETE_KEY,
+};
+use agent::persona::{self, PersonaConfig};
+use agent::planner::{plan_queries, RESULTS_PER_QUERY};
+use agent::progress::ReportProgress;
+use agent::provenance::{
+    add_footnotes, record_report_provenance, report_provenance, ReportProvenance,
+    SectionProvenance, StatementProvenance,
+};
+use agent::quick::{
+    self as quick, build_quick_answer, DEFAULT_QUICK_ASK_BUDGET_MS, QUICK_CONTEXT_TOKEN_BUDGET,
+    QUICK_PHASE, QUICK_SEARCH_LIMIT,
+};
+use agent::review_gate::{self, PendingReview, PENDING_REVIEW_PHASE, PENDING_TRANSLATION_PHASE};
+use agent::share::{package_report, ReportSectionFilter, ShareFormat, SharedReport};
+use agent::stepping::{Stepper, TaskIntermediate};
+use agent::timing::{PhaseTimer, PhaseTimingCollector, PhaseTimingStats, TimedPhase};
+use agent::topics::{message_topic, split_intake, take_split_suggestion};
 use agent::{
-    advance_intake_index, build_report, collect_facts, format_facts_summary, intake_state,
-    mark_intake_done, save_answer, start_intake, AgentPhase,
+    advance_intake_index, assistant_role, build_report, case_dates, case_facts, clear_failed_task,
+    collect_facts, drafted_sections, fact_line, failed_task, format_case_metadata,
+    format_facts_summary, format_legal_analysis, glossary_enabled, intake_checkpoints,
+    intake_state, keeps_figures, legal_references, long_text_stub, mark_answer_unconfirmed,
+    mark_intake_done, merge_intake, name_intake_checkpoint, named_checkpoint,
+    normalize_case_metadata, note_invalid_answer, open_questions, record_drafted_sections,
+    record_failed_task, record_report_review, record_report_snapshot, report_disclaimer,
+    report_review, report_snapshot, report_tone, reset_intake, run_bounded, run_what_if,
+    save_answer, session_preference, session_time_zone, set_case_fact, set_session_preference,
+    start_intake, start_reask, take_pending_reask, user_words, AgentPhase, CaseFact, DraftSection,
+    DraftedSections, FactConflict, FactMergePolicy, FactOverride, FailedTask, IntakeCheckpoint,
+    OpenQuestion, ReportContent, ReportKbSnapshot, ReportReview, ReportSection, ReportTone,
+    TaskPriority, DEFAULT_MAX_MESSAGE_CHARS, DEFAULT_RISK_NOTICE, MAX_PARALLEL_SECTIONS,
+    OPEN_QUESTIONS_PHASE, PARALLEL_DRAFT_SECTIONS, PROCESS_PATH, TRANSLATION_PHASE, WHAT_IF_PHASE,
 };
+use analytics::query_log::{self, QueryLog, QueryLogCapture, QueryLogEvaluation};
+use analytics::{AnalyticsBatch, AnalyticsCollector, AnalyticsEvent, AnalyticsSink, KbFileUsage};
+use clock::{Clock, SystemClock};
 use error::{CoreError, CoreResult};
-use model::{ModelConnector, OpenRouterConfig, RetryConfig};
-use retrieval::{KnowledgeInfo, RetrievalEngine, SearchResult};
-use safety::{SafetyCheckResult, SafetyEngine, Severity};
-use storage::{LogEntry, Message, Session, SqliteStorage};
-use tools::{ToolContext, ToolRegistry};
+use events::payloads::{
+    self, AgentPhase as AgentPhaseEvent, Cancelled, Cancelling, Completed, ConfigUpdated,
+    DraftEstimated, EscalationRequired, FactsUpdated, IntakeDone, IntakeProgress, IntakeReset,
+    KbChanged, KbIntegrityFailed, LongTextStored, MessageCreated, MessagesCreated,
+    ModelConnectionOk, ModelPing, ModelReady, ModelUpdated, ModelWarmupFailed,
+    OpenQuestionsOffered, ProfileSwitched, ReportPendingReview, ReportRegenerating, ReportReleased,
+    ReportSection as ReportSectionEvent, ReportStale, RetentionPurged, ReviewAdjusted,
+    ReviewIntercepted, SecretRotated, SessionCreated, SessionSplit, SessionSplitSuggested,
+    SessionsMerged, SettingsCacheInvalidated, Subscribed, TaskError, TaskPaused, TaskProgress,
+    TaskRetrying, TestEvent, ToolCallClosed, ToolCallRequest, ToolCallResponse, ToolCallResult,
+    ToolCallSkipped, ToolCallsResponded, UserDataErased, WhatIfCompleted,
+};
+use events::EventHub;
+use faults::FAULT_SETTING_PREFIX;
+use health::HealthReport;
+use model::{
+    CallPriority, HttpPoolConfig, ModelConnector, ModelPhase, ModelPhaseUsage, ModelRoute,
+    ModelScheduler, OpenRouterConfig, RequestMemo, RetryConfig, SchedulerLimits,
+};
+use region::{DeploymentRegion, UserTimeZone};
+use retrieval::{
+    merge_query_results, trim_to_token_budget, KbIntegrityReport, KbValidationReport, KbWatcher,
+    KnowledgeChunk, KnowledgeFile, KnowledgeInfo, RetrievalConfig, RetrievalEngine, SearchResult,
+};
+use safety::{SafetyCheckResult, SafetyEngine, SafetyEvaluation, Severity};
+use secrets::{ApiKey, SecretProvider, MESSAGE_CONTENT_KEY_SECRET, MODEL_API_KEY_SECRET};
+use storage::{
+    activity::ActivityCursor, erasure::CONFIRM_TOKEN_TTL_SECS, monitor::QueryLimits, retention,
+    thread_messages, ActivityPage, Attachment, CaseMetadata, ContentCipher, DataDir, DataDirLayout,
+    ErasurePreview, ErasureSummary, EventFilter, LogEntry, Message, NewMessage, Profile,
+    PurgeSummary, QueryLogEntry, RetentionPolicy, SafetyIncident, SafetyIncidentFilter, Session,
+    SessionListOptions, SessionSearchHit, Setting, SqliteStorage, TaskTraceEntry,
+    DEFAULT_PROFILE_ID,
+};
+use tools::glossary::{append_glossary, GlossaryEntry};
+use tools::venue::venue_fallback;
+use tools::{
+    intake_questions_for_scenario, resolve_permission, save_seeded_policy, validate_permission,
+    PermissionPolicy, PostProcessStep, ToolConcurrency, ToolContext, ToolRegistry, ToolStats,
+    DEBUG_SETTING_PREFIX,
+};
+use transcription::{
+    clear_pending_transcript, pending_transcript, save_pending_transcript, transcribe, AudioInput,
+    IntakeTranscript, Transcriber,
+};
 
 static RUNTIME: Lazy<tokio::runtime::Runtime> = Lazy::new(|| {
     tokio::runtime::Builder::new_multi_thread()
@@ -37,11 +132,139 @@ static RUNTIME: Lazy<tokio::runtime::Runtime> = Lazy::new(|| {
         .expect("tokio runtime")
 });
 
+/// Token budget for retrieved snippets quoted in the legal analysis section
+/// when no model prompt limit applies.
+const LEGAL_CONTEXT_TOKEN_BUDGET: u32 = 1_500;
+
+/// With a prompt limit, those snippets get one in this many of its tokens;
+/// the style pass carries them together with the rest of the report.
+const LEGAL_CONTEXT_PROMPT_SHARE: u32 = 4;
+
+/// Most recent log rows scanned for a session's diagnostics export.
+const DIAGNOSTICS_LOG_SCAN: u32 = 500;
+
+/// Trace fields that carry user text (search queries, tool arguments); the
+/// diagnostics export keeps only their length.
+const DIAGNOSTICS_REDACTED_FIELDS: [&str; 2] = ["arguments", "query"];
+
+/// Maximum hits returned by `search_across_sessions`.
+const SESSION_SEARCH_LIMIT: u32 = 20;
+
+/// Items per page of `get_session_activity`, by default and at most.
+const ACTIVITY_PAGE_DEFAULT: u32 = 50;
+const ACTIVITY_PAGE_MAX: u32 = 500;
+
+/// Settings key of the profile selected with `switch_profile`.
+const ACTIVE_PROFILE_KEY: &str = "profile:active";
+
+/// Namespace of per-profile host settings, `profile:{id}:{key}`, outside
+/// the default profile.
+const PROFILE_SETTING_PREFIX: &str = "profile:";
+
+/// Setting keys that name a session or configure the whole core, and so
+/// never need a profile namespace.
+const UNSCOPED_SETTING_PREFIXES: [&str; 4] = [
+    "intake:",
+    "session:",
+    FAULT_SETTING_PREFIX,
+    DEBUG_SETTING_PREFIX,
+];
+
+/// How often the background retention job re-applies the purge policy.
+const RETENTION_PURGE_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
 #[derive(Debug, Clone, uniffi::Record)]
 pub struct CoreConfig {
     pub kb_path: String,
+    /// Database file; may be empty when `data_dir` is set.
     pub db_path: String,
     pub max_iterations: u32,
+    #[uniffi(default = None)]
+    pub retrieval: Option<RetrievalConfig>,
+    /// Watch `kb_path` for edits and emit `kb_changed`. Off by default since
+    /// file watching keeps the process awake on battery-powered devices.
+    #[uniffi(default = false)]
+    pub watch_kb: bool,
+    /// Selects disclaimer wording, escalation hotlines and date format.
+    /// Defaults to mainland China.
+    #[uniffi(default = None)]
+    pub region: Option<DeploymentRegion>,
+    /// Zone of dates shown to users, e.g. `Asia/Shanghai` or `+08:00`;
+    /// sessions may override it with the `timezone` preference. Defaults
+    /// to the region's: China Standard Time, or UTC overseas.
+    #[uniffi(default = None)]
+    pub time_zone: Option<String>,
+    /// Longest a storage call may keep SQLite busy before it is interrupted.
+    #[uniffi(default = None)]
+    pub query_timeout_ms: Option<u64>,
+    /// SQL statements slower than this are logged and counted in metrics.
+    #[uniffi(default = None)]
+    pub slow_query_threshold_ms: Option<u64>,
+    /// Longest user message stored as is, by `send_message` and
+    /// `create_message(s_bulk)` alike; longer text is kept as an attachment
+    /// and replaced by a short stub. Defaults to 4000.
+    #[uniffi(default = None)]
+    pub max_message_chars: Option<u32>,
+    /// Locale of the agent's own copy (intake prompts, acknowledgements).
+    /// Defaults to `zh-CN`; see `set_copy_override`.
+    #[uniffi(default = None)]
+    pub locale: Option<String>,
+    /// How many recent events to keep in the database for
+    /// `get_recent_events`. Unset or 0 records nothing.
+    #[uniffi(default = None)]
+    pub event_history_size: Option<u32>,
+    /// Directory holding the database (`core.db` unless `db_path` is set)
+    /// and the `index`, `cache` and `attachments` directories. Defaults to
+    /// the directory of `db_path`.
+    #[uniffi(default = None)]
+    pub data_dir: Option<String>,
+    /// Open an existing database without taking its lock, e.g. for a UI
+    /// process next to a service core. Writes fail. Only one writable core
+    /// can have a database open at a time.
+    #[uniffi(default = false)]
+    pub read_only: bool,
+    /// JSON file of default tool permissions, per scenario or for all; see
+    /// `get_tool_permission` for how they combine with the user's choices.
+    #[uniffi(default = None)]
+    pub permission_policy_path: Option<String>,
+    /// Encrypt message content, recorded event payloads, intake answers,
+    /// report drafts, traces, safety incidents and the query log in the
+    /// database with a key derived from the `message_content_key` secret;
+    /// see `set_secret_provider`. `update_core_config` only turns it on once
+    /// that key is known. Cross-session search is off meanwhile, since its
+    /// full-text index would hold the same text in the clear.
+    #[uniffi(default = false)]
+    pub encrypt_message_content: bool,
+    /// Withhold finished reports until a reviewer approves them; see
+    /// `list_pending_reviews`.
+    #[uniffi(default = false)]
+    pub require_report_review: bool,
+}
+
+impl CoreConfig {
+    /// A config with the required paths and iteration limit and every other
+    /// setting at its default; set the rest with struct update syntax.
+    pub fn new(kb_path: String, db_path: String, max_iterations: u32) -> Self {
+        Self {
+            kb_path,
+            db_path,
+            max_iterations,
+            retrieval: None,
+            watch_kb: false,
+            region: None,
+            time_zone: None,
+            query_timeout_ms: None,
+            slow_query_threshold_ms: None,
+            max_message_chars: None,
+            locale: None,
+            event_history_size: None,
+            data_dir: None,
+            read_only: false,
+            permission_policy_path: None,
+            encrypt_message_content: false,
+            require_report_review: false,
+        }
+    }
 }
 
 #[derive(Debug, Clone, uniffi::Record)]
@@ -53,6 +276,32 @@ pub struct ModelConfig {
     pub retry_initial_delay_ms: u64,
     pub retry_max_delay_ms: u64,
     pub retry_backoff_factor: f64,
+    /// Estimated prompt tokens allowed per model call; longer prompts are
+    /// trimmed to fit. `0` disables the guard.
+    #[uniffi(default = 16000)]
+    pub max_prompt_tokens: u32,
+    /// Idle pooled connections are closed after this many seconds.
+    #[uniffi(default = 90)]
+    pub pool_idle_timeout_secs: u64,
+    #[uniffi(default = 4)]
+    pub pool_max_idle_per_host: u32,
+    /// HTTP/2 keep-alive ping interval; `0` disables pings.
+    #[uniffi(default = 30)]
+    pub http2_keep_alive_interval_secs: u64,
+    /// Model calls allowed in flight across all sessions; `0` is treated as 1.
+    #[uniffi(default = 2)]
+    pub max_concurrent_requests: u32,
+    /// Minimum spacing between model calls, for rate-limited providers.
+    #[uniffi(default = 0)]
+    pub min_request_interval_ms: u64,
+    /// Pre-flight the model in the background after `update_model_config`,
+    /// reporting `model_ready` or `model_warmup_failed`.
+    #[uniffi(default = false)]
+    pub warm_up: bool,
+    /// Model and parameters per agent phase, e.g. a cheap model for
+    /// `plan`; unrouted phases use `model_name`.
+    #[uniffi(default = [])]
+    pub phase_models: Vec<ModelRoute>,
 }
 
 impl Default for ModelConfig {
@@ -65,10 +314,48 @@ impl Default for ModelConfig {
             retry_initial_delay_ms: 200,
             retry_max_delay_ms: 10_000,
             retry_backoff_factor: 2.0,
+            max_prompt_tokens: 16_000,
+            pool_idle_timeout_secs: 90,
+            pool_max_idle_per_host: 4,
+            http2_keep_alive_interval_secs: 30,
+            max_concurrent_requests: 2,
+            min_request_interval_ms: 0,
+            warm_up: false,
+            phase_models: Vec::new(),
         }
     }
 }
 
+/// Runtime counters for diagnostics screens. Model counters cover the current
+/// model configuration and restart from zero on `update_model_config`; query
+/// counters cover the lifetime of the `Core`.
+#[derive(Debug, Clone, Default, uniffi::Record)]
+pub struct CoreMetrics {
+    pub model_requests: u64,
+    pub model_connections_opened: u64,
+    pub model_connections_reused: u64,
+    /// SQL statements that ran past the slow-query threshold.
+    pub slow_queries: u64,
+    pub slowest_query_ms: u64,
+    /// Literal-free text of the slowest statement.
+    pub slowest_query: Option<String>,
+    /// Storage calls interrupted by the query timeout.
+    pub queries_interrupted: u64,
+    /// Reads of tool permissions and hot settings answered from memory,
+    /// and those that went to the database.
+    pub settings_cache_hits: u64,
+    pub settings_cache_misses: u64,
+    /// Panics caught in `EventListener::on_event`, and listeners
+    /// unsubscribed after failing repeatedly.
+    pub listener_panics: u64,
+    pub listeners_unsubscribed: u64,
+    pub tool_stats: Vec<ToolStats>,
+    /// Average time per agent phase over tasks completed since startup.
+    pub phase_timings: Vec<PhaseTimingStats>,
+    /// Agent model calls per phase and model.
+    pub model_usage: Vec<ModelPhaseUsage>,
+}
+
 #[derive(Debug, Clone, uniffi::Record)]
 pub struct CoreEvent {
     pub kind: String,
@@ -76,6 +363,78 @@ pub struct CoreEvent {
     pub timestamp: i64,
 }
 
+/// KB integrity together with how the agent uses each file.
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct KbHealthReport {
+    pub integrity: KbIntegrityReport,
+    /// Every KB file, most cited first; files the agent never used have
+    /// zero counts. Empty counts throughout while analytics is off.
+    pub usage: Vec<KbFileUsage>,
+    /// KB files never cited in a report.
+    pub uncited_files: u32,
+}
+
+/// Whether a session's report still reflects the KB.
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct ReportFreshness {
+    pub message_id: String,
+    pub generated_at: i64,
+    /// `KnowledgeInfo::updated_at` when the report was generated.
+    pub report_kb_updated_at: i64,
+    pub current_kb_updated_at: i64,
+    /// KB-relative paths modified since the report.
+    pub changed_files: Vec<String>,
+    /// Files changed, or files were added or removed.
+    pub stale: bool,
+}
+
+/// What `merge_sessions` moved into the target session.
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct SessionMergeResult {
+    pub target_id: String,
+    pub messages_moved: u32,
+    pub attachments_moved: u32,
+    pub logs_moved: u32,
+    /// Intake answers the sessions disagreed on, with how each was settled.
+    pub conflicts: Vec<FactConflict>,
+}
+
+/// What `split_session` moved into the new session.
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct SessionSplitResult {
+    pub session_id: String,
+    pub scenario: String,
+    pub messages_moved: u32,
+    /// Intake answers taken from the moved messages and carried over,
+    /// which happens when both sessions share a scenario. They are
+    /// reopened in the source.
+    pub facts_moved: u32,
+    /// Intake answers taken from the moved messages that the new session's
+    /// scenario does not ask, so they are dropped; reopened in the source
+    /// as well.
+    #[uniffi(default = 0)]
+    pub facts_discarded: u32,
+}
+
+/// A tool call waiting for the user's approval.
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct PendingToolCallInfo {
+    pub request_id: String,
+    pub task_id: String,
+    pub session_id: String,
+    pub tool_name: String,
+    /// Tool arguments as JSON, as in the `tool_call_request` event.
+    pub arguments_json: String,
+    pub requested_at: i64,
+}
+
+/// Outcome of one request of a `respond_tool_calls_bulk` batch.
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct BulkToolCallOutcome {
+    pub request_id: String,
+    pub outcome: ToolCallOutcome,
+}
+
 #[derive(Debug, Clone, uniffi::Record)]
 pub struct Subscription {
     pub id: u64,
@@ -88,6 +447,71 @@ pub enum ToolResponse {
     Deny,
 }
 
+/// What happened to a `respond_tool_call` answer. Only an unknown request id
+/// is an error; every other race is reported here so the host can close the
+/// approval dialog without guessing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, uniffi::Enum)]
+pub enum ToolCallOutcome {
+    Delivered,
+    TaskCancelled,
+    Expired,
+    AlreadyResponded,
+    /// The host skipped the call with `cancel_pending_tool_call`.
+    Skipped,
+}
+
+impl ToolCallOutcome {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Delivered => "delivered",
+            Self::TaskCancelled => "task_cancelled",
+            Self::Expired => "expired",
+            Self::AlreadyResponded => "already_responded",
+            Self::Skipped => "skipped",
+        }
+    }
+}
+
+/// How `regenerate_report_with_mode` rebuilds the report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, uniffi::Enum)]
+pub enum ReportRegenerationMode {
+    Full,
+    /// Keep facts and conclusions; re-check the legal basis and citations
+    /// against the current KB.
+    CitationsOnly,
+}
+
+impl ReportRegenerationMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Full => "full",
+            Self::CitationsOnly => "citations_only",
+        }
+    }
+
+    fn request(self) -> &'static str {
+        match self {
+            Self::Full => "请基于已收集的事实重新生成一版完整法律咨询报告。",
+            Self::CitationsOnly => {
+                "请保留已收集的事实与结论，依据最新知识库重新核对法律依据与引用，重新生成报告。"
+            }
+        }
+    }
+}
+
+/// What a task was started for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TaskKind {
+    /// Answer the user's message.
+    Answer,
+    /// Rebuild the latest report; see `Core::regenerate_report_with_mode`.
+    Regenerate(ReportRegenerationMode),
+    /// Write the draft an estimate stopped at; see `Core::confirm_draft`.
+    ConfirmedDraft,
+}
+
+/// A listener that panics is skipped for that event; after a few panics in
+/// a row it is unsubscribed.
 #[uniffi::export(callback_interface)]
 pub trait EventListener: Send + Sync {
     fn on_event(&self, event: CoreEvent);
@@ -96,12 +520,15 @@ pub trait EventListener: Send + Sync {
 #[derive(Default)]
 struct TaskControl {
     cancelled: AtomicBool,
+    /// Set for tasks of step-through sessions; see `agent::stepping`.
+    stepper: Option<Stepper>,
 }
 
 impl TaskControl {
-    fn new() -> Self {
+    fn new(stepper: Option<Stepper>) -> Self {
         Self {
             cancelled: AtomicBool::new(false),
+            stepper,
         }
     }
 
@@ -114,34 +541,61 @@ impl TaskControl {
     }
 }
 
+/// Answer delivered to a worker waiting on a tool approval.
+#[derive(Clone)]
+enum ToolDecision {
+    Respond(ToolResponse),
+    /// Deny this one call and let the agent fall back where it can.
+    Skip {
+        reason: String,
+    },
+}
+
+/// Result of a tool call that went through the approval flow.
+enum ToolRun {
+    Ran(Value),
+    Skipped(String),
+}
+
 struct PendingToolCall {
-    sender: mpsc::Sender<ToolResponse>,
+    sender: mpsc::Sender<ToolDecision>,
+    task_id: String,
     session_id: String,
     tool_name: String,
+    arguments: Value,
+    requested_at: i64,
 }
 
-#[derive(uniffi::Object)]
-pub struct Core {
+/// How long a worker waits for the host to answer a tool approval request.
+const TOOL_CALL_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// Reason recorded when the host skips a pending tool call.
+const TOOL_CALL_SKIPPED_REASON: &str = "cancelled by user";
+/// Number of settled request ids remembered for late `respond_tool_call`s.
+const SETTLED_TOOL_CALL_CAPACITY: usize = 256;
+
+/// The part of `CoreConfig` that `update_core_config` can swap without a
+/// new `Core`. Tasks copy what they need when they start, so a swap never
+/// changes a running task.
+struct RuntimeConfig {
     kb_path: String,
     max_iterations: u32,
-    storage: Arc<SqliteStorage>,
+    max_message_chars: usize,
+    locale: String,
+    region: DeploymentRegion,
+    time_zone: UserTimeZone,
     retrieval: Arc<RetrievalEngine>,
-    safety: Arc<SafetyEngine>,
-    tools: Arc<ToolRegistry>,
-    model_connector: Arc<RwLock<Option<ModelConnector>>>,
-    listeners: Arc<Mutex<HashMap<u64, Arc<dyn EventListener>>>>,
-    next_listener_id: AtomicU64,
-    task_controls: Arc<Mutex<HashMap<String, Arc<TaskControl>>>>,
-    pending_tool_calls: Arc<Mutex<HashMap<String, PendingToolCall>>>,
-    session_allow_all: Arc<Mutex<HashSet<String>>>,
-    /// Per-session lock: ensures only one AgentWorker runs per session at a time
-    session_locks: Arc<Mutex<HashMap<String, Arc<Mutex<()>>>>>,
+    permission_policy: Arc<PermissionPolicy>,
+    require_report_review: bool,
 }
 
-#[uniffi::export]
-impl Core {
-    #[uniffi::constructor]
-    pub fn new(config: CoreConfig) -> CoreResult<Arc<Self>> {
+impl RuntimeConfig {
+    /// Validate `config` and open its KB, creating `kb_path` if needed.
+    /// `retrieval` is reused instead when given; a new engine reads `clock`.
+    fn new(
+        config: &CoreConfig,
+        retrieval: Option<Arc<RetrievalEngine>>,
+        clock: &Arc<dyn Clock>,
+    ) -> CoreResult<Self> {
         if config.max_iterations == 0 {
             return Err(CoreError::Config("max_iterations must be > 0".to_owned()));
         }
@@ -154,31 +608,128 @@ impl Core {
             }
         }
 
-        if let Some(parent) = Path::new(&config.db_path).parent() {
-            std::fs::create_dir_all(parent)
-                .map_err(|e| CoreError::Config(format!("failed to create db directory: {e}")))?;
+        let permission_policy = match config
+            .permission_policy_path
+            .as_deref()
+            .filter(|path| !path.is_empty())
+        {
+            Some(path) => PermissionPolicy::load(Path::new(path))?,
+            None => PermissionPolicy::default(),
+        };
+        let region = config.region.unwrap_or_default();
+        let time_zone = match config.time_zone.as_deref() {
+            Some(raw) => UserTimeZone::parse(raw).ok_or_else(|| {
+                CoreError::Config(format!(
+                    "invalid time_zone {raw}: expected an offset such as +08:00 or a zone \
+                     without daylight saving time such as Asia/Shanghai"
+                ))
+            })?,
+            None => region.time_zone(),
+        };
+
+        Ok(Self {
+            kb_path: config.kb_path.clone(),
+            max_iterations: config.max_iterations,
+            max_message_chars: config
+                .max_message_chars
+                .unwrap_or(DEFAULT_MAX_MESSAGE_CHARS) as usize,
+            locale: config
+                .locale
+                .clone()
+                .unwrap_or_else(|| catalog::DEFAULT_LOCALE.to_owned()),
+            region,
+            time_zone,
+            retrieval: retrieval.unwrap_or_else(|| {
+                let engine = RetrievalEngine::new(
+                    &config.kb_path,
+                    config.retrieval.clone().unwrap_or_default(),
+                )
+                .with_clock(clock.clone());
+                // A KB that cannot be written is searched with the bundled
+                // statutes in memory instead.
+                if !config.read_only {
+                    match engine.bootstrap_builtin_kb() {
+                        Ok(0) => {}
+                        Ok(written) => tracing::info!(
+                            "kb at {} was empty; extracted {written} bundled statute files",
+                            config.kb_path
+                        ),
+                        Err(err) => tracing::warn!("bundled statutes not extracted: {err}"),
+                    }
+                }
+                Arc::new(engine)
+            }),
+            permission_policy: Arc::new(permission_policy),
+            require_report_review: config.require_report_review,
+        })
+    }
+}
+
+/// Requests that are no longer pending, with the reason they closed. Bounded:
+/// the oldest entries are forgotten first.
+#[derive(Default)]
+struct SettledToolCalls {
+    outcomes: HashMap<String, ToolCallOutcome>,
+    order: VecDeque<String>,
+}
+
+impl SettledToolCalls {
+    fn insert(&mut self, request_id: String, outcome: ToolCallOutcome) {
+        if self.outcomes.insert(request_id.clone(), outcome).is_none() {
+            self.order.push_back(request_id);
+        }
+        while self.order.len() > SETTLED_TOOL_CALL_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.outcomes.remove(&oldest);
+            }
         }
+    }
 
-        let storage = Arc::new(SqliteStorage::new(&config.db_path)?);
-        let retrieval = Arc::new(RetrievalEngine::new(&config.kb_path));
-        let safety = Arc::new(SafetyEngine::default());
-        let tools = Arc::new(ToolRegistry::with_builtins());
+    fn get(&self, request_id: &str) -> Option<ToolCallOutcome> {
+        self.outcomes.get(request_id).copied()
+    }
+}
 
-        Ok(Arc::new(Self {
-            kb_path: config.kb_path,
-            max_iterations: config.max_iterations,
-            storage,
-            retrieval,
-            safety,
-            tools,
-            model_connector: Arc::new(RwLock::new(None)),
-            listeners: Arc::new(Mutex::new(HashMap::new())),
-            next_listener_id: AtomicU64::new(1),
-            task_controls: Arc::new(Mutex::new(HashMap::new())),
-            pending_tool_calls: Arc::new(Mutex::new(HashMap::new())),
-            session_allow_all: Arc::new(Mutex::new(HashSet::new())),
-            session_locks: Arc::new(Mutex::new(HashMap::new())),
-        }))
+#[derive(uniffi::Object)]
+pub struct Core {
+    data_dir: DataDir,
+    event_history_size: u32,
+    runtime: RwLock<Arc<RuntimeConfig>>,
+    clock: Arc<dyn Clock>,
+    storage: Arc<SqliteStorage>,
+    /// Profile whose sessions and settings the host currently sees.
+    active_profile: RwLock<String>,
+    kb_watcher: Mutex<Option<KbWatcher>>,
+    safety: Arc<SafetyEngine>,
+    tools: Arc<ToolRegistry>,
+    model_connector: Arc<RwLock<Option<ModelConnector>>>,
+    /// Bumped on every `update_model_config`; warm-ups of a replaced
+    /// configuration stay silent.
+    model_generation: Arc<AtomicU64>,
+    /// Orders model calls of all running tasks under the shared limits.
+    model_scheduler: Arc<ModelScheduler>,
+    secret_provider: RwLock<Option<Arc<dyn SecretProvider>>>,
+    transcriber: RwLock<Option<Arc<dyn Transcriber>>>,
+    events: Arc<EventHub>,
+    task_controls: Arc<Mutex<HashMap<String, Arc<TaskControl>>>>,
+    pending_tool_calls: Arc<Mutex<HashMap<String, PendingToolCall>>>,
+    settled_tool_calls: Arc<Mutex<SettledToolCalls>>,
+    session_allow_all: Arc<Mutex<HashSet<String>>>,
+    analytics: Arc<AnalyticsCollector>,
+    query_log: Arc<QueryLog>,
+    phase_timings: Arc<PhaseTimingCollector>,
+    /// Per-session task queue: one AgentWorker runs per session at a time,
+    /// waiting tasks start in `TaskPriority` order.
+    session_queues: Arc<Mutex<HashMap<String, Arc<ModelScheduler>>>>,
+    /// Token of the last `preview_delete_all_user_data` and when it expires.
+    erasure_token: Mutex<Option<(String, i64)>>,
+}
+
+#[uniffi::export]
+impl Core {
+    #[uniffi::constructor]
+    pub fn new(config: CoreConfig) -> CoreResult<Arc<Self>> {
+        Self::with_clock(config, Arc::new(SystemClock))
     }
 
     pub fn hello(&self) -> String {
@@ -186,1192 +737,8308 @@ impl Core {
     }
 
     pub fn core_info(&self) -> String {
+        let runtime = self.runtime();
         format!(
             "kb_path={}, max_iterations={}",
-            self.kb_path, self.max_iterations
+            runtime.kb_path, runtime.max_iterations
         )
     }
 
-    pub fn subscribe_events(&self, listener: Box<dyn EventListener>) -> CoreResult<Subscription> {
-        let id = self.next_listener_id.fetch_add(1, Ordering::Relaxed);
-        let listener: Arc<dyn EventListener> = Arc::from(listener);
-        {
-            let mut listeners = self
-                .listeners
-                .lock()
-                .map_err(|_| CoreError::InvalidState("event listener lock poisoned".to_owned()))?;
-            listeners.insert(id, listener);
-        } // <-- drop lock BEFORE emitting
-
-        emit_event_static(
-            &self.listeners,
-            "subscribed",
-            format!("subscription_id={id}"),
-        );
-        Ok(Subscription { id })
+    /// Where this core keeps its database and files.
+    pub fn data_dir_layout(&self) -> DataDirLayout {
+        self.data_dir.layout()
     }
 
-    pub fn unsubscribe_events(&self, subscription_id: u64) -> CoreResult<()> {
-        let mut listeners = self
-            .listeners
-            .lock()
-            .map_err(|_| CoreError::InvalidState("event listener lock poisoned".to_owned()))?;
-
-        if listeners.remove(&subscription_id).is_none() {
-            return Err(CoreError::NotFound(format!(
-                "subscription {subscription_id}"
+    /// Apply a new configuration to this `Core`: KB, limits, locale, region,
+    /// query limits and KB watching. Storage, listeners and running tasks
+    /// are kept; tasks started from now on use the new values. `db_path`,
+    /// `data_dir`, `read_only` and `event_history_size` cannot change this
+    /// way.
+    pub fn update_core_config(&self, config: CoreConfig) -> CoreResult<()> {
+        if DataDir::resolve_db_path(config.data_dir.as_deref(), &config.db_path)?
+            != self.data_dir.db_path()
+            || config
+                .data_dir
+                .as_deref()
+                .is_some_and(|root| root != self.data_dir.layout().root)
+            || config.read_only != self.data_dir.read_only()
+        {
+            return Err(CoreError::Config(
+                "db_path, data_dir and read_only cannot change without a new Core".to_owned(),
+            ));
+        }
+        if config.event_history_size.unwrap_or(0) != self.event_history_size {
+            return Err(CoreError::Config(
+                "event_history_size cannot change without a new Core".to_owned(),
+            ));
+        }
+        if config.encrypt_message_content
+            && !self.storage.content_encryption()
+            && !self.storage.has_content_cipher()
+        {
+            return Err(CoreError::Config(format!(
+                "encrypt_message_content needs secret {MESSAGE_CONTENT_KEY_SECRET}; register a secret provider first"
             )));
         }
+        let current = self.runtime();
+        let kb_moved = current.kb_path != config.kb_path;
+        // Keep the loaded engine unless the KB or its effective retrieval
+        // settings change; no `retrieval` means the defaults.
+        let kb_reloaded =
+            kb_moved || config.retrieval.clone().unwrap_or_default() != *current.retrieval.config();
+        let runtime = Arc::new(RuntimeConfig::new(
+            &config,
+            (!kb_reloaded).then(|| current.retrieval.clone()),
+            &self.clock,
+        )?);
+
+        let mut watcher = self
+            .kb_watcher
+            .lock()
+            .map_err(|_| CoreError::InvalidState("kb watcher lock poisoned".to_owned()))?;
+        // Everything that can fail happens before the swap.
+        let restarted = if config.watch_kb && (kb_moved || watcher.is_none()) {
+            Some(start_kb_watcher(&runtime.kb_path, &self.events)?)
+        } else {
+            None
+        };
+        self.storage.set_query_limits(query_limits(&config))?;
+        self.storage
+            .set_content_encryption(config.encrypt_message_content);
+        self.seal_message_content()?;
+        *self
+            .runtime
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = runtime.clone();
+        if !config.watch_kb {
+            *watcher = None;
+        } else if restarted.is_some() {
+            *watcher = restarted;
+        }
+        drop(watcher);
 
+        if kb_reloaded {
+            check_kb_integrity(&runtime.retrieval, &self.events)?;
+        }
+        self.events.emit(&ConfigUpdated {
+            kb_path: runtime.kb_path.clone(),
+            max_iterations: runtime.max_iterations,
+            kb_reloaded,
+        });
         Ok(())
     }
 
-    pub fn emit_test_event(&self, message: String) {
-        emit_event_static(&self.listeners, "test", message);
+    pub fn subscribe_events(&self, listener: Box<dyn EventListener>) -> CoreResult<Subscription> {
+        let id = self.events.subscribe(Arc::from(listener))?;
+
+        self.events.emit(&Subscribed {
+            subscription_id: id,
+        });
+        Ok(Subscription { id })
     }
 
-    pub fn create_session(&self, scenario: String, title: Option<String>) -> CoreResult<String> {
-        let session = self.storage.create_session(&scenario, title.as_deref())?;
-        emit_event_static(
-            &self.listeners,
-            "session_created",
-            format!("session_id={},scenario={}", session.id, session.scenario),
-        );
-        Ok(session.id)
+    pub fn unsubscribe_events(&self, subscription_id: u64) -> CoreResult<()> {
+        self.events.unsubscribe(subscription_id)
     }
 
-    pub fn list_sessions(&self) -> CoreResult<Vec<Session>> {
-        self.storage.list_sessions()
+    /// Events recorded at or after `since_ts`, oldest first, for a consumer
+    /// catching up on what it missed. Reads the shared database, so events
+    /// recorded by another process using the same `db_path` are included;
+    /// only cores with `event_history_size` set record any. Subscribe before
+    /// calling and drop duplicates, since timestamps have second resolution.
+    pub fn get_recent_events(
+        &self,
+        since_ts: i64,
+        filter: Option<EventFilter>,
+    ) -> CoreResult<Vec<CoreEvent>> {
+        self.storage
+            .list_events(since_ts, &filter.unwrap_or_default())
     }
 
-    pub fn update_session_title(&self, session_id: String, title: String) -> CoreResult<()> {
-        self.storage.update_session_title(&session_id, &title)
+    /// JSON Schema of every event payload, keyed by event kind, for
+    /// generating typed payload decoders on the client. Payloads carry the
+    /// same `schema_version` as the document.
+    pub fn get_event_schema(&self) -> String {
+        payloads::event_schema().to_string()
     }
 
-    pub fn delete_session(&self, session_id: String) -> CoreResult<()> {
-        self.storage.delete_session(&session_id)
+    pub fn emit_test_event(&self, message: String) {
+        self.events.emit(&TestEvent { message });
     }
 
-    pub fn create_message(
-        &self,
-        session_id: String,
-        role: String,
-        content: String,
-        phase: Option<String>,
-        tool_calls_json: Option<String>,
-    ) -> CoreResult<Message> {
-        let tool_calls: Option<Value> =
-            match tool_calls_json {
-                Some(raw) => Some(serde_json::from_str(&raw).map_err(|e| {
-                    CoreError::InvalidState(format!("invalid tool_calls json: {e}"))
-                })?),
-                None => None,
-            };
+    /// Set up a fresh install: copy `options.starter_kb_path` into
+    /// `kb_path`, seed default tool permissions and the copy and persona
+    /// the user has not set, optionally add a demo session with a sample
+    /// report, and record onboarding as done. Everything but the KB copy
+    /// is stored in one transaction. Once done, later calls change nothing
+    /// and return `already_completed`.
+    pub fn initialize_first_run(&self, options: FirstRunOptions) -> CoreResult<FirstRunResult> {
+        if onboarding_completed_at(&self.storage)?.is_some() {
+            return Ok(FirstRunResult {
+                already_completed: true,
+                ..FirstRunResult::default()
+            });
+        }
+        for seed in &options.tool_permissions {
+            validate_permission(&seed.tool_name, &seed.permission)?;
+        }
+        for seed in &options.copy_overrides {
+            CopyKey::parse(&seed.key)
+                .ok_or_else(|| CoreError::Config(format!("unknown copy key {}", seed.key)))?;
+        }
+        let persona = options
+            .persona
+            .map(|config| persona::normalize(config, &self.safety))
+            .transpose()?
+            .filter(|config| !config.is_empty());
+        let demo = options
+            .create_demo_session
+            .then(|| {
+                demo_case(
+                    options
+                        .demo_scenario
+                        .as_deref()
+                        .unwrap_or(DEFAULT_DEMO_SCENARIO),
+                )
+            })
+            .transpose()?;
+
+        let runtime = self.runtime();
+        let mut result = FirstRunResult::default();
+        if let Some(source) = options
+            .starter_kb_path
+            .as_deref()
+            .filter(|path| !path.is_empty())
+        {
+            if runtime.kb_path.is_empty() {
+                return Err(CoreError::Config(
+                    "kb_path must be set to copy a starter KB".to_owned(),
+                ));
+            }
+            result.kb_files_copied =
+                copy_starter_kb(Path::new(source), Path::new(&runtime.kb_path))?;
+            if !result.kb_files_copied.is_empty() {
+                self.events.emit(&KbChanged {
+                    paths: result.kb_files_copied.clone(),
+                });
+            }
+        }
 
-        let message = self.storage.create_message(
-            &session_id,
-            &role,
-            &content,
-            phase.as_deref(),
-            tool_calls.as_ref(),
-        )?;
+        let demo_session = self.storage.with_tx(|| {
+            if !options.tool_permissions.is_empty() {
+                let defaults = options
+                    .tool_permissions
+                    .iter()
+                    .map(|seed| (seed.tool_name.clone(), seed.permission.clone()))
+                    .collect::<HashMap<_, _>>();
+                result.permissions_seeded = defaults.len() as u32;
+                save_seeded_policy(&self.storage, &PermissionPolicy::from_defaults(defaults))?;
+            }
+            for seed in &options.copy_overrides {
+                if !catalog::has_copy_override(&self.storage, &runtime.locale, &seed.key)? {
+                    catalog::set_copy_override(
+                        &self.storage,
+                        &runtime.locale,
+                        &seed.key,
+                        Some(&seed.text),
+                    )?;
+                    result.copy_overrides_seeded += 1;
+                }
+            }
+            if let Some(persona) = &persona {
+                if persona::load(&self.storage)?.is_empty() {
+                    persona::save(&self.storage, persona)?;
+                    result.persona_seeded = true;
+                }
+            }
+            let demo_session = demo
+                .map(|case| self.create_demo_session(&runtime, case))
+                .transpose()?;
+            self.storage
+                .set_setting(ONBOARDING_COMPLETE_KEY, &self.clock.timestamp().to_string())?;
+            Ok(demo_session)
+        })?;
 
-        emit_event_static(
-            &self.listeners,
-            "message_created",
-            format!(
-                "session_id={},message_id={}",
-                message.session_id, message.id
-            ),
-        );
-        Ok(message)
+        // Announced only once committed.
+        if let Some((session, messages)) = demo_session {
+            self.analytics.record(AnalyticsEvent::SessionStarted);
+            self.events.emit(&SessionCreated {
+                session_id: session.id.clone(),
+                scenario: session.scenario,
+            });
+            for message in messages {
+                self.events.emit(&MessageCreated {
+                    session_id: message.session_id,
+                    message_id: message.id,
+                });
+            }
+            result.demo_session_id = Some(session.id);
+        }
+        Ok(result)
     }
 
-    pub fn get_messages(&self, session_id: String) -> CoreResult<Vec<Message>> {
-        self.storage.get_messages(&session_id)
+    pub fn is_onboarding_complete(&self) -> CoreResult<bool> {
+        Ok(onboarding_completed_at(&self.storage)?.is_some())
     }
 
-    pub fn set_setting(&self, key: String, value: String) -> CoreResult<()> {
-        self.storage.set_setting(&key, &value)
+    pub fn create_session(&self, scenario: String, title: Option<String>) -> CoreResult<String> {
+        let session =
+            self.storage
+                .create_session(&scenario, title.as_deref(), &self.active_profile_id())?;
+        self.analytics.record(AnalyticsEvent::SessionStarted);
+        self.events.emit(&SessionCreated {
+            session_id: session.id.clone(),
+            scenario: session.scenario,
+        });
+        Ok(session.id)
     }
 
-    pub fn get_setting(&self, key: String) -> CoreResult<Option<String>> {
-        self.storage.get_setting(&key)
+    /// Sessions of the active profile sorted and filtered by `options`;
+    /// `None` lists all of them, pinned sessions first.
+    #[uniffi::method(default(options = None))]
+    pub fn list_sessions(&self, options: Option<SessionListOptions>) -> CoreResult<Vec<Session>> {
+        self.storage
+            .list_sessions(&self.active_profile_id(), &options.unwrap_or_default())
     }
 
-    pub fn set_tool_permission(&self, tool_name: String, permission: String) -> CoreResult<()> {
-        self.storage.set_tool_permission(&tool_name, &permission)
+    /// Add a profile for someone else sharing the device. It starts with no
+    /// sessions; `switch_profile` makes it active.
+    pub fn create_profile(&self, name: String) -> CoreResult<Profile> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(CoreError::Config(
+                "profile name must not be empty".to_owned(),
+            ));
+        }
+        self.storage.create_profile(name)
     }
 
-    pub fn get_tool_permission(&self, tool_name: String) -> CoreResult<String> {
-        self.storage.get_tool_permission(&tool_name)
+    pub fn list_profiles(&self) -> CoreResult<Vec<Profile>> {
+        self.storage.list_profiles()
     }
 
-    pub fn append_log(
-        &self,
-        level: String,
-        message: String,
-        session_id: Option<String>,
-    ) -> CoreResult<i64> {
+    pub fn get_active_profile(&self) -> CoreResult<Profile> {
+        let profile_id = self.active_profile_id();
         self.storage
-            .append_log(&level, &message, session_id.as_deref())
+            .get_profile(&profile_id)?
+            .ok_or_else(|| CoreError::NotFound(format!("profile {profile_id}")))
     }
 
-    pub fn list_logs(&self, limit: u32) -> CoreResult<Vec<LogEntry>> {
-        self.storage.list_logs(limit)
+    /// Make `profile_id` the profile whose sessions, case search and
+    /// settings the host sees, also after a restart.
+    pub fn switch_profile(&self, profile_id: String) -> CoreResult<()> {
+        if self.storage.get_profile(&profile_id)?.is_none() {
+            return Err(CoreError::NotFound(format!("profile {profile_id}")));
+        }
+        self.storage.set_setting(ACTIVE_PROFILE_KEY, &profile_id)?;
+        *self
+            .active_profile
+            .write()
+            .map_err(|_| CoreError::InvalidState("active_profile lock poisoned".to_owned()))? =
+            profile_id.clone();
+        self.events.emit(&ProfileSwitched { profile_id });
+        Ok(())
     }
 
-    pub fn update_model_config(&self, config: ModelConfig) -> CoreResult<()> {
-        let connector = ModelConnector::new(OpenRouterConfig {
-            api_key: config.api_key,
-            model_name: config.model_name,
-            base_url: config
-                .base_url
-                .unwrap_or_else(|| "https://openrouter.ai/api/v1".to_owned()),
-            retry: RetryConfig {
-                max_retries: config.retry_max_retries,
-                initial_delay_ms: config.retry_initial_delay_ms,
-                max_delay_ms: config.retry_max_delay_ms,
-                backoff_factor: config.retry_backoff_factor,
-            },
-        })?;
+    pub fn pin_session(&self, session_id: String) -> CoreResult<()> {
+        self.storage.set_session_pinned(&session_id, true)
+    }
 
-        let mut slot = self
-            .model_connector
-            .write()
-            .map_err(|_| CoreError::InvalidState("model connector lock poisoned".to_owned()))?;
-        *slot = Some(connector);
+    pub fn unpin_session(&self, session_id: String) -> CoreResult<()> {
+        self.storage.set_session_pinned(&session_id, false)
+    }
 
-        emit_event_static(
-            &self.listeners,
-            "model_updated",
-            "model config updated".to_owned(),
-        );
+    pub fn update_session_title(&self, session_id: String, title: String) -> CoreResult<()> {
+        self.storage.update_session_title(&session_id, &title)
+    }
+
+    /// Replace the case details shown on the session's card and stated in
+    /// the facts section of its reports; blank fields are cleared. Returns
+    /// the updated session.
+    pub fn set_case_metadata(
+        &self,
+        session_id: String,
+        metadata: CaseMetadata,
+    ) -> CoreResult<Session> {
+        let metadata = normalize_case_metadata(metadata)?;
+        self.storage.set_case_metadata(&session_id, &metadata)?;
+        self.storage
+            .get_session(&session_id)?
+            .ok_or_else(|| CoreError::NotFound(format!("session {session_id}")))
+    }
+
+    pub fn delete_session(&self, session_id: String) -> CoreResult<()> {
+        self.storage.delete_session(&session_id)?;
+        self.storage
+            .delete_setting(&session_muted_key(&session_id))?;
+        self.storage
+            .delete_setting(&session_step_through_key(&session_id))?;
+        self.events.forget_session(&session_id);
+        prune_session_queue(&self.session_queues, &session_id);
         Ok(())
     }
 
-    pub fn test_model_connection(&self) -> CoreResult<()> {
-        let connector = {
-            let slot = self
-                .model_connector
-                .read()
-                .map_err(|_| CoreError::InvalidState("model connector lock poisoned".to_owned()))?;
-            slot.clone()
+    /// Quiet mode for a case: its non-critical events (tool results, intake
+    /// progress, report sections, ...) are held back instead of delivered
+    /// to listeners, for `take_muted_events`, and still recorded in the
+    /// event history when that is on. `error`, `escalation_required` and
+    /// `tool_call_request` always get through.
+    pub fn set_session_muted(&self, session_id: String, muted: bool) -> CoreResult<()> {
+        self.storage
+            .get_session(&session_id)?
+            .ok_or_else(|| CoreError::NotFound(format!("session {session_id}")))?;
+        let key = session_muted_key(&session_id);
+        if muted {
+            self.storage.set_setting(&key, "1")?;
+        } else {
+            self.storage.delete_setting(&key)?;
         }
-        .ok_or_else(|| CoreError::InvalidState("model not configured".to_owned()))?;
-
-        RUNTIME.block_on(connector.test_connection())?;
-        emit_event_static(
-            &self.listeners,
-            "model_connection_ok",
-            "openrouter reachable".to_owned(),
-        );
+        self.events.set_session_muted(&session_id, muted);
         Ok(())
     }
 
-    pub fn ping_model(&self, prompt: String) -> CoreResult<String> {
-        let connector = {
-            let slot = self
-                .model_connector
-                .read()
-                .map_err(|_| CoreError::InvalidState("model connector lock poisoned".to_owned()))?;
-            slot.clone()
-        }
-        .ok_or_else(|| CoreError::InvalidState("model not configured".to_owned()))?;
-
-        let messages = vec![model::ChatMessage {
-            role: "user".to_owned(),
-            content: prompt,
-        }];
+    pub fn is_session_muted(&self, session_id: String) -> bool {
+        self.events.is_session_muted(&session_id)
+    }
 
-        let result = RUNTIME.block_on(connector.chat_completion(&messages))?;
-        emit_event_static(
-            &self.listeners,
-            "model_ping",
-            "chat completion finished".to_owned(),
-        );
-        Ok(result)
+    /// Events held back while the session was muted, oldest first, and
+    /// forget them. Only the newest 200 are kept.
+    pub fn take_muted_events(&self, session_id: String) -> Vec<CoreEvent> {
+        self.events.take_muted_events(&session_id)
     }
 
-    pub fn send_message(&self, session_id: String, content: String) -> CoreResult<String> {
-        let session = self
-            .storage
+    /// Set a per-session preference. Supported keys: `tone`
+    /// (`plain` / `standard` / `formal`), `glossary` (`on` / `off`),
+    /// `language` (`zh-CN` / `en`, detected from the first message) and
+    /// `timezone` (see `CoreConfig::time_zone`).
+    pub fn set_session_preference(
+        &self,
+        session_id: String,
+        key: String,
+        value: String,
+    ) -> CoreResult<()> {
+        self.storage
             .get_session(&session_id)?
             .ok_or_else(|| CoreError::NotFound(format!("session {session_id}")))?;
+        set_session_preference(&self.storage, &session_id, &key, &value)
+    }
 
-        self.storage
-            .create_message(&session_id, "user", &content, Some("plan"), None)?;
+    pub fn get_session_preference(
+        &self,
+        session_id: String,
+        key: String,
+    ) -> CoreResult<Option<String>> {
+        session_preference(&self.storage, &session_id, &key)
+    }
 
-        let task_id = Uuid::new_v4().to_string();
-        let control = Arc::new(TaskControl::new());
+    /// Archived sessions are exempt from retention purges and muted (see
+    /// `set_session_muted`).
+    pub fn archive_session(&self, session_id: String) -> CoreResult<()> {
+        self.storage
+            .update_session_status(&session_id, "archived")?;
+        self.set_session_muted(session_id, true)
+    }
 
+    /// Fold a session started by mistake for the same dispute into
+    /// `target_id`: messages, attachments, logs, intake answers and session
+    /// settings move to the target and the emptied source is archived. Both
+    /// sessions must share a scenario and profile, and neither may have a
+    /// running task.
+    #[uniffi::method(default(policy = None))]
+    pub fn merge_sessions(
+        &self,
+        source_id: String,
+        target_id: String,
+        policy: Option<FactMergePolicy>,
+    ) -> CoreResult<SessionMergeResult> {
+        if source_id == target_id {
+            return Err(CoreError::Config(
+                "cannot merge a session into itself".to_owned(),
+            ));
+        }
+        let session = |id: &str| {
+            self.storage
+                .get_session(id)?
+                .ok_or_else(|| CoreError::NotFound(format!("session {id}")))
+        };
+        let source = session(&source_id)?;
+        let target = session(&target_id)?;
+        if source.scenario != target.scenario {
+            return Err(CoreError::Config(format!(
+                "cannot merge a {} session into a {} session",
+                source.scenario, target.scenario
+            )));
+        }
+        if source.profile_id != target.profile_id {
+            return Err(CoreError::Config(
+                "cannot merge sessions of different profiles".to_owned(),
+            ));
+        }
+        if let Some(busy) = [&source_id, &target_id]
+            .into_iter()
+            .find(|id| self.events.has_session_tasks(id))
         {
-            let mut controls = self
-                .task_controls
-                .lock()
-                .map_err(|_| CoreError::InvalidState("task_controls lock poisoned".to_owned()))?;
-            controls.insert(task_id.clone(), control.clone());
+            return Err(CoreError::InvalidState(format!(
+                "session {busy} has a task running; wait for it or cancel it before merging"
+            )));
         }
 
-        // Obtain per-session lock Arc (create if absent)
-        let session_lock = {
-            let mut locks = self
-                .session_locks
-                .lock()
-                .map_err(|_| CoreError::InvalidState("session_locks lock poisoned".to_owned()))?;
-            locks
-                .entry(session_id.clone())
-                .or_insert_with(|| Arc::new(Mutex::new(())))
-                .clone()
-        };
+        let (conflicts, moved) = self.storage.with_tx(|| {
+            let (conflicts, intake_writes) =
+                merge_intake(&self.storage, &source, &target, policy.unwrap_or_default())?;
+            let moved =
+                self.storage
+                    .move_session_records(&source_id, &target_id, &intake_writes)?;
+            let facts = collect_facts(&self.storage, &target_id, &target.scenario)?;
+            self.storage
+                .index_case_text(&target_id, "facts", &format_facts_summary(&facts))?;
+            Ok((conflicts, moved))
+        })?;
 
-        let worker = AgentWorker {
-            task_id: task_id.clone(),
-            session_id,
-            scenario: session.scenario,
-            user_content: content,
-            max_iterations: self.max_iterations,
-            storage: self.storage.clone(),
-            retrieval: self.retrieval.clone(),
-            safety: self.safety.clone(),
-            tools: self.tools.clone(),
-            listeners: self.listeners.clone(),
-            pending_tool_calls: self.pending_tool_calls.clone(),
-            session_allow_all: self.session_allow_all.clone(),
-            control: control.clone(),
-            task_controls: self.task_controls.clone(),
-        };
+        self.events.emit(&SessionsMerged {
+            source_id,
+            target_id: target_id.clone(),
+            conflict_count: conflicts.len(),
+        });
+        Ok(SessionMergeResult {
+            target_id,
+            messages_moved: moved.messages,
+            attachments_moved: moved.attachments,
+            logs_moved: moved.logs,
+            conflicts,
+        })
+    }
 
-        thread::spawn(move || {
-            // Acquire per-session lock so only one AgentWorker runs per session
-            let _session_guard = session_lock.lock();
-
-            let run_result = worker.run();
-            if let Err(err) = run_result {
-                if matches!(err, CoreError::Cancelled) {
-                    emit_event_static(&worker.listeners, "cancelled", worker.task_id.clone());
-                } else {
-                    emit_event_static(
-                        &worker.listeners,
-                        "error",
-                        json!({
-                            "task_id": worker.task_id,
-                            "message": err.to_string(),
-                            "retryable": false
-                        })
-                        .to_string(),
-                    );
-                }
+    /// Move `message_ids`, and the replies to them, into a new session of
+    /// the same profile, for a second dispute raised in this one (see
+    /// `session_split_suggested`). Without `scenario` the new session takes
+    /// the one the moved messages are about, else the source's. Refused
+    /// while a task of the session is queued or running.
+    #[uniffi::method(default(scenario = None))]
+    pub fn split_session(
+        &self,
+        session_id: String,
+        message_ids: Vec<String>,
+        scenario: Option<String>,
+    ) -> CoreResult<SessionSplitResult> {
+        let source = self
+            .storage
+            .get_session(&session_id)?
+            .ok_or_else(|| CoreError::NotFound(format!("session {session_id}")))?;
+        if message_ids.is_empty() {
+            return Err(CoreError::Config("no messages to split off".to_owned()));
+        }
+        // Hold the session's turn, so no reply lands in the source while
+        // the messages it would answer are moving out.
+        let (target, moved, split) = self.with_session_turn(&session_id, "splitting it", || {
+            let messages = self.storage.get_messages(&session_id)?;
+            if let Some(missing) = message_ids
+                .iter()
+                .find(|id| !messages.iter().any(|message| &message.id == *id))
+            {
+                return Err(CoreError::NotFound(format!(
+                    "message {missing} of session {session_id}"
+                )));
             }
-
-            if let Ok(mut controls) = worker.task_controls.lock() {
-                controls.remove(&worker.task_id);
+            let moving = messages
+                .iter()
+                .filter(|message| {
+                    message_ids.contains(&message.id)
+                        || message
+                            .reply_to_message_id
+                            .as_ref()
+                            .is_some_and(|reply_to| message_ids.contains(reply_to))
+                })
+                .collect::<Vec<_>>();
+            if moving.len() == messages.len() {
+                return Err(CoreError::Config(
+                    "cannot split off every message of a session".to_owned(),
+                ));
             }
-        });
+            let scenario = scenario.unwrap_or_else(|| {
+                let text = moving
+                    .iter()
+                    .filter(|message| message.role == "user")
+                    .map(|message| message.content.as_str())
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                message_topic(&text).map_or_else(|| source.scenario.clone(), ToOwned::to_owned)
+            });
+            let moving = moving
+                .into_iter()
+                .map(|message| message.id.clone())
+                .collect::<Vec<_>>();
+
+            self.storage.with_tx(|| {
+                let target = self
+                    .storage
+                    .create_session(&scenario, None, &source.profile_id)?;
+                let moved = self
+                    .storage
+                    .move_messages(&session_id, &target.id, &moving)?;
+                let split = split_intake(&self.storage, &source, &target, &moving)?;
+                for session in [&source, &target] {
+                    let facts = collect_facts(&self.storage, &session.id, &session.scenario)?;
+                    self.storage.index_case_text(
+                        &session.id,
+                        "facts",
+                        &format_facts_summary(&facts),
+                    )?;
+                }
+                Ok((target, moved, split))
+            })
+        })?;
 
-        Ok(task_id)
+        self.analytics.record(AnalyticsEvent::SessionStarted);
+        self.events.emit(&SessionCreated {
+            session_id: target.id.clone(),
+            scenario: target.scenario.clone(),
+        });
+        self.events.emit(&SessionSplit {
+            source_id: session_id,
+            target_id: target.id.clone(),
+            message_count: moved as usize,
+        });
+        Ok(SessionSplitResult {
+            session_id: target.id,
+            scenario: target.scenario,
+            messages_moved: moved,
+            facts_moved: split.moved,
+            facts_discarded: split.discarded,
+        })
     }
 
-    pub fn cancel_agent_task(&self, task_id: String) -> CoreResult<()> {
-        let controls = self
-            .task_controls
-            .lock()
-            .map_err(|_| CoreError::InvalidState("task_controls lock poisoned".to_owned()))?;
-        let control = controls
-            .get(&task_id)
-            .ok_or_else(|| CoreError::NotFound(format!("task {task_id}")))?;
-        control.cancel();
+    /// Store a message. User text over `CoreConfig::max_message_chars` is
+    /// kept as an attachment and replaced by a stub, as in `send_message`.
+    pub fn create_message(
+        &self,
+        session_id: String,
+        role: String,
+        content: String,
+        phase: Option<String>,
+        tool_calls_json: Option<String>,
+    ) -> CoreResult<Message> {
+        let message = NewMessage {
+            role,
+            content,
+            phase,
+            tool_calls: tool_calls_json,
+            created_at: None,
+        };
+        let (mut created, long_text) = self.storage.with_tx(|| {
+            let (message, long_text) = self.prepare_message(&session_id, message, None)?;
+            let created = self
+                .storage
+                .create_messages_bulk(&session_id, std::slice::from_ref(&message))?;
+            Ok((created, long_text))
+        })?;
+        let message = created
+            .pop()
+            .ok_or_else(|| CoreError::Storage("message was not stored".to_owned()))?;
 
-        emit_event_static(
-            &self.listeners,
-            "cancelling",
-            json!({"task_id": task_id}).to_string(),
-        );
-        Ok(())
+        if let Some(event) = long_text {
+            self.events.emit(&event);
+        }
+        self.events.emit(&MessageCreated {
+            session_id: message.session_id.clone(),
+            message_id: message.id.clone(),
+        });
+        Ok(message)
     }
 
-    pub fn respond_tool_call(&self, request_id: String, response: ToolResponse) -> CoreResult<()> {
-        let pending = {
-            let mut pending_map = self.pending_tool_calls.lock().map_err(|_| {
-                CoreError::InvalidState("pending_tool_calls lock poisoned".to_owned())
-            })?;
-            pending_map
-                .remove(&request_id)
-                .ok_or_else(|| CoreError::NotFound(format!("request {request_id}")))?
-        };
+    /// Store `messages` in order in one transaction, for imports and
+    /// migrations; either all are stored or none is. Each passes the checks
+    /// of `create_message`. Emits one `messages_created` event instead of a
+    /// `message_created` per message.
+    pub fn create_messages_bulk(
+        &self,
+        session_id: String,
+        messages: Vec<NewMessage>,
+    ) -> CoreResult<Vec<Message>> {
+        let (created, long_texts) = self.storage.with_tx(|| {
+            let mut long_texts = Vec::new();
+            let messages = messages
+                .into_iter()
+                .enumerate()
+                .map(|(index, message)| {
+                    let (message, long_text) =
+                        self.prepare_message(&session_id, message, Some(index))?;
+                    long_texts.extend(long_text);
+                    Ok(message)
+                })
+                .collect::<CoreResult<Vec<_>>>()?;
+            let created = self.storage.create_messages_bulk(&session_id, &messages)?;
+            Ok((created, long_texts))
+        })?;
 
-        if matches!(response, ToolResponse::AllowAllThisSession) {
-            if let Ok(mut allow_all) = self.session_allow_all.lock() {
-                allow_all.insert(pending.session_id.clone());
-            }
+        for event in long_texts {
+            self.events.emit(&event);
         }
-
-        if let ToolResponse::Allow { always: true } = response {
-            let _ = self
-                .storage
-                .set_tool_permission(&pending.tool_name, "allow");
+        if !created.is_empty() {
+            self.events.emit(&MessagesCreated {
+                session_id,
+                count: created.len(),
+            });
         }
+        Ok(created)
+    }
 
-        pending
-            .sender
-            .send(response)
-            .map_err(|_| CoreError::InvalidState("tool request channel closed".to_owned()))?;
-
-        emit_event_static(
-            &self.listeners,
-            "tool_call_response",
-            json!({
-                "request_id": request_id,
-                "tool_name": pending.tool_name,
-                "session_id": pending.session_id
-            })
-            .to_string(),
-        );
-
-        Ok(())
+    /// Messages of a session in creation order. With `threaded`, each
+    /// user message is followed by the replies to it instead; see
+    /// `Message::reply_to_message_id`.
+    #[uniffi::method(default(threaded = false))]
+    pub fn get_messages(&self, session_id: String, threaded: bool) -> CoreResult<Vec<Message>> {
+        let mut messages = self.storage.get_messages(&session_id)?;
+        messages.retain(|message| !review_gate::is_withheld(message));
+        Ok(if threaded {
+            thread_messages(messages)
+        } else {
+            messages
+        })
     }
 
-    pub fn list_tools(&self) -> Vec<String> {
-        self.tools.list_tools()
+    /// The session's history as one chronological feed of messages, tool
+    /// calls, phases, safety incidents and status changes, for rendering
+    /// history after a restart. Pass `next_cursor` of a page to get the
+    /// next one; `limit` defaults to 50 items and is capped at 500.
+    #[uniffi::method(default(cursor = None, limit = None))]
+    pub fn get_session_activity(
+        &self,
+        session_id: String,
+        cursor: Option<String>,
+        limit: Option<u32>,
+    ) -> CoreResult<ActivityPage> {
+        if self.storage.get_session(&session_id)?.is_none() {
+            return Err(CoreError::NotFound(format!("session {session_id}")));
+        }
+        let after = cursor.as_deref().map(ActivityCursor::parse).transpose()?;
+        let limit = limit
+            .unwrap_or(ACTIVITY_PAGE_DEFAULT)
+            .clamp(1, ACTIVITY_PAGE_MAX);
+        let mut page = self.storage.session_activity(&session_id, after, limit)?;
+        page.items.retain(|item| {
+            item.message
+                .as_ref()
+                .is_none_or(|message| !review_gate::is_withheld(message))
+        });
+        Ok(page)
     }
 
-    pub fn search_knowledge(
+    /// Attach evidence to a session. `extracted_text` is the file's text as
+    /// read by the host (OCR for images and scans); the next report digests
+    /// it into its evidence section.
+    pub fn add_attachment(
         &self,
-        query: String,
-        scenario: String,
-        top_k: u32,
-    ) -> CoreResult<Vec<SearchResult>> {
-        self.retrieval.search(&query, &scenario, top_k as usize)
+        session_id: String,
+        file_name: String,
+        mime_type: String,
+        extracted_text: String,
+    ) -> CoreResult<Attachment> {
+        self.storage
+            .create_attachment(&session_id, &file_name, &mime_type, &extracted_text)
     }
 
-    pub fn read_knowledge_file(&self, file_path: String) -> CoreResult<String> {
-        self.retrieval.read_file(&file_path)
+    pub fn list_attachments(&self, session_id: String) -> CoreResult<Vec<Attachment>> {
+        self.storage.list_attachments(&session_id)
     }
 
-    pub fn get_knowledge_info(&self) -> CoreResult<KnowledgeInfo> {
-        self.retrieval.knowledge_info()
+    /// Replace the agent copy `key` (e.g. `intake.ack.1`) for `locale`, or
+    /// restore the built-in text with `None`. Takes effect on the next task.
+    pub fn set_copy_override(
+        &self,
+        locale: String,
+        key: String,
+        text: Option<String>,
+    ) -> CoreResult<()> {
+        catalog::set_copy_override(&self.storage, &locale, &key, text.as_deref())
     }
 
-    pub fn generate_report(&self, session_id: String) -> CoreResult<String> {
-        let messages = self.storage.get_messages(&session_id)?;
-        let report = messages
-            .iter()
-            .rev()
-            .find(|msg| msg.role == "assistant" && msg.phase.as_deref() == Some("review"))
-            .or_else(|| {
-                messages.iter().rev().find(|msg| {
-                    msg.role == "assistant"
-                        && msg.content.contains("【事实摘要】")
-                        && msg.content.contains("【免责声明】")
-                })
-            })
-            .map(|msg| msg.content.clone())
-            .ok_or_else(|| CoreError::NotFound(format!("report for session {session_id}")))?;
-        Ok(report)
+    /// Current template of the agent copy `key` in the configured locale.
+    pub fn get_copy(&self, key: String) -> CoreResult<String> {
+        let key = CopyKey::parse(&key)
+            .ok_or_else(|| CoreError::Config(format!("unknown copy key {key}")))?;
+        Ok(CopyCatalog::new(&self.storage, &self.runtime().locale).text(key))
     }
 
-    pub fn export_report_markdown(&self, session_id: String, path: String) -> CoreResult<()> {
-        let report = self.generate_report(session_id.clone())?;
-        std::fs::write(&path, report)
-            .map_err(|e| CoreError::Storage(format!("write markdown failed: {e}")))?;
+    pub fn list_copy_keys(&self) -> Vec<String> {
+        CopyKey::ALL
+            .iter()
+            .map(|key| key.as_str().to_owned())
+            .collect()
+    }
 
-        let _ = self.storage.append_log(
-            "info",
-            &format!("report exported: {path}"),
-            Some(session_id.as_str()),
-        );
+    /// Settings are per profile; see `profile_setting_key`.
+    pub fn set_setting(&self, key: String, value: String) -> CoreResult<()> {
+        self.storage
+            .set_setting(&self.profile_setting_key(&key), &value)?;
+        // Only once stored, so the rules never differ from the database.
+        self.storage.faults().apply(&key, Some(&value));
         Ok(())
     }
 
-    pub fn regenerate_report(&self, session_id: String) -> CoreResult<String> {
-        emit_event_static(
-            &self.listeners,
-            "report_regenerating",
-            json!({ "session_id": session_id }).to_string(),
-        );
-
-        self.send_message(
-            session_id,
-            "请基于已收集的事实重新生成一版完整法律咨询报告。".to_owned(),
-        )
+    pub fn get_setting(&self, key: String) -> CoreResult<Option<String>> {
+        self.storage.get_setting(&self.profile_setting_key(&key))
     }
-}
-
-struct AgentWorker {
-    task_id: String,
-    session_id: String,
-    scenario: String,
-    user_content: String,
-    max_iterations: u32,
-    storage: Arc<SqliteStorage>,
-    retrieval: Arc<RetrievalEngine>,
-    safety: Arc<SafetyEngine>,
-    tools: Arc<ToolRegistry>,
-    listeners: Arc<Mutex<HashMap<u64, Arc<dyn EventListener>>>>,
-    pending_tool_calls: Arc<Mutex<HashMap<String, PendingToolCall>>>,
-    session_allow_all: Arc<Mutex<HashSet<String>>>,
-    control: Arc<TaskControl>,
-    task_controls: Arc<Mutex<HashMap<String, Arc<TaskControl>>>>,
-}
 
-impl AgentWorker {
-    fn run(&self) -> CoreResult<()> {
-        self.run_with_iteration(1)
+    /// Settings whose key starts with `prefix`, keyed as the host sets them.
+    pub fn list_settings(&self, prefix: String) -> CoreResult<Vec<Setting>> {
+        let scoped = self.profile_setting_key(&prefix);
+        let namespace = &scoped[..scoped.len() - prefix.len()];
+        let profile_scoped = !namespace.is_empty();
+        Ok(self
+            .storage
+            .list_settings(&scoped)?
+            .into_iter()
+            // The default profile's keys are unprefixed; other profiles'
+            // settings must not show up under it.
+            .filter(|setting| profile_scoped || !setting.key.starts_with(PROFILE_SETTING_PREFIX))
+            .map(|setting| Setting {
+                key: setting.key[namespace.len()..].to_owned(),
+                value: setting.value,
+            })
+            .collect())
     }
 
-    fn run_with_iteration(&self, iteration: u32) -> CoreResult<()> {
-        if iteration > self.max_iterations {
-            return Err(CoreError::Unknown(format!(
-                "max_iterations exceeded: {}",
-                self.max_iterations
+    /// Delete the settings whose key starts with `prefix`; returns how many
+    /// were removed.
+    pub fn delete_settings(&self, prefix: String) -> CoreResult<u32> {
+        let scoped = self.profile_setting_key(&prefix);
+        if scoped == prefix
+            && (prefix.starts_with(PROFILE_SETTING_PREFIX)
+                || PROFILE_SETTING_PREFIX.starts_with(&prefix))
+        {
+            return Err(CoreError::Config(format!(
+                "settings prefix {prefix:?} would reach other profiles' settings"
             )));
         }
+        let deleted = self.storage.delete_settings(&scoped)?;
+        self.storage.faults().clear(&scoped);
+        Ok(deleted)
+    }
 
-        self.guard_not_cancelled()?;
-
-        emit_event_static(
-            &self.listeners,
-            "agent_phase",
-            json!({"task_id": self.task_id, "phase": AgentPhase::Plan.as_str()}).to_string(),
-        );
+    /// Forget cached tool permissions and settings after the database was
+    /// written outside this core, e.g. by a restore tool. With `prefix`,
+    /// only settings whose key starts with it are dropped. Returns how many
+    /// entries were dropped.
+    #[uniffi::method(default(prefix = None))]
+    pub fn invalidate_settings_cache(&self, prefix: Option<String>) -> CoreResult<u32> {
+        let scoped = prefix
+            .as_deref()
+            .map(|prefix| self.profile_setting_key(prefix));
+        let entries = self.storage.invalidate_cache(scoped.as_deref())?;
+        self.events
+            .emit(&SettingsCacheInvalidated { prefix, entries });
+        Ok(entries)
+    }
 
-        let intake = intake_state(&self.storage, &self.session_id, &self.scenario)?;
-        if !intake.done {
-            return self.handle_intake(iteration, intake);
+    /// Write all `settings` at once; a failure leaves every key unchanged.
+    pub fn set_settings_bulk(&self, settings: Vec<Setting>) -> CoreResult<()> {
+        let scoped = settings
+            .into_iter()
+            .map(|setting| Setting {
+                key: self.profile_setting_key(&setting.key),
+                value: setting.value,
+            })
+            .collect::<Vec<_>>();
+        self.storage.set_settings_bulk(&scoped)?;
+        for setting in &scoped {
+            self.storage
+                .faults()
+                .apply(&setting.key, Some(&setting.value));
         }
+        Ok(())
+    }
 
-        emit_event_static(
-            &self.listeners,
-            "agent_phase",
-            json!({"task_id": self.task_id, "phase": AgentPhase::Draft.as_str()}).to_string(),
-        );
+    pub fn set_tool_permission(&self, tool_name: String, permission: String) -> CoreResult<()> {
+        self.storage.set_tool_permission(&tool_name, &permission)
+    }
 
-        let tool_ctx = ToolContext {
-            retrieval: self.retrieval.clone(),
-            safety: self.safety.clone(),
-        };
+    /// Permission of `tool_name` in sessions of `scenario`: the one set
+    /// with `set_tool_permission`, else the permission policy's for the
+    /// scenario, else the policy's default, else the built-in default.
+    /// Without `scenario`, scenario entries of the policy are skipped.
+    #[uniffi::method(default(scenario = None))]
+    pub fn get_tool_permission(
+        &self,
+        tool_name: String,
+        scenario: Option<String>,
+    ) -> CoreResult<String> {
+        resolve_permission(
+            &self.storage,
+            &self.runtime().permission_policy,
+            &tool_name,
+            scenario.as_deref(),
+        )
+    }
 
-        let facts = collect_facts(&self.storage, &self.session_id, &self.scenario)?;
-        let facts_map: serde_json::Map<String, Value> = facts
-            .iter()
-            .map(|(k, v)| (k.clone(), Value::String(v.clone())))
-            .collect();
-        let summary_value = self.execute_tool_with_permission(
-            "summarize_facts",
-            json!({"facts": facts_map}),
-            &tool_ctx,
-        )?;
-        let facts_summary = summary_value
-            .get("summary")
-            .and_then(Value::as_str)
-            .map(ToOwned::to_owned)
-            .unwrap_or_else(|| format_facts_summary(&facts));
+    pub fn append_log(
+        &self,
+        level: String,
+        message: String,
+        session_id: Option<String>,
+    ) -> CoreResult<i64> {
+        self.storage
+            .append_log(&level, &message, session_id.as_deref())
+    }
 
-        let query_text = if self.user_content.trim().is_empty() {
-            "劳动仲裁".to_owned()
-        } else {
-            format!("劳动仲裁 {}", self.user_content)
-        };
+    pub fn list_logs(&self, limit: u32) -> CoreResult<Vec<LogEntry>> {
+        self.storage.list_logs(limit)
+    }
 
-        let search_value = self.execute_tool_with_permission(
-            "kb_search",
-            json!({"query": query_text, "scenario": self.scenario, "top_k": 3}),
-            &tool_ctx,
-        )?;
+    /// Recorded decisions of one agent task, in order.
+    pub fn get_task_trace(&self, task_id: String) -> CoreResult<Vec<TaskTraceEntry>> {
+        self.storage.get_task_trace(&task_id)
+    }
 
-        let search_results: Vec<SearchResult> = serde_json::from_value(search_value)
-            .map_err(|e| CoreError::Unknown(format!("parse search result failed: {e}")))?;
+    /// JSON bundle for bug reports: session metadata, task traces, safety
+    /// incidents and recent logs of the session. Contains no message bodies;
+    /// queries and tool arguments in the traces are reduced to their length.
+    pub fn export_diagnostics(&self, session_id: String) -> CoreResult<String> {
+        let session = self
+            .storage
+            .get_session(&session_id)?
+            .ok_or_else(|| CoreError::NotFound(format!("session {session_id}")))?;
 
-        let legal_analysis = if search_results.is_empty() {
-            "当前未检索到足够的法规条文。建议补充案情细节（时间、金额、证据）后再生成一次分析。".to_owned()
-        } else {
-            let references = search_results
-                .iter()
-                .take(3)
-                .enumerate()
-                .map(|(idx, item)| {
-                    format!(
-                        "{}. 《{}》提到：{}",
-                        idx + 1,
-                        item.title.trim(),
-                        item.snippet.replace('\n', " ")
-                    )
+        let traces = self
+            .storage
+            .list_session_traces(&session_id)?
+            .into_iter()
+            .map(|entry| {
+                json!({
+                    "task_id": entry.task_id,
+                    "seq": entry.seq,
+                    "kind": entry.kind,
+                    "data": redact_trace_data(
+                        serde_json::from_str::<Value>(&entry.data).unwrap_or(Value::Null)
+                    ),
+                    "created_at": entry.created_at
                 })
-                .collect::<Vec<_>>()
-                .join("\n");
-
-            format!(
-                "结合知识库中的条文信息，现阶段可以先这样理解：\n{}\n\n以上为通用分析，最终判断仍要结合当地裁审口径和证据完整度。",
-                references
-            )
-        };
-
-        let citation_sources = search_results
-            .iter()
-            .take(3)
-            .map(|item| {
+            })
+            .collect::<Vec<_>>();
+        let incidents = self
+            .storage
+            .list_safety_incidents(&SafetyIncidentFilter {
+                session_id: Some(session_id.clone()),
+                ..Default::default()
+            })?
+            .into_iter()
+            .map(|incident| {
                 json!({
-                    "file_path": item.file_path,
-                    "line_start": item.line_start,
-                    "line_end": item.line_end
+                    "rule_name": incident.rule_name,
+                    "category": incident.category,
+                    "severity": incident.severity,
+                    "task_id": incident.task_id,
+                    "created_at": incident.created_at
                 })
             })
             .collect::<Vec<_>>();
-        let citation_value = self.execute_tool_with_permission(
-            "cite",
-            json!({"sources": citation_sources}),
-            &tool_ctx,
-        )?;
-        let citations = citation_value
-            .get("citations")
-            .and_then(Value::as_str)
-            .unwrap_or_default();
-
-        let process_path = "1. 先把证据按时间线整理：合同/考勤/工资流水/沟通记录尽量对应到具体日期。\n2. 准备并提交仲裁申请：写清诉求、金额和事实经过，向有管辖权的仲裁委递交。\n3. 参加调解或开庭：围绕劳动关系、欠薪事实、金额计算这三点陈述，并按要求补充材料。";
-        let risk_value = self.execute_tool_with_permission(
-            "suggest_escalation",
-            json!({"content": self.user_content}),
-            &tool_ctx,
-        )?;
-        let risk_message = risk_value
-            .get("message")
-            .and_then(Value::as_str)
-            .unwrap_or("本回答基于你当前提供的信息，存在不确定性；若金额较大或争议复杂，建议尽快咨询执业律师。");
-
-        let draft_report = build_report(
-            &facts_summary,
-            &format!("{}\n\n【引用】\n{}", legal_analysis, citations),
-            process_path,
-            risk_message,
-        );
-
-        emit_event_static(
-            &self.listeners,
-            "agent_phase",
-            json!({"task_id": self.task_id, "phase": AgentPhase::Review.as_str()}).to_string(),
-        );
-
-        let safety_value = self.execute_tool_with_permission(
-            "check_safety",
-            json!({"content": draft_report}),
-            &tool_ctx,
-        )?;
-        let fallback_modified_content = safety_value
-            .get("modified_content")
-            .and_then(Value::as_str)
-            .unwrap_or_default()
-            .to_owned();
-        let safety_result = serde_json::from_value::<SafetyCheckResult>(safety_value).unwrap_or(
-            SafetyCheckResult {
-                modified_content: fallback_modified_content,
-                issues: Vec::new(),
-                has_critical: false,
-            },
-        );
-
-        if !safety_result.issues.is_empty() {
-            let critical_count = safety_result
-                .issues
-                .iter()
-                .filter(|issue| issue.severity == Severity::Critical)
-                .count();
-            let event_name = if safety_result.has_critical {
-                "review_intercepted"
-            } else {
-                "review_adjusted"
-            };
-
-            emit_event_static(
-                &self.listeners,
-                event_name,
+        let logs = self
+            .storage
+            .list_logs(DIAGNOSTICS_LOG_SCAN)?
+            .into_iter()
+            .filter(|log| log.session_id.as_deref() == Some(session_id.as_str()))
+            .map(|log| {
                 json!({
-                    "task_id": self.task_id,
-                    "session_id": self.session_id,
-                    "issue_count": safety_result.issues.len(),
-                    "critical_count": critical_count
+                    "level": log.level,
+                    "message": log.message,
+                    "created_at": log.created_at
                 })
-                .to_string(),
-            );
-        }
+            })
+            .collect::<Vec<_>>();
+        let time_zone = self.time_zone(&session_id)?;
+
+        Ok(json!({
+            "session": {
+                "id": session.id,
+                "scenario": session.scenario,
+                "status": session.status,
+                "created_at": session.created_at,
+                "updated_at": session.updated_at
+            },
+            "task_traces": traces,
+            "safety_incidents": incidents,
+            "logs": logs,
+            "exported_at": self.clock.timestamp(),
+            "time_zone": {"name": time_zone.name, "utc_offset": time_zone.label()}
+        })
+        .to_string())
+    }
 
-        let mut final_report = safety_result.modified_content;
-        if safety_result.has_critical {
-            let critical_count = safety_result
-                .issues
-                .iter()
-                .filter(|issue| issue.severity == Severity::Critical)
-                .count();
-            final_report = format!(
-                "【安全审查】\n检测到 {} 处高风险表述，已自动拦截并改写。\n\n{}",
-                critical_count, final_report
-            );
+    /// Start or stop watching the KB directory for edits, e.g. when the app
+    /// moves between foreground and background.
+    pub fn set_kb_watching(&self, enabled: bool) -> CoreResult<()> {
+        let mut watcher = self
+            .kb_watcher
+            .lock()
+            .map_err(|_| CoreError::InvalidState("kb watcher lock poisoned".to_owned()))?;
+        if !enabled {
+            *watcher = None;
+        } else if watcher.is_none() {
+            *watcher = Some(start_kb_watcher(&self.runtime().kb_path, &self.events)?);
         }
+        Ok(())
+    }
 
-        self.guard_not_cancelled()?;
-        self.storage.create_message(
-            &self.session_id,
-            "assistant",
-            &final_report,
-            Some("review"),
-            None,
-        )?;
+    /// Run the built-in safety rules over a directory of sample texts and
+    /// report per-rule hits, for measuring false positives before shipping
+    /// rule changes. Nothing is stored.
+    pub fn test_safety_rules(&self, corpus_path: String) -> CoreResult<SafetyEvaluation> {
+        self.safety.evaluate_corpus(Path::new(&corpus_path))
+    }
 
-        emit_event_static(
-            &self.listeners,
-            "completed",
-            json!({
-                "task_id": self.task_id,
-                "session_id": self.session_id,
-                "report": final_report
-            })
-            .to_string(),
-        );
+    /// Everything the safety review intercepted or adjusted, newest first.
+    pub fn list_safety_incidents(
+        &self,
+        filter: SafetyIncidentFilter,
+    ) -> CoreResult<Vec<SafetyIncident>> {
+        self.storage.list_safety_incidents(&filter)
+    }
 
-        Ok(())
+    /// Register the host's secret store. Once set, `ModelConfig.api_key` may be
+    /// left empty and the key is fetched from the provider on every request.
+    ///
+    /// The message content key is read once here. With
+    /// `encrypt_message_content` on, the provider must have it, and messages
+    /// still stored in the clear are encrypted now.
+    pub fn set_secret_provider(&self, provider: Box<dyn SecretProvider>) -> CoreResult<()> {
+        let provider: Arc<dyn SecretProvider> = Arc::from(provider);
+        match provider.get_secret(MESSAGE_CONTENT_KEY_SECRET.to_owned()) {
+            Some(secret) => self
+                .storage
+                .set_content_cipher(Some(ContentCipher::from_secret(&secret)?))?,
+            None if self.storage.content_encryption() => {
+                return Err(CoreError::Config(format!(
+                    "encrypt_message_content is on but secret {MESSAGE_CONTENT_KEY_SECRET} is not available"
+                )));
+            }
+            None => {}
+        }
+        let mut slot = self
+            .secret_provider
+            .write()
+            .map_err(|_| CoreError::InvalidState("secret provider lock poisoned".to_owned()))?;
+        *slot = Some(provider);
+        drop(slot);
+        self.seal_message_content()
     }
 
-    fn handle_intake(&self, iteration: u32, state: agent::IntakeState) -> CoreResult<()> {
-        let tool_ctx = ToolContext {
-            retrieval: self.retrieval.clone(),
-            safety: self.safety.clone(),
+    /// Tell the core a secret changed in the host store. Provider-backed keys
+    /// are re-read on the next request; listeners get `secret_rotated` so the
+    /// UI can re-test the connection.
+    pub fn notify_secret_rotated(&self, name: String) {
+        self.events.emit(&SecretRotated { name });
+    }
+
+    pub fn update_model_config(&self, config: ModelConfig) -> CoreResult<()> {
+        let limits = SchedulerLimits {
+            max_in_flight: config.max_concurrent_requests as usize,
+            min_interval: Duration::from_millis(config.min_request_interval_ms),
+        };
+        let api_key = if config.api_key.trim().is_empty() {
+            let provider = self
+                .secret_provider
+                .read()
+                .map_err(|_| CoreError::InvalidState("secret provider lock poisoned".to_owned()))?
+                .clone();
+            match provider {
+                Some(provider) => ApiKey::Provided {
+                    provider,
+                    name: MODEL_API_KEY_SECRET.to_owned(),
+                },
+                None => ApiKey::Static(config.api_key),
+            }
+        } else {
+            ApiKey::Static(config.api_key)
         };
 
-        if state.current_index == 0 {
-            let first = self.execute_tool_with_permission(
-                "ask_user",
-                json!({"scenario": self.scenario, "index": 0}),
-                &tool_ctx,
-            )?;
-            start_intake(&self.storage, &self.session_id)?;
+        let warm_up = config.warm_up;
+        let model_name = config.model_name.clone();
+        let connector = ModelConnector::new(
+            OpenRouterConfig {
+                api_key,
+                model_name: config.model_name,
+                base_url: config
+                    .base_url
+                    .unwrap_or_else(|| "https://openrouter.ai/api/v1".to_owned()),
+                retry: RetryConfig {
+                    max_retries: config.retry_max_retries,
+                    initial_delay_ms: config.retry_initial_delay_ms,
+                    max_delay_ms: config.retry_max_delay_ms,
+                    backoff_factor: config.retry_backoff_factor,
+                },
+                max_prompt_tokens: config.max_prompt_tokens,
+                pool: HttpPoolConfig {
+                    idle_timeout: Duration::from_secs(config.pool_idle_timeout_secs),
+                    max_idle_per_host: config.pool_max_idle_per_host as usize,
+                    keep_alive_interval: (config.http2_keep_alive_interval_secs > 0)
+                        .then(|| Duration::from_secs(config.http2_keep_alive_interval_secs)),
+                    ..HttpPoolConfig::default()
+                },
+            },
+            self.clock.clone(),
+        )?
+        .with_routes(config.phase_models)?;
+        self.model_scheduler.set_limits(limits);
 
-            let question = first
-                .get("question")
-                .and_then(Value::as_str)
-                .unwrap_or("请描述您的情况");
-            let total = first.get("total").and_then(Value::as_u64).unwrap_or(1);
-            let text = format!(
-                "我先帮你把案情梳理清楚，接下来会问你 {} 个小问题。\n你按知道的回答就可以，不确定也可以说“暂不清楚”。\n\n进度：1/{}\n\n第 1 题：{}",
-                total, total, question
-            );
+        let mut slot = self
+            .model_connector
+            .write()
+            .map_err(|_| CoreError::InvalidState("model connector lock poisoned".to_owned()))?;
+        *slot = Some(connector.clone());
+        let generation = self.model_generation.fetch_add(1, Ordering::SeqCst) + 1;
+        drop(slot);
 
-            self.storage.create_message(
-                &self.session_id,
-                "assistant",
-                &text,
-                Some("draft"),
-                None,
-            )?;
+        self.events.emit(&ModelUpdated {
+            model: model_name.clone(),
+        });
+        if warm_up {
+            self.spawn_model_warm_up(connector, model_name, generation);
+        }
+        Ok(())
+    }
 
-            emit_event_static(
-                &self.listeners,
-                "intake_progress",
-                json!({
-                    "task_id": self.task_id,
-                    "current": 1,
-                    "total": total,
-                    "question": question
-                })
-                .to_string(),
-            );
-            emit_event_static(
-                &self.listeners,
-                "completed",
-                json!({
-                    "task_id": self.task_id,
-                    "session_id": self.session_id,
-                    "message": text
-                })
-                .to_string(),
-            );
-            return Ok(());
+    pub fn test_model_connection(&self) -> CoreResult<()> {
+        let connector = {
+            let slot = self
+                .model_connector
+                .read()
+                .map_err(|_| CoreError::InvalidState("model connector lock poisoned".to_owned()))?;
+            slot.clone()
         }
+        .ok_or_else(|| CoreError::InvalidState("model not configured".to_owned()))?;
 
-        let answered_index = state.current_index.saturating_sub(1);
-        save_answer(
-            &self.storage,
-            &self.session_id,
-            answered_index,
-            &self.user_content,
-        )?;
+        let _permit = self
+            .model_scheduler
+            .acquire("", CallPriority::Interactive, || false);
+        RUNTIME.block_on(connector.test_connection())?;
+        self.events.emit(&ModelConnectionOk {});
+        Ok(())
+    }
 
-        if state.current_index < state.questions.len() {
-            let next_value = self.execute_tool_with_permission(
-                "ask_user",
-                json!({"scenario": self.scenario, "index": state.current_index}),
-                &tool_ctx,
-            )?;
-            let question = next_value
-                .get("question")
-                .and_then(Value::as_str)
-                .unwrap_or("请继续补充信息");
-            let current = next_value
-                .get("current")
-                .and_then(Value::as_u64)
-                .unwrap_or((state.current_index + 1) as u64);
-            let total = next_value
-                .get("total")
-                .and_then(Value::as_u64)
-                .unwrap_or(state.questions.len() as u64);
+    pub fn get_metrics(&self) -> CoreResult<CoreMetrics> {
+        let connector = self
+            .model_connector
+            .read()
+            .map_err(|_| CoreError::InvalidState("model connector lock poisoned".to_owned()))?
+            .clone();
 
-            advance_intake_index(&self.storage, &self.session_id, state.current_index + 1)?;
+        let connections = connector
+            .as_ref()
+            .map(|connector| connector.connection_stats())
+            .unwrap_or_default();
+        let queries = self.storage.slow_query_stats();
+        let cache = self.storage.read_cache_stats();
+        let listeners = self.events.listener_failure_stats();
+        Ok(CoreMetrics {
+            model_requests: connections.requests,
+            model_connections_opened: connections.connections_opened,
+            model_connections_reused: connections.reused(),
+            slow_queries: queries.slow_queries,
+            slowest_query_ms: queries.slowest_ms,
+            slowest_query: queries.slowest_fingerprint,
+            queries_interrupted: queries.interrupted,
+            settings_cache_hits: cache.hits,
+            settings_cache_misses: cache.misses,
+            listener_panics: listeners.panics,
+            listeners_unsubscribed: listeners.removed,
+            tool_stats: self.tools.stats_snapshot(),
+            phase_timings: self.phase_timings.snapshot(),
+            model_usage: connector
+                .map(|connector| connector.phase_usage())
+                .unwrap_or_default(),
+        })
+    }
 
-            let ack = self.intake_acknowledgement(answered_index, &self.user_content);
-            let text = format!(
-                "{}\n\n进度：{}/{}\n\n下一题：{}",
-                ack, current, total, question
-            );
-            self.storage.create_message(
-                &self.session_id,
-                "assistant",
-                &text,
-                Some("draft"),
-                None,
-            )?;
+    /// Status of storage, KB, model, task queue and disk, each `Ok`, `Warn`
+    /// or `Fail`, for a system status page. Makes no model request; see
+    /// `test_model_connection` for that.
+    pub fn health_check(&self) -> CoreResult<HealthReport> {
+        let probe = (!self.data_dir.read_only())
+            .then(|| self.storage.probe_writable().map_err(|err| err.to_string()));
+        let storage = health::storage_health(probe, self.storage.schema_version()?);
+
+        let retrieval = self.runtime().retrieval.clone();
+        let info = retrieval.knowledge_info()?;
+        let knowledge_base = health::kb_health(
+            info.file_count,
+            cfg!(feature = "builtin-kb"),
+            info.updated_at,
+            &retrieval.verify_integrity()?,
+        );
 
-            emit_event_static(
-                &self.listeners,
-                "intake_progress",
-                json!({
-                    "task_id": self.task_id,
-                    "current": current,
-                    "total": total,
-                    "question": question
-                })
-                .to_string(),
-            );
-            emit_event_static(
-                &self.listeners,
-                "completed",
-                json!({
-                    "task_id": self.task_id,
-                    "session_id": self.session_id,
-                    "message": text
-                })
-                .to_string(),
-            );
-            return Ok(());
-        }
+        let connector = self
+            .model_connector
+            .read()
+            .map_err(|_| CoreError::InvalidState("model connector lock poisoned".to_owned()))?
+            .clone();
+        let model = health::model_health(
+            connector.is_some(),
+            connector.and_then(|connector| connector.last_outcome()),
+        );
 
-        mark_intake_done(&self.storage, &self.session_id)?;
-        emit_event_static(
-            &self.listeners,
-            "intake_done",
-            json!({"task_id": self.task_id, "session_id": self.session_id}).to_string(),
+        let tasks = self
+            .task_controls
+            .lock()
+            .map_err(|_| CoreError::InvalidState("task_controls lock poisoned".to_owned()))?
+            .len();
+        let queued = self
+            .session_queues
+            .lock()
+            .map_err(|_| CoreError::InvalidState("session_queues lock poisoned".to_owned()))?
+            .values()
+            .map(|queue| queue.waiting())
+            .sum::<usize>();
+        // Queued tasks are registered as soon as they are sent.
+        let tasks = health::task_queue_health(
+            tasks.saturating_sub(queued) as u32,
+            queued as u32,
+            self.model_scheduler.waiting() as u32,
         );
-        self.run_with_iteration(iteration + 1)
+
+        let root = self.data_dir.layout().root;
+        let disk = health::disk_health(
+            fs4::available_space(&root).ok(),
+            fs4::total_space(&root).ok(),
+        );
+
+        Ok(HealthReport::new(
+            storage,
+            knowledge_base,
+            model,
+            tasks,
+            disk,
+            self.clock.timestamp(),
+        ))
     }
 
-    fn execute_tool_with_permission(
-        &self,
-        tool_name: &str,
-        args: Value,
-        ctx: &ToolContext,
-    ) -> CoreResult<Value> {
-        self.guard_not_cancelled()?;
+    /// Invocation, failure and denial counts, run-time percentiles and
+    /// queueing under concurrency limits per tool, accumulated across
+    /// restarts.
+    pub fn get_tool_stats(&self) -> Vec<ToolStats> {
+        self.tools.stats_snapshot()
+    }
 
-        let mut permission = self.storage.get_tool_permission(tool_name)?;
-        let allow_all = self
-            .session_allow_all
-            .lock()
-            .map_err(|_| CoreError::InvalidState("session_allow_all lock poisoned".to_owned()))?
-            .contains(&self.session_id);
-        if allow_all && permission == "ask" {
-            permission = "allow".to_owned();
+    pub fn ping_model(&self, prompt: String) -> CoreResult<String> {
+        let connector = {
+            let slot = self
+                .model_connector
+                .read()
+                .map_err(|_| CoreError::InvalidState("model connector lock poisoned".to_owned()))?;
+            slot.clone()
         }
+        .ok_or_else(|| CoreError::InvalidState("model not configured".to_owned()))?;
 
-        if permission == "deny" {
-            return Err(CoreError::Tool(format!("tool {tool_name} is denied")));
-        }
+        let messages = vec![model::ChatMessage {
+            role: "user".to_owned(),
+            content: prompt,
+        }];
 
-        if permission == "ask" {
-            let request_id = Uuid::new_v4().to_string();
-            let (tx, rx) = mpsc::channel::<ToolResponse>();
+        let _permit = self
+            .model_scheduler
+            .acquire("", CallPriority::Interactive, || false);
+        self.storage.faults().model()?;
+        let result = RUNTIME.block_on(connector.chat_completion(&messages))?;
+        self.events.emit(&ModelPing {});
+        Ok(result)
+    }
 
-            {
-                let mut pending_map = self.pending_tool_calls.lock().map_err(|_| {
-                    CoreError::InvalidState("pending_tool_calls lock poisoned".to_owned())
-                })?;
-                pending_map.insert(
-                    request_id.clone(),
-                    PendingToolCall {
-                        sender: tx,
-                        session_id: self.session_id.clone(),
-                        tool_name: tool_name.to_owned(),
-                    },
+    /// Append a user message and start a task answering it; `priority`
+    /// defaults to `Normal`. Returns the task id.
+    #[uniffi::method(default(priority = None))]
+    pub fn send_message(
+        &self,
+        session_id: String,
+        content: String,
+        priority: Option<TaskPriority>,
+    ) -> CoreResult<String> {
+        self.start_message_task(
+            session_id,
+            content,
+            priority.unwrap_or(TaskPriority::Normal),
+            TaskKind::Answer,
+        )
+    }
+
+    /// Register the host's speech recognizer for `submit_intake_audio`.
+    pub fn set_transcriber(&self, transcriber: Box<dyn Transcriber>) -> CoreResult<()> {
+        let mut slot = self
+            .transcriber
+            .write()
+            .map_err(|_| CoreError::InvalidState("transcriber lock poisoned".to_owned()))?;
+        *slot = Some(Arc::from(transcriber));
+        Ok(())
+    }
+
+    /// Transcribe a spoken intake answer with the host's transcriber. The
+    /// text is returned for the user to check and kept until
+    /// `confirm_intake_transcript`; nothing is sent before that.
+    pub fn submit_intake_audio(
+        &self,
+        session_id: String,
+        audio: AudioInput,
+    ) -> CoreResult<IntakeTranscript> {
+        let session = self
+            .storage
+            .get_session(&session_id)?
+            .ok_or_else(|| CoreError::NotFound(format!("session {session_id}")))?;
+        let state = intake_state(&self.storage, &session_id, &session.scenario)?;
+        if state.done {
+            return Err(CoreError::InvalidState(format!(
+                "intake of session {session_id} is finished"
+            )));
+        }
+        let transcriber = self
+            .transcriber
+            .read()
+            .map_err(|_| CoreError::InvalidState("transcriber lock poisoned".to_owned()))?
+            .clone()
+            .ok_or_else(|| CoreError::Config("no transcriber registered".to_owned()))?;
+
+        let locale = language::session_language(&self.storage, &session_id)?
+            .unwrap_or_else(|| self.runtime().locale.clone());
+        let text = transcribe(transcriber.as_ref(), audio, &locale)?;
+        save_pending_transcript(&self.storage, &session_id, &text)?;
+        let question = state
+            .current_index
+            .checked_sub(1)
+            .and_then(|index| state.questions.get(index))
+            .map(|question| question.question_in(&locale));
+        Ok(IntakeTranscript {
+            session_id,
+            text,
+            question,
+        })
+    }
+
+    /// Send the pending transcript, or the user's corrected `text`, as the
+    /// answer. It goes through the same intake path as a typed answer and
+    /// is kept for another try when sending fails. Returns the task id.
+    #[uniffi::method(default(text = None))]
+    pub fn confirm_intake_transcript(
+        &self,
+        session_id: String,
+        text: Option<String>,
+    ) -> CoreResult<String> {
+        let pending = pending_transcript(&self.storage, &session_id)?.ok_or_else(|| {
+            CoreError::NotFound(format!("pending transcript for session {session_id}"))
+        })?;
+        let answer = text
+            .map(|text| text.trim().to_owned())
+            .filter(|text| !text.is_empty())
+            .unwrap_or(pending);
+        let task_id = self.send_message(session_id.clone(), answer, None)?;
+        clear_pending_transcript(&self.storage, &session_id)?;
+        Ok(task_id)
+    }
+
+    /// Re-run the last failed task of a session from its stored user message,
+    /// without appending a new message. Returns the new task id.
+    pub fn retry_last_task(&self, session_id: String) -> CoreResult<String> {
+        let session = self
+            .storage
+            .get_session(&session_id)?
+            .ok_or_else(|| CoreError::NotFound(format!("session {session_id}")))?;
+        if self.events.has_session_tasks(&session_id) {
+            return Err(CoreError::InvalidState(format!(
+                "session {session_id} has a task running; wait for it or cancel it before retrying"
+            )));
+        }
+        let failed = failed_task(&self.storage, &session_id)?
+            .ok_or_else(|| CoreError::NotFound(format!("failed task for session {session_id}")))?;
+        let user_message = self
+            .storage
+            .get_message(&failed.user_message_id)?
+            .ok_or_else(|| CoreError::NotFound(format!("message {}", failed.user_message_id)))?;
+
+        let task_id = self.spawn_agent_task(
+            session,
+            user_message,
+            TaskPriority::Normal,
+            TaskKind::Answer,
+        )?;
+        self.events.emit(&TaskRetrying {
+            session_id,
+            task_id: task_id.clone(),
+            failed_task_id: failed.task_id,
+        });
+        Ok(task_id)
+    }
+
+    pub fn cancel_agent_task(&self, task_id: String) -> CoreResult<()> {
+        let controls = self
+            .task_controls
+            .lock()
+            .map_err(|_| CoreError::InvalidState("task_controls lock poisoned".to_owned()))?;
+        let control = controls
+            .get(&task_id)
+            .ok_or_else(|| CoreError::NotFound(format!("task {task_id}")))?;
+        control.cancel();
+
+        self.events.emit(&Cancelling { task_id });
+        Ok(())
+    }
+
+    /// Debug mode for prompt work: report tasks started in the session from
+    /// now on pause after each phase (`task_paused`) until `advance_task`.
+    pub fn set_session_step_through(&self, session_id: String, enabled: bool) -> CoreResult<()> {
+        self.storage
+            .get_session(&session_id)?
+            .ok_or_else(|| CoreError::NotFound(format!("session {session_id}")))?;
+        let key = session_step_through_key(&session_id);
+        if enabled {
+            self.storage.set_setting(&key, "1")
+        } else {
+            self.storage.delete_setting(&key)
+        }
+    }
+
+    /// Run the next phase of a paused step-through task.
+    pub fn advance_task(&self, task_id: String) -> CoreResult<()> {
+        if !self.with_stepper(&task_id, Stepper::advance)? {
+            return Err(CoreError::InvalidState(format!(
+                "task {task_id} is not paused"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Facts, retrieved chunks and draft of a running step-through task,
+    /// as far as it got.
+    pub fn get_task_intermediate(&self, task_id: String) -> CoreResult<TaskIntermediate> {
+        self.with_stepper(&task_id, Stepper::snapshot)
+    }
+
+    pub fn respond_tool_call(
+        &self,
+        request_id: String,
+        response: ToolResponse,
+    ) -> CoreResult<ToolCallOutcome> {
+        let Some(pending) =
+            self.deliver_tool_decision(&request_id, ToolDecision::Respond(response.clone()))?
+        else {
+            return self.settled_outcome(&request_id);
+        };
+
+        self.apply_tool_response(&pending, &response);
+        self.events.emit(&ToolCallResponse {
+            request_id,
+            tool_name: pending.tool_name,
+            session_id: pending.session_id,
+        });
+        Ok(ToolCallOutcome::Delivered)
+    }
+
+    /// Approval requests still waiting for an answer in `session_id`,
+    /// oldest first, e.g. to rebuild the approval cards after a reconnect.
+    pub fn list_pending_tool_calls(
+        &self,
+        session_id: String,
+    ) -> CoreResult<Vec<PendingToolCallInfo>> {
+        let pending_map = self
+            .pending_tool_calls
+            .lock()
+            .map_err(|_| CoreError::InvalidState("pending_tool_calls lock poisoned".to_owned()))?;
+        let mut calls = pending_map
+            .iter()
+            .filter(|(_, pending)| pending.session_id == session_id)
+            .map(|(request_id, pending)| PendingToolCallInfo {
+                request_id: request_id.clone(),
+                task_id: pending.task_id.clone(),
+                session_id: pending.session_id.clone(),
+                tool_name: pending.tool_name.clone(),
+                arguments_json: pending.arguments.to_string(),
+                requested_at: pending.requested_at,
+            })
+            .collect::<Vec<_>>();
+        calls.sort_by(|a, b| (a.requested_at, &a.request_id).cmp(&(b.requested_at, &b.request_id)));
+        Ok(calls)
+    }
+
+    /// Answer several approval requests with the same `response` ("approve
+    /// all pending"). The batch is all or nothing: an unknown request id
+    /// fails it before any worker is answered. Delivered answers are
+    /// announced together in one `tool_calls_responded` event.
+    pub fn respond_tool_calls_bulk(
+        &self,
+        request_ids: Vec<String>,
+        response: ToolResponse,
+    ) -> CoreResult<Vec<BulkToolCallOutcome>> {
+        let mut unique = HashSet::new();
+        let request_ids = request_ids
+            .into_iter()
+            .filter(|request_id| unique.insert(request_id.clone()))
+            .collect::<Vec<_>>();
+        let delivered =
+            self.deliver_tool_decisions(&request_ids, ToolDecision::Respond(response.clone()))?;
+
+        let mut outcomes = Vec::with_capacity(request_ids.len());
+        let mut responded = ToolCallsResponded {
+            request_ids: Vec::new(),
+            tool_names: Vec::new(),
+            session_ids: Vec::new(),
+        };
+        for (request_id, pending) in request_ids.into_iter().zip(delivered) {
+            let outcome = match pending {
+                Some(pending) => {
+                    self.apply_tool_response(&pending, &response);
+                    if !responded.session_ids.contains(&pending.session_id) {
+                        responded.session_ids.push(pending.session_id);
+                    }
+                    responded.tool_names.push(pending.tool_name);
+                    responded.request_ids.push(request_id.clone());
+                    ToolCallOutcome::Delivered
+                }
+                None => self.settled_outcome(&request_id)?,
+            };
+            outcomes.push(BulkToolCallOutcome {
+                request_id,
+                outcome,
+            });
+        }
+        if !responded.request_ids.is_empty() {
+            self.events.emit(&responded);
+        }
+        Ok(outcomes)
+    }
+
+    /// Abort one pending approval without cancelling its task. The worker
+    /// treats the call as denied and continues with a degraded result where
+    /// the tool has one (e.g. a report without retrieved law for kb_search).
+    pub fn cancel_pending_tool_call(&self, request_id: String) -> CoreResult<ToolCallOutcome> {
+        let decision = ToolDecision::Skip {
+            reason: TOOL_CALL_SKIPPED_REASON.to_owned(),
+        };
+        if self.deliver_tool_decision(&request_id, decision)?.is_none() {
+            return self.settled_outcome(&request_id);
+        }
+
+        emit_tool_call_closed(&self.events, &request_id, ToolCallOutcome::Skipped);
+        Ok(ToolCallOutcome::Skipped)
+    }
+
+    pub fn list_tools(&self) -> Vec<String> {
+        self.tools.list_tools()
+    }
+
+    pub fn get_tool_post_processing(&self, tool_name: String) -> CoreResult<Vec<PostProcessStep>> {
+        self.tools.post_processing(&tool_name)
+    }
+
+    /// Configure the steps applied to `tool_name` output before it reaches
+    /// the agent and events (safety scan, length trim, metadata).
+    pub fn set_tool_post_processing(
+        &self,
+        tool_name: String,
+        steps: Vec<PostProcessStep>,
+    ) -> CoreResult<()> {
+        self.tools.set_post_processing(&tool_name, steps)
+    }
+
+    pub fn get_tool_concurrency(&self, tool_name: String) -> CoreResult<ToolConcurrency> {
+        self.tools.concurrency(&tool_name)
+    }
+
+    /// Limit how many runs of `tool_name` may execute at once, across
+    /// sessions; further runs wait their turn.
+    pub fn set_tool_concurrency(
+        &self,
+        tool_name: String,
+        policy: ToolConcurrency,
+    ) -> CoreResult<()> {
+        self.tools.set_concurrency(&tool_name, policy)
+    }
+
+    pub fn search_knowledge(
+        &self,
+        query: String,
+        scenario: String,
+        top_k: u32,
+    ) -> CoreResult<Vec<SearchResult>> {
+        let results = self
+            .runtime()
+            .retrieval
+            .search(&query, &scenario, top_k as usize)?;
+        self.query_log.record_search(
+            &query,
+            &scenario,
+            results
+                .iter()
+                .map(|result| result.chunk_id.clone())
+                .collect(),
+        );
+        Ok(results)
+    }
+
+    pub fn read_knowledge_file(&self, file_path: String) -> CoreResult<String> {
+        self.runtime().retrieval.read_file(&file_path)
+    }
+
+    /// Check every KB file against the checksum manifest (`kb-manifest.json`
+    /// at the KB root). Files that fail are neither searched nor readable
+    /// until the manifest matches again.
+    pub fn verify_knowledge_integrity(&self) -> CoreResult<KbIntegrityReport> {
+        self.runtime().retrieval.verify_integrity()
+    }
+
+    /// How often the agent retrieved and cited each KB file, from anonymous
+    /// per-chunk counters with differential-privacy noise. Recorded only
+    /// while analytics is enabled.
+    pub fn get_kb_usage_stats(&self) -> CoreResult<Vec<KbFileUsage>> {
+        self.analytics.kb_usage()
+    }
+
+    /// Integrity check plus per-file usage, for KB maintainers.
+    pub fn get_kb_health(&self) -> CoreResult<KbHealthReport> {
+        let retrieval = self.runtime().retrieval.clone();
+        let integrity = retrieval.verify_integrity()?;
+        let mut usage = self.analytics.kb_usage()?;
+        let files = retrieval.list_files(None, None)?;
+        usage.retain(|used| {
+            files
+                .iter()
+                .any(|file| file.relative_path == used.file_path)
+        });
+        for file in files {
+            if !usage
+                .iter()
+                .any(|used| used.file_path == file.relative_path)
+            {
+                usage.push(KbFileUsage {
+                    file_path: file.relative_path,
+                    hits: 0,
+                    citations: 0,
+                    chunks_used: 0,
+                });
+            }
+        }
+        let uncited_files = usage.iter().filter(|used| used.citations == 0).count() as u32;
+        Ok(KbHealthReport {
+            integrity,
+            usage,
+            uncited_files,
+        })
+    }
+
+    /// Authoring checks for KB curators: frontmatter, headings, size,
+    /// encoding, duplicate titles and empty scenarios, listed per file.
+    pub fn validate_knowledge_base(&self) -> CoreResult<KbValidationReport> {
+        self.runtime().retrieval.validate()
+    }
+
+    /// Write the checksum manifest from the KB as it is now, trusting its
+    /// current content. Meant for the step that bundles the KB.
+    pub fn write_knowledge_manifest(&self) -> CoreResult<u32> {
+        self.runtime().retrieval.write_manifest()
+    }
+
+    /// The KB passage behind a citation's `chunk_id`, with surrounding lines
+    /// for a citation viewer. Opening a result of the last `search_knowledge`
+    /// this way counts as a click in the query log.
+    pub fn get_knowledge_chunk(&self, chunk_id: String) -> CoreResult<KnowledgeChunk> {
+        let chunk = self.runtime().retrieval.knowledge_chunk(&chunk_id)?;
+        self.query_log.record_click(&chunk_id);
+        Ok(chunk)
+    }
+
+    /// List KB markdown files, optionally narrowed to a scenario and a
+    /// subdirectory inside it. Paths are relative to the KB root.
+    pub fn list_knowledge_files(
+        &self,
+        scenario: Option<String>,
+        subdir: Option<String>,
+    ) -> CoreResult<Vec<KnowledgeFile>> {
+        self.runtime()
+            .retrieval
+            .list_files(scenario.as_deref(), subdir.as_deref())
+    }
+
+    /// Intake facts of a session as typed fields for the case card.
+    pub fn get_facts(&self, session_id: String) -> CoreResult<Vec<CaseFact>> {
+        let session = self
+            .storage
+            .get_session(&session_id)?
+            .ok_or_else(|| CoreError::NotFound(format!("session {session_id}")))?;
+        case_facts(&self.storage, &session_id, &session.scenario)
+    }
+
+    /// Correct one fact by field id (see `CaseFact::field`). The change is
+    /// picked up the next time a report is generated.
+    pub fn set_fact(&self, session_id: String, field: String, value: String) -> CoreResult<()> {
+        let session = self
+            .storage
+            .get_session(&session_id)?
+            .ok_or_else(|| CoreError::NotFound(format!("session {session_id}")))?;
+        set_case_fact(
+            &self.storage,
+            &session_id,
+            &session.scenario,
+            &field,
+            &value,
+        )?;
+        self.events.emit(&FactsUpdated { session_id, field });
+        Ok(())
+    }
+
+    /// Intake questions the user answered with "暂不清楚" or skipped and
+    /// has not answered since.
+    pub fn list_open_questions(&self, session_id: String) -> CoreResult<Vec<OpenQuestion>> {
+        let session = self
+            .storage
+            .get_session(&session_id)?
+            .ok_or_else(|| CoreError::NotFound(format!("session {session_id}")))?;
+        open_questions(&self.storage, &session_id, &session.scenario)
+    }
+
+    /// Intake questions asked so far, for picking a `reset_intake` target.
+    pub fn list_intake_checkpoints(&self, session_id: String) -> CoreResult<Vec<IntakeCheckpoint>> {
+        let session = self
+            .storage
+            .get_session(&session_id)?
+            .ok_or_else(|| CoreError::NotFound(format!("session {session_id}")))?;
+        intake_checkpoints(&self.storage, &session_id, &session.scenario)
+    }
+
+    /// Name the checkpoint at question `question_index` (zero-based) for
+    /// `reset_intake_to_checkpoint`; `None` or a blank name clears it.
+    pub fn name_intake_checkpoint(
+        &self,
+        session_id: String,
+        question_index: u32,
+        name: Option<String>,
+    ) -> CoreResult<()> {
+        let session = self
+            .storage
+            .get_session(&session_id)?
+            .ok_or_else(|| CoreError::NotFound(format!("session {session_id}")))?;
+        name_intake_checkpoint(
+            &self.storage,
+            &session_id,
+            &session.scenario,
+            question_index as usize,
+            name.as_deref(),
+        )
+    }
+
+    /// `reset_intake` to the checkpoint named `name`.
+    pub fn reset_intake_to_checkpoint(
+        &self,
+        session_id: String,
+        name: String,
+    ) -> CoreResult<Message> {
+        let session = self
+            .storage
+            .get_session(&session_id)?
+            .ok_or_else(|| CoreError::NotFound(format!("session {session_id}")))?;
+        let index = named_checkpoint(&self.storage, &session_id, &session.scenario, &name)?;
+        self.reset_intake(session_id, index as u32)
+    }
+
+    /// Roll intake back to question `to_question_index` (zero-based) and ask
+    /// it again: that answer and all later ones are cleared, the transcript
+    /// is kept. Refused while a task of the session is queued or running;
+    /// one started meanwhile waits for the reset. Returns the message asking
+    /// the question.
+    pub fn reset_intake(&self, session_id: String, to_question_index: u32) -> CoreResult<Message> {
+        let session = self
+            .storage
+            .get_session(&session_id)?
+            .ok_or_else(|| CoreError::NotFound(format!("session {session_id}")))?;
+        let index = to_question_index as usize;
+        let answers_cleared = self.with_session_turn(&session_id, "resetting intake", || {
+            self.storage.with_tx(|| {
+                let cleared = reset_intake(&self.storage, &session_id, &session.scenario, index)?;
+                clear_pending_transcript(&self.storage, &session_id)?;
+                let facts = collect_facts(&self.storage, &session_id, &session.scenario)?;
+                self.storage.index_case_text(
+                    &session_id,
+                    "facts",
+                    &format_facts_summary(&facts),
+                )?;
+                Ok(cleared)
+            })
+        })?;
+
+        let questions = intake_questions_for_scenario(&session.scenario);
+        let locale = language::session_language(&self.storage, &session_id)?
+            .unwrap_or_else(|| self.runtime().locale.clone());
+        let question = questions[index].question_in(&locale);
+        let text = CopyCatalog::new(&self.storage, &locale).render(
+            CopyKey::IntakeReset,
+            &[
+                ("current", &(index + 1).to_string()),
+                ("total", &questions.len().to_string()),
+                ("question", &question),
+            ],
+        );
+        let message = self.create_message(
+            session_id.clone(),
+            "assistant".to_owned(),
+            text,
+            Some("draft".to_owned()),
+            None,
+        )?;
+        self.events.emit(&IntakeReset {
+            session_id,
+            question_index: to_question_index,
+            answers_cleared,
+            question,
+        });
+        Ok(message)
+    }
+
+    /// Ask the open question `field` again. The session's next message is
+    /// taken as its answer, and the report regenerated with it.
+    pub fn reask_open_question(&self, session_id: String, field: String) -> CoreResult<Message> {
+        let session = self
+            .storage
+            .get_session(&session_id)?
+            .ok_or_else(|| CoreError::NotFound(format!("session {session_id}")))?;
+        let question = start_reask(&self.storage, &session_id, &session.scenario, &field)?;
+        let locale = language::session_language(&self.storage, &session_id)?.unwrap_or_default();
+        self.create_message(
+            session_id,
+            "assistant".to_owned(),
+            question.question_in(&locale),
+            Some(OPEN_QUESTIONS_PHASE.to_owned()),
+            None,
+        )
+    }
+
+    /// Answer "what if" with some intake answers replaced. The result is
+    /// stored as a `whatif` message and clearly marked as hypothetical; the
+    /// recorded facts are not changed. With a persona and a model
+    /// configured, the answer is written in the persona's voice.
+    pub fn what_if(&self, session_id: String, overrides: Vec<FactOverride>) -> CoreResult<Message> {
+        let session = self
+            .storage
+            .get_session(&session_id)?
+            .ok_or_else(|| CoreError::NotFound(format!("session {session_id}")))?;
+
+        let runtime = self.runtime();
+        let answer = run_what_if(
+            &self.storage,
+            &runtime.retrieval,
+            &session_id,
+            &session.scenario,
+            &overrides,
+            runtime.region,
+        )?;
+        let answer = self.review_answer(&session_id, &self.what_if_in_persona(answer))?;
+        let message = self.storage.create_message(
+            &session_id,
+            "assistant",
+            &answer,
+            Some(WHAT_IF_PHASE),
+            None,
+        )?;
+
+        self.events.emit(&WhatIfCompleted {
+            session_id,
+            message_id: message.id.clone(),
+        });
+        Ok(message)
+    }
+
+    /// Answer a short question like "仲裁要多久" in one paragraph, without
+    /// intake or a report: one KB search and, with a model configured and
+    /// time left, one model call; otherwise the best KB match is quoted.
+    /// Returns within `budget_ms` (default 5000), search included, plus the
+    /// time to store the exchange, which is kept with phase `quick` and
+    /// reviewed like any other answer. Fails while a task runs in the
+    /// session.
+    #[uniffi::method(default(budget_ms = None))]
+    pub fn quick_ask(
+        &self,
+        session_id: String,
+        question: String,
+        budget_ms: Option<u64>,
+    ) -> CoreResult<Message> {
+        let deadline = Instant::now()
+            + Duration::from_millis(budget_ms.unwrap_or(DEFAULT_QUICK_ASK_BUDGET_MS));
+        let question = question.trim();
+        if question.is_empty() {
+            return Err(CoreError::Config("question is empty".to_owned()));
+        }
+        let session = self
+            .storage
+            .get_session(&session_id)?
+            .ok_or_else(|| CoreError::NotFound(format!("session {session_id}")))?;
+
+        let (asked, answer, long_text) =
+            self.with_session_turn(&session_id, "asking a quick question", || {
+                let runtime = self.runtime();
+                let results = self.quick_search(question, &session.scenario, deadline)?;
+                let body = self
+                    .quick_model_answer(
+                        &session.scenario,
+                        question,
+                        &results,
+                        deadline.saturating_duration_since(Instant::now()),
+                    )
+                    .unwrap_or_else(|| quick::template_answer(&results));
+
+                self.storage.with_tx(|| {
+                    let (asked, long_text) = self.prepare_message(
+                        &session_id,
+                        NewMessage {
+                            role: "user".to_owned(),
+                            content: question.to_owned(),
+                            phase: Some(QUICK_PHASE.to_owned()),
+                            tool_calls: None,
+                            created_at: None,
+                        },
+                        None,
+                    )?;
+                    let asked = self
+                        .storage
+                        .create_messages_bulk(&session_id, std::slice::from_ref(&asked))?
+                        .pop()
+                        .ok_or_else(|| CoreError::Storage("message was not stored".to_owned()))?;
+                    // Reviewed once the question is stored, so quoting it
+                    // back is not held against the answer.
+                    let content = self.review_answer(
+                        &session_id,
+                        &build_quick_answer(&body, runtime.region.disclaimer()),
+                    )?;
+                    let answer = self.storage.create_reply(
+                        &session_id,
+                        &asked.id,
+                        "assistant",
+                        &content,
+                        Some(QUICK_PHASE),
+                    )?;
+                    Ok((asked, answer, long_text))
+                })
+            })?;
+
+        if let Some(event) = long_text {
+            self.events.emit(&event);
+        }
+        for message in [&asked, &answer] {
+            self.events.emit(&MessageCreated {
+                session_id: session_id.clone(),
+                message_id: message.id.clone(),
+            });
+        }
+        Ok(answer)
+    }
+
+    /// Search reports and intake facts of every session of the active
+    /// profile, e.g. for "what did the report on my last case say". Fails
+    /// while `encrypt_message_content` is on.
+    pub fn search_across_sessions(&self, query: String) -> CoreResult<Vec<SessionSearchHit>> {
+        self.storage
+            .search_case_index(&query, SESSION_SEARCH_LIMIT, &self.active_profile_id())
+    }
+
+    pub fn get_knowledge_info(&self) -> CoreResult<KnowledgeInfo> {
+        self.runtime().retrieval.knowledge_info()
+    }
+
+    pub fn generate_report(&self, session_id: String) -> CoreResult<String> {
+        Ok(self.latest_report(&session_id)?.content)
+    }
+
+    /// Sources of each section of the session's latest report: the intake
+    /// answers and KB chunks it was written from.
+    pub fn get_report_provenance(&self, session_id: String) -> CoreResult<ReportProvenance> {
+        report_provenance(&self.storage, &session_id)?.ok_or_else(|| {
+            CoreError::NotFound(format!("report provenance for session {session_id}"))
+        })
+    }
+
+    /// What the safety review rewrote in the session's latest report, and
+    /// which rule made each edit.
+    pub fn get_report_review(&self, session_id: String) -> CoreResult<ReportReview> {
+        report_review(&self.storage, &session_id)?
+            .ok_or_else(|| CoreError::NotFound(format!("report review for session {session_id}")))
+    }
+
+    /// Write the session's report to `path`. With `footnotes`, each section
+    /// heading and each statement still as drafted gets a `[^n]` marker
+    /// pointing at its sources.
+    #[uniffi::method(default(footnotes = false))]
+    pub fn export_report_markdown(
+        &self,
+        session_id: String,
+        path: String,
+        footnotes: bool,
+    ) -> CoreResult<()> {
+        let mut report = self.generate_report(session_id.clone())?;
+        if footnotes {
+            if let Some(provenance) = report_provenance(&self.storage, &session_id)? {
+                let labels = self
+                    .get_facts(session_id.clone())?
+                    .into_iter()
+                    .map(|fact| (fact.field, fact.label))
+                    .collect();
+                report = add_footnotes(
+                    &report,
+                    &provenance,
+                    &labels,
+                    &catalog::report_copy(&self.storage),
+                );
+            }
+        }
+        let report = self.stamp_report(&session_id, &report, ShareFormat::Markdown)?;
+        std::fs::write(&path, report)
+            .map_err(|e| CoreError::Storage(format!("write markdown failed: {e}")))?;
+
+        let _ = self.storage.append_log(
+            "info",
+            &format!("report exported: {path}"),
+            Some(session_id.as_str()),
+        );
+        self.analytics.record(AnalyticsEvent::ReportExported);
+        Ok(())
+    }
+
+    /// The session's report cut down to the sections `sections` keeps, for
+    /// sharing. With `redact_pii`, phone, id and account numbers, emails,
+    /// names, employers, addresses and the case's opposing party are masked
+    /// in the kept sections. Written to `path` when given; the content is
+    /// returned either way.
+    #[uniffi::method(default(redact_pii = true, path = None))]
+    pub fn export_report_selective(
+        &self,
+        session_id: String,
+        sections: ReportSectionFilter,
+        format: ShareFormat,
+        redact_pii: bool,
+        path: Option<String>,
+    ) -> CoreResult<SharedReport> {
+        let report = self.generate_report(session_id.clone())?;
+        let session = self
+            .storage
+            .get_session(&session_id)?
+            .ok_or_else(|| CoreError::NotFound(format!("session {session_id}")))?;
+        let known = [session.metadata.opposing_party.as_deref().unwrap_or("")];
+        let (content, sections, redactions) =
+            package_report(&report, &sections, redact_pii, &known, format)?;
+        let content = self.stamp_report(&session_id, &content, format)?;
+        if let Some(path) = &path {
+            std::fs::write(path, &content)
+                .map_err(|e| CoreError::Storage(format!("write shared report failed: {e}")))?;
+        }
+
+        let _ = self.storage.append_log(
+            "info",
+            &format!(
+                "report shared: sections {}, {redactions} redaction(s)",
+                sections.join(",")
+            ),
+            Some(session_id.as_str()),
+        );
+        self.analytics.record(AnalyticsEvent::ReportExported);
+        let time_zone = self.time_zone(&session_id)?;
+        Ok(SharedReport {
+            file_name: format!(
+                "法律咨询报告-{}.{}",
+                time_zone.date(self.clock.now()).format("%Y%m%d"),
+                format.extension()
+            ),
+            time_zone: time_zone.name,
+            mime_type: format.mime_type().to_owned(),
+            content: content.into_bytes(),
+            sections,
+            redactions,
+        })
+    }
+
+    /// Check an exported report, given as a file path or as its text,
+    /// against the fingerprint in its footer and the exports on record.
+    pub fn verify_report(&self, path_or_text: String) -> CoreResult<ReportVerification> {
+        let is_path = !path_or_text.contains('\n') && Path::new(&path_or_text).is_file();
+        let text = if is_path {
+            std::fs::read_to_string(&path_or_text)
+                .map_err(|e| CoreError::Storage(format!("read report failed: {e}")))?
+        } else {
+            path_or_text
+        };
+        let mut verification = verify_report(&self.storage, &text)?;
+        if let Some(fingerprint) = &verification.fingerprint {
+            verification.superseded = match self.latest_report(&fingerprint.session_id) {
+                Ok(report) => report.id != fingerprint.message_id,
+                Err(CoreError::NotFound(_)) => true,
+                Err(err) => return Err(err),
+            };
+        }
+        Ok(verification)
+    }
+
+    /// Accept the estimate the session stopped at and write the report
+    /// from the session's last user message, without appending a new one.
+    /// Returns the task id.
+    pub fn confirm_draft(&self, session_id: String) -> CoreResult<String> {
+        let session = self
+            .storage
+            .get_session(&session_id)?
+            .ok_or_else(|| CoreError::NotFound(format!("session {session_id}")))?;
+        if !estimate::confirm(&self.storage, &session_id)? {
+            return Err(CoreError::NotFound(format!(
+                "draft estimate awaiting confirmation for session {session_id}"
+            )));
+        }
+        let user_message = self
+            .storage
+            .get_messages(&session_id)?
+            .into_iter()
+            .rfind(|message| message.role == "user")
+            .ok_or_else(|| CoreError::NotFound(format!("user message in session {session_id}")))?;
+        self.spawn_agent_task(
+            session,
+            user_message,
+            TaskPriority::Normal,
+            TaskKind::ConfirmedDraft,
+        )
+    }
+
+    /// `priority` defaults to `Background`: nobody is waiting on the result.
+    #[uniffi::method(default(priority = None))]
+    pub fn regenerate_report(
+        &self,
+        session_id: String,
+        priority: Option<TaskPriority>,
+    ) -> CoreResult<String> {
+        self.regenerate_report_with_mode(session_id, ReportRegenerationMode::Full, priority)
+    }
+
+    /// Rebuild the session's report in `mode`. `CitationsOnly` redrafts the
+    /// legal analysis and its citations alone and keeps the other sections
+    /// of the latest report as they were drafted; a session whose latest
+    /// report predates that record is rebuilt in full. `priority` defaults
+    /// to `Background`. Returns the task id.
+    #[uniffi::method(default(priority = None))]
+    pub fn regenerate_report_with_mode(
+        &self,
+        session_id: String,
+        mode: ReportRegenerationMode,
+        priority: Option<TaskPriority>,
+    ) -> CoreResult<String> {
+        // A regeneration request is no answer to a question asked again.
+        take_pending_reask(&self.storage, &session_id)?;
+        self.events.emit(&ReportRegenerating {
+            session_id: session_id.clone(),
+        });
+
+        self.start_message_task(
+            session_id,
+            mode.request().to_owned(),
+            priority.unwrap_or(TaskPriority::Background),
+            TaskKind::Regenerate(mode),
+        )
+    }
+
+    /// Reports withheld for review across sessions, oldest first. Only
+    /// populated with `CoreConfig::require_report_review`.
+    pub fn list_pending_reviews(&self) -> CoreResult<Vec<PendingReview>> {
+        review_gate::pending(&self.storage)
+    }
+
+    /// Release the session's pending report to the user, emitting
+    /// `report_released`. Returns the message the user reads.
+    pub fn approve_report(&self, session_id: String) -> CoreResult<Message> {
+        let (report, released) = review_gate::approve(&self.storage, &session_id)?;
+        self.storage
+            .index_case_text(&session_id, "report", &report.content)?;
+        self.events.emit(&ReportReleased {
+            session_id,
+            message_id: released.id.clone(),
+            report: released.content.clone(),
+        });
+        Ok(released)
+    }
+
+    /// Send the session's pending report back and redraft it to address
+    /// `comment`; the redraft waits for review again. Returns the task id.
+    #[uniffi::method(default(priority = None))]
+    pub fn request_changes(
+        &self,
+        session_id: String,
+        comment: String,
+        priority: Option<TaskPriority>,
+    ) -> CoreResult<String> {
+        review_gate::request_changes(&self.storage, &session_id, &comment)?;
+        self.regenerate_report(session_id, priority)
+    }
+
+    /// Compare the KB the session's report was generated against with the
+    /// KB now, emitting `report_stale` when it changed since. Reports older
+    /// than the snapshot count as generated against the KB at their
+    /// creation time.
+    pub fn check_report_freshness(&self, session_id: String) -> CoreResult<ReportFreshness> {
+        let report = self
+            .storage
+            .get_messages(&session_id)?
+            .into_iter()
+            .rev()
+            .find(|message| {
+                message.role == "assistant" && message.phase.as_deref() == Some("review")
+            })
+            .ok_or_else(|| CoreError::NotFound(format!("report for session {session_id}")))?;
+        let retrieval = self.runtime().retrieval.clone();
+        let current = retrieval.knowledge_info()?;
+        let snapshot = self.report_generation(&session_id, &report, current.file_count)?;
+
+        let changed_files = retrieval
+            .list_files(None, None)?
+            .into_iter()
+            .filter(|file| file.modified_at > snapshot.kb_updated_at)
+            .map(|file| file.relative_path)
+            .collect::<Vec<_>>();
+        let stale = !changed_files.is_empty() || current.file_count != snapshot.kb_file_count;
+        if stale {
+            self.events.emit(&ReportStale {
+                session_id,
+                message_id: report.id.clone(),
+                changed_files: changed_files.clone(),
+                suggested_mode: ReportRegenerationMode::CitationsOnly.as_str().to_owned(),
+            });
+        }
+
+        Ok(ReportFreshness {
+            message_id: report.id,
+            generated_at: snapshot.generated_at,
+            report_kb_updated_at: snapshot.kb_updated_at,
+            current_kb_updated_at: current.updated_at,
+            changed_files,
+            stale,
+        })
+    }
+
+    pub fn set_retention_policy(&self, policy: RetentionPolicy) -> CoreResult<()> {
+        retention::save_policy(&self.storage, &policy)
+    }
+
+    pub fn get_retention_policy(&self) -> CoreResult<RetentionPolicy> {
+        retention::load_policy(&self.storage)
+    }
+
+    /// Set the assistant's persona for every session. Fails with
+    /// `CoreError::Safety` when its tone or signature phrases would trip a
+    /// safety rule or a prohibition names a safeguard such as the
+    /// disclaimer; an empty persona clears it. Returns the persona as
+    /// stored, trimmed and without duplicates.
+    pub fn set_persona_config(&self, config: PersonaConfig) -> CoreResult<PersonaConfig> {
+        let config = persona::normalize(config, &self.safety)?;
+        persona::save(&self.storage, &config)?;
+        Ok(config)
+    }
+
+    pub fn get_persona_config(&self) -> CoreResult<PersonaConfig> {
+        persona::load(&self.storage)
+    }
+
+    /// Report what the current retention policy would delete right now.
+    pub fn preview_purge(&self) -> CoreResult<PurgeSummary> {
+        let policy = retention::load_policy(&self.storage)?;
+        retention::preview_purge(&self.storage, &policy, self.clock.timestamp())
+    }
+
+    /// Apply the retention policy immediately instead of waiting for the
+    /// background job.
+    pub fn run_retention_purge(&self) -> CoreResult<PurgeSummary> {
+        run_retention_purge(&self.storage, &self.events)
+    }
+
+    /// What `delete_all_user_data` would remove, with the token it needs.
+    /// The token is valid for five minutes and only for the next call.
+    pub fn preview_delete_all_user_data(&self) -> CoreResult<ErasurePreview> {
+        self.ensure_writable("erase user data")?;
+        let mut summary = self.storage.count_user_data()?;
+        summary.file_count = self.data_dir.user_files()?.len() as u32;
+
+        let confirm_token = Uuid::new_v4().simple().to_string();
+        let expires_at = self.clock.timestamp() + CONFIRM_TOKEN_TTL_SECS;
+        *self
+            .erasure_token
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) =
+            Some((confirm_token.clone(), expires_at));
+        Ok(ErasurePreview {
+            summary,
+            confirm_token,
+            expires_at,
+        })
+    }
+
+    /// Erase everything stored about the user: all sessions of all profiles
+    /// with their messages, facts, attachments and traces, the logs and
+    /// usage records, and the attachment and cache files, which are
+    /// overwritten before deletion. The database is vacuumed afterwards and
+    /// `user_data_erased` reports the counts.
+    ///
+    /// Fails while a task is running. `confirm_token` comes from
+    /// `preview_delete_all_user_data`; a wrong or expired token fails with
+    /// `InvalidState` and is used up.
+    pub fn delete_all_user_data(&self, confirm_token: String) -> CoreResult<ErasureSummary> {
+        self.ensure_writable("erase user data")?;
+        let running = self
+            .task_controls
+            .lock()
+            .map_err(|_| CoreError::InvalidState("task_controls lock poisoned".to_owned()))?
+            .len();
+        if running > 0 {
+            return Err(CoreError::InvalidState(format!(
+                "{running} task(s) still running; cancel them before erasing data"
+            )));
+        }
+        let issued = self
+            .erasure_token
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .take();
+        match issued {
+            Some((token, expires_at))
+                if token == confirm_token && self.clock.timestamp() <= expires_at => {}
+            _ => {
+                return Err(CoreError::InvalidState(
+                    "confirmation token is invalid or expired; request a new preview".to_owned(),
+                ))
+            }
+        }
+
+        let mut summary = self.storage.erase_user_data()?;
+        summary.file_count = self.data_dir.shred_user_files()?;
+        self.storage.vacuum()?;
+
+        self.events.unmute_all_sessions();
+        self.session_queues
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .retain(|_, queue| Arc::strong_count(queue) > 1);
+        self.session_allow_all
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clear();
+        tracing::info!(
+            "erased user data: {} sessions, {} messages, {} files",
+            summary.session_count,
+            summary.message_count,
+            summary.file_count
+        );
+        self.events.emit(&UserDataErased {
+            session_count: summary.session_count,
+            message_count: summary.message_count,
+            attachment_count: summary.attachment_count,
+            fact_count: summary.fact_count,
+            log_count: summary.log_count,
+            trace_count: summary.trace_count,
+            record_count: summary.record_count,
+            file_count: summary.file_count,
+        });
+        Ok(summary)
+    }
+
+    /// Opt in to (or out of) local funnel counters. Opting out clears any
+    /// counters collected so far.
+    pub fn set_analytics_enabled(&self, enabled: bool) -> CoreResult<()> {
+        self.analytics.set_enabled(enabled)
+    }
+
+    pub fn is_analytics_enabled(&self) -> bool {
+        self.analytics.is_enabled()
+    }
+
+    pub fn export_analytics(&self) -> CoreResult<AnalyticsBatch> {
+        self.analytics.snapshot()
+    }
+
+    pub fn set_analytics_sink(&self, sink: Box<dyn AnalyticsSink>) -> CoreResult<()> {
+        self.analytics.set_sink(Some(Arc::from(sink)))
+    }
+
+    pub fn clear_analytics_sink(&self) -> CoreResult<()> {
+        self.analytics.set_sink(None)
+    }
+
+    /// Push the current batch to the registered sink and reset local counters.
+    /// Returns `None` when no sink is registered.
+    pub fn flush_analytics(&self) -> CoreResult<Option<AnalyticsBatch>> {
+        self.analytics.flush()
+    }
+
+    /// Opt in to logging retrieval queries locally, hashed or with personal
+    /// details masked. `Off` deletes the entries logged so far.
+    pub fn set_query_log_capture(&self, capture: QueryLogCapture) -> CoreResult<()> {
+        self.query_log.set_capture(capture)
+    }
+
+    pub fn get_query_log_capture(&self) -> QueryLogCapture {
+        self.query_log.capture()
+    }
+
+    /// Logged queries with `created_at >= since`, oldest first.
+    pub fn export_query_log(&self, since: i64) -> CoreResult<Vec<QueryLogEntry>> {
+        self.query_log.export(since)
+    }
+
+    pub fn clear_query_log(&self) -> CoreResult<()> {
+        self.query_log.clear()
+    }
+
+    /// Replay logged queries against the current KB and report how many of
+    /// the chunks cited or opened for them come back in the top `top_k`.
+    pub fn evaluate_query_log(&self, top_k: u32) -> CoreResult<QueryLogEvaluation> {
+        let retrieval = self.runtime().retrieval.clone();
+        let top_k = top_k as usize;
+        query_log::evaluate(&self.query_log.export(0)?, |query, scenario| {
+            let batches = query
+                .lines()
+                .map(|query| retrieval.search(query, scenario, top_k))
+                .collect::<CoreResult<Vec<_>>>()?;
+            Ok(merge_query_results(batches, top_k)
+                .into_iter()
+                .map(|result| result.chunk_id)
+                .collect())
+        })
+    }
+}
+
+impl Core {
+    /// Construct a core whose storage timestamps, event timestamps and model
+    /// retry backoff all read from `clock`.
+    pub fn with_clock(config: CoreConfig, clock: Arc<dyn Clock>) -> CoreResult<Arc<Self>> {
+        let runtime = RuntimeConfig::new(&config, None, &clock)?;
+
+        let data_dir = DataDir::open(
+            config.data_dir.as_deref(),
+            &config.db_path,
+            config.read_only,
+        )?;
+        let storage = Arc::new(if config.read_only {
+            SqliteStorage::open_read_only(data_dir.db_path(), clock.clone())?
+        } else {
+            SqliteStorage::new(data_dir.db_path(), clock.clone())?
+        });
+        storage.set_query_limits(query_limits(&config))?;
+        storage.set_content_encryption(config.encrypt_message_content);
+        storage
+            .faults()
+            .load(&storage.list_settings(FAULT_SETTING_PREFIX)?);
+        // A profile deleted behind our back falls back to the default one.
+        let active_profile = match storage.get_setting(ACTIVE_PROFILE_KEY)? {
+            Some(id) if storage.get_profile(&id)?.is_some() => id,
+            _ => DEFAULT_PROFILE_ID.to_owned(),
+        };
+        let safety = Arc::new(SafetyEngine::default());
+        let tools = Arc::new(ToolRegistry::with_builtins());
+        tools.stats().load(&storage);
+        let analytics = Arc::new(AnalyticsCollector::new(storage.clone()));
+        let query_log = Arc::new(QueryLog::new(storage.clone()));
+        let events = match config.event_history_size {
+            Some(keep) if keep > 0 => {
+                EventHub::new(clock.clone()).with_history(storage.clone(), keep)
+            }
+            _ => EventHub::new(clock.clone()),
+        };
+        for setting in storage.list_settings("session:")? {
+            if let Some(session_id) = setting
+                .key
+                .strip_prefix("session:")
+                .and_then(|key| key.strip_suffix(":muted"))
+            {
+                events.set_session_muted(session_id, true);
+            }
+        }
+        let events = Arc::new(events);
+
+        check_kb_integrity(&runtime.retrieval, &events)?;
+
+        if !config.read_only {
+            spawn_retention_job(Arc::downgrade(&storage), Arc::downgrade(&events));
+        }
+        let kb_watcher = if config.watch_kb {
+            Some(start_kb_watcher(&config.kb_path, &events)?)
+        } else {
+            None
+        };
+
+        let model_scheduler = Arc::new(ModelScheduler::new(
+            SchedulerLimits::default(),
+            clock.clone(),
+        ));
+        Ok(Arc::new(Self {
+            data_dir,
+            event_history_size: config.event_history_size.unwrap_or(0),
+            runtime: RwLock::new(Arc::new(runtime)),
+            clock,
+            active_profile: RwLock::new(active_profile),
+            storage,
+            kb_watcher: Mutex::new(kb_watcher),
+            safety,
+            tools,
+            model_connector: Arc::new(RwLock::new(None)),
+            model_generation: Arc::new(AtomicU64::new(0)),
+            model_scheduler,
+            secret_provider: RwLock::new(None),
+            transcriber: RwLock::new(None),
+            events,
+            task_controls: Arc::new(Mutex::new(HashMap::new())),
+            pending_tool_calls: Arc::new(Mutex::new(HashMap::new())),
+            settled_tool_calls: Arc::new(Mutex::new(SettledToolCalls::default())),
+            session_allow_all: Arc::new(Mutex::new(HashSet::new())),
+            analytics,
+            query_log,
+            phase_timings: Arc::new(PhaseTimingCollector::default()),
+            session_queues: Arc::new(Mutex::new(HashMap::new())),
+            erasure_token: Mutex::new(None),
+        }))
+    }
+}
+
+impl Core {
+    /// The session's latest report: the reviewed one, else the last
+    /// message that reads as a report. Reports withheld for human review
+    /// are not the user's yet and never count.
+    fn latest_report(&self, session_id: &str) -> CoreResult<Message> {
+        let messages = self.storage.get_messages(session_id)?;
+        let review = messages
+            .iter()
+            .rposition(|msg| msg.role == "assistant" && msg.phase.as_deref() == Some("review"));
+        review
+            .or_else(|| {
+                messages.iter().rposition(|msg| {
+                    msg.role == "assistant"
+                        && !review_gate::is_withheld(msg)
+                        && msg.content.contains("【事实摘要】")
+                        && msg.content.contains("【免责声明】")
+                })
+            })
+            .map(|index| messages[index].clone())
+            .ok_or_else(|| CoreError::NotFound(format!("report for session {session_id}")))
+    }
+
+    /// The KB snapshot of `report`. Reports older than the snapshot count as
+    /// generated against the KB at their creation time, of
+    /// `kb_file_count` files.
+    fn report_generation(
+        &self,
+        session_id: &str,
+        report: &Message,
+        kb_file_count: u32,
+    ) -> CoreResult<ReportKbSnapshot> {
+        Ok(report_snapshot(&self.storage, session_id)?
+            .filter(|snapshot| snapshot.message_id == report.id)
+            .unwrap_or_else(|| ReportKbSnapshot {
+                message_id: report.id.clone(),
+                generated_at: report.created_at,
+                kb_updated_at: report.created_at,
+                kb_file_count,
+                model: None,
+            }))
+    }
+
+    /// `content`, an export of the session's latest report, with its
+    /// fingerprint footer.
+    fn stamp_report(
+        &self,
+        session_id: &str,
+        content: &str,
+        format: ShareFormat,
+    ) -> CoreResult<String> {
+        let report = self.latest_report(session_id)?;
+        let kb_file_count = self.runtime().retrieval.knowledge_info()?.file_count;
+        let snapshot = self.report_generation(session_id, &report, kb_file_count)?;
+        stamp_report(
+            &self.storage,
+            content,
+            format,
+            session_id,
+            &snapshot,
+            self.clock.timestamp(),
+            &self.time_zone(session_id)?,
+        )
+    }
+
+    /// Zone of dates shown in `session_id`; see `CoreConfig::time_zone`.
+    fn time_zone(&self, session_id: &str) -> CoreResult<UserTimeZone> {
+        session_time_zone(&self.storage, session_id, &self.runtime().time_zone)
+    }
+
+    /// The safety review reports get, for an answer made outside a report
+    /// task: incidents are recorded, and a critical finding puts the answer
+    /// under the review notice.
+    fn review_answer(&self, session_id: &str, answer: &str) -> CoreResult<String> {
+        let scenario = self
+            .storage
+            .get_session(session_id)?
+            .ok_or_else(|| CoreError::NotFound(format!("session {session_id}")))?
+            .scenario;
+        let user_text = user_words(&self.storage, session_id, &scenario)?;
+        let checked = self.safety.check_quoting(
+            answer,
+            &user_text.iter().map(String::as_str).collect::<Vec<_>>(),
+        );
+        for issue in &checked.issues {
+            self.storage.record_safety_incident(
+                session_id,
+                None,
+                &issue.rule_name,
+                &issue.category,
+                issue.severity.as_str(),
+                &issue.context,
+            )?;
+        }
+        if !checked.has_critical {
+            return Ok(checked.modified_content);
+        }
+        let critical_count = checked
+            .issues
+            .iter()
+            .filter(|issue| issue.severity == Severity::Critical && !issue.quoted)
+            .count();
+        let locale = language::session_language(&self.storage, session_id)?
+            .unwrap_or_else(|| self.runtime().locale.clone());
+        Ok(CopyCatalog::new(&self.storage, &locale).render(
+            CopyKey::ReviewIntercepted,
+            &[
+                ("count", &critical_count.to_string()),
+                ("report", &checked.modified_content),
+            ],
+        ))
+    }
+
+    fn runtime(&self) -> Arc<RuntimeConfig> {
+        self.runtime
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    /// Pre-flight `connector` on a background thread so the first real
+    /// request finds a warm connection and a validated key.
+    fn spawn_model_warm_up(&self, connector: ModelConnector, model_name: String, generation: u64) {
+        let current = self.model_generation.clone();
+        let scheduler = self.model_scheduler.clone();
+        let events = Arc::downgrade(&self.events);
+        thread::spawn(move || {
+            let replaced = || current.load(Ordering::SeqCst) != generation;
+            let Some(_permit) = scheduler.acquire("", CallPriority::Background, replaced) else {
+                return;
+            };
+            let started = Instant::now();
+            let result = RUNTIME.block_on(connector.preflight());
+            let Some(events) = events.upgrade().filter(|_| !replaced()) else {
+                return;
+            };
+            match result {
+                Ok(()) => events.emit(&ModelReady {
+                    model: model_name,
+                    latency_ms: started.elapsed().as_millis() as u64,
+                }),
+                Err(failure) => events.emit(&ModelWarmupFailed {
+                    model: model_name,
+                    stage: failure.stage.as_str().to_owned(),
+                    status: failure.status,
+                    message: failure.message,
+                }),
+            }
+        });
+    }
+
+    fn active_profile_id(&self) -> String {
+        self.active_profile
+            .read()
+            .map(|id| id.clone())
+            .unwrap_or_else(|poisoned| poisoned.into_inner().clone())
+    }
+
+    /// `answer`, a templated what-if answer, reworded by the model in the
+    /// persona's voice. Without a persona or a model, or when the rewording
+    /// drops a section heading or changes a figure, `answer` stands.
+    fn what_if_in_persona(&self, answer: String) -> String {
+        match persona::load(&self.storage) {
+            Ok(config) if !config.is_empty() => {}
+            Ok(_) => return answer,
+            Err(err) => {
+                tracing::warn!("persona not applied: {err}");
+                return answer;
+            }
+        }
+        let Some(connector) = self
+            .model_connector
+            .read()
+            .ok()
+            .and_then(|connector| connector.clone())
+        else {
+            return answer;
+        };
+        let Some(_permit) = self
+            .model_scheduler
+            .acquire("", CallPriority::Interactive, || false)
+        else {
+            return answer;
+        };
+        if let Err(err) = self.storage.faults().model() {
+            tracing::warn!("what-if persona pass failed: {err}");
+            return answer;
+        }
+        let mut messages = vec![
+            model::ChatMessage {
+                role: "system".to_owned(),
+                content: "请在不改变事实、金额、日期和【】章节标题的前提下润色用户给出的假设分析。只输出润色后的完整内容。".to_owned(),
+            },
+            model::ChatMessage {
+                role: "user".to_owned(),
+                content: answer.clone(),
+            },
+        ];
+        persona::apply(&self.storage, &mut messages);
+        let call = connector.chat_completion_for(ModelPhase::Followup, &messages);
+        match RUNTIME.block_on(call) {
+            Ok(styled)
+                if answer
+                    .split('【')
+                    .skip(1)
+                    .filter_map(|rest| rest.split_once('】'))
+                    .all(|(heading, _)| styled.contains(&format!("【{heading}】")))
+                    && keeps_figures(&answer, &styled) =>
+            {
+                styled.trim().to_owned()
+            }
+            Ok(_) => {
+                tracing::warn!(
+                    "what-if persona pass changed sections or figures; using template output"
+                );
+                answer
+            }
+            Err(err) => {
+                tracing::warn!("what-if persona pass failed: {err}");
+                answer
+            }
+        }
+    }
+
+    /// The KB search of `quick_ask`, given up at `deadline`: a slow remote
+    /// KB leaves the answer without KB context rather than late.
+    fn quick_search(
+        &self,
+        question: &str,
+        scenario: &str,
+        deadline: Instant,
+    ) -> CoreResult<Vec<SearchResult>> {
+        let retrieval = self.runtime().retrieval.clone();
+        let (tx, rx) = mpsc::channel();
+        let (query, scenario_owned) = (question.to_owned(), scenario.to_owned());
+        thread::spawn(move || {
+            let _ = tx.send(retrieval.search(&query, &scenario_owned, QUICK_SEARCH_LIMIT));
+        });
+        let results = match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(results) => trim_to_token_budget(results?, QUICK_CONTEXT_TOKEN_BUDGET),
+            Err(_) => {
+                tracing::warn!("quick answer search ran out of time");
+                Vec::new()
+            }
+        };
+        let chunk_ids = results
+            .iter()
+            .map(|result| result.chunk_id.clone())
+            .collect::<Vec<_>>();
+        self.query_log
+            .record(question, scenario, results.len(), &chunk_ids);
+        Ok(results)
+    }
+
+    /// The model's answer to a quick question, or `None` without a model,
+    /// on failure, or when it does not come within `remaining`.
+    fn quick_model_answer(
+        &self,
+        scenario: &str,
+        question: &str,
+        results: &[SearchResult],
+        remaining: Duration,
+    ) -> Option<String> {
+        let connector = self.model_connector.read().ok()?.clone()?;
+        let deadline = Instant::now() + remaining;
+        let _permit = self
+            .model_scheduler
+            .acquire("", CallPriority::Interactive, || Instant::now() >= deadline)?;
+        let remaining = deadline.checked_duration_since(Instant::now())?;
+        if let Err(err) = self.storage.faults().model() {
+            tracing::warn!("quick answer failed: {err}");
+            return None;
+        }
+        let mut messages = quick::quick_prompt(scenario, question, results);
+        persona::apply(&self.storage, &mut messages);
+        let call = connector.chat_completion_for(ModelPhase::Followup, &messages);
+        // The timer has to be made inside the runtime.
+        match RUNTIME.block_on(async { tokio::time::timeout(remaining, call).await }) {
+            Ok(Ok(answer)) if !answer.trim().is_empty() => Some(answer),
+            Ok(Ok(_)) => None,
+            Ok(Err(err)) => {
+                tracing::warn!("quick answer failed: {err}");
+                None
+            }
+            Err(_) => {
+                tracing::warn!("quick answer timed out after {}ms", remaining.as_millis());
+                None
+            }
+        }
+    }
+
+    /// A session of `case` with its answers and a report written from the
+    /// KB without a model, and its two messages. Emits nothing; the caller
+    /// announces them once stored.
+    fn create_demo_session(
+        &self,
+        runtime: &RuntimeConfig,
+        case: &DemoCase,
+    ) -> CoreResult<(Session, [Message; 2])> {
+        let session = self.storage.create_session(
+            case.scenario,
+            Some(case.title),
+            &self.active_profile_id(),
+        )?;
+        seed_demo_intake(&self.storage, &session.id, case)?;
+        self.storage.index_case_text(
+            &session.id,
+            "facts",
+            &demo_facts_summary(&self.storage, &session.id, case)?,
+        )?;
+        let report = demo_report(
+            &self.storage,
+            &runtime.retrieval,
+            &session.id,
+            case,
+            runtime.region,
+            &self.time_zone(&session.id)?,
+            self.clock.now(),
+        )?;
+
+        let question =
+            self.storage
+                .create_message(&session.id, "user", case.question, None, None)?;
+        let answer = self.storage.create_reply(
+            &session.id,
+            &question.id,
+            "assistant",
+            &report,
+            Some("review"),
+        )?;
+        Ok((session, [question, answer]))
+    }
+
+    fn ensure_writable(&self, action: &str) -> CoreResult<()> {
+        if self.data_dir.read_only() {
+            return Err(CoreError::InvalidState(format!(
+                "a read-only core cannot {action}"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Encrypt messages stored in the clear while content encryption is on
+    /// and the key is known.
+    fn seal_message_content(&self) -> CoreResult<()> {
+        // Without a key yet, this runs again from `set_secret_provider`.
+        if self.data_dir.read_only()
+            || !self.storage.content_encryption()
+            || !self.storage.has_content_cipher()
+        {
+            return Ok(());
+        }
+        let sealed = self.storage.seal_plaintext_content()?;
+        if sealed > 0 {
+            tracing::info!("encrypted {sealed} stored messages");
+        }
+        Ok(())
+    }
+
+    /// Host settings of the default profile keep their bare keys, so
+    /// databases from before profiles read the same; other profiles get
+    /// their own `profile:{id}:` namespace. Session-scoped keys are already
+    /// apart by session id and stay as they are.
+    fn profile_setting_key(&self, key: &str) -> String {
+        let profile_id = self.active_profile_id();
+        if profile_id == DEFAULT_PROFILE_ID
+            || UNSCOPED_SETTING_PREFIXES
+                .iter()
+                .any(|prefix| key.starts_with(prefix))
+        {
+            key.to_owned()
+        } else {
+            format!("{PROFILE_SETTING_PREFIX}{profile_id}:{key}")
+        }
+    }
+
+    /// `f` on the stepper of the running step-through task `task_id`.
+    fn with_stepper<T>(&self, task_id: &str, f: impl FnOnce(&Stepper) -> T) -> CoreResult<T> {
+        let controls = self
+            .task_controls
+            .lock()
+            .map_err(|_| CoreError::InvalidState("task_controls lock poisoned".to_owned()))?;
+        controls
+            .get(task_id)
+            .and_then(|control| control.stepper.as_ref())
+            .map(f)
+            .ok_or_else(|| CoreError::NotFound(format!("step-through task {task_id}")))
+    }
+
+    /// Hand `decision` to the worker waiting on `request_id`. Returns the
+    /// pending call when it was delivered, `None` when the request had already
+    /// been settled (the outcome is then in `settled_tool_calls`).
+    fn deliver_tool_decision(
+        &self,
+        request_id: &str,
+        decision: ToolDecision,
+    ) -> CoreResult<Option<PendingToolCall>> {
+        let mut delivered = self.deliver_tool_decisions(&[request_id.to_owned()], decision)?;
+        Ok(delivered.pop().flatten())
+    }
+
+    /// `deliver_tool_decision` for a batch, in order, under one lock: either
+    /// every id is known (pending or settled) and each pending one gets
+    /// `decision`, or nothing is delivered.
+    fn deliver_tool_decisions(
+        &self,
+        request_ids: &[String],
+        decision: ToolDecision,
+    ) -> CoreResult<Vec<Option<PendingToolCall>>> {
+        // Lock order is pending -> settled everywhere, so a request id is
+        // always in exactly one of the two maps.
+        let mut pending_map = self
+            .pending_tool_calls
+            .lock()
+            .map_err(|_| CoreError::InvalidState("pending_tool_calls lock poisoned".to_owned()))?;
+        let mut settled = self
+            .settled_tool_calls
+            .lock()
+            .map_err(|_| CoreError::InvalidState("settled_tool_calls lock poisoned".to_owned()))?;
+        if let Some(unknown) = request_ids.iter().find(|request_id| {
+            !pending_map.contains_key(*request_id) && settled.get(request_id).is_none()
+        }) {
+            return Err(CoreError::NotFound(format!("request {unknown}")));
+        }
+
+        let outcome = match &decision {
+            ToolDecision::Respond(_) => ToolCallOutcome::Delivered,
+            ToolDecision::Skip { .. } => ToolCallOutcome::Skipped,
+        };
+        Ok(request_ids
+            .iter()
+            .map(|request_id| {
+                let pending = pending_map.remove(request_id)?;
+                // The worker may have been cancelled between removing the
+                // request and dropping its receiver; nothing was applied then.
+                if pending.sender.send(decision.clone()).is_err() {
+                    settled.insert(request_id.clone(), ToolCallOutcome::TaskCancelled);
+                    return None;
+                }
+                settled.insert(request_id.clone(), outcome);
+                Some(pending)
+            })
+            .collect())
+    }
+
+    /// Side effects of a delivered answer beyond the call itself.
+    fn apply_tool_response(&self, pending: &PendingToolCall, response: &ToolResponse) {
+        if matches!(response, ToolResponse::AllowAllThisSession) {
+            if let Ok(mut allow_all) = self.session_allow_all.lock() {
+                allow_all.insert(pending.session_id.clone());
+            }
+        }
+
+        if let ToolResponse::Allow { always: true } = response {
+            let _ = self
+                .storage
+                .set_tool_permission(&pending.tool_name, "allow");
+        }
+    }
+
+    /// Report a request that was settled before this answer arrived.
+    fn settled_outcome(&self, request_id: &str) -> CoreResult<ToolCallOutcome> {
+        let outcome = match self
+            .settled_tool_calls
+            .lock()
+            .map_err(|_| CoreError::InvalidState("settled_tool_calls lock poisoned".to_owned()))?
+            .get(request_id)
+        {
+            Some(ToolCallOutcome::Delivered) => ToolCallOutcome::AlreadyResponded,
+            Some(outcome) => outcome,
+            None => return Err(CoreError::NotFound(format!("request {request_id}"))),
+        };
+        emit_tool_call_closed(&self.events, request_id, outcome);
+        Ok(outcome)
+    }
+
+    /// Append the user message `content` and start a `kind` task on it;
+    /// see `send_message`.
+    fn start_message_task(
+        &self,
+        session_id: String,
+        content: String,
+        priority: TaskPriority,
+        kind: TaskKind,
+    ) -> CoreResult<String> {
+        let session = self
+            .storage
+            .get_session(&session_id)?
+            .ok_or_else(|| CoreError::NotFound(format!("session {session_id}")))?;
+
+        let message = NewMessage {
+            role: "user".to_owned(),
+            content,
+            phase: Some("plan".to_owned()),
+            tool_calls: None,
+            created_at: None,
+        };
+        let (mut created, long_text) = self.storage.with_tx(|| {
+            let (message, long_text) = self.prepare_message(&session_id, message, None)?;
+            let created = self
+                .storage
+                .create_messages_bulk(&session_id, std::slice::from_ref(&message))?;
+            Ok((created, long_text))
+        })?;
+        let user_message = created
+            .pop()
+            .ok_or_else(|| CoreError::Storage("message was not stored".to_owned()))?;
+        if let Some(event) = long_text {
+            self.events.emit(&event);
+        }
+        if let Some(drift) = take_split_suggestion(&self.storage, &session)? {
+            self.events.emit(&SessionSplitSuggested {
+                session_id: session_id.clone(),
+                topic: drift.topic,
+                message_ids: drift.message_ids,
+            });
+        }
+
+        self.spawn_agent_task(session, user_message, priority, kind)
+    }
+
+    /// `message` checked and fitted for storage, the one path every new
+    /// message takes: its tool calls must be JSON, and user text over
+    /// `max_message_chars` is stored as an attachment and replaced by a
+    /// stub. Returns the `LongTextStored` event for the caller to emit once
+    /// the message is stored. `index` names the message of a batch in
+    /// errors.
+    fn prepare_message(
+        &self,
+        session_id: &str,
+        message: NewMessage,
+        index: Option<usize>,
+    ) -> CoreResult<(NewMessage, Option<LongTextStored>)> {
+        let tool_calls = match message.tool_calls.as_deref() {
+            Some(raw) => Some(
+                serde_json::from_str::<Value>(raw)
+                    .map_err(|e| {
+                        CoreError::InvalidState(match index {
+                            Some(index) => {
+                                format!("invalid tool_calls json in message {index}: {e}")
+                            }
+                            None => format!("invalid tool_calls json: {e}"),
+                        })
+                    })?
+                    .to_string(),
+            ),
+            None => None,
+        };
+        let chars = message.content.chars().count();
+        if message.role != "user" || chars <= self.runtime().max_message_chars {
+            return Ok((
+                NewMessage {
+                    tool_calls,
+                    ..message
+                },
+                None,
+            ));
+        }
+
+        let local = self
+            .clock
+            .now()
+            .with_timezone(&self.time_zone(session_id)?.offset());
+        let file_name = format!("长文本-{}.txt", local.format("%Y%m%d-%H%M%S"));
+        let attachment = self.storage.create_attachment(
+            session_id,
+            &file_name,
+            "text/plain",
+            &message.content,
+        )?;
+        let locale = language::session_language(&self.storage, session_id)?
+            .unwrap_or_else(|| self.runtime().locale.clone());
+        let content = long_text_stub(
+            &CopyCatalog::new(&self.storage, &locale),
+            &message.content,
+            &file_name,
+        );
+        Ok((
+            NewMessage {
+                content,
+                tool_calls,
+                ..message
+            },
+            Some(LongTextStored {
+                session_id: session_id.to_owned(),
+                attachment_id: attachment.id,
+                chars,
+            }),
+        ))
+    }
+
+    /// The session's queue, created if absent; one task of a session runs
+    /// at a time.
+    fn session_queue(&self, session_id: &str) -> CoreResult<Arc<ModelScheduler>> {
+        let mut queues = self
+            .session_queues
+            .lock()
+            .map_err(|_| CoreError::InvalidState("session_queues lock poisoned".to_owned()))?;
+        Ok(queues
+            .entry(session_id.to_owned())
+            .or_insert_with(|| {
+                Arc::new(ModelScheduler::new(
+                    SchedulerLimits {
+                        max_in_flight: 1,
+                        min_interval: Duration::ZERO,
+                    },
+                    self.clock.clone(),
+                ))
+            })
+            .clone())
+    }
+
+    /// Run `f` holding the session's turn, so no task of the session starts
+    /// before it returns. Refused without running `f` while a task of the
+    /// session is queued or running; `action` names `f` in the error.
+    fn with_session_turn<T>(
+        &self,
+        session_id: &str,
+        action: &str,
+        f: impl FnOnce() -> CoreResult<T>,
+    ) -> CoreResult<T> {
+        let queue = self.session_queue(session_id)?;
+        let result = match queue.acquire("", CallPriority::Interactive, || {
+            self.events.has_session_tasks(session_id)
+        }) {
+            Some(_turn) => f(),
+            None => Err(CoreError::InvalidState(format!(
+                "session {session_id} has a task running; wait for it or cancel it before {action}"
+            ))),
+        };
+        drop(queue);
+        prune_session_queue(&self.session_queues, session_id);
+        result
+    }
+
+    fn spawn_agent_task(
+        &self,
+        session: Session,
+        user_message: Message,
+        priority: TaskPriority,
+        kind: TaskKind,
+    ) -> CoreResult<String> {
+        let task_id = Uuid::new_v4().to_string();
+        let stepper = self
+            .storage
+            .get_setting(&session_step_through_key(&session.id))?
+            .map(|_| Stepper::new(&task_id, &session.id));
+        let control = Arc::new(TaskControl::new(stepper));
+
+        {
+            let mut controls = self
+                .task_controls
+                .lock()
+                .map_err(|_| CoreError::InvalidState("task_controls lock poisoned".to_owned()))?;
+            controls.insert(task_id.clone(), control.clone());
+        }
+        self.events.track_task(&task_id, &session.id);
+
+        let session_queues = self.session_queues.clone();
+        let session_queue = self.session_queue(&session.id)?;
+
+        let messages = self.storage.get_messages(&session.id)?;
+        let followup = messages
+            .iter()
+            .any(|message| message.phase.as_deref() == Some("review"));
+        let first_message = messages
+            .iter()
+            .find(|message| message.role == "user")
+            .map_or(user_message.content.as_str(), |message| {
+                message.content.as_str()
+            });
+        let language = resolve_session_language(&self.storage, &session.id, first_message)?;
+        let runtime = self.runtime();
+        let worker = AgentWorker {
+            task_id: task_id.clone(),
+            session_id: session.id,
+            profile_id: session.profile_id,
+            scenario: session.scenario,
+            user_message_id: user_message.id,
+            user_content: user_message.content,
+            max_iterations: runtime.max_iterations,
+            locale: language.unwrap_or_else(|| runtime.locale.clone()),
+            region: runtime.region,
+            time_zone: runtime.time_zone.clone(),
+            permission_policy: runtime.permission_policy.clone(),
+            require_report_review: runtime.require_report_review,
+            clock: self.clock.clone(),
+            storage: self.storage.clone(),
+            retrieval: runtime.retrieval.clone(),
+            safety: self.safety.clone(),
+            tools: self.tools.clone(),
+            events: self.events.clone(),
+            pending_tool_calls: self.pending_tool_calls.clone(),
+            settled_tool_calls: self.settled_tool_calls.clone(),
+            session_allow_all: self.session_allow_all.clone(),
+            control: control.clone(),
+            task_controls: self.task_controls.clone(),
+            analytics: self.analytics.clone(),
+            query_log: self.query_log.clone(),
+            model_connector: self.model_connector.clone(),
+            model_scheduler: self.model_scheduler.clone(),
+            model_memo: RequestMemo::default(),
+            drafted_sections: Mutex::new(BTreeMap::new()),
+            legal_provenance: Mutex::new(None),
+            verified_sources: Mutex::new(Vec::new()),
+            citation_coverage: Mutex::new(None),
+            conclusion_confidence: Mutex::new(None),
+            phase_timings: self.phase_timings.clone(),
+            phase_timer: Mutex::new(PhaseTimer::start(self.clock.clone())),
+            followup,
+            priority,
+            kind,
+        };
+
+        thread::spawn(move || {
+            // Wait for the session's turn so only one AgentWorker runs per
+            // session; a task cancelled while waiting never starts.
+            let turn = session_queue.acquire(&worker.task_id, priority.queue_priority(), || {
+                worker.control.is_cancelled()
+            });
+            let result = match turn {
+                Some(_) => worker.run(),
+                None => Err(CoreError::Cancelled),
+            };
+            drop(turn);
+            drop(session_queue);
+            prune_session_queue(&session_queues, &worker.session_id);
+            worker.trace(
+                "outcome",
+                match &result {
+                    Ok(()) => json!({"status": "completed"}),
+                    Err(CoreError::Cancelled) => json!({"status": "cancelled"}),
+                    Err(err) => json!({"status": "failed", "error": err.to_string()}),
+                },
+            );
+            match result {
+                Ok(()) => {
+                    if let Err(err) = clear_failed_task(&worker.storage, &worker.session_id) {
+                        tracing::warn!("clear failed task failed: {err}");
+                    }
+                }
+                Err(CoreError::Cancelled) => {
+                    let draft = worker.save_partial_draft();
+                    worker.events.emit(&Cancelled {
+                        task_id: worker.task_id.clone(),
+                        session_id: worker.session_id.clone(),
+                        draft_message_id: draft.as_ref().map(|message| message.id.clone()),
+                        partial_report: draft.map(|message| message.content),
+                    });
+                }
+                Err(err) => {
+                    let failed = FailedTask {
+                        task_id: worker.task_id.clone(),
+                        user_message_id: worker.user_message_id.clone(),
+                        error: err.to_string(),
+                        failed_at: worker.clock.timestamp(),
+                    };
+                    let retryable =
+                        match record_failed_task(&worker.storage, &worker.session_id, &failed) {
+                            Ok(()) => true,
+                            Err(record_err) => {
+                                tracing::warn!("record failed task failed: {record_err}");
+                                false
+                            }
+                        };
+                    worker.events.emit(&TaskError {
+                        task_id: worker.task_id.clone(),
+                        session_id: worker.session_id.clone(),
+                        message: err.to_string(),
+                        retryable,
+                    });
+                }
+            }
+
+            if let Ok(mut controls) = worker.task_controls.lock() {
+                controls.remove(&worker.task_id);
+            }
+            worker.events.untrack_task(&worker.task_id);
+        });
+
+        Ok(task_id)
+    }
+}
+
+struct AgentWorker {
+    task_id: String,
+    session_id: String,
+    profile_id: String,
+    scenario: String,
+    user_message_id: String,
+    user_content: String,
+    max_iterations: u32,
+    /// The session's language, or the configured locale until it is known.
+    locale: String,
+    region: DeploymentRegion,
+    /// Configured zone of user-facing dates; sessions may override it.
+    time_zone: UserTimeZone,
+    permission_policy: Arc<PermissionPolicy>,
+    /// Reports are withheld for review; see `agent::review_gate`.
+    require_report_review: bool,
+    clock: Arc<dyn Clock>,
+    storage: Arc<SqliteStorage>,
+    retrieval: Arc<RetrievalEngine>,
+    safety: Arc<SafetyEngine>,
+    tools: Arc<ToolRegistry>,
+    events: Arc<EventHub>,
+    pending_tool_calls: Arc<Mutex<HashMap<String, PendingToolCall>>>,
+    settled_tool_calls: Arc<Mutex<SettledToolCalls>>,
+    session_allow_all: Arc<Mutex<HashSet<String>>>,
+    control: Arc<TaskControl>,
+    task_controls: Arc<Mutex<HashMap<String, Arc<TaskControl>>>>,
+    analytics: Arc<AnalyticsCollector>,
+    query_log: Arc<QueryLog>,
+    model_connector: Arc<RwLock<Option<ModelConnector>>>,
+    model_scheduler: Arc<ModelScheduler>,
+    /// Completions of this task's model calls, reused for repeated prompts.
+    model_memo: RequestMemo,
+    /// Report sections sent so far, by position, kept as a draft if the task
+    /// is cancelled before the report completes.
+    drafted_sections: Mutex<BTreeMap<u32, String>>,
+    /// Sources of the legal analysis, set once it is drafted.
+    legal_provenance: Mutex<Option<SectionProvenance>>,
+    /// KB chunks retrieved for the legal analysis; the claims of the
+    /// report may cite only these.
+    verified_sources: Mutex<Vec<VerifiedSource>>,
+    /// Set by the review, reported with the completed report.
+    citation_coverage: Mutex<Option<CitationCoverage>>,
+    /// Set once the legal analysis is drafted, reported with the completed
+    /// report.
+    conclusion_confidence: Mutex<Option<ConclusionConfidence>>,
+    phase_timings: Arc<PhaseTimingCollector>,
+    phase_timer: Mutex<PhaseTimer>,
+    /// The session already had a report when this task started.
+    followup: bool,
+    priority: TaskPriority,
+    kind: TaskKind,
+}
+
+impl AgentWorker {
+    fn run(&self) -> CoreResult<()> {
+        self.run_with_iteration(1)
+    }
+
+    fn run_with_iteration(&self, iteration: u32) -> CoreResult<()> {
+        if iteration > self.max_iterations {
+            return Err(CoreError::Unknown(format!(
+                "max_iterations exceeded: {}",
+                self.max_iterations
+            )));
+        }
+
+        self.guard_not_cancelled()?;
+
+        self.enter_phase(AgentPhase::Plan);
+
+        let intake = intake_state(&self.storage, &self.session_id, &self.scenario)?;
+        if !intake.done {
+            self.time_phase(TimedPhase::Intake);
+            return self.handle_intake(iteration, intake);
+        }
+        if let Some(index) = take_pending_reask(&self.storage, &self.session_id)? {
+            self.answer_open_question(&intake, index)?;
+        }
+        if self.stop_at_estimate()? {
+            return Ok(());
+        }
+        if self.control.stepper.is_some() {
+            let facts = case_facts(&self.storage, &self.session_id, &self.scenario)?;
+            self.record_intermediate(|intermediate| intermediate.facts = facts);
+        }
+        self.pause_after(AgentPhase::Plan)?;
+
+        self.enter_phase(AgentPhase::Draft);
+
+        let tool_ctx = ToolContext {
+            retrieval: self.retrieval.clone(),
+            safety: self.safety.clone(),
+            storage: self.storage.clone(),
+            region: self.region,
+            profile_id: self.profile_id.clone(),
+        };
+
+        // A citations-only regeneration redrafts the legal analysis alone.
+        let kept = match self.kind {
+            TaskKind::Regenerate(ReportRegenerationMode::CitationsOnly) => {
+                drafted_sections(&self.storage, &self.session_id)?
+            }
+            _ => None,
+        };
+        if let TaskKind::Regenerate(mode) = self.kind {
+            self.trace(
+                "regeneration",
+                json!({"mode": mode.as_str(), "kept_sections": kept.is_some()}),
+            );
+        }
+        let facts_summary = match &kept {
+            Some(kept) => kept.facts_summary.clone(),
+            None => self.summarize_facts(&tool_ctx)?,
+        };
+        self.record_intermediate(|intermediate| {
+            intermediate.facts_summary = Some(facts_summary.clone());
+        });
+
+        let tone = report_tone(&self.storage, &self.session_id)?;
+        let time_zone = session_time_zone(&self.storage, &self.session_id, &self.time_zone)?;
+        let disclaimer = report_disclaimer(self.region, self.clock.now(), &time_zone);
+        let has_evidence = !self.storage.list_attachments(&self.session_id)?.is_empty();
+        let total = ReportSection::ALL.len() - usize::from(!has_evidence);
+        self.emit_report_section(tone, ReportSection::Conclusion, "", total);
+        self.emit_report_section(tone, ReportSection::Facts, &facts_summary, total);
+        self.emit_report_section(tone, ReportSection::ProcessPath, PROCESS_PATH, total);
+        self.emit_report_section(tone, ReportSection::Disclaimer, &disclaimer, total);
+
+        let drafted: &[DraftSection] = match &kept {
+            Some(_) => &[DraftSection::LegalAnalysis],
+            None => &PARALLEL_DRAFT_SECTIONS,
+        };
+        let emit = |section: DraftSection, content: &str| {
+            if !content.is_empty() || section != DraftSection::Evidence {
+                self.emit_report_section(tone, section.report_section(), content, total);
+            }
+        };
+        let sections = run_bounded(drafted, MAX_PARALLEL_SECTIONS, |section| {
+            let content = self.draft_section(*section, &tool_ctx)?;
+            emit(*section, &content);
+            Ok(content)
+        });
+        let mut legal_analysis = String::new();
+        let (mut evidence_summary, mut venue, mut risk_message) = match kept {
+            Some(kept) => {
+                emit(DraftSection::Evidence, &kept.evidence_summary);
+                emit(DraftSection::Venue, &kept.venue);
+                emit(DraftSection::RiskNotice, &kept.risk_notice);
+                (kept.evidence_summary, kept.venue, kept.risk_notice)
+            }
+            None => Default::default(),
+        };
+        for (section, content) in drafted.iter().zip(sections) {
+            let content = content?;
+            match section {
+                DraftSection::LegalAnalysis => legal_analysis = content,
+                DraftSection::Evidence => evidence_summary = content,
+                DraftSection::Venue => venue = content,
+                DraftSection::RiskNotice => risk_message = content,
+            }
+        }
+
+        // The event carries the reason in the session's language; the
+        // report is composed in Chinese and translated afterwards.
+        let (confidence, confidence_line) = {
+            let sources = self
+                .verified_sources
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            let facts = case_facts(&self.storage, &self.session_id, &self.scenario)?;
+            let metadata = self
+                .storage
+                .get_session(&self.session_id)?
+                .map(|session| session.metadata)
+                .unwrap_or_default();
+            let today = time_zone.date(self.clock.now());
+            let assess = |copy: &CopyCatalog| {
+                assess_conclusion(&intake.questions, &facts, &metadata, &sources, today, copy)
+            };
+            let report_copy = catalog::report_copy(&self.storage);
+            (
+                assess(&self.copy()),
+                assess(&report_copy).render(&report_copy),
+            )
+        };
+        self.trace("confidence", json!(confidence));
+        // Sent first without it; the sources were not known yet.
+        self.emit_report_section(tone, ReportSection::Conclusion, &confidence_line, total);
+        *self
+            .conclusion_confidence
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(confidence);
+
+        let draft_report = build_report(
+            tone,
+            &ReportContent {
+                confidence: &confidence_line,
+                facts_summary: &facts_summary,
+                evidence_summary: &evidence_summary,
+                legal_analysis: &legal_analysis,
+                process_path: PROCESS_PATH,
+                venue: &venue,
+                risk_notice: &risk_message,
+                disclaimer: &disclaimer,
+            },
+        );
+        let draft_report = self.apply_style_pass(tone, draft_report);
+        self.record_intermediate(|intermediate| intermediate.draft = Some(draft_report.clone()));
+        self.pause_after(AgentPhase::Draft)?;
+
+        self.enter_phase(AgentPhase::Review);
+        self.emit_progress(ReportProgress::reviewing(total));
+
+        let (draft_report, coverage) = {
+            let sources = self
+                .verified_sources
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            enforce_citations(
+                &draft_report,
+                &sources,
+                &self.copy().text(CopyKey::CitationHedge),
+            )
+        };
+        self.trace("citations", json!(coverage));
+        *self
+            .citation_coverage
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(coverage);
+
+        let user_text = user_words(&self.storage, &self.session_id, &self.scenario)?;
+        let safety_value = self.execute_tool_with_permission(
+            "check_safety",
+            json!({"content": draft_report, "user_text": user_text}),
+            &tool_ctx,
+        )?;
+        let fallback_modified_content = safety_value
+            .get("modified_content")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_owned();
+        let safety_result = serde_json::from_value::<SafetyCheckResult>(safety_value).unwrap_or(
+            SafetyCheckResult {
+                modified_content: fallback_modified_content,
+                issues: Vec::new(),
+                has_critical: false,
+                edits: Vec::new(),
+            },
+        );
+
+        self.trace(
+            "safety",
+            json!({
+                "issue_count": safety_result.issues.len(),
+                "has_critical": safety_result.has_critical,
+                "rules": safety_result
+                    .issues
+                    .iter()
+                    .map(|issue| issue.rule_name.as_str())
+                    .collect::<Vec<_>>()
+            }),
+        );
+        for issue in &safety_result.issues {
+            self.storage.record_safety_incident(
+                &self.session_id,
+                Some(&self.task_id),
+                &issue.rule_name,
+                &issue.category,
+                issue.severity.as_str(),
+                &issue.context,
+            )?;
+        }
+
+        let critical_count = safety_result
+            .issues
+            .iter()
+            .filter(|issue| issue.severity == Severity::Critical && !issue.quoted)
+            .count();
+        let mut final_report = safety_result.modified_content;
+        let mut edits = safety_result.edits;
+        if safety_result.has_critical {
+            let reviewed = final_report;
+            final_report = self.copy().render(
+                CopyKey::ReviewIntercepted,
+                &[
+                    ("count", &critical_count.to_string()),
+                    ("report", &reviewed),
+                ],
+            );
+            // Keep edit offsets pointing into the report under the notice.
+            let notice = final_report
+                .find(&reviewed)
+                .map_or(0, |at| final_report[..at].chars().count() as u32);
+            for edit in &mut edits {
+                edit.offset += notice;
+            }
+        }
+
+        if !safety_result.issues.is_empty() {
+            let task_id = self.task_id.clone();
+            let session_id = self.session_id.clone();
+            let issue_count = safety_result.issues.len();
+            let edits = edits.clone();
+            if safety_result.has_critical {
+                self.events.emit(&ReviewIntercepted {
+                    task_id,
+                    session_id,
+                    issue_count,
+                    critical_count,
+                    edits,
+                });
+            } else {
+                self.events.emit(&ReviewAdjusted {
+                    task_id,
+                    session_id,
+                    issue_count,
+                    critical_count,
+                    edits,
+                });
+            }
+        }
+
+        let final_report = self.explain_terms(final_report, &tool_ctx)?;
+        self.record_intermediate(|intermediate| intermediate.draft = Some(final_report.clone()));
+        self.pause_after(AgentPhase::Review)?;
+
+        self.guard_not_cancelled()?;
+        let gated = self.require_report_review;
+        let message = self.reply(
+            &final_report,
+            if gated {
+                PENDING_REVIEW_PHASE
+            } else {
+                "review"
+            },
+        )?;
+        self.record_provenance(&message.id)?;
+        record_drafted_sections(
+            &self.storage,
+            &self.session_id,
+            &DraftedSections {
+                facts_summary,
+                evidence_summary,
+                venue,
+                risk_notice: risk_message,
+            },
+        )?;
+        record_report_review(
+            &self.storage,
+            &self.session_id,
+            &ReportReview {
+                message_id: message.id.clone(),
+                edits,
+            },
+        )?;
+        let kb = self.retrieval.knowledge_info()?;
+        record_report_snapshot(
+            &self.storage,
+            &self.session_id,
+            &ReportKbSnapshot {
+                message_id: message.id.clone(),
+                generated_at: message.created_at,
+                kb_updated_at: kb.updated_at,
+                kb_file_count: kb.file_count,
+                model: self
+                    .configured_model()
+                    .map(|connector| connector.model_name().to_owned()),
+            },
+        )?;
+        // A withheld report is indexed once it is released.
+        if !gated {
+            self.storage
+                .index_case_text(&self.session_id, "report", &final_report)?;
+        }
+        let translation = self.translate_report(
+            &final_report,
+            if gated {
+                PENDING_TRANSLATION_PHASE
+            } else {
+                TRANSLATION_PHASE
+            },
+        )?;
+        self.analytics.record(AnalyticsEvent::ReportGenerated);
+        self.emit_progress(ReportProgress::done(total));
+
+        if gated {
+            review_gate::submit(
+                &self.storage,
+                &self.session_id,
+                &message,
+                translation.as_ref(),
+            )?;
+            self.events.emit(&ReportPendingReview {
+                task_id: self.task_id.clone(),
+                session_id: self.session_id.clone(),
+                message_id: message.id,
+            });
+            self.emit_completed(None, Some(self.copy().text(CopyKey::ReportPendingReview)));
+            return Ok(());
+        }
+        self.offer_open_questions()?;
+
+        self.emit_completed(
+            Some(translation.map_or(final_report, |translation| translation.content)),
+            None,
+        );
+
+        Ok(())
+    }
+
+    /// Emit the draft's cost estimate. Returns `true` when the session
+    /// wants drafts confirmed and this one is not yet: the estimate is posted
+    /// and the task ends until `confirm_draft`. Drafts without model calls
+    /// cost nothing and never wait.
+    fn stop_at_estimate(&self) -> CoreResult<bool> {
+        let estimate = self.draft_estimate()?;
+        let awaiting = estimate.model_calls > 0
+            && estimate::confirmation_required(&self.storage, &self.session_id)?
+            && !estimate::take_confirmation(&self.storage, &self.session_id)?;
+        self.trace(
+            "estimate",
+            json!({
+                "model_calls": estimate.model_calls,
+                "prompt_tokens": estimate.prompt_tokens,
+                "completion_tokens": estimate.completion_tokens,
+                "duration_ms": estimate.duration_ms,
+                "from_history": estimate.from_history,
+                "awaiting_confirmation": awaiting,
+            }),
+        );
+        self.events.emit(&DraftEstimated {
+            task_id: self.task_id.clone(),
+            session_id: self.session_id.clone(),
+            model_calls: estimate.model_calls,
+            prompt_tokens: estimate.prompt_tokens,
+            completion_tokens: estimate.completion_tokens,
+            duration_ms: estimate.duration_ms,
+            from_history: estimate.from_history,
+            awaiting_confirmation: awaiting,
+        });
+        if !awaiting {
+            return Ok(false);
+        }
+
+        estimate::await_confirmation(&self.storage, &self.session_id)?;
+        let text = self.copy().render(
+            CopyKey::DraftEstimate,
+            &[
+                ("calls", &estimate.model_calls.to_string()),
+                (
+                    "tokens",
+                    &(estimate.prompt_tokens + estimate.completion_tokens).to_string(),
+                ),
+                ("seconds", &estimate.duration_ms.div_ceil(1_000).to_string()),
+            ],
+        );
+        self.reply(&text, "plan")?;
+        self.emit_completed(None, Some(text));
+        Ok(true)
+    }
+
+    fn draft_estimate(&self) -> CoreResult<DraftEstimate> {
+        let connector = self.configured_model();
+        let unsummarized_evidence = if connector.is_some() {
+            self.storage
+                .list_attachments(&self.session_id)?
+                .into_iter()
+                .filter(|attachment| attachment.summary.is_none())
+                .map(|attachment| attachment.extracted_text)
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let plan = DraftPlan {
+            model_configured: connector.is_some(),
+            unsummarized_evidence,
+            style_pass: report_tone(&self.storage, &self.session_id)?
+                .style_instruction()
+                .is_some(),
+            translated: !language::is_chinese(&self.locale),
+        };
+        let usage = connector
+            .map(|connector| connector.phase_usage())
+            .unwrap_or_default();
+        Ok(estimate_draft(
+            &plan,
+            &usage,
+            &self.phase_timings.snapshot(),
+        ))
+    }
+
+    /// Save the user's message as the answer to the open question at
+    /// `index`, asked again with `reask_open_question`.
+    fn answer_open_question(&self, intake: &agent::IntakeState, index: usize) -> CoreResult<()> {
+        let Some(question) = intake.questions.get(index) else {
+            return Ok(());
+        };
+        let rejected = question
+            .validator
+            .as_ref()
+            .is_some_and(|validator| !validator.accepts(&self.user_content));
+        self.storage.with_tx(|| {
+            save_answer(
+                &self.storage,
+                &self.session_id,
+                index,
+                &self.user_content,
+                Some(&self.user_message_id),
+            )?;
+            if rejected {
+                mark_answer_unconfirmed(&self.storage, &self.session_id, index)?;
+            }
+            Ok(())
+        })?;
+        self.events.emit(&FactsUpdated {
+            session_id: self.session_id.clone(),
+            field: question.field.clone(),
+        });
+        Ok(())
+    }
+
+    /// After a follow-up report, offer to ask the open intake questions
+    /// again.
+    fn offer_open_questions(&self) -> CoreResult<()> {
+        if !self.followup {
+            return Ok(());
+        }
+        let open = open_questions(&self.storage, &self.session_id, &self.scenario)?;
+        if open.is_empty() {
+            return Ok(());
+        }
+        let separator = if language::is_chinese(&self.locale) {
+            "、"
+        } else {
+            ", "
+        };
+        let labels = open
+            .iter()
+            .map(|question| question.label.as_str())
+            .collect::<Vec<_>>()
+            .join(separator);
+        let text = self.copy().render(
+            CopyKey::FollowupOpenQuestions,
+            &[("count", &open.len().to_string()), ("labels", &labels)],
+        );
+        self.reply(&text, OPEN_QUESTIONS_PHASE)?;
+        self.events.emit(&OpenQuestionsOffered {
+            task_id: self.task_id.clone(),
+            session_id: self.session_id.clone(),
+            fields: open.into_iter().map(|question| question.field).collect(),
+        });
+        Ok(())
+    }
+
+    /// Record which facts and KB chunks the report `message_id` was
+    /// written from.
+    fn record_provenance(&self, message_id: &str) -> CoreResult<()> {
+        let answered = case_facts(&self.storage, &self.session_id, &self.scenario)?
+            .into_iter()
+            .filter(|fact| fact.value.is_some())
+            .map(|fact| fact.field)
+            .collect::<Vec<_>>();
+        let fact_statements = intake_questions_for_scenario(&self.scenario)
+            .into_iter()
+            .zip(collect_facts(
+                &self.storage,
+                &self.session_id,
+                &self.scenario,
+            )?)
+            .filter(|(question, _)| answered.contains(&question.field))
+            .map(|(question, (label, answer))| StatementProvenance {
+                text: fact_line(&label, &answer),
+                fact_fields: vec![question.field],
+                chunk_ids: Vec::new(),
+            })
+            .collect();
+        let legal = self
+            .legal_provenance
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .take()
+            .unwrap_or_else(|| {
+                SectionProvenance::new(ReportSection::LegalAnalysis, Vec::new(), Vec::new())
+            });
+        let venue = answered
+            .iter()
+            .filter(|field| *field == "region")
+            .cloned()
+            .collect();
+        let provenance = ReportProvenance::new(
+            message_id.to_owned(),
+            vec![
+                SectionProvenance::new(ReportSection::Facts, answered, Vec::new())
+                    .with_statements(fact_statements),
+                legal,
+                SectionProvenance::new(ReportSection::Venue, venue, Vec::new()),
+            ],
+        );
+        record_report_provenance(&self.storage, &self.session_id, &provenance)
+    }
+
+    /// The user's message as text to search the Chinese KB with. In other
+    /// languages it is translated together with the intake answers, by the
+    /// model when one is configured and with `language::fallback_query`
+    /// otherwise.
+    fn retrieval_text(&self, facts: &[CaseFact]) -> String {
+        if language::is_chinese(&self.locale) {
+            return self.user_content.clone();
+        }
+        let text = std::iter::once(self.user_content.as_str())
+            .chain(facts.iter().filter_map(|fact| fact.value.as_deref()))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let instruction = format!(
+            "你是{}。把用户的话翻译成简体中文，用于检索中文法律知识库。保留金额、日期和地名，只输出译文。",
+            assistant_role(&self.scenario)
+        );
+        let translated = self.translate(AgentPhase::Plan, &instruction, &text, false);
+        let (method, query) = match translated {
+            Some(query) => ("model", query),
+            None => ("glossary", language::fallback_query(&self.scenario, &text)),
+        };
+        self.trace(
+            "query_translation",
+            json!({"language": self.locale, "method": method, "query": query}),
+        );
+        query
+    }
+
+    /// `text` rewritten by the model under `instruction`, or `None` without
+    /// a model or when the call fails. `for_user` adds the persona, for text
+    /// the user reads.
+    fn translate(
+        &self,
+        phase: AgentPhase,
+        instruction: &str,
+        text: &str,
+        for_user: bool,
+    ) -> Option<String> {
+        let connector = self.configured_model()?;
+        let _permit = self.model_scheduler.acquire(
+            &self.task_id,
+            phase.call_priority(self.priority),
+            || self.control.is_cancelled(),
+        )?;
+        let mut messages = vec![
+            model::ChatMessage {
+                role: "system".to_owned(),
+                content: instruction.to_owned(),
+            },
+            model::ChatMessage {
+                role: "user".to_owned(),
+                content: text.to_owned(),
+            },
+        ];
+        if for_user {
+            persona::apply(&self.storage, &mut messages);
+        }
+        match self.complete(&connector, self.model_phase(phase), &messages) {
+            Ok(translated) if !translated.trim().is_empty() => Some(translated.trim().to_owned()),
+            Ok(_) => None,
+            Err(err) => {
+                tracing::warn!("translation failed: {err}");
+                None
+            }
+        }
+    }
+
+    /// For sessions in another language, the reviewed report translated by
+    /// the model and stored after it in `phase`. The Chinese report stays the
+    /// record: citations, review edits and provenance refer to it.
+    ///
+    /// The safety rules only read Chinese, so the translation is read back
+    /// into Chinese and released only when that reading has nothing
+    /// critical; otherwise, or when it cannot be read back, the user keeps
+    /// the Chinese report.
+    fn translate_report(&self, report: &str, phase: &str) -> CoreResult<Option<Message>> {
+        if language::is_chinese(&self.locale) {
+            return Ok(None);
+        }
+        let role = assistant_role(&self.scenario);
+        let instruction = format!(
+            "你是{role}。把下面的法律咨询报告完整翻译成{}。【】章节标题、引用的文件路径、金额和日期保持原样，不增删内容，只输出译文。",
+            language::language_name(&self.locale)
+        );
+        let Some(translated) = self.translate(AgentPhase::Review, &instruction, report, true)
+        else {
+            return Ok(None);
+        };
+        if !translated.contains("【免责声明】") {
+            tracing::warn!("report translation dropped sections; keeping the Chinese report");
+            return Ok(None);
+        }
+        let back_instruction = format!(
+            "你是{role}。把下面的法律咨询报告逐句翻译成简体中文，不润色、不增删内容，只输出译文。"
+        );
+        let Some(read_back) =
+            self.translate(AgentPhase::Review, &back_instruction, &translated, false)
+        else {
+            tracing::warn!("report translation could not be checked; keeping the Chinese report");
+            return Ok(None);
+        };
+        let user_text = user_words(&self.storage, &self.session_id, &self.scenario)?;
+        let checked = self.safety.check_quoting(
+            &read_back,
+            &user_text.iter().map(String::as_str).collect::<Vec<_>>(),
+        );
+        self.trace(
+            "report_translation",
+            json!({
+                "language": self.locale,
+                "chars": translated.chars().count(),
+                "has_critical": checked.has_critical,
+                "rules": checked
+                    .issues
+                    .iter()
+                    .map(|issue| issue.rule_name.as_str())
+                    .collect::<Vec<_>>()
+            }),
+        );
+        if checked.has_critical {
+            for issue in checked.issues.iter().filter(|issue| !issue.quoted) {
+                self.storage.record_safety_incident(
+                    &self.session_id,
+                    Some(&self.task_id),
+                    &issue.rule_name,
+                    &issue.category,
+                    issue.severity.as_str(),
+                    &issue.context,
+                )?;
+            }
+            tracing::warn!(
+                "report translation failed the safety check; keeping the Chinese report"
+            );
+            return Ok(None);
+        }
+        self.reply(&translated, phase).map(Some)
+    }
+
+    /// With a model configured, rewrite the templated draft in the session's
+    /// tone and, under review, as the reviewer asked of the previous draft.
+    /// Any failure keeps the template output; the review phase still runs
+    /// on whatever comes back.
+    fn apply_style_pass(&self, tone: ReportTone, draft: String) -> String {
+        let comment = if self.require_report_review {
+            review_gate::changes_comment(&self.storage, &self.session_id).unwrap_or_else(|err| {
+                tracing::warn!("review comment lookup failed: {err}");
+                None
+            })
+        } else {
+            None
+        };
+        let reworked = comment.is_some();
+        let system = match (tone.style_instruction(), comment) {
+            (None, None) => return draft,
+            (Some(instruction), None) => format!(
+                "请在不改变事实、金额、日期、引用和【】章节标题的前提下，把用户给出的法律咨询报告改写为{instruction}。只输出改写后的完整报告。"
+            ),
+            (instruction, Some(comment)) => format!(
+                "审核人员对上一版报告提出了修改意见：{comment}\n请在不改变事实、金额、日期、引用和【】章节标题的前提下，按意见修改用户给出的法律咨询报告{}。只输出修改后的完整报告。",
+                instruction
+                    .map(|instruction| format!("，并改写为{instruction}"))
+                    .unwrap_or_default()
+            ),
+        };
+        let Some(connector) = self.configured_model() else {
+            return draft;
+        };
+        let Some(_permit) = self.model_scheduler.acquire(
+            &self.task_id,
+            AgentPhase::Draft.call_priority(self.priority),
+            || self.control.is_cancelled(),
+        ) else {
+            return draft;
+        };
+
+        let mut messages = vec![
+            model::ChatMessage {
+                role: "system".to_owned(),
+                content: system,
+            },
+            model::ChatMessage {
+                role: "user".to_owned(),
+                content: draft.clone(),
+            },
+        ];
+        persona::apply(&self.storage, &mut messages);
+
+        // Rework a reviewer asked for runs on the review route.
+        let phase = self.model_phase(if reworked {
+            AgentPhase::Review
+        } else {
+            AgentPhase::Draft
+        });
+        match self.complete(&connector, phase, &messages) {
+            Ok(styled) if !(styled.contains("【事实摘要】") && styled.contains("【免责声明】")) =>
+            {
+                tracing::warn!("style pass dropped report sections; using template output");
+                draft
+            }
+            Ok(styled) if !keeps_figures(&draft, &styled) => {
+                tracing::warn!("style pass changed amounts or dates; using template output");
+                draft
+            }
+            Ok(styled) => styled,
+            Err(err) => {
+                tracing::warn!("style pass failed: {err}");
+                draft
+            }
+        }
+    }
+
+    fn complete(
+        &self,
+        connector: &ModelConnector,
+        phase: ModelPhase,
+        messages: &[model::ChatMessage],
+    ) -> CoreResult<String> {
+        self.complete_capped(connector, phase, messages, None)
+    }
+
+    /// `complete` answering in at most `max_tokens`.
+    fn complete_capped(
+        &self,
+        connector: &ModelConnector,
+        phase: ModelPhase,
+        messages: &[model::ChatMessage],
+        max_tokens: Option<u32>,
+    ) -> CoreResult<String> {
+        if let Some(completion) = self.model_memo.get(phase, messages, max_tokens) {
+            self.trace(
+                "model_memo_hit",
+                json!({"phase": phase.as_str(), "chars": completion.chars().count()}),
+            );
+            return Ok(completion);
+        }
+        self.storage.faults().model()?;
+        let completion =
+            RUNTIME.block_on(connector.chat_completion_capped(phase, messages, max_tokens))?;
+        self.model_memo
+            .insert(phase, messages, max_tokens, &completion);
+        Ok(completion)
+    }
+
+    /// Route for model calls made in `phase`: follow-up tasks have their own.
+    fn model_phase(&self, phase: AgentPhase) -> ModelPhase {
+        if self.followup {
+            ModelPhase::Followup
+        } else {
+            phase.model_phase()
+        }
+    }
+
+    fn configured_model(&self) -> Option<ModelConnector> {
+        self.model_connector.read().ok()?.clone()
+    }
+
+    /// Token budget for the legal analysis' retrieved snippets, a share of
+    /// the model's prompt limit when one applies.
+    fn legal_context_budget(&self) -> u32 {
+        match self
+            .configured_model()
+            .map(|connector| connector.max_prompt_tokens())
+        {
+            Some(limit) if limit > 0 => limit / LEGAL_CONTEXT_PROMPT_SHARE,
+            _ => LEGAL_CONTEXT_TOKEN_BUDGET,
+        }
+    }
+
+    /// Venue text for the region the user gave at intake, or the scenario's
+    /// general advice on where to go.
+    fn lookup_venue(&self, tool_ctx: &ToolContext) -> CoreResult<String> {
+        let region = case_facts(&self.storage, &self.session_id, &self.scenario)?
+            .into_iter()
+            .find(|fact| fact.field == "region")
+            .and_then(|fact| fact.value)
+            .unwrap_or_default();
+        let venue = self.execute_tool_or_fallback(
+            "venue_lookup",
+            json!({"region": region, "scenario": self.scenario}),
+            tool_ctx,
+            json!({}),
+        )?;
+        Ok(venue
+            .get("message")
+            .and_then(Value::as_str)
+            .unwrap_or_else(|| venue_fallback(&self.scenario))
+            .to_owned())
+    }
+
+    /// Append 【名词解释】 for the legal terms the report uses, unless the
+    /// session turned the glossary off.
+    fn explain_terms(&self, report: String, tool_ctx: &ToolContext) -> CoreResult<String> {
+        if !glossary_enabled(&self.storage, &self.session_id)? {
+            return Ok(report);
+        }
+        let found = self.execute_tool_or_fallback(
+            "glossary_lookup",
+            json!({"text": report}),
+            tool_ctx,
+            json!({"terms": []}),
+        )?;
+        let entries = found
+            .get("terms")
+            .cloned()
+            .and_then(|terms| serde_json::from_value::<Vec<GlossaryEntry>>(terms).ok())
+            .unwrap_or_default();
+        self.trace(
+            "glossary",
+            json!({"terms": entries.iter().map(|entry| entry.term.as_str()).collect::<Vec<_>>()}),
+        );
+        Ok(append_glossary(report, &entries))
+    }
+
+    /// The facts section: the intake answers summarized, after the case
+    /// details. Indexed for cross-session search.
+    fn summarize_facts(&self, tool_ctx: &ToolContext) -> CoreResult<String> {
+        let facts = collect_facts(&self.storage, &self.session_id, &self.scenario)?;
+        let facts_map: serde_json::Map<String, Value> = facts
+            .iter()
+            .map(|(k, v)| (k.clone(), Value::String(v.clone())))
+            .collect();
+        let summary_value = self.execute_tool_or_fallback(
+            "summarize_facts",
+            json!({"facts": facts_map}),
+            tool_ctx,
+            json!({}),
+        )?;
+        let facts_summary = summary_value
+            .get("summary")
+            .and_then(Value::as_str)
+            .map(ToOwned::to_owned)
+            .unwrap_or_else(|| format_facts_summary(&facts));
+        let case_details = self
+            .storage
+            .get_session(&self.session_id)?
+            .map(|session| format_case_metadata(&session.metadata))
+            .unwrap_or_default();
+        let facts_summary = if case_details.is_empty() {
+            facts_summary
+        } else {
+            format!("{case_details}\n{facts_summary}")
+        };
+        self.storage
+            .index_case_text(&self.session_id, "facts", &facts_summary)?;
+        Ok(facts_summary)
+    }
+
+    /// Digest every attachment of the session for the evidence section. With
+    /// a model configured, attachments without a summary are summarized by
+    /// the model first; `evidence_summary` covers the rest heuristically.
+    fn summarize_evidence(&self, tool_ctx: &ToolContext) -> CoreResult<String> {
+        let attachments = self.storage.list_attachments(&self.session_id)?;
+        if attachments.is_empty() {
+            return Ok(String::new());
+        }
+
+        if let Some(connector) = self.configured_model() {
+            for attachment in attachments.iter().filter(|a| a.summary.is_none()) {
+                let Some(_permit) = self.model_scheduler.acquire(
+                    &self.task_id,
+                    AgentPhase::Draft.call_priority(self.priority),
+                    || self.control.is_cancelled(),
+                ) else {
+                    break;
+                };
+                let messages = vec![
+                    model::ChatMessage {
+                        role: "system".to_owned(),
+                        content: "下面是用户上传的一份证据材料的识别文字。请用一句话概括其中与劳动争议有关的关键信息（时间、金额、异常情况），不要推测文字中没有的内容。".to_owned(),
+                    },
+                    model::ChatMessage {
+                        role: "user".to_owned(),
+                        content: attachment.extracted_text.clone(),
+                    },
+                ];
+                let phase = self.model_phase(AgentPhase::Draft);
+                match self.complete(&connector, phase, &messages) {
+                    Ok(summary) if !summary.trim().is_empty() => {
+                        self.storage
+                            .set_attachment_summary(&attachment.id, summary.trim())?;
+                    }
+                    Ok(_) => {}
+                    Err(err) => tracing::warn!("evidence summary failed: {err}"),
+                }
+            }
+        }
+
+        let digests = self.execute_tool_or_fallback(
+            "evidence_summary",
+            json!({"session_id": self.session_id}),
+            tool_ctx,
+            json!([]),
+        )?;
+        Ok(digests
+            .as_array()
+            .map(|digests| {
+                digests
+                    .iter()
+                    .map(|digest| {
+                        format!(
+                            "- {}：{}",
+                            digest["file_name"].as_str().unwrap_or_default(),
+                            digest["summary"].as_str().unwrap_or_default()
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            })
+            .unwrap_or_default())
+    }
+
+    /// Generate one facts-independent report section. Called concurrently for
+    /// every entry of `PARALLEL_DRAFT_SECTIONS`.
+    fn draft_section(&self, section: DraftSection, tool_ctx: &ToolContext) -> CoreResult<String> {
+        match section {
+            DraftSection::LegalAnalysis => {
+                let facts = case_facts(&self.storage, &self.session_id, &self.scenario)?;
+                let plan = plan_queries(&self.scenario, &facts, &self.retrieval_text(&facts));
+                let planned_fields = facts
+                    .iter()
+                    .filter(|fact| fact.value.is_some())
+                    .map(|fact| fact.field.clone())
+                    .filter(|field| {
+                        plan.iter()
+                            .any(|query| query.fields.contains(&field.as_str()))
+                    })
+                    .collect::<Vec<_>>();
+                self.trace(
+                    "retrieval_plan",
+                    json!(plan
+                        .iter()
+                        .map(|query| json!({"topic": query.topic, "query": query.query}))
+                        .collect::<Vec<_>>()),
+                );
+                let queries = plan
+                    .into_iter()
+                    .map(|query| query.query)
+                    .collect::<Vec<_>>();
+                let today = session_time_zone(&self.storage, &self.session_id, &self.time_zone)?
+                    .date(self.clock.now());
+                let dates = self
+                    .storage
+                    .get_session(&self.session_id)?
+                    .and_then(|session| {
+                        case_dates(&session.metadata, &facts, &self.scenario, today)
+                    });
+
+                let search_value = self.execute_tool_or_fallback(
+                    "kb_search",
+                    json!({
+                        "queries": &queries,
+                        "scenario": self.scenario,
+                        "top_k": queries.len() * RESULTS_PER_QUERY,
+                        "case_dates": dates
+                    }),
+                    tool_ctx,
+                    json!([]),
+                )?;
+                let search_results: Vec<SearchResult> = serde_json::from_value(search_value)
+                    .map_err(|e| CoreError::Unknown(format!("parse search result failed: {e}")))?;
+                // The analysis ends up in the style-pass prompt; keep the
+                // retrieved context within its share of the budget.
+                let search_results =
+                    trim_to_token_budget(search_results, self.legal_context_budget());
+                self.record_intermediate(|intermediate| {
+                    intermediate.retrieved_chunks = search_results.clone();
+                });
+                *self
+                    .verified_sources
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner()) =
+                    search_results.iter().map(VerifiedSource::from).collect();
+
+                let citation_sources = search_results
+                    .iter()
+                    .take(3)
+                    .map(|item| {
+                        json!({
+                            "file_path": item.file_path,
+                            "line_start": item.line_start,
+                            "line_end": item.line_end,
+                            "chunk_id": item.chunk_id,
+                            "anchor": item.anchor,
+                            "effective_date": item.effective_date,
+                            "repeal_date": item.repeal_date
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                let citation_value = self.execute_tool_or_fallback(
+                    "cite",
+                    json!({"sources": citation_sources}),
+                    tool_ctx,
+                    json!({}),
+                )?;
+                let chunk_ids = search_results
+                    .iter()
+                    .map(|item| item.chunk_id.clone())
+                    .filter(|chunk_id| !chunk_id.is_empty())
+                    .collect::<Vec<_>>();
+                // The chunks `cite` linked; a skipped `cite` leaves the
+                // report without citations.
+                let cited = citation_value
+                    .get("links")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|link| link.get("chunk_id").and_then(Value::as_str))
+                    .filter(|chunk_id| !chunk_id.is_empty())
+                    .map(ToOwned::to_owned)
+                    .collect::<Vec<_>>();
+                self.analytics.record_kb_usage(&chunk_ids, &cited);
+                self.query_log
+                    .record(&queries.join("\n"), &self.scenario, chunk_ids.len(), &cited);
+                *self
+                    .legal_provenance
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(
+                    SectionProvenance::new(ReportSection::LegalAnalysis, planned_fields, cited)
+                        .with_statements(
+                            legal_references(&search_results)
+                                .into_iter()
+                                .zip(&search_results)
+                                .filter(|(_, item)| !item.chunk_id.is_empty())
+                                .map(|(text, item)| StatementProvenance {
+                                    text,
+                                    fact_fields: Vec::new(),
+                                    chunk_ids: vec![item.chunk_id.clone()],
+                                })
+                                .collect(),
+                        ),
+                );
+                let citations = citation_value
+                    .get("citations")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+
+                Ok(format!(
+                    "{}\n\n【引用】\n{}",
+                    format_legal_analysis(&catalog::report_copy(&self.storage), &search_results),
+                    citations
+                ))
+            }
+            DraftSection::Evidence => self.summarize_evidence(tool_ctx),
+            DraftSection::Venue => self.lookup_venue(tool_ctx),
+            DraftSection::RiskNotice => {
+                let risk_value = self.execute_tool_or_fallback(
+                    "suggest_escalation",
+                    json!({"content": self.user_content}),
+                    tool_ctx,
+                    json!({}),
+                )?;
+                let message = risk_value
+                    .get("message")
+                    .and_then(Value::as_str)
+                    .unwrap_or(DEFAULT_RISK_NOTICE)
+                    .to_owned();
+                if risk_value.get("need_escalation").and_then(Value::as_bool) == Some(true) {
+                    self.events.emit(&EscalationRequired {
+                        task_id: self.task_id.clone(),
+                        session_id: self.session_id.clone(),
+                        message: message.clone(),
+                    });
+                }
+                Ok(message)
+            }
+        }
+    }
+
+    fn handle_intake(&self, iteration: u32, state: agent::IntakeState) -> CoreResult<()> {
+        let tool_ctx = ToolContext {
+            retrieval: self.retrieval.clone(),
+            safety: self.safety.clone(),
+            storage: self.storage.clone(),
+            region: self.region,
+            profile_id: self.profile_id.clone(),
+        };
+
+        if state.current_index == 0 {
+            let first = self.execute_tool_with_permission(
+                "ask_user",
+                json!({"scenario": self.scenario, "index": 0, "locale": self.locale}),
+                &tool_ctx,
+            )?;
+            start_intake(&self.storage, &self.session_id)?;
+            self.analytics.record(AnalyticsEvent::IntakeStarted);
+
+            let copy = self.copy();
+            let question = first
+                .get("question")
+                .and_then(Value::as_str)
+                .map(ToOwned::to_owned)
+                .unwrap_or_else(|| copy.text(CopyKey::IntakeFallbackQuestion));
+            let total = first.get("total").and_then(Value::as_u64).unwrap_or(1);
+            let text = copy.render(
+                CopyKey::IntakeIntro,
+                &[("total", &total.to_string()), ("question", &question)],
+            );
+
+            self.reply(&text, "draft")?;
+
+            self.events.emit(&IntakeProgress {
+                task_id: self.task_id.clone(),
+                current: 1,
+                total,
+                question,
+                reask: false,
+            });
+            self.emit_completed(None, Some(text));
+            return Ok(());
+        }
+
+        let answered_index = state.current_index.saturating_sub(1);
+        let invalid_validator = state
+            .questions
+            .get(answered_index)
+            .and_then(|question| question.validator.as_ref())
+            .filter(|validator| !validator.accepts(&self.user_content));
+        if let Some(validator) = invalid_validator {
+            if note_invalid_answer(&self.storage, &self.session_id, answered_index)? {
+                let question = &state.questions[answered_index].question_in(&self.locale);
+                let current = answered_index as u64 + 1;
+                let total = state.questions.len() as u64;
+                let text = self.copy().render(
+                    CopyKey::IntakeReask,
+                    &[
+                        ("hint", &validator.hint(&self.locale)),
+                        ("current", &current.to_string()),
+                        ("total", &total.to_string()),
+                        ("question", question),
+                    ],
+                );
+                self.reply(&text, "draft")?;
+                self.events.emit(&IntakeProgress {
+                    task_id: self.task_id.clone(),
+                    current,
+                    total,
+                    question: question.clone(),
+                    reask: true,
+                });
+                self.emit_completed(None, Some(text));
+                return Ok(());
+            }
+        }
+        self.storage.with_tx(|| {
+            save_answer(
+                &self.storage,
+                &self.session_id,
+                answered_index,
+                &self.user_content,
+                Some(&self.user_message_id),
+            )?;
+            if invalid_validator.is_some() {
+                mark_answer_unconfirmed(&self.storage, &self.session_id, answered_index)?;
+            }
+            Ok(())
+        })?;
+
+        if state.current_index < state.questions.len() {
+            let next_value = self.execute_tool_with_permission(
+                "ask_user",
+                json!({
+                    "scenario": self.scenario,
+                    "index": state.current_index,
+                    "locale": self.locale,
+                }),
+                &tool_ctx,
+            )?;
+            let copy = self.copy();
+            let question = next_value
+                .get("question")
+                .and_then(Value::as_str)
+                .map(ToOwned::to_owned)
+                .unwrap_or_else(|| copy.text(CopyKey::IntakeFallbackNextQuestion));
+            let current = next_value
+                .get("current")
+                .and_then(Value::as_u64)
+                .unwrap_or((state.current_index + 1) as u64);
+            let total = next_value
+                .get("total")
+                .and_then(Value::as_u64)
+                .unwrap_or(state.questions.len() as u64);
+
+            advance_intake_index(&self.storage, &self.session_id, state.current_index + 1)?;
+
+            let answered = state.questions[answered_index].question_in(&self.locale);
+            let ack = self.intake_acknowledgement(answered_index, &answered, &self.user_content);
+            let text = copy.render(
+                CopyKey::IntakeNext,
+                &[
+                    ("ack", &ack),
+                    ("current", &current.to_string()),
+                    ("total", &total.to_string()),
+                    ("question", &question),
+                ],
+            );
+            self.reply(&text, "draft")?;
+
+            self.events.emit(&IntakeProgress {
+                task_id: self.task_id.clone(),
+                current,
+                total,
+                question,
+                reask: false,
+            });
+            self.emit_completed(None, Some(text));
+            return Ok(());
+        }
+
+        mark_intake_done(&self.storage, &self.session_id)?;
+        self.analytics.record(AnalyticsEvent::IntakeCompleted);
+        self.events.emit(&IntakeDone {
+            task_id: self.task_id.clone(),
+            session_id: self.session_id.clone(),
+        });
+        self.run_with_iteration(iteration + 1)
+    }
+
+    fn execute_tool_with_permission(
+        &self,
+        tool_name: &str,
+        args: Value,
+        ctx: &ToolContext,
+    ) -> CoreResult<Value> {
+        match self.run_tool_with_permission(tool_name, args, ctx)? {
+            ToolRun::Ran(result) => Ok(result),
+            ToolRun::Skipped(reason) => Err(CoreError::Tool(format!(
+                "tool {tool_name} skipped: {reason}"
+            ))),
+        }
+    }
+
+    /// Like `execute_tool_with_permission`, but a call the host skipped
+    /// yields `fallback` so the task can finish in a degraded form.
+    fn execute_tool_or_fallback(
+        &self,
+        tool_name: &str,
+        args: Value,
+        ctx: &ToolContext,
+        fallback: Value,
+    ) -> CoreResult<Value> {
+        match self.run_tool_with_permission(tool_name, args, ctx)? {
+            ToolRun::Ran(result) => Ok(result),
+            ToolRun::Skipped(_) => Ok(fallback),
+        }
+    }
+
+    fn run_tool_with_permission(
+        &self,
+        tool_name: &str,
+        args: Value,
+        ctx: &ToolContext,
+    ) -> CoreResult<ToolRun> {
+        self.guard_not_cancelled()?;
+
+        let mut permission = resolve_permission(
+            &self.storage,
+            &self.permission_policy,
+            tool_name,
+            Some(&self.scenario),
+        )?;
+        let allow_all = self
+            .session_allow_all
+            .lock()
+            .map_err(|_| CoreError::InvalidState("session_allow_all lock poisoned".to_owned()))?
+            .contains(&self.session_id);
+        if allow_all && permission == "ask" {
+            permission = "allow".to_owned();
+        }
+
+        if permission == "deny" {
+            self.tools.stats().record_denial(tool_name);
+            self.tools.stats().persist_if_due(&self.storage);
+            return Err(CoreError::Tool(format!("tool {tool_name} is denied")));
+        }
+
+        if permission == "ask" {
+            let request_id = Uuid::new_v4().to_string();
+            let (tx, rx) = mpsc::channel::<ToolDecision>();
+
+            {
+                let mut pending_map = self.pending_tool_calls.lock().map_err(|_| {
+                    CoreError::InvalidState("pending_tool_calls lock poisoned".to_owned())
+                })?;
+                pending_map.insert(
+                    request_id.clone(),
+                    PendingToolCall {
+                        sender: tx,
+                        task_id: self.task_id.clone(),
+                        session_id: self.session_id.clone(),
+                        tool_name: tool_name.to_owned(),
+                        arguments: args.clone(),
+                        requested_at: self.clock.timestamp(),
+                    },
                 );
             }
 
-            emit_event_static(
-                &self.listeners,
-                "tool_call_request",
-                json!({
-                    "task_id": self.task_id,
-                    "request_id": request_id,
-                    "tool_name": tool_name,
-                    "arguments": args
+            self.events.emit(&ToolCallRequest {
+                task_id: self.task_id.clone(),
+                request_id: request_id.clone(),
+                tool_name: tool_name.to_owned(),
+                arguments: args.clone(),
+            });
+
+            let deadline = Instant::now() + TOOL_CALL_TIMEOUT;
+            let decision = loop {
+                if let Err(err) = self.guard_not_cancelled() {
+                    self.settle_pending_tool_call(&request_id, ToolCallOutcome::TaskCancelled);
+                    return Err(err);
+                }
+                if Instant::now() >= deadline {
+                    self.settle_pending_tool_call(&request_id, ToolCallOutcome::Expired);
+                    return Err(CoreError::Tool(format!(
+                        "approval for tool {tool_name} timed out"
+                    )));
+                }
+                match rx.recv_timeout(Duration::from_millis(300)) {
+                    Ok(resp) => break resp,
+                    Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                    Err(mpsc::RecvTimeoutError::Disconnected) => {
+                        self.settle_pending_tool_call(&request_id, ToolCallOutcome::TaskCancelled);
+                        return Err(CoreError::InvalidState(
+                            "approval channel disconnected".to_owned(),
+                        ));
+                    }
+                }
+            };
+
+            let response = match decision {
+                ToolDecision::Respond(response) => response,
+                ToolDecision::Skip { reason } => {
+                    self.trace("tool_skipped", json!({"tool": tool_name, "reason": reason}));
+                    self.events.emit(&ToolCallSkipped {
+                        task_id: self.task_id.clone(),
+                        request_id,
+                        tool_name: tool_name.to_owned(),
+                        reason: reason.clone(),
+                    });
+                    return Ok(ToolRun::Skipped(reason));
+                }
+            };
+            match response {
+                ToolResponse::Allow { always } => {
+                    if always {
+                        self.storage.set_tool_permission(tool_name, "allow")?;
+                    }
+                }
+                ToolResponse::AllowAllThisSession => {
+                    if let Ok(mut allow_all_set) = self.session_allow_all.lock() {
+                        allow_all_set.insert(self.session_id.clone());
+                    }
+                }
+                ToolResponse::Deny => {
+                    self.tools.stats().record_denial(tool_name);
+                    self.tools.stats().persist_if_due(&self.storage);
+                    return Err(CoreError::Tool(format!("tool {tool_name} denied by user")));
+                }
+            }
+        }
+
+        self.trace("tool_call", json!({"tool": tool_name, "arguments": args}));
+        let result = self
+            .storage
+            .faults()
+            .tool(tool_name)
+            .and_then(|()| self.tools.run(tool_name, args.clone(), ctx))?;
+        self.trace(
+            "tool_result",
+            json!({"tool": tool_name, "chunk_ids": retrieved_chunk_ids(&result)}),
+        );
+        self.events.emit(&ToolCallResult {
+            task_id: self.task_id.clone(),
+            tool_name: tool_name.to_owned(),
+            result: result.clone(),
+        });
+
+        Ok(ToolRun::Ran(result))
+    }
+
+    /// Close a request the host never answered and tell it why, so an open
+    /// approval dialog can dismiss itself.
+    fn settle_pending_tool_call(&self, request_id: &str, outcome: ToolCallOutcome) {
+        let removed = match self.pending_tool_calls.lock() {
+            Ok(mut pending_map) => {
+                let removed = pending_map.remove(request_id).is_some();
+                if removed {
+                    if let Ok(mut settled) = self.settled_tool_calls.lock() {
+                        settled.insert(request_id.to_owned(), outcome);
+                    }
+                }
+                removed
+            }
+            Err(_) => false,
+        };
+
+        if removed {
+            emit_tool_call_closed(&self.events, request_id, outcome);
+        }
+    }
+
+    /// Send one finished draft section so the host can render the report
+    /// progressively. Sections are safety-scanned on their own first; the
+    /// reviewed, styled report in the final `completed` event supersedes them.
+    /// `total` is the number of sections this report will have; evidence is
+    /// left out when nothing is attached.
+    fn emit_report_section(
+        &self,
+        tone: ReportTone,
+        section: ReportSection,
+        content: &str,
+        total: usize,
+    ) {
+        let body = tone.section_body(section, content);
+        let content = self.safety.check(&body).modified_content;
+        let (sections_done, revised) = {
+            let mut drafted = self
+                .drafted_sections
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            let previous = drafted.insert(
+                section.order(),
+                format!("【{}】\n{}", section.title(), content),
+            );
+            (drafted.len(), previous.is_some())
+        };
+        // A report under review reaches the host only once approved.
+        if !self.require_report_review {
+            self.events.emit(&ReportSectionEvent {
+                task_id: self.task_id.clone(),
+                session_id: self.session_id.clone(),
+                section: section.id().to_owned(),
+                title: section.title().to_owned(),
+                order: section.order(),
+                total,
+                content,
+            });
+        }
+        // A section sent again replaces the earlier one and adds no step.
+        if !revised {
+            self.emit_progress(ReportProgress::drafting(sections_done, total));
+        }
+    }
+
+    fn emit_progress(&self, progress: ReportProgress) {
+        self.events.emit(&TaskProgress {
+            task_id: self.task_id.clone(),
+            session_id: self.session_id.clone(),
+            phase: progress.phase.as_str().to_owned(),
+            completed: progress.completed,
+            total: progress.total,
+            percent: progress.percent(),
+            label: progress.label(&self.copy()),
+        });
+    }
+
+    /// Store an assistant message answering this task's user message.
+    fn reply(&self, content: &str, phase: &str) -> CoreResult<Message> {
+        self.storage.create_reply(
+            &self.session_id,
+            &self.user_message_id,
+            "assistant",
+            content,
+            Some(phase),
+        )
+    }
+
+    /// Store the sections sent before cancellation as a `draft_partial`
+    /// message. Best-effort: a failed write is logged, the task is already
+    /// over.
+    fn save_partial_draft(&self) -> Option<Message> {
+        let sections = std::mem::take(
+            &mut *self
+                .drafted_sections
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner()),
+        );
+        if sections.is_empty() {
+            return None;
+        }
+        let content = sections.into_values().collect::<Vec<_>>().join("\n\n");
+        match self.reply(&content, "draft_partial") {
+            Ok(message) => Some(message),
+            Err(err) => {
+                tracing::warn!("saving partial draft failed: {err}");
+                None
+            }
+        }
+    }
+
+    fn enter_phase(&self, phase: AgentPhase) {
+        self.time_phase(phase.into());
+        self.trace("phase", json!({"phase": phase.as_str()}));
+        self.events.emit(&AgentPhaseEvent {
+            task_id: self.task_id.clone(),
+            phase: phase.as_str().to_owned(),
+        });
+    }
+
+    /// Keep an artifact for `get_task_intermediate` in step-through runs.
+    fn record_intermediate(&self, update: impl FnOnce(&mut TaskIntermediate)) {
+        if let Some(stepper) = &self.control.stepper {
+            stepper.record(update);
+        }
+    }
+
+    /// In step-through runs, wait after `phase` until the host advances
+    /// the task. The wait counts towards the phase's timing.
+    fn pause_after(&self, phase: AgentPhase) -> CoreResult<()> {
+        let Some(stepper) = &self.control.stepper else {
+            return Ok(());
+        };
+        self.trace("paused", json!({"phase": phase.as_str()}));
+        self.events.emit(&TaskPaused {
+            task_id: self.task_id.clone(),
+            session_id: self.session_id.clone(),
+            phase: phase.as_str().to_owned(),
+        });
+        // The host's think time is no phase's.
+        let timer = || {
+            self.phase_timer
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+        };
+        timer().pause();
+        let resumed = stepper.pause(phase.as_str(), || self.control.is_cancelled());
+        timer().resume();
+        if !resumed {
+            return Err(CoreError::Cancelled);
+        }
+        Ok(())
+    }
+
+    fn time_phase(&self, phase: TimedPhase) {
+        self.phase_timer
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .enter(phase);
+    }
+
+    /// Stop the phase timer, trace and record the breakdown, and tell the
+    /// host the task is done: with the `report`, or with the next intake
+    /// `message`.
+    fn emit_completed(&self, report: Option<String>, message: Option<String>) {
+        let (timings, spans) = {
+            let mut timer = self
+                .phase_timer
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            (timer.finish(), timer.spans().to_vec())
+        };
+        self.trace("timings", json!({"timings": timings, "spans": spans}));
+        self.phase_timings.record(&timings);
+        let (citation_coverage, confidence) = if report.is_some() {
+            (
+                *self
+                    .citation_coverage
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner()),
+                self.conclusion_confidence
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                    .clone(),
+            )
+        } else {
+            (None, None)
+        };
+        self.events.emit(&Completed {
+            task_id: self.task_id.clone(),
+            session_id: self.session_id.clone(),
+            report,
+            message,
+            timings,
+            citation_coverage,
+            confidence,
+        });
+    }
+
+    /// Write-ahead record of an agent decision. Best-effort: tracing must not
+    /// fail the task.
+    fn trace(&self, kind: &str, data: Value) {
+        if let Err(err) =
+            self.storage
+                .append_task_trace(&self.task_id, &self.session_id, kind, &data.to_string())
+        {
+            tracing::warn!("task trace write failed: {err}");
+        }
+    }
+
+    fn guard_not_cancelled(&self) -> CoreResult<()> {
+        if self.control.is_cancelled() {
+            return Err(CoreError::Cancelled);
+        }
+        Ok(())
+    }
+
+    /// With a model configured, a short acknowledgement of `answer` in
+    /// the user's words (see `agent::acknowledgement`); the static ones
+    /// otherwise, and for skipped questions.
+    fn intake_acknowledgement(
+        &self,
+        answered_index: usize,
+        question: &str,
+        answer: &str,
+    ) -> String {
+        if answer.contains("（用户跳过此题）") || answer.contains("跳过") {
+            return self.copy().text(CopyKey::AckSkipped);
+        }
+        if let Some(ack) = self.model_acknowledgement(question, answer) {
+            return ack;
+        }
+
+        self.copy()
+            .text(CopyKey::ACKS[answered_index % CopyKey::ACKS.len()])
+    }
+
+    /// The model's acknowledgement, or `None` when it does not come within
+    /// `acknowledgement::ACK_DEADLINE`.
+    fn model_acknowledgement(&self, question: &str, answer: &str) -> Option<String> {
+        let connector = self.configured_model()?;
+        let deadline = Instant::now() + acknowledgement::ACK_DEADLINE;
+        let _permit = self.model_scheduler.acquire(
+            &self.task_id,
+            AgentPhase::Plan.call_priority(self.priority),
+            || self.control.is_cancelled() || Instant::now() >= deadline,
+        )?;
+        let remaining = deadline.checked_duration_since(Instant::now())?;
+        if let Err(err) = self.storage.faults().model() {
+            tracing::warn!("intake acknowledgement failed: {err}");
+            return None;
+        }
+        let mut messages = acknowledgement::prompt(&self.scenario, question, answer, &self.locale);
+        persona::apply(&self.storage, &mut messages);
+        let call = connector.chat_completion_capped(
+            self.model_phase(AgentPhase::Plan),
+            &messages,
+            Some(acknowledgement::ACK_MAX_TOKENS),
+        );
+        // The timer has to be made inside the runtime.
+        let raw = match RUNTIME.block_on(async { tokio::time::timeout(remaining, call).await }) {
+            Ok(Ok(raw)) => raw,
+            Ok(Err(err)) => {
+                tracing::warn!("intake acknowledgement failed: {err}");
+                return None;
+            }
+            Err(_) => {
+                self.trace("intake_ack", json!({"timed_out": true}));
+                return None;
+            }
+        };
+        let ack = acknowledgement::accept(&raw, &self.locale, &self.safety);
+        self.trace("intake_ack", json!({"accepted": ack.is_some()}));
+        ack
+    }
+
+    fn copy(&self) -> CopyCatalog<'_> {
+        CopyCatalog::new(&self.storage, &self.locale)
+    }
+}
+
+/// `chunk_id`s of retrieved chunks in a tool result, if any.
+fn retrieved_chunk_ids(result: &Value) -> Vec<String> {
+    result
+        .as_array()
+        .map(|items| {
+            items
+                .iter()
+                .filter_map(|item| Some(item.get("chunk_id")?.as_str()?.to_owned()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Trace data with every `DIAGNOSTICS_REDACTED_FIELDS` value replaced by its
+/// length in characters.
+fn redact_trace_data(data: Value) -> Value {
+    match data {
+        Value::Object(fields) => Value::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| {
+                    if DIAGNOSTICS_REDACTED_FIELDS.contains(&key.as_str()) {
+                        let chars = match &value {
+                            Value::String(text) => text.chars().count(),
+                            other => other.to_string().chars().count(),
+                        };
+                        (key, json!({ "redacted_chars": chars }))
+                    } else {
+                        (key, redact_trace_data(value))
+                    }
+                })
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.into_iter().map(redact_trace_data).collect()),
+        other => other,
+    }
+}
+
+fn emit_tool_call_closed(events: &EventHub, request_id: &str, outcome: ToolCallOutcome) {
+    events.emit(&ToolCallClosed {
+        request_id: request_id.to_owned(),
+        reason: outcome.as_str().to_owned(),
+    });
+}
+
+fn run_retention_purge(storage: &SqliteStorage, events: &EventHub) -> CoreResult<PurgeSummary> {
+    let policy = retention::load_policy(storage)?;
+    let summary = retention::purge(storage, &policy)?;
+    if !summary.is_empty() {
+        events.emit(&RetentionPurged {
+            session_count: summary.session_ids.len(),
+            message_count: summary.message_count,
+            log_count: summary.log_count,
+            trace_count: summary.trace_count,
+        });
+    }
+    Ok(summary)
+}
+
+/// Emit `kb_integrity_failed` when files of `retrieval`'s KB fail the
+/// checksum manifest.
+fn check_kb_integrity(retrieval: &RetrievalEngine, events: &EventHub) -> CoreResult<()> {
+    let integrity = retrieval.verify_integrity()?;
+    if integrity.manifest_error.is_some() || !integrity.issues.is_empty() {
+        tracing::warn!(
+            "kb integrity check failed for {} file(s)",
+            integrity.issues.len()
+        );
+        events.emit(&KbIntegrityFailed {
+            manifest_error: integrity.manifest_error,
+            files: integrity
+                .issues
+                .iter()
+                .map(|issue| issue.relative_path.clone())
+                .collect(),
+        });
+    }
+    Ok(())
+}
+
+/// Drop `session_id`'s task queue once no task holds or waits on it, so the
+/// map does not grow with every session ever used.
+fn prune_session_queue(queues: &Mutex<HashMap<String, Arc<ModelScheduler>>>, session_id: &str) {
+    let mut queues = queues
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    // Tasks clone the queue under this lock, so only the map's own
+    // reference is left when none is using it.
+    if queues
+        .get(session_id)
+        .is_some_and(|queue| Arc::strong_count(queue) == 1)
+    {
+        queues.remove(session_id);
+    }
+}
+
+/// Settings key set while a session is muted, so the mute survives restarts.
+fn session_muted_key(session_id: &str) -> String {
+    format!("session:{session_id}:muted")
+}
+
+/// Settings key set while the session's report tasks step through phases.
+fn session_step_through_key(session_id: &str) -> String {
+    format!("session:{session_id}:step_through")
+}
+
+fn query_limits(config: &CoreConfig) -> QueryLimits {
+    let defaults = QueryLimits::default();
+    QueryLimits {
+        timeout: config
+            .query_timeout_ms
+            .map_or(defaults.timeout, Duration::from_millis),
+        slow_threshold: config
+            .slow_query_threshold_ms
+            .map_or(defaults.slow_threshold, Duration::from_millis),
+    }
+}
+
+/// Watch `kb_path` for edits and report them as `kb_changed` events.
+/// Searches re-read the KB on every call, so a change only needs to reach
+/// the host (file lists, open documents) as a `kb_changed` event.
+fn start_kb_watcher(kb_path: &str, events: &Arc<EventHub>) -> CoreResult<KbWatcher> {
+    let events = Arc::downgrade(events);
+    KbWatcher::start(Path::new(kb_path), move |paths| {
+        if let Some(events) = events.upgrade() {
+            events.emit(&KbChanged { paths });
+        }
+    })
+}
+
+/// Periodically apply the retention policy until the owning Core is dropped.
+fn spawn_retention_job(storage: Weak<SqliteStorage>, events: Weak<EventHub>) {
+    RUNTIME.spawn(async move {
+        let mut interval = tokio::time::interval(RETENTION_PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let (Some(storage), Some(events)) = (storage.upgrade(), events.upgrade()) else {
+                break;
+            };
+            if let Err(err) = run_retention_purge(&storage, &events) {
+                tracing::warn!("retention purge failed: {err}");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use tempfile::TempDir;
+
+    use serde_json::Value;
+
+    use super::{
+        AudioInput, BulkToolCallOutcome, CaseMetadata, Core, CoreConfig, CoreError, CoreEvent,
+        EventFilter, EventListener, FactMergePolicy, ReportRegenerationMode, RetrievalConfig,
+        SafetyIncidentFilter, Setting, ToolCallOutcome, ToolResponse, Transcriber,
+        DEFAULT_PROFILE_ID,
+    };
+    use crate::agent::fingerprint::ReportIntegrity;
+    use crate::agent::onboarding::{CopySeed, FirstRunOptions, ToolPermissionSeed};
+    use crate::agent::persona::PersonaConfig;
+    use crate::agent::quick::QUICK_PHASE;
+    use crate::agent::share::{ReportSectionFilter, ShareFormat};
+    use crate::agent::{
+        collect_facts, save_answer, FactOverride, TaskPriority, MAX_INTAKE_REASKS,
+        OPEN_QUESTIONS_PHASE,
+    };
+    use crate::clock::ManualClock;
+    use crate::health::HealthLevel;
+    use crate::secrets::{SecretProvider, MESSAGE_CONTENT_KEY_SECRET};
+    use crate::storage::activity::ActivityKind;
+    use crate::storage::sqlite::SCHEMA_VERSION;
+    use crate::storage::ContentCipher;
+
+    #[derive(Clone, Default)]
+    struct EventCollector {
+        events: Arc<Mutex<Vec<CoreEvent>>>,
+    }
+
+    impl EventCollector {
+        fn push(&self, event: CoreEvent) {
+            if let Ok(mut events) = self.events.lock() {
+                events.push(event);
+            }
+        }
+
+        fn snapshot(&self) -> Vec<CoreEvent> {
+            self.events
+                .lock()
+                .map(|events| events.clone())
+                .unwrap_or_default()
+        }
+
+        fn wait_for<F>(&self, timeout: Duration, predicate: F) -> bool
+        where
+            F: Fn(&[CoreEvent]) -> bool,
+        {
+            let deadline = Instant::now() + timeout;
+            while Instant::now() < deadline {
+                let snapshot = self.snapshot();
+                if predicate(&snapshot) {
+                    return true;
+                }
+                thread::sleep(Duration::from_millis(30));
+            }
+            false
+        }
+    }
+
+    struct TestListener {
+        collector: EventCollector,
+    }
+
+    impl EventListener for TestListener {
+        fn on_event(&self, event: CoreEvent) {
+            self.collector.push(event);
+        }
+    }
+
+    fn setup_core(max_iterations: u32) -> (TempDir, Arc<Core>, EventCollector, String) {
+        setup_core_with_doc(
+            max_iterations,
+            "# 劳动仲裁\n拖欠工资可申请劳动仲裁，准备劳动合同、工资流水和沟通记录。",
+        )
+    }
+
+    fn setup_core_with_doc(
+        max_iterations: u32,
+        labor_doc_content: &str,
+    ) -> (TempDir, Arc<Core>, EventCollector, String) {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let kb_root = temp_dir.path().join("kb");
+        let labor = kb_root.join("labor");
+        fs::create_dir_all(&labor).expect("create labor dir");
+        fs::write(labor.join("law.md"), labor_doc_content).expect("write kb file");
+
+        let db_path = temp_dir.path().join("core.db");
+        let core = Core::new(CoreConfig::new(
+            kb_root.to_string_lossy().to_string(),
+            db_path.to_string_lossy().to_string(),
+            max_iterations,
+        ))
+        .expect("init core");
+
+        let collector = EventCollector::default();
+        core.subscribe_events(Box::new(TestListener {
+            collector: collector.clone(),
+        }))
+        .expect("subscribe");
+
+        let session_id = core
+            .create_session("labor".to_owned(), Some("测试".to_owned()))
+            .expect("create session");
+
+        (temp_dir, core, collector, session_id)
+    }
+
+    /// Answers to the labor intake questions that pass their validators.
+    const INTAKE_ANSWERS: [&str; 6] = [
+        "广东深圳",
+        "2022年3月入职，签了劳动合同",
+        "做销售，月薪8000元",
+        "拖欠三个月，一共24000元",
+        "补发工资",
+        "劳动合同和工资流水",
+    ];
+
+    /// Set all built-in tools to "allow" so Agent never blocks on permission
+    fn allow_all_tools(core: &Core) {
+        for tool_name in [
+            "ask_user",
+            "kb_search",
+            "kb_read",
+            "cite",
+            "summarize_facts",
+            "check_safety",
+            "suggest_escalation",
+            "venue_lookup",
+        ] {
+            core.set_tool_permission(tool_name.to_owned(), "allow".to_owned())
+                .expect("allow tool");
+        }
+    }
+
+    #[test]
+    fn event_timestamps_come_from_injected_clock() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let clock = Arc::new(ManualClock::at_timestamp(1_700_000_000));
+        let core = Core::with_clock(
+            CoreConfig::new(
+                temp_dir.path().join("kb").to_string_lossy().to_string(),
+                temp_dir
+                    .path()
+                    .join("core.db")
+                    .to_string_lossy()
+                    .to_string(),
+                4,
+            ),
+            clock.clone(),
+        )
+        .expect("init core");
+        let collector = EventCollector::default();
+        core.subscribe_events(Box::new(TestListener {
+            collector: collector.clone(),
+        }))
+        .expect("subscribe");
+
+        clock.advance(Duration::from_secs(42));
+        let session_id = core
+            .create_session("labor".to_owned(), None)
+            .expect("create session");
+
+        let created = collector
+            .snapshot()
+            .into_iter()
+            .find(|event| event.kind == "session_created")
+            .expect("session_created event");
+        assert_eq!(created.timestamp, 1_700_000_042);
+
+        let session = core
+            .list_sessions(None)
+            .expect("list sessions")
+            .into_iter()
+            .find(|session| session.id == session_id)
+            .expect("session listed");
+        assert_eq!(session.created_at, 1_700_000_042);
+    }
+
+    #[test]
+    fn profiles_only_see_their_own_sessions_search_and_settings() {
+        let (_temp_dir, core, _collector, family_session) = setup_core(4);
+        core.storage
+            .index_case_text(&family_session, "report", "拖欠工资两个月")
+            .expect("index");
+        core.set_setting("theme".to_owned(), "dark".to_owned())
+            .expect("setting");
+
+        let child = core.create_profile("  孩子 ".to_owned()).expect("profile");
+        assert_eq!(child.name, "孩子");
+        assert!(core.create_profile(" ".to_owned()).is_err());
+        assert!(core.switch_profile("missing".to_owned()).is_err());
+        core.switch_profile(child.id.clone()).expect("switch");
+        assert_eq!(core.get_active_profile().expect("active").id, child.id);
+
+        assert!(core.list_sessions(None).expect("list").is_empty());
+        assert!(core
+            .search_across_sessions("拖欠工资".to_owned())
+            .expect("search")
+            .is_empty());
+        assert_eq!(core.get_setting("theme".to_owned()).expect("get"), None);
+
+        let own_session = core
+            .create_session("rental".to_owned(), None)
+            .expect("create session");
+        let listed = core.list_sessions(None).expect("list");
+        assert_eq!(listed.len(), 1);
+        assert_eq!(
+            (listed[0].id.as_str(), listed[0].profile_id.as_str()),
+            (own_session.as_str(), child.id.as_str())
+        );
+
+        core.switch_profile(DEFAULT_PROFILE_ID.to_owned())
+            .expect("switch back");
+        let listed = core.list_sessions(None).expect("list");
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].id, family_session);
+        assert_eq!(
+            core.search_across_sessions("拖欠工资".to_owned())
+                .expect("search")
+                .len(),
+            1
+        );
+        assert_eq!(
+            core.get_setting("theme".to_owned())
+                .expect("get")
+                .as_deref(),
+            Some("dark")
+        );
+        assert_eq!(core.list_profiles().expect("profiles").len(), 2);
+    }
+
+    #[test]
+    fn settings_families_follow_the_active_profile() {
+        let (_temp_dir, core, _collector, _session_id) = setup_core(4);
+        let setting = |key: &str, value: &str| Setting {
+            key: key.to_owned(),
+            value: value.to_owned(),
+        };
+        core.set_settings_bulk(vec![setting("feature:a", "1"), setting("feature:b", "2")])
+            .expect("bulk set");
+
+        let child = core.create_profile("孩子".to_owned()).expect("profile");
+        core.switch_profile(child.id.clone()).expect("switch");
+        core.set_settings_bulk(vec![setting("feature:a", "child")])
+            .expect("bulk set");
+        assert_eq!(
+            core.list_settings("feature:".to_owned()).expect("list"),
+            vec![setting("feature:a", "child")]
+        );
+        assert_eq!(
+            core.delete_settings("feature:".to_owned()).expect("delete"),
+            1
+        );
+
+        core.switch_profile(DEFAULT_PROFILE_ID.to_owned())
+            .expect("switch back");
+        let all = core.list_settings(String::new()).expect("list all");
+        assert!(all
+            .iter()
+            .all(|setting| !setting.key.starts_with("profile:")));
+        assert_eq!(
+            core.list_settings("feature:".to_owned()).expect("list"),
+            vec![setting("feature:a", "1"), setting("feature:b", "2")]
+        );
+        assert!(core.delete_settings("prof".to_owned()).is_err());
+        assert!(core.delete_settings(String::new()).is_err());
+    }
+
+    #[test]
+    fn recent_events_are_shared_through_the_database() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let clock = Arc::new(ManualClock::at_timestamp(1_700_000_000));
+        let config = |event_history_size, read_only| CoreConfig {
+            event_history_size,
+            read_only,
+            ..CoreConfig::new(
+                temp_dir.path().join("kb").to_string_lossy().to_string(),
+                temp_dir
+                    .path()
+                    .join("core.db")
+                    .to_string_lossy()
+                    .to_string(),
+                4,
+            )
+        };
+        let service =
+            Core::with_clock(config(Some(3), false), clock.clone()).expect("service core");
+        match Core::with_clock(config(None, false), clock.clone()) {
+            Err(CoreError::InvalidState(message)) => assert!(message.contains("already open")),
+            _ => panic!("second writable core on the same database"),
+        }
+        let ui = Core::with_clock(config(None, true), clock.clone()).expect("ui core");
+
+        for message in ["one", "two", "three"] {
+            clock.advance(Duration::from_secs(10));
+            service.emit_test_event(message.to_owned());
+        }
+        service
+            .create_session("labor".to_owned(), None)
+            .expect("create session");
+        ui.emit_test_event("not recorded".to_owned());
+
+        let message = |event: &CoreEvent| {
+            let payload = serde_json::from_str::<Value>(&event.payload).expect("payload");
+            payload["message"].as_str().unwrap_or_default().to_owned()
+        };
+        let recent = ui.get_recent_events(0, None).expect("recent events");
+        assert_eq!(
+            recent
+                .iter()
+                .map(|event| (event.kind.as_str(), message(event)))
+                .collect::<Vec<_>>()[..2],
+            [("test", "two".to_owned()), ("test", "three".to_owned())]
+        );
+        assert_eq!(recent[2].kind, "session_created");
+
+        let tests = ui
+            .get_recent_events(
+                1_700_000_030,
+                Some(EventFilter {
+                    kinds: vec!["test".to_owned()],
+                    limit: None,
+                }),
+            )
+            .expect("filtered events");
+        assert_eq!(tests.len(), 1);
+        assert_eq!(message(&tests[0]), "three");
+    }
+
+    #[test]
+    fn unknown_intake_answers_stay_open_until_reasked() {
+        let (_temp_dir, core, collector, session_id) = setup_core(6);
+        allow_all_tools(&core);
+        let send = |text: &str| {
+            let before = collector
+                .snapshot()
+                .iter()
+                .filter(|event| event.kind == "completed")
+                .count();
+            core.send_message(session_id.clone(), text.to_owned(), None)
+                .expect("send");
+            assert!(collector.wait_for(Duration::from_secs(10), |events| {
+                events
+                    .iter()
+                    .filter(|event| event.kind == "completed")
+                    .count()
+                    > before
+            }));
+        };
+
+        send("我想咨询劳动仲裁");
+        for (idx, answer) in INTAKE_ANSWERS.iter().enumerate() {
+            send(if idx == 2 { "暂不清楚" } else { answer });
+        }
+        let open = core
+            .list_open_questions(session_id.clone())
+            .expect("open questions");
+        assert_eq!(open.len(), 1);
+        assert_eq!(
+            (open[0].field.as_str(), open[0].answer.as_str()),
+            ("job_salary", "暂不清楚")
+        );
+        assert!(!collector
+            .snapshot()
+            .iter()
+            .any(|event| event.kind == "open_questions_offered"));
+
+        send("还需要准备什么？");
+        let offer = core
+            .get_messages(session_id.clone(), false)
+            .expect("messages")
+            .into_iter()
+            .rfind(|message| message.phase.as_deref() == Some(OPEN_QUESTIONS_PHASE))
+            .expect("offer");
+        assert!(offer.content.starts_with("你之前有1个问题没答"));
+        assert!(collector.snapshot().iter().any(|event| {
+            event.kind == "open_questions_offered" && event.payload.contains("job_salary")
+        }));
+
+        assert!(core
+            .reask_open_question(session_id.clone(), "region".to_owned())
+            .is_err());
+        core.reask_open_question(session_id.clone(), "job_salary".to_owned())
+            .expect("reask");
+        send(INTAKE_ANSWERS[2]);
+        assert!(core
+            .list_open_questions(session_id.clone())
+            .expect("open questions")
+            .is_empty());
+        let salary = core
+            .get_facts(session_id)
+            .expect("facts")
+            .into_iter()
+            .find(|fact| fact.field == "job_salary")
+            .expect("salary fact");
+        assert_eq!(salary.value.as_deref(), Some(INTAKE_ANSWERS[2]));
+    }
+
+    #[test]
+    fn invalid_intake_answer_is_reasked_then_kept_unconfirmed() {
+        let (_temp_dir, core, collector, session_id) = setup_core(6);
+        allow_all_tools(&core);
+        let completed = |collector: &EventCollector| {
+            collector
+                .snapshot()
+                .iter()
+                .filter(|event| event.kind == "completed")
+                .count()
+        };
+        let send = |text: &str| {
+            let before = completed(&collector);
+            core.send_message(session_id.clone(), text.to_owned(), None)
+                .expect("send");
+            assert!(collector.wait_for(Duration::from_secs(10), |events| {
+                events
+                    .iter()
+                    .filter(|event| event.kind == "completed")
+                    .count()
+                    > before
+            }));
+        };
+
+        send("我想咨询劳动仲裁");
+        send(INTAKE_ANSWERS[0]);
+        for _ in 0..=MAX_INTAKE_REASKS {
+            send("不知道啊随便");
+        }
+
+        let progress = collector
+            .snapshot()
+            .into_iter()
+            .filter(|event| event.kind == "intake_progress")
+            .filter_map(|event| serde_json::from_str::<Value>(&event.payload).ok())
+            .collect::<Vec<_>>();
+        let reasks = progress
+            .iter()
+            .filter(|payload| payload["reask"] == Value::Bool(true))
+            .collect::<Vec<_>>();
+        assert_eq!(reasks.len(), MAX_INTAKE_REASKS as usize);
+        assert!(reasks.iter().all(|payload| payload["current"] == 2));
+        assert_eq!(progress.last().expect("progress")["current"], 3);
+
+        let facts = collect_facts(&core.storage, &session_id, "labor").expect("facts");
+        assert_eq!(facts[1].1, "不知道啊随便（待确认）");
+    }
+
+    struct FixedTranscriber(Option<&'static str>);
+
+    impl Transcriber for FixedTranscriber {
+        fn transcribe(&self, audio: AudioInput, locale: String) -> Option<String> {
+            assert!(matches!(audio, AudioInput::File { .. }));
+            assert_eq!(locale, "zh-CN");
+            self.0.map(ToOwned::to_owned)
+        }
+    }
+
+    #[test]
+    fn english_first_message_runs_intake_in_english() {
+        let (_temp_dir, core, collector, session_id) = setup_core(6);
+        allow_all_tools(&core);
+        let send = |text: &str| {
+            let before = collector
+                .snapshot()
+                .iter()
+                .filter(|event| event.kind == "completed")
+                .count();
+            core.send_message(session_id.clone(), text.to_owned(), None)
+                .expect("send");
+            assert!(collector.wait_for(Duration::from_secs(10), |events| {
+                events
+                    .iter()
+                    .filter(|event| event.kind == "completed")
+                    .count()
+                    > before
+            }));
+        };
+        let last_reply = || {
+            core.get_messages(session_id.clone(), false)
+                .expect("messages")
+                .into_iter()
+                .rfind(|message| message.role == "assistant")
+                .expect("reply")
+                .content
+        };
+
+        send("My boss has not paid my salary for three months");
+        assert_eq!(
+            core.get_session_preference(session_id.clone(), "language".to_owned())
+                .expect("preference")
+                .as_deref(),
+            Some("en")
+        );
+        let intro = last_reply();
+        assert!(intro.starts_with("Let's get the facts of your case straight first."));
+        assert!(intro.contains("Question 1: First, where do you mainly work"));
+
+        // A later Chinese answer does not switch the session's language.
+        send("深圳");
+        assert!(last_reply().contains("Next question: When did you start the job"));
+        assert!(core
+            .set_session_preference(session_id, "language".to_owned(), "fr".to_owned())
+            .is_err());
+    }
+
+    #[test]
+    fn reset_intake_clears_later_answers_and_keeps_the_transcript() {
+        let (_temp_dir, core, collector, session_id) = setup_core(6);
+        allow_all_tools(&core);
+        let send = |text: &str| {
+            let before = collector
+                .snapshot()
+                .iter()
+                .filter(|event| event.kind == "completed")
+                .count();
+            core.send_message(session_id.clone(), text.to_owned(), None)
+                .expect("send");
+            assert!(collector.wait_for(Duration::from_secs(10), |events| {
+                events
+                    .iter()
+                    .filter(|event| event.kind == "completed")
+                    .count()
+                    > before
+            }));
+            // The task stays tracked for a moment after `completed`.
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while core.events.has_session_tasks(&session_id) && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(10));
+            }
+        };
+        send("公司拖欠工资");
+        send("深圳");
+        send("2021年3月入职，签了合同");
+        assert!(core.reset_intake(session_id.clone(), 3).is_err());
+        let checkpoints = core
+            .list_intake_checkpoints(session_id.clone())
+            .expect("checkpoints");
+        assert_eq!(checkpoints.len(), 3);
+        assert_eq!(checkpoints[1].field, "hire_date");
+        assert_eq!(checkpoints[2].answer, None);
+        core.name_intake_checkpoint(session_id.clone(), 1, Some(" 入职之前 ".to_owned()))
+            .expect("name");
+        core.name_intake_checkpoint(session_id.clone(), 2, Some("第三题".to_owned()))
+            .expect("name");
+        assert!(core
+            .name_intake_checkpoint(session_id.clone(), 2, Some("入职之前".to_owned()))
+            .is_err());
+        assert!(core
+            .name_intake_checkpoint(session_id.clone(), 3, Some("太远".to_owned()))
+            .is_err());
+        core.storage
+            .index_case_text(&session_id, "facts", "2021年3月入职，签了合同")
+            .expect("index");
+        let transcript = core
+            .get_messages(session_id.clone(), false)
+            .expect("messages")
+            .len();
+
+        let message = core
+            .reset_intake_to_checkpoint(session_id.clone(), "入职之前".to_owned())
+            .expect("reset");
+        assert!(message.content.contains("第 2 题"));
+        let facts = collect_facts(&core.storage, &session_id, "labor").expect("facts");
+        assert_eq!(facts[0].1, "深圳");
+        assert_eq!(facts[1].1, "未提供");
+        let checkpoints = core
+            .list_intake_checkpoints(session_id.clone())
+            .expect("checkpoints");
+        assert_eq!(checkpoints.len(), 2);
+        assert_eq!(checkpoints[1].name.as_deref(), Some("入职之前"));
+        assert!(core
+            .reset_intake_to_checkpoint(session_id.clone(), "第三题".to_owned())
+            .is_err());
+        // The search index forgets the cleared answers.
+        assert!(core
+            .storage
+            .search_case_index("签了合同", 10, &core.active_profile_id())
+            .expect("search")
+            .is_empty());
+        assert_eq!(
+            core.get_messages(session_id.clone(), false)
+                .expect("messages")
+                .len(),
+            transcript + 1
+        );
+        let reset = collector
+            .snapshot()
+            .into_iter()
+            .find(|event| event.kind == "intake_reset")
+            .map(|event| serde_json::from_str::<Value>(&event.payload).expect("payload"))
+            .expect("intake_reset");
+        assert_eq!(
+            (
+                reset["question_index"].as_u64(),
+                reset["answers_cleared"].as_u64()
+            ),
+            (Some(1), Some(1))
+        );
+
+        send("2023年5月入职，没签合同");
+        let facts = collect_facts(&core.storage, &session_id, "labor").expect("facts");
+        assert_eq!(facts[1].1, "2023年5月入职，没签合同");
+    }
+
+    #[test]
+    fn spoken_intake_answer_is_confirmed_then_saved() {
+        let (_temp_dir, core, collector, session_id) = setup_core(6);
+        allow_all_tools(&core);
+        let audio = || AudioInput::File {
+            path: "/tmp/answer.m4a".to_owned(),
+        };
+        assert!(core
+            .submit_intake_audio(session_id.clone(), audio())
+            .is_err());
+
+        core.send_message(session_id.clone(), "我想咨询劳动仲裁".to_owned(), None)
+            .expect("send");
+        assert!(collector.wait_for(Duration::from_secs(10), |events| {
+            events.iter().any(|event| event.kind == "completed")
+        }));
+
+        core.set_transcriber(Box::new(FixedTranscriber(None)))
+            .expect("set transcriber");
+        assert!(core
+            .submit_intake_audio(session_id.clone(), audio())
+            .is_err());
+        core.set_transcriber(Box::new(FixedTranscriber(Some(" 广东深圳 "))))
+            .expect("set transcriber");
+        let transcript = core
+            .submit_intake_audio(session_id.clone(), audio())
+            .expect("transcribe");
+        assert_eq!(transcript.text, "广东深圳");
+        assert!(transcript.question.expect("question").contains("工作地"));
+        assert!(core.get_facts(session_id.clone()).expect("facts")[0]
+            .value
+            .is_none());
+
+        core.confirm_intake_transcript(session_id.clone(), None)
+            .expect("confirm");
+        assert!(collector.wait_for(Duration::from_secs(10), |events| {
+            events
+                .iter()
+                .filter(|event| event.kind == "completed")
+                .count()
+                >= 2
+        }));
+        assert_eq!(
+            core.get_facts(session_id.clone()).expect("facts")[0]
+                .value
+                .as_deref(),
+            Some("广东深圳")
+        );
+        assert!(core.confirm_intake_transcript(session_id, None).is_err());
+    }
+
+    #[test]
+    fn agent_phase_transitions_plan_draft_review() {
+        let (_temp_dir, core, collector, session_id) = setup_core(12);
+        allow_all_tools(&core);
+
+        // First message starts intake (question 1/6)
+        core.send_message(session_id.clone(), "我想咨询劳动仲裁".to_owned(), None)
+            .expect("start intake");
+
+        // Wait for first intake question to complete before sending answers
+        // (per-session lock ensures serialization)
+        for answer in INTAKE_ANSWERS {
+            // Small pause to let the per-session lock serialize
+            thread::sleep(Duration::from_millis(200));
+            core.send_message(session_id.clone(), answer.to_owned(), None)
+                .expect("send answer");
+        }
+
+        let has_report = collector.wait_for(Duration::from_secs(30), |events| {
+            events
+                .iter()
+                .any(|event| event.kind == "completed" && event.payload.contains("\"report\""))
+        });
+        assert!(has_report, "final report completion event not observed");
+
+        let phases = collector
+            .snapshot()
+            .into_iter()
+            .filter(|event| event.kind == "agent_phase")
+            .filter_map(|event| {
+                serde_json::from_str::<serde_json::Value>(&event.payload)
+                    .ok()?
+                    .get("phase")
+                    .and_then(serde_json::Value::as_str)
+                    .map(ToOwned::to_owned)
+            })
+            .collect::<Vec<_>>();
+
+        assert!(phases.iter().any(|phase| phase == "planning"));
+        assert!(phases.iter().any(|phase| phase == "drafting"));
+        assert!(phases.iter().any(|phase| phase == "reviewing"));
+
+        let timings = collector
+            .snapshot()
+            .into_iter()
+            .filter(|event| event.kind == "completed")
+            .filter_map(|event| serde_json::from_str::<serde_json::Value>(&event.payload).ok())
+            .find(|payload| payload.get("report").is_some())
+            .map(|payload| payload["timings"].clone())
+            .expect("report completion");
+        assert!(timings["draft_ms"].is_u64());
+        assert!(timings["review_ms"].is_u64());
+        assert!(timings["total_ms"].as_u64() >= timings["draft_ms"].as_u64());
+
+        let draft = core
+            .get_metrics()
+            .expect("metrics")
+            .phase_timings
+            .into_iter()
+            .find(|stats| stats.phase == "draft")
+            .expect("draft stats");
+        assert!(draft.tasks >= 1);
+    }
+
+    #[test]
+    fn max_iterations_triggers_error_event() {
+        let (_temp_dir, core, collector, session_id) = setup_core(1);
+        allow_all_tools(&core);
+
+        // Mark intake as nearly done: set index to last question
+        core.set_setting(format!("intake:{session_id}:idx"), "6".to_owned())
+            .expect("set intake idx");
+
+        core.send_message(session_id, "最后一题答案".to_owned(), None)
+            .expect("send");
+
+        let hit_limit = collector.wait_for(Duration::from_secs(10), |events| {
+            events
+                .iter()
+                .any(|event| event.kind == "error" && event.payload.contains("max_iterations"))
+        });
+        assert!(hit_limit, "max_iterations error event not observed");
+    }
+
+    #[test]
+    fn cancel_agent_task_emits_cancelled_event() {
+        let (_temp_dir, core, collector, session_id) = setup_core(6);
+        // Leave ask_user at default "ask" so the agent blocks on tool_call_request
+        // and we can cancel it
+
+        let task_id = core
+            .send_message(session_id, "我想咨询劳动仲裁".to_owned(), None)
+            .expect("send");
+
+        let has_request = collector.wait_for(Duration::from_secs(10), |events| {
+            events.iter().any(|event| event.kind == "tool_call_request")
+        });
+        assert!(has_request, "tool call request not emitted");
+
+        core.cancel_agent_task(task_id).expect("cancel");
+        let cancelled = collector.wait_for(Duration::from_secs(10), |events| {
+            events.iter().any(|event| event.kind == "cancelled")
+        });
+        assert!(cancelled, "cancelled event not observed");
+    }
+
+    #[test]
+    fn cancelling_during_draft_keeps_finished_sections() {
+        let (_temp_dir, core, collector, session_id) = setup_core(6);
+        core.set_setting(format!("intake:{session_id}:done"), "1".to_owned())
+            .expect("mark intake done");
+
+        // kb_search stays at "ask", so drafting the legal analysis blocks on
+        // its tool call after the fixed sections have been sent.
+        let task_id = core
+            .send_message(session_id.clone(), "请给出分析".to_owned(), None)
+            .expect("send");
+        assert!(collector.wait_for(Duration::from_secs(10), |events| {
+            events.iter().any(|event| event.kind == "tool_call_request")
+        }));
+        core.cancel_agent_task(task_id.clone()).expect("cancel");
+        assert!(collector.wait_for(Duration::from_secs(10), |events| {
+            events.iter().any(|event| event.kind == "cancelled")
+        }));
+
+        let cancelled = collector
+            .snapshot()
+            .into_iter()
+            .find(|event| event.kind == "cancelled")
+            .map(|event| serde_json::from_str::<Value>(&event.payload).expect("payload"))
+            .expect("cancelled event");
+        assert_eq!(cancelled["task_id"], task_id.as_str());
+        let partial = cancelled["partial_report"]
+            .as_str()
+            .expect("partial report");
+        assert!(partial.starts_with("【先说结论】"));
+        assert!(partial.contains("【事实摘要】"));
+        assert!(!partial.contains("【法律分析】"));
+
+        let draft = core
+            .get_messages(session_id, false)
+            .expect("messages")
+            .into_iter()
+            .find(|message| message.phase.as_deref() == Some("draft_partial"))
+            .expect("draft message");
+        assert_eq!(cancelled["draft_message_id"], draft.id.as_str());
+        assert_eq!(draft.content, partial);
+    }
+
+    #[test]
+    fn respond_tool_call_reports_outcome_for_late_answers() {
+        let (_temp_dir, core, collector, session_id) = setup_core(6);
+        let request_ids = |collector: &EventCollector| {
+            collector
+                .snapshot()
+                .into_iter()
+                .filter(|event| event.kind == "tool_call_request")
+                .filter_map(|event| {
+                    serde_json::from_str::<Value>(&event.payload)
+                        .ok()?
+                        .get("request_id")?
+                        .as_str()
+                        .map(str::to_owned)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        core.send_message(session_id.clone(), "我想咨询劳动仲裁".to_owned(), None)
+            .expect("send");
+        assert!(collector.wait_for(Duration::from_secs(10), |events| {
+            events.iter().any(|event| event.kind == "tool_call_request")
+        }));
+        let first = request_ids(&collector).remove(0);
+        assert_eq!(
+            core.respond_tool_call(first.clone(), ToolResponse::Allow { always: false })
+                .expect("respond"),
+            ToolCallOutcome::Delivered
+        );
+        assert_eq!(
+            core.respond_tool_call(first, ToolResponse::Deny)
+                .expect("respond again"),
+            ToolCallOutcome::AlreadyResponded
+        );
+        assert!(collector.wait_for(Duration::from_secs(10), |events| {
+            events.iter().any(|event| event.kind == "completed")
+        }));
+
+        let task_id = core
+            .send_message(session_id, "公司拖欠了三个月工资".to_owned(), None)
+            .expect("send");
+        assert!(collector.wait_for(Duration::from_secs(10), |events| {
+            events
+                .iter()
+                .filter(|event| event.kind == "tool_call_request")
+                .count()
+                >= 2
+        }));
+        let second = request_ids(&collector).remove(1);
+        core.cancel_agent_task(task_id).expect("cancel");
+        assert!(collector.wait_for(Duration::from_secs(10), |events| {
+            events
+                .iter()
+                .any(|event| event.kind == "tool_call_closed" && event.payload.contains(&second))
+        }));
+        assert_eq!(
+            core.respond_tool_call(second, ToolResponse::Allow { always: false })
+                .expect("respond after cancel"),
+            ToolCallOutcome::TaskCancelled
+        );
+        assert!(core
+            .respond_tool_call("missing".to_owned(), ToolResponse::Deny)
+            .is_err());
+    }
+
+    #[test]
+    fn bulk_response_is_all_or_nothing() {
+        let (_temp_dir, core, collector, session_id) = setup_core(6);
+        core.send_message(session_id.clone(), "我想咨询劳动仲裁".to_owned(), None)
+            .expect("send");
+        assert!(collector.wait_for(Duration::from_secs(10), |events| {
+            events.iter().any(|event| event.kind == "tool_call_request")
+        }));
+
+        let pending = core
+            .list_pending_tool_calls(session_id.clone())
+            .expect("list");
+        assert_eq!(pending.len(), 1);
+        let request_id = pending[0].request_id.clone();
+        assert!(serde_json::from_str::<Value>(&pending[0].arguments_json).is_ok());
+        assert!(core
+            .list_pending_tool_calls("other".to_owned())
+            .expect("list other")
+            .is_empty());
+
+        assert!(core
+            .respond_tool_calls_bulk(
+                vec![request_id.clone(), "missing".to_owned()],
+                ToolResponse::Allow { always: false },
+            )
+            .is_err());
+        assert_eq!(
+            core.list_pending_tool_calls(session_id.clone())
+                .expect("list after failed batch")
+                .len(),
+            1
+        );
+
+        let outcomes = core
+            .respond_tool_calls_bulk(
+                vec![request_id.clone(), request_id.clone()],
+                ToolResponse::Allow { always: false },
+            )
+            .expect("bulk respond");
+        assert_eq!(
+            outcomes,
+            vec![BulkToolCallOutcome {
+                request_id: request_id.clone(),
+                outcome: ToolCallOutcome::Delivered,
+            }]
+        );
+        assert!(collector.wait_for(Duration::from_secs(10), |events| {
+            events.iter().any(|event| {
+                event.kind == "tool_calls_responded" && event.payload.contains(&request_id)
+            })
+        }));
+        assert_eq!(
+            core.respond_tool_calls_bulk(vec![request_id], ToolResponse::Deny)
+                .expect("late batch")[0]
+                .outcome,
+            ToolCallOutcome::AlreadyResponded
+        );
+    }
+
+    #[test]
+    fn skipped_tool_call_degrades_instead_of_failing() {
+        let (_temp_dir, core, collector, session_id) = setup_core(6);
+        allow_all_tools(&core);
+        core.set_tool_permission("kb_search".to_owned(), "ask".to_owned())
+            .expect("ask kb_search");
+        core.set_setting(format!("intake:{session_id}:done"), "1".to_owned())
+            .expect("mark intake done");
+
+        core.send_message(session_id.clone(), "请给出分析".to_owned(), None)
+            .expect("send");
+        assert!(collector.wait_for(Duration::from_secs(10), |events| {
+            events.iter().any(|event| event.kind == "tool_call_request")
+        }));
+        let request = collector
+            .snapshot()
+            .into_iter()
+            .find(|event| event.kind == "tool_call_request")
+            .expect("request event");
+        let request_id = serde_json::from_str::<Value>(&request.payload).expect("payload")
+            ["request_id"]
+            .as_str()
+            .expect("request id")
+            .to_owned();
+
+        assert_eq!(
+            core.cancel_pending_tool_call(request_id.clone())
+                .expect("skip"),
+            ToolCallOutcome::Skipped
+        );
+        assert!(collector.wait_for(Duration::from_secs(20), |events| {
+            events.iter().any(|event| event.kind == "completed")
+        }));
+        let events = collector.snapshot();
+        assert!(events.iter().any(|event| event.kind == "tool_call_skipped"));
+        assert!(!events.iter().any(|event| event.kind == "error"));
+        assert_eq!(
+            core.respond_tool_call(request_id, ToolResponse::Allow { always: false })
+                .expect("late answer"),
+            ToolCallOutcome::Skipped
+        );
+    }
+
+    #[test]
+    fn denied_tool_emits_error_event() {
+        let (_temp_dir, core, collector, session_id) = setup_core(6);
+        // Allow all tools first, then deny kb_search specifically
+        allow_all_tools(&core);
+        core.set_tool_permission("summarize_facts".to_owned(), "allow".to_owned())
+            .expect("allow summarize_facts");
+        core.set_tool_permission("kb_search".to_owned(), "deny".to_owned())
+            .expect("deny kb_search");
+
+        // Skip intake entirely
+        core.set_setting(format!("intake:{session_id}:done"), "1".to_owned())
+            .expect("mark intake done");
+
+        core.send_message(session_id, "直接生成报告".to_owned(), None)
+            .expect("send");
+
+        let denied_error = collector.wait_for(Duration::from_secs(10), |events| {
+            events
+                .iter()
+                .any(|event| event.kind == "error" && event.payload.contains("denied"))
+        });
+        assert!(denied_error, "denied tool error event not observed");
+
+        let kb_search = core
+            .get_tool_stats()
+            .into_iter()
+            .find(|stats| stats.tool_name == "kb_search")
+            .expect("kb_search stats");
+        assert_eq!((kb_search.invocations, kb_search.denials), (0, 1));
+    }
+
+    #[test]
+    fn failed_task_marks_session_and_can_be_retried() {
+        let (_temp_dir, core, collector, session_id) = setup_core(6);
+        allow_all_tools(&core);
+        core.set_tool_permission("kb_search".to_owned(), "deny".to_owned())
+            .expect("deny kb_search");
+        core.set_setting(format!("intake:{session_id}:done"), "1".to_owned())
+            .expect("mark intake done");
+
+        core.send_message(session_id.clone(), "直接生成报告".to_owned(), None)
+            .expect("send");
+        let failed = collector.wait_for(Duration::from_secs(10), |events| {
+            events
+                .iter()
+                .any(|event| event.kind == "error" && event.payload.contains("\"retryable\":true"))
+        });
+        assert!(failed, "retryable error event not observed");
+
+        let status_of = |core: &Core| {
+            core.list_sessions(None)
+                .expect("list sessions")
+                .into_iter()
+                .find(|session| session.id == session_id)
+                .expect("session")
+                .status
+        };
+        assert_eq!(status_of(&core), "needs_retry");
+
+        core.set_tool_permission("kb_search".to_owned(), "allow".to_owned())
+            .expect("allow kb_search");
+        // The failed task stays tracked for a moment after its error event.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while core.events.has_session_tasks(&session_id) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        core.retry_last_task(session_id.clone()).expect("retry");
+
+        let has_report = collector.wait_for(Duration::from_secs(20), |events| {
+            events
+                .iter()
+                .any(|event| event.kind == "completed" && event.payload.contains("\"report\""))
+        });
+        assert!(has_report, "retried report not observed");
+        // The session is reactivated just after the `completed` event.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while core.events.has_session_tasks(&session_id) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(status_of(&core), "active");
+
+        let user_messages = core
+            .get_messages(session_id.clone(), false)
+            .expect("messages")
+            .into_iter()
+            .filter(|message| message.role == "user")
+            .count();
+        assert_eq!(user_messages, 1);
+        assert!(core.retry_last_task(session_id).is_err());
+    }
+
+    #[cfg(feature = "fault-injection")]
+    #[test]
+    fn injected_faults_fail_the_pipeline_until_cleared() {
+        let (_temp_dir, core, collector, session_id) = setup_core(6);
+        allow_all_tools(&core);
+        core.set_setting(format!("intake:{session_id}:done"), "1".to_owned())
+            .expect("mark intake done");
+        core.set_setting("fault:tool:kb_search:fail_every".to_owned(), "1".to_owned())
+            .expect("inject tool fault");
+
+        core.send_message(session_id.clone(), "直接生成报告".to_owned(), None)
+            .expect("send");
+        assert!(collector.wait_for(Duration::from_secs(10), |events| {
+            events.iter().any(|event| {
+                event.kind == "error"
+                    && event.payload.contains("injected fault")
+                    && event.payload.contains("\"retryable\":true")
+            })
+        }));
+
+        core.set_setting("fault:storage:fail_every".to_owned(), "1".to_owned())
+            .expect("fault rules are stored past the storage fault");
+        assert!(core.get_messages(session_id.clone(), false).is_err());
+        core.set_setting("fault:storage:fail_every".to_owned(), "0".to_owned())
+            .expect("turn the fault off");
+        assert!(core.get_messages(session_id.clone(), false).is_ok());
+        assert_eq!(
+            core.delete_settings("fault:".to_owned())
+                .expect("clear faults"),
+            2
+        );
+
+        core.retry_last_task(session_id).expect("retry");
+        assert!(collector.wait_for(Duration::from_secs(20), |events| {
+            events
+                .iter()
+                .any(|event| event.kind == "completed" && event.payload.contains("\"report\""))
+        }));
+    }
+
+    #[test]
+    fn task_trace_records_decisions_and_is_exported() {
+        let (_temp_dir, core, collector, session_id) = setup_core(6);
+        allow_all_tools(&core);
+        core.set_setting(format!("intake:{session_id}:done"), "1".to_owned())
+            .expect("mark intake done");
+
+        let task_id = core
+            .send_message(session_id.clone(), "请给出分析".to_owned(), None)
+            .expect("send");
+        assert!(collector.wait_for(Duration::from_secs(20), |events| {
+            events.iter().any(|event| event.kind == "completed")
+        }));
+        // The outcome entry is written right after the worker returns.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let trace = loop {
+            let trace = core.get_task_trace(task_id.clone()).expect("trace");
+            if trace.iter().any(|entry| entry.kind == "outcome") || Instant::now() > deadline {
+                break trace;
+            }
+            thread::sleep(Duration::from_millis(20));
+        };
+
+        let kinds = trace
+            .iter()
+            .map(|entry| entry.kind.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(kinds.first(), Some(&"phase"));
+        assert!(kinds.contains(&"safety"));
+        assert_eq!(kinds.last(), Some(&"outcome"));
+        let search_result = trace
+            .iter()
+            .find(|entry| entry.kind == "tool_result" && entry.data.contains("kb_search"))
+            .expect("kb_search result traced");
+        assert!(search_result.data.contains("law.md#L1-L"));
+
+        let diagnostics: Value =
+            serde_json::from_str(&core.export_diagnostics(session_id).expect("export"))
+                .expect("json");
+        assert_eq!(
+            diagnostics["task_traces"].as_array().map(Vec::len),
+            Some(trace.len())
+        );
+        let tool_call = diagnostics["task_traces"]
+            .as_array()
+            .and_then(|entries| {
+                entries.iter().find(|entry| {
+                    entry["kind"] == "tool_call" && entry["data"]["tool"] == "kb_search"
                 })
-                .to_string(),
-            );
+            })
+            .expect("kb_search call exported");
+        assert!(tool_call["data"]["arguments"]["redacted_chars"].is_u64());
+    }
 
-            let decision = loop {
-                if let Err(err) = self.guard_not_cancelled() {
-                    let _ = self.remove_pending_tool_call(&request_id);
-                    return Err(err);
-                }
-                match rx.recv_timeout(Duration::from_millis(300)) {
-                    Ok(resp) => break resp,
-                    Err(mpsc::RecvTimeoutError::Timeout) => continue,
-                    Err(mpsc::RecvTimeoutError::Disconnected) => {
-                        let _ = self.remove_pending_tool_call(&request_id);
-                        return Err(CoreError::InvalidState(
-                            "approval channel disconnected".to_owned(),
-                        ));
-                    }
-                }
-            };
+    #[test]
+    fn report_sections_stream_before_completion() {
+        let (_temp_dir, core, collector, session_id) = setup_core(6);
+        allow_all_tools(&core);
+        core.set_setting(format!("intake:{session_id}:done"), "1".to_owned())
+            .expect("mark intake done");
 
-            match decision {
-                ToolResponse::Allow { always } => {
-                    if always {
-                        self.storage.set_tool_permission(tool_name, "allow")?;
-                    }
-                }
-                ToolResponse::AllowAllThisSession => {
-                    if let Ok(mut allow_all_set) = self.session_allow_all.lock() {
-                        allow_all_set.insert(self.session_id.clone());
+        core.send_message(session_id, "请给出分析".to_owned(), None)
+            .expect("send");
+        assert!(collector.wait_for(Duration::from_secs(20), |events| {
+            events.iter().any(|event| event.kind == "completed")
+        }));
+
+        let events = collector.snapshot();
+        let completed_at = events
+            .iter()
+            .position(|event| event.kind == "completed")
+            .expect("completed");
+        let mut sections = events[..completed_at]
+            .iter()
+            .filter(|event| event.kind == "report_section")
+            .map(|event| serde_json::from_str::<Value>(&event.payload).expect("payload"))
+            .collect::<Vec<_>>();
+        let conclusions = sections
+            .iter()
+            .filter(|section| section["section"] == "conclusion")
+            .map(|section| section["content"].as_str().unwrap_or_default())
+            .collect::<Vec<_>>();
+        assert_eq!(conclusions.len(), 2);
+        assert!(!conclusions[0].contains("结论把握"));
+        assert!(conclusions[1].contains("结论把握："));
+        // Keep the last version of each section.
+        sections.reverse();
+        sections.sort_by_key(|section| section["order"].as_u64());
+        sections.dedup_by_key(|section| section["order"].as_u64());
+        let ids = sections
+            .iter()
+            .map(|section| section["section"].as_str().unwrap_or_default())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ids,
+            vec![
+                "conclusion",
+                "facts",
+                "legal_analysis",
+                "process_path",
+                "venue",
+                "risk_notice",
+                "disclaimer"
+            ]
+        );
+        assert!(sections[2]["content"]
+            .as_str()
+            .unwrap_or_default()
+            .contains("劳动仲裁"));
+
+        let progress = events[..completed_at]
+            .iter()
+            .filter(|event| event.kind == "task_progress")
+            .map(|event| serde_json::from_str::<Value>(&event.payload).expect("payload"))
+            .collect::<Vec<_>>();
+        let mut drafted = progress
+            .iter()
+            .filter(|step| step["phase"] == "drafting")
+            .map(|step| step["completed"].as_u64().unwrap_or_default())
+            .collect::<Vec<_>>();
+        drafted.sort_unstable();
+        assert_eq!(drafted, [1, 2, 3, 4, 5, 6, 7]);
+        assert!(progress
+            .iter()
+            .any(|step| step["phase"] == "reviewing" && step["percent"] == 87));
+        let last = progress.last().expect("progress");
+        assert_eq!(last["percent"], 100);
+        assert_eq!(last["label"], "报告已生成");
+
+        let completed = serde_json::from_str::<Value>(&events[completed_at].payload)
+            .expect("completed payload");
+        assert!(["high", "medium", "low"].contains(
+            &completed["confidence"]["level"]
+                .as_str()
+                .unwrap_or_default()
+        ));
+        assert!(completed["report"]
+            .as_str()
+            .unwrap_or_default()
+            .contains("结论把握："));
+        let coverage = &completed["citation_coverage"];
+        assert!(coverage["claims"].as_u64().unwrap_or_default() >= 1);
+        assert_eq!(coverage["cited"], coverage["claims"]);
+        assert_eq!(coverage["removed"], 0);
+    }
+
+    #[cfg(feature = "model-remote")]
+    #[test]
+    fn model_warm_up_reports_structured_failure() {
+        use super::ModelConfig;
+
+        let (_temp_dir, core, collector, _session_id) = setup_core(6);
+
+        core.update_model_config(ModelConfig {
+            api_key: "test-key".to_owned(),
+            model_name: "test-model".to_owned(),
+            // Nothing listens on port 1.
+            base_url: Some("http://127.0.0.1:1".to_owned()),
+            retry_max_retries: 0,
+            warm_up: true,
+            ..ModelConfig::default()
+        })
+        .expect("update config");
+
+        assert!(collector.wait_for(Duration::from_secs(10), |events| {
+            events
+                .iter()
+                .any(|event| event.kind == "model_warmup_failed")
+        }));
+        let failure = collector
+            .snapshot()
+            .into_iter()
+            .find(|event| event.kind == "model_warmup_failed")
+            .map(|event| serde_json::from_str::<Value>(&event.payload).expect("payload"))
+            .expect("failure event");
+        assert_eq!(failure["model"], "test-model");
+        assert_eq!(failure["stage"], "models");
+        assert!(failure["status"].is_null());
+        assert!(!collector
+            .snapshot()
+            .iter()
+            .any(|event| event.kind == "model_ready"));
+    }
+
+    /// A model server answering every chat completion with `reply`, and
+    /// the request bodies it got.
+    #[cfg(feature = "model-remote")]
+    fn serve_completions(reply: &str) -> (String, Arc<Mutex<Vec<String>>>) {
+        use std::io::{BufRead, BufReader, Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+        let base_url = format!("http://{}", listener.local_addr().expect("addr"));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let body = serde_json::json!({"choices": [{"message": {"content": reply}}]}).to_string();
+        let seen = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { return };
+                let (seen, body) = (seen.clone(), body.clone());
+                thread::spawn(move || {
+                    let mut reader = BufReader::new(stream.try_clone().expect("clone stream"));
+                    // Keep-alive: answer every request on the socket.
+                    loop {
+                        let mut length = 0;
+                        let mut line = String::new();
+                        loop {
+                            line.clear();
+                            if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                                return;
+                            }
+                            if line == "\r\n" {
+                                break;
+                            }
+                            if let Some(value) =
+                                line.to_ascii_lowercase().strip_prefix("content-length:")
+                            {
+                                length = value.trim().parse().unwrap_or(0);
+                            }
+                        }
+                        let mut request = vec![0; length];
+                        if reader.read_exact(&mut request).is_err() {
+                            return;
+                        }
+                        seen.lock()
+                            .expect("requests")
+                            .push(String::from_utf8_lossy(&request).into_owned());
+                        let response = format!(
+                            "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{body}",
+                            body.len()
+                        );
+                        if stream.write_all(response.as_bytes()).is_err() {
+                            return;
+                        }
                     }
-                }
-                ToolResponse::Deny => {
-                    return Err(CoreError::Tool(format!("tool {tool_name} denied by user")));
-                }
+                });
+            }
+        });
+        (base_url, requests)
+    }
+
+    #[cfg(feature = "model-remote")]
+    #[test]
+    fn intake_answers_are_acknowledged_by_the_model() {
+        use super::ModelConfig;
+
+        let (_temp_dir, core, collector, session_id) = setup_core(6);
+        allow_all_tools(&core);
+        let (base_url, requests) = serve_completions("好的，你在深圳上班，已经记下了。");
+        core.update_model_config(ModelConfig {
+            api_key: "test-key".to_owned(),
+            model_name: "test-model".to_owned(),
+            base_url: Some(base_url),
+            retry_max_retries: 0,
+            ..ModelConfig::default()
+        })
+        .expect("model config");
+
+        for text in ["公司拖欠工资", "深圳"] {
+            let before = collector
+                .snapshot()
+                .iter()
+                .filter(|event| event.kind == "completed")
+                .count();
+            core.send_message(session_id.clone(), text.to_owned(), None)
+                .expect("send");
+            assert!(collector.wait_for(Duration::from_secs(10), |events| {
+                events
+                    .iter()
+                    .filter(|event| event.kind == "completed")
+                    .count()
+                    > before
+            }));
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while core.events.has_session_tasks(&session_id) && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(10));
             }
         }
 
-        let result = self.tools.run(tool_name, args.clone(), ctx)?;
-        emit_event_static(
-            &self.listeners,
-            "tool_call_result",
-            json!({
-                "task_id": self.task_id,
-                "tool_name": tool_name,
-                "result": result
-            })
-            .to_string(),
+        let reply = core
+            .get_messages(session_id.clone(), false)
+            .expect("messages")
+            .into_iter()
+            .rfind(|message| message.role == "assistant")
+            .expect("reply")
+            .content;
+        assert!(
+            reply.contains("好的，你在深圳上班，已经记下了。"),
+            "{reply}"
         );
+        assert!(requests
+            .lock()
+            .expect("requests")
+            .iter()
+            .any(|body| body.contains("劳动法咨询助手，正在逐条询问") && body.contains("深圳")));
+    }
 
-        Ok(result)
+    #[test]
+    fn oversize_message_is_stored_as_attachment() {
+        let (_temp_dir, core, _collector, session_id) = setup_core(6);
+        let pasted = "2024年3月 工资未发放\n".repeat(400);
+
+        core.send_message(session_id.clone(), pasted.clone(), None)
+            .expect("send");
+
+        let attachments = core
+            .list_attachments(session_id.clone())
+            .expect("attachments");
+        assert_eq!(attachments.len(), 1);
+        assert_eq!(attachments[0].extracted_text, pasted);
+        let message = core
+            .get_messages(session_id, false)
+            .expect("messages")
+            .into_iter()
+            .find(|message| message.role == "user")
+            .expect("user message");
+        assert!(message.content.chars().count() < 1_000);
+        assert!(message
+            .content
+            .contains(&format!("【长文本已存为附件】{}", attachments[0].file_name)));
     }
 
-    fn remove_pending_tool_call(&self, request_id: &str) -> CoreResult<()> {
-        let mut pending_map = self
-            .pending_tool_calls
-            .lock()
-            .map_err(|_| CoreError::InvalidState("pending_tool_calls lock poisoned".to_owned()))?;
-        pending_map.remove(request_id);
-        Ok(())
+    #[test]
+    fn imported_messages_pass_the_same_checks() {
+        let (_temp_dir, core, _collector, session_id) = setup_core(6);
+        let pasted = "2024年3月 工资未发放\n".repeat(400);
+        let message = |role: &str, content: &str, tool_calls: Option<&str>| crate::NewMessage {
+            role: role.to_owned(),
+            content: content.to_owned(),
+            phase: None,
+            tool_calls: tool_calls.map(str::to_owned),
+            created_at: None,
+        };
+
+        let error = core
+            .create_messages_bulk(
+                session_id.clone(),
+                vec![
+                    message("user", &pasted, None),
+                    message("assistant", "好的", Some("not json")),
+                ],
+            )
+            .expect_err("bad tool calls");
+        assert!(error.to_string().contains("message 1"), "{error}");
+        assert!(core
+            .list_attachments(session_id.clone())
+            .expect("attachments")
+            .is_empty());
+        assert!(core
+            .create_message(
+                session_id.clone(),
+                "assistant".to_owned(),
+                "好的".to_owned(),
+                None,
+                Some("not json".to_owned()),
+            )
+            .is_err());
+
+        let created = core
+            .create_messages_bulk(
+                session_id.clone(),
+                vec![
+                    message("user", &pasted, None),
+                    message("assistant", &pasted, Some("[]")),
+                ],
+            )
+            .expect("import");
+        let attachments = core
+            .list_attachments(session_id.clone())
+            .expect("attachments");
+        assert_eq!(attachments.len(), 1);
+        assert!(created[0]
+            .content
+            .contains(&format!("【长文本已存为附件】{}", attachments[0].file_name)));
+        assert_eq!(created[1].content, pasted);
+
+        let single = core
+            .create_message(session_id.clone(), "user".to_owned(), pasted, None, None)
+            .expect("single");
+        assert!(single.content.contains("【长文本已存为附件】"));
+        assert_eq!(
+            core.list_attachments(session_id)
+                .expect("attachments")
+                .len(),
+            2
+        );
     }
 
-    fn guard_not_cancelled(&self) -> CoreResult<()> {
-        if self.control.is_cancelled() {
-            return Err(CoreError::Cancelled);
+    #[test]
+    fn attached_evidence_is_digested_into_the_report() {
+        let (_temp_dir, core, collector, session_id) = setup_core(6);
+        allow_all_tools(&core);
+        core.set_setting(format!("intake:{session_id}:done"), "1".to_owned())
+            .expect("mark intake done");
+        core.add_attachment(
+            session_id.clone(),
+            "工资流水.jpg".to_owned(),
+            "image/jpeg".to_owned(),
+            "招商银行 交易明细\n2024年2月10日 工资 8000.00元\n2024年3月至6月 无工资入账记录"
+                .to_owned(),
+        )
+        .expect("attach");
+
+        core.send_message(session_id.clone(), "请给出分析".to_owned(), None)
+            .expect("send");
+        assert!(collector.wait_for(Duration::from_secs(20), |events| {
+            events.iter().any(|event| event.kind == "completed")
+        }));
+
+        let report = core
+            .get_messages(session_id.clone(), false)
+            .expect("messages")
+            .into_iter()
+            .rev()
+            .find(|message| message.phase.as_deref() == Some("review"))
+            .expect("report")
+            .content;
+        assert!(report.contains(
+            "【证据摘要】\n- 工资流水.jpg：2024年2月10日 工资 8000.00元；2024年3月至6月 无工资入账记录"
+        ));
+        let attachments = core.list_attachments(session_id).expect("attachments");
+        assert!(attachments[0].summary.is_some());
+    }
+
+    #[test]
+    fn report_contains_required_sections_and_citations() {
+        let (temp_dir, core, collector, session_id) = setup_core(8);
+        allow_all_tools(&core);
+        core.set_setting(format!("intake:{session_id}:done"), "1".to_owned())
+            .expect("mark intake done");
+
+        core.send_message(session_id.clone(), "请生成劳动仲裁报告".to_owned(), None)
+            .expect("send");
+
+        let mut report_text = String::new();
+        let has_report = collector.wait_for(Duration::from_secs(20), |events| {
+            for event in events.iter().rev() {
+                if event.kind == "completed" && event.payload.contains("\"report\"") {
+                    return true;
+                }
+            }
+            false
+        });
+        assert!(has_report, "report completion event not observed");
+
+        for event in collector.snapshot().iter().rev() {
+            if event.kind == "completed" {
+                if let Ok(data) = serde_json::from_str::<serde_json::Value>(&event.payload) {
+                    if let Some(text) = data.get("report").and_then(serde_json::Value::as_str) {
+                        report_text = text.to_owned();
+                        break;
+                    }
+                }
+            }
         }
-        Ok(())
-    }
 
-    fn intake_acknowledgement(&self, answered_index: usize, answer: &str) -> &'static str {
-        if answer.contains("（用户跳过此题）") || answer.contains("跳过") {
-            return "好的，这题先记为待补充，不影响我们继续往下走。";
-        }
+        assert!(report_text.contains("【事实摘要】"));
+        assert!(report_text.contains("【法律分析】"));
+        assert!(report_text.contains("【办事路径】"));
+        assert!(report_text.contains("【办理地点】"));
+        assert!(report_text.contains("【风险提示】"));
+        assert!(report_text.contains("【免责声明】"));
+        assert!(report_text.contains("【引用】"));
+        assert!(report_text.contains("【名词解释】\n- "));
 
-        const ACKS: [&str; 4] = [
-            "收到，这条信息很有帮助。",
-            "明白了，我已经记下这一点。",
-            "好的，信息很关键，继续下一题。",
-            "了解，感谢补充，我们再确认下一项。",
-        ];
-        ACKS[answered_index % ACKS.len()]
+        let provenance = core
+            .get_report_provenance(session_id.clone())
+            .expect("provenance");
+        let legal = provenance
+            .sections
+            .iter()
+            .find(|section| section.section == "legal_analysis")
+            .expect("legal analysis sources");
+        assert!(!legal.chunk_ids.is_empty());
+        let path = temp_dir.path().join("report.md");
+        core.export_report_markdown(session_id, path.to_string_lossy().to_string(), true)
+            .expect("export");
+        let exported = fs::read_to_string(&path).expect("read export");
+        assert!(exported.contains("【法律分析】[^1]"));
+        assert!(exported.contains(&format!("[^1]: 条文：{}", legal.chunk_ids.join("、"))));
+        let statement = &legal.statements[0];
+        assert!(exported.contains(&format!("{}[^2]", statement.text)));
+        assert!(exported.contains(&format!("[^2]: 条文：{}", statement.chunk_ids[0])));
     }
-}
 
-fn emit_event_static(
-    listeners: &Arc<Mutex<HashMap<u64, Arc<dyn EventListener>>>>,
-    kind: &str,
-    payload: String,
-) {
-    let event = CoreEvent {
-        kind: kind.to_owned(),
-        payload,
-        timestamp: Utc::now().timestamp(),
-    };
+    #[test]
+    fn kb_health_reports_anonymous_usage_per_file() {
+        let (_temp_dir, core, collector, session_id) = setup_core(6);
+        allow_all_tools(&core);
+        core.set_analytics_enabled(true).expect("enable analytics");
+        core.set_setting(format!("intake:{session_id}:done"), "1".to_owned())
+            .expect("mark intake done");
 
-    let listeners_snapshot = match listeners.lock() {
-        Ok(lock) => lock.values().cloned().collect::<Vec<_>>(),
-        Err(_) => return,
-    };
+        core.send_message(session_id, "公司拖欠工资".to_owned(), None)
+            .expect("send");
+        assert!(collector.wait_for(Duration::from_secs(20), |events| {
+            events.iter().any(|event| event.kind == "completed")
+        }));
 
-    for listener in listeners_snapshot {
-        listener.on_event(event.clone());
+        assert!(core
+            .storage
+            .list_kb_usage()
+            .expect("raw usage")
+            .iter()
+            .any(|(_, _, citations)| *citations >= 1));
+        let stats = core.get_kb_usage_stats().expect("usage stats");
+        assert!(stats.iter().all(|file| !file.file_path.contains('#')));
+
+        let health = core.get_kb_health().expect("health");
+        assert_eq!(
+            health.usage.len(),
+            core.list_knowledge_files(None, None).expect("files").len()
+        );
+        assert_eq!(
+            health.uncited_files as usize,
+            health
+                .usage
+                .iter()
+                .filter(|file| file.citations == 0)
+                .count()
+        );
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use std::fs;
-    use std::sync::{Arc, Mutex};
-    use std::thread;
-    use std::time::{Duration, Instant};
 
-    use tempfile::TempDir;
+    #[test]
+    fn core_config_updates_in_place() {
+        let (temp_dir, core, collector, _session_id) = setup_core(4);
+        let other_kb = temp_dir.path().join("other-kb");
+        fs::create_dir_all(other_kb.join("labor")).expect("create labor dir");
+        fs::write(other_kb.join("labor/wage.md"), "# 工资\n按月足额支付。").expect("write");
+        let config = |kb_path: &std::path::Path, db_path: &std::path::Path, max_iterations: u32| {
+            CoreConfig::new(
+                kb_path.to_string_lossy().to_string(),
+                db_path.to_string_lossy().to_string(),
+                max_iterations,
+            )
+        };
+        let db_path = temp_dir.path().join("core.db");
 
-    use super::{Core, CoreConfig, CoreEvent, EventListener};
+        assert!(core
+            .update_core_config(config(&other_kb, &db_path, 0))
+            .is_err());
+        assert!(core
+            .update_core_config(config(&other_kb, &temp_dir.path().join("x.db"), 9))
+            .is_err());
+        assert!(core.core_info().contains("max_iterations=4"));
+
+        core.update_core_config(config(&other_kb, &db_path, 9))
+            .expect("update");
+        assert!(core.core_info().contains("max_iterations=9"));
+        assert_eq!(
+            core.list_knowledge_files(None, None)
+                .expect("files")
+                .into_iter()
+                .map(|file| file.relative_path)
+                .collect::<Vec<_>>(),
+            ["labor/wage.md"]
+        );
+        assert!(collector.wait_for(Duration::from_secs(5), |events| {
+            events.iter().any(|event| {
+                event.kind == "config_updated" && event.payload.contains("\"kb_reloaded\":true")
+            })
+        }));
 
-    #[derive(Clone, Default)]
-    struct EventCollector {
-        events: Arc<Mutex<Vec<CoreEvent>>>,
+        let reloads = || {
+            collector
+                .snapshot()
+                .iter()
+                .filter(|event| {
+                    event.kind == "config_updated" && event.payload.contains("\"kb_reloaded\":true")
+                })
+                .count()
+        };
+        let before = reloads();
+        core.update_core_config(CoreConfig {
+            retrieval: Some(RetrievalConfig::default()),
+            ..config(&other_kb, &db_path, 9)
+        })
+        .expect("same retrieval settings");
+        core.update_core_config(CoreConfig {
+            retrieval: Some(RetrievalConfig {
+                max_results_per_title: 1,
+                ..RetrievalConfig::default()
+            }),
+            ..config(&other_kb, &db_path, 9)
+        })
+        .expect("new retrieval settings");
+        assert!(collector.wait_for(Duration::from_secs(5), |events| {
+            events
+                .iter()
+                .filter(|event| event.kind == "config_updated")
+                .count()
+                >= 3
+        }));
+        assert_eq!(reloads(), before + 1);
     }
 
-    impl EventCollector {
-        fn push(&self, event: CoreEvent) {
-            if let Ok(mut events) = self.events.lock() {
-                events.push(event);
+    #[test]
+    fn message_content_is_encrypted_at_rest() {
+        struct Keystore(Option<&'static str>);
+        impl SecretProvider for Keystore {
+            fn get_secret(&self, name: String) -> Option<String> {
+                (name == MESSAGE_CONTENT_KEY_SECRET)
+                    .then_some(self.0)
+                    .flatten()
+                    .map(ToOwned::to_owned)
             }
         }
 
-        fn snapshot(&self) -> Vec<CoreEvent> {
-            self.events
-                .lock()
-                .map(|events| events.clone())
-                .unwrap_or_default()
-        }
+        let (temp_dir, core, _collector, session_id) = setup_core(4);
+        core.storage
+            .create_message(&session_id, "user", "公司拖欠工资三个月", None, None)
+            .expect("plain message");
+        core.storage
+            .index_case_text(&session_id, "facts", "拖欠工资三个月")
+            .expect("index");
+        save_answer(&core.storage, &session_id, 1, "拖欠工资三个月", None).expect("answer");
+        core.storage
+            .append_task_trace("task-1", &session_id, "phase", r#"{"facts":"拖欠工资"}"#)
+            .expect("trace");
+        core.storage
+            .record_safety_incident(&session_id, None, "rule", "legal", "warning", "拖欠工资")
+            .expect("incident");
+        core.storage
+            .append_query_log("hash", Some("拖欠工资 仲裁"), "labor", 1, &[])
+            .expect("query log");
 
-        fn wait_for<F>(&self, timeout: Duration, predicate: F) -> bool
-        where
-            F: Fn(&[CoreEvent]) -> bool,
-        {
-            let deadline = Instant::now() + timeout;
-            while Instant::now() < deadline {
-                let snapshot = self.snapshot();
-                if predicate(&snapshot) {
-                    return true;
-                }
-                thread::sleep(Duration::from_millis(30));
-            }
-            false
+        let db_path = temp_dir.path().join("core.db");
+        let config = CoreConfig {
+            encrypt_message_content: true,
+            ..CoreConfig::new(
+                temp_dir.path().join("kb").to_string_lossy().to_string(),
+                db_path.to_string_lossy().to_string(),
+                4,
+            )
+        };
+        // Without a key, turning encryption on would make every write fail.
+        assert!(matches!(
+            core.update_core_config(config.clone()),
+            Err(CoreError::Config(_))
+        ));
+        assert!(!core.storage.content_encryption());
+        core.set_secret_provider(Box::new(Keystore(None)))
+            .expect("provider without the key");
+        assert!(core.update_core_config(config.clone()).is_err());
+
+        core.set_secret_provider(Box::new(Keystore(Some("device-key-0123456789abcdef"))))
+            .expect("provider");
+        core.update_core_config(config).expect("enable encryption");
+        core.storage
+            .create_message(&session_id, "assistant", "建议申请劳动仲裁", None, None)
+            .expect("sealed message");
+
+        let raw = rusqlite::Connection::open(&db_path).expect("raw db");
+        let column = |sql: &str| {
+            raw.prepare(sql)
+                .and_then(|mut stmt| {
+                    stmt.query_map([], |row| row.get::<_, String>(0))?
+                        .collect::<Result<Vec<_>, _>>()
+                })
+                .expect("raw column")
+        };
+        let stored = column("SELECT content FROM messages");
+        assert_eq!(stored.len(), 2);
+        for sql in [
+            "SELECT content FROM messages",
+            "SELECT value FROM settings WHERE key LIKE 'intake:%:answer:%'",
+            "SELECT data FROM task_traces",
+            "SELECT context FROM safety_incidents",
+            "SELECT query_text FROM query_log",
+        ] {
+            let values = column(sql);
+            assert!(!values.is_empty(), "{sql}");
+            assert!(
+                values
+                    .iter()
+                    .all(|value| ContentCipher::is_sealed(value) && !value.contains("工资")),
+                "{sql}"
+            );
         }
-    }
+        let indexed: u32 = raw
+            .query_row("SELECT COUNT(*) FROM case_index", [], |row| row.get(0))
+            .expect("index rows");
+        assert_eq!(indexed, 0);
 
-    struct TestListener {
-        collector: EventCollector,
+        let contents = core
+            .storage
+            .get_messages(&session_id)
+            .expect("messages")
+            .into_iter()
+            .map(|message| message.content)
+            .collect::<Vec<_>>();
+        assert_eq!(contents, ["公司拖欠工资三个月", "建议申请劳动仲裁"]);
+        assert!(collect_facts(&core.storage, &session_id, "labor")
+            .expect("facts")
+            .iter()
+            .any(|(_, answer)| answer == "拖欠工资三个月"));
+        assert_eq!(
+            core.storage.get_task_trace("task-1").expect("trace")[0].data,
+            r#"{"facts":"拖欠工资"}"#
+        );
+        assert_eq!(
+            core.storage
+                .list_safety_incidents(&SafetyIncidentFilter::default())
+                .expect("incidents")[0]
+                .context,
+            "拖欠工资"
+        );
+        assert_eq!(
+            core.storage.list_query_log(0).expect("query log")[0]
+                .query_text
+                .as_deref(),
+            Some("拖欠工资 仲裁")
+        );
+        assert!(core.search_across_sessions("拖欠工资".to_owned()).is_err());
+        assert!(core
+            .set_secret_provider(Box::new(Keystore(Some("some other key"))))
+            .is_err());
     }
 
-    impl EventListener for TestListener {
-        fn on_event(&self, event: CoreEvent) {
-            self.collector.push(event);
-        }
+    #[test]
+    fn delete_all_user_data_needs_a_token_and_wipes_everything() {
+        let (_temp_dir, core, collector, session_id) = setup_core(4);
+        core.storage
+            .create_message(&session_id, "user", "公司拖欠工资三个月", None, None)
+            .expect("message");
+        save_answer(&core.storage, &session_id, 0, "深圳", None).expect("answer");
+        core.add_attachment(
+            session_id.clone(),
+            "工资条.txt".to_owned(),
+            "text/plain".to_owned(),
+            "3月工资 8000".to_owned(),
+        )
+        .expect("attachment");
+        core.set_setting("retention:log_days".to_owned(), "30".to_owned())
+            .expect("app setting");
+        let layout = core.data_dir_layout();
+        let payslip = std::path::Path::new(&layout.attachments_dir).join("scans/payslip.jpg");
+        std::fs::create_dir_all(payslip.parent().expect("parent")).expect("scans dir");
+        std::fs::write(&payslip, b"jpeg").expect("attachment file");
+        std::fs::write(
+            std::path::Path::new(&layout.cache_dir).join("ocr.json"),
+            b"{}",
+        )
+        .expect("cache file");
+
+        let preview = core.preview_delete_all_user_data().expect("preview");
+        assert_eq!(
+            (
+                preview.summary.session_count,
+                preview.summary.message_count,
+                preview.summary.attachment_count,
+                preview.summary.fact_count,
+                preview.summary.file_count,
+            ),
+            (1, 1, 1, 1, 2)
+        );
+        // A wrong token fails and uses up the issued one.
+        assert!(core.delete_all_user_data("guess".to_owned()).is_err());
+        assert!(core
+            .delete_all_user_data(preview.confirm_token.clone())
+            .is_err());
+        assert!(core
+            .storage
+            .get_session(&session_id)
+            .expect("session")
+            .is_some());
+
+        let preview = core.preview_delete_all_user_data().expect("preview");
+        let erased = core
+            .delete_all_user_data(preview.confirm_token.clone())
+            .expect("erase");
+        assert_eq!(erased, preview.summary);
+        assert!(core.delete_all_user_data(preview.confirm_token).is_err());
+
+        assert!(core.list_sessions(None).expect("sessions").is_empty());
+        assert!(core
+            .list_settings(format!("intake:{session_id}:"))
+            .expect("settings")
+            .is_empty());
+        assert_eq!(
+            core.get_setting("retention:log_days".to_owned())
+                .expect("setting")
+                .as_deref(),
+            Some("30")
+        );
+        assert!(!payslip.exists() && payslip.parent().is_some_and(|dir| !dir.exists()));
+        assert!(std::path::Path::new(&layout.attachments_dir).is_dir());
+        assert_eq!(
+            core.preview_delete_all_user_data()
+                .expect("preview")
+                .summary
+                .file_count,
+            0
+        );
+        assert!(collector.snapshot().iter().any(|event| {
+            event.kind == "user_data_erased" && event.payload.contains("\"file_count\":2")
+        }));
     }
 
-    fn setup_core(max_iterations: u32) -> (TempDir, Arc<Core>, EventCollector, String) {
-        setup_core_with_doc(
-            max_iterations,
-            "# 劳动仲裁\n拖欠工资可申请劳动仲裁，准备劳动合同、工资流水和沟通记录。",
-        )
+    #[test]
+    fn health_check_reports_each_subsystem() {
+        let (temp_dir, core, _collector, _session_id) = setup_core(4);
+        let report = core.health_check().expect("health");
+        assert_eq!(report.storage.level, HealthLevel::Ok);
+        assert!(report.storage.writable);
+        assert_eq!(report.storage.schema_version, SCHEMA_VERSION);
+        assert_eq!(
+            (
+                report.knowledge_base.level,
+                report.knowledge_base.file_count
+            ),
+            (HealthLevel::Ok, 1)
+        );
+        // Running without a model is supported but worth pointing out.
+        assert_eq!(
+            (
+                report.model.level,
+                report.model.configured,
+                report.model.reachable
+            ),
+            (HealthLevel::Warn, false, None)
+        );
+        assert_eq!(report.tasks.running_tasks + report.tasks.queued_tasks, 0);
+        assert!(report.disk.available_bytes.is_some());
+        assert!(report.level >= HealthLevel::Warn);
+
+        std::fs::remove_file(temp_dir.path().join("kb/labor/law.md")).expect("remove kb file");
+        let report = core.health_check().expect("health");
+        // Searches fall back to the bundled statutes when the build has them.
+        let expected = if cfg!(feature = "builtin-kb") {
+            HealthLevel::Warn
+        } else {
+            HealthLevel::Fail
+        };
+        assert_eq!(
+            (
+                report.knowledge_base.level,
+                report.knowledge_base.builtin_fallback,
+                report.level
+            ),
+            (expected, cfg!(feature = "builtin-kb"), expected)
+        );
     }
 
-    fn setup_core_with_doc(
-        max_iterations: u32,
-        labor_doc_content: &str,
-    ) -> (TempDir, Arc<Core>, EventCollector, String) {
-        let temp_dir = TempDir::new().expect("temp dir");
-        let kb_root = temp_dir.path().join("kb");
-        let labor = kb_root.join("labor");
-        fs::create_dir_all(&labor).expect("create labor dir");
-        fs::write(labor.join("law.md"), labor_doc_content).expect("write kb file");
+    #[test]
+    fn glossary_section_follows_session_toggle() {
+        let (_temp_dir, core, collector, session_id) = setup_core(6);
+        allow_all_tools(&core);
+        core.set_setting(format!("intake:{session_id}:done"), "1".to_owned())
+            .expect("mark intake done");
+        assert!(core
+            .set_session_preference(
+                session_id.clone(),
+                "glossary".to_owned(),
+                "maybe".to_owned()
+            )
+            .is_err());
+        core.set_session_preference(session_id.clone(), "glossary".to_owned(), "off".to_owned())
+            .expect("turn glossary off");
 
-        let db_path = temp_dir.path().join("core.db");
-        let core = Core::new(CoreConfig {
-            kb_path: kb_root.to_string_lossy().to_string(),
-            db_path: db_path.to_string_lossy().to_string(),
-            max_iterations,
-        })
-        .expect("init core");
+        core.send_message(session_id.clone(), "请给出分析".to_owned(), None)
+            .expect("send");
+        assert!(collector.wait_for(Duration::from_secs(20), |events| {
+            events.iter().any(|event| event.kind == "completed")
+        }));
 
-        let collector = EventCollector::default();
-        core.subscribe_events(Box::new(TestListener {
-            collector: collector.clone(),
-        }))
-        .expect("subscribe");
+        let report = core
+            .get_messages(session_id, false)
+            .expect("messages")
+            .into_iter()
+            .rev()
+            .find(|message| message.phase.as_deref() == Some("review"))
+            .expect("report")
+            .content;
+        assert!(report.contains("劳动仲裁"));
+        assert!(!report.contains("【名词解释】"));
+    }
 
-        let session_id = core
-            .create_session("labor".to_owned(), Some("测试".to_owned()))
-            .expect("create session");
+    #[test]
+    fn report_goes_stale_when_kb_changes_after_it() {
+        let (temp_dir, core, collector, session_id) = setup_core(6);
+        allow_all_tools(&core);
+        assert!(matches!(
+            core.check_report_freshness(session_id.clone()),
+            Err(CoreError::NotFound(_))
+        ));
+        core.set_setting(format!("intake:{session_id}:done"), "1".to_owned())
+            .expect("mark intake done");
+        core.send_message(session_id.clone(), "请给出分析".to_owned(), None)
+            .expect("send");
+        assert!(collector.wait_for(Duration::from_secs(20), |events| {
+            events.iter().any(|event| event.kind == "completed")
+        }));
+
+        let fresh = core
+            .check_report_freshness(session_id.clone())
+            .expect("freshness");
+        assert!(!fresh.stale);
+        assert!(fresh.changed_files.is_empty());
+        assert_eq!(fresh.report_kb_updated_at, fresh.current_kb_updated_at);
+
+        let added = temp_dir.path().join("kb/labor/new.md");
+        fs::write(&added, "# 新规\n加班费计算基数调整。").expect("write kb file");
+        fs::File::options()
+            .write(true)
+            .open(&added)
+            .and_then(|file| {
+                file.set_modified(std::time::SystemTime::now() + Duration::from_secs(60))
+            })
+            .expect("set mtime");
 
-        (temp_dir, core, collector, session_id)
+        let stale = core
+            .check_report_freshness(session_id.clone())
+            .expect("freshness");
+        assert!(stale.stale);
+        assert_eq!(stale.message_id, fresh.message_id);
+        assert_eq!(stale.changed_files, ["labor/new.md"]);
+        assert!(stale.current_kb_updated_at > stale.report_kb_updated_at);
+
+        let hint = collector
+            .snapshot()
+            .into_iter()
+            .find(|event| event.kind == "report_stale")
+            .expect("report_stale event");
+        let payload = serde_json::from_str::<serde_json::Value>(&hint.payload).expect("payload");
+        assert_eq!(payload["session_id"], session_id.as_str());
+        assert_eq!(payload["suggested_mode"], "citations_only");
     }
 
-    /// Set all built-in tools to "allow" so Agent never blocks on permission
-    fn allow_all_tools(core: &Core) {
-        for tool_name in [
-            "ask_user",
-            "kb_search",
-            "kb_read",
-            "cite",
-            "summarize_facts",
-            "check_safety",
-            "suggest_escalation",
-        ] {
-            core.set_tool_permission(tool_name.to_owned(), "allow".to_owned())
-                .expect("allow tool");
+    #[test]
+    fn citations_only_regeneration_keeps_the_other_sections() {
+        let (_temp_dir, core, collector, session_id) = setup_core(6);
+        allow_all_tools(&core);
+        core.set_setting(format!("intake:{session_id}:done"), "1".to_owned())
+            .expect("mark intake done");
+        core.send_message(session_id.clone(), "请给出分析".to_owned(), None)
+            .expect("send");
+        assert!(collector.wait_for(Duration::from_secs(20), |events| {
+            events.iter().any(|event| event.kind == "completed")
+        }));
+        // The sections are recorded right after the reply.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while core.events.has_session_tasks(&session_id) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(20));
         }
+        let mut kept = crate::agent::drafted_sections(&core.storage, &session_id)
+            .expect("load sections")
+            .expect("sections recorded with the report");
+        kept.facts_summary = "沿用上一份报告的事实摘要".to_owned();
+        crate::agent::record_drafted_sections(&core.storage, &session_id, &kept)
+            .expect("record sections");
+
+        let task_id = core
+            .regenerate_report_with_mode(
+                session_id.clone(),
+                ReportRegenerationMode::CitationsOnly,
+                None,
+            )
+            .expect("regenerate");
+        assert!(collector.wait_for(Duration::from_secs(20), |events| {
+            events
+                .iter()
+                .filter(|event| event.kind == "completed")
+                .count()
+                >= 2
+        }));
+
+        let trace = core.get_task_trace(task_id).expect("trace");
+        let regeneration = trace
+            .iter()
+            .find(|entry| entry.kind == "regeneration")
+            .expect("regeneration traced");
+        assert!(regeneration.data.contains("\"kept_sections\":true"));
+        let report = core.latest_report(&session_id).expect("report");
+        assert!(report.content.contains("沿用上一份报告的事实摘要"));
     }
 
     #[test]
-    fn agent_phase_transitions_plan_draft_review() {
-        let (_temp_dir, core, collector, session_id) = setup_core(12);
+    fn reviewed_reports_reach_the_user_only_once_approved() {
+        let (temp_dir, core, collector, session_id) = setup_core(6);
         allow_all_tools(&core);
+        core.update_core_config(CoreConfig {
+            require_report_review: true,
+            ..CoreConfig::new(
+                temp_dir.path().join("kb").to_string_lossy().to_string(),
+                temp_dir
+                    .path()
+                    .join("core.db")
+                    .to_string_lossy()
+                    .to_string(),
+                6,
+            )
+        })
+        .expect("require review");
+        core.set_setting(format!("intake:{session_id}:done"), "1".to_owned())
+            .expect("mark intake done");
+        let submissions = |count: usize| {
+            collector.wait_for(Duration::from_secs(20), |events| {
+                events
+                    .iter()
+                    .filter(|event| event.kind == "report_pending_review")
+                    .count()
+                    >= count
+            })
+        };
+        let reports = |core: &Core| {
+            core.get_messages(session_id.clone(), false)
+                .expect("messages")
+                .into_iter()
+                .filter(|message| message.phase.as_deref() == Some("review"))
+                .count()
+        };
 
-        // First message starts intake (question 1/6)
-        core.send_message(session_id.clone(), "我想咨询劳动仲裁".to_owned())
-            .expect("start intake");
+        core.send_message(session_id.clone(), "请给出分析".to_owned(), None)
+            .expect("send");
+        assert!(submissions(1));
+        assert_eq!(reports(&core), 0);
+        let session = core
+            .storage
+            .get_session(&session_id)
+            .expect("get session")
+            .expect("session");
+        assert_eq!(session.status, "pending_review");
+        let pending = core.list_pending_reviews().expect("pending");
+        assert_eq!(pending.len(), 1);
+        assert!(pending[0].report.contains("【事实摘要】"));
+        assert!(pending[0].previous_comment.is_none());
+        // Nothing of the withheld draft reaches the user.
+        assert!(!collector
+            .snapshot()
+            .iter()
+            .any(|event| event.kind == "report_section"));
+        let export_path = temp_dir.path().join("report.md");
+        let export = || {
+            core.export_report_markdown(
+                session_id.clone(),
+                export_path.to_string_lossy().into_owned(),
+                false,
+            )
+        };
+        assert!(matches!(export(), Err(CoreError::NotFound(_))));
+        assert!(matches!(
+            core.generate_report(session_id.clone()),
+            Err(CoreError::NotFound(_))
+        ));
+
+        assert!(matches!(
+            core.request_changes(session_id.clone(), " ".to_owned(), None),
+            Err(CoreError::Config(_))
+        ));
+        core.request_changes(session_id.clone(), "补充仲裁时效".to_owned(), None)
+            .expect("request changes");
+        assert!(submissions(2));
+        let pending = core.list_pending_reviews().expect("pending");
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].previous_comment.as_deref(), Some("补充仲裁时效"));
+        assert_eq!(reports(&core), 0);
+
+        let released = core.approve_report(session_id.clone()).expect("approve");
+        assert_eq!(released.id, pending[0].message_id);
+        assert_eq!(reports(&core), 1);
+        export().expect("export once approved");
+        assert!(core.list_pending_reviews().expect("pending").is_empty());
+        let session = core
+            .storage
+            .get_session(&session_id)
+            .expect("get session")
+            .expect("session");
+        assert_eq!(session.status, "active");
+        assert!(collector
+            .snapshot()
+            .iter()
+            .any(|event| event.kind == "report_released"));
+        assert!(matches!(
+            core.approve_report(session_id),
+            Err(CoreError::NotFound(_))
+        ));
+    }
 
-        // Wait for first intake question to complete before sending answers
-        // (per-session lock ensures serialization)
-        for idx in 0..6 {
-            // Small pause to let the per-session lock serialize
-            thread::sleep(Duration::from_millis(200));
-            core.send_message(session_id.clone(), format!("补充信息{}", idx + 1))
-                .expect("send answer");
+    #[test]
+    fn interactive_tasks_jump_the_session_queue() {
+        let (_temp_dir, core, collector, session_id) = setup_core(6);
+        allow_all_tools(&core);
+        core.set_setting(format!("intake:{session_id}:done"), "1".to_owned())
+            .expect("mark intake done");
+        core.set_session_step_through(session_id.clone(), true)
+            .expect("step through");
+        let blocking = core
+            .send_message(session_id.clone(), "请给出分析".to_owned(), None)
+            .expect("send");
+        assert!(collector.wait_for(Duration::from_secs(20), |events| {
+            events.iter().any(|event| event.kind == "task_paused")
+        }));
+        core.set_session_step_through(session_id.clone(), false)
+            .expect("step through off");
+
+        let background = core
+            .regenerate_report(session_id.clone(), Some(TaskPriority::Background))
+            .expect("regenerate");
+        let interactive = core
+            .send_message(
+                session_id.clone(),
+                "加班费怎么算".to_owned(),
+                Some(TaskPriority::Interactive),
+            )
+            .expect("send");
+        let waiting = || {
+            core.session_queues
+                .lock()
+                .expect("queues")
+                .get(&session_id)
+                .map_or(0, |queue| queue.waiting())
+        };
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while waiting() < 2 {
+            assert!(Instant::now() < deadline, "tasks not queued");
+            thread::sleep(Duration::from_millis(20));
         }
+        core.cancel_agent_task(blocking).expect("cancel");
 
-        let has_report = collector.wait_for(Duration::from_secs(30), |events| {
+        let completed_at = |events: &[CoreEvent], task_id: &str| {
             events
                 .iter()
-                .any(|event| event.kind == "completed" && event.payload.contains("\"report\""))
-        });
-        assert!(has_report, "final report completion event not observed");
+                .position(|event| event.kind == "completed" && event.payload.contains(task_id))
+        };
+        assert!(collector.wait_for(Duration::from_secs(30), |events| {
+            completed_at(events, &background).is_some()
+        }));
+        let events = collector.snapshot();
+        let interactive_at =
+            completed_at(&events, &interactive).expect("interactive task completed first");
+        assert!(Some(interactive_at) < completed_at(&events, &background));
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while core.events.has_session_tasks(&session_id) {
+            assert!(Instant::now() < deadline, "tasks not released");
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert!(core.session_queues.lock().expect("queues").is_empty());
+    }
 
-        let phases = collector
-            .snapshot()
-            .into_iter()
-            .filter(|event| event.kind == "agent_phase")
-            .filter_map(|event| {
-                serde_json::from_str::<serde_json::Value>(&event.payload)
-                    .ok()?
-                    .get("phase")
-                    .and_then(serde_json::Value::as_str)
-                    .map(ToOwned::to_owned)
+    #[test]
+    fn step_through_tasks_pause_after_each_phase() {
+        let (_temp_dir, core, collector, session_id) = setup_core(6);
+        allow_all_tools(&core);
+        assert!(core
+            .set_session_step_through("missing".to_owned(), true)
+            .is_err());
+        core.set_session_step_through(session_id.clone(), true)
+            .expect("step through");
+        core.set_setting(format!("intake:{session_id}:done"), "1".to_owned())
+            .expect("mark intake done");
+        let paused_after = |phase: &str| {
+            collector.wait_for(Duration::from_secs(20), |events| {
+                events.iter().any(|event| {
+                    event.kind == "task_paused"
+                        && serde_json::from_str::<Value>(&event.payload)
+                            .is_ok_and(|payload| payload["phase"] == phase)
+                })
             })
-            .collect::<Vec<_>>();
+        };
 
-        assert!(phases.iter().any(|phase| phase == "planning"));
-        assert!(phases.iter().any(|phase| phase == "drafting"));
-        assert!(phases.iter().any(|phase| phase == "reviewing"));
+        let task_id = core
+            .send_message(session_id.clone(), "请给出分析".to_owned(), None)
+            .expect("send");
+        assert!(paused_after("planning"));
+        let intermediate = core
+            .get_task_intermediate(task_id.clone())
+            .expect("intermediate");
+        assert_eq!(intermediate.paused_after.as_deref(), Some("planning"));
+        assert!(!intermediate.facts.is_empty());
+        assert!(intermediate.draft.is_none());
+
+        core.advance_task(task_id.clone()).expect("advance");
+        assert!(paused_after("drafting"));
+        let intermediate = core
+            .get_task_intermediate(task_id.clone())
+            .expect("intermediate");
+        assert!(intermediate.facts_summary.is_some());
+        assert!(!intermediate.retrieved_chunks.is_empty());
+        let draft = intermediate.draft.expect("draft");
+        assert!(draft.contains("【事实摘要】"));
+        assert!(!collector
+            .snapshot()
+            .iter()
+            .any(|event| event.kind == "completed"));
+
+        core.advance_task(task_id.clone()).expect("advance");
+        assert!(paused_after("reviewing"));
+        core.advance_task(task_id.clone()).expect("advance");
+        assert!(collector.wait_for(Duration::from_secs(20), |events| {
+            events.iter().any(|event| event.kind == "completed")
+        }));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while core.get_task_intermediate(task_id.clone()).is_ok() {
+            assert!(Instant::now() < deadline, "task not released");
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        assert!(matches!(
+            core.advance_task(task_id),
+            Err(CoreError::NotFound(_))
+        ));
     }
 
     #[test]
-    fn max_iterations_triggers_error_event() {
-        let (_temp_dir, core, collector, session_id) = setup_core(1);
+    fn muted_session_only_delivers_critical_events() {
+        let (_temp_dir, core, collector, session_id) = setup_core(6);
         allow_all_tools(&core);
+        core.set_setting(format!("intake:{session_id}:done"), "1".to_owned())
+            .expect("mark intake done");
+        assert!(core.set_session_muted("missing".to_owned(), true).is_err());
+        core.archive_session(session_id.clone()).expect("archive");
+        assert!(core.is_session_muted(session_id.clone()));
+        let before = collector.snapshot().len();
+
+        core.send_message(
+            session_id.clone(),
+            "公司说我涉嫌犯罪，要不要找律师".to_owned(),
+            None,
+        )
+        .expect("send");
+        assert!(collector.wait_for(Duration::from_secs(20), |events| {
+            events
+                .iter()
+                .any(|event| event.kind == "escalation_required")
+        }));
+        let deadline = Instant::now() + Duration::from_secs(20);
+        while !core
+            .get_messages(session_id.clone(), false)
+            .expect("messages")
+            .iter()
+            .any(|message| message.phase.as_deref() == Some("review"))
+        {
+            assert!(Instant::now() < deadline, "report not stored");
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        std::thread::sleep(Duration::from_millis(200));
+        let delivered = collector.snapshot()[before..]
+            .iter()
+            .map(|event| event.kind.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(delivered, ["escalation_required"]);
+        let held = core.take_muted_events(session_id.clone());
+        assert!(held.iter().any(|event| event.kind == "tool_call_result"));
+        assert!(held.iter().all(|event| event.kind != "escalation_required"));
+        assert!(core.take_muted_events(session_id.clone()).is_empty());
+
+        core.set_session_muted(session_id.clone(), false)
+            .expect("unmute");
+        assert!(!core.is_session_muted(session_id.clone()));
+        core.send_message(session_id, "请再分析一次".to_owned(), None)
+            .expect("send");
+        assert!(collector.wait_for(Duration::from_secs(20), |events| {
+            events[before..]
+                .iter()
+                .any(|event| event.kind == "tool_call_result")
+        }));
+    }
 
-        // Mark intake as nearly done: set index to last question
-        core.set_setting(format!("intake:{session_id}:idx"), "6".to_owned())
-            .expect("set intake idx");
+    #[test]
+    fn formal_tone_preference_changes_report_wording() {
+        let (_temp_dir, core, collector, session_id) = setup_core(8);
+        allow_all_tools(&core);
+        assert!(core
+            .set_session_preference(session_id.clone(), "tone".to_owned(), "loud".to_owned())
+            .is_err());
+        core.set_session_preference(session_id.clone(), "tone".to_owned(), "formal".to_owned())
+            .expect("set tone");
+        core.set_setting(format!("intake:{session_id}:done"), "1".to_owned())
+            .expect("mark intake done");
 
-        core.send_message(session_id, "最后一题答案".to_owned())
+        core.send_message(session_id, "请生成劳动仲裁报告".to_owned(), None)
             .expect("send");
 
-        let hit_limit = collector.wait_for(Duration::from_secs(10), |events| {
+        let has_formal_report = collector.wait_for(Duration::from_secs(20), |events| {
             events
                 .iter()
-                .any(|event| event.kind == "error" && event.payload.contains("max_iterations"))
+                .any(|event| event.kind == "completed" && event.payload.contains("申请人陈述"))
         });
-        assert!(hit_limit, "max_iterations error event not observed");
+        assert!(has_formal_report, "formal report not observed");
     }
 
     #[test]
-    fn cancel_agent_task_emits_cancelled_event() {
-        let (_temp_dir, core, collector, session_id) = setup_core(6);
-        // Leave ask_user at default "ask" so the agent blocks on tool_call_request
-        // and we can cancel it
+    fn case_metadata_shows_on_cards_and_in_the_report() {
+        let (_temp_dir, core, collector, session_id) = setup_core(8);
+        allow_all_tools(&core);
+        let metadata = |claim_amount: &str, dispute_started_on: &str| CaseMetadata {
+            opposing_party: Some(" 某科技有限公司 ".to_owned()),
+            claim_amount: Some(claim_amount.to_owned()),
+            dispute_started_on: Some(dispute_started_on.to_owned()),
+            jurisdiction: Some(String::new()),
+        };
+        assert!(core
+            .set_case_metadata(session_id.clone(), metadata("很多", "2024-03-01"))
+            .is_err());
+        assert!(core
+            .set_case_metadata(session_id.clone(), metadata("3万元", "2024年3月"))
+            .is_err());
+        core.set_case_metadata(session_id.clone(), metadata("3万元", "2024-03-01"))
+            .expect("set metadata");
+
+        let card = core
+            .list_sessions(None)
+            .expect("sessions")
+            .into_iter()
+            .find(|session| session.id == session_id)
+            .expect("session listed");
+        assert_eq!(
+            card.metadata,
+            CaseMetadata {
+                opposing_party: Some("某科技有限公司".to_owned()),
+                claim_amount: Some("3万元".to_owned()),
+                dispute_started_on: Some("2024-03-01".to_owned()),
+                jurisdiction: None,
+            }
+        );
 
-        let task_id = core
-            .send_message(session_id, "我想咨询劳动仲裁".to_owned())
+        core.set_setting(format!("intake:{session_id}:done"), "1".to_owned())
+            .expect("mark intake done");
+        core.send_message(session_id, "请生成劳动仲裁报告".to_owned(), None)
             .expect("send");
+        assert!(collector.wait_for(Duration::from_secs(20), |events| {
+            events.iter().any(|event| {
+                event.kind == "completed"
+                    && event.payload.contains("对方当事人：某科技有限公司")
+                    && event.payload.contains("争议金额：3万元")
+            })
+        }));
+    }
 
-        let has_request = collector.wait_for(Duration::from_secs(10), |events| {
-            events.iter().any(|event| event.kind == "tool_call_request")
-        });
-        assert!(has_request, "tool call request not emitted");
+    #[test]
+    fn session_time_zone_overrides_the_configured_one() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let config = |time_zone: &str| CoreConfig {
+            time_zone: Some(time_zone.to_owned()),
+            ..CoreConfig::new(
+                temp_dir.path().join("kb").to_string_lossy().to_string(),
+                temp_dir
+                    .path()
+                    .join("core.db")
+                    .to_string_lossy()
+                    .to_string(),
+                4,
+            )
+        };
+        // 2023-11-14 16:00 UTC, already the 15th in China.
+        let clock = Arc::new(ManualClock::at_timestamp(1_699_977_600));
+        assert!(matches!(
+            Core::with_clock(config("Europe/Paris"), clock.clone()),
+            Err(CoreError::Config(_))
+        ));
+        let core = Core::with_clock(config("utc"), clock).expect("core");
+        let session_id = core
+            .create_session("labor".to_owned(), None)
+            .expect("session");
+        let time_zone = |core: &Core| {
+            let diagnostics = core
+                .export_diagnostics(session_id.clone())
+                .expect("diagnostics");
+            serde_json::from_str::<Value>(&diagnostics).expect("json")["time_zone"].clone()
+        };
+        assert_eq!(
+            time_zone(&core),
+            serde_json::json!({"name": "UTC", "utc_offset": "UTC"})
+        );
 
-        core.cancel_agent_task(task_id).expect("cancel");
-        let cancelled = collector.wait_for(Duration::from_secs(10), |events| {
-            events.iter().any(|event| event.kind == "cancelled")
-        });
-        assert!(cancelled, "cancelled event not observed");
+        assert!(core
+            .set_session_preference(session_id.clone(), "timezone".to_owned(), "+25".to_owned())
+            .is_err());
+        core.set_session_preference(
+            session_id.clone(),
+            "timezone".to_owned(),
+            "UTC+8".to_owned(),
+        )
+        .expect("set timezone");
+        assert_eq!(
+            time_zone(&core),
+            serde_json::json!({"name": "UTC+8", "utc_offset": "UTC+08:00"})
+        );
+        let zone = core.time_zone(&session_id).expect("zone");
+        assert_eq!(
+            zone.date(core.clock.now()),
+            chrono::NaiveDate::from_ymd_opt(2023, 11, 15).expect("date")
+        );
     }
 
+    #[cfg(feature = "model-remote")]
     #[test]
-    fn denied_tool_emits_error_event() {
-        let (_temp_dir, core, collector, session_id) = setup_core(6);
-        // Allow all tools first, then deny kb_search specifically
-        allow_all_tools(&core);
-        core.set_tool_permission("summarize_facts".to_owned(), "allow".to_owned())
-            .expect("allow summarize_facts");
-        core.set_tool_permission("kb_search".to_owned(), "deny".to_owned())
-            .expect("deny kb_search");
+    fn draft_waits_at_its_estimate_until_confirmed() {
+        use super::ModelConfig;
 
-        // Skip intake entirely
+        let (_temp_dir, core, collector, session_id) = setup_core(8);
+        allow_all_tools(&core);
+        core.update_model_config(ModelConfig {
+            api_key: "test-key".to_owned(),
+            model_name: "test-model".to_owned(),
+            // Nothing listens on port 1; the style pass falls back to the
+            // template.
+            base_url: Some("http://127.0.0.1:1".to_owned()),
+            retry_max_retries: 0,
+            ..ModelConfig::default()
+        })
+        .expect("update config");
+        core.set_session_preference(session_id.clone(), "tone".to_owned(), "formal".to_owned())
+            .expect("set tone");
+        core.set_session_preference(
+            session_id.clone(),
+            "confirm_draft".to_owned(),
+            "on".to_owned(),
+        )
+        .expect("set confirm_draft");
         core.set_setting(format!("intake:{session_id}:done"), "1".to_owned())
             .expect("mark intake done");
+        assert!(core.confirm_draft(session_id.clone()).is_err());
 
-        core.send_message(session_id, "直接生成报告".to_owned())
+        core.send_message(session_id.clone(), "请生成劳动仲裁报告".to_owned(), None)
             .expect("send");
-
-        let denied_error = collector.wait_for(Duration::from_secs(10), |events| {
+        assert!(collector.wait_for(Duration::from_secs(10), |events| {
+            events.iter().any(|event| event.kind == "completed")
+        }));
+        let estimates = || {
+            collector
+                .snapshot()
+                .into_iter()
+                .filter(|event| event.kind == "estimate")
+                .map(|event| serde_json::from_str::<Value>(&event.payload).expect("payload"))
+                .collect::<Vec<_>>()
+        };
+        let first = &estimates()[0];
+        assert_eq!(first["model_calls"], 1);
+        assert!(first["completion_tokens"].as_u64() > Some(0));
+        assert_eq!(first["awaiting_confirmation"], true);
+        assert!(!collector
+            .snapshot()
+            .iter()
+            .any(|event| event.kind == "agent_phase" && event.payload.contains("drafting")));
+
+        let user_messages = || {
+            core.storage
+                .get_messages(&session_id)
+                .expect("messages")
+                .into_iter()
+                .filter(|message| message.role == "user")
+                .count()
+        };
+        assert_eq!(user_messages(), 1);
+        core.confirm_draft(session_id.clone()).expect("confirm");
+        assert!(collector.wait_for(Duration::from_secs(20), |events| {
             events
                 .iter()
-                .any(|event| event.kind == "error" && event.payload.contains("denied"))
-        });
-        assert!(denied_error, "denied tool error event not observed");
+                .any(|event| event.kind == "completed" && event.payload.contains("【免责声明】"))
+        }));
+        assert_eq!(estimates()[1]["awaiting_confirmation"], false);
+        // Confirming starts the draft without a message in the transcript.
+        assert_eq!(user_messages(), 1);
+        assert!(core.confirm_draft(session_id).is_err());
     }
 
     #[test]
-    fn report_contains_required_sections_and_citations() {
-        let (_temp_dir, core, collector, session_id) = setup_core(8);
-        allow_all_tools(&core);
+    fn selective_export_drops_facts_and_masks_numbers() {
+        let (temp_dir, core, _collector, session_id) = setup_core(6);
+        core.storage
+            .create_message(
+                &session_id,
+                "assistant",
+                "【事实摘要】\n电话13800138000，月薪8000元。\n\n【法律分析】\n联系邮箱 hr@example.com，电话138-0013-8000，可向某某餐饮店主张加班费。\n\n【免责声明】\n仅供参考。",
+                Some("review"),
+                None,
+            )
+            .expect("report");
+
+        core.set_case_metadata(
+            session_id.clone(),
+            CaseMetadata {
+                opposing_party: Some("某某餐饮店".to_owned()),
+                ..CaseMetadata::default()
+            },
+        )
+        .expect("metadata");
+
+        let path = temp_dir.path().join("share.md");
+        let shared = core
+            .export_report_selective(
+                session_id.clone(),
+                ReportSectionFilter {
+                    include: Vec::new(),
+                    exclude: vec!["facts".to_owned()],
+                },
+                ShareFormat::Markdown,
+                true,
+                Some(path.to_string_lossy().into_owned()),
+            )
+            .expect("export");
+        let content = String::from_utf8(shared.content).expect("utf-8");
+        assert_eq!(shared.sections, ["legal_analysis", "disclaimer"]);
+        assert_eq!(shared.redactions, 3);
+        assert!(!content.contains("13800138000"));
+        assert!(content.contains("联系邮箱 ***，电话***，可向***主张加班费。"));
+        assert!(shared.file_name.ends_with(".md"));
+        assert_eq!(std::fs::read_to_string(&path).expect("file"), content);
+
+        assert!(core
+            .export_report_selective(
+                session_id,
+                ReportSectionFilter {
+                    include: vec!["contacts".to_owned()],
+                    exclude: Vec::new(),
+                },
+                ShareFormat::Text,
+                true,
+                None,
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn exported_reports_carry_a_fingerprint_that_detects_edits() {
+        let (temp_dir, core, _collector, session_id) = setup_core(6);
+        let report = core
+            .storage
+            .create_message(
+                &session_id,
+                "assistant",
+                "【事实摘要】\n月薪8000元。\n\n【法律分析】\n可主张经济补偿。\n\n【免责声明】\n仅供参考。",
+                Some("review"),
+                None,
+            )
+            .expect("report");
+
+        let path = temp_dir.path().join("share.html");
+        let shared = core
+            .export_report_selective(
+                session_id.clone(),
+                ReportSectionFilter::default(),
+                ShareFormat::Html,
+                true,
+                Some(path.to_string_lossy().into_owned()),
+            )
+            .expect("export");
+        let content = String::from_utf8(shared.content).expect("utf-8");
+        assert!(content.contains("<pre class=\"report-fingerprint\">\n—— 报告指纹 ——"));
+        assert!(content.contains(&format!("会话：{session_id}")));
+        assert!(content.contains("模型：未使用模型（模板生成）"));
+        assert!(content.ends_with("</pre>\n</body>\n</html>\n"));
+
+        let verified = core
+            .verify_report(path.to_string_lossy().into_owned())
+            .expect("verify");
+        assert_eq!(verified.integrity, ReportIntegrity::Intact);
+        assert!(!verified.superseded);
+        let fingerprint = verified.fingerprint.expect("fingerprint");
+        assert_eq!(fingerprint.message_id, report.id);
+        assert!(content.contains(&fingerprint.content_sha256));
+
+        let edited = content.replace("可主张经济补偿", "必然获得经济补偿");
+        let tampered = core.verify_report(edited).expect("verify edited");
+        assert_eq!(tampered.integrity, ReportIntegrity::Modified);
+        assert_eq!(tampered.fingerprint, Some(fingerprint));
+        let crlf = core
+            .verify_report(content.replace('\n', "\r\n"))
+            .expect("verify crlf");
+        assert_eq!(crlf.integrity, ReportIntegrity::Intact);
+        let stripped = content[..content.find("—— 报告指纹 ——").expect("footer")].to_owned();
+        assert_eq!(
+            core.verify_report(stripped)
+                .expect("verify stripped")
+                .integrity,
+            ReportIntegrity::Missing
+        );
+
+        let path = temp_dir.path().join("report.md");
+        core.export_report_markdown(
+            session_id.clone(),
+            path.to_string_lossy().into_owned(),
+            false,
+        )
+        .expect("export markdown");
+        core.storage
+            .create_message(
+                &session_id,
+                "assistant",
+                "【事实摘要】\n更新。",
+                Some("review"),
+                None,
+            )
+            .expect("newer report");
+        let older = core
+            .verify_report(path.to_string_lossy().into_owned())
+            .expect("verify markdown");
+        assert_eq!(older.integrity, ReportIntegrity::Intact);
+        assert!(older.superseded);
+    }
+
+    #[test]
+    fn drifting_session_is_offered_a_split_and_split_by_topic() {
+        let (_temp_dir, core, collector, session_id) = setup_core(6);
         core.set_setting(format!("intake:{session_id}:done"), "1".to_owned())
-            .expect("mark intake done");
+            .expect("skip intake");
+        allow_all_tools(&core);
+        core.storage
+            .create_message(
+                &session_id,
+                "user",
+                "公司拖欠工资三个月",
+                Some("plan"),
+                None,
+            )
+            .expect("labor message");
+        let deposit = core
+            .storage
+            .create_message(&session_id, "user", "另外房东不退押金", Some("plan"), None)
+            .expect("rental message");
+        save_answer(&core.storage, &session_id, 0, "广东深圳", Some(&deposit.id)).expect("answer");
 
-        core.send_message(session_id, "请生成劳动仲裁报告".to_owned())
+        core.send_message(session_id.clone(), "退租时房东说要扣房租".to_owned(), None)
             .expect("send");
+        let suggested = collector
+            .snapshot()
+            .into_iter()
+            .find(|event| event.kind == "session_split_suggested")
+            .expect("split suggested");
+        let payload: Value = serde_json::from_str(&suggested.payload).expect("payload");
+        assert_eq!(payload["topic"], "rental");
+        let message_ids = payload["message_ids"]
+            .as_array()
+            .expect("ids")
+            .iter()
+            .map(|id| id.as_str().expect("id").to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(message_ids.len(), 2);
+        assert!(collector.wait_for(Duration::from_secs(20), |events| {
+            events.iter().any(|event| event.kind == "completed")
+        }));
+
+        let split = core
+            .split_session(session_id.clone(), message_ids, None)
+            .expect("split");
+        assert_eq!(split.scenario, "rental");
+        // The two messages and the report answering the second one.
+        assert_eq!(split.messages_moved, 3);
+        // The rental intake does not ask where the user works.
+        assert_eq!(split.facts_moved, 0);
+        assert_eq!(split.facts_discarded, 1);
+        let moved = core
+            .get_messages(split.session_id.clone(), false)
+            .expect("moved messages");
+        assert!(moved
+            .iter()
+            .any(|message| message.content == "另外房东不退押金"));
+        let kept = core.get_messages(session_id.clone(), false).expect("kept");
+        assert!(!kept.iter().any(|message| message.content.contains("房东")));
+        assert_eq!(
+            core.get_facts(session_id.clone()).expect("facts")[0]
+                .value
+                .as_deref(),
+            Some("暂不清楚")
+        );
+        assert!(core
+            .split_session(session_id.clone(), vec!["missing".to_owned()], None)
+            .is_err());
+
+        core.set_tool_permission("kb_search".to_owned(), "ask".to_owned())
+            .expect("ask kb_search");
+        core.send_message(session_id.clone(), "房东还扣了水电费".to_owned(), None)
+            .expect("send while split");
+        assert!(collector.wait_for(Duration::from_secs(10), |events| {
+            events.iter().any(|event| event.kind == "tool_call_request")
+        }));
+        let waiting = core
+            .get_messages(session_id.clone(), false)
+            .expect("messages")
+            .into_iter()
+            .rfind(|message| message.role == "user")
+            .expect("waiting message");
+        assert!(matches!(
+            core.split_session(session_id.clone(), vec![waiting.id], None),
+            Err(CoreError::InvalidState(_))
+        ));
+        let pending = core.list_pending_tool_calls(session_id).expect("pending");
+        core.respond_tool_call(pending[0].request_id.clone(), ToolResponse::Deny)
+            .expect("deny");
+    }
 
-        let mut report_text = String::new();
-        let has_report = collector.wait_for(Duration::from_secs(20), |events| {
-            for event in events.iter().rev() {
-                if event.kind == "completed" && event.payload.contains("\"report\"") {
-                    return true;
-                }
+    #[test]
+    fn session_activity_pages_through_messages_tools_and_status_changes() {
+        let (_temp_dir, core, collector, session_id) = setup_core(6);
+        core.set_setting(format!("intake:{session_id}:done"), "1".to_owned())
+            .expect("skip intake");
+        allow_all_tools(&core);
+        core.send_message(session_id.clone(), "公司拖欠工资怎么办".to_owned(), None)
+            .expect("send");
+        assert!(collector.wait_for(Duration::from_secs(20), |events| {
+            events.iter().any(|event| event.kind == "completed")
+        }));
+        core.archive_session(session_id.clone()).expect("archive");
+        // Archiving twice is one change.
+        core.archive_session(session_id.clone())
+            .expect("archive again");
+
+        let mut items = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = core
+                .get_session_activity(session_id.clone(), cursor, Some(2))
+                .expect("activity page");
+            assert!(page.items.len() <= 2);
+            items.extend(page.items);
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
             }
-            false
+        }
+        let whole = core
+            .get_session_activity(session_id.clone(), None, Some(500))
+            .expect("whole feed");
+        assert_eq!(
+            items.iter().map(|item| &item.id).collect::<Vec<_>>(),
+            whole.items.iter().map(|item| &item.id).collect::<Vec<_>>()
+        );
+        assert!(whole.next_cursor.is_none());
+        assert!(items
+            .windows(2)
+            .all(|pair| pair[0].created_at <= pair[1].created_at));
+
+        let messages = core
+            .get_messages(session_id.clone(), false)
+            .expect("messages");
+        let feed_messages = items
+            .iter()
+            .filter_map(|item| item.message.as_ref())
+            .map(|message| message.id.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            feed_messages,
+            messages
+                .into_iter()
+                .map(|message| message.id)
+                .collect::<Vec<_>>()
+        );
+        assert!(items
+            .iter()
+            .any(|item| item.kind == ActivityKind::ToolCall && item.task_id.is_some()));
+        assert!(items.iter().any(|item| item.kind == ActivityKind::Phase));
+        let statuses = items
+            .iter()
+            .filter(|item| item.kind == ActivityKind::StatusChange)
+            .collect::<Vec<_>>();
+        assert_eq!(statuses.len(), 1);
+        assert_eq!(
+            serde_json::from_str::<Value>(&statuses[0].data).expect("data")["status"],
+            "archived"
+        );
+
+        assert!(core
+            .get_session_activity(session_id, Some("later".to_owned()), None)
+            .is_err());
+        assert!(core
+            .get_session_activity("missing".to_owned(), None, None)
+            .is_err());
+    }
+
+    #[test]
+    fn persona_config_is_stored_and_refused_when_unsafe() {
+        let (_temp_dir, core, _collector, _session_id) = setup_core(4);
+        assert!(core.get_persona_config().expect("empty persona").is_empty());
+
+        let stored = core
+            .set_persona_config(PersonaConfig {
+                style: vec!["简明扼要 ".to_owned()],
+                forbidden: vec!["不推测当事人的刑事责任".to_owned()],
+                signature_phrases: Vec::new(),
+            })
+            .expect("set persona");
+        assert_eq!(stored.style, ["简明扼要"]);
+        assert_eq!(core.get_persona_config().expect("persona"), stored);
+
+        let refused = core.set_persona_config(PersonaConfig {
+            signature_phrases: vec!["保证胜诉".to_owned()],
+            ..PersonaConfig::default()
         });
-        assert!(has_report, "report completion event not observed");
+        assert!(matches!(refused, Err(CoreError::Safety(_))));
+        assert_eq!(core.get_persona_config().expect("kept persona"), stored);
 
-        for event in collector.snapshot().iter().rev() {
-            if event.kind == "completed" {
-                if let Ok(data) = serde_json::from_str::<serde_json::Value>(&event.payload) {
-                    if let Some(text) = data.get("report").and_then(serde_json::Value::as_str) {
-                        report_text = text.to_owned();
-                        break;
-                    }
-                }
-            }
-        }
+        core.set_persona_config(PersonaConfig::default())
+            .expect("clear persona");
+        assert!(core.get_persona_config().expect("cleared").is_empty());
+    }
 
-        assert!(report_text.contains("【事实摘要】"));
-        assert!(report_text.contains("【法律分析】"));
-        assert!(report_text.contains("【办事路径】"));
-        assert!(report_text.contains("【风险提示】"));
-        assert!(report_text.contains("【免责声明】"));
-        assert!(report_text.contains("【引用】"));
+    #[test]
+    fn quick_ask_answers_from_the_kb_without_starting_intake() {
+        let (_temp_dir, core, _collector, session_id) = setup_core_with_doc(
+            4,
+            "# 仲裁时限\n仲裁庭应当自受理仲裁申请之日起四十五日内结束。",
+        );
+
+        let answer = core
+            .quick_ask(session_id.clone(), "仲裁要多久".to_owned(), Some(2_000))
+            .expect("quick answer");
+        assert!(answer
+            .content
+            .starts_with("【快速回答】\n《仲裁时限》提到："));
+        assert!(answer.content.contains("四十五日"));
+        assert!(answer.content.contains("【免责声明】"));
+
+        let messages = core
+            .get_messages(session_id.clone(), false)
+            .expect("messages");
+        assert_eq!(messages.len(), 2);
+        assert!(messages
+            .iter()
+            .all(|message| message.phase.as_deref() == Some(QUICK_PHASE)));
+        assert_eq!(
+            answer.reply_to_message_id.as_deref(),
+            Some(messages[0].id.as_str())
+        );
+        assert!(core
+            .get_setting(format!("intake:{session_id}:idx"))
+            .expect("intake idx")
+            .is_none());
+        assert!(core.quick_ask(session_id, "  ".to_owned(), None).is_err());
+    }
+
+    #[test]
+    fn quick_ask_answers_get_the_safety_review() {
+        let (_temp_dir, core, _collector, session_id) =
+            setup_core_with_doc(4, "# 仲裁结果\n这类仲裁包赢，不用担心。");
+
+        let answer = core
+            .quick_ask(session_id.clone(), "仲裁能赢吗".to_owned(), Some(2_000))
+            .expect("quick answer");
+        assert!(!answer.content.contains("包赢"));
+        assert!(answer.content.contains("【安全审查】"));
+        let incidents = core
+            .list_safety_incidents(SafetyIncidentFilter {
+                session_id: Some(session_id),
+                ..Default::default()
+            })
+            .expect("incidents");
+        assert!(incidents
+            .iter()
+            .any(|incident| incident.rule_name == "must_win"));
+    }
+
+    #[cfg(feature = "model-remote")]
+    #[test]
+    fn quick_ask_answers_with_the_model_in_its_budget() {
+        use super::ModelConfig;
+
+        let (_temp_dir, core, _collector, session_id) = setup_core_with_doc(
+            4,
+            "# 仲裁时限\n仲裁庭应当自受理仲裁申请之日起四十五日内结束。",
+        );
+        let (base_url, requests) = serve_completions("一般在受理后四十五日内结束。");
+        core.update_model_config(ModelConfig {
+            api_key: "test-key".to_owned(),
+            model_name: "test-model".to_owned(),
+            base_url: Some(base_url),
+            retry_max_retries: 0,
+            ..ModelConfig::default()
+        })
+        .expect("model config");
+
+        let answer = core
+            .quick_ask(session_id, "仲裁要多久".to_owned(), Some(5_000))
+            .expect("quick answer");
+        assert!(answer.content.contains("一般在受理后四十五日内结束。"));
+        assert_eq!(requests.lock().expect("requests").len(), 1);
+    }
+
+    #[cfg(feature = "model-remote")]
+    #[test]
+    fn what_if_answers_speak_in_the_persona() {
+        use super::ModelConfig;
+
+        let (_temp_dir, core, _collector, session_id) = setup_core(4);
+        let overrides = vec![FactOverride {
+            question_index: 0,
+            answer: "上海".to_owned(),
+        }];
+        core.set_persona_config(PersonaConfig {
+            style: vec!["温和体贴".to_owned()],
+            ..PersonaConfig::default()
+        })
+        .expect("persona");
+        let template = core
+            .what_if(session_id.clone(), overrides.clone())
+            .expect("without a model")
+            .content;
+
+        let (base_url, requests) = serve_completions(&format!("您好。{template}"));
+        core.update_model_config(ModelConfig {
+            api_key: "test-key".to_owned(),
+            model_name: "test-model".to_owned(),
+            base_url: Some(base_url),
+            retry_max_retries: 0,
+            ..ModelConfig::default()
+        })
+        .expect("model config");
+        let styled = core
+            .what_if(session_id, overrides)
+            .expect("with a model")
+            .content;
+        assert_eq!(styled, format!("您好。{template}"));
+        assert!(requests
+            .lock()
+            .expect("requests")
+            .iter()
+            .any(|body| body.contains("语气要求：温和体贴")));
+    }
+
+    #[test]
+    fn first_run_demo_follows_the_scenario() {
+        let (_temp_dir, core, _collector, _session_id) = setup_core(4);
+        let result = core
+            .initialize_first_run(FirstRunOptions {
+                create_demo_session: true,
+                demo_scenario: Some("rental".to_owned()),
+                ..FirstRunOptions::default()
+            })
+            .expect("first run");
+
+        let demo = result.demo_session_id.expect("demo session");
+        let session = core
+            .storage
+            .get_session(&demo)
+            .expect("session")
+            .expect("demo stored");
+        assert_eq!(session.scenario, "rental");
+        let report = core.generate_report(demo).expect("demo report");
+        assert!(report.contains("押金3000元"));
+        assert!(report.contains("人民调解委员会"));
+    }
+
+    #[test]
+    fn first_run_seeds_the_install_once() {
+        let (temp_dir, core, collector, _session_id) = setup_core(4);
+        let starter = temp_dir.path().join("starter");
+        fs::create_dir_all(starter.join("labor")).expect("create starter dir");
+        fs::write(
+            starter.join("labor").join("wage.md"),
+            "# 欠薪\n用人单位拖欠工资的，劳动者可以申请劳动仲裁。",
+        )
+        .expect("write starter file");
+        fs::write(starter.join("labor").join("law.md"), "starter copy").expect("write");
+        core.set_tool_permission("kb_read".to_owned(), "deny".to_owned())
+            .expect("user permission");
+
+        let options = FirstRunOptions {
+            starter_kb_path: Some(starter.to_string_lossy().to_string()),
+            tool_permissions: vec![
+                ToolPermissionSeed {
+                    tool_name: "kb_search".to_owned(),
+                    permission: "allow".to_owned(),
+                },
+                ToolPermissionSeed {
+                    tool_name: "kb_read".to_owned(),
+                    permission: "allow".to_owned(),
+                },
+            ],
+            copy_overrides: vec![CopySeed {
+                key: "intake.ack.1".to_owned(),
+                text: "收到。".to_owned(),
+            }],
+            persona: Some(PersonaConfig {
+                style: vec!["温和体贴".to_owned()],
+                ..PersonaConfig::default()
+            }),
+            create_demo_session: true,
+            demo_scenario: None,
+        };
+        assert!(core
+            .initialize_first_run(FirstRunOptions {
+                demo_scenario: Some("tax".to_owned()),
+                ..options.clone()
+            })
+            .is_err());
+        assert!(!core.is_onboarding_complete().expect("not complete"));
+
+        let result = core
+            .initialize_first_run(options.clone())
+            .expect("first run");
+        assert!(!result.already_completed);
+        assert_eq!(result.kb_files_copied, ["labor/wage.md"]);
+        assert_eq!(result.permissions_seeded, 2);
+        assert_eq!(result.copy_overrides_seeded, 1);
+        assert!(result.persona_seeded);
+        assert_eq!(
+            core.get_persona_config().expect("persona").style,
+            ["温和体贴"]
+        );
+        assert_eq!(
+            core.get_tool_permission("kb_search".to_owned(), None)
+                .expect("seeded permission"),
+            "allow"
+        );
+        // Seeds are defaults, not choices the user made.
+        assert_eq!(
+            core.storage
+                .stored_tool_permission("kb_search")
+                .expect("stored permission"),
+            None
+        );
+        assert!(collector
+            .snapshot()
+            .iter()
+            .any(|event| event.kind == "kb_changed"));
+        assert_eq!(
+            core.get_tool_permission("kb_read".to_owned(), None)
+                .expect("permission"),
+            "deny"
+        );
+        assert_eq!(
+            core.get_copy("intake.ack.1".to_owned()).expect("copy"),
+            "收到。"
+        );
+
+        let demo = result.demo_session_id.expect("demo session");
+        let report = core.generate_report(demo.clone()).expect("demo report");
+        assert!(report.contains("拖欠三个月，一共24000元"));
+        assert!(report.contains("《欠薪》提到"));
+        assert_eq!(core.get_messages(demo, false).expect("messages").len(), 2);
+        assert!(core.is_onboarding_complete().expect("complete"));
+
+        let again = core.initialize_first_run(options).expect("second run");
+        assert!(again.already_completed);
+        assert_eq!(again.demo_session_id, None);
+    }
+
+    #[test]
+    fn what_if_answers_get_the_report_safety_review() {
+        let (_temp_dir, core, _collector, session_id) = setup_core(4);
+
+        let answer = core
+            .what_if(
+                session_id.clone(),
+                vec![FactOverride {
+                    question_index: 0,
+                    answer: "老板说这官司包赢".to_owned(),
+                }],
+            )
+            .expect("what if");
+
+        assert!(!answer.content.contains("包赢"));
+        assert!(answer.content.contains("【安全审查】"));
+        let incidents = core
+            .list_safety_incidents(SafetyIncidentFilter {
+                session_id: Some(session_id),
+                ..Default::default()
+            })
+            .expect("incidents");
+        assert!(incidents
+            .iter()
+            .any(|incident| incident.rule_name == "must_win"));
     }
 
     #[test]
@@ -1379,10 +9046,14 @@ mod tests {
         let (_temp_dir, core, collector, session_id) =
             setup_core_with_doc(8, "# 劳动仲裁\n这个方案包赢，保证胜诉。");
         allow_all_tools(&core);
+        // KB output is scanned by default; turn that off so the unsafe text
+        // reaches the review phase.
+        core.set_tool_post_processing("kb_search".to_owned(), Vec::new())
+            .expect("disable kb_search post-processing");
         core.set_setting(format!("intake:{session_id}:done"), "1".to_owned())
             .expect("mark intake done");
 
-        core.send_message(session_id, "请给出分析".to_owned())
+        core.send_message(session_id.clone(), "请给出分析".to_owned(), None)
             .expect("send");
 
         let intercepted = collector.wait_for(Duration::from_secs(20), |events| {
@@ -1391,6 +9062,20 @@ mod tests {
                 .any(|event| event.kind == "review_intercepted")
         });
         assert!(intercepted, "review_intercepted event not observed");
+        assert!(collector.wait_for(Duration::from_secs(10), |events| {
+            events.iter().any(|event| event.kind == "completed")
+        }));
+
+        let incidents = core
+            .list_safety_incidents(SafetyIncidentFilter {
+                session_id: Some(session_id.clone()),
+                severity: Some("critical".to_owned()),
+                ..Default::default()
+            })
+            .expect("incidents");
+        assert!(incidents
+            .iter()
+            .any(|incident| incident.rule_name == "must_win"));
 
         let mut report_text = String::new();
         for event in collector.snapshot().iter().rev() {
@@ -1406,6 +9091,139 @@ mod tests {
 
         assert!(report_text.contains("【安全审查】"));
         assert!(!report_text.contains("包赢"));
+
+        let review = core.get_report_review(session_id).expect("review");
+        assert!(review.edits.iter().any(|edit| edit.original == "包赢"));
+        let chars = report_text.chars().collect::<Vec<_>>();
+        for edit in &review.edits {
+            let start = edit.offset as usize;
+            let at = chars[start..start + edit.replacement.chars().count()]
+                .iter()
+                .collect::<String>();
+            assert_eq!(at, edit.replacement);
+        }
+        assert!(collector.snapshot().iter().any(|event| {
+            event.kind == "review_intercepted" && event.payload.contains("\"original\":\"包赢\"")
+        }));
+    }
+
+    #[test]
+    fn merging_sessions_moves_records_and_settles_fact_conflicts() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let clock = Arc::new(ManualClock::at_timestamp(1_700_000_000));
+        let core = Core::with_clock(
+            CoreConfig::new(
+                temp_dir.path().join("kb").to_string_lossy().to_string(),
+                temp_dir
+                    .path()
+                    .join("core.db")
+                    .to_string_lossy()
+                    .to_string(),
+                4,
+            ),
+            clock.clone(),
+        )
+        .expect("core");
+        let set_fact = |session_id: &str, field: &str, value: &str| {
+            core.set_fact(session_id.to_owned(), field.to_owned(), value.to_owned())
+                .expect("set fact");
+        };
+
+        let target = core
+            .create_session("labor".to_owned(), None)
+            .expect("target");
+        set_fact(&target, "region", "深圳");
+        set_fact(&target, "job_salary", "月薪9000元");
+        set_fact(&target, "goal", "补发工资");
+        clock.advance(Duration::from_secs(60));
+        let source = core
+            .create_session("labor".to_owned(), None)
+            .expect("source");
+        set_fact(&source, "job_salary", "月薪1.2万");
+        set_fact(&source, "goal", "解除合同并要求赔偿");
+        set_fact(&source, "hire_date", "2022年3月入职");
+        let set_preference = |session_id: &str, key: &str, value: &str| {
+            core.set_session_preference(session_id.to_owned(), key.to_owned(), value.to_owned())
+                .expect("set preference");
+        };
+        set_preference(&target, "tone", "formal");
+        set_preference(&source, "tone", "plain");
+        set_preference(&source, "glossary", "off");
+        core.create_message(
+            source.clone(),
+            "user".to_owned(),
+            "老板不发工资".to_owned(),
+            None,
+            None,
+        )
+        .expect("message");
+
+        let prompted = core
+            .create_session("labor".to_owned(), None)
+            .expect("prompted");
+        set_fact(&prompted, "region", "广州");
+
+        assert!(core
+            .merge_sessions(target.clone(), target.clone(), None)
+            .is_err());
+        let merged = core
+            .merge_sessions(source.clone(), target.clone(), None)
+            .expect("merge");
+        assert_eq!(merged.messages_moved, 1);
+        assert_eq!(
+            merged
+                .conflicts
+                .iter()
+                .map(|conflict| (conflict.field.as_str(), conflict.took_source))
+                .collect::<Vec<_>>(),
+            [("job_salary", true), ("goal", true)]
+        );
+
+        let facts = core.get_facts(target.clone()).expect("facts");
+        let value = |field: &str| {
+            facts
+                .iter()
+                .find(|fact| fact.field == field)
+                .and_then(|fact| fact.value.clone())
+        };
+        assert_eq!(value("region").as_deref(), Some("深圳"));
+        assert_eq!(value("hire_date").as_deref(), Some("2022年3月入职"));
+        assert_eq!(value("job_salary").as_deref(), Some("月薪1.2万"));
+        assert_eq!(
+            core.get_messages(target.clone(), false)
+                .expect("messages")
+                .len(),
+            1
+        );
+        let source_session = core
+            .storage
+            .get_session(&source)
+            .expect("get")
+            .expect("source kept");
+        assert_eq!(source_session.status, "archived");
+        let preference = |session_id: &str, key: &str| {
+            core.get_session_preference(session_id.to_owned(), key.to_owned())
+                .expect("preference")
+        };
+        assert_eq!(preference(&target, "tone").as_deref(), Some("formal"));
+        assert_eq!(preference(&target, "glossary").as_deref(), Some("off"));
+        assert_eq!(preference(&source, "glossary"), None);
+        assert!(core
+            .get_facts(source)
+            .expect("source facts")
+            .iter()
+            .all(|fact| fact.value.is_none()));
+
+        let merged = core
+            .merge_sessions(prompted, target.clone(), Some(FactMergePolicy::Prompt))
+            .expect("prompted merge");
+        assert_eq!(merged.conflicts.len(), 1);
+        assert!(!merged.conflicts[0].took_source);
+        assert_eq!(merged.conflicts[0].source_value, "广州");
+        assert_eq!(
+            core.get_facts(target).expect("facts")[0].value.as_deref(),
+            Some("深圳")
+        );
     }
 }
 
@@ -1,10 +1,13 @@
 use std::time::Duration;
 
-use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
-use tokio::time::sleep;
 
-use crate::error::{CoreError, CoreResult};
+use crate::secrets::ApiKey;
+
+#[cfg(feature = "model-remote")]
+pub use remote::ModelConnector;
+#[cfg(not(feature = "model-remote"))]
+pub use unavailable::ModelConnector;
 
 #[derive(Debug, Clone, uniffi::Record)]
 pub struct RetryConfig {
@@ -25,187 +28,687 @@ impl Default for RetryConfig {
     }
 }
 
+/// Connection pool settings for the model HTTP client. Mobile networks drop
+/// idle sockets aggressively, so connections are kept warm with HTTP/2 pings.
+#[derive(Debug, Clone)]
+pub struct HttpPoolConfig {
+    pub idle_timeout: Duration,
+    pub max_idle_per_host: usize,
+    /// `None` disables HTTP/2 keep-alive pings.
+    pub keep_alive_interval: Option<Duration>,
+    pub keep_alive_timeout: Duration,
+}
+
+impl Default for HttpPoolConfig {
+    fn default() -> Self {
+        Self {
+            idle_timeout: Duration::from_secs(90),
+            max_idle_per_host: 4,
+            keep_alive_interval: Some(Duration::from_secs(30)),
+            keep_alive_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Request and connection counters of one connector.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectionStats {
+    /// HTTP requests sent, retries included.
+    pub requests: u64,
+    /// New connections dialed (TCP + TLS handshakes).
+    pub connections_opened: u64,
+}
+
+impl ConnectionStats {
+    /// Requests served over an already open connection.
+    pub fn reused(&self) -> u64 {
+        self.requests.saturating_sub(self.connections_opened)
+    }
+}
+
+/// How the recent requests of a connector went, for health checks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallOutcome {
+    /// Unix seconds of the last call.
+    pub at: i64,
+    /// `None` when the last call succeeded.
+    pub error: Option<String>,
+    /// Calls in a row that failed, up to and including the last.
+    pub consecutive_failures: u32,
+    /// Unix seconds of the last call that succeeded.
+    pub last_success_at: Option<i64>,
+}
+
 #[derive(Debug, Clone)]
 pub struct OpenRouterConfig {
-    pub api_key: String,
+    pub api_key: ApiKey,
     pub model_name: String,
     pub base_url: String,
     pub retry: RetryConfig,
+    /// Estimated prompt tokens above which `chat_completion` refuses to send.
+    /// `0` disables the guard.
+    pub max_prompt_tokens: u32,
+    pub pool: HttpPoolConfig,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, uniffi::Record)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, uniffi::Record)]
 pub struct ChatMessage {
     pub role: String,
     pub content: String,
 }
 
-#[derive(Debug, Deserialize)]
-struct ChatResponse {
-    choices: Vec<Choice>,
+/// Step of `ModelConnector::preflight` that failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreflightStage {
+    /// Listing models: reachability, TLS and the API key.
+    Models,
+    /// A one-token completion: the configured model itself.
+    Completion,
 }
 
-#[derive(Debug, Deserialize)]
-struct Choice {
-    message: ChoiceMessage,
+impl PreflightStage {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Models => "models",
+            Self::Completion => "completion",
+        }
+    }
 }
 
-#[derive(Debug, Deserialize)]
-struct ChoiceMessage {
-    content: String,
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreflightFailure {
+    pub stage: PreflightStage,
+    /// HTTP status of the refused request; `None` when nothing came back.
+    pub status: Option<u16>,
+    pub message: String,
 }
 
-#[derive(Clone)]
-pub struct ModelConnector {
-    client: reqwest::Client,
-    config: OpenRouterConfig,
-}
+#[cfg(feature = "model-remote")]
+mod remote {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll};
+    use std::time::Duration;
 
-impl ModelConnector {
-    pub fn new(config: OpenRouterConfig) -> CoreResult<Self> {
-        if config.api_key.trim().is_empty() {
-            return Err(CoreError::Config("OpenRouter API key is empty".to_owned()));
+    use reqwest::StatusCode;
+    use serde::Deserialize;
+    use tower::Service;
+
+    use crate::clock::Clock;
+    use crate::error::{CoreError, CoreResult};
+    use crate::secrets::ApiKey;
+
+    use super::super::routing::{
+        validate_routes, ModelPhase, ModelPhaseUsage, ModelRoute, PhaseUsageCounters,
+    };
+    use super::super::tokens::TokenEstimator;
+    use super::{
+        CallOutcome, ChatMessage, ConnectionStats, OpenRouterConfig, PreflightFailure,
+        PreflightStage, RetryConfig,
+    };
+
+    #[derive(Debug, Default)]
+    struct ConnectionCounters {
+        requests: AtomicU64,
+        connections_opened: AtomicU64,
+    }
+
+    /// Connector middleware counting every connection the pool has to open.
+    #[derive(Clone)]
+    struct CountConnections<S> {
+        inner: S,
+        counters: Arc<ConnectionCounters>,
+    }
+
+    impl<S, R> Service<R> for CountConnections<S>
+    where
+        S: Service<R>,
+    {
+        type Response = S::Response;
+        type Error = S::Error;
+        type Future = S::Future;
+
+        fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            self.inner.poll_ready(cx)
         }
-        if config.model_name.trim().is_empty() {
-            return Err(CoreError::Config("Model name is empty".to_owned()));
+
+        fn call(&mut self, request: R) -> Self::Future {
+            self.counters
+                .connections_opened
+                .fetch_add(1, Ordering::Relaxed);
+            self.inner.call(request)
         }
+    }
 
-        let client = reqwest::Client::builder()
-            .timeout(Duration::from_secs(60))
-            .build()
-            .map_err(|e| CoreError::Model(e.to_string()))?;
+    #[derive(Debug, Deserialize)]
+    struct ChatResponse {
+        choices: Vec<Choice>,
+    }
 
-        Ok(Self { client, config })
+    #[derive(Debug, Deserialize)]
+    struct Choice {
+        message: ChoiceMessage,
     }
 
-    pub async fn test_connection(&self) -> CoreResult<()> {
-        let base = self.config.base_url.trim_end_matches('/');
-        let url = format!("{base}/models");
+    #[derive(Debug, Deserialize)]
+    struct ChoiceMessage {
+        content: String,
+    }
 
-        let response = self
-            .request_with_retry(|| {
-                self.client
-                    .get(&url)
-                    .header("Authorization", format!("Bearer {}", self.config.api_key))
+    #[derive(Clone)]
+    pub struct ModelConnector {
+        client: reqwest::Client,
+        config: OpenRouterConfig,
+        estimator: TokenEstimator,
+        counters: Arc<ConnectionCounters>,
+        clock: Arc<dyn Clock>,
+        routes: Arc<Vec<ModelRoute>>,
+        phase_usage: Arc<PhaseUsageCounters>,
+        last_outcome: Arc<Mutex<Option<CallOutcome>>>,
+    }
+
+    impl ModelConnector {
+        /// Retry backoff sleeps go through `clock`.
+        pub fn new(config: OpenRouterConfig, clock: Arc<dyn Clock>) -> CoreResult<Self> {
+            if let ApiKey::Static(key) = &config.api_key {
+                if key.trim().is_empty() {
+                    return Err(CoreError::Config("OpenRouter API key is empty".to_owned()));
+                }
+            }
+            if config.model_name.trim().is_empty() {
+                return Err(CoreError::Config("Model name is empty".to_owned()));
+            }
+
+            let counters = Arc::new(ConnectionCounters::default());
+            let layer_counters = counters.clone();
+            let client = reqwest::Client::builder()
+                .timeout(Duration::from_secs(60))
+                .pool_idle_timeout(config.pool.idle_timeout)
+                .pool_max_idle_per_host(config.pool.max_idle_per_host)
+                .http2_keep_alive_interval(config.pool.keep_alive_interval)
+                .http2_keep_alive_timeout(config.pool.keep_alive_timeout)
+                .http2_keep_alive_while_idle(true)
+                .connector_layer(tower::layer::layer_fn(move |inner| CountConnections {
+                    inner,
+                    counters: layer_counters.clone(),
+                }))
+                .build()
+                .map_err(|e| CoreError::Model(e.to_string()))?;
+
+            Ok(Self {
+                client,
+                estimator: TokenEstimator::for_model(&config.model_name),
+                config,
+                counters,
+                clock,
+                routes: Arc::new(Vec::new()),
+                phase_usage: Arc::new(PhaseUsageCounters::default()),
+                last_outcome: Arc::new(Mutex::new(None)),
             })
-            .await?;
+        }
 
-        if response.status().is_success() {
-            Ok(())
-        } else {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            Err(CoreError::Model(format!(
-                "model connection failed with status {}: {}",
-                status, body
-            )))
+        /// Use `routes` for the phases they name in `chat_completion_for`.
+        pub fn with_routes(mut self, routes: Vec<ModelRoute>) -> CoreResult<Self> {
+            validate_routes(&routes)?;
+            self.routes = Arc::new(routes);
+            Ok(self)
         }
-    }
 
-    pub async fn chat_completion(&self, messages: &[ChatMessage]) -> CoreResult<String> {
-        let base = self.config.base_url.trim_end_matches('/');
-        let url = format!("{base}/chat/completions");
+        /// The default model; routes may send some phases elsewhere.
+        pub fn model_name(&self) -> &str {
+            &self.config.model_name
+        }
 
-        let payload = serde_json::json!({
-            "model": self.config.model_name,
-            "messages": messages,
-            "stream": false,
-        });
+        /// Estimated prompt tokens allowed per call; `0` when unguarded.
+        pub fn max_prompt_tokens(&self) -> u32 {
+            self.config.max_prompt_tokens
+        }
+
+        /// Calls and estimated tokens per phase since this connector was built.
+        pub fn phase_usage(&self) -> Vec<ModelPhaseUsage> {
+            self.phase_usage.snapshot()
+        }
+
+        pub fn connection_stats(&self) -> ConnectionStats {
+            ConnectionStats {
+                requests: self.counters.requests.load(Ordering::Relaxed),
+                connections_opened: self.counters.connections_opened.load(Ordering::Relaxed),
+            }
+        }
+
+        /// Outcome of the last connection test, preflight or completion.
+        pub fn last_outcome(&self) -> Option<CallOutcome> {
+            self.last_outcome
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .clone()
+        }
+
+        fn record_outcome(&self, error: Option<String>) {
+            let at = self.clock.timestamp();
+            let mut last = self
+                .last_outcome
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            let previous = last.take();
+            *last = Some(match error {
+                None => CallOutcome {
+                    at,
+                    error: None,
+                    consecutive_failures: 0,
+                    last_success_at: Some(at),
+                },
+                Some(error) => CallOutcome {
+                    at,
+                    error: Some(error),
+                    consecutive_failures: previous
+                        .as_ref()
+                        .map_or(0, |previous| previous.consecutive_failures)
+                        + 1,
+                    last_success_at: previous.and_then(|previous| previous.last_success_at),
+                },
+            });
+        }
+
+        pub async fn test_connection(&self) -> CoreResult<()> {
+            let result = self.list_models().await;
+            self.record_outcome(result.as_ref().err().map(ToString::to_string));
+            result
+        }
+
+        async fn list_models(&self) -> CoreResult<()> {
+            let base = self.config.base_url.trim_end_matches('/');
+            let url = format!("{base}/models");
+            let api_key = self.config.api_key.resolve()?;
+
+            let response = self
+                .request_with_retry(|| {
+                    self.client
+                        .get(&url)
+                        .header("Authorization", format!("Bearer {api_key}"))
+                })
+                .await?;
+
+            if response.status().is_success() {
+                Ok(())
+            } else {
+                let status = response.status();
+                let body = response.text().await.unwrap_or_default();
+                Err(CoreError::Model(format!(
+                    "model connection failed with status {}: {}",
+                    status, body
+                )))
+            }
+        }
+
+        /// Open a pooled connection and validate the key and model before the
+        /// first real request: lists models, then asks for a single token.
+        pub async fn preflight(&self) -> Result<(), PreflightFailure> {
+            let result = self.run_preflight().await;
+            self.record_outcome(result.as_ref().err().map(|failure| failure.message.clone()));
+            result
+        }
 
-        let response = self
-            .request_with_retry(|| {
+        async fn run_preflight(&self) -> Result<(), PreflightFailure> {
+            let base = self.config.base_url.trim_end_matches('/');
+            let models_url = format!("{base}/models");
+            let completion_url = format!("{base}/chat/completions");
+            let api_key = self
+                .config
+                .api_key
+                .resolve()
+                .map_err(|err| PreflightFailure {
+                    stage: PreflightStage::Models,
+                    status: None,
+                    message: err.to_string(),
+                })?;
+            let payload = serde_json::json!({
+                "model": self.config.model_name,
+                "messages": [{"role": "user", "content": "ping"}],
+                "max_tokens": 1,
+                "stream": false,
+            });
+
+            self.preflight_step(PreflightStage::Models, || {
+                self.client
+                    .get(&models_url)
+                    .header("Authorization", format!("Bearer {api_key}"))
+            })
+            .await?;
+            self.preflight_step(PreflightStage::Completion, || {
                 self.client
-                    .post(&url)
-                    .header("Authorization", format!("Bearer {}", self.config.api_key))
+                    .post(&completion_url)
+                    .header("Authorization", format!("Bearer {api_key}"))
                     .header("Content-Type", "application/json")
                     .json(&payload)
             })
-            .await?;
+            .await
+        }
 
-        let status = response.status();
-        if !status.is_success() {
-            let body = response.text().await.unwrap_or_default();
-            return Err(CoreError::Model(format!(
-                "chat completion failed with status {}: {}",
-                status, body
-            )));
+        async fn preflight_step(
+            &self,
+            stage: PreflightStage,
+            build_request: impl FnMut() -> reqwest::RequestBuilder,
+        ) -> Result<(), PreflightFailure> {
+            let response = self
+                .request_with_retry(build_request)
+                .await
+                .map_err(|err| PreflightFailure {
+                    stage,
+                    status: None,
+                    message: err.to_string(),
+                })?;
+            let status = response.status();
+            if status.is_success() {
+                return Ok(());
+            }
+            Err(PreflightFailure {
+                stage,
+                status: Some(status.as_u16()),
+                message: response.text().await.unwrap_or_default(),
+            })
         }
 
-        let body: ChatResponse = response
-            .json()
-            .await
-            .map_err(|e| CoreError::Model(e.to_string()))?;
+        pub async fn chat_completion(&self, messages: &[ChatMessage]) -> CoreResult<String> {
+            self.complete(messages, &self.config.model_name, None, None)
+                .await
+        }
 
-        let content = body
-            .choices
-            .first()
-            .map(|choice| choice.message.content.clone())
-            .ok_or_else(|| CoreError::Model("empty model response".to_owned()))?;
+        /// `chat_completion` with the model and parameters routed to `phase`,
+        /// counted in `phase_usage`.
+        pub async fn chat_completion_for(
+            &self,
+            phase: ModelPhase,
+            messages: &[ChatMessage],
+        ) -> CoreResult<String> {
+            self.chat_completion_capped(phase, messages, None).await
+        }
 
-        Ok(content)
-    }
+        /// `chat_completion_for` answering in at most `max_tokens`, or the
+        /// route's own limit when that is lower.
+        pub async fn chat_completion_capped(
+            &self,
+            phase: ModelPhase,
+            messages: &[ChatMessage],
+            max_tokens: Option<u32>,
+        ) -> CoreResult<String> {
+            let route = self.routes.iter().find(|route| route.phase == phase);
+            let model_name = route.map_or(self.config.model_name.as_str(), |route| {
+                route.model_name.as_str()
+            });
+            let estimator = TokenEstimator::for_model(model_name);
+            let prompt_tokens = estimator.estimate_messages(messages);
+            let result = self
+                .complete(
+                    messages,
+                    model_name,
+                    route.and_then(|route| route.temperature),
+                    match (route.and_then(|route| route.max_output_tokens), max_tokens) {
+                        (Some(route_max), Some(max)) => Some(route_max.min(max)),
+                        (route_max, max) => route_max.or(max),
+                    },
+                )
+                .await;
+            self.phase_usage.record(
+                phase,
+                model_name,
+                prompt_tokens,
+                result
+                    .as_ref()
+                    .ok()
+                    .map(|content| estimator.estimate(content)),
+            );
+            result
+        }
 
-    async fn request_with_retry(
-        &self,
-        mut build_request: impl FnMut() -> reqwest::RequestBuilder,
-    ) -> CoreResult<reqwest::Response> {
-        let mut attempt: u32 = 0;
+        async fn complete(
+            &self,
+            messages: &[ChatMessage],
+            model_name: &str,
+            temperature: Option<f64>,
+            max_tokens: Option<u32>,
+        ) -> CoreResult<String> {
+            let result = self
+                .send_completion(messages, model_name, temperature, max_tokens)
+                .await;
+            self.record_outcome(result.as_ref().err().map(ToString::to_string));
+            result
+        }
 
-        loop {
-            let result = build_request().send().await;
+        async fn send_completion(
+            &self,
+            messages: &[ChatMessage],
+            model_name: &str,
+            temperature: Option<f64>,
+            max_tokens: Option<u32>,
+        ) -> CoreResult<String> {
+            let estimator = if model_name == self.config.model_name {
+                self.estimator
+            } else {
+                TokenEstimator::for_model(model_name)
+            };
+            let prompt_tokens = estimator.estimate_messages(messages);
+            let limit = self.config.max_prompt_tokens;
+            let trimmed;
+            let messages = if limit > 0 && prompt_tokens > limit {
+                trimmed = estimator.fit_messages(messages, limit).ok_or_else(|| {
+                    CoreError::Model(format!(
+                        "prompt is about {prompt_tokens} tokens and its instructions alone \
+                         exceed the {limit} token limit"
+                    ))
+                })?;
+                tracing::warn!("trimmed a {prompt_tokens} token prompt to the {limit} token limit");
+                trimmed.as_slice()
+            } else {
+                messages
+            };
+
+            let base = self.config.base_url.trim_end_matches('/');
+            let url = format!("{base}/chat/completions");
+
+            let mut payload = serde_json::json!({
+                "model": model_name,
+                "messages": messages,
+                "stream": false,
+            });
+            if let Some(temperature) = temperature {
+                payload["temperature"] = temperature.into();
+            }
+            if let Some(max_tokens) = max_tokens {
+                payload["max_tokens"] = max_tokens.into();
+            }
+            let api_key = self.config.api_key.resolve()?;
+
+            let response = self
+                .request_with_retry(|| {
+                    self.client
+                        .post(&url)
+                        .header("Authorization", format!("Bearer {api_key}"))
+                        .header("Content-Type", "application/json")
+                        .json(&payload)
+                })
+                .await?;
 
-            match result {
-                Ok(response) => {
-                    if response.status().is_success() {
-                        return Ok(response);
-                    }
+            let status = response.status();
+            if !status.is_success() {
+                let body = response.text().await.unwrap_or_default();
+                return Err(CoreError::Model(format!(
+                    "chat completion failed with status {}: {}",
+                    status, body
+                )));
+            }
 
-                    if !is_retryable_status(response.status()) {
-                        return Ok(response);
-                    }
+            let body: ChatResponse = response
+                .json()
+                .await
+                .map_err(|e| CoreError::Model(e.to_string()))?;
 
-                    if attempt >= self.config.retry.max_retries {
-                        return Ok(response);
+            let content = body
+                .choices
+                .first()
+                .map(|choice| choice.message.content.clone())
+                .ok_or_else(|| CoreError::Model("empty model response".to_owned()))?;
+
+            Ok(content)
+        }
+
+        async fn request_with_retry(
+            &self,
+            mut build_request: impl FnMut() -> reqwest::RequestBuilder,
+        ) -> CoreResult<reqwest::Response> {
+            let mut attempt: u32 = 0;
+
+            loop {
+                self.counters.requests.fetch_add(1, Ordering::Relaxed);
+                let result = build_request().send().await;
+
+                match result {
+                    Ok(response) => {
+                        if response.status().is_success() {
+                            return Ok(response);
+                        }
+
+                        if !is_retryable_status(response.status()) {
+                            return Ok(response);
+                        }
+
+                        if attempt >= self.config.retry.max_retries {
+                            return Ok(response);
+                        }
                     }
-                }
-                Err(err) => {
-                    if attempt >= self.config.retry.max_retries || !is_retryable_error(&err) {
-                        return Err(CoreError::Model(err.to_string()));
+                    Err(err) => {
+                        if attempt >= self.config.retry.max_retries || !is_retryable_error(&err) {
+                            return Err(CoreError::Model(err.to_string()));
+                        }
                     }
                 }
-            }
 
-            let delay_ms = compute_backoff_ms(attempt, &self.config.retry);
-            sleep(Duration::from_millis(delay_ms)).await;
-            attempt += 1;
+                let delay_ms = compute_backoff_ms(attempt, &self.config.retry);
+                self.clock.sleep(Duration::from_millis(delay_ms)).await;
+                attempt += 1;
+            }
         }
     }
-}
 
-pub(crate) fn is_retryable_status(status: StatusCode) -> bool {
-    matches!(
-        status,
-        StatusCode::TOO_MANY_REQUESTS
-            | StatusCode::INTERNAL_SERVER_ERROR
-            | StatusCode::BAD_GATEWAY
-            | StatusCode::SERVICE_UNAVAILABLE
-            | StatusCode::GATEWAY_TIMEOUT
-    )
-}
+    pub(crate) fn is_retryable_status(status: StatusCode) -> bool {
+        matches!(
+            status,
+            StatusCode::TOO_MANY_REQUESTS
+                | StatusCode::INTERNAL_SERVER_ERROR
+                | StatusCode::BAD_GATEWAY
+                | StatusCode::SERVICE_UNAVAILABLE
+                | StatusCode::GATEWAY_TIMEOUT
+        )
+    }
 
-pub(crate) fn is_retryable_error(err: &reqwest::Error) -> bool {
-    err.is_timeout() || err.is_connect() || err.is_request()
+    pub(crate) fn is_retryable_error(err: &reqwest::Error) -> bool {
+        err.is_timeout() || err.is_connect() || err.is_request()
+    }
+
+    pub(crate) fn compute_backoff_ms(attempt: u32, config: &RetryConfig) -> u64 {
+        let raw = (config.initial_delay_ms as f64) * config.backoff_factor.powf(attempt as f64);
+        raw.min(config.max_delay_ms as f64) as u64
+    }
 }
 
-pub(crate) fn compute_backoff_ms(attempt: u32, config: &RetryConfig) -> u64 {
-    let raw = (config.initial_delay_ms as f64) * config.backoff_factor.powf(attempt as f64);
-    raw.min(config.max_delay_ms as f64) as u64
+/// Stand-in for builds without the `model-remote` feature. `new` always
+/// fails, so no connector ever exists and every model-backed step keeps its
+/// template output.
+#[cfg(not(feature = "model-remote"))]
+mod unavailable {
+    use std::convert::Infallible;
+    use std::sync::Arc;
+
+    use crate::clock::Clock;
+    use crate::error::{CoreError, CoreResult};
+
+    use super::super::routing::{ModelPhase, ModelPhaseUsage, ModelRoute};
+    use super::{CallOutcome, ChatMessage, ConnectionStats, OpenRouterConfig, PreflightFailure};
+
+    #[derive(Clone)]
+    pub struct ModelConnector {
+        never: Infallible,
+    }
+
+    impl ModelConnector {
+        pub fn new(_config: OpenRouterConfig, _clock: Arc<dyn Clock>) -> CoreResult<Self> {
+            Err(CoreError::Config(
+                "model calls need a build with the model-remote feature".to_owned(),
+            ))
+        }
+
+        pub fn with_routes(self, _routes: Vec<ModelRoute>) -> CoreResult<Self> {
+            match self.never {}
+        }
+
+        pub fn model_name(&self) -> &str {
+            match self.never {}
+        }
+
+        pub fn max_prompt_tokens(&self) -> u32 {
+            match self.never {}
+        }
+
+        pub fn phase_usage(&self) -> Vec<ModelPhaseUsage> {
+            match self.never {}
+        }
+
+        pub fn connection_stats(&self) -> ConnectionStats {
+            match self.never {}
+        }
+
+        pub fn last_outcome(&self) -> Option<CallOutcome> {
+            match self.never {}
+        }
+
+        pub async fn test_connection(&self) -> CoreResult<()> {
+            match self.never {}
+        }
+
+        pub async fn preflight(&self) -> Result<(), PreflightFailure> {
+            match self.never {}
+        }
+
+        pub async fn chat_completion(&self, _messages: &[ChatMessage]) -> CoreResult<String> {
+            match self.never {}
+        }
+
+        pub async fn chat_completion_for(
+            &self,
+            _phase: ModelPhase,
+            _messages: &[ChatMessage],
+        ) -> CoreResult<String> {
+            match self.never {}
+        }
+
+        pub async fn chat_completion_capped(
+            &self,
+            _phase: ModelPhase,
+            _messages: &[ChatMessage],
+            _max_tokens: Option<u32>,
+        ) -> CoreResult<String> {
+            match self.never {}
+        }
+    }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "model-remote"))]
 mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
     use reqwest::StatusCode;
 
-    use super::{compute_backoff_ms, is_retryable_status, RetryConfig};
+    use super::remote::{compute_backoff_ms, is_retryable_status};
+    use super::{
+        ChatMessage, HttpPoolConfig, ModelConnector, OpenRouterConfig, PreflightFailure,
+        PreflightStage, RetryConfig,
+    };
+    use crate::clock::ManualClock;
+    use crate::secrets::ApiKey;
 
     #[test]
     fn retryable_status_is_correct() {
@@ -235,4 +738,183 @@ mod tests {
         assert_eq!(compute_backoff_ms(3, &config), 1000);
         assert_eq!(compute_backoff_ms(4, &config), 1000);
     }
+
+    #[test]
+    fn retry_backoff_uses_injected_clock() {
+        let clock = Arc::new(ManualClock::at_timestamp(1_700_000_000));
+        let connector = ModelConnector::new(
+            OpenRouterConfig {
+                api_key: ApiKey::Static("test-key".to_owned()),
+                model_name: "test-model".to_owned(),
+                // Nothing listens on port 1, so every attempt is a retryable
+                // connect error.
+                base_url: "http://127.0.0.1:1".to_owned(),
+                retry: RetryConfig {
+                    max_retries: 3,
+                    initial_delay_ms: 200,
+                    max_delay_ms: 10_000,
+                    backoff_factor: 2.0,
+                },
+                max_prompt_tokens: 0,
+                pool: HttpPoolConfig::default(),
+            },
+            clock.clone(),
+        )
+        .expect("connector");
+
+        let result = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("runtime")
+            .block_on(connector.test_connection());
+
+        assert!(result.is_err());
+        assert_eq!(
+            clock.sleeps(),
+            vec![
+                Duration::from_millis(200),
+                Duration::from_millis(400),
+                Duration::from_millis(800)
+            ]
+        );
+    }
+    #[test]
+    fn untrimmable_prompt_is_rejected_before_sending() {
+        let clock = Arc::new(ManualClock::at_timestamp(1_700_000_000));
+        let connector = ModelConnector::new(
+            OpenRouterConfig {
+                api_key: ApiKey::Static("test-key".to_owned()),
+                model_name: "test-model".to_owned(),
+                base_url: "http://127.0.0.1:1".to_owned(),
+                retry: RetryConfig::default(),
+                max_prompt_tokens: 8,
+                pool: HttpPoolConfig::default(),
+            },
+            clock.clone(),
+        )
+        .expect("connector");
+
+        let messages = vec![
+            ChatMessage {
+                role: "system".to_owned(),
+                content: "你是劳动法咨询助手，请根据事实给出建议。".to_owned(),
+            },
+            ChatMessage {
+                role: "user".to_owned(),
+                content: "公司拖欠工资三个月，我应该怎么办？".to_owned(),
+            },
+        ];
+        let result = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("runtime")
+            .block_on(connector.chat_completion(&messages));
+
+        let err = result.expect_err("prompt over budget");
+        assert!(err.to_string().contains("token limit"));
+        assert!(clock.sleeps().is_empty(), "no request should be attempted");
+    }
+
+    #[test]
+    fn pooled_connection_is_reused_across_requests() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let addr = listener.local_addr().expect("addr");
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { return };
+                let mut buf = [0u8; 4096];
+                // Keep-alive: answer every request on the same socket.
+                while let Ok(read) = stream.read(&mut buf) {
+                    if read == 0
+                        || stream
+                            .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                            .is_err()
+                    {
+                        break;
+                    }
+                }
+            }
+        });
+
+        let connector = ModelConnector::new(
+            OpenRouterConfig {
+                api_key: ApiKey::Static("test-key".to_owned()),
+                model_name: "test-model".to_owned(),
+                base_url: format!("http://{addr}"),
+                retry: RetryConfig::default(),
+                max_prompt_tokens: 0,
+                pool: HttpPoolConfig::default(),
+            },
+            Arc::new(ManualClock::at_timestamp(1_700_000_000)),
+        )
+        .expect("connector");
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("runtime");
+        for _ in 0..3 {
+            runtime
+                .block_on(connector.test_connection())
+                .expect("connection test");
+        }
+
+        let stats = connector.connection_stats();
+        assert_eq!(stats.requests, 3);
+        assert_eq!(stats.connections_opened, 1);
+        assert_eq!(stats.reused(), 2);
+    }
+
+    #[test]
+    fn preflight_reports_the_failing_stage() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let addr = listener.local_addr().expect("addr");
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { return };
+                let mut buf = [0u8; 4096];
+                while let Ok(read) = stream.read(&mut buf) {
+                    let response: &[u8] = if read == 0 {
+                        break;
+                    } else if buf.starts_with(b"GET /models") {
+                        b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n"
+                    } else {
+                        b"HTTP/1.1 404 Not Found\r\ncontent-length: 13\r\n\r\nno such model"
+                    };
+                    if stream.write_all(response).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+
+        let connector = ModelConnector::new(
+            OpenRouterConfig {
+                api_key: ApiKey::Static("test-key".to_owned()),
+                model_name: "missing-model".to_owned(),
+                base_url: format!("http://{addr}"),
+                retry: RetryConfig::default(),
+                max_prompt_tokens: 0,
+                pool: HttpPoolConfig::default(),
+            },
+            Arc::new(ManualClock::at_timestamp(1_700_000_000)),
+        )
+        .expect("connector");
+
+        let result = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("runtime")
+            .block_on(connector.preflight());
+
+        assert_eq!(
+            result,
+            Err(PreflightFailure {
+                stage: PreflightStage::Completion,
+                status: Some(404),
+                message: "no such model".to_owned(),
+            })
+        );
+        assert_eq!(connector.connection_stats().connections_opened, 1);
+    }
 }
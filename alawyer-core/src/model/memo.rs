@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+
+use super::{ChatMessage, ModelPhase};
+
+/// Completions of one task by prompt, so a prompt the task sends again
+/// (e.g. after a clarification loop) reuses the first answer. Prompts are
+/// compared after collapsing whitespace, along with the answer's token cap;
+/// only successful calls are kept.
+#[derive(Default)]
+pub struct RequestMemo {
+    completions: Mutex<HashMap<String, String>>,
+}
+
+impl RequestMemo {
+    pub fn get(
+        &self,
+        phase: ModelPhase,
+        messages: &[ChatMessage],
+        max_tokens: Option<u32>,
+    ) -> Option<String> {
+        self.lock()
+            .get(&prompt_key(phase, messages, max_tokens))
+            .cloned()
+    }
+
+    pub fn insert(
+        &self,
+        phase: ModelPhase,
+        messages: &[ChatMessage],
+        max_tokens: Option<u32>,
+        completion: &str,
+    ) {
+        self.lock().insert(
+            prompt_key(phase, messages, max_tokens),
+            completion.to_owned(),
+        );
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, String>> {
+        self.completions
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// The phase (its route picks the model), the token cap (a capped answer
+/// may be cut short) and each message's role and whitespace-normalized
+/// content.
+fn prompt_key(phase: ModelPhase, messages: &[ChatMessage], max_tokens: Option<u32>) -> String {
+    let mut key = phase.as_str().to_owned();
+    if let Some(max_tokens) = max_tokens {
+        key.push_str(&format!("\u{1d}{max_tokens}"));
+    }
+    for message in messages {
+        key.push('\u{1e}');
+        key.push_str(&message.role);
+        key.push('\u{1f}');
+        key.push_str(
+            &message
+                .content
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" "),
+        );
+    }
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RequestMemo;
+    use crate::model::{ChatMessage, ModelPhase};
+
+    fn prompt(content: &str) -> Vec<ChatMessage> {
+        vec![
+            ChatMessage {
+                role: "system".to_owned(),
+                content: "概括证据".to_owned(),
+            },
+            ChatMessage {
+                role: "user".to_owned(),
+                content: content.to_owned(),
+            },
+        ]
+    }
+
+    #[test]
+    fn identical_prompts_hit_after_normalization() {
+        let memo = RequestMemo::default();
+        assert_eq!(
+            memo.get(ModelPhase::Draft, &prompt("工资条 3月"), None),
+            None
+        );
+        memo.insert(
+            ModelPhase::Draft,
+            &prompt("工资条 3月"),
+            None,
+            "3月工资未发",
+        );
+
+        assert_eq!(
+            memo.get(ModelPhase::Draft, &prompt("  工资条\n\t3月 "), None)
+                .as_deref(),
+            Some("3月工资未发")
+        );
+        assert_eq!(
+            memo.get(ModelPhase::Followup, &prompt("工资条 3月"), None),
+            None
+        );
+        assert_eq!(
+            memo.get(ModelPhase::Draft, &prompt("工资条 4月"), None),
+            None
+        );
+    }
+
+    #[test]
+    fn token_cap_is_part_of_the_key() {
+        let memo = RequestMemo::default();
+        memo.insert(
+            ModelPhase::Draft,
+            &prompt("工资条 3月"),
+            Some(16),
+            "3月工资",
+        );
+
+        assert_eq!(
+            memo.get(ModelPhase::Draft, &prompt("工资条 3月"), None),
+            None
+        );
+        assert_eq!(
+            memo.get(ModelPhase::Draft, &prompt("工资条 3月"), Some(400)),
+            None
+        );
+        assert_eq!(
+            memo.get(ModelPhase::Draft, &prompt("工资条 3月"), Some(16))
+                .as_deref(),
+            Some("3月工资")
+        );
+    }
+}
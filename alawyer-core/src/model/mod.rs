@@ -1,3 +1,13 @@
 pub mod connector;
+pub mod memo;
+pub mod routing;
+pub mod scheduler;
+pub mod tokens;
 
-pub use connector::{ChatMessage, ModelConnector, OpenRouterConfig, RetryConfig};
+pub use connector::{
+    CallOutcome, ChatMessage, HttpPoolConfig, ModelConnector, OpenRouterConfig, RetryConfig,
+};
+pub use memo::RequestMemo;
+pub use routing::{ModelPhase, ModelPhaseUsage, ModelRoute};
+pub use scheduler::{CallPriority, ModelScheduler, SchedulerLimits};
+pub use tokens::estimate_tokens;
@@ -0,0 +1,201 @@
+//! Per-phase model selection: a cheap model for intake, a strong one for
+//! the analysis. Phases without a route use `ModelConfig::model_name`.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::error::{CoreError, CoreResult};
+
+/// Agent step a model call is made for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, uniffi::Enum)]
+pub enum ModelPhase {
+    Plan,
+    Draft,
+    /// Calls on a reviewed report: its translation, and the rework a human
+    /// reviewer asked for (see `Core::request_changes`).
+    Review,
+    /// Any call of a task started after the session already has a report.
+    Followup,
+}
+
+impl ModelPhase {
+    pub const ALL: [Self; 4] = [Self::Plan, Self::Draft, Self::Review, Self::Followup];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Plan => "plan",
+            Self::Draft => "draft",
+            Self::Review => "review",
+            Self::Followup => "followup",
+        }
+    }
+}
+
+/// Model and sampling parameters for the calls of one phase.
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct ModelRoute {
+    pub phase: ModelPhase,
+    pub model_name: String,
+    /// Sampling temperature, 0.0–2.0; the provider default when unset.
+    #[uniffi(default = None)]
+    pub temperature: Option<f64>,
+    /// Cap on completion tokens; the provider default when unset.
+    #[uniffi(default = None)]
+    pub max_output_tokens: Option<u32>,
+}
+
+/// Reject routes with an empty model, an out-of-range temperature or a
+/// phase routed twice.
+pub fn validate_routes(routes: &[ModelRoute]) -> CoreResult<()> {
+    for (index, route) in routes.iter().enumerate() {
+        let phase = route.phase.as_str();
+        if route.model_name.trim().is_empty() {
+            return Err(CoreError::Config(format!(
+                "model route {phase}: model name is empty"
+            )));
+        }
+        if route
+            .temperature
+            .is_some_and(|temperature| !(0.0..=2.0).contains(&temperature))
+        {
+            return Err(CoreError::Config(format!(
+                "model route {phase}: temperature must be between 0 and 2"
+            )));
+        }
+        if routes[..index]
+            .iter()
+            .any(|other| other.phase == route.phase)
+        {
+            return Err(CoreError::Config(format!(
+                "model route {phase} is set twice"
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Completions made for one phase under the current model configuration.
+/// Token counts are estimates, like the prompt guard's.
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct ModelPhaseUsage {
+    /// `plan`, `draft`, `review` or `followup`.
+    pub phase: String,
+    pub model_name: String,
+    pub calls: u64,
+    pub failures: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+#[derive(Default)]
+pub(crate) struct PhaseUsageCounters {
+    usage: Mutex<HashMap<(ModelPhase, String), ModelPhaseUsage>>,
+}
+
+impl PhaseUsageCounters {
+    /// Count one call; `completion_tokens` is `None` when it failed.
+    pub(crate) fn record(
+        &self,
+        phase: ModelPhase,
+        model_name: &str,
+        prompt_tokens: u32,
+        completion_tokens: Option<u32>,
+    ) {
+        let mut usage = self
+            .usage
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let entry = usage
+            .entry((phase, model_name.to_owned()))
+            .or_insert_with(|| ModelPhaseUsage {
+                phase: phase.as_str().to_owned(),
+                model_name: model_name.to_owned(),
+                calls: 0,
+                failures: 0,
+                prompt_tokens: 0,
+                completion_tokens: 0,
+            });
+        entry.calls += 1;
+        entry.prompt_tokens += u64::from(prompt_tokens);
+        match completion_tokens {
+            Some(tokens) => entry.completion_tokens += u64::from(tokens),
+            None => entry.failures += 1,
+        }
+    }
+
+    /// Usage in phase order, then by model.
+    pub(crate) fn snapshot(&self) -> Vec<ModelPhaseUsage> {
+        let usage = self
+            .usage
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut entries = usage.iter().collect::<Vec<_>>();
+        entries.sort_by_key(|((phase, model), _)| {
+            (
+                ModelPhase::ALL
+                    .iter()
+                    .position(|candidate| candidate == phase),
+                model.clone(),
+            )
+        });
+        entries
+            .into_iter()
+            .map(|(_, usage)| usage.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{validate_routes, ModelPhase, ModelRoute, PhaseUsageCounters};
+
+    fn route(phase: ModelPhase, model_name: &str) -> ModelRoute {
+        ModelRoute {
+            phase,
+            model_name: model_name.to_owned(),
+            temperature: None,
+            max_output_tokens: None,
+        }
+    }
+
+    #[test]
+    fn validates_routes_and_counts_usage_per_phase() {
+        let routes = [
+            route(ModelPhase::Plan, "cheap"),
+            ModelRoute {
+                temperature: Some(0.2),
+                ..route(ModelPhase::Draft, "strong")
+            },
+        ];
+        assert!(validate_routes(&routes).is_ok());
+        assert!(validate_routes(&[route(ModelPhase::Plan, " ")]).is_err());
+        assert!(validate_routes(&[ModelRoute {
+            temperature: Some(2.5),
+            ..route(ModelPhase::Plan, "cheap")
+        }])
+        .is_err());
+        let twice = [
+            route(ModelPhase::Review, "a"),
+            route(ModelPhase::Review, "b"),
+        ];
+        assert!(validate_routes(&twice)
+            .expect_err("duplicate phase")
+            .to_string()
+            .contains("set twice"));
+
+        let counters = PhaseUsageCounters::default();
+        counters.record(ModelPhase::Followup, "cheap", 10, Some(5));
+        counters.record(ModelPhase::Draft, "strong", 100, Some(40));
+        counters.record(ModelPhase::Draft, "strong", 120, None);
+        let usage = counters.snapshot();
+        assert_eq!(
+            usage
+                .iter()
+                .map(|entry| (entry.phase.as_str(), entry.calls, entry.failures))
+                .collect::<Vec<_>>(),
+            [("draft", 2, 1), ("followup", 1, 0)]
+        );
+        assert_eq!(usage[0].prompt_tokens, 220);
+        assert_eq!(usage[0].completion_tokens, 40);
+    }
+}
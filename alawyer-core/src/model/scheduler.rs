@@ -0,0 +1,318 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use crate::clock::Clock;
+
+/// How often a queued caller re-checks its cancellation flag.
+const CANCEL_POLL: Duration = Duration::from_millis(50);
+
+/// Queue class of a model call. Interactive calls keep a user waiting on the
+/// next question and always go before drafting; background calls go last.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CallPriority {
+    Interactive,
+    Normal,
+    Background,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SchedulerLimits {
+    /// Model calls allowed in flight across all tasks; at least 1. With more
+    /// than one, background calls leave one slot free for the others.
+    pub max_in_flight: usize,
+    /// Minimum spacing between call starts; zero disables the rate limit.
+    pub min_interval: Duration,
+}
+
+impl Default for SchedulerLimits {
+    fn default() -> Self {
+        Self {
+            max_in_flight: 2,
+            min_interval: Duration::ZERO,
+        }
+    }
+}
+
+struct Ticket {
+    seq: u64,
+    task_id: String,
+    priority: CallPriority,
+}
+
+#[derive(Default)]
+struct SchedulerState {
+    limits: SchedulerLimits,
+    waiting: Vec<Ticket>,
+    in_flight: usize,
+    next_seq: u64,
+    /// Grant counter value at each task's most recent call. Tasks served
+    /// longest ago go first within a priority class, giving round-robin.
+    last_served: HashMap<String, u64>,
+    grants: u64,
+    last_start: Option<DateTime<Utc>>,
+}
+
+impl SchedulerState {
+    fn next_ticket(&self) -> Option<u64> {
+        self.waiting
+            .iter()
+            .min_by_key(|ticket| {
+                (
+                    ticket.priority,
+                    self.last_served.get(&ticket.task_id).copied().unwrap_or(0),
+                    ticket.seq,
+                )
+            })
+            .map(|ticket| ticket.seq)
+    }
+
+    /// Slots a call of `priority` may start in.
+    fn slots_for(&self, priority: CallPriority) -> usize {
+        let slots = self.limits.max_in_flight.max(1);
+        match priority {
+            CallPriority::Background => (slots - 1).max(1),
+            CallPriority::Interactive | CallPriority::Normal => slots,
+        }
+    }
+
+    fn remove(&mut self, seq: u64) -> Option<Ticket> {
+        let index = self.waiting.iter().position(|ticket| ticket.seq == seq)?;
+        Some(self.waiting.remove(index))
+    }
+}
+
+/// Orders model calls from concurrent agent tasks and enforces the shared
+/// concurrency and rate limits. Callers block in `acquire` until their turn.
+/// With `max_in_flight` 1 it also serves as the queue of one session's tasks.
+pub struct ModelScheduler {
+    state: Mutex<SchedulerState>,
+    turn: Condvar,
+    clock: Arc<dyn Clock>,
+}
+
+impl ModelScheduler {
+    pub fn new(limits: SchedulerLimits, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            state: Mutex::new(SchedulerState {
+                limits,
+                ..SchedulerState::default()
+            }),
+            turn: Condvar::new(),
+            clock,
+        }
+    }
+
+    pub fn set_limits(&self, limits: SchedulerLimits) {
+        self.lock().limits = limits;
+        self.turn.notify_all();
+    }
+
+    /// Wait for a slot for one call of `task_id`. Returns `None` once
+    /// `is_cancelled` reports true while still queued.
+    pub fn acquire(
+        &self,
+        task_id: &str,
+        priority: CallPriority,
+        is_cancelled: impl Fn() -> bool,
+    ) -> Option<ModelPermit<'_>> {
+        let mut state = self.lock();
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        state.waiting.push(Ticket {
+            seq,
+            task_id: task_id.to_owned(),
+            priority,
+        });
+
+        loop {
+            if is_cancelled() {
+                state.remove(seq);
+                drop(state);
+                self.turn.notify_all();
+                return None;
+            }
+
+            let mut wait = CANCEL_POLL;
+            if state.next_ticket() == Some(seq) && state.in_flight < state.slots_for(priority) {
+                let ready_at = state.last_start.and_then(|last| {
+                    last.checked_add_signed(
+                        chrono::Duration::from_std(state.limits.min_interval).ok()?,
+                    )
+                });
+                let now = self.clock.now();
+                match ready_at {
+                    Some(ready_at) if ready_at > now => {
+                        wait = wait.min((ready_at - now).to_std().unwrap_or(CANCEL_POLL));
+                    }
+                    _ => {
+                        let ticket = state.remove(seq).expect("queued ticket");
+                        state.grants += 1;
+                        let grant = state.grants;
+                        state.last_served.insert(ticket.task_id, grant);
+                        state.in_flight += 1;
+                        state.last_start = Some(now);
+                        return Some(ModelPermit { scheduler: self });
+                    }
+                }
+            }
+
+            state = self
+                .turn
+                .wait_timeout(state, wait)
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .0;
+        }
+    }
+
+    fn release(&self) {
+        {
+            let mut state = self.lock();
+            state.in_flight -= 1;
+            if state.in_flight == 0 && state.waiting.is_empty() {
+                // Nothing is competing; forget history so it cannot grow
+                // with the number of tasks ever run.
+                state.last_served.clear();
+            }
+        }
+        self.turn.notify_all();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, SchedulerState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Callers queued in `acquire`.
+    pub fn waiting(&self) -> usize {
+        self.lock().waiting.len()
+    }
+}
+
+/// A granted call slot; dropping it lets the next queued call start.
+pub struct ModelPermit<'a> {
+    scheduler: &'a ModelScheduler,
+}
+
+impl Drop for ModelPermit<'_> {
+    fn drop(&mut self) {
+        self.scheduler.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{CallPriority, ModelScheduler, SchedulerLimits};
+    use crate::clock::{ManualClock, SystemClock};
+
+    fn scheduler(max_in_flight: usize) -> ModelScheduler {
+        ModelScheduler::new(
+            SchedulerLimits {
+                max_in_flight,
+                min_interval: Duration::ZERO,
+            },
+            Arc::new(SystemClock),
+        )
+    }
+
+    #[test]
+    fn interactive_calls_go_first_then_tasks_take_turns() {
+        let scheduler = Arc::new(scheduler(1));
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let held = scheduler
+            .acquire("busy", CallPriority::Background, || false)
+            .expect("permit");
+
+        let mut handles = Vec::new();
+        for (task, priority) in [
+            ("a", CallPriority::Background),
+            ("a", CallPriority::Background),
+            ("b", CallPriority::Background),
+            ("c", CallPriority::Interactive),
+        ] {
+            let queued = scheduler.waiting();
+            let queue = scheduler.clone();
+            let order = order.clone();
+            handles.push(thread::spawn(move || {
+                let _permit = queue.acquire(task, priority, || false).expect("permit");
+                order.lock().expect("order").push(task);
+            }));
+            while scheduler.waiting() == queued {
+                thread::sleep(Duration::from_millis(5));
+            }
+        }
+
+        let cancelled = AtomicBool::new(false);
+        let cancel_after_queueing = || {
+            let was = cancelled.load(Ordering::Relaxed);
+            cancelled.store(true, Ordering::Relaxed);
+            was
+        };
+        assert!(scheduler
+            .acquire("d", CallPriority::Interactive, cancel_after_queueing)
+            .is_none());
+
+        drop(held);
+        for handle in handles {
+            handle.join().expect("join");
+        }
+        assert_eq!(*order.lock().expect("order"), vec!["c", "a", "b", "a"]);
+    }
+
+    #[test]
+    fn background_calls_leave_a_slot_free() {
+        let scheduler = scheduler(2);
+        let _held = scheduler
+            .acquire("a", CallPriority::Background, || false)
+            .expect("permit");
+        let give_up_after_first_try = || {
+            let checked = AtomicBool::new(false);
+            move || checked.swap(true, Ordering::Relaxed)
+        };
+
+        assert!(scheduler
+            .acquire("b", CallPriority::Background, give_up_after_first_try())
+            .is_none());
+        assert!(scheduler
+            .acquire("c", CallPriority::Normal, give_up_after_first_try())
+            .is_some());
+    }
+
+    #[test]
+    fn rate_limit_follows_the_injected_clock() {
+        let clock = Arc::new(ManualClock::at_timestamp(1_700_000_000));
+        let scheduler = ModelScheduler::new(
+            SchedulerLimits {
+                max_in_flight: 1,
+                min_interval: Duration::from_secs(10),
+            },
+            clock.clone(),
+        );
+        drop(
+            scheduler
+                .acquire("a", CallPriority::Normal, || false)
+                .expect("first call"),
+        );
+
+        let checked = AtomicBool::new(false);
+        assert!(scheduler
+            .acquire("a", CallPriority::Normal, || checked
+                .swap(true, Ordering::Relaxed))
+            .is_none());
+
+        clock.advance(Duration::from_secs(10));
+        let checked = AtomicBool::new(false);
+        assert!(scheduler
+            .acquire("a", CallPriority::Normal, || checked
+                .swap(true, Ordering::Relaxed))
+            .is_some());
+    }
+}
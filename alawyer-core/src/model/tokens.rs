@@ -0,0 +1,165 @@
+use super::ChatMessage;
+
+/// Fixed per-message cost of the chat format (role, separators).
+const MESSAGE_OVERHEAD_TOKENS: u32 = 4;
+
+/// Rough token counts without shipping a BPE vocabulary. CJK text is counted
+/// per character and everything else per run of characters; the ratios are
+/// tuned per model family and err on the high side.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TokenEstimator {
+    cjk_tokens_per_char: f32,
+    other_chars_per_token: f32,
+}
+
+impl Default for TokenEstimator {
+    fn default() -> Self {
+        Self {
+            cjk_tokens_per_char: 1.0,
+            other_chars_per_token: 4.0,
+        }
+    }
+}
+
+impl TokenEstimator {
+    pub fn for_model(model_name: &str) -> Self {
+        let name = model_name.to_ascii_lowercase();
+        if ["qwen", "deepseek", "glm", "moonshot", "kimi"]
+            .iter()
+            .any(|family| name.contains(family))
+        {
+            // Vocabularies trained on Chinese merge most common characters.
+            Self {
+                cjk_tokens_per_char: 0.7,
+                other_chars_per_token: 4.0,
+            }
+        } else if name.contains("claude") {
+            Self {
+                cjk_tokens_per_char: 1.3,
+                other_chars_per_token: 3.5,
+            }
+        } else {
+            Self::default()
+        }
+    }
+
+    pub fn estimate(&self, text: &str) -> u32 {
+        let (cjk, other) = text.chars().fold((0u32, 0u32), |(cjk, other), ch| {
+            if is_cjk(ch) {
+                (cjk + 1, other)
+            } else if ch.is_whitespace() {
+                (cjk, other)
+            } else {
+                (cjk, other + 1)
+            }
+        });
+
+        let cjk_tokens = (cjk as f32 * self.cjk_tokens_per_char).ceil() as u32;
+        let other_tokens = (other as f32 / self.other_chars_per_token).ceil() as u32;
+        cjk_tokens + other_tokens
+    }
+
+    pub fn estimate_messages(&self, messages: &[ChatMessage]) -> u32 {
+        messages
+            .iter()
+            .map(|message| self.estimate(&message.content) + MESSAGE_OVERHEAD_TOKENS)
+            .sum()
+    }
+
+    /// `messages` cut down to about `limit` tokens: the longest non-system
+    /// message loses its tail until the prompt fits, so instructions survive
+    /// and retrieved context or pasted text is what gets shortened. `None`
+    /// when the system messages alone are over the limit.
+    pub fn fit_messages(&self, messages: &[ChatMessage], limit: u32) -> Option<Vec<ChatMessage>> {
+        let mut fitted = messages.to_vec();
+        loop {
+            let total = self.estimate_messages(&fitted);
+            if total <= limit {
+                return Some(fitted);
+            }
+            let (longest, tokens) = fitted
+                .iter()
+                .enumerate()
+                .filter(|(_, message)| message.role != "system")
+                .map(|(index, message)| (index, self.estimate(&message.content)))
+                .filter(|(_, tokens)| *tokens > 0)
+                .max_by_key(|(_, tokens)| *tokens)?;
+            let keep = tokens.saturating_sub(total - limit);
+            let content = &mut fitted[longest].content;
+            *content = self.truncate(content, keep);
+        }
+    }
+
+    /// Longest prefix of `text` estimated at no more than `budget` tokens.
+    fn truncate(&self, text: &str, budget: u32) -> String {
+        let chars = text.chars().collect::<Vec<_>>();
+        let (mut low, mut high) = (0, chars.len());
+        while low < high {
+            let mid = (low + high).div_ceil(2);
+            if self.estimate(&chars[..mid].iter().collect::<String>()) <= budget {
+                low = mid;
+            } else {
+                high = mid - 1;
+            }
+        }
+        chars[..low].iter().collect()
+    }
+}
+
+/// Estimate with the default (model-agnostic) ratios.
+pub fn estimate_tokens(text: &str) -> u32 {
+    TokenEstimator::default().estimate(text)
+}
+
+fn is_cjk(ch: char) -> bool {
+    matches!(
+        ch,
+        '\u{3000}'..='\u{303F}'
+            | '\u{3400}'..='\u{4DBF}'
+            | '\u{4E00}'..='\u{9FFF}'
+            | '\u{F900}'..='\u{FAFF}'
+            | '\u{FF00}'..='\u{FFEF}'
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{estimate_tokens, TokenEstimator};
+    use crate::model::ChatMessage;
+
+    #[test]
+    fn cjk_counts_per_char_and_latin_per_run() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("劳动仲裁"), 4);
+        assert_eq!(estimate_tokens("salary"), 2);
+        assert_eq!(estimate_tokens("拖欠 salary 三个月"), 7);
+
+        let qwen = TokenEstimator::for_model("qwen/qwen-2.5-72b-instruct");
+        assert!(qwen.estimate("劳动仲裁") < estimate_tokens("劳动仲裁"));
+    }
+
+    #[test]
+    fn oversize_prompts_lose_the_tail_of_their_longest_message() {
+        let message = |role: &str, content: &str| ChatMessage {
+            role: role.to_owned(),
+            content: content.to_owned(),
+        };
+        let estimator = TokenEstimator::default();
+        let messages = vec![
+            message("system", "只依据资料回答"),
+            message("user", &"拖欠工资".repeat(50)),
+        ];
+
+        let fitted = estimator.fit_messages(&messages, 60).expect("fits");
+        assert!(estimator.estimate_messages(&fitted) <= 60);
+        assert_eq!(fitted[0].content, messages[0].content);
+        assert!(messages[1].content.starts_with(&fitted[1].content));
+        assert!(!fitted[1].content.is_empty());
+
+        assert_eq!(
+            estimator.fit_messages(&messages, 1_000),
+            Some(messages.clone())
+        );
+        assert_eq!(estimator.fit_messages(&messages, 10), None);
+    }
+}
@@ -0,0 +1,110 @@
+//! Masking of personal details in text that leaves the session, for shared
+//! reports and the query log alike, so both mask the same things.
+
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+
+/// Stands in for each masked detail.
+pub const MASK: &str = "***";
+
+/// Emails, and digit runs long enough to be a phone, id card or bank
+/// account number, also when written in groups (`138-0013-8000`). Amounts,
+/// dates and months are shorter and stay.
+static CONTACT_DETAILS: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(\.[A-Za-z0-9-]+)+|\d(?:[ -]?\d){10,}[Xx]?")
+        .expect("valid regex")
+});
+
+/// A name, employer or address after the label introducing it, up to the
+/// end of the clause: `姓名：张三`, `用人单位：…`, `我叫张三`, `家住…`. Bare
+/// words such as 用人单位 in a sentence are legal terms and stay.
+static LABELLED_DETAILS: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"((?:姓名|名字|联系人|用人单位|工作单位|单位名称|公司名称|雇主|住址|地址|住所|居住地)\s*[：:]\s*|我叫|家住)([^，。；、,;\n]{1,40})",
+    )
+    .expect("valid regex")
+});
+
+/// Company names by their legal suffix, and street addresses down to the
+/// house number. The name may not start at a preposition or pronoun, so
+/// `我在某某有限公司` keeps `我在`.
+static NAMED_PLACES: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"[\p{Han}&&[^在于被与和向从到给对是的了我你他她]][\p{Han}A-Za-z0-9]{1,15}?(?:有限责任公司|股份有限公司|有限公司)|[\p{Han}&&[^在于被与和向从到给对是的了我你他她]]\p{Han}{1,5}(?:路|街|巷|弄|大道)\d+号(?:\d+(?:栋|幢|单元|楼|室))*",
+    )
+    .expect("valid regex")
+});
+
+/// `text` with personal details masked, and how many were. `known` are
+/// details the caller has on record, such as the case's opposing party;
+/// blank ones are skipped.
+pub fn redact(text: &str, known: &[&str]) -> (String, u32) {
+    let mut count = 0;
+    let mut text = text.to_owned();
+
+    let mut known = known
+        .iter()
+        .map(|detail| detail.trim())
+        .filter(|detail| !detail.is_empty())
+        .collect::<Vec<_>>();
+    // Longest first, so a name containing another is masked whole.
+    known.sort_by_key(|detail| std::cmp::Reverse(detail.chars().count()));
+    for detail in known {
+        count += text.matches(detail).count() as u32;
+        text = text.replace(detail, MASK);
+    }
+
+    text = LABELLED_DETAILS
+        .replace_all(&text, |captures: &Captures| {
+            if captures[2].trim() == MASK {
+                return captures[0].to_owned();
+            }
+            count += 1;
+            format!("{}{MASK}", &captures[1])
+        })
+        .into_owned();
+
+    for pattern in [&*CONTACT_DETAILS, &*NAMED_PLACES] {
+        count += pattern.find_iter(&text).count() as u32;
+        text = pattern.replace_all(&text, MASK).into_owned();
+    }
+    (text, count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::redact;
+
+    #[test]
+    fn contact_numbers_are_masked_in_any_grouping() {
+        let (text, count) = redact(
+            "手机138-0013-8000，身份证11010519900307123X，邮箱a.b@example.com，2024-03-15入职，月薪8000元。",
+            &[],
+        );
+        assert_eq!(
+            text,
+            "手机***，身份证***，邮箱***，2024-03-15入职，月薪8000元。"
+        );
+        assert_eq!(count, 3);
+    }
+
+    #[test]
+    fn names_employers_and_addresses_are_masked() {
+        let (text, count) = redact(
+            "姓名：张三，我在北京某某科技有限公司工作，家住朝阳区建国路88号。用人单位应支付加班费。",
+            &[],
+        );
+        assert_eq!(
+            text,
+            "姓名：***，我在***工作，家住***。用人单位应支付加班费。"
+        );
+        assert_eq!(count, 3);
+
+        let (text, count) = redact(
+            "对方是某某餐饮店，某某餐饮店拖欠工资。",
+            &["某某餐饮店", " "],
+        );
+        assert_eq!(text, "对方是***，***拖欠工资。");
+        assert_eq!(count, 2);
+    }
+}
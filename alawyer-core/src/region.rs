@@ -0,0 +1,202 @@
+use chrono::{DateTime, FixedOffset, NaiveDate, Utc};
+
+/// Where the app is deployed. Selects the disclaimer wording, where users
+/// are pointed for professional help, and how dates are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, uniffi::Enum)]
+pub enum DeploymentRegion {
+    #[default]
+    Mainland,
+    HongKong,
+    Overseas,
+}
+
+const CHINA_STANDARD_TIME_SECS: i32 = 8 * 60 * 60;
+const HOUR_SECS: i32 = 60 * 60;
+/// Furthest offsets in use, UTC-12 to UTC+14.
+const MIN_OFFSET_SECS: i32 = -12 * HOUR_SECS;
+const MAX_OFFSET_SECS: i32 = 14 * HOUR_SECS;
+
+/// Zone names accepted besides plain offsets. Only zones without daylight
+/// saving time are listed, since a fixed offset would be wrong half the
+/// year for the others; give those as an offset.
+const NAMED_ZONES: [(&str, i32); 14] = [
+    ("UTC", 0),
+    ("Asia/Shanghai", CHINA_STANDARD_TIME_SECS),
+    ("Asia/Chongqing", CHINA_STANDARD_TIME_SECS),
+    ("Asia/Urumqi", 6 * HOUR_SECS),
+    ("Asia/Hong_Kong", CHINA_STANDARD_TIME_SECS),
+    ("Asia/Macau", CHINA_STANDARD_TIME_SECS),
+    ("Asia/Taipei", CHINA_STANDARD_TIME_SECS),
+    ("Asia/Singapore", CHINA_STANDARD_TIME_SECS),
+    ("Asia/Kuala_Lumpur", CHINA_STANDARD_TIME_SECS),
+    ("Asia/Tokyo", 9 * HOUR_SECS),
+    ("Asia/Seoul", 9 * HOUR_SECS),
+    ("Asia/Bangkok", 7 * HOUR_SECS),
+    ("Asia/Kolkata", 5 * HOUR_SECS + 30 * 60),
+    ("Asia/Dubai", 4 * HOUR_SECS),
+];
+
+/// Time zone of dates shown to users: report dates, file names and "today"
+/// in fact checks. Timestamps in storage and events stay Unix seconds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserTimeZone {
+    /// As configured, e.g. `Asia/Shanghai` or `+08:00`.
+    pub name: String,
+    offset: FixedOffset,
+}
+
+impl UserTimeZone {
+    /// A name of `NAMED_ZONES`, or an offset such as `+08:00`, `-0530`,
+    /// `UTC+8` or `GMT-5`.
+    pub fn parse(raw: &str) -> Option<Self> {
+        let raw = raw.trim();
+        let (name, seconds) = match NAMED_ZONES
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(raw))
+        {
+            Some((name, seconds)) => (*name, *seconds),
+            None => (raw, parse_offset(raw)?),
+        };
+        Some(Self {
+            name: name.to_owned(),
+            offset: FixedOffset::east_opt(seconds)?,
+        })
+    }
+
+    pub fn offset(&self) -> FixedOffset {
+        self.offset
+    }
+
+    /// `UTC` or `UTC+08:00`.
+    pub fn label(&self) -> String {
+        match self.offset.local_minus_utc() {
+            0 => "UTC".to_owned(),
+            _ => format!("UTC{}", self.offset),
+        }
+    }
+
+    pub fn date(&self, at: DateTime<Utc>) -> NaiveDate {
+        at.with_timezone(&self.offset).date_naive()
+    }
+}
+
+/// `+08:00`, `+0800`, `+8`, optionally after `UTC` or `GMT`.
+fn parse_offset(raw: &str) -> Option<i32> {
+    let raw = ["UTC", "GMT", "utc", "gmt"]
+        .iter()
+        .find_map(|prefix| raw.strip_prefix(prefix))
+        .unwrap_or(raw);
+    let (sign, rest) = match raw.as_bytes().first()? {
+        b'+' => (1, &raw[1..]),
+        b'-' => (-1, &raw[1..]),
+        _ => return None,
+    };
+    let (hours, minutes) = match rest.split_once(':') {
+        Some((hours, minutes)) => (hours, minutes),
+        None if rest.len() == 4 => rest.split_at(2),
+        None => (rest, "0"),
+    };
+    let hours = hours.parse::<i32>().ok().filter(|hours| *hours <= 14)?;
+    let minutes = minutes
+        .parse::<i32>()
+        .ok()
+        .filter(|minutes| *minutes < 60)?;
+    let seconds = sign * (hours * HOUR_SECS + minutes * 60);
+    (MIN_OFFSET_SECS..=MAX_OFFSET_SECS)
+        .contains(&seconds)
+        .then_some(seconds)
+}
+
+impl DeploymentRegion {
+    /// Numbered disclaimer lines, without the `【免责声明】` heading.
+    pub fn disclaimer(self) -> &'static str {
+        match self {
+            Self::Mainland => {
+                "1. 本报告由AI生成，仅供参考，不构成法律意见或律师建议\n2. 案件具体情况可能影响法律适用，建议咨询执业律师\n3. 法规可能存在时效性，请以最新颁布版本为准\n4. 本报告不保证准确性、完整性或适用性"
+            }
+            Self::HongKong => {
+                "1. 本报告由AI生成，仅供参考，不构成法律意见或律师建议\n2. 香港特别行政区实行普通法制度，内地法规及案例未必适用，请以香港法例为准\n3. 案件具体情况可能影响法律适用，建议咨询香港执业律师\n4. 本报告不保证准确性、完整性或适用性"
+            }
+            Self::Overseas => {
+                "1. 本报告由AI生成，仅供参考，不构成法律意见或律师建议\n2. 本报告依据中国内地法律整理，您所在国家或地区的法律可能有很大不同\n3. 案件具体情况可能影响法律适用，建议咨询当地执业律师\n4. 本报告不保证准确性、完整性或适用性"
+            }
+        }
+    }
+
+    /// Where to get help beyond this app, appended to escalation advice.
+    pub fn escalation_resources(self) -> &'static str {
+        match self {
+            Self::Mainland => "可拨打 12348 公共法律服务热线，或前往当地法律援助中心咨询。",
+            Self::HongKong => "可致电法律援助署（2537 7677）或香港律师会（2846 0500）查询。",
+            Self::Overseas => {
+                "可联系当地律师协会；如需领事协助，可拨打外交部全球领事保护与服务应急热线 +86-10-12308。"
+            }
+        }
+    }
+
+    /// Zone of user-facing dates unless one is configured. Overseas users
+    /// could be anywhere, so dates there default to UTC.
+    pub fn time_zone(self) -> UserTimeZone {
+        let name = match self {
+            Self::Mainland => "Asia/Shanghai",
+            Self::HongKong => "Asia/Hong_Kong",
+            Self::Overseas => "UTC",
+        };
+        UserTimeZone::parse(name).expect("named zone")
+    }
+
+    /// Calendar date in the region's usual notation, in `zone`.
+    pub fn format_date(self, at: DateTime<Utc>, zone: &UserTimeZone) -> String {
+        let local = at.with_timezone(&zone.offset());
+        match self {
+            Self::Mainland => local.format("%Y年%m月%d日").to_string(),
+            Self::HongKong => local.format("%d/%m/%Y").to_string(),
+            // ISO 8601 with the zone named is unambiguous anywhere.
+            Self::Overseas => format!("{} ({})", local.format("%Y-%m-%d"), zone.label()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::{DeploymentRegion, UserTimeZone};
+
+    #[test]
+    fn regions_format_dates_in_local_conventions() {
+        // 2024-03-05 17:30 UTC is already March 6th in Beijing and Hong Kong.
+        let at = Utc
+            .with_ymd_and_hms(2024, 3, 5, 17, 30, 0)
+            .single()
+            .expect("date");
+
+        let date = |region: DeploymentRegion| region.format_date(at, &region.time_zone());
+        assert_eq!(date(DeploymentRegion::Mainland), "2024年03月06日");
+        assert_eq!(date(DeploymentRegion::HongKong), "06/03/2024");
+        assert_eq!(date(DeploymentRegion::Overseas), "2024-03-05 (UTC)");
+
+        // A user in Los Angeles (UTC-8 in March) is still on the 5th.
+        let pacific = UserTimeZone::parse("UTC-8").expect("offset");
+        assert_eq!(
+            DeploymentRegion::Mainland.format_date(at, &pacific),
+            "2024年03月05日"
+        );
+        let tokyo = UserTimeZone::parse("+09:00").expect("offset");
+        assert_eq!(
+            DeploymentRegion::Overseas.format_date(at, &tokyo),
+            "2024-03-06 (UTC+09:00)"
+        );
+        assert_eq!(
+            UserTimeZone::parse("asia/kolkata").map(|zone| zone.label()),
+            Some("UTC+05:30".to_owned())
+        );
+        for invalid in ["America/New_York", "+15:00", "8", "+08:75", ""] {
+            assert!(UserTimeZone::parse(invalid).is_none(), "{invalid}");
+        }
+        assert!(DeploymentRegion::HongKong.disclaimer().contains("香港法例"));
+        assert!(DeploymentRegion::Mainland
+            .escalation_resources()
+            .contains("12348"));
+    }
+}
@@ -0,0 +1,90 @@
+//! A minimal statutes corpus compiled into the app (`builtin-kb` feature),
+//! so a fresh install whose KB is still empty finds the core articles of
+//! each scenario instead of nothing. On first run it is extracted into an
+//! empty `kb_path`; while the KB has no files at all, e.g. because it
+//! could not be written, the corpus is searched in memory.
+//!
+//! Its files sit under `{scenario}/builtin/`, say `（内置摘录）` in their
+//! titles and are marked `SearchResult::builtin`, so they are never taken
+//! for a KB the user installed.
+
+use std::fs;
+use std::path::Path;
+
+use crate::error::{CoreError, CoreResult};
+
+#[cfg(feature = "builtin-kb")]
+mod corpus {
+    use include_flate::flate;
+
+    flate!(pub static LABOR_CONTRACT: str from "data/builtin_kb/labor/labor-contract-law.md");
+    flate!(pub static LABOR_ARBITRATION: str
+        from "data/builtin_kb/labor/labor-dispute-arbitration-law.md");
+    flate!(pub static RENTAL: str from "data/builtin_kb/rental/civil-code-lease.md");
+    flate!(pub static CONSUMER: str from "data/builtin_kb/consumer/consumer-rights-law.md");
+    flate!(pub static FAMILY: str from "data/builtin_kb/family/civil-code-marriage.md");
+    flate!(pub static TRAFFIC: str from "data/builtin_kb/traffic/road-traffic-safety-law.md");
+}
+
+/// The corpus as `(path relative to the KB root, content)`.
+#[cfg(feature = "builtin-kb")]
+pub fn documents() -> Vec<(&'static str, &'static str)> {
+    vec![
+        (
+            "labor/builtin/labor-contract-law.md",
+            corpus::LABOR_CONTRACT.as_str(),
+        ),
+        (
+            "labor/builtin/labor-dispute-arbitration-law.md",
+            corpus::LABOR_ARBITRATION.as_str(),
+        ),
+        (
+            "rental/builtin/civil-code-lease.md",
+            corpus::RENTAL.as_str(),
+        ),
+        (
+            "consumer/builtin/consumer-rights-law.md",
+            corpus::CONSUMER.as_str(),
+        ),
+        (
+            "family/builtin/civil-code-marriage.md",
+            corpus::FAMILY.as_str(),
+        ),
+        (
+            "traffic/builtin/road-traffic-safety-law.md",
+            corpus::TRAFFIC.as_str(),
+        ),
+    ]
+}
+
+#[cfg(not(feature = "builtin-kb"))]
+pub fn documents() -> Vec<(&'static str, &'static str)> {
+    Vec::new()
+}
+
+/// Whether `relative_path` is a file of the corpus.
+pub fn is_builtin(relative_path: &str) -> bool {
+    content(relative_path).is_some()
+}
+
+pub fn content(relative_path: &str) -> Option<&'static str> {
+    documents()
+        .into_iter()
+        .find(|(path, _)| *path == relative_path)
+        .map(|(_, content)| content)
+}
+
+/// Write the corpus into `kb_root`; returns how many files were written.
+pub fn extract(kb_root: &Path) -> CoreResult<u32> {
+    let documents = documents();
+    for (relative, content) in &documents {
+        let file = kb_root.join(relative);
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| CoreError::Storage(format!("create builtin kb dir failed: {e}")))?;
+        }
+        fs::write(&file, content)
+            .map_err(|e| CoreError::Storage(format!("write builtin kb file failed: {e}")))?;
+    }
+    Ok(documents.len() as u32)
+}
@@ -0,0 +1,354 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::Arc;
+#[cfg(feature = "model-remote")]
+use std::time::Duration;
+
+use ring::digest::{digest, SHA256};
+
+#[cfg(feature = "model-remote")]
+use serde::Deserialize;
+
+use crate::error::{CoreError, CoreResult};
+
+#[cfg(feature = "model-remote")]
+const DEFAULT_EMBEDDING_BASE_URL: &str = "https://openrouter.ai/api/v1";
+
+/// Texts sent per `/embeddings` request.
+#[cfg(feature = "model-remote")]
+const REMOTE_BATCH_SIZE: usize = 64;
+
+/// Chunk vectors kept in memory; the oldest are dropped beyond this.
+const CACHE_CAPACITY: usize = 20_000;
+
+/// Where the hybrid retrieval path gets its embeddings from.
+#[derive(Clone, PartialEq, Eq, uniffi::Enum)]
+pub enum EmbeddingConfig {
+    /// An OpenAI-compatible `/embeddings` endpoint, e.g. OpenRouter or
+    /// OpenAI. KB chunks and queries leave the device.
+    Remote {
+        base_url: Option<String>,
+        api_key: String,
+        model_name: String,
+    },
+    /// A BERT-style sentence encoder exported to ONNX (e.g. bge-small-zh)
+    /// with its WordPiece `vocab.txt`, run on the device. Needs a build with
+    /// the `onnx` feature.
+    LocalOnnx {
+        model_path: String,
+        vocab_path: String,
+    },
+}
+
+impl fmt::Debug for EmbeddingConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Remote {
+                base_url,
+                model_name,
+                ..
+            } => f
+                .debug_struct("Remote")
+                .field("base_url", base_url)
+                .field("api_key", &"<redacted>")
+                .field("model_name", model_name)
+                .finish(),
+            Self::LocalOnnx {
+                model_path,
+                vocab_path,
+            } => f
+                .debug_struct("LocalOnnx")
+                .field("model_path", model_path)
+                .field("vocab_path", vocab_path)
+                .finish(),
+        }
+    }
+}
+
+/// Turns texts into vectors for semantic ranking. Vectors of one provider
+/// must share a dimension; they need not be normalized.
+pub trait EmbeddingProvider: Send + Sync {
+    fn embed(&self, texts: &[String]) -> CoreResult<Vec<Vec<f32>>>;
+}
+
+pub fn provider_for(config: &EmbeddingConfig) -> CoreResult<Arc<dyn EmbeddingProvider>> {
+    match config {
+        #[cfg(feature = "model-remote")]
+        EmbeddingConfig::Remote {
+            base_url,
+            api_key,
+            model_name,
+        } => Ok(Arc::new(RemoteEmbeddingProvider::new(
+            base_url.as_deref().unwrap_or(DEFAULT_EMBEDDING_BASE_URL),
+            api_key,
+            model_name,
+        )?)),
+        #[cfg(not(feature = "model-remote"))]
+        EmbeddingConfig::Remote { .. } => Err(CoreError::Config(
+            "remote embeddings need a build with the model-remote feature".to_owned(),
+        )),
+        #[cfg(feature = "onnx")]
+        EmbeddingConfig::LocalOnnx {
+            model_path,
+            vocab_path,
+        } => Ok(Arc::new(onnx::OnnxEmbeddingProvider::load(
+            std::path::Path::new(model_path),
+            std::path::Path::new(vocab_path),
+        )?)),
+        #[cfg(not(feature = "onnx"))]
+        EmbeddingConfig::LocalOnnx { .. } => Err(CoreError::Config(
+            "local ONNX embeddings need a build with the onnx feature".to_owned(),
+        )),
+    }
+}
+
+/// Chunk vectors by a digest of the chunk text, holding at most
+/// `CACHE_CAPACITY` vectors; the oldest go first.
+#[derive(Default)]
+pub(crate) struct EmbeddingCache {
+    vectors: HashMap<[u8; 32], Vec<f32>>,
+    order: VecDeque<[u8; 32]>,
+}
+
+impl EmbeddingCache {
+    pub(crate) fn get(&self, text: &str) -> Option<&Vec<f32>> {
+        self.vectors.get(&text_key(text))
+    }
+
+    pub(crate) fn contains(&self, text: &str) -> bool {
+        self.vectors.contains_key(&text_key(text))
+    }
+
+    pub(crate) fn insert(&mut self, text: &str, vector: Vec<f32>) {
+        let key = text_key(text);
+        if self.vectors.insert(key, vector).is_none() {
+            self.order.push_back(key);
+        }
+        while self.order.len() > CACHE_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.vectors.remove(&oldest);
+            }
+        }
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.vectors.len()
+    }
+}
+
+fn text_key(text: &str) -> [u8; 32] {
+    let mut key = [0; 32];
+    key.copy_from_slice(digest(&SHA256, text.as_bytes()).as_ref());
+    key
+}
+
+pub(crate) fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot = a.iter().zip(b).map(|(x, y)| x * y).sum::<f32>();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let denominator = norm(a) * norm(b);
+    if denominator == 0.0 {
+        0.0
+    } else {
+        dot / denominator
+    }
+}
+
+#[cfg(feature = "model-remote")]
+pub struct RemoteEmbeddingProvider {
+    client: reqwest::Client,
+    url: String,
+    api_key: String,
+    model_name: String,
+}
+
+#[cfg(feature = "model-remote")]
+#[derive(Debug, Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingData>,
+}
+
+#[cfg(feature = "model-remote")]
+#[derive(Debug, Deserialize)]
+struct EmbeddingData {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+#[cfg(feature = "model-remote")]
+impl RemoteEmbeddingProvider {
+    pub fn new(base_url: &str, api_key: &str, model_name: &str) -> CoreResult<Self> {
+        if api_key.trim().is_empty() {
+            return Err(CoreError::Config("embedding API key is empty".to_owned()));
+        }
+        if model_name.trim().is_empty() {
+            return Err(CoreError::Config(
+                "embedding model name is empty".to_owned(),
+            ));
+        }
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(60))
+            .build()
+            .map_err(|e| CoreError::Model(e.to_string()))?;
+        Ok(Self {
+            client,
+            url: format!("{}/embeddings", base_url.trim_end_matches('/')),
+            api_key: api_key.to_owned(),
+            model_name: model_name.to_owned(),
+        })
+    }
+
+    async fn embed_batch(&self, texts: &[String]) -> CoreResult<Vec<Vec<f32>>> {
+        let response = self
+            .client
+            .post(&self.url)
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .json(&serde_json::json!({
+                "model": self.model_name,
+                "input": texts,
+            }))
+            .send()
+            .await
+            .map_err(|e| CoreError::Model(e.to_string()))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(CoreError::Model(format!(
+                "embedding request failed with status {status}: {body}"
+            )));
+        }
+        let mut body: EmbeddingResponse = response
+            .json()
+            .await
+            .map_err(|e| CoreError::Model(e.to_string()))?;
+        if body.data.len() != texts.len() {
+            return Err(CoreError::Model(format!(
+                "expected {} embeddings, got {}",
+                texts.len(),
+                body.data.len()
+            )));
+        }
+        body.data.sort_by_key(|data| data.index);
+        Ok(body.data.into_iter().map(|data| data.embedding).collect())
+    }
+}
+
+#[cfg(feature = "model-remote")]
+impl EmbeddingProvider for RemoteEmbeddingProvider {
+    fn embed(&self, texts: &[String]) -> CoreResult<Vec<Vec<f32>>> {
+        let mut vectors = Vec::with_capacity(texts.len());
+        for batch in texts.chunks(REMOTE_BATCH_SIZE) {
+            vectors.extend(crate::RUNTIME.block_on(self.embed_batch(batch))?);
+        }
+        Ok(vectors)
+    }
+}
+
+#[cfg(feature = "onnx")]
+mod onnx {
+    use std::path::Path;
+    use std::sync::Mutex;
+
+    use ort::session::Session;
+    use ort::value::Tensor;
+
+    use super::super::wordpiece::WordPiece;
+    use super::EmbeddingProvider;
+    use crate::error::{CoreError, CoreResult};
+
+    /// Longest input the BERT-style encoders accept, special tokens included.
+    const MAX_SEQUENCE_TOKENS: usize = 512;
+
+    /// Encodes one text at a time and pools the `[CLS]` token, as bge models
+    /// are trained to be used.
+    pub struct OnnxEmbeddingProvider {
+        session: Mutex<Session>,
+        tokenizer: WordPiece,
+    }
+
+    impl OnnxEmbeddingProvider {
+        pub fn load(model_path: &Path, vocab_path: &Path) -> CoreResult<Self> {
+            let session = Session::builder()
+                .and_then(|builder| builder.commit_from_file(model_path))
+                .map_err(|e| CoreError::Config(format!("load embedding model failed: {e}")))?;
+            Ok(Self {
+                session: Mutex::new(session),
+                tokenizer: WordPiece::load(vocab_path)?,
+            })
+        }
+
+        fn embed_one(&self, session: &mut Session, text: &str) -> CoreResult<Vec<f32>> {
+            let ids = self.tokenizer.encode(text, MAX_SEQUENCE_TOKENS);
+            let shape = [1_usize, ids.len()];
+            let tensor = |values: Vec<i64>| {
+                Tensor::from_array((shape, values))
+                    .map_err(|e| CoreError::Model(format!("build embedding input failed: {e}")))
+            };
+            let input_ids = tensor(ids.iter().map(|&id| i64::from(id)).collect())?;
+            let attention_mask = tensor(vec![1; ids.len()])?;
+            let token_type_ids = tensor(vec![0; ids.len()])?;
+
+            let outputs = session
+                .run(ort::inputs![
+                    "input_ids" => input_ids,
+                    "attention_mask" => attention_mask,
+                    "token_type_ids" => token_type_ids,
+                ])
+                .map_err(|e| CoreError::Model(format!("embedding inference failed: {e}")))?;
+            let (shape, hidden) = outputs[0]
+                .try_extract_tensor::<f32>()
+                .map_err(|e| CoreError::Model(format!("read embedding output failed: {e}")))?;
+            // [batch, tokens, hidden]; the first token of the only sequence.
+            let width = shape.last().copied().unwrap_or_default() as usize;
+            hidden
+                .get(..width)
+                .map(<[f32]>::to_vec)
+                .ok_or_else(|| CoreError::Model("embedding output is empty".to_owned()))
+        }
+    }
+
+    impl EmbeddingProvider for OnnxEmbeddingProvider {
+        fn embed(&self, texts: &[String]) -> CoreResult<Vec<Vec<f32>>> {
+            let mut session = self
+                .session
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            texts
+                .iter()
+                .map(|text| self.embed_one(&mut session, text))
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EmbeddingCache, EmbeddingConfig, CACHE_CAPACITY};
+
+    #[test]
+    fn cache_is_bounded_and_drops_the_oldest() {
+        let mut cache = EmbeddingCache::default();
+        for index in 0..=CACHE_CAPACITY {
+            cache.insert(&format!("chunk {index}"), vec![index as f32]);
+        }
+        assert_eq!(cache.len(), CACHE_CAPACITY);
+        assert!(!cache.contains("chunk 0"));
+        assert_eq!(
+            cache.get(&format!("chunk {CACHE_CAPACITY}")),
+            Some(&vec![CACHE_CAPACITY as f32])
+        );
+    }
+
+    #[test]
+    fn debug_output_hides_the_api_key() {
+        let config = EmbeddingConfig::Remote {
+            base_url: None,
+            api_key: "sk-secret".to_owned(),
+            model_name: "text-embedding-3-small".to_owned(),
+        };
+        let printed = format!("{config:?}");
+        assert!(!printed.contains("sk-secret"));
+        assert!(printed.contains("text-embedding-3-small"));
+    }
+}
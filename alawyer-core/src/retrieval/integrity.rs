@@ -0,0 +1,282 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard, RwLock};
+use std::time::SystemTime;
+
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{CoreError, CoreResult};
+
+/// Checksum manifest at the KB root. Without one, integrity checks are off
+/// and every file is trusted, as for a KB the user maintains themselves.
+pub const MANIFEST_FILE: &str = "kb-manifest.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, uniffi::Enum)]
+pub enum KbIntegrityProblem {
+    /// Content no longer matches the manifest checksum.
+    Modified,
+    /// Listed in the manifest but absent.
+    Missing,
+    /// Present but not listed, e.g. added after the KB was bundled.
+    Unlisted,
+    Unreadable,
+}
+
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct KbIntegrityIssue {
+    /// Path relative to the KB root, always `/`-separated.
+    pub relative_path: String,
+    pub problem: KbIntegrityProblem,
+    pub expected_sha256: Option<String>,
+    pub actual_sha256: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, uniffi::Record)]
+pub struct KbIntegrityReport {
+    pub manifest_present: bool,
+    /// Why the manifest could not be parsed. Every file then fails.
+    pub manifest_error: Option<String>,
+    pub files_checked: u32,
+    pub files_passed: u32,
+    pub issues: Vec<KbIntegrityIssue>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Manifest {
+    /// Relative path to lowercase hex SHA-256.
+    files: BTreeMap<String, String>,
+}
+
+enum ManifestState {
+    Absent,
+    Loaded(Manifest),
+    Invalid(String),
+}
+
+/// Verdicts keyed by relative path, valid while the file's modification
+/// time and size are unchanged, so searches do not re-hash the whole KB.
+type VerdictCache = HashMap<String, (Option<SystemTime>, u64, bool)>;
+
+pub(crate) struct IntegrityGuard {
+    manifest: RwLock<ManifestState>,
+    verdicts: Mutex<VerdictCache>,
+}
+
+impl IntegrityGuard {
+    pub(crate) fn load(kb_root: &Path) -> Self {
+        Self {
+            manifest: RwLock::new(read_manifest(kb_root)),
+            verdicts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn reload(&self, kb_root: &Path) {
+        *self
+            .manifest
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = read_manifest(kb_root);
+        self.lock_verdicts().clear();
+    }
+
+    /// Refuse `file` (named `relative` under the KB root) when a manifest is
+    /// present and the file does not match it.
+    pub(crate) fn check(&self, relative: &str, file: &Path) -> CoreResult<()> {
+        let manifest = self
+            .manifest
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let expected = match &*manifest {
+            ManifestState::Absent => return Ok(()),
+            ManifestState::Invalid(_) => None,
+            ManifestState::Loaded(manifest) => manifest.files.get(relative),
+        };
+        let refused = || CoreError::Safety(format!("kb file {relative} failed integrity check"));
+        let expected = expected.ok_or_else(refused)?;
+
+        let meta = fs::metadata(file).map_err(|_| refused())?;
+        let stamp = (meta.modified().ok(), meta.len());
+        if let Some((modified, len, passed)) = self.lock_verdicts().get(relative) {
+            if (*modified, *len) == stamp {
+                return if *passed { Ok(()) } else { Err(refused()) };
+            }
+        }
+
+        let passed = sha256_file(file).is_ok_and(|actual| &actual == expected);
+        self.lock_verdicts()
+            .insert(relative.to_owned(), (stamp.0, stamp.1, passed));
+        if passed {
+            Ok(())
+        } else {
+            Err(refused())
+        }
+    }
+
+    /// Check `files` (relative path, absolute path) against the manifest,
+    /// and every manifest entry for presence.
+    pub(crate) fn verify<'a>(
+        &self,
+        files: impl IntoIterator<Item = (String, &'a Path)>,
+    ) -> KbIntegrityReport {
+        let manifest = self
+            .manifest
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let (listed, manifest_error) = match &*manifest {
+            ManifestState::Absent => return KbIntegrityReport::default(),
+            ManifestState::Loaded(manifest) => (&manifest.files, None),
+            ManifestState::Invalid(err) => (&BTreeMap::new(), Some(err.clone())),
+        };
+        let mut report = KbIntegrityReport {
+            manifest_present: true,
+            manifest_error,
+            ..KbIntegrityReport::default()
+        };
+
+        let mut seen = Vec::new();
+        for (relative, file) in files {
+            report.files_checked += 1;
+            let expected = listed.get(&relative).cloned();
+            let problem = match (&expected, sha256_file(file)) {
+                (_, Err(_)) => Some((KbIntegrityProblem::Unreadable, None)),
+                (None, Ok(actual)) => Some((KbIntegrityProblem::Unlisted, Some(actual))),
+                (Some(expected), Ok(actual)) if &actual != expected => {
+                    Some((KbIntegrityProblem::Modified, Some(actual)))
+                }
+                (Some(_), Ok(_)) => None,
+            };
+            match problem {
+                Some((problem, actual_sha256)) => report.issues.push(KbIntegrityIssue {
+                    relative_path: relative.clone(),
+                    problem,
+                    expected_sha256: expected,
+                    actual_sha256,
+                }),
+                None => report.files_passed += 1,
+            }
+            seen.push(relative);
+        }
+
+        for (relative, expected) in listed {
+            if !seen.contains(relative) {
+                report.issues.push(KbIntegrityIssue {
+                    relative_path: relative.clone(),
+                    problem: KbIntegrityProblem::Missing,
+                    expected_sha256: Some(expected.clone()),
+                    actual_sha256: None,
+                });
+            }
+        }
+
+        // Later checks re-hash against what was just verified.
+        self.lock_verdicts().clear();
+        report
+    }
+
+    fn lock_verdicts(&self) -> MutexGuard<'_, VerdictCache> {
+        self.verdicts
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Write a manifest for `files` (relative path, absolute path) to the KB
+/// root, replacing any existing one.
+pub(crate) fn write_manifest<'a>(
+    kb_root: &Path,
+    files: impl IntoIterator<Item = (String, &'a Path)>,
+) -> CoreResult<u32> {
+    let mut manifest = Manifest::default();
+    for (relative, file) in files {
+        let checksum = sha256_file(file)
+            .map_err(|e| CoreError::Storage(format!("read kb file failed: {e}")))?;
+        manifest.files.insert(relative, checksum);
+    }
+    let raw = serde_json::to_string_pretty(&manifest)
+        .map_err(|e| CoreError::Unknown(format!("serialize kb manifest failed: {e}")))?;
+    fs::write(kb_root.join(MANIFEST_FILE), raw)
+        .map_err(|e| CoreError::Storage(format!("write kb manifest failed: {e}")))?;
+    Ok(manifest.files.len() as u32)
+}
+
+fn read_manifest(kb_root: &Path) -> ManifestState {
+    let raw = match fs::read_to_string(kb_root.join(MANIFEST_FILE)) {
+        Ok(raw) => raw,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return ManifestState::Absent,
+        Err(err) => return ManifestState::Invalid(err.to_string()),
+    };
+    match serde_json::from_str(&raw) {
+        Ok(manifest) => ManifestState::Loaded(manifest),
+        Err(err) => ManifestState::Invalid(err.to_string()),
+    }
+}
+
+fn sha256_file(file: &Path) -> std::io::Result<String> {
+    let bytes = fs::read(file)?;
+    Ok(digest(&SHA256, &bytes)
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use super::{KbIntegrityProblem, MANIFEST_FILE};
+    use crate::error::CoreError;
+    use crate::retrieval::{RetrievalConfig, RetrievalEngine};
+
+    #[test]
+    fn tampered_files_are_reported_and_refused() {
+        let dir = TempDir::new().expect("temp dir");
+        let labor = dir.path().join("labor");
+        fs::create_dir_all(&labor).expect("create labor dir");
+        fs::write(labor.join("wage.md"), "# 工资\n拖欠工资可以申请劳动仲裁。").expect("write");
+        fs::write(labor.join("leave.md"), "# 休假\n拖欠工资期间的年休假。").expect("write");
+        let engine = RetrievalEngine::new(dir.path(), RetrievalConfig::default());
+        assert!(!engine.verify_integrity().expect("verify").manifest_present);
+
+        assert_eq!(engine.write_manifest().expect("manifest"), 2);
+        let report = engine.verify_integrity().expect("verify");
+        assert_eq!((report.files_checked, report.files_passed), (2, 2));
+        assert!(report.issues.is_empty());
+
+        let wage = labor.join("wage.md");
+        fs::write(&wage, "# 工资\n拖欠工资无需任何证据。").expect("tamper");
+        fs::write(labor.join("extra.md"), "# 新增\n拖欠工资").expect("write unlisted");
+        fs::remove_file(labor.join("leave.md")).expect("remove");
+
+        let report = engine.verify_integrity().expect("verify");
+        let problems = report
+            .issues
+            .iter()
+            .map(|issue| (issue.relative_path.as_str(), issue.problem))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            problems,
+            [
+                ("labor/extra.md", KbIntegrityProblem::Unlisted),
+                ("labor/wage.md", KbIntegrityProblem::Modified),
+                ("labor/leave.md", KbIntegrityProblem::Missing),
+            ]
+        );
+        assert!(matches!(
+            engine.read_file(&wage.to_string_lossy()),
+            Err(CoreError::Safety(_))
+        ));
+        assert!(engine
+            .search("拖欠工资", "labor", 5)
+            .expect("search")
+            .is_empty());
+
+        fs::write(dir.path().join(MANIFEST_FILE), "not json").expect("corrupt manifest");
+        let report = engine.verify_integrity().expect("verify");
+        assert!(report.manifest_error.is_some());
+        assert_eq!(report.files_passed, 0);
+    }
+}
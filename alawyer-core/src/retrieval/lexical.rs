@@ -0,0 +1,293 @@
+//! Text ranking of KB chunks: a BM25 index over jieba tokens with the
+//! `retrieval-tantivy` feature, otherwise a substring scan that needs no
+//! index or dictionary, for builds where binary size matters more.
+
+/// A chunk to rank: its id and text.
+pub(crate) struct LexicalDoc<'a> {
+    pub id: String,
+    pub text: &'a str,
+}
+
+#[cfg(feature = "retrieval-tantivy")]
+pub(crate) use index::rank;
+#[cfg(not(feature = "retrieval-tantivy"))]
+pub(crate) use scan::rank;
+
+#[cfg(feature = "retrieval-tantivy")]
+mod index {
+    use std::sync::Arc;
+
+    use jieba_rs::Jieba;
+    use once_cell::sync::Lazy;
+    use tantivy::collector::TopDocs;
+    use tantivy::indexer::UserOperation;
+    use tantivy::query::QueryParser;
+    use tantivy::schema::Value;
+    use tantivy::schema::{
+        IndexRecordOption, SchemaBuilder, TextFieldIndexing, TextOptions, STORED,
+    };
+    use tantivy::{doc, Index, ReloadPolicy};
+
+    use super::LexicalDoc;
+    use crate::error::{CoreError, CoreResult};
+    use crate::retrieval::parallel::IndexPool;
+    use crate::retrieval::stopwords::TokenFilter;
+
+    /// Process-level singleton for Jieba tokenizer.
+    /// Loading the built-in dictionary is expensive (~350K entries decompressed at runtime).
+    /// Sharing a single instance across all RetrievalEngine instances avoids repeated init.
+    static JIEBA: Lazy<Arc<Jieba>> = Lazy::new(|| Arc::new(Jieba::new()));
+
+    const WRITER_BUDGET_BYTES: usize = 50_000_000;
+    /// Tantivy's minimum per-thread budget; used with a single writer thread.
+    const LOW_MEMORY_WRITER_BUDGET_BYTES: usize = 15_000_000;
+    /// Documents handed to the writer in one operation batch, rather than
+    /// one channel send per chunk.
+    const WRITER_BATCH_DOCS: usize = 512;
+
+    /// `(id, score)` of the `limit` best matches for `query`, best first.
+    pub(crate) fn rank(
+        docs: &[LexicalDoc<'_>],
+        query: &str,
+        limit: usize,
+        low_memory: bool,
+        tokens: &TokenFilter,
+        pool: &IndexPool,
+    ) -> CoreResult<Vec<(String, f32)>> {
+        let query = tokenize_zh(query, tokens);
+        if query.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut schema_builder = SchemaBuilder::default();
+        let text_indexing = TextFieldIndexing::default()
+            .set_tokenizer("default")
+            .set_index_option(IndexRecordOption::WithFreqsAndPositions);
+        let text_options = TextOptions::default()
+            .set_indexing_options(text_indexing)
+            .set_stored();
+
+        let chunk_id_f = schema_builder.add_text_field("chunk_id", STORED);
+        let content_f = schema_builder.add_text_field("content", text_options);
+
+        let schema = schema_builder.build();
+        let index = Index::create_in_ram(schema);
+        let writer = if low_memory {
+            index.writer_with_num_threads(1, LOW_MEMORY_WRITER_BUDGET_BYTES)
+        } else {
+            // Every writer thread needs the minimum budget out of the total.
+            let threads = pool
+                .threads()
+                .min(WRITER_BUDGET_BYTES / LOW_MEMORY_WRITER_BUDGET_BYTES);
+            index.writer_with_num_threads(threads, WRITER_BUDGET_BYTES)
+        };
+        let mut writer: tantivy::IndexWriter =
+            writer.map_err(|e| CoreError::Unknown(format!("index writer failed: {e}")))?;
+
+        let texts = pool.map(docs.iter().map(|doc| doc.text).collect(), |text| {
+            tokenize_zh(text, tokens)
+        });
+        let mut batch = Vec::with_capacity(WRITER_BATCH_DOCS.min(docs.len()));
+        for (doc, text) in docs.iter().zip(texts) {
+            batch.push(UserOperation::Add(doc!(
+                chunk_id_f => doc.id.clone(),
+                content_f => text,
+            )));
+            if batch.len() == WRITER_BATCH_DOCS {
+                writer
+                    .run(batch.drain(..))
+                    .map_err(|e| CoreError::Unknown(format!("index add documents failed: {e}")))?;
+            }
+        }
+        if !batch.is_empty() {
+            writer
+                .run(batch)
+                .map_err(|e| CoreError::Unknown(format!("index add documents failed: {e}")))?;
+        }
+
+        writer
+            .commit()
+            .map_err(|e| CoreError::Unknown(format!("index commit failed: {e}")))?;
+
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::Manual)
+            .try_into()
+            .map_err(|e| CoreError::Unknown(format!("index reader failed: {e}")))?;
+        reader
+            .reload()
+            .map_err(|e| CoreError::Unknown(format!("index reload failed: {e}")))?;
+
+        let searcher = reader.searcher();
+        let query_parser = QueryParser::for_index(&index, vec![content_f]);
+        let parsed_query = query_parser
+            .parse_query(&query)
+            .map_err(|e| CoreError::Unknown(format!("query parse failed: {e}")))?;
+
+        let top_docs = searcher
+            .search(&parsed_query, &TopDocs::with_limit(limit))
+            .map_err(|e| CoreError::Unknown(format!("search failed: {e}")))?;
+
+        let mut ranked = Vec::with_capacity(top_docs.len());
+        for (score, addr) in top_docs {
+            let retrieved = searcher
+                .doc::<tantivy::schema::TantivyDocument>(addr)
+                .map_err(|e| CoreError::Unknown(format!("doc read failed: {e}")))?;
+            if let Some(chunk_id) = retrieved.get_first(chunk_id_f).and_then(|v| v.as_str()) {
+                ranked.push((chunk_id.to_owned(), score));
+            }
+        }
+        Ok(ranked)
+    }
+
+    /// Space-separated jieba tokens of `input` without stopwords; spans
+    /// `tokens` keeps whole are not segmented, see `TokenFilter::whole_tokens`.
+    fn tokenize_zh(input: &str, tokens: &TokenFilter) -> String {
+        let mut words = Vec::new();
+        for (span, whole) in tokens.spans(input) {
+            if whole {
+                words.extend(tokens.whole_tokens(span));
+                continue;
+            }
+            words.extend(
+                JIEBA
+                    .cut(span, false)
+                    .into_iter()
+                    .filter(|token| !token.trim().is_empty() && !tokens.is_stopword(token))
+                    .map(str::to_owned),
+            );
+        }
+        words.join(" ")
+    }
+}
+
+#[cfg_attr(feature = "retrieval-tantivy", allow(dead_code))]
+mod scan {
+    use super::LexicalDoc;
+    use crate::error::CoreResult;
+    use crate::retrieval::parallel::IndexPool;
+    use crate::retrieval::stopwords::TokenFilter;
+
+    /// BM25 saturation and length normalization, as in tantivy's scorer.
+    const K1: f32 = 1.2;
+    const B: f32 = 0.75;
+
+    /// `(id, score)` of the `limit` best matches for `query`, best first,
+    /// scored with BM25 over the terms of `query_terms` that are not
+    /// stopwords. Character pairs are never split, so only stopwords that
+    /// form a whole term are dropped. Numerals are compared in Arabic
+    /// digits; see `TokenFilter::normalize_numbers`.
+    pub(crate) fn rank(
+        docs: &[LexicalDoc<'_>],
+        query: &str,
+        limit: usize,
+        _low_memory: bool,
+        tokens: &TokenFilter,
+        _pool: &IndexPool,
+    ) -> CoreResult<Vec<(String, f32)>> {
+        let mut terms = query_terms(&tokens.normalize_numbers(query));
+        terms.retain(|term| !tokens.is_stopword(term));
+        let texts = docs
+            .iter()
+            .map(|doc| tokens.normalize_numbers(doc.text).to_lowercase())
+            .collect::<Vec<_>>();
+        let lengths = texts
+            .iter()
+            .map(|text| text.chars().count() as f32)
+            .collect::<Vec<_>>();
+        let average_length = lengths.iter().sum::<f32>() / lengths.len().max(1) as f32;
+        let idf = terms
+            .iter()
+            .map(|term| {
+                let containing = texts.iter().filter(|text| text.contains(term)).count() as f32;
+                (1.0 + (docs.len() as f32 - containing + 0.5) / (containing + 0.5)).ln()
+            })
+            .collect::<Vec<_>>();
+
+        let mut ranked = docs
+            .iter()
+            .zip(texts.iter().zip(&lengths))
+            .filter_map(|(doc, (text, length))| {
+                let norm = K1 * (1.0 - B + B * length / average_length.max(1.0));
+                let score = terms
+                    .iter()
+                    .zip(&idf)
+                    .map(|(term, idf)| {
+                        let tf = text.matches(term.as_str()).count() as f32;
+                        idf * tf * (K1 + 1.0) / (tf + norm)
+                    })
+                    .sum::<f32>();
+                (score > 0.0).then(|| (doc.id.clone(), score))
+            })
+            .collect::<Vec<_>>();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+        ranked.truncate(limit);
+        Ok(ranked)
+    }
+
+    /// Lowercased words for alphanumeric runs and overlapping character
+    /// pairs for CJK runs, which have no spaces to split on.
+    pub(super) fn query_terms(query: &str) -> Vec<String> {
+        let mut terms = Vec::new();
+        let mut push = |term: String| {
+            if !terms.contains(&term) {
+                terms.push(term);
+            }
+        };
+        for run in query
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|run| !run.is_empty())
+        {
+            let chars = run.chars().collect::<Vec<_>>();
+            if chars.iter().all(char::is_ascii) || chars.len() == 1 {
+                push(run.to_lowercase());
+            } else {
+                for pair in chars.windows(2) {
+                    push(pair.iter().collect::<String>().to_lowercase());
+                }
+            }
+        }
+        terms
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::{query_terms, rank};
+        use crate::retrieval::lexical::LexicalDoc;
+        use crate::retrieval::parallel::IndexPool;
+        use crate::retrieval::stopwords::TokenFilter;
+
+        #[test]
+        fn scan_ranks_by_matching_terms() {
+            assert_eq!(
+                query_terms("拖欠工资 Wage"),
+                ["拖欠", "欠工", "工资", "wage"]
+            );
+            let docs = [
+                LexicalDoc {
+                    id: "a".to_owned(),
+                    text: "拖欠工资可申请劳动仲裁",
+                },
+                LexicalDoc {
+                    id: "b".to_owned(),
+                    text: "加班费按工资基数计算",
+                },
+                LexicalDoc {
+                    id: "c".to_owned(),
+                    text: "房屋租赁合同",
+                },
+            ];
+            let ranked = rank(
+                &docs,
+                "拖欠工资",
+                5,
+                false,
+                &TokenFilter::default(),
+                &IndexPool::new(1),
+            )
+            .expect("rank");
+            let ids = ranked.iter().map(|(id, _)| id.as_str()).collect::<Vec<_>>();
+            assert_eq!(ids, ["a", "b"]);
+        }
+    }
+}
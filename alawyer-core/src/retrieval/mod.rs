@@ -1,25 +1,166 @@
-use std::fs;
-use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use std::time::UNIX_EPOCH;
 
-use jieba_rs::Jieba;
-use once_cell::sync::Lazy;
-use tantivy::collector::TopDocs;
-use tantivy::query::QueryParser;
-use tantivy::schema::Value;
-use tantivy::schema::{
-    IndexRecordOption, NumericOptions, SchemaBuilder, TextFieldIndexing, TextOptions, STORED,
-};
-use tantivy::{doc, Index, ReloadPolicy};
+use chrono::{DateTime, NaiveDate};
+
 use walkdir::WalkDir;
 
+use crate::clock::{Clock, SystemClock};
 use crate::error::{CoreError, CoreResult};
+use crate::model::estimate_tokens;
+
+pub mod builtin;
+pub mod embedding;
+pub mod integrity;
+mod lexical;
+mod parallel;
+pub mod remote;
+pub mod stopwords;
+pub mod temporal;
+pub mod validate;
+pub mod watcher;
+mod wordpiece;
+
+use embedding::EmbeddingCache;
+pub use embedding::{EmbeddingConfig, EmbeddingProvider};
+pub use integrity::KbIntegrityReport;
+pub use remote::RemoteKbConfig;
+pub use temporal::CaseDates;
+pub use validate::KbValidationReport;
+pub use watcher::KbWatcher;
+
+use integrity::IntegrityGuard;
+use lexical::LexicalDoc;
+use parallel::IndexPool;
+use remote::{RemoteChunk, RemoteKb};
+use stopwords::TokenFilter;
+
+const LINES_PER_CHUNK: usize = 20;
+/// Lines shown above and below a chunk in `knowledge_chunk`.
+const CHUNK_CONTEXT_LINES: usize = 5;
+/// Chunks indexed per search in low-memory mode; files past the cap are not
+/// read at all.
+const LOW_MEMORY_MAX_CHUNKS: usize = 2_000;
+/// With the recency boost, embeddings or a diversity cap on, this many times
+/// `top_k` text matches are re-ranked so a newer or semantically closer
+/// document just outside the cut can still move up, or fill a slot a capped
+/// file gave up.
+const RECENCY_CANDIDATE_FACTOR: usize = 4;
+
+/// Chunks embedded at most per search; see `semantic_ranking`.
+const EMBEDDINGS_PER_SEARCH: usize = 256;
+/// Rank offset of reciprocal rank fusion; 60 is the usual choice and keeps
+/// a single list's top hit from dominating.
+const RRF_K: f32 = 60.0;
+const SECONDS_PER_DAY: f64 = 86_400.0;
+
+/// Attached to results whose source is older than `stale_after_days`.
+pub const OUTDATED_WARNING: &str = "可能已失效，请核对最新版本";
 
-/// Process-level singleton for Jieba tokenizer.
-/// Loading the built-in dictionary is expensive (~350K entries decompressed at runtime).
-/// Sharing a single instance across all RetrievalEngine instances avoids repeated init.
-static JIEBA: Lazy<Arc<Jieba>> = Lazy::new(|| Arc::new(Jieba::new()));
+/// Retrieval tuning. `low_memory` trades recall on very large KBs for a much
+/// smaller peak footprint on constrained devices: a single-threaded minimum
+/// size index writer, line-by-line file reading and a cap on indexed chunks.
+/// Otherwise files are chunked and tokenized on up to `index_threads`
+/// threads.
+///
+/// Documents are dated by their frontmatter `effective_date` (`YYYY-MM-DD`),
+/// the date of their current text, or by file modification time without
+/// one. A document's text score is
+/// multiplied by `1 + weight * 0.5^(age / half_life)`, so newer regulations
+/// outrank superseded ones on similar matches.
+///
+/// With `embedding` set, search is hybrid: the text ranking and a ranking by
+/// embedding similarity are merged with reciprocal rank fusion, so chunks
+/// that say the same thing in other words are found too.
+///
+/// Indexed text and queries are segmented the same way, without the
+/// stopwords of `stopwords::DEFAULT_STOPWORDS` and `extra_stopwords`.
+///
+/// After scoring, one file (and, with `max_results_per_title`, one title)
+/// fills at most that many of the `top_k` slots; the rest go to the next
+/// best other documents, so one long statute cannot crowd out the others.
+///
+/// Scenarios of a `remote_sources` entry are searched on that server
+/// instead of the KB on the device; see `remote`.
+///
+/// Searched for a case, documents not in force during it are downranked
+/// or, with `exclude_inapplicable`, dropped; see `temporal`.
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct RetrievalConfig {
+    pub low_memory: bool,
+    /// Age at which the recency boost halves; `0` disables the boost.
+    #[uniffi(default = 730)]
+    pub recency_half_life_days: u32,
+    /// Boost of a document dated today, in percent of its text score.
+    #[uniffi(default = 50)]
+    pub recency_weight_percent: u32,
+    /// Results older than this carry `OUTDATED_WARNING`; `0` disables it.
+    #[uniffi(default = 1825)]
+    pub stale_after_days: u32,
+    /// `None` keeps search purely lexical.
+    #[uniffi(default = None)]
+    pub embedding: Option<EmbeddingConfig>,
+    /// Drop the bundled legal-Chinese stopwords.
+    #[uniffi(default = true)]
+    pub default_stopwords: bool,
+    /// Stopwords dropped in addition to the bundled ones.
+    #[uniffi(default = [])]
+    pub extra_stopwords: Vec<String>,
+    /// Bundled stopwords to index after all, e.g. `根据` for a KB searched
+    /// by legal basis.
+    #[uniffi(default = [])]
+    pub kept_words: Vec<String>,
+    /// Keep numbers and article references such as `第三十八条` as one
+    /// token, in Arabic digits, instead of segmenting them.
+    #[uniffi(default = true)]
+    pub keep_legal_numbers: bool,
+    /// Most results from one file while other files still match; `0` (the
+    /// default) lifts the cap.
+    #[uniffi(default = 0)]
+    pub max_results_per_file: u32,
+    /// Most results from documents sharing a title, e.g. one statute split
+    /// over several files; `0` (the default) lifts the cap.
+    #[uniffi(default = 0)]
+    pub max_results_per_title: u32,
+    /// Threads reading, chunking and tokenizing the KB for an index build;
+    /// `0` (the default) takes one per core. Ignored with `low_memory`,
+    /// which indexes on the calling thread.
+    #[uniffi(default = 0)]
+    pub index_threads: u32,
+    /// Servers that answer the searches of some scenarios; the first one
+    /// listing a scenario serves it.
+    #[uniffi(default = [])]
+    pub remote_sources: Vec<RemoteKbConfig>,
+    /// Drop results of documents not in force during the case instead of
+    /// downranking them.
+    #[uniffi(default = false)]
+    pub exclude_inapplicable: bool,
+}
+
+impl Default for RetrievalConfig {
+    fn default() -> Self {
+        Self {
+            low_memory: false,
+            recency_half_life_days: 730,
+            recency_weight_percent: 50,
+            stale_after_days: 1825,
+            embedding: None,
+            default_stopwords: true,
+            extra_stopwords: Vec::new(),
+            kept_words: Vec::new(),
+            keep_legal_numbers: true,
+            max_results_per_file: 0,
+            max_results_per_title: 0,
+            index_threads: 0,
+            remote_sources: Vec::new(),
+            exclude_inapplicable: false,
+        }
+    }
+}
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, uniffi::Record)]
 pub struct SearchResult {
@@ -29,6 +170,42 @@ pub struct SearchResult {
     pub line_start: u32,
     pub line_end: u32,
     pub score: f32,
+    /// Estimated model tokens of `snippet`.
+    #[serde(default)]
+    pub token_count: u32,
+    /// `YYYY-MM-DD` the source took effect, or was last modified.
+    #[serde(default)]
+    pub effective_date: Option<String>,
+    /// `OUTDATED_WARNING` when the source is past `stale_after_days`.
+    #[serde(default)]
+    pub outdated_warning: Option<String>,
+    /// `relative/path.md#L<start>-L<end>`; see `RetrievalEngine::knowledge_chunk`.
+    #[serde(default)]
+    pub chunk_id: String,
+    /// Slug of the heading the chunk sits under, for scrolling a rendered
+    /// document to it.
+    #[serde(default)]
+    pub anchor: Option<String>,
+    /// `RemoteKbConfig::name` of the server the chunk came from; `None`
+    /// for the KB on the device.
+    #[serde(default)]
+    pub remote_source: Option<String>,
+    /// Served from the remote source's cache because the server could not
+    /// be reached.
+    #[serde(default)]
+    pub from_cache: bool,
+    /// From the statutes bundled with the app, not a KB the user
+    /// installed; see `builtin`.
+    #[serde(default)]
+    pub builtin: bool,
+    /// `YYYY-MM-DD` the source was repealed, from its frontmatter
+    /// `repeal_date`.
+    #[serde(default)]
+    pub repeal_date: Option<String>,
+    /// Why the source did not govern the case searched for; see
+    /// `temporal`.
+    #[serde(default)]
+    pub inapplicable_warning: Option<String>,
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, uniffi::Record)]
@@ -38,6 +215,31 @@ pub struct KnowledgeInfo {
     pub updated_at: i64,
 }
 
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, uniffi::Record)]
+pub struct KnowledgeFile {
+    /// Path relative to the KB root, always `/`-separated.
+    pub relative_path: String,
+    pub title: String,
+    pub size_bytes: u64,
+    pub modified_at: i64,
+}
+
+/// A chunk with its neighbouring lines, for a citation viewer.
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct KnowledgeChunk {
+    pub chunk_id: String,
+    /// Path relative to the KB root, always `/`-separated.
+    pub relative_path: String,
+    pub title: String,
+    pub anchor: Option<String>,
+    pub line_start: u32,
+    pub line_end: u32,
+    pub text: String,
+    /// Up to `CHUNK_CONTEXT_LINES` lines before and after `text`.
+    pub context_before: String,
+    pub context_after: String,
+}
+
 #[derive(Debug, Clone)]
 struct KbChunk {
     file_path: String,
@@ -45,145 +247,517 @@ struct KbChunk {
     snippet: String,
     line_start: u32,
     line_end: u32,
+    anchor: Option<String>,
+    /// Unix seconds; see `effective_at`.
+    effective_at: i64,
+    /// Frontmatter `first_effective_date`, else `effective_date`, without
+    /// the modification time fallback; see `temporal`.
+    valid_from: Option<NaiveDate>,
+    /// Frontmatter `repeal_date`.
+    repealed_on: Option<NaiveDate>,
 }
 
 #[derive(Clone)]
 pub struct RetrievalEngine {
     kb_root: PathBuf,
-    jieba: Arc<Jieba>,
+    config: RetrievalConfig,
+    tokens: Arc<TokenFilter>,
+    integrity: Arc<IntegrityGuard>,
+    pool: Arc<IndexPool>,
+    embedder: Option<Arc<dyn EmbeddingProvider>>,
+    embedding_cache: Arc<Mutex<EmbeddingCache>>,
+    remotes: Arc<Vec<RemoteKb>>,
+    /// "Today" for recency boosts and effective dates.
+    clock: Arc<dyn Clock>,
 }
 
 impl RetrievalEngine {
-    pub fn new<P: AsRef<Path>>(kb_root: P) -> Self {
+    /// An unusable `config.embedding` is logged and search stays lexical;
+    /// a remote source without a usable client serves its cache.
+    pub fn new<P: AsRef<Path>>(kb_root: P, config: RetrievalConfig) -> Self {
+        let embedder =
+            config.embedding.as_ref().and_then(|embedding| {
+                match embedding::provider_for(embedding) {
+                    Ok(provider) => Some(provider),
+                    Err(err) => {
+                        tracing::warn!("embeddings disabled: {err}");
+                        None
+                    }
+                }
+            });
+        let remotes = Arc::new(
+            config
+                .remote_sources
+                .iter()
+                .cloned()
+                .map(RemoteKb::new)
+                .collect(),
+        );
         Self {
             kb_root: kb_root.as_ref().to_path_buf(),
-            jieba: JIEBA.clone(),
+            tokens: Arc::new(TokenFilter::new(&config)),
+            pool: Arc::new(IndexPool::new(if config.low_memory {
+                1
+            } else {
+                config.index_threads
+            })),
+            config,
+            integrity: Arc::new(IntegrityGuard::load(kb_root.as_ref())),
+            embedder,
+            embedding_cache: Arc::new(Mutex::new(EmbeddingCache::default())),
+            remotes,
+            clock: Arc::new(SystemClock),
         }
     }
 
+    pub fn config(&self) -> &RetrievalConfig {
+        &self.config
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    #[cfg(test)]
+    fn with_embedding_provider(mut self, provider: Arc<dyn EmbeddingProvider>) -> Self {
+        self.embedder = Some(provider);
+        self.embedding_cache = Arc::new(Mutex::new(EmbeddingCache::default()));
+        self
+    }
+
+    #[cfg(test)]
+    fn with_remote_client(mut self, client: Arc<dyn remote::RemoteKbClient>) -> Self {
+        self.remotes = Arc::new(
+            self.config
+                .remote_sources
+                .iter()
+                .cloned()
+                .map(|config| RemoteKb::new(config).with_client(client.clone()))
+                .collect(),
+        );
+        self
+    }
+
     pub fn search(
         &self,
         query: &str,
         scenario: &str,
         top_k: usize,
+    ) -> CoreResult<Vec<SearchResult>> {
+        self.search_for_case(query, scenario, top_k, None)
+    }
+
+    /// `search` for a case spanning `case`: results of documents not in
+    /// force during it carry `inapplicable_warning` and rank lower, or are
+    /// dropped with `exclude_inapplicable`.
+    pub fn search_for_case(
+        &self,
+        query: &str,
+        scenario: &str,
+        top_k: usize,
+        case: Option<&CaseDates>,
     ) -> CoreResult<Vec<SearchResult>> {
         let query = query.trim();
         if query.is_empty() {
             return Ok(Vec::new());
         }
+        let remote = self.remotes.iter().find(|remote| remote.serves(scenario));
+        let rerank = self.config.recency_half_life_days > 0
+            || case.is_some()
+            || (self.embedder.is_some() && remote.is_none())
+            || self.config.max_results_per_file > 0
+            || self.config.max_results_per_title > 0;
+        let candidates = if rerank {
+            top_k.saturating_mul(RECENCY_CANDIDATE_FACTOR)
+        } else {
+            top_k
+        };
+        if let Some(remote) = remote {
+            let mut results = self.search_remote(remote, query, scenario, candidates, case)?;
+            results.sort_by(|a, b| b.score.total_cmp(&a.score));
+            return Ok(diversify(
+                self.drop_inapplicable(results),
+                top_k,
+                self.config.max_results_per_file as usize,
+                self.config.max_results_per_title as usize,
+            ));
+        }
 
         let chunks = self.collect_chunks(scenario)?;
         if chunks.is_empty() {
             return Ok(Vec::new());
         }
 
-        let mut schema_builder = SchemaBuilder::default();
-        let text_indexing = TextFieldIndexing::default()
-            .set_tokenizer("default")
-            .set_index_option(IndexRecordOption::WithFreqsAndPositions);
-        let text_options = TextOptions::default()
-            .set_indexing_options(text_indexing)
-            .set_stored();
-
-        let file_path_f = schema_builder.add_text_field("file_path", STORED);
-        let title_f = schema_builder.add_text_field("title", STORED);
-        let snippet_f = schema_builder.add_text_field("snippet", STORED);
-        let content_f = schema_builder.add_text_field("content", text_options);
-
-        let number_options = NumericOptions::default().set_stored().set_fast();
-        let line_start_f = schema_builder.add_u64_field("line_start", number_options.clone());
-        let line_end_f = schema_builder.add_u64_field("line_end", number_options);
-
-        let schema = schema_builder.build();
-        let index = Index::create_in_ram(schema);
-        let mut writer = index
-            .writer(50_000_000)
-            .map_err(|e| CoreError::Unknown(format!("index writer failed: {e}")))?;
-
-        for chunk in &chunks {
-            let tokenized = self.tokenize_zh(&chunk.snippet);
-            writer
-                .add_document(doc!(
-                    file_path_f => chunk.file_path.clone(),
-                    title_f => chunk.title.clone(),
-                    snippet_f => chunk.snippet.clone(),
-                    content_f => tokenized,
-                    line_start_f => u64::from(chunk.line_start),
-                    line_end_f => u64::from(chunk.line_end),
+        let docs = chunks
+            .iter()
+            .map(|chunk| LexicalDoc {
+                id: self.chunk_id(chunk),
+                text: &chunk.snippet,
+            })
+            .collect::<Vec<_>>();
+        let by_id = docs
+            .iter()
+            .zip(&chunks)
+            .map(|(doc, chunk)| (doc.id.clone(), chunk))
+            .collect::<HashMap<_, _>>();
+
+        let ranked = lexical::rank(
+            &docs,
+            query,
+            candidates,
+            self.config.low_memory,
+            &self.tokens,
+            &self.pool,
+        )?;
+        let now = self.clock.timestamp();
+
+        let mut results = ranked
+            .into_iter()
+            .filter_map(|(chunk_id, score)| {
+                let chunk = by_id.get(&chunk_id)?;
+                Some(self.search_result(chunk, score, now, case))
+            })
+            .collect::<Vec<_>>();
+        results.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+        if let Some(embedder) = &self.embedder {
+            match self.semantic_ranking(embedder.as_ref(), query, &chunks, &results, candidates) {
+                Ok(semantic) => {
+                    results = fuse_rankings(&results, &semantic)
+                        .into_iter()
+                        .filter_map(|(chunk_id, fused)| {
+                            let chunk = by_id.get(&chunk_id)?;
+                            Some(self.search_result(chunk, fused, now, case))
+                        })
+                        .collect();
+                    results.sort_by(|a, b| b.score.total_cmp(&a.score));
+                }
+                // Lexical results still answer the query.
+                Err(err) => tracing::warn!("semantic ranking failed: {err}"),
+            }
+        }
+
+        Ok(diversify(
+            self.drop_inapplicable(results),
+            top_k,
+            self.config.max_results_per_file as usize,
+            self.config.max_results_per_title as usize,
+        ))
+    }
+
+    /// Up to `top_k` results of `remote`'s server, or, while it cannot be
+    /// reached, of its cache ranked like the local KB, scored like local
+    /// results but not yet sorted or diversified. Fails when neither has
+    /// anything.
+    fn search_remote(
+        &self,
+        remote: &RemoteKb,
+        query: &str,
+        scenario: &str,
+        top_k: usize,
+        case: Option<&CaseDates>,
+    ) -> CoreResult<Vec<SearchResult>> {
+        let now = self.clock.timestamp();
+        let err = match remote.fetch(query, scenario, top_k) {
+            Ok(chunks) => {
+                return Ok(chunks
+                    .iter()
+                    .map(|chunk| self.remote_result(remote, chunk, chunk.score, false, now, case))
+                    .collect())
+            }
+            Err(err) => err,
+        };
+        let cached = remote.cached(scenario);
+        if cached.is_empty() {
+            return Err(err);
+        }
+        tracing::warn!(
+            "remote kb {} unreachable, searching its cache: {err}",
+            remote.config.name
+        );
+        let docs = cached
+            .iter()
+            .map(|chunk| LexicalDoc {
+                id: chunk.chunk_id(),
+                text: &chunk.snippet,
+            })
+            .collect::<Vec<_>>();
+        let ranked = lexical::rank(
+            &docs,
+            query,
+            top_k,
+            self.config.low_memory,
+            &self.tokens,
+            &self.pool,
+        )?;
+        Ok(ranked
+            .into_iter()
+            .filter_map(|(chunk_id, score)| {
+                let chunk = cached.iter().find(|chunk| chunk.chunk_id() == chunk_id)?;
+                Some(self.remote_result(remote, chunk, score, true, now, case))
+            })
+            .collect())
+    }
+
+    /// The result for a remote `chunk`, scored as `search_result` scores a
+    /// local one: boosted by recency as of `now` and penalised when not in
+    /// force during `case`. Snippets longer than a local chunk are cut to
+    /// its length, so one remote document cannot take a prompt's whole
+    /// budget.
+    fn remote_result(
+        &self,
+        remote: &RemoteKb,
+        chunk: &RemoteChunk,
+        score: f32,
+        from_cache: bool,
+        now: i64,
+        case: Option<&CaseDates>,
+    ) -> SearchResult {
+        let stale_after_days = self.config.stale_after_days;
+        let effective = chunk
+            .effective_date
+            .as_deref()
+            .and_then(temporal::parse_date);
+        let inapplicable = case.and_then(|case| {
+            case.inapplicable(
+                chunk
+                    .first_effective_date
+                    .as_deref()
+                    .and_then(temporal::parse_date)
+                    .or(effective),
+                chunk.repeal_date.as_deref().and_then(temporal::parse_date),
+            )
+        });
+        // Undated chunks get no boost, as if infinitely old, but are never
+        // flagged outdated.
+        let age_days = effective.map(|date| {
+            let effective_at = date
+                .and_hms_opt(0, 0, 0)
+                .map_or(now, |at| at.and_utc().timestamp());
+            (now - effective_at).max(0) as f64 / SECONDS_PER_DAY
+        });
+        let outdated =
+            age_days.is_some_and(|age| stale_after_days > 0 && age > f64::from(stale_after_days));
+        let boost = age_days.map_or(1.0, |age| self.recency_boost(age));
+        let lines = chunk.snippet.lines().collect::<Vec<_>>();
+        let snippet = lines[..lines.len().min(LINES_PER_CHUNK)].join("\n");
+        let line_end = if lines.len() > LINES_PER_CHUNK && chunk.line_start > 0 {
+            chunk.line_start + LINES_PER_CHUNK as u32 - 1
+        } else {
+            chunk.line_end
+        };
+        SearchResult {
+            file_path: chunk.file_path.clone(),
+            title: chunk.title.clone(),
+            token_count: estimate_tokens(&snippet),
+            snippet,
+            line_start: chunk.line_start,
+            line_end,
+            score: applicability_score(score * boost, &inapplicable),
+            effective_date: chunk.effective_date.clone(),
+            outdated_warning: outdated.then(|| OUTDATED_WARNING.to_owned()),
+            chunk_id: chunk.chunk_id(),
+            anchor: chunk.anchor.clone(),
+            remote_source: Some(remote.config.name.clone()),
+            from_cache,
+            builtin: false,
+            repeal_date: chunk.repeal_date.clone(),
+            inapplicable_warning: inapplicable,
+        }
+    }
+
+    /// Chunk ids of the `limit` chunks closest to `query`, closest first.
+    /// Chunk vectors are cached by text, so only new or edited chunks are
+    /// embedded again, `EMBEDDINGS_PER_SEARCH` at a time with the `lexical`
+    /// hits first: a large KB is covered over several searches instead of
+    /// stalling the first one, and until then the rest rank lexically only.
+    fn semantic_ranking(
+        &self,
+        embedder: &dyn EmbeddingProvider,
+        query: &str,
+        chunks: &[KbChunk],
+        lexical: &[SearchResult],
+        limit: usize,
+    ) -> CoreResult<Vec<String>> {
+        let lexical = lexical
+            .iter()
+            .map(|result| result.chunk_id.as_str())
+            .collect::<HashSet<_>>();
+        let mut missing = {
+            let cache = self
+                .embedding_cache
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            chunks
+                .iter()
+                .filter(|chunk| !cache.contains(&chunk.snippet))
+                .collect::<Vec<_>>()
+        };
+        missing.sort_by_key(|chunk| !lexical.contains(self.chunk_id(chunk).as_str()));
+        let mut seen = HashSet::new();
+        let missing = missing
+            .into_iter()
+            .map(|chunk| chunk.snippet.clone())
+            .filter(|snippet| seen.insert(snippet.clone()))
+            .take(EMBEDDINGS_PER_SEARCH)
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            let vectors = embedder.embed(&missing)?;
+            if vectors.len() != missing.len() {
+                return Err(CoreError::Model(
+                    "embedding provider returned a wrong number of vectors".to_owned(),
+                ));
+            }
+            let mut cache = self
+                .embedding_cache
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            for (snippet, vector) in missing.iter().zip(vectors) {
+                cache.insert(snippet, vector);
+            }
+        }
+
+        let query_vector = embedder
+            .embed(&[query.to_owned()])?
+            .pop()
+            .ok_or_else(|| CoreError::Model("empty query embedding".to_owned()))?;
+        let cache = self
+            .embedding_cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut scored = chunks
+            .iter()
+            .filter_map(|chunk| {
+                let vector = cache.get(&chunk.snippet)?;
+                Some((
+                    embedding::cosine_similarity(&query_vector, vector),
+                    self.chunk_id(chunk),
                 ))
-                .map_err(|e| CoreError::Unknown(format!("index add document failed: {e}")))?;
-        }
-
-        writer
-            .commit()
-            .map_err(|e| CoreError::Unknown(format!("index commit failed: {e}")))?;
-
-        let reader = index
-            .reader_builder()
-            .reload_policy(ReloadPolicy::Manual)
-            .try_into()
-            .map_err(|e| CoreError::Unknown(format!("index reader failed: {e}")))?;
-        reader
-            .reload()
-            .map_err(|e| CoreError::Unknown(format!("index reload failed: {e}")))?;
-
-        let searcher = reader.searcher();
-        let query_parser = QueryParser::for_index(&index, vec![content_f]);
-        let parsed_query = query_parser
-            .parse_query(&self.tokenize_zh(query))
-            .map_err(|e| CoreError::Unknown(format!("query parse failed: {e}")))?;
-
-        let top_docs = searcher
-            .search(&parsed_query, &TopDocs::with_limit(top_k))
-            .map_err(|e| CoreError::Unknown(format!("search failed: {e}")))?;
-
-        let mut results = Vec::with_capacity(top_docs.len());
-        for (score, addr) in top_docs {
-            let retrieved = searcher
-                .doc::<tantivy::schema::TantivyDocument>(addr)
-                .map_err(|e| CoreError::Unknown(format!("doc read failed: {e}")))?;
-
-            let file_path = retrieved
-                .get_first(file_path_f)
-                .and_then(|v| v.as_str())
-                .unwrap_or_default()
-                .to_owned();
-            let title = retrieved
-                .get_first(title_f)
-                .and_then(|v| v.as_str())
-                .unwrap_or_default()
-                .to_owned();
-            let snippet = retrieved
-                .get_first(snippet_f)
-                .and_then(|v| v.as_str())
-                .unwrap_or_default()
-                .to_owned();
-            let line_start = retrieved
-                .get_first(line_start_f)
-                .and_then(|v| v.as_u64())
-                .unwrap_or_default() as u32;
-            let line_end = retrieved
-                .get_first(line_end_f)
-                .and_then(|v| v.as_u64())
-                .unwrap_or_default() as u32;
-
-            results.push(SearchResult {
-                file_path,
-                title,
-                snippet,
-                line_start,
-                line_end,
-                score,
-            });
+            })
+            .collect::<Vec<_>>();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        Ok(scored
+            .into_iter()
+            .take(limit)
+            .map(|(_, chunk_id)| chunk_id)
+            .collect())
+    }
+
+    /// `score` is the chunk's text or fused score before the recency boost
+    /// and the penalty of a document not in force during `case`.
+    fn search_result(
+        &self,
+        chunk: &KbChunk,
+        score: f32,
+        now: i64,
+        case: Option<&CaseDates>,
+    ) -> SearchResult {
+        let age_days = (now - chunk.effective_at).max(0) as f64 / SECONDS_PER_DAY;
+        let builtin = builtin::is_builtin(&self.relative_path(Path::new(&chunk.file_path)));
+        // Bundled excerpts ship with the app and are updated with it; their
+        // date is the law's, not a sign the copy is out of date.
+        let stale_after_days = if builtin {
+            0
+        } else {
+            self.config.stale_after_days
+        };
+        let inapplicable =
+            case.and_then(|case| case.inapplicable(chunk.valid_from, chunk.repealed_on));
+        SearchResult {
+            file_path: chunk.file_path.clone(),
+            title: chunk.title.clone(),
+            snippet: chunk.snippet.clone(),
+            line_start: chunk.line_start,
+            line_end: chunk.line_end,
+            score: applicability_score(score * self.recency_boost(age_days), &inapplicable),
+            token_count: estimate_tokens(&chunk.snippet),
+            effective_date: DateTime::from_timestamp(chunk.effective_at, 0)
+                .map(|at| at.format("%Y-%m-%d").to_string()),
+            outdated_warning: (stale_after_days > 0 && age_days > f64::from(stale_after_days))
+                .then(|| OUTDATED_WARNING.to_owned()),
+            chunk_id: self.chunk_id(chunk),
+            anchor: chunk.anchor.clone(),
+            remote_source: None,
+            from_cache: false,
+            builtin,
+            repeal_date: chunk
+                .repealed_on
+                .map(|date| date.format("%Y-%m-%d").to_string()),
+            inapplicable_warning: inapplicable,
         }
+    }
 
-        Ok(results)
+    fn drop_inapplicable(&self, mut results: Vec<SearchResult>) -> Vec<SearchResult> {
+        if self.config.exclude_inapplicable {
+            results.retain(|result| result.inapplicable_warning.is_none());
+        }
+        results
+    }
+
+    /// Fails with `CoreError::Safety` when the KB has a manifest and the file
+    /// does not match it.
+    pub fn kb_root(&self) -> &Path {
+        &self.kb_root
     }
 
     pub fn read_file(&self, file_path: &str) -> CoreResult<String> {
         let path = Path::new(file_path);
-        fs::read_to_string(path)
-            .map_err(|e| CoreError::Storage(format!("read kb file failed: {e}")))
+        let relative = self.relative_path(path);
+        self.integrity.check(&relative, path)?;
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(content),
+            // The bundled corpus, searched in memory.
+            Err(_) if !path.exists() && builtin::is_builtin(&relative) => {
+                Ok(builtin::content(&relative).unwrap_or_default().to_owned())
+            }
+            Err(e) => Err(CoreError::Storage(format!("read kb file failed: {e}"))),
+        }
+    }
+
+    /// Extract the bundled statutes into the KB when it has no files yet,
+    /// so a fresh install has something to search. Returns how many files
+    /// were written.
+    pub fn bootstrap_builtin_kb(&self) -> CoreResult<u32> {
+        if !self.collect_markdown_files(&self.kb_root)?.is_empty() {
+            return Ok(0);
+        }
+        builtin::extract(&self.kb_root)
+    }
+
+    /// Re-read the manifest and check every KB file against it.
+    pub fn verify_integrity(&self) -> CoreResult<KbIntegrityReport> {
+        self.integrity.reload(&self.kb_root);
+        let files = self.collect_markdown_files(&self.kb_root)?;
+        Ok(self.integrity.verify(
+            files
+                .iter()
+                .map(|file| (self.relative_path(file), file.as_path())),
+        ))
+    }
+
+    /// Record the current checksum of every KB file as trusted, e.g. when
+    /// bundling the KB. Returns the number of files listed.
+    pub fn write_manifest(&self) -> CoreResult<u32> {
+        let files = self.collect_markdown_files(&self.kb_root)?;
+        let listed = integrity::write_manifest(
+            &self.kb_root,
+            files
+                .iter()
+                .map(|file| (self.relative_path(file), file.as_path())),
+        )?;
+        self.integrity.reload(&self.kb_root);
+        Ok(listed)
+    }
+
+    /// Authoring checks over every KB file; see `validate`.
+    pub fn validate(&self) -> CoreResult<KbValidationReport> {
+        let files = self
+            .collect_markdown_files(&self.kb_root)?
+            .into_iter()
+            .map(|file| (self.relative_path(&file), file))
+            .collect::<Vec<_>>();
+        Ok(validate::validate(&self.kb_root, &files))
     }
 
     pub fn knowledge_info(&self) -> CoreResult<KnowledgeInfo> {
@@ -207,13 +781,107 @@ impl RetrievalEngine {
         })
     }
 
-    fn tokenize_zh(&self, input: &str) -> String {
-        self.jieba
-            .cut(input, false)
-            .into_iter()
-            .filter(|token| !token.trim().is_empty())
+    /// List markdown files under `scenario`/`subdir` (both optional), sorted by
+    /// relative path. Neither component may escape the KB root.
+    pub fn list_files(
+        &self,
+        scenario: Option<&str>,
+        subdir: Option<&str>,
+    ) -> CoreResult<Vec<KnowledgeFile>> {
+        let mut root = self.kb_root.clone();
+        for component in [scenario, subdir].into_iter().flatten() {
+            root = root.join(sandboxed_relative(component)?);
+        }
+
+        let files = self.collect_markdown_files(&root)?;
+        let mut listed = Vec::with_capacity(files.len());
+        for file in files {
+            let meta = fs::metadata(&file)
+                .map_err(|e| CoreError::Storage(format!("stat kb file failed: {e}")))?;
+            let modified_at = meta
+                .modified()
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map(|duration| duration.as_secs() as i64)
+                .unwrap_or_default();
+            let content = fs::read_to_string(&file).unwrap_or_default();
+
+            listed.push(KnowledgeFile {
+                relative_path: self.relative_path(&file),
+                title: extract_title(&file, &content),
+                size_bytes: meta.len(),
+                modified_at,
+            });
+        }
+
+        Ok(listed)
+    }
+
+    /// The chunk named by a `SearchResult::chunk_id`, with a few lines of
+    /// context on either side. Ids stay valid until the file is edited.
+    pub fn knowledge_chunk(&self, chunk_id: &str) -> CoreResult<KnowledgeChunk> {
+        let not_found = || CoreError::NotFound(format!("knowledge chunk {chunk_id}"));
+        let (relative, range) = chunk_id.rsplit_once("#L").ok_or_else(not_found)?;
+        let (start, end) = range.split_once("-L").ok_or_else(not_found)?;
+        let line_start = start.parse::<usize>().map_err(|_| not_found())?;
+        let line_end = end.parse::<usize>().map_err(|_| not_found())?;
+
+        let file = self.kb_root.join(sandboxed_relative(relative)?);
+        self.integrity.check(&self.relative_path(&file), &file)?;
+        let content = fs::read_to_string(&file)
+            .ok()
+            .or_else(|| builtin::content(relative).map(ToOwned::to_owned))
+            .ok_or_else(not_found)?;
+        let lines = content.lines().collect::<Vec<_>>();
+        if line_start == 0 || line_start > line_end || line_end > lines.len() {
+            return Err(not_found());
+        }
+
+        let (first, last) = (line_start - 1, line_end);
+        let heading = lines[..first]
+            .iter()
+            .rev()
+            .find(|line| line.trim_start().starts_with('#'))
+            .copied();
+        Ok(KnowledgeChunk {
+            chunk_id: chunk_id.to_owned(),
+            relative_path: self.relative_path(&file),
+            title: extract_title(&file, &content),
+            anchor: chunk_anchor(heading, &lines[first..last]),
+            line_start: line_start as u32,
+            line_end: line_end as u32,
+            text: lines[first..last].join("\n"),
+            context_before: lines[first.saturating_sub(CHUNK_CONTEXT_LINES)..first].join("\n"),
+            context_after: lines[last..(last + CHUNK_CONTEXT_LINES).min(lines.len())].join("\n"),
+        })
+    }
+
+    /// `file` relative to the KB root, `/`-separated on every platform.
+    fn relative_path(&self, file: &Path) -> String {
+        file.strip_prefix(&self.kb_root)
+            .unwrap_or(file)
+            .components()
+            .map(|part| part.as_os_str().to_string_lossy().to_string())
             .collect::<Vec<_>>()
-            .join(" ")
+            .join("/")
+    }
+
+    fn chunk_id(&self, chunk: &KbChunk) -> String {
+        format!(
+            "{}#L{}-L{}",
+            self.relative_path(Path::new(&chunk.file_path)),
+            chunk.line_start,
+            chunk.line_end
+        )
+    }
+
+    fn recency_boost(&self, age_days: f64) -> f32 {
+        let half_life = self.config.recency_half_life_days;
+        if half_life == 0 {
+            return 1.0;
+        }
+        let weight = f64::from(self.config.recency_weight_percent) / 100.0;
+        (1.0 + weight * 0.5_f64.powf(age_days / f64::from(half_life))) as f32
     }
 
     fn collect_chunks(&self, scenario: &str) -> CoreResult<Vec<KbChunk>> {
@@ -225,13 +893,69 @@ impl RetrievalEngine {
         };
 
         let files = self.collect_markdown_files(&target_root)?;
-        let mut chunks = Vec::new();
+        if files.is_empty() && self.collect_markdown_files(&self.kb_root)?.is_empty() {
+            return Ok(self.builtin_chunks(scenario));
+        }
+        if self.config.low_memory {
+            return self.stream_files(files);
+        }
 
-        for file in files {
+        let per_file = self.pool.map(files, |file| -> CoreResult<Vec<KbChunk>> {
+            // Unverified files are never indexed, so nothing cites them.
+            if let Err(err) = self.integrity.check(&self.relative_path(&file), &file) {
+                tracing::warn!("skipping kb file: {err}");
+                return Ok(Vec::new());
+            }
             let content = fs::read_to_string(&file)
                 .map_err(|e| CoreError::Storage(format!("read kb file failed: {e}")))?;
             let title = extract_title(&file, &content);
-            chunks.extend(chunk_markdown(&file, &title, &content, 20));
+            let mut chunks = chunk_markdown(&file, &title, &content, LINES_PER_CHUNK);
+            FrontmatterDates::parse(content.lines().map(|line| Ok(line.to_owned())))
+                .apply(&mut chunks, &file);
+            Ok(chunks)
+        });
+
+        let mut chunks = Vec::new();
+        for file_chunks in per_file {
+            chunks.extend(file_chunks?);
+        }
+        Ok(chunks)
+    }
+
+    /// Chunks of the bundled statutes of `scenario`, for a KB without any
+    /// files.
+    fn builtin_chunks(&self, scenario: &str) -> Vec<KbChunk> {
+        let prefix = format!("{scenario}/");
+        builtin::documents()
+            .into_iter()
+            .filter(|(relative, _)| relative.starts_with(&prefix))
+            .flat_map(|(relative, content)| {
+                let file = self.kb_root.join(relative);
+                let title = extract_title(&file, content);
+                let mut chunks = chunk_markdown(&file, &title, content, LINES_PER_CHUNK);
+                FrontmatterDates::parse(content.lines().map(|line| Ok(line.to_owned())))
+                    .apply(&mut chunks, &file);
+                chunks
+            })
+            .collect()
+    }
+
+    /// Low-memory `collect_chunks`: one file at a time, read line by line,
+    /// until `LOW_MEMORY_MAX_CHUNKS`.
+    fn stream_files(&self, files: Vec<PathBuf>) -> CoreResult<Vec<KbChunk>> {
+        let mut chunks = Vec::new();
+        for file in files {
+            if let Err(err) = self.integrity.check(&self.relative_path(&file), &file) {
+                tracing::warn!("skipping kb file: {err}");
+                continue;
+            }
+            let remaining = LOW_MEMORY_MAX_CHUNKS.saturating_sub(chunks.len());
+            if remaining == 0 {
+                break;
+            }
+            let first = chunks.len();
+            chunks.extend(stream_chunks(&file, LINES_PER_CHUNK, remaining)?);
+            FrontmatterDates::of_file(&file).apply(&mut chunks[first..], &file);
         }
 
         Ok(chunks)
@@ -262,6 +986,214 @@ impl RetrievalEngine {
     }
 }
 
+fn applicability_score(score: f32, inapplicable: &Option<String>) -> f32 {
+    if inapplicable.is_some() {
+        score * temporal::INAPPLICABLE_PENALTY
+    } else {
+        score
+    }
+}
+
+/// Keep results, best first, while their snippets fit in `budget` tokens.
+/// A result that does not fit is skipped so a smaller later one can still
+/// be used.
+pub fn trim_to_token_budget(results: Vec<SearchResult>, budget: u32) -> Vec<SearchResult> {
+    let mut used = 0u32;
+    results
+        .into_iter()
+        .filter(|result| {
+            let tokens = if result.token_count > 0 {
+                result.token_count
+            } else {
+                estimate_tokens(&result.snippet)
+            };
+            if used + tokens > budget {
+                return false;
+            }
+            used += tokens;
+            true
+        })
+        .collect()
+}
+
+/// Most results one file may contribute to a merged multi-query result
+/// while other files still have some to offer.
+const MAX_MERGED_PER_FILE: usize = 2;
+
+/// Merge the results of several queries into `top_k`: take each query's
+/// best remaining result in turn, skip chunks already taken, and hold back
+/// a file once it has `MAX_MERGED_PER_FILE` results until nothing else is
+/// left.
+pub fn merge_query_results(batches: Vec<Vec<SearchResult>>, top_k: usize) -> Vec<SearchResult> {
+    let depth = batches.iter().map(Vec::len).max().unwrap_or(0);
+    let round_robin = (0..depth)
+        .flat_map(|rank| batches.iter().filter_map(move |batch| batch.get(rank)))
+        .collect::<Vec<_>>();
+
+    let mut merged: Vec<SearchResult> = Vec::with_capacity(top_k);
+    let mut seen = HashSet::new();
+    let mut per_file = HashMap::<&str, usize>::new();
+    for capped in [true, false] {
+        for result in &round_robin {
+            if merged.len() >= top_k {
+                return merged;
+            }
+            let key = if result.chunk_id.is_empty() {
+                format!("{}#L{}", result.file_path, result.line_start)
+            } else {
+                result.chunk_id.clone()
+            };
+            let count = per_file.entry(result.file_path.as_str()).or_default();
+            if seen.contains(&key) || (capped && *count >= MAX_MERGED_PER_FILE) {
+                continue;
+            }
+            *count += 1;
+            seen.insert(key);
+            merged.push((*result).clone());
+        }
+    }
+    merged
+}
+
+/// The best `top_k` of `results` (sorted best first) with at most
+/// `per_file` from one file and `per_title` from one title; `0` lifts a
+/// cap. Capped results come back only when no other document is left to
+/// fill the slots.
+fn diversify(
+    results: Vec<SearchResult>,
+    top_k: usize,
+    per_file: usize,
+    per_title: usize,
+) -> Vec<SearchResult> {
+    if per_file == 0 && per_title == 0 {
+        let mut results = results;
+        results.truncate(top_k);
+        return results;
+    }
+    let within = |cap: usize, count: usize| cap == 0 || count < cap;
+
+    let mut picked = vec![false; results.len()];
+    let mut files = HashMap::<&str, usize>::new();
+    let mut titles = HashMap::<&str, usize>::new();
+    let mut taken = 0;
+    for (idx, result) in results.iter().enumerate() {
+        if taken >= top_k {
+            break;
+        }
+        let file = files.entry(result.file_path.as_str()).or_default();
+        let title = titles.entry(result.title.as_str()).or_default();
+        if within(per_file, *file) && within(per_title, *title) {
+            *file += 1;
+            *title += 1;
+            picked[idx] = true;
+            taken += 1;
+        }
+    }
+    // Backfill from the held-back results, best first.
+    for slot in picked.iter_mut().filter(|picked| !**picked) {
+        if taken >= top_k {
+            break;
+        }
+        *slot = true;
+        taken += 1;
+    }
+
+    let mut diverse = results
+        .into_iter()
+        .zip(picked)
+        .filter_map(|(result, picked)| picked.then_some(result))
+        .collect::<Vec<_>>();
+    diverse.sort_by(|a, b| b.score.total_cmp(&a.score));
+    diverse
+}
+
+/// Accept only plain relative paths (no root, no `..`) so callers cannot
+/// list or read outside the KB root.
+fn sandboxed_relative(raw: &str) -> CoreResult<PathBuf> {
+    let path = Path::new(raw.trim());
+    let mut clean = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => clean.push(part),
+            Component::CurDir => {}
+            _ => {
+                return Err(CoreError::InvalidState(format!(
+                    "path escapes knowledge base: {raw}"
+                )))
+            }
+        }
+    }
+    Ok(clean)
+}
+
+/// Validity window of a document, from its frontmatter block.
+#[derive(Debug, Default)]
+struct FrontmatterDates {
+    effective: Option<NaiveDate>,
+    first_effective: Option<NaiveDate>,
+    repealed: Option<NaiveDate>,
+}
+
+impl FrontmatterDates {
+    /// Only the frontmatter block of `file_path` is read.
+    fn of_file(file_path: &Path) -> Self {
+        File::open(file_path)
+            .map(|file| Self::parse(BufReader::new(file).lines()))
+            .unwrap_or_default()
+    }
+
+    fn parse(mut lines: impl Iterator<Item = std::io::Result<String>>) -> Self {
+        let mut dates = Self::default();
+        if !matches!(lines.next(), Some(Ok(line)) if line.trim() == "---") {
+            return dates;
+        }
+        for line in lines {
+            let Ok(line) = line else {
+                break;
+            };
+            let line = line.trim();
+            if line == "---" {
+                break;
+            }
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim().trim_matches(|c| c == '"' || c == '\'');
+            match key.trim() {
+                "effective_date" => dates.effective = temporal::parse_date(value),
+                "first_effective_date" => dates.first_effective = temporal::parse_date(value),
+                "repeal_date" => dates.repealed = temporal::parse_date(value),
+                _ => {}
+            }
+        }
+        dates
+    }
+
+    /// Date `chunks` of `file_path`. For the recency boost, a document
+    /// without an `effective_date` is dated by its modification time.
+    fn apply(&self, chunks: &mut [KbChunk], file_path: &Path) {
+        let effective_at = self
+            .effective
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+            .map(|at| at.and_utc().timestamp())
+            .unwrap_or_else(|| modified_at(file_path));
+        for chunk in chunks {
+            chunk.effective_at = effective_at;
+            chunk.valid_from = self.first_effective.or(self.effective);
+            chunk.repealed_on = self.repealed;
+        }
+    }
+}
+
+fn modified_at(file_path: &Path) -> i64 {
+    fs::metadata(file_path)
+        .and_then(|meta| meta.modified())
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or_default()
+}
+
 fn extract_title(file_path: &Path, content: &str) -> String {
     if let Some(title_line) = content
         .lines()
@@ -289,7 +1221,8 @@ fn chunk_markdown(
     }
 
     let mut chunks = Vec::new();
-    let mut start = 0usize;
+    let mut start = frontmatter_len(&lines);
+    let mut heading = None;
 
     while start < lines.len() {
         let end = (start + lines_per_chunk).min(lines.len());
@@ -302,22 +1235,214 @@ fn chunk_markdown(
                 snippet,
                 line_start: (start + 1) as u32,
                 line_end: end as u32,
+                anchor: chunk_anchor(heading, &lines[start..end]),
+                effective_at: 0,
+                valid_from: None,
+                repealed_on: None,
             });
         }
 
+        heading = lines[start..end]
+            .iter()
+            .rev()
+            .find(|line| line.trim_start().starts_with('#'))
+            .copied()
+            .or(heading);
         start = end;
     }
 
     chunks
 }
 
+/// Lines of the frontmatter block opening `lines`, its `---` fences
+/// included: up to the closing fence, or every line when it is missing.
+/// The block dates the file (see `FrontmatterDates`) and is no text to cite.
+fn frontmatter_len<S: AsRef<str>>(lines: &[S]) -> usize {
+    let is_fence = |line: &S| line.as_ref().trim() == "---";
+    if !lines.first().is_some_and(is_fence) {
+        return 0;
+    }
+    lines[1..]
+        .iter()
+        .position(is_fence)
+        .map_or(lines.len(), |closing| closing + 2)
+}
+
+/// Low-memory variant of `chunk_markdown`: reads the file line by line and
+/// stops after `max_chunks`. The title is the first heading seen so far, so
+/// chunks before any heading fall back to the file stem.
+fn stream_chunks(
+    file_path: &Path,
+    lines_per_chunk: usize,
+    max_chunks: usize,
+) -> CoreResult<Vec<KbChunk>> {
+    let file = File::open(file_path)
+        .map_err(|e| CoreError::Storage(format!("read kb file failed: {e}")))?;
+    let mut title: Option<String> = None;
+    let mut heading: Option<String> = None;
+    let mut chunks = Vec::new();
+    let mut buffer = Vec::with_capacity(lines_per_chunk);
+    let mut line_start = 1u32;
+    let mut line_no = 0u32;
+
+    let mut in_frontmatter = false;
+
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(|e| CoreError::Storage(format!("read kb file failed: {e}")))?;
+        line_no += 1;
+        // Skip the frontmatter as `frontmatter_len` measures it.
+        if (line_no == 1 && line.trim() == "---") || in_frontmatter {
+            in_frontmatter = line_no == 1 || line.trim() != "---";
+            line_start = line_no + 1;
+            continue;
+        }
+        if title.is_none() && line.trim_start().starts_with('#') {
+            title = Some(line.trim_start().trim_start_matches('#').trim().to_owned());
+        }
+        buffer.push(line);
+
+        if buffer.len() == lines_per_chunk {
+            chunks.extend(drain_chunk(
+                file_path,
+                &title,
+                &mut heading,
+                &mut buffer,
+                line_start,
+                line_no,
+            ));
+            line_start = line_no + 1;
+            if chunks.len() >= max_chunks {
+                return Ok(chunks);
+            }
+        }
+    }
+    chunks.extend(drain_chunk(
+        file_path,
+        &title,
+        &mut heading,
+        &mut buffer,
+        line_start,
+        line_no,
+    ));
+
+    chunks.truncate(max_chunks);
+    Ok(chunks)
+}
+
+/// `heading` is the last heading before `buffer`, and is advanced past it.
+fn drain_chunk(
+    file_path: &Path,
+    title: &Option<String>,
+    heading: &mut Option<String>,
+    buffer: &mut Vec<String>,
+    line_start: u32,
+    line_end: u32,
+) -> Option<KbChunk> {
+    let snippet = buffer.join("\n").trim().to_owned();
+    let anchor = chunk_anchor(heading.as_deref(), buffer);
+    if let Some(last) = buffer
+        .iter()
+        .rev()
+        .find(|line| line.trim_start().starts_with('#'))
+    {
+        *heading = Some(last.clone());
+    }
+    buffer.clear();
+    if snippet.is_empty() {
+        return None;
+    }
+
+    Some(KbChunk {
+        file_path: file_path.to_string_lossy().to_string(),
+        title: title
+            .clone()
+            .unwrap_or_else(|| extract_title(file_path, "")),
+        snippet,
+        line_start,
+        line_end,
+        anchor,
+        effective_at: 0,
+        valid_from: None,
+        repealed_on: None,
+    })
+}
+
+/// Reciprocal rank fusion: every ranking adds `1 / (RRF_K + rank)` to the
+/// chunks it lists. Returns chunk ids with fused scores, best first.
+fn fuse_rankings(lexical: &[SearchResult], semantic: &[String]) -> Vec<(String, f32)> {
+    let mut fused = HashMap::<&str, f32>::new();
+    let lexical = lexical.iter().map(|result| result.chunk_id.as_str());
+    for ranking in [
+        lexical.collect::<Vec<_>>(),
+        semantic.iter().map(String::as_str).collect(),
+    ] {
+        for (rank, chunk_id) in ranking.into_iter().enumerate() {
+            *fused.entry(chunk_id).or_default() += 1.0 / (RRF_K + rank as f32 + 1.0);
+        }
+    }
+    let mut fused = fused
+        .into_iter()
+        .map(|(chunk_id, score)| (chunk_id.to_owned(), score))
+        .collect::<Vec<_>>();
+    fused.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    fused
+}
+
+/// Anchor of the heading a chunk belongs to: its own first line when that is
+/// a heading, else the last heading above it, else the first one inside it.
+fn chunk_anchor<S: AsRef<str>>(preceding: Option<&str>, lines: &[S]) -> Option<String> {
+    let is_heading = |line: &str| line.trim_start().starts_with('#');
+    let first_line = lines
+        .iter()
+        .map(AsRef::as_ref)
+        .find(|line| !line.trim().is_empty());
+    let heading = match first_line {
+        Some(line) if is_heading(line) => Some(line),
+        _ => preceding.or_else(|| {
+            lines
+                .iter()
+                .map(AsRef::as_ref)
+                .find(|line| is_heading(line))
+        }),
+    }?;
+    let slug = heading_slug(heading);
+    (!slug.is_empty()).then_some(slug)
+}
+
+/// GitHub-style heading slug: lowercase, punctuation dropped, spaces to `-`.
+/// CJK characters are kept as-is.
+fn heading_slug(heading: &str) -> String {
+    heading
+        .trim_start()
+        .trim_start_matches('#')
+        .trim()
+        .to_lowercase()
+        .chars()
+        .filter_map(|ch| match ch {
+            ' ' => Some('-'),
+            '-' | '_' => Some(ch),
+            _ if ch.is_alphanumeric() => Some(ch),
+            _ => None,
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use std::fs;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+    use std::sync::Arc;
 
     use tempfile::TempDir;
 
-    use super::RetrievalEngine;
+    use super::remote::{RemoteChunk, RemoteKbClient};
+    use super::{
+        builtin, merge_query_results, trim_to_token_budget, EmbeddingProvider, RemoteKbConfig,
+        RetrievalConfig, RetrievalEngine, SearchResult, LOW_MEMORY_MAX_CHUNKS,
+    };
+    use crate::error::{CoreError, CoreResult};
+    use crate::model::estimate_tokens;
 
     fn setup_kb() -> (TempDir, RetrievalEngine) {
         let dir = TempDir::new().expect("temp dir");
@@ -338,7 +1463,7 @@ mod tests {
         )
         .expect("write rental file");
 
-        let engine = RetrievalEngine::new(dir.path());
+        let engine = RetrievalEngine::new(dir.path(), RetrievalConfig::default());
         (dir, engine)
     }
 
@@ -351,6 +1476,70 @@ mod tests {
         assert!(results[0].snippet.contains("拖欠工资"));
     }
 
+    #[test]
+    fn results_carry_chunk_ids_that_resolve_with_context() {
+        let (dir, engine) = setup_kb();
+        let mut lines = (1..=30).map(|n| format!("第{n}行")).collect::<Vec<_>>();
+        lines[0] = "# 加班".to_owned();
+        lines[14] = "## Overtime Pay: 加班费".to_owned();
+        lines[24] = "延时加班应按百分之一百五十支付加班费。".to_owned();
+        fs::write(
+            dir.path().join("labor").join("overtime.md"),
+            lines.join("\n"),
+        )
+        .expect("write overtime file");
+
+        for config in [
+            RetrievalConfig::default(),
+            RetrievalConfig {
+                low_memory: true,
+                ..RetrievalConfig::default()
+            },
+        ] {
+            let engine = RetrievalEngine::new(dir.path(), config);
+            let results = engine.search("百分之一百五十", "labor", 1).expect("search");
+            assert_eq!(results[0].chunk_id, "labor/overtime.md#L21-L30");
+            assert_eq!(results[0].anchor.as_deref(), Some("overtime-pay-加班费"));
+        }
+
+        let chunk = engine
+            .knowledge_chunk("labor/overtime.md#L21-L30")
+            .expect("chunk");
+        assert_eq!(chunk.relative_path, "labor/overtime.md");
+        assert_eq!(chunk.title, "加班");
+        assert_eq!(chunk.anchor.as_deref(), Some("overtime-pay-加班费"));
+        assert!(chunk.text.starts_with("第21行") && chunk.text.ends_with("第30行"));
+        assert_eq!(
+            chunk.context_before,
+            "第16行\n第17行\n第18行\n第19行\n第20行"
+        );
+        assert_eq!(chunk.context_after, "");
+
+        assert!(engine.knowledge_chunk("labor/overtime.md#L21-L31").is_err());
+        assert!(engine.knowledge_chunk("../secret.md#L1-L2").is_err());
+    }
+
+    #[test]
+    fn article_references_match_across_numeral_styles() {
+        let (dir, _engine) = setup_kb();
+        fs::write(
+            dir.path().join("labor").join("contract.md"),
+            "# 劳动合同法\n依照第八十二条第二款，用人单位应当支付二倍工资。",
+        )
+        .expect("write contract file");
+        let engine = RetrievalEngine::new(dir.path(), RetrievalConfig::default());
+
+        for query in ["第82条", "第八十二条", "第82条第2款"] {
+            let results = engine.search(query, "labor", 5).expect("search article");
+            assert!(
+                results
+                    .first()
+                    .is_some_and(|result| result.file_path.ends_with("contract.md")),
+                "{query} did not find the article"
+            );
+        }
+    }
+
     #[test]
     fn scenario_isolation_works() {
         let (_dir, engine) = setup_kb();
@@ -366,11 +1555,196 @@ mod tests {
     }
 
     #[test]
-    fn empty_index_returns_empty() {
+    fn list_files_returns_relative_metadata_and_blocks_escape() {
+        let (_dir, engine) = setup_kb();
+
+        let all = engine.list_files(None, None).expect("list all");
+        let paths = all
+            .iter()
+            .map(|file| file.relative_path.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(paths, vec!["labor/wage.md", "rental/deposit.md"]);
+        assert_eq!(all[0].title, "劳动仲裁流程");
+        assert!(all[0].size_bytes > 0);
+
+        let labor = engine.list_files(Some("labor"), None).expect("list labor");
+        assert_eq!(labor.len(), 1);
+
+        assert!(engine.list_files(Some("labor"), Some("../..")).is_err());
+        assert!(engine.list_files(None, Some("/etc")).is_err());
+    }
+
+    #[test]
+    fn one_file_fills_at_most_its_share_of_the_results() {
+        let dir = TempDir::new().expect("temp dir");
+        let labor = dir.path().join("labor");
+        fs::create_dir_all(&labor).expect("create labor dir");
+        let long = format!(
+            "# 加班规定\n{}",
+            "延长工作时间应支付加班费，加班费按工资计算。\n".repeat(59)
+        );
+        fs::write(labor.join("overtime.md"), long).expect("write long file");
+        fs::write(labor.join("wage.md"), "# 工资支付\n加班费应按时支付。").expect("write wage");
+        fs::write(
+            labor.join("wage_2024.md"),
+            "# 工资支付\n加班费计入工资总额。",
+        )
+        .expect("write wage 2024");
+        let search = |max_results_per_file: u32, max_results_per_title: u32, top_k: usize| {
+            RetrievalEngine::new(
+                dir.path(),
+                RetrievalConfig {
+                    max_results_per_file,
+                    max_results_per_title,
+                    ..RetrievalConfig::default()
+                },
+            )
+            .search("加班费", "labor", top_k)
+            .expect("search")
+            .into_iter()
+            .map(|result| {
+                result
+                    .file_path
+                    .rsplit('/')
+                    .next()
+                    .unwrap_or_default()
+                    .to_owned()
+            })
+            .collect::<Vec<_>>()
+        };
+        let count = |files: &[String], file: &str| files.iter().filter(|f| *f == file).count();
+
+        let uncapped = search(0, 0, 3);
+        assert_eq!(count(&uncapped, "overtime.md"), 3);
+
+        let capped = search(2, 0, 4);
+        assert_eq!(capped.len(), 4);
+        assert_eq!(count(&capped, "overtime.md"), 2);
+        assert_eq!(
+            count(&capped, "wage.md") + count(&capped, "wage_2024.md"),
+            2
+        );
+
+        // Both wage files share a title; the freed slot is backfilled from
+        // the long file once no other title is left.
+        let by_title = search(0, 1, 3);
+        assert_eq!(by_title.len(), 3);
+        assert_eq!(count(&by_title, "overtime.md"), 2);
+    }
+
+    #[test]
+    fn bundled_statutes_apply_since_they_first_took_effect() {
+        use super::CaseDates;
+        use chrono::NaiveDate;
+
+        if !cfg!(feature = "builtin-kb") {
+            return;
+        }
         let dir = TempDir::new().expect("temp dir");
-        let engine = RetrievalEngine::new(dir.path());
-        let results = engine.search("劳动仲裁", "labor", 3).expect("search empty");
-        assert!(results.is_empty());
+        let engine = RetrievalEngine::new(dir.path(), RetrievalConfig::default());
+        let case = |date: &str| {
+            CaseDates::spanning([NaiveDate::parse_from_str(date, "%Y-%m-%d").expect("date")])
+                .expect("case")
+        };
+
+        // Amended in 2021, in force since 2004.
+        let results = engine
+            .search_for_case("交通事故", "traffic", 3, Some(&case("2019-06-01")))
+            .expect("search");
+        assert!(!results.is_empty());
+        assert!(results
+            .iter()
+            .all(|result| result.inapplicable_warning.is_none()));
+        assert_eq!(results[0].effective_date.as_deref(), Some("2021-04-29"));
+
+        let results = engine
+            .search_for_case("交通事故", "traffic", 3, Some(&case("2003-06-01")))
+            .expect("search");
+        assert!(results
+            .iter()
+            .all(|result| result.inapplicable_warning.is_some()));
+    }
+
+    #[test]
+    fn empty_kb_searches_the_bundled_statutes() {
+        let dir = TempDir::new().expect("temp dir");
+        let engine = RetrievalEngine::new(dir.path(), RetrievalConfig::default());
+        let other = engine.search("劳动仲裁", "tax", 3).expect("search empty");
+        assert!(other.is_empty());
+        let results = engine
+            .search("劳动仲裁", "labor", 3)
+            .expect("search bundled");
+        if !cfg!(feature = "builtin-kb") {
+            assert!(results.is_empty());
+            return;
+        }
+
+        // Searched in memory while the KB has no files.
+        let first = results.first().expect("bundled result");
+        assert!(results.iter().all(|result| result.builtin));
+        // Bundled excerpts are dated by their law, not flagged as stale.
+        assert!(results.iter().all(|result| {
+            result.outdated_warning.is_none() && !result.snippet.contains("effective_date")
+        }));
+        assert!(first.title.ends_with("（内置摘录）"));
+        assert!(first.chunk_id.starts_with("labor/builtin/"));
+        let chunk = engine
+            .knowledge_chunk(&first.chunk_id)
+            .expect("bundled chunk");
+        assert!(chunk
+            .text
+            .contains(first.snippet.lines().next().expect("line")));
+        assert!(engine
+            .read_file(&first.file_path)
+            .expect("bundled file")
+            .contains("内置"));
+
+        let written = engine.bootstrap_builtin_kb().expect("extract");
+        assert_eq!(written as usize, builtin::documents().len());
+        assert_eq!(engine.bootstrap_builtin_kb().expect("kb not empty"), 0);
+        let extracted = engine
+            .search("劳动仲裁", "labor", 3)
+            .expect("search extracted");
+        assert!(extracted
+            .iter()
+            .all(|result| result.builtin && result.outdated_warning.is_none()));
+        assert!(Path::new(&extracted[0].file_path).exists());
+
+        // Files the user installs next to them are their own.
+        fs::write(
+            dir.path().join("labor").join("mine.md"),
+            "# 我的笔记\n劳动仲裁要带证据",
+        )
+        .expect("write");
+        let mixed = engine.search("劳动仲裁", "labor", 5).expect("search mixed");
+        assert!(mixed
+            .iter()
+            .any(|result| !result.builtin && result.title == "我的笔记"));
+    }
+
+    #[test]
+    fn frontmatter_is_not_cited() {
+        let dir = TempDir::new().expect("temp dir");
+        fs::create_dir_all(dir.path().join("labor")).expect("create dir");
+        fs::write(
+            dir.path().join("labor").join("law.md"),
+            "---\neffective_date: 2008-05-01\n---\n# 仲裁法\n劳动争议申请仲裁的时效期间为一年。",
+        )
+        .expect("write");
+
+        for config in [
+            RetrievalConfig::default(),
+            RetrievalConfig {
+                low_memory: true,
+                ..RetrievalConfig::default()
+            },
+        ] {
+            let engine = RetrievalEngine::new(dir.path(), config);
+            let results = engine.search("仲裁 时效", "labor", 1).expect("search");
+            assert_eq!(results[0].chunk_id, "labor/law.md#L4-L5");
+            assert!(results[0].snippet.starts_with("# 仲裁法"));
+            assert_eq!(results[0].effective_date.as_deref(), Some("2008-05-01"));
+        }
     }
 
     #[test]
@@ -382,5 +1756,492 @@ mod tests {
         assert!(first.file_path.ends_with("wage.md"));
         assert!(first.line_start >= 1);
         assert!(first.line_end >= first.line_start);
+        assert_eq!(first.token_count, estimate_tokens(&first.snippet));
+    }
+
+    #[test]
+    fn trim_to_token_budget_skips_results_that_do_not_fit() {
+        let result = |snippet: &str| SearchResult {
+            file_path: "labor/law.md".to_owned(),
+            title: "劳动仲裁".to_owned(),
+            snippet: snippet.to_owned(),
+            line_start: 1,
+            line_end: 1,
+            score: 1.0,
+            token_count: estimate_tokens(snippet),
+            effective_date: None,
+            outdated_warning: None,
+            chunk_id: "labor/law.md#L1-L1".to_owned(),
+            anchor: None,
+            remote_source: None,
+            from_cache: false,
+            builtin: false,
+            repeal_date: None,
+            inapplicable_warning: None,
+        };
+        let results = vec![
+            result("拖欠工资可申请劳动仲裁"),
+            result("准备劳动合同、工资流水和沟通记录等证据材料"),
+            result("仲裁时效一年"),
+        ];
+
+        let trimmed = trim_to_token_budget(results, 18);
+        let snippets = trimmed
+            .iter()
+            .map(|item| item.snippet.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(snippets, vec!["拖欠工资可申请劳动仲裁", "仲裁时效一年"]);
+    }
+
+    #[test]
+    fn merged_query_results_interleave_and_spread_files() {
+        let result = |file: &str, line: u32| SearchResult {
+            file_path: file.to_owned(),
+            title: file.to_owned(),
+            snippet: String::new(),
+            line_start: line,
+            line_end: line,
+            score: 1.0,
+            token_count: 0,
+            effective_date: None,
+            outdated_warning: None,
+            chunk_id: format!("{file}#L{line}-L{line}"),
+            anchor: None,
+            remote_source: None,
+            from_cache: false,
+            builtin: false,
+            repeal_date: None,
+            inapplicable_warning: None,
+        };
+        let batches = vec![
+            vec![result("a.md", 1), result("a.md", 2), result("a.md", 3)],
+            vec![result("a.md", 1), result("b.md", 1)],
+            vec![result("a.md", 4), result("c.md", 1)],
+        ];
+
+        let ids = |results: Vec<SearchResult>| {
+            results
+                .into_iter()
+                .map(|result| result.chunk_id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            ids(merge_query_results(batches.clone(), 4)),
+            ["a.md#L1-L1", "a.md#L4-L4", "b.md#L1-L1", "c.md#L1-L1"]
+        );
+        assert_eq!(
+            ids(merge_query_results(batches, 6)),
+            [
+                "a.md#L1-L1",
+                "a.md#L4-L4",
+                "b.md#L1-L1",
+                "c.md#L1-L1",
+                "a.md#L2-L2",
+                "a.md#L3-L3"
+            ]
+        );
+    }
+
+    #[test]
+    fn low_memory_mode_matches_default_results_and_caps_chunks() {
+        let (dir, engine) = setup_kb();
+        let low = RetrievalEngine::new(
+            dir.path(),
+            RetrievalConfig {
+                low_memory: true,
+                ..RetrievalConfig::default()
+            },
+        );
+
+        let expected = engine.search("劳动仲裁", "labor", 3).expect("search");
+        let actual = low.search("劳动仲裁", "labor", 3).expect("search");
+        assert_eq!(expected.len(), actual.len());
+        assert_eq!(expected[0].title, actual[0].title);
+        assert_eq!(expected[0].snippet, actual[0].snippet);
+        assert_eq!(expected[0].line_end, actual[0].line_end);
+
+        let big = "工资条款\n".repeat(20 * (LOW_MEMORY_MAX_CHUNKS + 10));
+        fs::write(dir.path().join("labor").join("big.md"), big).expect("write big file");
+        assert_eq!(
+            low.collect_chunks("labor").expect("chunks").len(),
+            LOW_MEMORY_MAX_CHUNKS
+        );
+    }
+
+    #[test]
+    fn parallel_index_build_matches_a_serial_one() {
+        let (dir, _) = setup_kb();
+        for n in 0..40 {
+            let body = format!("# 第{n}号规定\n\n")
+                + &format!("用人单位拖欠工资{n}个月的，劳动者可以申请劳动仲裁。\n").repeat(300);
+            fs::write(dir.path().join("labor").join(format!("rule-{n}.md")), body)
+                .expect("write rule");
+        }
+        let engine = |index_threads| {
+            RetrievalEngine::new(
+                dir.path(),
+                RetrievalConfig {
+                    index_threads,
+                    ..RetrievalConfig::default()
+                },
+            )
+        };
+        let (serial, parallel) = (engine(1), engine(4));
+
+        let chunk_ids = |engine: &RetrievalEngine| {
+            engine
+                .collect_chunks("labor")
+                .expect("chunks")
+                .iter()
+                .map(|chunk| engine.chunk_id(chunk))
+                .collect::<Vec<_>>()
+        };
+        assert!(chunk_ids(&serial).len() > 512);
+        assert_eq!(chunk_ids(&serial), chunk_ids(&parallel));
+
+        let ranked = |engine: &RetrievalEngine| {
+            let mut results = engine
+                .search("拖欠工资 劳动仲裁", "labor", 10)
+                .expect("search")
+                .into_iter()
+                .map(|result| (result.chunk_id, result.score.to_bits()))
+                .collect::<Vec<_>>();
+            results.sort();
+            results
+        };
+        assert_eq!(ranked(&serial), ranked(&parallel));
+    }
+
+    /// A remote KB with one document about deposits, unreachable while
+    /// `offline` is set.
+    #[derive(Default)]
+    struct FakeRemoteKb {
+        offline: AtomicBool,
+        queries: AtomicU32,
+    }
+
+    impl RemoteKbClient for FakeRemoteKb {
+        fn query(&self, query: &str, scenario: &str, top_k: usize) -> CoreResult<Vec<RemoteChunk>> {
+            self.queries.fetch_add(1, Ordering::SeqCst);
+            if self.offline.load(Ordering::SeqCst) {
+                return Err(CoreError::Storage("connection refused".to_owned()));
+            }
+            assert_eq!(scenario, "rental");
+            assert!(top_k >= 3);
+            Ok([
+                ("租房押金", "房东无正当理由不退押金的，租客可以起诉。"),
+                ("租金上涨", "租期内房东不得单方上涨租金。"),
+            ]
+            .into_iter()
+            .filter(|(_, snippet)| snippet.contains(query))
+            .enumerate()
+            .map(|(index, (title, snippet))| RemoteChunk {
+                file_path: format!("rental/{index}.md"),
+                title: title.to_owned(),
+                snippet: snippet.to_owned(),
+                line_start: 1,
+                line_end: 2,
+                score: 1.0,
+                effective_date: Some("2024-01-01".to_owned()),
+                first_effective_date: None,
+                repeal_date: None,
+                anchor: None,
+            })
+            .collect())
+        }
+    }
+
+    #[test]
+    fn remote_scenarios_are_searched_remotely_and_cached_for_offline_use() {
+        let (dir, _) = setup_kb();
+        let cache_path = dir.path().join("remote-cache.json");
+        let config = RetrievalConfig {
+            remote_sources: vec![RemoteKbConfig {
+                name: "总部法务库".to_owned(),
+                scenarios: vec!["rental".to_owned()],
+                base_url: "http://kb.internal".to_owned(),
+                api_key: None,
+                timeout_ms: 5_000,
+                cache_path: Some(cache_path.to_string_lossy().into_owned()),
+            }],
+            ..RetrievalConfig::default()
+        };
+        let remote = Arc::new(FakeRemoteKb::default());
+        let engine =
+            RetrievalEngine::new(dir.path(), config.clone()).with_remote_client(remote.clone());
+
+        let results = engine.search("押金", "rental", 3).expect("remote search");
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].title, "租房押金");
+        assert_eq!(results[0].remote_source.as_deref(), Some("总部法务库"));
+        assert!(!results[0].from_cache);
+        // Other scenarios stay on the device.
+        let local = engine.search("拖欠工资", "labor", 3).expect("local search");
+        assert!(local.iter().all(|result| result.remote_source.is_none()));
+        assert_eq!(remote.queries.load(Ordering::SeqCst), 1);
+
+        remote.offline.store(true, Ordering::SeqCst);
+        let cached = engine.search("押金", "rental", 3).expect("cached search");
+        assert_eq!(cached[0].chunk_id, "rental/0.md#L1-L2");
+        assert!(cached[0].from_cache);
+
+        // The cache outlives the engine.
+        let restarted = RetrievalEngine::new(dir.path(), config).with_remote_client(remote);
+        let cached = restarted
+            .search("押金", "rental", 3)
+            .expect("after restart");
+        assert_eq!(cached[0].title, "租房押金");
+        assert!(cached[0].from_cache);
+    }
+
+    /// A remote KB answering every query with the same chunks.
+    struct FixedRemoteKb(Vec<RemoteChunk>);
+
+    impl RemoteKbClient for FixedRemoteKb {
+        fn query(&self, _: &str, _: &str, _: usize) -> CoreResult<Vec<RemoteChunk>> {
+            Ok(self.0.clone())
+        }
+    }
+
+    #[test]
+    fn remote_results_are_ranked_like_local_ones() {
+        use crate::clock::ManualClock;
+
+        let chunk = |file: &str, line_start: u32, date: &str, snippet: String| RemoteChunk {
+            file_path: file.to_owned(),
+            title: file.to_owned(),
+            snippet,
+            line_start,
+            line_end: line_start + 1,
+            score: 1.0,
+            effective_date: Some(date.to_owned()),
+            first_effective_date: None,
+            repeal_date: None,
+            anchor: None,
+        };
+        let remote = Arc::new(FixedRemoteKb(vec![
+            chunk("old.md", 1, "2010-01-01", "押金旧规定".to_owned()),
+            chunk("new.md", 1, "2024-01-01", "押金新规定".to_owned()),
+            chunk("new.md", 30, "2024-01-01", "押金\n".repeat(40)),
+        ]));
+        let (dir, _) = setup_kb();
+        let config = RetrievalConfig {
+            remote_sources: vec![RemoteKbConfig {
+                name: "总部法务库".to_owned(),
+                scenarios: vec!["rental".to_owned()],
+                base_url: "http://kb.internal".to_owned(),
+                api_key: Some("secret-token".to_owned()),
+                timeout_ms: 5_000,
+                cache_path: None,
+            }],
+            max_results_per_file: 1,
+            ..RetrievalConfig::default()
+        };
+        assert!(!format!("{config:?}").contains("secret-token"));
+        // 2030-01-01, when even the 2024 text is past `stale_after_days`.
+        let engine = RetrievalEngine::new(dir.path(), config)
+            .with_clock(Arc::new(ManualClock::at_timestamp(1_893_456_000)))
+            .with_remote_client(remote);
+
+        let results = engine.search("押金", "rental", 2).expect("search");
+        assert_eq!(
+            results
+                .iter()
+                .map(|result| result.file_path.as_str())
+                .collect::<Vec<_>>(),
+            ["new.md", "old.md"]
+        );
+        assert!(results[0].outdated_warning.is_some());
+        assert!(results[1].outdated_warning.is_some());
+
+        let results = engine.search("押金", "rental", 3).expect("search");
+        let long = results
+            .iter()
+            .find(|result| result.line_start == 30)
+            .expect("long chunk");
+        assert_eq!(long.snippet.lines().count(), super::LINES_PER_CHUNK);
+        assert_eq!(long.line_end, 49);
+    }
+
+    /// Places texts about dismissal and about wages on separate axes,
+    /// whatever words they use.
+    struct TopicEmbedder;
+    impl EmbeddingProvider for TopicEmbedder {
+        fn embed(&self, texts: &[String]) -> CoreResult<Vec<Vec<f32>>> {
+            let topic = |text: &str, words: &[&str]| {
+                f32::from(u8::from(words.iter().any(|word| text.contains(word))))
+            };
+            Ok(texts
+                .iter()
+                .map(|text| {
+                    vec![
+                        topic(text, &["开除", "解除", "辞退"]),
+                        topic(text, &["工资", "薪水"]),
+                        0.1,
+                    ]
+                })
+                .collect())
+        }
+    }
+
+    #[test]
+    fn hybrid_search_finds_paraphrased_chunks() {
+        let dir = TempDir::new().expect("temp dir");
+        let labor = dir.path().join("labor");
+        fs::create_dir_all(&labor).expect("create labor dir");
+        fs::write(
+            labor.join("dismissal.md"),
+            "# 违法解除\n用人单位违法解除劳动合同的，应当支付赔偿金。",
+        )
+        .expect("write dismissal");
+        fs::write(labor.join("wage.md"), "# 欠薪\n拖欠工资可以申请劳动仲裁。").expect("write wage");
+
+        let lexical = RetrievalEngine::new(dir.path(), RetrievalConfig::default());
+        assert!(lexical
+            .search("我被老板开除了", "labor", 2)
+            .expect("search")
+            .is_empty());
+
+        let hybrid = lexical.with_embedding_provider(Arc::new(TopicEmbedder));
+        let results = hybrid.search("我被老板开除了", "labor", 1).expect("search");
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].chunk_id, "labor/dismissal.md#L1-L2");
+
+        let results = hybrid.search("拖欠工资", "labor", 2).expect("search");
+        assert_eq!(results[0].chunk_id, "labor/wage.md#L1-L2");
+    }
+
+    // Relies on the index's scores: unboosted, the older text wins.
+    #[cfg(feature = "retrieval-tantivy")]
+    #[test]
+    fn newer_regulations_outrank_superseded_ones() {
+        use super::OUTDATED_WARNING;
+
+        let dir = TempDir::new().expect("temp dir");
+        let labor = dir.path().join("labor");
+        fs::create_dir_all(&labor).expect("create dir");
+        fs::write(
+            labor.join("a_old.md"),
+            "---\neffective_date: 2008-01-01\n---\n# 旧条例\n加班费按工资的百分之一百五十支付，加班费不得克扣。",
+        )
+        .expect("write old");
+        fs::write(
+            labor.join("b_new.md"),
+            "# 新条例\n加班费按工资的百分之一百五十支付。",
+        )
+        .expect("write new");
+
+        let results = RetrievalEngine::new(dir.path(), RetrievalConfig::default())
+            .search("加班费", "labor", 2)
+            .expect("search");
+        assert!(results[0].file_path.ends_with("b_new.md"));
+        assert_eq!(results[0].outdated_warning, None);
+        assert_eq!(results[1].effective_date.as_deref(), Some("2008-01-01"));
+        assert_eq!(
+            results[1].outdated_warning.as_deref(),
+            Some(OUTDATED_WARNING)
+        );
+        assert!(results[0].score > results[1].score);
+
+        let unboosted = RetrievalEngine::new(
+            dir.path(),
+            RetrievalConfig {
+                recency_half_life_days: 0,
+                stale_after_days: 0,
+                ..RetrievalConfig::default()
+            },
+        )
+        .search("加班费", "labor", 2)
+        .expect("search");
+        assert!(unboosted[0].file_path.ends_with("a_old.md"));
+        assert!(unboosted.iter().all(|r| r.outdated_warning.is_none()));
+    }
+
+    #[test]
+    fn staleness_is_measured_against_the_injected_clock() {
+        use crate::clock::ManualClock;
+
+        let dir = TempDir::new().expect("temp dir");
+        let labor = dir.path().join("labor");
+        fs::create_dir_all(&labor).expect("create dir");
+        fs::write(
+            labor.join("old.md"),
+            "---\neffective_date: 2008-01-01\n---\n# 旧条例\n加班费按工资的百分之一百五十支付。",
+        )
+        .expect("write old");
+
+        let search_at = |timestamp| {
+            RetrievalEngine::new(dir.path(), RetrievalConfig::default())
+                .with_clock(Arc::new(ManualClock::at_timestamp(timestamp)))
+                .search("加班费", "labor", 1)
+                .expect("search")
+        };
+        // 2009-01-01 and 2020-01-01.
+        assert_eq!(search_at(1_230_768_000)[0].outdated_warning, None);
+        assert!(search_at(1_577_836_800)[0].outdated_warning.is_some());
+    }
+
+    #[test]
+    fn laws_not_in_force_during_the_case_rank_lower() {
+        use super::CaseDates;
+        use chrono::NaiveDate;
+
+        let dir = TempDir::new().expect("temp dir");
+        let labor = dir.path().join("labor");
+        fs::create_dir_all(&labor).expect("create dir");
+        fs::write(
+            labor.join("a_old.md"),
+            "---\neffective_date: 2008-01-01\nrepeal_date: 2021-01-01\n---\n# 旧条例\n加班费按工资的百分之一百五十支付。",
+        )
+        .expect("write old");
+        fs::write(
+            labor.join("b_new.md"),
+            "---\neffective_date: 2021-01-01\n---\n# 新条例\n加班费按工资的百分之一百五十支付。",
+        )
+        .expect("write new");
+        let config = RetrievalConfig {
+            recency_half_life_days: 0,
+            ..RetrievalConfig::default()
+        };
+        let engine = RetrievalEngine::new(dir.path(), config.clone());
+        let case = |date: &str| {
+            let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").expect("date");
+            CaseDates::spanning([date])
+        };
+
+        let in_2019 = engine
+            .search_for_case("加班费", "labor", 2, case("2019-05-01").as_ref())
+            .expect("search");
+        assert!(in_2019[0].file_path.ends_with("a_old.md"));
+        assert_eq!(in_2019[0].repeal_date.as_deref(), Some("2021-01-01"));
+        assert_eq!(in_2019[0].inapplicable_warning, None);
+        assert_eq!(
+            in_2019[1].inapplicable_warning.as_deref(),
+            Some("案件发生时尚未施行（2021-01-01起施行）")
+        );
+        assert!(in_2019[0].score > in_2019[1].score);
+
+        let in_2023 = engine
+            .search_for_case("加班费", "labor", 2, case("2023-05-01").as_ref())
+            .expect("search");
+        assert!(in_2023[0].file_path.ends_with("b_new.md"));
+        assert!(in_2023[1].inapplicable_warning.is_some());
+        assert!(engine
+            .search("加班费", "labor", 2)
+            .expect("search")
+            .iter()
+            .all(|result| result.inapplicable_warning.is_none()));
+
+        let excluding = RetrievalEngine::new(
+            dir.path(),
+            RetrievalConfig {
+                exclude_inapplicable: true,
+                ..config
+            },
+        )
+        .search_for_case("加班费", "labor", 2, case("2023-05-01").as_ref())
+        .expect("search");
+        assert_eq!(excluding.len(), 1);
+        assert!(excluding[0].file_path.ends_with("b_new.md"));
     }
 }
@@ -0,0 +1,72 @@
+//! Worker threads of an index build: reading and chunking KB files and
+//! tokenizing chunks run on a pool bounded by
+//! `RetrievalConfig::index_threads`, so a cold build of a large KB uses the
+//! cores of a desktop without taking over a phone.
+
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+/// `None` runs everything on the calling thread.
+pub(crate) struct IndexPool(Option<ThreadPool>);
+
+impl IndexPool {
+    /// `threads` of `0` takes one per core. A single thread, or a pool that
+    /// cannot be started, runs serially.
+    pub(crate) fn new(threads: u32) -> Self {
+        let threads = match threads {
+            0 => std::thread::available_parallelism().map_or(1, usize::from),
+            threads => threads as usize,
+        };
+        if threads <= 1 {
+            return Self(None);
+        }
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|index| format!("kb-index-{index}"))
+            .build();
+        match pool {
+            Ok(pool) => Self(Some(pool)),
+            Err(err) => {
+                tracing::warn!("index pool unavailable, indexing serially: {err}");
+                Self(None)
+            }
+        }
+    }
+
+    /// Writer threads of the tantivy index follow this.
+    #[cfg_attr(not(feature = "retrieval-tantivy"), allow(dead_code))]
+    pub(crate) fn threads(&self) -> usize {
+        self.0.as_ref().map_or(1, ThreadPool::current_num_threads)
+    }
+
+    /// `f` over `items`, results in the order of `items`.
+    pub(crate) fn map<T, U, F>(&self, items: Vec<T>, f: F) -> Vec<U>
+    where
+        T: Send,
+        U: Send,
+        F: Fn(T) -> U + Sync + Send,
+    {
+        match &self.0 {
+            Some(pool) => pool.install(|| items.into_par_iter().map(f).collect()),
+            None => items.into_iter().map(f).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IndexPool;
+
+    #[test]
+    fn parallel_map_keeps_the_input_order() {
+        let items = (0..1_000).collect::<Vec<u32>>();
+        let serial = IndexPool::new(1);
+        let parallel = IndexPool::new(4);
+        assert_eq!(serial.threads(), 1);
+        assert_eq!(parallel.threads(), 4);
+        assert_eq!(
+            parallel.map(items.clone(), |n| n * 2),
+            serial.map(items, |n| n * 2)
+        );
+    }
+}
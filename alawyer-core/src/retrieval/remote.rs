@@ -0,0 +1,291 @@
+//! Knowledge bases hosted on a server instead of the device, for
+//! deployments whose authoritative KB lives on an internal service.
+//! Scenarios listed in a `RemoteKbConfig` are searched there; every chunk
+//! fetched is cached, and while the server cannot be reached the cache is
+//! searched locally instead. Results say which source they came from in
+//! `SearchResult::remote_source`.
+//!
+//! The server answers `POST {base_url}/query` with a JSON body of
+//! `{"query", "scenario", "top_k"}` by `{"results": [...]}`, each result
+//! shaped like `RemoteChunk`, best first.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::sync::{Arc, Mutex, MutexGuard};
+#[cfg(feature = "model-remote")]
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{CoreError, CoreResult};
+
+/// Chunks kept per scenario for offline search; the oldest fetched go
+/// first.
+const MAX_CACHED_CHUNKS: usize = 500;
+
+#[derive(Clone, PartialEq, Eq, uniffi::Record)]
+pub struct RemoteKbConfig {
+    /// Shown as the provenance of the source's results, e.g. `总部法务库`.
+    pub name: String,
+    /// Scenarios searched on this source; others stay on the local KB.
+    pub scenarios: Vec<String>,
+    pub base_url: String,
+    /// Sent as a bearer token when set.
+    #[uniffi(default = None)]
+    pub api_key: Option<String>,
+    #[uniffi(default = 5000)]
+    pub timeout_ms: u32,
+    /// JSON file the fetched chunks are kept in across restarts; `None`
+    /// keeps them in memory only.
+    #[uniffi(default = None)]
+    pub cache_path: Option<String>,
+}
+
+impl fmt::Debug for RemoteKbConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RemoteKbConfig")
+            .field("name", &self.name)
+            .field("scenarios", &self.scenarios)
+            .field("base_url", &self.base_url)
+            .field("api_key", &self.api_key.as_ref().map(|_| "<redacted>"))
+            .field("timeout_ms", &self.timeout_ms)
+            .field("cache_path", &self.cache_path)
+            .finish()
+    }
+}
+
+/// One result of a remote source, as the server sends it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RemoteChunk {
+    /// The document's path on the server; it need not exist locally.
+    pub file_path: String,
+    pub title: String,
+    pub snippet: String,
+    #[serde(default)]
+    pub line_start: u32,
+    #[serde(default)]
+    pub line_end: u32,
+    #[serde(default)]
+    pub score: f32,
+    /// `YYYY-MM-DD` of the current text.
+    #[serde(default)]
+    pub effective_date: Option<String>,
+    /// `YYYY-MM-DD` the law first took effect, when it has been amended
+    /// since; see `temporal`.
+    #[serde(default)]
+    pub first_effective_date: Option<String>,
+    /// `YYYY-MM-DD`, once repealed.
+    #[serde(default)]
+    pub repeal_date: Option<String>,
+    #[serde(default)]
+    pub anchor: Option<String>,
+}
+
+impl RemoteChunk {
+    pub(crate) fn chunk_id(&self) -> String {
+        format!("{}#L{}-L{}", self.file_path, self.line_start, self.line_end)
+    }
+}
+
+/// Runs a query on a remote source.
+pub trait RemoteKbClient: Send + Sync {
+    fn query(&self, query: &str, scenario: &str, top_k: usize) -> CoreResult<Vec<RemoteChunk>>;
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct CacheFile {
+    scenarios: HashMap<String, Vec<RemoteChunk>>,
+}
+
+/// A configured remote source with its cache.
+pub(crate) struct RemoteKb {
+    pub(crate) config: RemoteKbConfig,
+    /// `None` when no client could be built; only the cache is searched.
+    client: Option<Arc<dyn RemoteKbClient>>,
+    cache: Mutex<CacheFile>,
+}
+
+impl RemoteKb {
+    /// A client that cannot be built, or a cache file that cannot be read,
+    /// is logged; the source then serves what it has.
+    pub(crate) fn new(config: RemoteKbConfig) -> Self {
+        let client = match client_for(&config) {
+            Ok(client) => Some(client),
+            Err(err) => {
+                tracing::warn!("remote kb {} offline: {err}", config.name);
+                None
+            }
+        };
+        let cache = config
+            .cache_path
+            .as_deref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|raw| match serde_json::from_str(&raw) {
+                Ok(cache) => Some(cache),
+                Err(err) => {
+                    tracing::warn!("remote kb cache of {} unreadable: {err}", config.name);
+                    None
+                }
+            })
+            .unwrap_or_default();
+        Self {
+            config,
+            client,
+            cache: Mutex::new(cache),
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn with_client(mut self, client: Arc<dyn RemoteKbClient>) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    pub(crate) fn serves(&self, scenario: &str) -> bool {
+        self.config
+            .scenarios
+            .iter()
+            .any(|served| served == scenario)
+    }
+
+    /// Results of the server, which are cached.
+    pub(crate) fn fetch(
+        &self,
+        query: &str,
+        scenario: &str,
+        top_k: usize,
+    ) -> CoreResult<Vec<RemoteChunk>> {
+        let client = self.client.as_ref().ok_or_else(|| {
+            CoreError::Storage(format!("remote kb {} has no client", self.config.name))
+        })?;
+        let mut chunks = client.query(query, scenario, top_k)?;
+        chunks.truncate(top_k);
+        self.remember(scenario, &chunks);
+        Ok(chunks)
+    }
+
+    /// Chunks fetched for `scenario` so far.
+    pub(crate) fn cached(&self, scenario: &str) -> Vec<RemoteChunk> {
+        self.lock_cache()
+            .scenarios
+            .get(scenario)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn remember(&self, scenario: &str, chunks: &[RemoteChunk]) {
+        if chunks.is_empty() {
+            return;
+        }
+        let mut cache = self.lock_cache();
+        let cached = cache.scenarios.entry(scenario.to_owned()).or_default();
+        cached.retain(|old| !chunks.iter().any(|new| new.chunk_id() == old.chunk_id()));
+        cached.extend(chunks.iter().cloned());
+        let excess = cached.len().saturating_sub(MAX_CACHED_CHUNKS);
+        cached.drain(..excess);
+
+        // A cache that cannot be written must not fail the search.
+        if let Some(path) = &self.config.cache_path {
+            let written = serde_json::to_string(&*cache)
+                .map_err(|e| e.to_string())
+                .and_then(|raw| fs::write(path, raw).map_err(|e| e.to_string()));
+            if let Err(err) = written {
+                tracing::warn!("remote kb cache of {} not saved: {err}", self.config.name);
+            }
+        }
+    }
+
+    fn lock_cache(&self) -> MutexGuard<'_, CacheFile> {
+        self.cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn client_for(config: &RemoteKbConfig) -> CoreResult<Arc<dyn RemoteKbClient>> {
+    if config.base_url.trim().is_empty() {
+        return Err(CoreError::Config(format!(
+            "remote kb {} has no base_url",
+            config.name
+        )));
+    }
+    #[cfg(feature = "model-remote")]
+    {
+        Ok(Arc::new(HttpRemoteKb::new(config)?))
+    }
+    #[cfg(not(feature = "model-remote"))]
+    {
+        Err(CoreError::Config(
+            "remote knowledge bases need a build with the model-remote feature".to_owned(),
+        ))
+    }
+}
+
+#[cfg(feature = "model-remote")]
+struct HttpRemoteKb {
+    client: reqwest::Client,
+    url: String,
+    api_key: Option<String>,
+}
+
+#[cfg(feature = "model-remote")]
+#[derive(Debug, Deserialize)]
+struct QueryResponse {
+    results: Vec<RemoteChunk>,
+}
+
+#[cfg(feature = "model-remote")]
+impl HttpRemoteKb {
+    fn new(config: &RemoteKbConfig) -> CoreResult<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(u64::from(config.timeout_ms)))
+            .build()
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+        Ok(Self {
+            client,
+            url: format!("{}/query", config.base_url.trim_end_matches('/')),
+            api_key: config.api_key.clone().filter(|key| !key.trim().is_empty()),
+        })
+    }
+
+    async fn post(
+        &self,
+        query: &str,
+        scenario: &str,
+        top_k: usize,
+    ) -> CoreResult<Vec<RemoteChunk>> {
+        let mut request = self.client.post(&self.url).json(&serde_json::json!({
+            "query": query,
+            "scenario": scenario,
+            "top_k": top_k,
+        }));
+        if let Some(api_key) = &self.api_key {
+            request = request.header("Authorization", format!("Bearer {api_key}"));
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| CoreError::Storage(format!("remote kb request failed: {e}")))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(CoreError::Storage(format!(
+                "remote kb query failed with status {status}: {body}"
+            )));
+        }
+        let body: QueryResponse = response
+            .json()
+            .await
+            .map_err(|e| CoreError::Storage(format!("invalid remote kb response: {e}")))?;
+        Ok(body.results)
+    }
+}
+
+#[cfg(feature = "model-remote")]
+impl RemoteKbClient for HttpRemoteKb {
+    fn query(&self, query: &str, scenario: &str, top_k: usize) -> CoreResult<Vec<RemoteChunk>> {
+        crate::RUNTIME.block_on(self.post(query, scenario, top_k))
+    }
+}
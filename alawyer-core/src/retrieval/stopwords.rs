@@ -0,0 +1,242 @@
+//! Words left out of the text index and of queries: particles such as
+//! 的/了/在 that occur in nearly every chunk bloat the index and pull BM25
+//! scores towards long chunks without telling documents apart.
+//!
+//! The bundled list can be turned off or adjusted through
+//! `RetrievalConfig`. With `keep_legal_numbers`, numbers and article
+//! references such as 第三十八条 or 第82条第二款 stay one token, so a query
+//! for an article does not also match every chunk mentioning "条". Their
+//! numerals are written in Arabic digits (第三十八条 and 第38条 are both
+//! `第38条`), and a reference to a paragraph also yields its article, so
+//! 第82条第二款 is found by a query for 第八十二条.
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use super::RetrievalConfig;
+
+/// Bundled stopwords for Chinese legal text: particles, pronouns and
+/// conjunctions. Modal verbs such as 应当 and 可以 stay searchable, since
+/// they tell a duty from a right.
+pub const DEFAULT_STOPWORDS: &[&str] = &[
+    "的", "了", "在", "是", "和", "与", "及", "或", "或者", "以及", "并", "并且", "而", "也", "都",
+    "就", "又", "还", "被", "把", "将", "对", "对于", "于", "由", "从", "向", "以", "为", "之",
+    "其", "该", "此", "这", "那", "这个", "那个", "这些", "那些", "我", "你", "他", "她", "它",
+    "我们", "你们", "他们", "吗", "呢", "吧", "啊", "呀", "着", "过", "等", "等等", "个", "些",
+    "有关", "依照", "按照", "根据", "所", "者",
+];
+
+/// Article references (第三十八条, 第82条第二款之一) and numbers
+/// (2022, 1.5, ２００), matched before segmentation.
+static LEGAL_NUMBER: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"第[0-9０-９零〇一二三四五六七八九十百千两]+条(?:之[一二三四五六七八九十]+)?(?:第[0-9０-９零〇一二三四五六七八九十百]+[款项])*|[0-9０-９]+(?:\.[0-9０-９]+)?",
+    )
+    .expect("legal number pattern")
+});
+
+/// Runs of numerals inside a `LEGAL_NUMBER` match.
+static NUMERAL_RUN: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"[0-9０-９零〇一二三四五六七八九十百千两]+").expect("numeral pattern")
+});
+
+/// Drops stopwords from token streams and finds the spans to keep whole.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TokenFilter {
+    stopwords: HashSet<String>,
+    keep_legal_numbers: bool,
+}
+
+impl TokenFilter {
+    pub fn new(config: &RetrievalConfig) -> Self {
+        let mut stopwords = HashSet::new();
+        if config.default_stopwords {
+            stopwords.extend(DEFAULT_STOPWORDS.iter().map(|word| (*word).to_owned()));
+        }
+        for word in &config.kept_words {
+            stopwords.remove(word.trim());
+        }
+        stopwords.extend(
+            config
+                .extra_stopwords
+                .iter()
+                .map(|word| word.trim().to_owned())
+                .filter(|word| !word.is_empty()),
+        );
+        Self {
+            stopwords,
+            keep_legal_numbers: config.keep_legal_numbers,
+        }
+    }
+
+    pub fn is_stopword(&self, token: &str) -> bool {
+        self.stopwords.contains(token.trim())
+    }
+
+    /// `text` with the numerals of its article references and numbers in
+    /// Arabic digits, when `keep_legal_numbers` is on.
+    #[cfg_attr(feature = "retrieval-tantivy", allow(dead_code))]
+    pub fn normalize_numbers<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        if !self.keep_legal_numbers {
+            return Cow::Borrowed(text);
+        }
+        arabic_legal_numbers(text)
+    }
+
+    /// Index tokens of a span `spans` keeps whole: the span in Arabic
+    /// digits and, for a paragraph or item of an article, the article too.
+    #[cfg_attr(not(feature = "retrieval-tantivy"), allow(dead_code))]
+    pub fn whole_tokens(&self, span: &str) -> Vec<String> {
+        let normalized = arabic_numerals(span);
+        let article = normalized
+            .starts_with('第')
+            .then(|| normalized.find('条'))
+            .flatten()
+            .map(|end| &normalized[..end + '条'.len_utf8()])
+            .filter(|article| article.len() < normalized.len())
+            .map(str::to_owned);
+        std::iter::once(normalized.clone()).chain(article).collect()
+    }
+
+    /// `input` split into `(text, keep_whole)` pieces, in order: article
+    /// references and numbers are kept whole when `keep_legal_numbers` is on,
+    /// everything else is left to the segmenter.
+    #[cfg_attr(not(feature = "retrieval-tantivy"), allow(dead_code))]
+    pub fn spans<'a>(&self, input: &'a str) -> Vec<(&'a str, bool)> {
+        if !self.keep_legal_numbers {
+            return vec![(input, false)];
+        }
+        let mut spans = Vec::new();
+        let mut last = 0;
+        for found in LEGAL_NUMBER.find_iter(input) {
+            if found.start() > last {
+                spans.push((&input[last..found.start()], false));
+            }
+            spans.push((found.as_str(), true));
+            last = found.end();
+        }
+        if last < input.len() {
+            spans.push((&input[last..], false));
+        }
+        spans
+    }
+}
+
+/// `text` with the numerals of its article references and numbers in
+/// Arabic digits, so 第三十八条 and 第38条 compare equal.
+pub(crate) fn arabic_legal_numbers(text: &str) -> Cow<'_, str> {
+    LEGAL_NUMBER.replace_all(text, |found: &regex::Captures<'_>| {
+        arabic_numerals(&found[0])
+    })
+}
+
+/// `text` with every run of numerals written in Arabic digits; runs that
+/// do not read as a number are left alone.
+fn arabic_numerals(text: &str) -> String {
+    NUMERAL_RUN
+        .replace_all(text, |found: &regex::Captures<'_>| {
+            let run = &found[0];
+            numeral_value(run).unwrap_or_else(|| run.to_owned())
+        })
+        .into_owned()
+}
+
+/// Digits of a numeral run: ASCII and full-width digits as they are,
+/// Chinese numerals by value (三十八 is 38, 二〇二二 is 2022).
+fn numeral_value(run: &str) -> Option<String> {
+    let digit = |c: char| match c {
+        '0'..='9' => c.to_digit(10),
+        '０'..='９' => Some(c as u32 - '０' as u32),
+        '零' | '〇' => Some(0),
+        '一' => Some(1),
+        '二' | '两' => Some(2),
+        '三' => Some(3),
+        '四' => Some(4),
+        '五' => Some(5),
+        '六' => Some(6),
+        '七' => Some(7),
+        '八' => Some(8),
+        '九' => Some(9),
+        _ => None,
+    };
+    let unit = |c: char| match c {
+        '十' => Some(10),
+        '百' => Some(100),
+        '千' => Some(1000),
+        _ => None,
+    };
+    if run.chars().all(|c| digit(c).is_some()) {
+        return run
+            .chars()
+            .map(|c| digit(c).and_then(|d| char::from_digit(d, 10)))
+            .collect();
+    }
+    let (mut total, mut current) = (0u64, 0u64);
+    for c in run.chars() {
+        if let Some(d) = digit(c) {
+            current = u64::from(d);
+        } else {
+            // 十二 is 12: a unit without a digit before it counts once.
+            total += current.max(1) * unit(c)?;
+            current = 0;
+        }
+    }
+    Some((total + current).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TokenFilter;
+    use crate::retrieval::RetrievalConfig;
+
+    #[test]
+    fn overrides_adjust_the_bundled_list_and_articles_stay_whole() {
+        let filter = TokenFilter::new(&RetrievalConfig {
+            extra_stopwords: vec!["本法".to_owned()],
+            kept_words: vec!["依照".to_owned()],
+            ..RetrievalConfig::default()
+        });
+        assert!(filter.is_stopword("的"));
+        assert!(filter.is_stopword("本法"));
+        assert!(!filter.is_stopword("依照"));
+        assert!(!filter.is_stopword("应当"));
+        assert!(!filter.is_stopword("可以"));
+        assert!(!filter.is_stopword("工资"));
+
+        assert_eq!(
+            filter.spans("依照第三十八条第二款支付1.5倍工资"),
+            [
+                ("依照", false),
+                ("第三十八条第二款", true),
+                ("支付", false),
+                ("1.5", true),
+                ("倍工资", false),
+            ]
+        );
+        assert_eq!(
+            filter.whole_tokens("第三十八条第二款"),
+            ["第38条第2款", "第38条"]
+        );
+        assert_eq!(filter.whole_tokens("第38条"), ["第38条"]);
+        assert_eq!(
+            filter.whole_tokens("第一百零五条之一"),
+            ["第105条之1", "第105条"]
+        );
+        assert_eq!(filter.whole_tokens("２０２２"), ["2022"]);
+        assert_eq!(
+            filter.normalize_numbers("依照第八十二条第二款，二〇二二年"),
+            "依照第82条第2款，二〇二二年"
+        );
+
+        let plain = TokenFilter::new(&RetrievalConfig {
+            default_stopwords: false,
+            keep_legal_numbers: false,
+            ..RetrievalConfig::default()
+        });
+        assert!(!plain.is_stopword("的"));
+        assert_eq!(plain.spans("第82条"), [("第82条", false)]);
+    }
+}
@@ -0,0 +1,118 @@
+//! Which KB documents were in force during a case. Documents carry their
+//! validity window in frontmatter: `first_effective_date`, when the law
+//! first took effect, and `repeal_date` once repealed. `effective_date` is
+//! the date of the current text, so an amended law has both; without
+//! `first_effective_date` it counts as the first. A case spans its key
+//! dates. A document that only took effect
+//! after the case's last key date, or was repealed before its first one,
+//! did not govern the case: its results are downranked with a warning, or
+//! dropped with `RetrievalConfig::exclude_inapplicable`.
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+/// Text score multiplier of a result that did not apply to the case.
+pub const INAPPLICABLE_PENALTY: f32 = 0.2;
+
+/// The span of a case's key dates, e.g. the start of employment up to the
+/// day the dispute began.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CaseDates {
+    pub earliest: NaiveDate,
+    pub latest: NaiveDate,
+}
+
+impl CaseDates {
+    /// The span of `dates`; `None` without any.
+    pub fn spanning(dates: impl IntoIterator<Item = NaiveDate>) -> Option<Self> {
+        dates.into_iter().fold(None, |span, date| {
+            Some(match span {
+                None => Self {
+                    earliest: date,
+                    latest: date,
+                },
+                Some(Self { earliest, latest }) => Self {
+                    earliest: earliest.min(date),
+                    latest: latest.max(date),
+                },
+            })
+        })
+    }
+
+    /// Why a document valid from `valid_from` until `repealed_on` did not
+    /// govern the case, or `None` when it did or its dates are unknown.
+    pub fn inapplicable(
+        &self,
+        valid_from: Option<NaiveDate>,
+        repealed_on: Option<NaiveDate>,
+    ) -> Option<String> {
+        if let Some(from) = valid_from.filter(|from| *from > self.latest) {
+            return Some(format!("案件发生时尚未施行（{from}起施行）"));
+        }
+        repealed_on
+            .filter(|repealed| *repealed <= self.earliest)
+            .map(|repealed| format!("案件发生时已废止（{repealed}废止）"))
+    }
+}
+
+/// `（2008-01-01 起施行）`, or `（2008-01-01 至 2020-12-31 有效）` once
+/// repealed; empty without either date.
+pub fn validity_window(valid_from: Option<&str>, repealed_on: Option<&str>) -> String {
+    match (valid_from, repealed_on) {
+        (Some(from), Some(until)) => format!("（{from} 至 {until} 有效）"),
+        (Some(from), None) => format!("（{from} 起施行）"),
+        (None, Some(until)) => format!("（{until} 废止）"),
+        (None, None) => String::new(),
+    }
+}
+
+pub(crate) fn parse_date(value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d").ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::{validity_window, CaseDates};
+
+    fn date(value: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(value, "%Y-%m-%d").expect("date")
+    }
+
+    #[test]
+    fn documents_outside_the_case_span_are_inapplicable() {
+        let case = CaseDates::spanning([date("2022-03-01"), date("2019-06-01")]).expect("span");
+        assert_eq!(case.earliest, date("2019-06-01"));
+        assert_eq!(case.latest, date("2022-03-01"));
+
+        assert!(case.inapplicable(Some(date("2008-01-01")), None).is_none());
+        assert!(case
+            .inapplicable(Some(date("2008-01-01")), Some(date("2020-01-01")))
+            .is_none());
+        assert!(case.inapplicable(None, None).is_none());
+        assert_eq!(
+            case.inapplicable(Some(date("2024-01-01")), None).as_deref(),
+            Some("案件发生时尚未施行（2024-01-01起施行）")
+        );
+        assert_eq!(
+            case.inapplicable(Some(date("1995-01-01")), Some(date("2019-06-01")))
+                .as_deref(),
+            Some("案件发生时已废止（2019-06-01废止）")
+        );
+        assert!(CaseDates::spanning([]).is_none());
+    }
+
+    #[test]
+    fn windows_name_the_known_dates() {
+        assert_eq!(
+            validity_window(Some("2008-01-01"), Some("2020-12-31")),
+            "（2008-01-01 至 2020-12-31 有效）"
+        );
+        assert_eq!(
+            validity_window(Some("2008-01-01"), None),
+            "（2008-01-01 起施行）"
+        );
+        assert_eq!(validity_window(None, None), "");
+    }
+}
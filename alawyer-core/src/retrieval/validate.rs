@@ -0,0 +1,451 @@
+//! Authoring checks for KB curators: problems that make a markdown file
+//! chunk, date or cite badly, found before the file ships instead of in a
+//! report.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::NaiveDate;
+
+use super::extract_title;
+
+/// Frontmatter keys every KB file should set: when the text took effect,
+/// and who issued it or where it was taken from.
+pub const REQUIRED_FRONTMATTER: [&str; 2] = ["effective_date", "source"];
+/// Files above this are read whole on every search and crowd other files
+/// out of the results; split them by chapter.
+pub const OVERSIZE_FILE_BYTES: u64 = 512 * 1024;
+/// Chunks are cut by line, so a longer line ends up as one oversized
+/// snippet.
+pub const OVERSIZE_LINE_CHARS: usize = 2_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, uniffi::Enum)]
+pub enum KbIssueSeverity {
+    /// The file is skipped, misdated or breaks search.
+    Error,
+    /// Indexed, but chunks or citations suffer.
+    Warning,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, uniffi::Enum)]
+pub enum KbIssueKind {
+    /// No frontmatter block, or one without all of `REQUIRED_FRONTMATTER`.
+    IncompleteFrontmatter,
+    /// Unclosed block, or an `effective_date`, `first_effective_date` or
+    /// `repeal_date` that is not `YYYY-MM-DD`.
+    InvalidFrontmatter,
+    HeadingStructure,
+    Oversize,
+    /// Not UTF-8, or UTF-8 with a byte order mark.
+    UnsupportedEncoding,
+    /// Another file has the same title, so citations by title are ambiguous.
+    DuplicateTitle,
+    /// A scenario directory without any markdown file.
+    EmptyScenario,
+}
+
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct KbValidationIssue {
+    pub kind: KbIssueKind,
+    pub severity: KbIssueSeverity,
+    pub message: String,
+    /// 1-based line the issue is on, when it has one.
+    pub line: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct KbFileValidation {
+    /// Path relative to the KB root, always `/`-separated; a directory for
+    /// `EmptyScenario`.
+    pub relative_path: String,
+    pub issues: Vec<KbValidationIssue>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, uniffi::Record)]
+pub struct KbValidationReport {
+    pub files_checked: u32,
+    pub errors: u32,
+    pub warnings: u32,
+    /// Only paths with issues, sorted by path.
+    pub files: Vec<KbFileValidation>,
+}
+
+/// Check `files`, given as `(relative path, path)`, and the scenario
+/// directories directly under `kb_root`.
+pub(crate) fn validate(kb_root: &Path, files: &[(String, PathBuf)]) -> KbValidationReport {
+    let mut issues: BTreeMap<String, Vec<KbValidationIssue>> = BTreeMap::new();
+    let mut titles: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+    for (relative, path) in files {
+        let (found, title) = validate_file(path);
+        if let Some(title) = title {
+            titles.entry(title).or_default().push(relative.clone());
+        }
+        if !found.is_empty() {
+            issues.entry(relative.clone()).or_default().extend(found);
+        }
+    }
+
+    for (title, paths) in &titles {
+        if paths.len() < 2 {
+            continue;
+        }
+        for relative in paths {
+            let others = paths
+                .iter()
+                .filter(|other| *other != relative)
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+                .join(", ");
+            issues.entry(relative.clone()).or_default().push(warning(
+                KbIssueKind::DuplicateTitle,
+                format!("title \"{title}\" is also used by {others}"),
+                None,
+            ));
+        }
+    }
+
+    for scenario in scenario_dirs(kb_root) {
+        let prefix = format!("{scenario}/");
+        if !files
+            .iter()
+            .any(|(relative, _)| relative.starts_with(&prefix))
+        {
+            issues.entry(scenario).or_default().push(error(
+                KbIssueKind::EmptyScenario,
+                "scenario has no markdown files, so its searches find nothing".to_owned(),
+                None,
+            ));
+        }
+    }
+
+    let mut report = KbValidationReport {
+        files_checked: files.len() as u32,
+        ..KbValidationReport::default()
+    };
+    for (relative_path, issues) in issues {
+        for issue in &issues {
+            match issue.severity {
+                KbIssueSeverity::Error => report.errors += 1,
+                KbIssueSeverity::Warning => report.warnings += 1,
+            }
+        }
+        report.files.push(KbFileValidation {
+            relative_path,
+            issues,
+        });
+    }
+    report
+}
+
+/// Issues of one file, and its title when the file could be read.
+fn validate_file(path: &Path) -> (Vec<KbValidationIssue>, Option<String>) {
+    let mut issues = Vec::new();
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            issues.push(error(
+                KbIssueKind::UnsupportedEncoding,
+                format!("unreadable: {err}"),
+                None,
+            ));
+            return (issues, None);
+        }
+    };
+    if bytes.len() as u64 > OVERSIZE_FILE_BYTES {
+        issues.push(warning(
+            KbIssueKind::Oversize,
+            format!(
+                "{} KiB, over the {} KiB limit; split it by chapter",
+                bytes.len() / 1024,
+                OVERSIZE_FILE_BYTES / 1024
+            ),
+            None,
+        ));
+    }
+    if bytes.starts_with(&[0xFF, 0xFE]) || bytes.starts_with(&[0xFE, 0xFF]) {
+        issues.push(error(
+            KbIssueKind::UnsupportedEncoding,
+            "UTF-16 text; save the file as UTF-8".to_owned(),
+            None,
+        ));
+        return (issues, None);
+    }
+    let content = match std::str::from_utf8(&bytes) {
+        Ok(content) => content,
+        Err(err) => {
+            let line = bytes[..err.valid_up_to()]
+                .iter()
+                .filter(|byte| **byte == b'\n')
+                .count()
+                + 1;
+            issues.push(error(
+                KbIssueKind::UnsupportedEncoding,
+                "not valid UTF-8 (GBK?); save the file as UTF-8".to_owned(),
+                Some(line as u32),
+            ));
+            return (issues, None);
+        }
+    };
+    if let Some(rest) = content.strip_prefix('\u{feff}') {
+        issues.push(error(
+            KbIssueKind::UnsupportedEncoding,
+            "UTF-8 with a byte order mark, which hides the frontmatter; save without BOM"
+                .to_owned(),
+            Some(1),
+        ));
+        check_lines(rest, &mut issues);
+    } else {
+        check_lines(content, &mut issues);
+    }
+    (issues, Some(extract_title(path, content)))
+}
+
+fn check_lines(content: &str, issues: &mut Vec<KbValidationIssue>) {
+    let lines = content.lines().collect::<Vec<_>>();
+    let body_start = check_frontmatter(&lines, issues);
+
+    let mut in_fence = false;
+    let mut previous_level = 0;
+    let mut titles = 0;
+    for (idx, line) in lines.iter().enumerate().skip(body_start) {
+        let number = Some(idx as u32 + 1);
+        let trimmed = line.trim_start();
+        if line.chars().count() > OVERSIZE_LINE_CHARS {
+            issues.push(warning(
+                KbIssueKind::Oversize,
+                format!("line is over {OVERSIZE_LINE_CHARS} characters and becomes one huge chunk"),
+                number,
+            ));
+        }
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence || !trimmed.starts_with('#') {
+            continue;
+        }
+
+        let level = trimmed.chars().take_while(|c| *c == '#').count();
+        if trimmed[level..].trim().is_empty() {
+            issues.push(warning(
+                KbIssueKind::HeadingStructure,
+                "heading without text".to_owned(),
+                number,
+            ));
+        }
+        if previous_level == 0 && level != 1 {
+            issues.push(warning(
+                KbIssueKind::HeadingStructure,
+                format!("first heading is level {level}; start with a `#` title"),
+                number,
+            ));
+        } else if level == 1 && titles > 0 {
+            issues.push(warning(
+                KbIssueKind::HeadingStructure,
+                "second `#` title; only the first one names the document".to_owned(),
+                number,
+            ));
+        } else if previous_level > 0 && level > previous_level + 1 {
+            issues.push(warning(
+                KbIssueKind::HeadingStructure,
+                format!("heading jumps from level {previous_level} to {level}"),
+                number,
+            ));
+        }
+        if level == 1 {
+            titles += 1;
+        }
+        previous_level = level;
+    }
+    if previous_level == 0 {
+        issues.push(warning(
+            KbIssueKind::HeadingStructure,
+            "no heading; the file name is used as the title".to_owned(),
+            None,
+        ));
+    }
+}
+
+/// Check the frontmatter block and return the index of the first line
+/// after it.
+fn check_frontmatter(lines: &[&str], issues: &mut Vec<KbValidationIssue>) -> usize {
+    if lines.first().map(|line| line.trim()) != Some("---") {
+        issues.push(warning(
+            KbIssueKind::IncompleteFrontmatter,
+            format!(
+                "no frontmatter; add {} between `---` lines",
+                REQUIRED_FRONTMATTER.join(" and ")
+            ),
+            Some(1),
+        ));
+        return 0;
+    }
+    let Some(close) = lines.iter().skip(1).position(|line| line.trim() == "---") else {
+        issues.push(error(
+            KbIssueKind::InvalidFrontmatter,
+            "frontmatter is not closed with `---`".to_owned(),
+            Some(1),
+        ));
+        return 0;
+    };
+    let close = close + 1;
+
+    let mut keys = Vec::new();
+    for (idx, line) in lines.iter().enumerate().take(close).skip(1) {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let (key, value) = (key.trim(), value.trim());
+        if value.is_empty() {
+            continue;
+        }
+        if matches!(
+            key,
+            "effective_date" | "first_effective_date" | "repeal_date"
+        ) && NaiveDate::parse_from_str(value.trim_matches(|c| c == '"' || c == '\''), "%Y-%m-%d")
+            .is_err()
+        {
+            issues.push(error(
+                KbIssueKind::InvalidFrontmatter,
+                format!("{key} \"{value}\" is not YYYY-MM-DD"),
+                Some(idx as u32 + 1),
+            ));
+        }
+        keys.push(key);
+    }
+    let missing = REQUIRED_FRONTMATTER
+        .iter()
+        .filter(|required| !keys.contains(required))
+        .copied()
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        issues.push(warning(
+            KbIssueKind::IncompleteFrontmatter,
+            format!("frontmatter is missing {}", missing.join(", ")),
+            Some(1),
+        ));
+    }
+    close + 1
+}
+
+/// Names of the directories directly under `kb_root`, hidden ones aside.
+fn scenario_dirs(kb_root: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(kb_root) else {
+        return Vec::new();
+    };
+    let mut dirs = entries
+        .flatten()
+        .filter(|entry| entry.file_type().map(|kind| kind.is_dir()).unwrap_or(false))
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|name| !name.starts_with('.'))
+        .collect::<Vec<_>>();
+    dirs.sort();
+    dirs
+}
+
+fn error(kind: KbIssueKind, message: String, line: Option<u32>) -> KbValidationIssue {
+    KbValidationIssue {
+        kind,
+        severity: KbIssueSeverity::Error,
+        message,
+        line,
+    }
+}
+
+fn warning(kind: KbIssueKind, message: String, line: Option<u32>) -> KbValidationIssue {
+    KbValidationIssue {
+        kind,
+        severity: KbIssueSeverity::Warning,
+        message,
+        line,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use super::{KbIssueKind, KbIssueSeverity};
+    use crate::retrieval::{RetrievalConfig, RetrievalEngine};
+
+    #[test]
+    fn curator_mistakes_are_reported_per_file() {
+        let kb = TempDir::new().expect("kb");
+        let root = kb.path();
+        for dir in ["labor", "family", "traffic"] {
+            fs::create_dir_all(root.join(dir)).expect("mkdir");
+        }
+        fs::write(
+            root.join("labor/wage.md"),
+            "---\neffective_date: 2008-01-01\nsource: 劳动合同法\n---\n# 欠薪\n## 仲裁\n拖欠工资可申请劳动仲裁。",
+        )
+        .expect("write");
+        fs::write(
+            root.join("labor/overtime.md"),
+            "---\neffective_date: 2008/01/01\n---\n## 加班\n#### 计算\n```\n# not a heading\n```",
+        )
+        .expect("write");
+        fs::write(root.join("family/divorce.md"), "# 欠薪\n离婚诉讼").expect("write");
+        fs::write(root.join("family/gbk.md"), b"# \xc0\xeb\xbb\xe9").expect("write");
+        fs::write(root.join("traffic/notes.txt"), "not markdown").expect("write");
+
+        let engine = RetrievalEngine::new(root, RetrievalConfig::default());
+        let report = engine.validate().expect("validate");
+        assert_eq!(report.files_checked, 4);
+
+        let kinds = |path: &str| {
+            report
+                .files
+                .iter()
+                .find(|file| file.relative_path == path)
+                .map(|file| {
+                    file.issues
+                        .iter()
+                        .map(|issue| (issue.kind, issue.line))
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default()
+        };
+        assert_eq!(
+            kinds("labor/wage.md"),
+            [(KbIssueKind::DuplicateTitle, None)]
+        );
+        assert_eq!(
+            kinds("labor/overtime.md"),
+            [
+                (KbIssueKind::InvalidFrontmatter, Some(2)),
+                (KbIssueKind::IncompleteFrontmatter, Some(1)),
+                (KbIssueKind::HeadingStructure, Some(4)),
+                (KbIssueKind::HeadingStructure, Some(5)),
+            ]
+        );
+        assert_eq!(
+            kinds("family/divorce.md"),
+            [
+                (KbIssueKind::IncompleteFrontmatter, Some(1)),
+                (KbIssueKind::DuplicateTitle, None),
+            ]
+        );
+        assert_eq!(
+            kinds("family/gbk.md"),
+            [(KbIssueKind::UnsupportedEncoding, Some(1))]
+        );
+        assert_eq!(kinds("traffic"), [(KbIssueKind::EmptyScenario, None)]);
+        assert_eq!(report.errors, 3);
+        assert_eq!(report.warnings, 6);
+        assert!(report.files[0]
+            .issues
+            .iter()
+            .all(|issue| !issue.message.is_empty()));
+        assert!(report
+            .files
+            .iter()
+            .flat_map(|file| &file.issues)
+            .filter(|issue| issue.kind == KbIssueKind::EmptyScenario)
+            .all(|issue| issue.severity == KbIssueSeverity::Error));
+    }
+}
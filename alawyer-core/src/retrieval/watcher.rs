@@ -0,0 +1,124 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::error::{CoreError, CoreResult};
+
+/// Quiet period before a burst of file events is reported. Editors often
+/// write a file several times (temp file, rename, metadata) per save.
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Watches the KB directory and reports changed markdown files, debounced.
+/// Dropping the watcher stops the notification thread.
+pub struct KbWatcher {
+    _watcher: RecommendedWatcher,
+}
+
+impl KbWatcher {
+    /// `on_change` receives the `/`-separated paths (relative to `kb_root`) of
+    /// markdown files created, modified or removed since the last call.
+    pub fn start<F>(kb_root: &Path, on_change: F) -> CoreResult<Self>
+    where
+        F: Fn(Vec<String>) + Send + 'static,
+    {
+        // Event paths are absolute and canonical on some platforms.
+        let root = kb_root
+            .canonicalize()
+            .map_err(|e| CoreError::Config(format!("failed to watch kb_path: {e}")))?;
+        let (tx, rx) = mpsc::channel::<notify::Result<Event>>();
+        let mut watcher = notify::recommended_watcher(tx)
+            .map_err(|e| CoreError::Config(format!("failed to watch kb_path: {e}")))?;
+        watcher
+            .watch(&root, RecursiveMode::Recursive)
+            .map_err(|e| CoreError::Config(format!("failed to watch kb_path: {e}")))?;
+
+        thread::spawn(move || {
+            // Ends when the watcher, and with it the sender, is dropped.
+            while let Ok(first) = rx.recv() {
+                let mut changed = BTreeSet::new();
+                collect_paths(&root, first, &mut changed);
+                loop {
+                    match rx.recv_timeout(DEBOUNCE) {
+                        Ok(event) => collect_paths(&root, event, &mut changed),
+                        Err(mpsc::RecvTimeoutError::Timeout) => break,
+                        Err(mpsc::RecvTimeoutError::Disconnected) => return,
+                    }
+                }
+                if !changed.is_empty() {
+                    on_change(changed.into_iter().collect());
+                }
+            }
+        });
+
+        Ok(Self { _watcher: watcher })
+    }
+}
+
+fn collect_paths(root: &Path, event: notify::Result<Event>, changed: &mut BTreeSet<String>) {
+    let event = match event {
+        Ok(event) => event,
+        Err(err) => {
+            tracing::warn!("kb watcher error: {err}");
+            return;
+        }
+    };
+    if event.kind.is_access() {
+        return;
+    }
+
+    for path in event.paths {
+        if let Some(relative) = relative_markdown_path(root, &path) {
+            changed.insert(relative);
+        }
+    }
+}
+
+fn relative_markdown_path(root: &Path, path: &Path) -> Option<String> {
+    let is_markdown = path
+        .extension()
+        .map(|ext| ext.eq_ignore_ascii_case("md"))
+        .unwrap_or(false);
+    if !is_markdown {
+        return None;
+    }
+
+    let relative = path.strip_prefix(root).ok()?;
+    Some(relative.to_string_lossy().replace('\\', "/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    use tempfile::TempDir;
+
+    use super::KbWatcher;
+
+    #[test]
+    fn markdown_changes_are_reported_once_per_burst() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let kb_root = temp_dir.path();
+        fs::create_dir_all(kb_root.join("labor")).expect("dir");
+
+        let (tx, rx) = mpsc::channel();
+        let _watcher = KbWatcher::start(kb_root, move |paths| {
+            let _ = tx.send(paths);
+        })
+        .expect("watcher");
+
+        fs::write(kb_root.join("labor/law.md"), "# 劳动法").expect("write");
+        fs::write(kb_root.join("labor/law.md"), "# 劳动法\n第一条").expect("rewrite");
+        fs::write(kb_root.join("notes.txt"), "ignored").expect("write");
+
+        let paths = rx
+            .recv_timeout(Duration::from_secs(10))
+            .expect("change reported");
+        assert_eq!(paths, vec!["labor/law.md".to_owned()]);
+    }
+}
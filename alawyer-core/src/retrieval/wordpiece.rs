@@ -0,0 +1,142 @@
+//! BERT WordPiece tokenization for on-device embedding models. Only the
+//! `onnx` build runs it.
+#![cfg_attr(not(feature = "onnx"), allow(dead_code))]
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::error::{CoreError, CoreResult};
+
+/// Words longer than this many characters become `[UNK]`, as in BERT.
+const MAX_WORD_CHARS: usize = 100;
+
+/// Uncased WordPiece over a `vocab.txt` (one token per line, id = line
+/// number). CJK characters and punctuation are split into tokens of their
+/// own before the greedy longest-match pass.
+pub(crate) struct WordPiece {
+    vocab: HashMap<String, u32>,
+    unk: u32,
+    cls: u32,
+    sep: u32,
+}
+
+impl WordPiece {
+    pub(crate) fn load(vocab_path: &Path) -> CoreResult<Self> {
+        let raw = fs::read_to_string(vocab_path)
+            .map_err(|e| CoreError::Config(format!("read embedding vocab failed: {e}")))?;
+        Self::from_vocab(raw.lines())
+    }
+
+    fn from_vocab<'a>(tokens: impl IntoIterator<Item = &'a str>) -> CoreResult<Self> {
+        let vocab = tokens
+            .into_iter()
+            .enumerate()
+            .map(|(id, token)| (token.trim_end().to_owned(), id as u32))
+            .collect::<HashMap<_, _>>();
+        let special = |token: &str| {
+            vocab
+                .get(token)
+                .copied()
+                .ok_or_else(|| CoreError::Config(format!("embedding vocab lacks {token}")))
+        };
+        Ok(Self {
+            unk: special("[UNK]")?,
+            cls: special("[CLS]")?,
+            sep: special("[SEP]")?,
+            vocab,
+        })
+    }
+
+    /// `[CLS] tokens… [SEP]`, truncated to `max_tokens` in total.
+    pub(crate) fn encode(&self, text: &str, max_tokens: usize) -> Vec<u32> {
+        let mut ids = vec![self.cls];
+        for word in basic_tokens(&text.to_lowercase()) {
+            self.push_word_pieces(&word, &mut ids);
+        }
+        ids.truncate(max_tokens.max(2) - 1);
+        ids.push(self.sep);
+        ids
+    }
+
+    fn push_word_pieces(&self, word: &str, ids: &mut Vec<u32>) {
+        let chars = word.chars().collect::<Vec<_>>();
+        if chars.len() > MAX_WORD_CHARS {
+            ids.push(self.unk);
+            return;
+        }
+        let mut pieces = Vec::new();
+        let mut start = 0;
+        while start < chars.len() {
+            let piece = (start + 1..=chars.len()).rev().find_map(|end| {
+                let text = chars[start..end].iter().collect::<String>();
+                let key = if start == 0 {
+                    text
+                } else {
+                    format!("##{text}")
+                };
+                self.vocab.get(&key).map(|&id| (id, end))
+            });
+            let Some((id, end)) = piece else {
+                ids.push(self.unk);
+                return;
+            };
+            pieces.push(id);
+            start = end;
+        }
+        ids.extend(pieces);
+    }
+}
+
+fn basic_tokens(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    for ch in text.chars() {
+        if ch.is_whitespace() || ch.is_control() {
+            tokens.extend((!current.is_empty()).then(|| std::mem::take(&mut current)));
+        } else if is_cjk(ch) || ch.is_ascii_punctuation() || is_cjk_punctuation(ch) {
+            tokens.extend((!current.is_empty()).then(|| std::mem::take(&mut current)));
+            tokens.push(ch.to_string());
+        } else {
+            current.push(ch);
+        }
+    }
+    tokens.extend((!current.is_empty()).then_some(current));
+    tokens
+}
+
+fn is_cjk(ch: char) -> bool {
+    matches!(ch as u32,
+        0x4E00..=0x9FFF
+        | 0x3400..=0x4DBF
+        | 0x20000..=0x2A6DF
+        | 0x2A700..=0x2B73F
+        | 0x2B740..=0x2B81F
+        | 0x2B820..=0x2CEAF
+        | 0xF900..=0xFAFF
+        | 0x2F800..=0x2FA1F)
+}
+
+fn is_cjk_punctuation(ch: char) -> bool {
+    matches!(ch as u32, 0x3000..=0x303F | 0xFF00..=0xFF0F | 0xFF1A..=0xFF20)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WordPiece;
+
+    #[test]
+    fn splits_cjk_per_character_and_latin_into_word_pieces() {
+        let vocab = [
+            "[PAD]", "[UNK]", "[CLS]", "[SEP]", "工", "资", "，", "over", "##time", "pay",
+        ];
+        let tokenizer = WordPiece::from_vocab(vocab).expect("vocab");
+
+        assert_eq!(
+            tokenizer.encode("工资，Overtime pay 拖", 512),
+            vec![2, 4, 5, 6, 7, 8, 9, 1, 3]
+        );
+        assert_eq!(tokenizer.encode("工资，Overtime", 4), vec![2, 4, 5, 3]);
+        assert!(WordPiece::from_vocab(["[CLS]", "[SEP]"]).is_err());
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use walkdir::WalkDir;
+
+use crate::error::{CoreError, CoreResult};
+
+use super::SafetyEngine;
+
+/// Matched snippets kept per rule; hit counts are always complete.
+const MAX_SAMPLES_PER_RULE: usize = 20;
+
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct SafetyRuleSample {
+    /// Path relative to the corpus root.
+    pub file: String,
+    pub matched_text: String,
+    pub context: String,
+}
+
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct SafetyRuleStats {
+    pub rule_name: String,
+    pub category: String,
+    pub severity: String,
+    pub hit_count: u32,
+    pub file_count: u32,
+    pub samples: Vec<SafetyRuleSample>,
+}
+
+/// Result of running every rule over a corpus. Rules without hits are
+/// listed too, in engine order.
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct SafetyEvaluation {
+    pub files_scanned: u32,
+    pub files_flagged: u32,
+    pub rules: Vec<SafetyRuleStats>,
+}
+
+impl SafetyEngine {
+    /// Run each rule independently over every UTF-8 file under `root`.
+    /// Unlike `check`, later rules see the original text rather than earlier
+    /// replacements, so the counts reflect each regex on its own.
+    pub fn evaluate_corpus(&self, root: &Path) -> CoreResult<SafetyEvaluation> {
+        if !root.is_dir() {
+            return Err(CoreError::Config(format!(
+                "safety corpus {} is not a directory",
+                root.display()
+            )));
+        }
+
+        let mut rules = self
+            .rules
+            .iter()
+            .map(|rule| SafetyRuleStats {
+                rule_name: rule.name.to_owned(),
+                category: rule.category.to_owned(),
+                severity: rule.severity.as_str().to_owned(),
+                hit_count: 0,
+                file_count: 0,
+                samples: Vec::new(),
+            })
+            .collect::<Vec<_>>();
+        let mut files_scanned = 0;
+        let mut flagged = HashSet::new();
+
+        let entries = WalkDir::new(root)
+            .sort_by_file_name()
+            .into_iter()
+            .flatten()
+            .filter(|entry| entry.file_type().is_file());
+        for entry in entries {
+            // Binary or non-UTF-8 files are not samples.
+            let Ok(content) = fs::read_to_string(entry.path()) else {
+                continue;
+            };
+            files_scanned += 1;
+            let file = entry
+                .path()
+                .strip_prefix(root)
+                .unwrap_or(entry.path())
+                .to_string_lossy()
+                .replace('\\', "/");
+
+            for (rule, stats) in self.rules.iter().zip(rules.iter_mut()) {
+                let mut hit = false;
+                for m in rule.regex.find_iter(&content) {
+                    hit = true;
+                    stats.hit_count += 1;
+                    if stats.samples.len() < MAX_SAMPLES_PER_RULE {
+                        stats.samples.push(SafetyRuleSample {
+                            file: file.clone(),
+                            matched_text: m.as_str().to_owned(),
+                            context: super::context_window(&content, m.start(), m.end()),
+                        });
+                    }
+                }
+                if hit {
+                    stats.file_count += 1;
+                    flagged.insert(file.clone());
+                }
+            }
+        }
+
+        Ok(SafetyEvaluation {
+            files_scanned,
+            files_flagged: flagged.len() as u32,
+            rules,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use crate::safety::SafetyEngine;
+
+    #[test]
+    fn corpus_counts_hits_per_rule() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("replies")).expect("dir");
+        fs::write(root.join("replies/a.md"), "包赢。这个也是包赢，保证胜诉").expect("write");
+        fs::write(root.join("b.txt"), "建议咨询律师并核实最新法规").expect("write");
+        fs::write(root.join("c.bin"), [0xff, 0xfe, 0x00]).expect("write");
+
+        let evaluation = SafetyEngine::default()
+            .evaluate_corpus(root)
+            .expect("evaluate");
+
+        assert_eq!(evaluation.files_scanned, 2);
+        assert_eq!(evaluation.files_flagged, 1);
+        let must_win = evaluation
+            .rules
+            .iter()
+            .find(|rule| rule.rule_name == "must_win")
+            .expect("must_win listed");
+        assert_eq!(must_win.hit_count, 2);
+        assert_eq!(must_win.file_count, 1);
+        assert_eq!(must_win.samples[0].file, "replies/a.md");
+        let guarantee = evaluation
+            .rules
+            .iter()
+            .find(|rule| rule.rule_name == "guarantee_win")
+            .expect("guarantee_win listed");
+        assert_eq!(guarantee.hit_count, 1);
+        assert!(evaluation
+            .rules
+            .iter()
+            .any(|rule| rule.rule_name == "legal_effect" && rule.hit_count == 0));
+
+        assert!(SafetyEngine::default()
+            .evaluate_corpus(&root.join("missing"))
+            .is_err());
+    }
+}
@@ -1,29 +1,92 @@
+use std::ops::Range;
+
+use once_cell::sync::Lazy;
 use regex::Regex;
 
+pub mod evaluation;
+
+pub use evaluation::SafetyEvaluation;
+
+/// Characters kept on each side of a match in `SafetyIssue::context`.
+const CONTEXT_WINDOW_CHARS: usize = 20;
+
+/// Long digit runs (phone numbers, ID numbers, amounts) are masked in stored
+/// context windows.
+static LONG_DIGITS: Lazy<Regex> = Lazy::new(|| Regex::new(r"\d{6,}").expect("valid regex"));
+
+/// Opening and closing marks of an explicit quotation.
+const QUOTE_MARKS: [(char, char); 5] = [
+    ('“', '”'),
+    ('「', '」'),
+    ('『', '』'),
+    ('‘', '’'),
+    ('"', '"'),
+];
+/// Added after a closing quotation mark instead of rewriting a flagged
+/// phrase inside it: a boss's threat quoted in the facts has to stay as
+/// said, but must not read as our own statement.
+const QUOTE_NOTE: &str = "（当事人引述原话，非本报告观点）";
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub enum Severity {
     Critical,
     Warning,
 }
 
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Critical => "critical",
+            Self::Warning => "warning",
+        }
+    }
+}
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct SafetyIssue {
     pub rule_name: String,
+    #[serde(default)]
+    pub category: String,
     pub matched_text: String,
     pub replacement: String,
     pub severity: Severity,
+    /// Sanitized text around the match, taken before the replacement.
+    #[serde(default)]
+    pub context: String,
+    /// Inside an explicit quotation of the user's own words: left as
+    /// written with `QUOTE_NOTE` after the quotation, and `replacement`
+    /// only a suggestion.
+    #[serde(default)]
+    pub quoted: bool,
+}
+
+/// One rewrite the check made, for showing the user what changed and why.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize, uniffi::Record)]
+pub struct SafetyEdit {
+    /// `SafetyIssue::rule_name` of the rule that made the edit.
+    pub rule: String,
+    /// Empty for a note added after a quotation.
+    pub original: String,
+    pub replacement: String,
+    /// Character offset of `replacement` in `modified_content`.
+    pub offset: u32,
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct SafetyCheckResult {
     pub modified_content: String,
     pub issues: Vec<SafetyIssue>,
+    /// A critical phrase was rewritten; quoted ones do not count.
     pub has_critical: bool,
+    /// Edits in the order they were made.
+    #[serde(default)]
+    pub edits: Vec<SafetyEdit>,
 }
 
 #[derive(Debug, Clone)]
 struct SafetyRule {
     name: &'static str,
+    category: &'static str,
     regex: Regex,
     replacement: &'static str,
     severity: Severity,
@@ -40,36 +103,42 @@ impl Default for SafetyEngine {
             rules: vec![
                 SafetyRule {
                     name: "guarantee_win",
+                    category: "outcome_promise",
                     regex: Regex::new(r"(?i)(保证.*胜诉|肯定.*赢)").expect("valid regex"),
                     replacement: "无法保证案件结果",
                     severity: Severity::Critical,
                 },
                 SafetyRule {
                     name: "fake_lawyer_identity",
+                    category: "identity",
                     regex: Regex::new(r"(?i)(我是律师|本律师|根据律师意见)").expect("valid regex"),
                     replacement: "本回答由AI生成",
                     severity: Severity::Critical,
                 },
                 SafetyRule {
                     name: "absolute_certainty",
+                    category: "certainty",
                     regex: Regex::new(r"(?i)(绝对没问题|肯定没事|一定行)").expect("valid regex"),
                     replacement: "存在不确定性",
                     severity: Severity::Warning,
                 },
                 SafetyRule {
                     name: "must_win",
+                    category: "outcome_promise",
                     regex: Regex::new(r"(?i)(包赢|必赢|必胜|一定.*赢)").expect("valid regex"),
                     replacement: "结果不确定",
                     severity: Severity::Critical,
                 },
                 SafetyRule {
                     name: "crime_judgement",
+                    category: "criminal_judgement",
                     regex: Regex::new(r"(?i)(你构成.*罪|你.*坐牢|你.*犯罪)").expect("valid regex"),
                     replacement: "建议咨询专业律师",
                     severity: Severity::Critical,
                 },
                 SafetyRule {
                     name: "legal_effect",
+                    category: "legal_effect",
                     regex: Regex::new(r"(?i)(具有法律效力|法律上有效)").expect("valid regex"),
                     replacement: "需执业律师确认效力",
                     severity: Severity::Warning,
@@ -80,35 +149,136 @@ impl Default for SafetyEngine {
 }
 
 impl SafetyEngine {
+    /// Rewrite flagged phrases rule by rule; a phrase is never matched
+    /// across a quotation. Nothing is taken as the user's own words, so
+    /// quoted phrases are rewritten too; see `check_quoting`.
     pub fn check(&self, content: &str) -> SafetyCheckResult {
+        self.check_quoting(content, &[])
+    }
+
+    /// `check`, keeping phrases inside an explicit quotation of the user's
+    /// own words: a quotation whose text is part of one of `user_text`,
+    /// such as the intake answers a facts summary is built from. Those are
+    /// flagged but kept, with `QUOTE_NOTE` after the quotation. Any other
+    /// quotation, e.g. a model putting its own promise in quotation marks,
+    /// is rewritten like the text around it.
+    pub fn check_quoting(&self, content: &str, user_text: &[&str]) -> SafetyCheckResult {
+        let from_user = |quote: &str| {
+            let quote = quote.trim();
+            !quote.is_empty() && user_text.iter().any(|text| text.contains(quote))
+        };
         let mut current = content.to_owned();
         let mut issues = Vec::new();
+        // Edits with the byte offset of their replacement in `current`.
+        let mut edits: Vec<(usize, SafetyEdit)> = Vec::new();
 
         for rule in &self.rules {
-            let mut matched = false;
-            for m in rule.regex.find_iter(&current) {
-                matched = true;
+            let quotes = quotations(&current);
+            let masked = mask_quotations(&current, &quotes);
+            let outside = rule.regex.find_iter(&masked).map(|m| (m.start(), m.end()));
+            let inside = quotes.iter().flat_map(|quote| {
+                rule.regex
+                    .find_iter(&current[quote.inner.clone()])
+                    .map(|m| (quote.inner.start + m.start(), quote.inner.start + m.end()))
+            });
+            let mut matches = outside.chain(inside).collect::<Vec<_>>();
+            if matches.is_empty() {
+                continue;
+            }
+            matches.sort_unstable();
+
+            // `(start, end, replacement)` of each rewrite; a note is an
+            // empty range at the end of its quotation.
+            let mut rewrites: Vec<(usize, usize, &str)> = Vec::new();
+            for (start, end) in matches {
+                let quote = quotes
+                    .iter()
+                    .find(|quote| quote.inner.start <= start && end <= quote.inner.end)
+                    .filter(|quote| from_user(&current[quote.inner.clone()]));
                 issues.push(SafetyIssue {
                     rule_name: rule.name.to_owned(),
-                    matched_text: m.as_str().to_owned(),
+                    category: rule.category.to_owned(),
+                    matched_text: current[start..end].to_owned(),
                     replacement: rule.replacement.to_owned(),
                     severity: rule.severity,
+                    context: context_window(&current, start, end),
+                    quoted: quote.is_some(),
                 });
+                match quote {
+                    Some(quote) => {
+                        let noted = current[quote.end..].starts_with(QUOTE_NOTE)
+                            || rewrites.iter().any(|&(at, _, _)| at == quote.end);
+                        if !noted {
+                            rewrites.push((quote.end, quote.end, QUOTE_NOTE));
+                        }
+                    }
+                    None => rewrites.push((start, end, rule.replacement)),
+                }
+            }
+            // A rewrite that swallows a closing mark takes the quotation
+            // with it; its note has nothing left to follow.
+            let replaced = rewrites
+                .iter()
+                .filter(|(start, end, _)| start < end)
+                .map(|&(start, end, _)| start..end)
+                .collect::<Vec<_>>();
+            rewrites.retain(|&(at, end, _)| {
+                at < end
+                    || !replaced
+                        .iter()
+                        .any(|range| range.start < at && at < range.end)
+            });
+            rewrites.sort_by_key(|&(start, end, _)| (start, end));
+
+            let mut rewritten = String::with_capacity(current.len());
+            let mut copied = 0;
+            // `(start, end)` of each rewrite, where its replacement lands
+            // and how long it is.
+            let mut moved = Vec::with_capacity(rewrites.len());
+            for &(start, end, replacement) in &rewrites {
+                rewritten.push_str(&current[copied..start]);
+                moved.push((start, end, rewritten.len(), replacement.len()));
+                rewritten.push_str(replacement);
+                copied = end;
             }
+            rewritten.push_str(&current[copied..]);
 
-            if matched {
-                current = rule
-                    .regex
-                    .replace_all(&current, rule.replacement)
-                    .to_string();
+            // Earlier edits move with the text around them; one inside a
+            // match now points at that match's replacement.
+            for (offset, _) in &mut edits {
+                *offset = match moved.iter().rfind(|(start, _, _, _)| start <= offset) {
+                    Some(&(_, end, new_start, _)) if *offset < end => new_start,
+                    Some(&(_, end, new_start, len)) => new_start + len + *offset - end,
+                    None => *offset,
+                };
             }
+            for ((start, end, replacement), (_, _, new_start, _)) in rewrites.into_iter().zip(moved)
+            {
+                edits.push((
+                    new_start,
+                    SafetyEdit {
+                        rule: rule.name.to_owned(),
+                        original: current[start..end].to_owned(),
+                        replacement: replacement.to_owned(),
+                        offset: 0,
+                    },
+                ));
+            }
+            current = rewritten;
         }
 
         let has_critical = issues
             .iter()
-            .any(|issue| issue.severity == Severity::Critical);
+            .any(|issue| issue.severity == Severity::Critical && !issue.quoted);
 
         SafetyCheckResult {
+            edits: edits
+                .into_iter()
+                .map(|(offset, edit)| SafetyEdit {
+                    offset: current[..offset].chars().count() as u32,
+                    ..edit
+                })
+                .collect(),
             modified_content: current,
             issues,
             has_critical,
@@ -116,6 +286,66 @@ impl SafetyEngine {
     }
 }
 
+/// One explicit quotation: the byte range between its marks, and the byte
+/// offset just past the closing mark.
+struct Quotation {
+    inner: Range<usize>,
+    end: usize,
+}
+
+/// Quotations in `content`, outermost only. An unclosed mark quotes
+/// nothing.
+fn quotations(content: &str) -> Vec<Quotation> {
+    let mut found = Vec::new();
+    let mut open: Option<(char, usize)> = None;
+    for (at, ch) in content.char_indices() {
+        match open {
+            Some((close, start)) if ch == close => {
+                found.push(Quotation {
+                    inner: start..at,
+                    end: at + ch.len_utf8(),
+                });
+                open = None;
+            }
+            Some(_) => {}
+            None => {
+                if let Some(&(_, close)) = QUOTE_MARKS.iter().find(|(mark, _)| *mark == ch) {
+                    open = Some((close, at + ch.len_utf8()));
+                }
+            }
+        }
+    }
+    found
+}
+
+/// `content` with each quotation's inside blanked out byte for byte with
+/// line breaks, which no rule matches across.
+fn mask_quotations(content: &str, quotes: &[Quotation]) -> String {
+    let mut masked = String::with_capacity(content.len());
+    let mut copied = 0;
+    for quote in quotes {
+        masked.push_str(&content[copied..quote.inner.start]);
+        masked.extend(std::iter::repeat_n('\n', quote.inner.len()));
+        copied = quote.inner.end;
+    }
+    masked.push_str(&content[copied..]);
+    masked
+}
+
+fn context_window(content: &str, start: usize, end: usize) -> String {
+    let before = content[..start].chars().collect::<Vec<_>>();
+    let after = content[end..].chars().take(CONTEXT_WINDOW_CHARS);
+
+    let window = before[before.len().saturating_sub(CONTEXT_WINDOW_CHARS)..]
+        .iter()
+        .copied()
+        .chain(content[start..end].chars())
+        .chain(after)
+        .map(|ch| if ch.is_whitespace() { ' ' } else { ch })
+        .collect::<String>();
+    LONG_DIGITS.replace_all(&window, "***").into_owned()
+}
+
 #[cfg(test)]
 mod tests {
     use super::{SafetyEngine, Severity};
@@ -187,6 +417,118 @@ mod tests {
         assert!(result.modified_content.contains("建议咨询专业律师"));
     }
 
+    #[test]
+    fn edits_point_at_their_replacement_in_the_result() {
+        let engine = SafetyEngine::default();
+        let result = engine.check("我是律师。绝对没问题，这份协议具有法律效力。");
+        assert_eq!(
+            result.modified_content,
+            "本回答由AI生成。存在不确定性，这份协议需执业律师确认效力。"
+        );
+        let chars = result.modified_content.chars().collect::<Vec<_>>();
+        let found = result
+            .edits
+            .iter()
+            .map(|edit| {
+                let start = edit.offset as usize;
+                let text = chars[start..start + edit.replacement.chars().count()]
+                    .iter()
+                    .collect::<String>();
+                (edit.rule.as_str(), edit.original.as_str(), text)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            [
+                (
+                    "fake_lawyer_identity",
+                    "我是律师",
+                    "本回答由AI生成".to_owned()
+                ),
+                (
+                    "absolute_certainty",
+                    "绝对没问题",
+                    "存在不确定性".to_owned()
+                ),
+                (
+                    "legal_effect",
+                    "具有法律效力",
+                    "需执业律师确认效力".to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn quoted_phrases_are_flagged_but_kept() {
+        let engine = SafetyEngine::default();
+        let answers = [
+            "他原话是：你去告也肯定赢不了，我保证你胜诉不了",
+            "微信截图：包赢的官司你也打不赢",
+        ];
+        let result = engine.check_quoting(
+            "老板说“你去告也肯定赢不了，我保证你胜诉不了”。但我是律师，你必胜。",
+            &answers,
+        );
+        assert_eq!(
+            result.modified_content,
+            "老板说“你去告也肯定赢不了，我保证你胜诉不了”（当事人引述原话，非本报告观点）。\
+             但本回答由AI生成，你结果不确定。"
+        );
+        let flagged = result
+            .issues
+            .iter()
+            .map(|issue| (issue.rule_name.as_str(), issue.quoted))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            flagged,
+            [
+                ("guarantee_win", true),
+                ("guarantee_win", true),
+                ("fake_lawyer_identity", false),
+                ("must_win", false),
+            ]
+        );
+        assert!(result.has_critical);
+        let note = &result.edits[0];
+        assert_eq!(
+            (note.rule.as_str(), note.original.as_str()),
+            ("guarantee_win", "")
+        );
+        let chars = result.modified_content.chars().collect::<Vec<_>>();
+        let at = note.offset as usize;
+        assert_eq!(
+            chars[at..at + note.replacement.chars().count()]
+                .iter()
+                .collect::<String>(),
+            note.replacement
+        );
+
+        let quoted_only = engine.check_quoting("对方在微信里说「包赢的官司你也打不赢」", &answers);
+        assert!(!quoted_only.has_critical);
+        assert_eq!(quoted_only.edits.len(), 1);
+        assert!(quoted_only
+            .modified_content
+            .contains("「包赢的官司你也打不赢」（"));
+    }
+
+    #[test]
+    fn quotations_not_from_the_user_are_rewritten() {
+        let engine = SafetyEngine::default();
+        let answers = ["老板说我去告也赢不了"];
+        let result = engine.check_quoting("本报告认为“保证胜诉”，且“我是律师”。", &answers);
+        assert_eq!(
+            result.modified_content,
+            "本报告认为“无法保证案件结果”，且“本回答由AI生成”。"
+        );
+        assert!(result.issues.iter().all(|issue| !issue.quoted));
+        assert!(result.has_critical);
+        assert_eq!(
+            engine.check("对方说「包赢」").modified_content,
+            "对方说「结果不确定」"
+        );
+    }
+
     #[test]
     fn normal_expression_not_blocked() {
         let engine = SafetyEngine::default();
@@ -196,6 +538,18 @@ mod tests {
         assert!(result.issues.is_empty());
     }
 
+    #[test]
+    fn issue_context_is_windowed_and_masked() {
+        let engine = SafetyEngine::default();
+        let result = engine.check("请联系13800138000，这个案子包赢\n不用担心");
+        let issue = &result.issues[0];
+        assert_eq!(issue.category, "outcome_promise");
+        assert!(issue.context.contains("包赢"));
+        assert!(issue.context.contains("***"));
+        assert!(!issue.context.contains("13800138000"));
+        assert!(!issue.context.contains('\n'));
+    }
+
     #[test]
     fn mixed_text_detects_multiple_rules() {
         let engine = SafetyEngine::default();
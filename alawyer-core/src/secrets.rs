@@ -0,0 +1,96 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::error::{CoreError, CoreResult};
+
+/// Secret name the model connector asks the host for when
+/// `ModelConfig.api_key` is left empty.
+pub const MODEL_API_KEY_SECRET: &str = "model_api_key";
+/// Secret the message content key is derived from when
+/// `CoreConfig.encrypt_message_content` is on. Changing it leaves content
+/// sealed under the old value unreadable.
+pub const MESSAGE_CONTENT_KEY_SECRET: &str = "message_content_key";
+
+/// Host-side secret store (Keychain, Android Keystore, ...). The core asks for
+/// a secret each time it needs one and never writes the value to storage.
+#[uniffi::export(callback_interface)]
+pub trait SecretProvider: Send + Sync {
+    fn get_secret(&self, name: String) -> Option<String>;
+}
+
+/// Where an API key comes from. `Provided` keys are looked up per request, so
+/// a rotated key takes effect on the next call without reconfiguring.
+#[derive(Clone)]
+pub enum ApiKey {
+    Static(String),
+    Provided {
+        provider: Arc<dyn SecretProvider>,
+        name: String,
+    },
+}
+
+impl ApiKey {
+    pub fn resolve(&self) -> CoreResult<String> {
+        let value = match self {
+            Self::Static(value) => value.clone(),
+            Self::Provided { provider, name } => provider
+                .get_secret(name.clone())
+                .ok_or_else(|| CoreError::Config(format!("secret {name} not available")))?,
+        };
+
+        if value.trim().is_empty() {
+            return Err(CoreError::Config("OpenRouter API key is empty".to_owned()));
+        }
+        Ok(value)
+    }
+}
+
+impl fmt::Debug for ApiKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Static(_) => f.write_str("ApiKey::Static(<redacted>)"),
+            Self::Provided { name, .. } => write!(f, "ApiKey::Provided({name})"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::{ApiKey, SecretProvider};
+
+    struct RotatingProvider {
+        value: Mutex<Option<String>>,
+    }
+
+    impl SecretProvider for RotatingProvider {
+        fn get_secret(&self, _name: String) -> Option<String> {
+            self.value.lock().expect("lock").clone()
+        }
+    }
+
+    #[test]
+    fn provided_key_is_resolved_on_each_call() {
+        let provider = Arc::new(RotatingProvider {
+            value: Mutex::new(Some("key-1".to_owned())),
+        });
+        let key = ApiKey::Provided {
+            provider: provider.clone(),
+            name: "model_api_key".to_owned(),
+        };
+        assert_eq!(key.resolve().expect("resolve"), "key-1");
+
+        *provider.value.lock().expect("lock") = Some("key-2".to_owned());
+        assert_eq!(key.resolve().expect("resolve"), "key-2");
+
+        *provider.value.lock().expect("lock") = None;
+        assert!(key.resolve().is_err());
+    }
+
+    #[test]
+    fn debug_output_redacts_static_key() {
+        let key = ApiKey::Static("sk-secret".to_owned());
+        assert!(!format!("{key:?}").contains("sk-secret"));
+    }
+}
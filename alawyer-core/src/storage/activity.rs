@@ -0,0 +1,118 @@
+//! A session's history as one chronological feed: its messages, the tool
+//! calls and phases of its tasks, safety incidents and status changes. All
+//! of it is read back from storage, so the feed after a restart is the
+//! feed the host saw live.
+//!
+//! Items are ordered by time; within a second messages come first, then
+//! task traces, incidents and status changes, each in the order written.
+//! A page ends in a cursor the next page continues after.
+
+use crate::error::{CoreError, CoreResult};
+
+use super::Message;
+
+/// Task trace kinds shown in the feed; timings, confidence and the like
+/// are diagnostics.
+pub(crate) const TRACE_ACTIVITY_KINDS: [&str; 3] = ["phase", "tool_call", "tool_skipped"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, uniffi::Enum)]
+pub enum ActivityKind {
+    Message,
+    ToolCall,
+    ToolSkipped,
+    Phase,
+    SafetyIncident,
+    StatusChange,
+}
+
+impl ActivityKind {
+    pub(crate) fn from_trace_kind(kind: &str) -> Option<Self> {
+        match kind {
+            "phase" => Some(Self::Phase),
+            "tool_call" => Some(Self::ToolCall),
+            "tool_skipped" => Some(Self::ToolSkipped),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, uniffi::Record)]
+pub struct ActivityItem {
+    /// Stable across pages, e.g. `message:{id}` or `trace:{row}`.
+    pub id: String,
+    pub kind: ActivityKind,
+    pub created_at: i64,
+    pub task_id: Option<String>,
+    /// Set for `Message` items.
+    pub message: Option<Message>,
+    /// JSON details: the trace data of tool calls and phases,
+    /// `{rule_name, category, severity, context}` of incidents and
+    /// `{status}` of status changes; `{}` for messages.
+    pub data: String,
+}
+
+#[derive(Debug, Clone, uniffi::Record)]
+pub struct ActivityPage {
+    pub items: Vec<ActivityItem>,
+    /// Pass back for the next page; `None` on the last one.
+    pub next_cursor: Option<String>,
+}
+
+/// Where an item sits in the feed: its time, then its source, then its row
+/// in that source's table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct ActivityCursor {
+    pub(crate) created_at: i64,
+    pub(crate) source: ActivitySource,
+    pub(crate) row: i64,
+}
+
+/// The tables the feed reads, in their order within a second.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum ActivitySource {
+    Message,
+    Trace,
+    Incident,
+    Status,
+}
+
+impl ActivitySource {
+    const ALL: [Self; 4] = [Self::Message, Self::Trace, Self::Incident, Self::Status];
+}
+
+impl ActivityCursor {
+    pub(crate) fn parse(raw: &str) -> CoreResult<Self> {
+        let invalid = || CoreError::Config(format!("invalid activity cursor: {raw}"));
+        let mut parts = raw.split(':');
+        let (Some(created_at), Some(source), Some(row), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+        let source = source
+            .parse::<usize>()
+            .ok()
+            .and_then(|source| ActivitySource::ALL.get(source).copied())
+            .ok_or_else(invalid)?;
+        Ok(Self {
+            created_at: created_at.parse().map_err(|_| invalid())?,
+            source,
+            row: row.parse().map_err(|_| invalid())?,
+        })
+    }
+
+    pub(crate) fn encode(&self) -> String {
+        format!("{}:{}:{}", self.created_at, self.source as usize, self.row)
+    }
+
+    /// The `(created_at, rowid)` rows of `source` must be above for their
+    /// items to come after this cursor.
+    pub(crate) fn lower_bound(&self, source: ActivitySource) -> (i64, i64) {
+        let row = match source.cmp(&self.source) {
+            std::cmp::Ordering::Less => i64::MAX,
+            std::cmp::Ordering::Equal => self.row,
+            std::cmp::Ordering::Greater => i64::MIN,
+        };
+        (self.created_at, row)
+    }
+}
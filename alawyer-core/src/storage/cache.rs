@@ -0,0 +1,137 @@
+//! Read-through cache of the settings every tool run and drafting step
+//! reads: stored tool permissions and a few hot keys. Without it each tool
+//! call queued on the connection lock behind drafting writes just to learn
+//! that nothing changed.
+//!
+//! Entries are filled and dropped while the connection lock is held, so a
+//! fill cannot race a write. Writes drop their keys, bulk deletes their
+//! prefix, and a rolled back transaction everything; nothing is filled
+//! while a transaction is open, so only committed values are cached.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Keys cached outright: switches and seeded tool permissions read on
+/// every tool run and copy overrides read for every message.
+const HOT_SETTING_PREFIXES: [&str; 5] = [
+    "analytics:",
+    "query_log:",
+    "copy:",
+    "debug:",
+    "permissions:",
+];
+/// Per-session preferences, `session:{id}:pref:{key}`, read throughout
+/// drafting.
+const SESSION_PREFERENCE_MARK: &str = ":pref:";
+
+/// Hits and misses since the storage was opened.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReadCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: u32,
+}
+
+#[derive(Default)]
+struct Entries {
+    /// `None` caches that the key is unset.
+    settings: HashMap<String, Option<String>>,
+    permissions: HashMap<String, Option<String>>,
+}
+
+#[derive(Default)]
+pub(crate) struct ReadCache {
+    entries: RwLock<Entries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl ReadCache {
+    /// Whether reads of `key` go through the cache. Keys of other profiles
+    /// are matched without their `profile:{id}:` namespace.
+    pub(crate) fn is_hot(key: &str) -> bool {
+        let key = key
+            .strip_prefix("profile:")
+            .and_then(|rest| rest.split_once(':'))
+            .map_or(key, |(_, key)| key);
+        HOT_SETTING_PREFIXES
+            .iter()
+            .any(|prefix| key.starts_with(prefix))
+            || (key.starts_with("session:") && key.contains(SESSION_PREFERENCE_MARK))
+    }
+
+    pub(crate) fn setting(&self, key: &str) -> Option<Option<String>> {
+        self.count(self.read().settings.get(key).cloned())
+    }
+
+    pub(crate) fn fill_setting(&self, key: &str, value: Option<String>) {
+        self.write().settings.insert(key.to_owned(), value);
+    }
+
+    pub(crate) fn permission(&self, tool_name: &str) -> Option<Option<String>> {
+        self.count(self.read().permissions.get(tool_name).cloned())
+    }
+
+    pub(crate) fn fill_permission(&self, tool_name: &str, permission: Option<String>) {
+        self.write()
+            .permissions
+            .insert(tool_name.to_owned(), permission);
+    }
+
+    pub(crate) fn invalidate_setting(&self, key: &str) {
+        self.write().settings.remove(key);
+    }
+
+    pub(crate) fn invalidate_permission(&self, tool_name: &str) {
+        self.write().permissions.remove(tool_name);
+    }
+
+    /// Drop settings whose key starts with `prefix`; returns how many.
+    pub(crate) fn invalidate_prefix(&self, prefix: &str) -> u32 {
+        let mut entries = self.write();
+        let before = entries.settings.len();
+        entries.settings.retain(|key, _| !key.starts_with(prefix));
+        (before - entries.settings.len()) as u32
+    }
+
+    /// Drop every entry; returns how many.
+    pub(crate) fn clear(&self) -> u32 {
+        let mut entries = self.write();
+        let count = entries.settings.len() + entries.permissions.len();
+        entries.settings.clear();
+        entries.permissions.clear();
+        count as u32
+    }
+
+    pub(crate) fn stats(&self) -> ReadCacheStats {
+        let entries = self.read();
+        ReadCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: (entries.settings.len() + entries.permissions.len()) as u32,
+        }
+    }
+
+    fn count<T>(&self, cached: Option<T>) -> Option<T> {
+        let counter = if cached.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        cached
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, Entries> {
+        self.entries
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Entries> {
+        self.entries
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
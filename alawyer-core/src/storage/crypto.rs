@@ -0,0 +1,159 @@
+//! Column-level encryption of message content, for deployments that do not
+//! want to rely on the OS disk encryption alone.
+//!
+//! Content is sealed with AES-256-GCM under a key derived from the host
+//! secret `MESSAGE_CONTENT_KEY_SECRET`, with the message id as associated
+//! data so a sealed value cannot be moved to another row. Sealed values are
+//! stored as `sealed:v1:<hex nonce and ciphertext>`; anything else is read
+//! as plaintext, so messages written before encryption was turned on stay
+//! readable until they are sealed.
+
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::hkdf::{Salt, HKDF_SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::error::{CoreError, CoreResult};
+
+pub(crate) const SEALED_PREFIX: &str = "sealed:v1:";
+const KEY_SALT: &[u8] = b"alawyer message content";
+const KEY_INFO: &[u8] = b"aes-256-gcm v1";
+/// Sealed under the key with `KEY_CHECK_AAD` and kept in settings, so a
+/// different key is caught before it seals anything.
+pub const KEY_CHECK_SETTING: &str = "storage:content_key_check";
+const KEY_CHECK_PLAINTEXT: &str = "alawyer";
+const KEY_CHECK_AAD: &str = "key-check";
+
+pub struct ContentCipher {
+    key: LessSafeKey,
+    rng: SystemRandom,
+}
+
+impl ContentCipher {
+    /// Derive the content key from `secret`, which should be a random value
+    /// of at least 32 bytes kept in the platform keystore.
+    pub fn from_secret(secret: &str) -> CoreResult<Self> {
+        if secret.trim().is_empty() {
+            return Err(CoreError::Config("message content key is empty".to_owned()));
+        }
+        let prk = Salt::new(HKDF_SHA256, KEY_SALT).extract(secret.as_bytes());
+        let okm = prk
+            .expand(&[KEY_INFO], &AES_256_GCM)
+            .map_err(|_| CoreError::Unknown("derive message content key".to_owned()))?;
+        Ok(Self {
+            key: LessSafeKey::new(UnboundKey::from(okm)),
+            rng: SystemRandom::new(),
+        })
+    }
+
+    pub fn is_sealed(stored: &str) -> bool {
+        stored.starts_with(SEALED_PREFIX)
+    }
+
+    /// `plaintext` sealed for the row identified by `row_id`.
+    pub fn seal(&self, plaintext: &str, row_id: &str) -> CoreResult<String> {
+        let mut nonce = [0u8; NONCE_LEN];
+        self.rng
+            .fill(&mut nonce)
+            .map_err(|_| CoreError::Unknown("no randomness for a nonce".to_owned()))?;
+        let mut sealed = plaintext.as_bytes().to_vec();
+        self.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(row_id.as_bytes()),
+                &mut sealed,
+            )
+            .map_err(|_| CoreError::Unknown("seal message content".to_owned()))?;
+
+        let mut stored =
+            String::with_capacity(SEALED_PREFIX.len() + 2 * (NONCE_LEN + sealed.len()));
+        stored.push_str(SEALED_PREFIX);
+        for byte in nonce.iter().chain(&sealed) {
+            stored.push_str(&format!("{byte:02x}"));
+        }
+        Ok(stored)
+    }
+
+    /// Plaintext of a value stored for `row_id`; values that are not sealed
+    /// are returned as they are.
+    pub fn open(&self, stored: &str, row_id: &str) -> CoreResult<String> {
+        let Some(encoded) = stored.strip_prefix(SEALED_PREFIX) else {
+            return Ok(stored.to_owned());
+        };
+        let unreadable =
+            || CoreError::Config("message content cannot be decrypted with this key".to_owned());
+        let mut bytes = decode_hex(encoded).ok_or_else(unreadable)?;
+        if bytes.len() < NONCE_LEN + AES_256_GCM.tag_len() {
+            return Err(unreadable());
+        }
+        let mut sealed = bytes.split_off(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(&bytes).map_err(|_| unreadable())?;
+        let plaintext = self
+            .key
+            .open_in_place(nonce, Aad::from(row_id.as_bytes()), &mut sealed)
+            .map_err(|_| unreadable())?;
+        String::from_utf8(plaintext.to_vec()).map_err(|_| unreadable())
+    }
+
+    pub fn key_check(&self) -> CoreResult<String> {
+        self.seal(KEY_CHECK_PLAINTEXT, KEY_CHECK_AAD)
+    }
+
+    /// Whether `check`, from `key_check`, was made with this key.
+    pub fn matches_key_check(&self, check: &str) -> bool {
+        self.open(check, KEY_CHECK_AAD)
+            .is_ok_and(|plaintext| plaintext == KEY_CHECK_PLAINTEXT)
+    }
+}
+
+/// Content as stored, sealed when `cipher` is set.
+pub(crate) fn seal_content(
+    cipher: Option<&ContentCipher>,
+    content: &str,
+    row_id: &str,
+) -> CoreResult<String> {
+    match cipher {
+        Some(cipher) => cipher.seal(content, row_id),
+        None => Ok(content.to_owned()),
+    }
+}
+
+fn decode_hex(encoded: &str) -> Option<Vec<u8>> {
+    if !encoded.len().is_multiple_of(2) || !encoded.is_ascii() {
+        return None;
+    }
+    (0..encoded.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(&encoded[idx..idx + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ContentCipher;
+
+    #[test]
+    fn sealed_content_opens_only_with_its_key_and_row() {
+        let cipher = ContentCipher::from_secret("0123456789abcdef0123456789abcdef").expect("key");
+        let sealed = cipher.seal("公司拖欠工资", "message-1").expect("seal");
+        assert!(ContentCipher::is_sealed(&sealed));
+        assert!(!sealed.contains("工资"));
+        assert_ne!(
+            sealed,
+            cipher.seal("公司拖欠工资", "message-1").expect("seal")
+        );
+
+        assert_eq!(
+            cipher.open(&sealed, "message-1").expect("open"),
+            "公司拖欠工资"
+        );
+        assert!(cipher.open(&sealed, "message-2").is_err());
+        assert_eq!(cipher.open("plain", "message-1").expect("open"), "plain");
+
+        let other = ContentCipher::from_secret("another key").expect("key");
+        assert!(other.open(&sealed, "message-1").is_err());
+        let check = cipher.key_check().expect("check");
+        assert!(cipher.matches_key_check(&check));
+        assert!(!other.matches_key_check(&check));
+        assert!(ContentCipher::from_secret(" ").is_err());
+    }
+}
@@ -0,0 +1,299 @@
+//! Where a core keeps its files, and the lock file that keeps a second
+//! writable core off the same database.
+
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{ErrorKind, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+use crate::error::{CoreError, CoreResult};
+
+/// Database file name inside a data directory.
+pub const DB_FILE_NAME: &str = "core.db";
+
+const INDEX_DIR: &str = "index";
+const CACHE_DIR: &str = "cache";
+const ATTACHMENTS_DIR: &str = "attachments";
+
+/// Paths of a core's data, for hosts that keep their own files alongside.
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct DataDirLayout {
+    pub root: String,
+    pub db_path: String,
+    pub index_dir: String,
+    pub cache_dir: String,
+    pub attachments_dir: String,
+    pub read_only: bool,
+}
+
+pub struct DataDir {
+    root: PathBuf,
+    db_path: PathBuf,
+    read_only: bool,
+    /// Held for the life of a writable core. The OS releases the lock when
+    /// the file closes, also when the process dies.
+    _lock: Option<File>,
+}
+
+impl DataDir {
+    /// `db_path` when set, else `core.db` in `root`.
+    pub fn resolve_db_path(root: Option<&str>, db_path: &str) -> CoreResult<PathBuf> {
+        match (root.filter(|root| !root.is_empty()), db_path.is_empty()) {
+            (_, false) => Ok(PathBuf::from(db_path)),
+            (Some(root), true) => Ok(Path::new(root).join(DB_FILE_NAME)),
+            (None, true) => Err(CoreError::Config(
+                "db_path or data_dir must be set".to_owned(),
+            )),
+        }
+    }
+
+    /// Open the data directory `root`, by default the directory of
+    /// `db_path`. A writable open creates the layout and takes the
+    /// database's lock file, failing with `InvalidState` while another
+    /// writable core holds it. A read-only open takes no lock and needs the
+    /// database to exist.
+    pub fn open(root: Option<&str>, db_path: &str, read_only: bool) -> CoreResult<Self> {
+        let db_path = Self::resolve_db_path(root, db_path)?;
+        let root = match root.filter(|root| !root.is_empty()) {
+            Some(root) => PathBuf::from(root),
+            None => db_path
+                .parent()
+                .filter(|parent| !parent.as_os_str().is_empty())
+                .map_or_else(|| PathBuf::from("."), Path::to_path_buf),
+        };
+
+        if read_only {
+            if !db_path.is_file() {
+                return Err(CoreError::Config(format!(
+                    "read-only core needs an existing database at {}",
+                    db_path.display()
+                )));
+            }
+            return Ok(Self {
+                root,
+                db_path,
+                read_only,
+                _lock: None,
+            });
+        }
+
+        let db_dir = db_path.parent().unwrap_or(&root);
+        for dir in [
+            root.clone(),
+            db_dir.to_path_buf(),
+            root.join(INDEX_DIR),
+            root.join(CACHE_DIR),
+            root.join(ATTACHMENTS_DIR),
+        ] {
+            fs::create_dir_all(&dir).map_err(|e| {
+                CoreError::Config(format!("failed to create {}: {e}", dir.display()))
+            })?;
+        }
+        let lock = lock_database(&db_path)?;
+        Ok(Self {
+            root,
+            db_path,
+            read_only,
+            _lock: Some(lock),
+        })
+    }
+
+    pub fn db_path(&self) -> &Path {
+        &self.db_path
+    }
+
+    pub fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn layout(&self) -> DataDirLayout {
+        let path = |path: &Path| path.to_string_lossy().into_owned();
+        DataDirLayout {
+            root: path(&self.root),
+            db_path: path(&self.db_path),
+            index_dir: path(&self.root.join(INDEX_DIR)),
+            cache_dir: path(&self.root.join(CACHE_DIR)),
+            attachments_dir: path(&self.root.join(ATTACHMENTS_DIR)),
+            read_only: self.read_only,
+        }
+    }
+
+    /// Files in the `attachments` and `cache` directories, which hold the
+    /// user's documents and derived data.
+    pub fn user_files(&self) -> CoreResult<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        for dir in [CACHE_DIR, ATTACHMENTS_DIR] {
+            collect_files(&self.root.join(dir), &mut files)?;
+        }
+        Ok(files)
+    }
+
+    /// Overwrite every file of `user_files` with zeros, then delete it and
+    /// the subdirectories it was in. Returns the number of files.
+    pub fn shred_user_files(&self) -> CoreResult<u32> {
+        if self.read_only {
+            return Err(CoreError::InvalidState(
+                "a read-only core cannot delete files".to_owned(),
+            ));
+        }
+        let files = self.user_files()?;
+        for path in &files {
+            shred(path).map_err(|e| {
+                CoreError::Storage(format!("failed to shred {}: {e}", path.display()))
+            })?;
+        }
+        for dir in [CACHE_DIR, ATTACHMENTS_DIR] {
+            remove_subdirs(&self.root.join(dir))?;
+        }
+        Ok(files.len() as u32)
+    }
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> CoreResult<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => {
+            return Err(CoreError::Storage(format!(
+                "failed to list {}: {e}",
+                dir.display()
+            )))
+        }
+    };
+    for entry in entries {
+        let entry = entry.map_err(|e| CoreError::Storage(e.to_string()))?;
+        let file_type = entry
+            .file_type()
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+        if file_type.is_dir() {
+            collect_files(&entry.path(), files)?;
+        } else {
+            // Symlinks count too; `shred` removes the link, not its target.
+            files.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+/// Zero the file in place and flush it before deleting it. Flash storage
+/// and copy-on-write file systems may still keep the old blocks, so this
+/// is best effort beyond the file system's own view.
+fn shred(path: &Path) -> std::io::Result<()> {
+    if fs::symlink_metadata(path)?.is_file() {
+        let mut file = OpenOptions::new().write(true).open(path)?;
+        let zeros = [0u8; 64 * 1024];
+        let mut remaining = file.metadata()?.len();
+        while remaining > 0 {
+            let chunk = remaining.min(zeros.len() as u64) as usize;
+            file.write_all(&zeros[..chunk])?;
+            remaining -= chunk as u64;
+        }
+        file.sync_all()?;
+    }
+    fs::remove_file(path)
+}
+
+fn remove_subdirs(dir: &Path) -> CoreResult<()> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Ok(());
+    };
+    for entry in entries.flatten() {
+        if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+            fs::remove_dir_all(entry.path()).map_err(|e| {
+                CoreError::Storage(format!("failed to remove {}: {e}", entry.path().display()))
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Take the exclusive lock on `{db_path}.lock` and note our pid in it, so
+/// the error a second writer gets can name the holder.
+fn lock_database(db_path: &Path) -> CoreResult<File> {
+    let mut lock_path = db_path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    let lock_path = PathBuf::from(lock_path);
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&lock_path)
+        .map_err(|e| CoreError::Storage(format!("failed to open {}: {e}", lock_path.display())))?;
+
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => {
+            let mut holder = String::new();
+            // Unreadable on platforms with mandatory locks; the pid is a hint.
+            let _ = file.read_to_string(&mut holder);
+            let holder = holder.trim();
+            let holder = if holder.is_empty() {
+                String::new()
+            } else {
+                format!(" (process {holder})")
+            };
+            return Err(CoreError::InvalidState(format!(
+                "database {} is already open in a writable core{holder}; close it or open this core read-only",
+                db_path.display()
+            )));
+        }
+        Err(TryLockError::Error(e)) => {
+            return Err(CoreError::Storage(format!(
+                "failed to lock {}: {e}",
+                lock_path.display()
+            )));
+        }
+    }
+
+    let noted = file
+        .set_len(0)
+        .and_then(|()| file.rewind())
+        .and_then(|()| write!(file, "{}", std::process::id()));
+    if let Err(err) = noted {
+        tracing::warn!("failed to note pid in {}: {err}", lock_path.display());
+    }
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::DataDir;
+    use crate::error::CoreError;
+
+    #[test]
+    fn one_writable_open_per_database() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let root = temp_dir.path().join("data");
+        let root = root.to_string_lossy();
+
+        assert!(DataDir::open(Some(&root), "", true).is_err());
+        let writer = DataDir::open(Some(&root), "", false).expect("writable");
+        let layout = writer.layout();
+        assert!(layout.db_path.ends_with("core.db"));
+        for dir in [
+            &layout.index_dir,
+            &layout.cache_dir,
+            &layout.attachments_dir,
+        ] {
+            assert!(std::path::Path::new(dir).is_dir(), "{dir}");
+        }
+
+        std::fs::write(writer.db_path(), b"").expect("create db");
+        match DataDir::open(Some(&root), "", false) {
+            Err(CoreError::InvalidState(message)) => {
+                assert!(message.contains(&format!("(process {})", std::process::id())));
+            }
+            other => panic!(
+                "expected a lock error, got {:?}",
+                other.map(|dir| dir.layout())
+            ),
+        }
+        let reader = DataDir::open(Some(&root), "", true).expect("read-only");
+        assert!(reader.read_only());
+
+        drop(writer);
+        DataDir::open(Some(&root), "", false).expect("writable after close");
+    }
+}
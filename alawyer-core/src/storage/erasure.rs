@@ -0,0 +1,39 @@
+//! "Erase all my data": the one operation behind a user's request to have
+//! everything about them deleted (GDPR / PIPL). It removes every session
+//! with its messages, facts, attachments and task traces, the logs and
+//! usage records, and the files in the `attachments` and `cache`
+//! directories. Profiles, tool permissions and app settings stay, as does
+//! the KB and its index.
+//!
+//! Erasing takes a confirmation token from `preview_delete_all_user_data`,
+//! so a host cannot wipe the data with a single stray call.
+
+/// How long a confirmation token stays valid.
+pub const CONFIRM_TOKEN_TTL_SECS: i64 = 300;
+
+/// What an erasure removed, or — for a preview — what it would remove.
+#[derive(Debug, Clone, Default, PartialEq, Eq, uniffi::Record)]
+pub struct ErasureSummary {
+    pub session_count: u32,
+    pub message_count: u32,
+    pub attachment_count: u32,
+    /// Intake answers.
+    pub fact_count: u32,
+    pub log_count: u32,
+    /// Task trace entries, which record each task's model and tool calls.
+    pub trace_count: u32,
+    /// Query log entries, safety incidents, session status changes,
+    /// recorded events and usage counters.
+    pub record_count: u32,
+    /// Files in the `attachments` and `cache` directories.
+    pub file_count: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct ErasurePreview {
+    pub summary: ErasureSummary,
+    /// Pass to `delete_all_user_data` to go ahead. Single use; a newer
+    /// preview replaces it.
+    pub confirm_token: String,
+    pub expires_at: i64,
+}
@@ -1,3 +1,20 @@
+pub mod activity;
+pub mod cache;
+pub mod crypto;
+pub mod data_dir;
+pub mod erasure;
+pub mod monitor;
+pub mod retention;
 pub mod sqlite;
 
-pub use sqlite::{LogEntry, Message, Session, SqliteStorage};
+pub use activity::ActivityPage;
+pub use crypto::ContentCipher;
+pub use data_dir::{DataDir, DataDirLayout};
+pub use erasure::{ErasurePreview, ErasureSummary};
+pub use retention::{PurgeSummary, RetentionPolicy};
+pub use sqlite::{default_permission_for_tool, thread_messages};
+pub use sqlite::{
+    Attachment, CaseMetadata, EventFilter, LogEntry, Message, NewMessage, Profile, QueryLogEntry,
+    SafetyIncident, SafetyIncidentFilter, Session, SessionListOptions, SessionSearchHit, Setting,
+    SqliteStorage, TaskTraceEntry, DEFAULT_PROFILE_ID,
+};
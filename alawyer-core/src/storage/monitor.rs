@@ -0,0 +1,161 @@
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// SQLite virtual machine steps between progress-handler deadline checks.
+pub(crate) const PROGRESS_CHECK_OPS: i32 = 10_000;
+
+/// Longest a fingerprint is kept; long `IN (...)` lists add nothing.
+const FINGERPRINT_MAX_CHARS: usize = 160;
+
+static STRING_LITERAL: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"'(?:[^']|'')*'").expect("valid regex"));
+static NUMBER_LITERAL: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\??\b\d+(?:\.\d+)?\b").expect("valid regex"));
+static WHITESPACE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\s+").expect("valid regex"));
+
+thread_local! {
+    /// Monitor of the connection this thread currently holds. SQLite calls the
+    /// profile hook synchronously on the executing thread, and the hook is a
+    /// plain `fn`, so this is how it finds its storage.
+    static ACTIVE: RefCell<Option<Arc<QueryMonitor>>> = const { RefCell::new(None) };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueryLimits {
+    /// How long one storage call may keep SQLite busy before it is
+    /// interrupted; also the busy timeout for a locked database file.
+    pub timeout: Duration,
+    /// Statements running at least this long are logged and counted.
+    pub slow_threshold: Duration,
+}
+
+impl Default for QueryLimits {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(5),
+            slow_threshold: Duration::from_millis(200),
+        }
+    }
+}
+
+/// Slow-query counters since the storage was opened.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SlowQueryStats {
+    pub slow_queries: u64,
+    pub slowest_ms: u64,
+    pub slowest_fingerprint: Option<String>,
+    pub interrupted: u64,
+}
+
+pub(crate) struct QueryMonitor {
+    limits: Mutex<QueryLimits>,
+    epoch: Instant,
+    /// Nanoseconds after `epoch` at which the running call is interrupted;
+    /// zero while no call holds the connection.
+    deadline: AtomicU64,
+    stats: Mutex<SlowQueryStats>,
+}
+
+impl QueryMonitor {
+    pub(crate) fn new(limits: QueryLimits) -> Self {
+        Self {
+            limits: Mutex::new(limits),
+            epoch: Instant::now(),
+            deadline: AtomicU64::new(0),
+            stats: Mutex::new(SlowQueryStats::default()),
+        }
+    }
+
+    pub(crate) fn limits(&self) -> QueryLimits {
+        *self
+            .limits
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub(crate) fn set_limits(&self, limits: QueryLimits) {
+        *self
+            .limits
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = limits;
+    }
+
+    pub(crate) fn stats(&self) -> SlowQueryStats {
+        self.stats
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    /// Start the timeout clock for a storage call on this thread.
+    pub(crate) fn begin(self: &Arc<Self>) {
+        let deadline = self.epoch.elapsed() + self.limits().timeout;
+        self.deadline
+            .store(deadline.as_nanos().max(1) as u64, Ordering::Relaxed);
+        ACTIVE.with(|active| *active.borrow_mut() = Some(self.clone()));
+    }
+
+    pub(crate) fn end(&self) {
+        self.deadline.store(0, Ordering::Relaxed);
+        ACTIVE.with(|active| *active.borrow_mut() = None);
+    }
+
+    /// Progress handler body: `true` interrupts the running statement.
+    pub(crate) fn past_deadline(&self) -> bool {
+        let deadline = self.deadline.load(Ordering::Relaxed);
+        if deadline == 0 || (self.epoch.elapsed().as_nanos() as u64) < deadline {
+            return false;
+        }
+        self.stats
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .interrupted += 1;
+        true
+    }
+
+    fn record(&self, sql: &str, duration: Duration) {
+        if duration < self.limits().slow_threshold {
+            return;
+        }
+        let fingerprint = fingerprint(sql);
+        let duration_ms = duration.as_millis() as u64;
+        tracing::warn!(duration_ms, fingerprint = %fingerprint, "slow sqlite query");
+
+        let mut stats = self
+            .stats
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        stats.slow_queries += 1;
+        if stats.slowest_fingerprint.is_none() || duration_ms > stats.slowest_ms {
+            stats.slowest_ms = duration_ms;
+            stats.slowest_fingerprint = Some(fingerprint);
+        }
+    }
+}
+
+/// Profile hook registered on the connection.
+pub(crate) fn profile(sql: &str, duration: Duration) {
+    ACTIVE.with(|active| {
+        if let Some(monitor) = active.borrow().as_ref() {
+            monitor.record(sql, duration);
+        }
+    });
+}
+
+/// Statement text with literals and numbered placeholders replaced by `?` and
+/// whitespace collapsed, so
+/// the same query with different values groups together in logs.
+pub fn fingerprint(sql: &str) -> String {
+    let sql = STRING_LITERAL.replace_all(sql, "?");
+    let sql = NUMBER_LITERAL.replace_all(&sql, "?");
+    let sql = WHITESPACE.replace_all(sql.trim(), " ");
+    match sql.char_indices().nth(FINGERPRINT_MAX_CHARS) {
+        Some((cut, _)) => format!("{}…", &sql[..cut]),
+        None => sql.into_owned(),
+    }
+}
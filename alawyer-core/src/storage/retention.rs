@@ -0,0 +1,214 @@
+use crate::error::CoreResult;
+use crate::storage::SqliteStorage;
+
+const SESSION_DAYS_KEY: &str = "retention:session_days";
+const LOG_DAYS_KEY: &str = "retention:log_days";
+const TRACE_DAYS_KEY: &str = "retention:trace_days";
+const SECONDS_PER_DAY: i64 = 86_400;
+
+/// Retention windows in days. `0` keeps data forever (the default).
+#[derive(Debug, Clone, Default, PartialEq, Eq, uniffi::Record)]
+pub struct RetentionPolicy {
+    pub session_days: u32,
+    pub log_days: u32,
+    /// Task traces: the model calls, tool calls and decisions of each agent
+    /// task. Traces of archived and pinned sessions are exempt.
+    #[uniffi(default = 0)]
+    pub trace_days: u32,
+}
+
+/// What a purge removed, or — for a preview — what it would remove.
+#[derive(Debug, Clone, Default, PartialEq, Eq, uniffi::Record)]
+pub struct PurgeSummary {
+    pub session_ids: Vec<String>,
+    pub message_count: u32,
+    pub log_count: u32,
+    pub trace_count: u32,
+}
+
+impl PurgeSummary {
+    pub fn is_empty(&self) -> bool {
+        self.session_ids.is_empty() && self.log_count == 0 && self.trace_count == 0
+    }
+}
+
+pub fn load_policy(storage: &SqliteStorage) -> CoreResult<RetentionPolicy> {
+    let read_days = |key: &str| -> CoreResult<u32> {
+        Ok(storage
+            .get_setting(key)?
+            .and_then(|raw| raw.parse::<u32>().ok())
+            .unwrap_or(0))
+    };
+
+    Ok(RetentionPolicy {
+        session_days: read_days(SESSION_DAYS_KEY)?,
+        log_days: read_days(LOG_DAYS_KEY)?,
+        trace_days: read_days(TRACE_DAYS_KEY)?,
+    })
+}
+
+pub fn save_policy(storage: &SqliteStorage, policy: &RetentionPolicy) -> CoreResult<()> {
+    storage.set_setting(SESSION_DAYS_KEY, &policy.session_days.to_string())?;
+    storage.set_setting(LOG_DAYS_KEY, &policy.log_days.to_string())?;
+    storage.set_setting(TRACE_DAYS_KEY, &policy.trace_days.to_string())
+}
+
+/// Compute what `purge` would delete at `now` without touching any data.
+/// Archived and pinned sessions are exempt.
+pub fn preview_purge(
+    storage: &SqliteStorage,
+    policy: &RetentionPolicy,
+    now: i64,
+) -> CoreResult<PurgeSummary> {
+    let mut summary = PurgeSummary::default();
+
+    if policy.session_days > 0 {
+        let cutoff = now - i64::from(policy.session_days) * SECONDS_PER_DAY;
+        for (session_id, message_count) in storage.list_expired_sessions(cutoff)? {
+            summary.session_ids.push(session_id);
+            summary.message_count += message_count;
+        }
+    }
+
+    if policy.log_days > 0 {
+        let cutoff = now - i64::from(policy.log_days) * SECONDS_PER_DAY;
+        summary.log_count = storage.count_logs_before(cutoff)?;
+    }
+
+    if policy.trace_days > 0 {
+        let cutoff = now - i64::from(policy.trace_days) * SECONDS_PER_DAY;
+        summary.trace_count = storage.count_task_traces_before(cutoff)?;
+    }
+
+    Ok(summary)
+}
+
+pub fn purge(storage: &SqliteStorage, policy: &RetentionPolicy) -> CoreResult<PurgeSummary> {
+    let now = storage.clock().timestamp();
+    let mut summary = preview_purge(storage, policy, now)?;
+
+    if !summary.session_ids.is_empty() {
+        storage.purge_sessions(&summary.session_ids)?;
+    }
+    if policy.log_days > 0 {
+        let cutoff = now - i64::from(policy.log_days) * SECONDS_PER_DAY;
+        summary.log_count = storage.purge_logs_before(cutoff)?;
+    }
+    if policy.trace_days > 0 {
+        let cutoff = now - i64::from(policy.trace_days) * SECONDS_PER_DAY;
+        summary.trace_count = storage.purge_task_traces_before(cutoff)?;
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use tempfile::TempDir;
+
+    use super::{load_policy, preview_purge, purge, save_policy, RetentionPolicy};
+    use crate::clock::{Clock, ManualClock};
+    use crate::storage::{SqliteStorage, DEFAULT_PROFILE_ID};
+
+    #[test]
+    fn purge_respects_window_and_archived_exemption() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let clock = Arc::new(ManualClock::at_timestamp(1_700_000_000));
+        let storage =
+            SqliteStorage::new(temp_dir.path().join("core.db"), clock.clone()).expect("storage");
+
+        let archived = storage
+            .create_session("labor", None, DEFAULT_PROFILE_ID)
+            .expect("session");
+        storage
+            .update_session_status(&archived.id, "archived")
+            .expect("archive");
+        let pinned = storage
+            .create_session("labor", None, DEFAULT_PROFILE_ID)
+            .expect("session");
+        storage.set_session_pinned(&pinned.id, true).expect("pin");
+        let expired = storage
+            .create_session("labor", None, DEFAULT_PROFILE_ID)
+            .expect("session");
+        storage
+            .create_message(&expired.id, "user", "hello", None, None)
+            .expect("message");
+        storage
+            .set_setting(&format!("intake:{}:idx", expired.id), "2")
+            .expect("setting");
+
+        let policy = RetentionPolicy {
+            session_days: 90,
+            log_days: 0,
+            trace_days: 0,
+        };
+        save_policy(&storage, &policy).expect("save policy");
+        assert_eq!(load_policy(&storage).expect("load policy"), policy);
+
+        // Nothing is old enough yet.
+        assert!(purge(&storage, &policy).expect("purge").is_empty());
+
+        // 91 days later only the active session has expired.
+        clock.advance(Duration::from_secs(91 * 86_400));
+        let preview = preview_purge(&storage, &policy, clock.timestamp()).expect("preview");
+        assert_eq!(preview.session_ids, vec![expired.id.clone()]);
+        assert_eq!(preview.message_count, 1);
+
+        assert_eq!(purge(&storage, &policy).expect("purge"), preview);
+        assert!(storage.get_session(&expired.id).expect("get").is_none());
+        assert!(storage.get_session(&archived.id).expect("get").is_some());
+        assert!(storage.get_session(&pinned.id).expect("get").is_some());
+        assert!(storage
+            .get_setting(&format!("intake:{}:idx", expired.id))
+            .expect("setting")
+            .is_none());
+    }
+
+    #[test]
+    fn trace_retention_spares_archived_sessions() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let clock = Arc::new(ManualClock::at_timestamp(1_700_000_000));
+        let storage =
+            SqliteStorage::new(temp_dir.path().join("core.db"), clock.clone()).expect("storage");
+
+        let archived = storage
+            .create_session("labor", None, DEFAULT_PROFILE_ID)
+            .expect("session");
+        storage
+            .update_session_status(&archived.id, "archived")
+            .expect("archive");
+        let active = storage
+            .create_session("labor", None, DEFAULT_PROFILE_ID)
+            .expect("session");
+        for session_id in [&archived.id, &active.id] {
+            storage
+                .append_task_trace("task", session_id, "model_memo_hit", "{}")
+                .expect("trace");
+        }
+
+        let policy = RetentionPolicy {
+            trace_days: 30,
+            ..RetentionPolicy::default()
+        };
+        clock.advance(Duration::from_secs(31 * 86_400));
+        let preview = preview_purge(&storage, &policy, clock.timestamp()).expect("preview");
+        assert_eq!(preview.trace_count, 1);
+        assert!(preview.session_ids.is_empty());
+
+        assert_eq!(purge(&storage, &policy).expect("purge").trace_count, 1);
+        assert!(storage
+            .list_session_traces(&active.id)
+            .expect("traces")
+            .is_empty());
+        assert_eq!(
+            storage
+                .list_session_traces(&archived.id)
+                .expect("traces")
+                .len(),
+            1
+        );
+    }
+}
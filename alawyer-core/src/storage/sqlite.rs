@@ -1,12 +1,26 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::{Deref, DerefMut};
 use std::path::Path;
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, RwLock};
+use std::thread::{self, ThreadId};
 
-use chrono::Utc;
-use rusqlite::{params, Connection, OptionalExtension};
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
 use serde_json::Value;
 use uuid::Uuid;
 
+use crate::clock::Clock;
 use crate::error::{CoreError, CoreResult};
+use crate::faults::{FaultInjector, FAULT_SETTING_PREFIX};
+use crate::CoreEvent;
+
+use super::activity::{
+    ActivityCursor, ActivityItem, ActivityKind, ActivityPage, ActivitySource, TRACE_ACTIVITY_KINDS,
+};
+use super::cache::{ReadCache, ReadCacheStats};
+use super::crypto::{seal_content, ContentCipher, KEY_CHECK_SETTING, SEALED_PREFIX};
+use super::erasure::ErasureSummary;
+use super::monitor::{self, QueryLimits, QueryMonitor, SlowQueryStats};
 
 #[derive(Debug, Clone, uniffi::Record)]
 pub struct Session {
@@ -16,6 +30,88 @@ pub struct Session {
     pub created_at: i64,
     pub updated_at: i64,
     pub status: String,
+    pub pinned: bool,
+    pub profile_id: String,
+    pub metadata: CaseMetadata,
+}
+
+/// Structured details of a case for session cards and report templates,
+/// set with `Core::set_case_metadata`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, uniffi::Record)]
+pub struct CaseMetadata {
+    #[uniffi(default = None)]
+    pub opposing_party: Option<String>,
+    /// As written, e.g. `3万元`.
+    #[uniffi(default = None)]
+    pub claim_amount: Option<String>,
+    /// `YYYY-MM-DD`.
+    #[uniffi(default = None)]
+    pub dispute_started_on: Option<String>,
+    /// Court or arbitration commission, or the city the case is heard in.
+    #[uniffi(default = None)]
+    pub jurisdiction: Option<String>,
+}
+
+/// Row counts moved by `SqliteStorage::move_session_records`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MovedRecords {
+    pub messages: u32,
+    pub attachments: u32,
+    pub logs: u32,
+}
+
+/// Task traces older than `?1`, except those of archived or pinned sessions.
+const EXPIRED_TRACE_FILTER: &str = "created_at < ?1
+     AND session_id NOT IN (SELECT id FROM sessions WHERE status = 'archived' OR pinned = 1)";
+
+/// Profile every database starts with; sessions created before profiles
+/// existed belong to it.
+pub const DEFAULT_PROFILE_ID: &str = "default";
+
+/// A person sharing the device. Sessions and host settings are kept apart
+/// per profile; see `Core::switch_profile`.
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct Profile {
+    pub id: String,
+    pub name: String,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct Setting {
+    pub key: String,
+    pub value: String,
+}
+
+/// Order of `list_sessions`. Dates sort newest first, titles A-Z with
+/// untitled sessions last.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, uniffi::Enum)]
+pub enum SessionSort {
+    /// Pinned sessions on top, each group by `updated_at`.
+    #[default]
+    PinnedFirst,
+    UpdatedAt,
+    CreatedAt,
+    Title,
+}
+
+impl SessionSort {
+    fn order_by(self) -> &'static str {
+        match self {
+            Self::PinnedFirst => "pinned DESC, updated_at DESC",
+            Self::UpdatedAt => "updated_at DESC",
+            Self::CreatedAt => "created_at DESC",
+            Self::Title => "title IS NULL, title COLLATE NOCASE ASC, updated_at DESC",
+        }
+    }
+}
+
+/// Unset filters match every session.
+#[derive(Debug, Clone, Default, uniffi::Record)]
+pub struct SessionListOptions {
+    pub sort: SessionSort,
+    pub scenario: Option<String>,
+    pub status: Option<String>,
 }
 
 #[derive(Debug, Clone, uniffi::Record)]
@@ -27,6 +123,168 @@ pub struct Message {
     pub phase: Option<String>,
     pub tool_calls: Option<String>,
     pub created_at: i64,
+    /// The user message this one answers; `None` for user messages and
+    /// messages written outside an agent task.
+    pub reply_to_message_id: Option<String>,
+}
+
+/// One message of a `create_messages_bulk` batch.
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct NewMessage {
+    pub role: String,
+    pub content: String,
+    #[uniffi(default = None)]
+    pub phase: Option<String>,
+    /// Tool calls as JSON, as in `Message::tool_calls`.
+    #[uniffi(default = None)]
+    pub tool_calls: Option<String>,
+    /// When the message was originally written, for imports; defaults to
+    /// now.
+    #[uniffi(default = None)]
+    pub created_at: Option<i64>,
+}
+
+/// Associated data a recorded event payload is sealed with.
+fn event_aad(kind: &str, timestamp: i64) -> String {
+    format!("event:{kind}:{timestamp}")
+}
+
+fn trace_aad(task_id: &str) -> String {
+    format!("trace:{task_id}")
+}
+
+fn incident_aad(session_id: &str) -> String {
+    format!("incident:{session_id}")
+}
+
+fn query_log_aad(query_hash: &str) -> String {
+    format!("query:{query_hash}")
+}
+
+/// Settings holding user content, sealed while content encryption is on:
+/// intake answers, checkpoint names, the pending transcript and what the
+/// latest report was drafted from. `report_kb`, review state and
+/// preferences hold none.
+const SEALED_SETTING_PATTERNS: &[&str] = &[
+    "intake:%:answer:%",
+    "intake:%:checkpoint:%",
+    "intake:%:pending_transcript",
+    "session:%:report_sections",
+    "session:%:report_review",
+    "session:%:report_provenance",
+];
+
+/// `messages` (in creation order) regrouped so every message is followed by
+/// the replies to it. Messages whose parent is missing start a group.
+pub fn thread_messages(messages: Vec<Message>) -> Vec<Message> {
+    let ids = messages
+        .iter()
+        .map(|message| message.id.clone())
+        .collect::<HashSet<_>>();
+    let mut roots = Vec::new();
+    let mut replies = HashMap::<String, Vec<Message>>::new();
+    for message in messages {
+        match message.reply_to_message_id.clone() {
+            Some(parent) if parent != message.id && ids.contains(&parent) => {
+                replies.entry(parent).or_default().push(message);
+            }
+            _ => roots.push(message),
+        }
+    }
+
+    let mut threaded = Vec::with_capacity(ids.len());
+    let mut stack = roots.into_iter().rev().collect::<Vec<_>>();
+    while let Some(message) = stack.pop() {
+        if let Some(children) = replies.remove(&message.id) {
+            stack.extend(children.into_iter().rev());
+        }
+        threaded.push(message);
+    }
+    threaded
+}
+
+/// A file the user attached as evidence. The host extracts the text (OCR
+/// for images and scans) before handing the attachment to the core.
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct Attachment {
+    pub id: String,
+    pub session_id: String,
+    pub file_name: String,
+    pub mime_type: String,
+    pub extracted_text: String,
+    /// One-paragraph digest, filled in by the `evidence_summary` tool.
+    pub summary: Option<String>,
+    pub created_at: i64,
+}
+
+/// One match from `search_case_index`: which session, which kind of text
+/// (`report` or `facts`) and a short snippet around the match.
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct SessionSearchHit {
+    pub session_id: String,
+    pub session_title: Option<String>,
+    pub kind: String,
+    pub snippet: String,
+    pub updated_at: i64,
+}
+
+/// One recorded agent decision. `data` is a JSON object whose shape depends
+/// on `kind` (`phase`, `tool_call`, `tool_result`, `safety`, `outcome`).
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct TaskTraceEntry {
+    pub task_id: String,
+    pub seq: u32,
+    pub kind: String,
+    pub data: String,
+    pub created_at: i64,
+}
+
+/// A safety-layer interception persisted for compliance review.
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct SafetyIncident {
+    pub id: i64,
+    pub session_id: String,
+    pub task_id: Option<String>,
+    pub rule_name: String,
+    pub category: String,
+    /// `critical` or `warning`.
+    pub severity: String,
+    pub context: String,
+    pub created_at: i64,
+}
+
+/// A retrieval query captured by the opt-in query log.
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct QueryLogEntry {
+    pub id: i64,
+    /// Lowercase hex SHA-256 of the query as issued.
+    pub query_hash: String,
+    /// The query with personal details masked; `None` when only the hash
+    /// is captured. Several agent queries are joined with newlines.
+    pub query_text: Option<String>,
+    pub scenario: String,
+    pub result_count: u32,
+    /// Chunks the agent cited or the user opened, in that order.
+    pub used_chunk_ids: Vec<String>,
+    pub created_at: i64,
+}
+
+/// All fields are optional; unset fields do not filter.
+#[derive(Debug, Clone, Default, uniffi::Record)]
+pub struct SafetyIncidentFilter {
+    pub session_id: Option<String>,
+    pub category: Option<String>,
+    pub severity: Option<String>,
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    pub limit: Option<u32>,
+}
+
+/// Narrows `get_recent_events`. An empty `kinds` matches every kind.
+#[derive(Debug, Clone, Default, uniffi::Record)]
+pub struct EventFilter {
+    pub kinds: Vec<String>,
+    pub limit: Option<u32>,
 }
 
 #[derive(Debug, Clone, uniffi::Record)]
@@ -39,23 +297,400 @@ pub struct LogEntry {
 }
 
 pub struct SqliteStorage {
-    conn: Mutex<Connection>,
+    conn: Mutex<Shared>,
+    /// Signalled when a `with_tx` transaction ends.
+    tx_ended: Condvar,
+    monitor: Arc<QueryMonitor>,
+    clock: Arc<dyn Clock>,
+    faults: FaultInjector,
+    /// New message content is sealed; see `set_content_encryption`.
+    encrypt_content: AtomicBool,
+    cipher: RwLock<Option<Arc<ContentCipher>>>,
+    cache: ReadCache,
+}
+
+struct Shared {
+    conn: Connection,
+    /// Thread running a `with_tx` closure; other threads wait for it.
+    tx_owner: Option<ThreadId>,
+}
+
+/// Locked connection whose SQLite work counts against the query timeout.
+struct ConnGuard<'a> {
+    conn: MutexGuard<'a, Shared>,
+    monitor: &'a QueryMonitor,
+}
+
+impl Deref for ConnGuard<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        &self.conn.conn
+    }
+}
+
+impl DerefMut for ConnGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Connection {
+        &mut self.conn.conn
+    }
+}
+
+impl Drop for ConnGuard<'_> {
+    fn drop(&mut self) {
+        self.monitor.end();
+    }
+}
+
+/// Ends the transaction `with_tx` opened, rolling it back if `f` panics.
+struct TxScope<'a> {
+    storage: &'a SqliteStorage,
+    done: bool,
+}
+
+impl TxScope<'_> {
+    fn end(&mut self, commit: bool) -> CoreResult<()> {
+        self.done = true;
+        let mut shared = self
+            .storage
+            .conn
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let result = if commit {
+            shared.conn.execute_batch("COMMIT")
+        } else {
+            shared.conn.execute_batch("ROLLBACK")
+        };
+        if result.is_err() && !shared.conn.is_autocommit() {
+            let _ = shared.conn.execute_batch("ROLLBACK");
+        }
+        if !commit || result.is_err() {
+            // Writes in the transaction dropped their entries, but reads
+            // after them may have seen values that are now gone.
+            self.storage.cache.clear();
+        }
+        shared.tx_owner = None;
+        self.storage.tx_ended.notify_all();
+        result.map_err(|e| CoreError::Storage(e.to_string()))
+    }
+}
+
+impl Drop for TxScope<'_> {
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = self.end(false);
+        }
+    }
 }
 
 impl SqliteStorage {
-    pub fn new<P: AsRef<Path>>(path: P) -> CoreResult<Self> {
+    pub fn new<P: AsRef<Path>>(path: P, clock: Arc<dyn Clock>) -> CoreResult<Self> {
         let conn = Connection::open(path).map_err(|e| CoreError::Storage(e.to_string()))?;
         conn.pragma_update(None, "foreign_keys", "ON")
             .map_err(|e| CoreError::Storage(e.to_string()))?;
         migrate(&conn)?;
+        Self::with_connection(conn, clock)
+    }
+
+    /// Open an existing database without migrating it; writes fail.
+    pub fn open_read_only<P: AsRef<Path>>(path: P, clock: Arc<dyn Clock>) -> CoreResult<Self> {
+        let conn = Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )
+        .map_err(|e| CoreError::Storage(e.to_string()))?;
+        Self::with_connection(conn, clock)
+    }
+
+    fn with_connection(mut conn: Connection, clock: Arc<dyn Clock>) -> CoreResult<Self> {
+        let limits = QueryLimits::default();
+        let monitor = Arc::new(QueryMonitor::new(limits));
+        conn.busy_timeout(limits.timeout)
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+        let deadline = monitor.clone();
+        conn.progress_handler(
+            monitor::PROGRESS_CHECK_OPS,
+            Some(move || deadline.past_deadline()),
+        );
+        conn.profile(Some(monitor::profile));
 
         Ok(Self {
-            conn: Mutex::new(conn),
+            conn: Mutex::new(Shared {
+                conn,
+                tx_owner: None,
+            }),
+            tx_ended: Condvar::new(),
+            monitor,
+            clock,
+            faults: FaultInjector::default(),
+            encrypt_content: AtomicBool::new(false),
+            cipher: RwLock::new(None),
+            cache: ReadCache::default(),
+        })
+    }
+
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
+
+    /// Change the query timeout and slow-query threshold.
+    pub fn set_query_limits(&self, limits: QueryLimits) -> CoreResult<()> {
+        self.conn()?
+            .busy_timeout(limits.timeout)
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+        self.monitor.set_limits(limits);
+        Ok(())
+    }
+
+    /// Seal user content written from now on, and keep `case_index` empty:
+    /// a full-text index would hold the same text in the clear. Covered are
+    /// messages, event payloads, the settings in `SEALED_SETTING_PATTERNS`,
+    /// trace data, safety incident context and query log text; ids,
+    /// timestamps, counters and KB chunk ids stay in the clear. Writing
+    /// content fails while this is on and no key is set.
+    pub fn set_content_encryption(&self, enabled: bool) {
+        self.encrypt_content.store(enabled, Ordering::SeqCst);
+    }
+
+    pub fn content_encryption(&self) -> bool {
+        self.encrypt_content.load(Ordering::SeqCst)
+    }
+
+    /// Key for sealing and opening message content. Fails when content was
+    /// already sealed under a different key.
+    pub fn set_content_cipher(&self, cipher: Option<ContentCipher>) -> CoreResult<()> {
+        if let (Some(cipher), Some(check)) = (&cipher, self.get_setting(KEY_CHECK_SETTING)?) {
+            if !cipher.matches_key_check(&check) {
+                return Err(CoreError::Config(
+                    "message content key differs from the one content was sealed with".to_owned(),
+                ));
+            }
+        }
+        *self
+            .cipher
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = cipher.map(Arc::new);
+        Ok(())
+    }
+
+    pub fn has_content_cipher(&self) -> bool {
+        self.cipher
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .is_some()
+    }
+
+    /// Seal all user content still stored in the clear and clear the case
+    /// index. Returns the number of messages sealed.
+    pub fn seal_plaintext_content(&self) -> CoreResult<u32> {
+        let Some(cipher) = self.sealing_cipher()? else {
+            return Ok(0);
+        };
+        self.with_tx(|| {
+            if self.get_setting(KEY_CHECK_SETTING)?.is_none() {
+                self.set_setting(KEY_CHECK_SETTING, &cipher.key_check()?)?;
+            }
+            let conn = self.conn()?;
+            conn.execute("DELETE FROM case_index", [])
+                .map_err(|e| CoreError::Storage(e.to_string()))?;
+            let plaintext = conn
+                .prepare("SELECT id, content FROM messages")
+                .and_then(|mut stmt| {
+                    stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get(1)?)))?
+                        .collect::<Result<Vec<(String, String)>, _>>()
+                })
+                .map_err(|e| CoreError::Storage(e.to_string()))?
+                .into_iter()
+                .filter(|(_, content)| !ContentCipher::is_sealed(content))
+                .collect::<Vec<_>>();
+            for (id, content) in &plaintext {
+                conn.execute(
+                    "UPDATE messages SET content = ?1 WHERE id = ?2",
+                    params![cipher.seal(content, id)?, id],
+                )
+                .map_err(|e| CoreError::Storage(e.to_string()))?;
+            }
+            let events = conn
+                .prepare("SELECT id, kind, payload, timestamp FROM event_history")
+                .and_then(|mut stmt| {
+                    stmt.query_map([], |row| {
+                        Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+                    })?
+                    .collect::<Result<Vec<(i64, String, String, i64)>, _>>()
+                })
+                .map_err(|e| CoreError::Storage(e.to_string()))?;
+            for (id, kind, payload, timestamp) in events {
+                if ContentCipher::is_sealed(&payload) {
+                    continue;
+                }
+                conn.execute(
+                    "UPDATE event_history SET payload = ?1 WHERE id = ?2",
+                    params![cipher.seal(&payload, &event_aad(&kind, timestamp))?, id],
+                )
+                .map_err(|e| CoreError::Storage(e.to_string()))?;
+            }
+            let settings_filter = SEALED_SETTING_PATTERNS
+                .iter()
+                .map(|pattern| format!("key LIKE '{pattern}'"))
+                .collect::<Vec<_>>()
+                .join(" OR ");
+            // `select` yields (rowid, what the associated data is made of,
+            // value); `update` seals one value.
+            let seal_column = |select: &str, update: &str, aad: fn(&str) -> String| {
+                let rows = conn
+                    .prepare(select)
+                    .and_then(|mut stmt| {
+                        stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+                            .collect::<Result<Vec<(i64, String, String)>, _>>()
+                    })
+                    .map_err(|e| CoreError::Storage(e.to_string()))?;
+                for (id, row_aad, value) in rows {
+                    if ContentCipher::is_sealed(&value) {
+                        continue;
+                    }
+                    conn.execute(update, params![cipher.seal(&value, &aad(&row_aad))?, id])
+                        .map_err(|e| CoreError::Storage(e.to_string()))?;
+                }
+                CoreResult::Ok(())
+            };
+            seal_column(
+                "SELECT id, task_id, data FROM task_traces",
+                "UPDATE task_traces SET data = ?1 WHERE id = ?2",
+                trace_aad,
+            )?;
+            seal_column(
+                "SELECT id, session_id, context FROM safety_incidents",
+                "UPDATE safety_incidents SET context = ?1 WHERE id = ?2",
+                incident_aad,
+            )?;
+            seal_column(
+                "SELECT id, query_hash, query_text FROM query_log WHERE query_text IS NOT NULL",
+                "UPDATE query_log SET query_text = ?1 WHERE id = ?2",
+                query_log_aad,
+            )?;
+            seal_column(
+                &format!("SELECT rowid, key, value FROM settings WHERE {settings_filter}"),
+                "UPDATE settings SET value = ?1 WHERE rowid = ?2",
+                str::to_owned,
+            )?;
+            Ok(plaintext.len() as u32)
+        })
+    }
+
+    /// The key to seal new content with, `None` while encryption is off.
+    fn sealing_cipher(&self) -> CoreResult<Option<Arc<ContentCipher>>> {
+        if !self.content_encryption() {
+            return Ok(None);
+        }
+        self.opening_cipher().map(Some)
+    }
+
+    fn opening_cipher(&self) -> CoreResult<Arc<ContentCipher>> {
+        self.cipher
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+            .ok_or_else(|| {
+                CoreError::Config(
+                    "message content is encrypted but its key is not available".to_owned(),
+                )
+            })
+    }
+
+    /// `stored` opened with `aad` if it is sealed, as it is otherwise.
+    fn open_column(&self, stored: String, aad: &str) -> CoreResult<String> {
+        if ContentCipher::is_sealed(&stored) {
+            self.opening_cipher()?.open(&stored, aad)
+        } else {
+            Ok(stored)
+        }
+    }
+
+    fn open_message(&self, mut message: Message) -> CoreResult<Message> {
+        if ContentCipher::is_sealed(&message.content) {
+            message.content = self.opening_cipher()?.open(&message.content, &message.id)?;
+        }
+        Ok(message)
+    }
+
+    pub fn slow_query_stats(&self) -> SlowQueryStats {
+        self.monitor.stats()
+    }
+
+    /// Faults injected into this storage and the pipeline using it.
+    pub fn faults(&self) -> &FaultInjector {
+        &self.faults
+    }
+
+    fn conn(&self) -> CoreResult<ConnGuard<'_>> {
+        self.faults.storage()?;
+        self.unfaulted_conn()
+    }
+
+    /// Writes under `fault:` skip the injected storage fault, so a rule can
+    /// always be stored, including the one turning that fault off.
+    fn settings_conn(&self, key: &str) -> CoreResult<ConnGuard<'_>> {
+        if key.starts_with(FAULT_SETTING_PREFIX) {
+            self.unfaulted_conn()
+        } else {
+            self.conn()
+        }
+    }
+
+    fn unfaulted_conn(&self) -> CoreResult<ConnGuard<'_>> {
+        let conn = self.lock_shared()?;
+        self.monitor.begin();
+        Ok(ConnGuard {
+            conn,
+            monitor: &self.monitor,
         })
     }
 
-    pub fn create_session(&self, scenario: &str, title: Option<&str>) -> CoreResult<Session> {
-        let now = Utc::now().timestamp();
+    /// Lock the connection once no other thread has a transaction open.
+    fn lock_shared(&self) -> CoreResult<MutexGuard<'_, Shared>> {
+        let poisoned = |_| CoreError::Storage("storage lock poisoned".to_owned());
+        let me = thread::current().id();
+        let mut shared = self.conn.lock().map_err(poisoned)?;
+        while shared.tx_owner.is_some_and(|owner| owner != me) {
+            shared = self.tx_ended.wait(shared).map_err(poisoned)?;
+        }
+        Ok(shared)
+    }
+
+    /// Run `f` in one transaction: every write it makes through this
+    /// storage is committed when it returns `Ok` and rolled back when it
+    /// returns `Err` or panics. Other threads wait until it is done; a
+    /// `with_tx` inside `f` joins the outer transaction.
+    pub fn with_tx<T>(&self, f: impl FnOnce() -> CoreResult<T>) -> CoreResult<T> {
+        {
+            let mut shared = self.lock_shared()?;
+            if shared.tx_owner.is_some() {
+                drop(shared);
+                return f();
+            }
+            self.faults.storage()?;
+            shared
+                .conn
+                .execute_batch("BEGIN IMMEDIATE")
+                .map_err(|e| CoreError::Storage(e.to_string()))?;
+            shared.tx_owner = Some(thread::current().id());
+        }
+
+        let mut scope = TxScope {
+            storage: self,
+            done: false,
+        };
+        let result = f();
+        let end = scope.end(result.is_ok());
+        result.and_then(|value| end.map(|()| value))
+    }
+
+    pub fn create_session(
+        &self,
+        scenario: &str,
+        title: Option<&str>,
+        profile_id: &str,
+    ) -> CoreResult<Session> {
+        let now = self.clock.timestamp();
         let session = Session {
             id: Uuid::new_v4().to_string(),
             title: title.map(ToOwned::to_owned),
@@ -63,22 +698,23 @@ impl SqliteStorage {
             created_at: now,
             updated_at: now,
             status: "active".to_owned(),
+            pinned: false,
+            profile_id: profile_id.to_owned(),
+            metadata: CaseMetadata::default(),
         };
 
-        let conn = self
-            .conn
-            .lock()
-            .map_err(|_| CoreError::Storage("storage lock poisoned".to_owned()))?;
+        let conn = self.conn()?;
         conn.execute(
-            "INSERT INTO sessions (id, title, scenario, created_at, updated_at, status)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            "INSERT INTO sessions (id, title, scenario, created_at, updated_at, status, profile_id)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
             params![
                 session.id,
                 session.title,
                 session.scenario,
                 session.created_at,
                 session.updated_at,
-                session.status
+                session.status,
+                session.profile_id
             ],
         )
         .map_err(|e| CoreError::Storage(e.to_string()))?;
@@ -86,30 +722,32 @@ impl SqliteStorage {
         Ok(session)
     }
 
-    pub fn list_sessions(&self) -> CoreResult<Vec<Session>> {
-        let conn = self
-            .conn
-            .lock()
-            .map_err(|_| CoreError::Storage("storage lock poisoned".to_owned()))?;
+    /// Sessions of `profile_id` matching `options`.
+    pub fn list_sessions(
+        &self,
+        profile_id: &str,
+        options: &SessionListOptions,
+    ) -> CoreResult<Vec<Session>> {
+        let conn = self.conn()?;
 
         let mut stmt = conn
-            .prepare(
-                "SELECT id, title, scenario, created_at, updated_at, status
-                 FROM sessions ORDER BY updated_at DESC",
-            )
+            .prepare(&format!(
+                "SELECT id, title, scenario, created_at, updated_at, status, pinned, profile_id,
+                        opposing_party, claim_amount, dispute_started_on, jurisdiction
+                 FROM sessions
+                 WHERE profile_id = ?1
+                   AND (?2 IS NULL OR scenario = ?2)
+                   AND (?3 IS NULL OR status = ?3)
+                 ORDER BY {}",
+                options.sort.order_by()
+            ))
             .map_err(|e| CoreError::Storage(e.to_string()))?;
 
         let sessions = stmt
-            .query_map([], |row| {
-                Ok(Session {
-                    id: row.get(0)?,
-                    title: row.get(1)?,
-                    scenario: row.get(2)?,
-                    created_at: row.get(3)?,
-                    updated_at: row.get(4)?,
-                    status: row.get(5)?,
-                })
-            })
+            .query_map(
+                params![profile_id, options.scenario, options.status],
+                row_to_session,
+            )
             .map_err(|e| CoreError::Storage(e.to_string()))?
             .collect::<Result<Vec<_>, _>>()
             .map_err(|e| CoreError::Storage(e.to_string()))?;
@@ -118,36 +756,65 @@ impl SqliteStorage {
     }
 
     pub fn get_session(&self, session_id: &str) -> CoreResult<Option<Session>> {
-        let conn = self
-            .conn
-            .lock()
-            .map_err(|_| CoreError::Storage("storage lock poisoned".to_owned()))?;
+        let conn = self.conn()?;
 
         conn.query_row(
-            "SELECT id, title, scenario, created_at, updated_at, status
+            "SELECT id, title, scenario, created_at, updated_at, status, pinned, profile_id,
+                    opposing_party, claim_amount, dispute_started_on, jurisdiction
              FROM sessions WHERE id = ?1",
             params![session_id],
-            |row| {
-                Ok(Session {
-                    id: row.get(0)?,
-                    title: row.get(1)?,
-                    scenario: row.get(2)?,
-                    created_at: row.get(3)?,
-                    updated_at: row.get(4)?,
-                    status: row.get(5)?,
-                })
-            },
+            row_to_session,
         )
         .optional()
         .map_err(|e| CoreError::Storage(e.to_string()))
     }
 
+    /// Replace the case metadata of `session_id` as a whole.
+    pub fn set_case_metadata(&self, session_id: &str, metadata: &CaseMetadata) -> CoreResult<()> {
+        let now = self.clock.timestamp();
+        let conn = self.conn()?;
+
+        let updated = conn
+            .execute(
+                "UPDATE sessions
+                 SET opposing_party = ?1, claim_amount = ?2, dispute_started_on = ?3,
+                     jurisdiction = ?4, updated_at = ?5
+                 WHERE id = ?6",
+                params![
+                    metadata.opposing_party,
+                    metadata.claim_amount,
+                    metadata.dispute_started_on,
+                    metadata.jurisdiction,
+                    now,
+                    session_id
+                ],
+            )
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+        if updated == 0 {
+            return Err(CoreError::NotFound(format!("session {session_id}")));
+        }
+        Ok(())
+    }
+
+    /// Pinning does not touch `updated_at`.
+    pub fn set_session_pinned(&self, session_id: &str, pinned: bool) -> CoreResult<()> {
+        let conn = self.conn()?;
+
+        let updated = conn
+            .execute(
+                "UPDATE sessions SET pinned = ?1 WHERE id = ?2",
+                params![pinned, session_id],
+            )
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+        if updated == 0 {
+            return Err(CoreError::NotFound(format!("session {session_id}")));
+        }
+        Ok(())
+    }
+
     pub fn update_session_title(&self, session_id: &str, title: &str) -> CoreResult<()> {
-        let now = Utc::now().timestamp();
-        let conn = self
-            .conn
-            .lock()
-            .map_err(|_| CoreError::Storage("storage lock poisoned".to_owned()))?;
+        let now = self.clock.timestamp();
+        let conn = self.conn()?;
 
         let updated = conn
             .execute(
@@ -162,11 +829,35 @@ impl SqliteStorage {
         Ok(())
     }
 
+    /// Changes are recorded for the session's activity feed.
+    pub fn update_session_status(&self, session_id: &str, status: &str) -> CoreResult<()> {
+        let now = self.clock.timestamp();
+        let mut conn = self.conn()?;
+        let tx = conn
+            .savepoint()
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+
+        tx.execute(
+            "INSERT INTO session_status_changes (session_id, status, created_at)
+             SELECT id, ?1, ?2 FROM sessions WHERE id = ?3 AND status != ?1",
+            params![status, now, session_id],
+        )
+        .map_err(|e| CoreError::Storage(e.to_string()))?;
+        let updated = tx
+            .execute(
+                "UPDATE sessions SET status = ?1, updated_at = ?2 WHERE id = ?3",
+                params![status, now, session_id],
+            )
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+
+        if updated == 0 {
+            return Err(CoreError::NotFound(format!("session {session_id}")));
+        }
+        tx.commit().map_err(|e| CoreError::Storage(e.to_string()))
+    }
+
     pub fn delete_session(&self, session_id: &str) -> CoreResult<()> {
-        let conn = self
-            .conn
-            .lock()
-            .map_err(|_| CoreError::Storage("storage lock poisoned".to_owned()))?;
+        let conn = self.conn()?;
 
         let deleted = conn
             .execute("DELETE FROM sessions WHERE id = ?1", params![session_id])
@@ -178,6 +869,52 @@ impl SqliteStorage {
         Ok(())
     }
 
+    pub fn create_profile(&self, name: &str) -> CoreResult<Profile> {
+        let profile = Profile {
+            id: Uuid::new_v4().to_string(),
+            name: name.to_owned(),
+            created_at: self.clock.timestamp(),
+        };
+
+        let conn = self.conn()?;
+        conn.execute(
+            "INSERT INTO profiles (id, name, created_at) VALUES (?1, ?2, ?3)",
+            params![profile.id, profile.name, profile.created_at],
+        )
+        .map_err(|e| CoreError::Storage(e.to_string()))?;
+
+        Ok(profile)
+    }
+
+    /// Every profile, the default one first.
+    pub fn list_profiles(&self) -> CoreResult<Vec<Profile>> {
+        let conn = self.conn()?;
+
+        let mut stmt = conn
+            .prepare("SELECT id, name, created_at FROM profiles ORDER BY created_at ASC, rowid ASC")
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+
+        let profiles = stmt
+            .query_map([], row_to_profile)
+            .map_err(|e| CoreError::Storage(e.to_string()))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+
+        Ok(profiles)
+    }
+
+    pub fn get_profile(&self, profile_id: &str) -> CoreResult<Option<Profile>> {
+        let conn = self.conn()?;
+
+        conn.query_row(
+            "SELECT id, name, created_at FROM profiles WHERE id = ?1",
+            params![profile_id],
+            row_to_profile,
+        )
+        .optional()
+        .map_err(|e| CoreError::Storage(e.to_string()))
+    }
+
     pub fn create_message(
         &self,
         session_id: &str,
@@ -186,24 +923,53 @@ impl SqliteStorage {
         phase: Option<&str>,
         tool_calls: Option<&Value>,
     ) -> CoreResult<Message> {
-        let now = Utc::now().timestamp();
-        let message = Message {
-            id: Uuid::new_v4().to_string(),
-            session_id: session_id.to_owned(),
-            role: role.to_owned(),
-            content: content.to_owned(),
-            phase: phase.map(ToOwned::to_owned),
-            tool_calls: tool_calls.map(|value| value.to_string()),
-            created_at: now,
-        };
+        self.insert_message(session_id, role, content, phase, tool_calls, None)
+    }
 
-        let conn = self
-            .conn
-            .lock()
-            .map_err(|_| CoreError::Storage("storage lock poisoned".to_owned()))?;
+    /// Store a message answering the user message `reply_to`.
+    pub fn create_reply(
+        &self,
+        session_id: &str,
+        reply_to: &str,
+        role: &str,
+        content: &str,
+        phase: Option<&str>,
+    ) -> CoreResult<Message> {
+        self.insert_message(session_id, role, content, phase, None, Some(reply_to))
+    }
 
-        // Check session exists within the same lock scope to avoid double-lock
-        let session_exists: bool = conn
+    fn insert_message(
+        &self,
+        session_id: &str,
+        role: &str,
+        content: &str,
+        phase: Option<&str>,
+        tool_calls: Option<&Value>,
+        reply_to: Option<&str>,
+    ) -> CoreResult<Message> {
+        let now = self.clock.timestamp();
+        let message = Message {
+            id: Uuid::new_v4().to_string(),
+            session_id: session_id.to_owned(),
+            role: role.to_owned(),
+            content: content.to_owned(),
+            phase: phase.map(ToOwned::to_owned),
+            tool_calls: tool_calls.map(|value| value.to_string()),
+            created_at: now,
+            reply_to_message_id: reply_to.map(ToOwned::to_owned),
+        };
+        let stored_content = seal_content(
+            self.sealing_cipher()?.as_deref(),
+            &message.content,
+            &message.id,
+        )?;
+
+        let mut conn = self.conn()?;
+        let tx = conn
+            .savepoint()
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+
+        let session_exists: bool = tx
             .query_row(
                 "SELECT EXISTS(SELECT 1 FROM sessions WHERE id = ?1)",
                 params![session_id],
@@ -215,40 +981,142 @@ impl SqliteStorage {
             return Err(CoreError::NotFound(format!("session {session_id}")));
         }
 
-        conn.execute(
-            "INSERT INTO messages (id, session_id, role, content, phase, tool_calls, created_at)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        tx.execute(
+            "INSERT INTO messages
+                 (id, session_id, role, content, phase, tool_calls, created_at, reply_to_message_id)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
             params![
                 message.id,
                 message.session_id,
                 message.role,
-                message.content,
+                stored_content,
                 message.phase,
                 message.tool_calls,
                 message.created_at,
+                message.reply_to_message_id,
             ],
         )
         .map_err(|e| CoreError::Storage(e.to_string()))?;
 
-        conn.execute(
+        tx.execute(
             "UPDATE sessions SET updated_at = ?1 WHERE id = ?2",
             params![now, session_id],
         )
         .map_err(|e| CoreError::Storage(e.to_string()))?;
 
+        tx.commit().map_err(|e| CoreError::Storage(e.to_string()))?;
         Ok(message)
     }
 
+    /// Store `messages` in order in one transaction, through a single
+    /// prepared statement, touching the session's `updated_at` once. Either
+    /// every message is stored or none is.
+    pub fn create_messages_bulk(
+        &self,
+        session_id: &str,
+        messages: &[NewMessage],
+    ) -> CoreResult<Vec<Message>> {
+        let now = self.clock.timestamp();
+        let cipher = self.sealing_cipher()?;
+
+        let mut conn = self.conn()?;
+        let tx = conn
+            .savepoint()
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+
+        let session_exists: bool = tx
+            .query_row(
+                "SELECT EXISTS(SELECT 1 FROM sessions WHERE id = ?1)",
+                params![session_id],
+                |row| row.get(0),
+            )
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+        if !session_exists {
+            return Err(CoreError::NotFound(format!("session {session_id}")));
+        }
+
+        let mut stored = Vec::with_capacity(messages.len());
+        {
+            let mut stmt = tx
+                .prepare(
+                    "INSERT INTO messages
+                         (id, session_id, role, content, phase, tool_calls, created_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                )
+                .map_err(|e| CoreError::Storage(e.to_string()))?;
+            for new in messages {
+                let message = Message {
+                    id: Uuid::new_v4().to_string(),
+                    session_id: session_id.to_owned(),
+                    role: new.role.clone(),
+                    content: new.content.clone(),
+                    phase: new.phase.clone(),
+                    tool_calls: new.tool_calls.clone(),
+                    created_at: new.created_at.unwrap_or(now),
+                    reply_to_message_id: None,
+                };
+                let stored_content =
+                    seal_content(cipher.as_deref(), &message.content, &message.id)?;
+                stmt.execute(params![
+                    message.id,
+                    message.session_id,
+                    message.role,
+                    stored_content,
+                    message.phase,
+                    message.tool_calls,
+                    message.created_at,
+                ])
+                .map_err(|e| CoreError::Storage(e.to_string()))?;
+                stored.push(message);
+            }
+        }
+
+        if !stored.is_empty() {
+            tx.execute(
+                "UPDATE sessions SET updated_at = ?1 WHERE id = ?2",
+                params![now, session_id],
+            )
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+        }
+        tx.commit().map_err(|e| CoreError::Storage(e.to_string()))?;
+        Ok(stored)
+    }
+
+    pub fn get_message(&self, message_id: &str) -> CoreResult<Option<Message>> {
+        let conn = self.conn()?;
+
+        conn.query_row(
+            "SELECT id, session_id, role, content, phase, tool_calls, created_at,
+                    reply_to_message_id
+             FROM messages WHERE id = ?1",
+            params![message_id],
+            |row| {
+                Ok(Message {
+                    id: row.get(0)?,
+                    session_id: row.get(1)?,
+                    role: row.get(2)?,
+                    content: row.get(3)?,
+                    phase: row.get(4)?,
+                    tool_calls: row.get(5)?,
+                    created_at: row.get(6)?,
+                    reply_to_message_id: row.get(7)?,
+                })
+            },
+        )
+        .optional()
+        .map_err(|e| CoreError::Storage(e.to_string()))?
+        .map(|message| self.open_message(message))
+        .transpose()
+    }
+
     pub fn get_messages(&self, session_id: &str) -> CoreResult<Vec<Message>> {
-        let conn = self
-            .conn
-            .lock()
-            .map_err(|_| CoreError::Storage("storage lock poisoned".to_owned()))?;
+        let conn = self.conn()?;
 
         let mut stmt = conn
             .prepare(
-                "SELECT id, session_id, role, content, phase, tool_calls, created_at
-                 FROM messages WHERE session_id = ?1 ORDER BY created_at ASC",
+                "SELECT id, session_id, role, content, phase, tool_calls, created_at,
+                        reply_to_message_id
+                 FROM messages WHERE session_id = ?1 ORDER BY created_at ASC, rowid ASC",
             )
             .map_err(|e| CoreError::Storage(e.to_string()))?;
 
@@ -262,20 +1130,127 @@ impl SqliteStorage {
                     phase: row.get(4)?,
                     tool_calls: row.get(5)?,
                     created_at: row.get(6)?,
+                    reply_to_message_id: row.get(7)?,
+                })
+            })
+            .map_err(|e| CoreError::Storage(e.to_string()))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+
+        messages
+            .into_iter()
+            .map(|message| self.open_message(message))
+            .collect()
+    }
+
+    /// Move a message to another phase, e.g. a report released by review.
+    pub fn set_message_phase(&self, message_id: &str, phase: &str) -> CoreResult<()> {
+        let conn = self.conn()?;
+        let updated = conn
+            .execute(
+                "UPDATE messages SET phase = ?1 WHERE id = ?2",
+                params![phase, message_id],
+            )
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+        if updated == 0 {
+            return Err(CoreError::NotFound(format!("message {message_id}")));
+        }
+        Ok(())
+    }
+
+    pub fn create_attachment(
+        &self,
+        session_id: &str,
+        file_name: &str,
+        mime_type: &str,
+        extracted_text: &str,
+    ) -> CoreResult<Attachment> {
+        let attachment = Attachment {
+            id: Uuid::new_v4().to_string(),
+            session_id: session_id.to_owned(),
+            file_name: file_name.to_owned(),
+            mime_type: mime_type.to_owned(),
+            extracted_text: extracted_text.to_owned(),
+            summary: None,
+            created_at: self.clock.timestamp(),
+        };
+
+        let conn = self.conn()?;
+        let session_exists: bool = conn
+            .query_row(
+                "SELECT EXISTS(SELECT 1 FROM sessions WHERE id = ?1)",
+                params![session_id],
+                |row| row.get(0),
+            )
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+        if !session_exists {
+            return Err(CoreError::NotFound(format!("session {session_id}")));
+        }
+
+        conn.execute(
+            "INSERT INTO attachments
+                (id, session_id, file_name, mime_type, extracted_text, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                attachment.id,
+                attachment.session_id,
+                attachment.file_name,
+                attachment.mime_type,
+                attachment.extracted_text,
+                attachment.created_at
+            ],
+        )
+        .map_err(|e| CoreError::Storage(e.to_string()))?;
+
+        Ok(attachment)
+    }
+
+    /// Attachments of a session, oldest first.
+    pub fn list_attachments(&self, session_id: &str) -> CoreResult<Vec<Attachment>> {
+        let conn = self.conn()?;
+
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, session_id, file_name, mime_type, extracted_text, summary, created_at
+                 FROM attachments WHERE session_id = ?1 ORDER BY created_at ASC, rowid ASC",
+            )
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+
+        let attachments = stmt
+            .query_map(params![session_id], |row| {
+                Ok(Attachment {
+                    id: row.get(0)?,
+                    session_id: row.get(1)?,
+                    file_name: row.get(2)?,
+                    mime_type: row.get(3)?,
+                    extracted_text: row.get(4)?,
+                    summary: row.get(5)?,
+                    created_at: row.get(6)?,
                 })
             })
             .map_err(|e| CoreError::Storage(e.to_string()))?
             .collect::<Result<Vec<_>, _>>()
             .map_err(|e| CoreError::Storage(e.to_string()))?;
 
-        Ok(messages)
+        Ok(attachments)
+    }
+
+    pub fn set_attachment_summary(&self, attachment_id: &str, summary: &str) -> CoreResult<()> {
+        let conn = self.conn()?;
+        let updated = conn
+            .execute(
+                "UPDATE attachments SET summary = ?1 WHERE id = ?2",
+                params![summary, attachment_id],
+            )
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+        if updated == 0 {
+            return Err(CoreError::NotFound(format!("attachment {attachment_id}")));
+        }
+        Ok(())
     }
 
     pub fn set_setting(&self, key: &str, value: &str) -> CoreResult<()> {
-        let conn = self
-            .conn
-            .lock()
-            .map_err(|_| CoreError::Storage("storage lock poisoned".to_owned()))?;
+        let conn = self.settings_conn(key)?;
 
         conn.execute(
             "INSERT INTO settings (key, value) VALUES (?1, ?2)
@@ -283,29 +1258,149 @@ impl SqliteStorage {
             params![key, value],
         )
         .map_err(|e| CoreError::Storage(e.to_string()))?;
+        if ReadCache::is_hot(key) {
+            self.cache.invalidate_setting(key);
+        }
         Ok(())
     }
 
+    /// Hot keys (see `ReadCache::is_hot`) are answered from memory once
+    /// read.
     pub fn get_setting(&self, key: &str) -> CoreResult<Option<String>> {
-        let conn = self
-            .conn
-            .lock()
-            .map_err(|_| CoreError::Storage("storage lock poisoned".to_owned()))?;
+        let hot = ReadCache::is_hot(key);
+        if hot {
+            self.faults.storage()?;
+            if let Some(value) = self.cache.setting(key) {
+                return Ok(value);
+            }
+        }
+        let conn = self.conn()?;
 
-        conn.query_row(
-            "SELECT value FROM settings WHERE key = ?1",
-            params![key],
-            |row| row.get(0),
-        )
-        .optional()
-        .map_err(|e| CoreError::Storage(e.to_string()))
+        let value = conn
+            .query_row(
+                "SELECT value FROM settings WHERE key = ?1",
+                params![key],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+        if hot && conn.conn.tx_owner.is_none() {
+            self.cache.fill_setting(key, value.clone());
+        }
+        Ok(value)
+    }
+
+    pub fn delete_setting(&self, key: &str) -> CoreResult<()> {
+        let conn = self.settings_conn(key)?;
+
+        conn.execute("DELETE FROM settings WHERE key = ?1", params![key])
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+        if ReadCache::is_hot(key) {
+            self.cache.invalidate_setting(key);
+        }
+        Ok(())
+    }
+
+    /// Store a setting holding user content, sealed with the key as
+    /// associated data while content encryption is on.
+    pub fn set_sealed_setting(&self, key: &str, value: &str) -> CoreResult<()> {
+        self.set_setting(key, &self.sealed_setting_value(key, value)?)
+    }
+
+    /// `value` as `set_sealed_setting` stores it under `key`, for writes
+    /// applied together in another transaction.
+    pub fn sealed_setting_value(&self, key: &str, value: &str) -> CoreResult<String> {
+        seal_content(self.sealing_cipher()?.as_deref(), value, key)
+    }
+
+    /// A setting written by `set_sealed_setting`, opened if it is sealed.
+    pub fn get_sealed_setting(&self, key: &str) -> CoreResult<Option<String>> {
+        self.get_setting(key)?
+            .map(|stored| self.open_column(stored, key))
+            .transpose()
+    }
+
+    /// Forget cached settings whose key starts with `prefix`, or every
+    /// cached setting and permission without one, after the database was
+    /// written behind this storage's back. Returns how many were dropped.
+    pub fn invalidate_cache(&self, prefix: Option<&str>) -> CoreResult<u32> {
+        let _conn = self.conn()?;
+        Ok(match prefix {
+            Some(prefix) => self.cache.invalidate_prefix(prefix),
+            None => self.cache.clear(),
+        })
+    }
+
+    pub fn read_cache_stats(&self) -> ReadCacheStats {
+        self.cache.stats()
+    }
+
+    /// Settings whose key starts with `prefix`, by key.
+    pub fn list_settings(&self, prefix: &str) -> CoreResult<Vec<Setting>> {
+        let conn = self.conn()?;
+
+        let mut stmt = conn
+            .prepare("SELECT key, value FROM settings WHERE key LIKE ?1 ESCAPE '\\' ORDER BY key")
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+        let rows = stmt
+            .query_map(params![format!("{}%", escape_like(prefix))], |row| {
+                Ok(Setting {
+                    key: row.get(0)?,
+                    value: row.get(1)?,
+                })
+            })
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+        rows.collect::<Result<Vec<_>, _>>()
+            .map_err(|e| CoreError::Storage(e.to_string()))
+    }
+
+    /// Delete every setting whose key starts with `prefix`; returns how many
+    /// were removed. An empty prefix is refused rather than clearing all
+    /// settings.
+    pub fn delete_settings(&self, prefix: &str) -> CoreResult<u32> {
+        if prefix.is_empty() {
+            return Err(CoreError::Config(
+                "settings prefix must not be empty".to_owned(),
+            ));
+        }
+        let conn = self.settings_conn(prefix)?;
+
+        let deleted = conn
+            .execute(
+                "DELETE FROM settings WHERE key LIKE ?1 ESCAPE '\\'",
+                params![format!("{}%", escape_like(prefix))],
+            )
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+        self.cache.invalidate_prefix(prefix);
+        Ok(deleted as u32)
+    }
+
+    /// Write all `settings` in one transaction: either every key is updated
+    /// or none is.
+    pub fn set_settings_bulk(&self, settings: &[Setting]) -> CoreResult<()> {
+        let mut conn = self.conn()?;
+        let tx = conn
+            .savepoint()
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+
+        for setting in settings {
+            tx.execute(
+                "INSERT INTO settings (key, value) VALUES (?1, ?2)
+                 ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+                params![setting.key, setting.value],
+            )
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+        }
+
+        tx.commit().map_err(|e| CoreError::Storage(e.to_string()))?;
+        for setting in settings {
+            self.cache.invalidate_setting(&setting.key);
+        }
+        Ok(())
     }
 
     pub fn set_tool_permission(&self, tool_name: &str, permission: &str) -> CoreResult<()> {
-        let conn = self
-            .conn
-            .lock()
-            .map_err(|_| CoreError::Storage("storage lock poisoned".to_owned()))?;
+        let conn = self.conn()?;
 
         conn.execute(
             "INSERT INTO tool_permissions (tool_name, permission) VALUES (?1, ?2)
@@ -313,14 +1408,18 @@ impl SqliteStorage {
             params![tool_name, permission],
         )
         .map_err(|e| CoreError::Storage(e.to_string()))?;
+        self.cache.invalidate_permission(tool_name);
         Ok(())
     }
 
-    pub fn get_tool_permission(&self, tool_name: &str) -> CoreResult<String> {
-        let conn = self
-            .conn
-            .lock()
-            .map_err(|_| CoreError::Storage("storage lock poisoned".to_owned()))?;
+    /// Permission set with `set_tool_permission`, if any; answered from
+    /// memory once read.
+    pub fn stored_tool_permission(&self, tool_name: &str) -> CoreResult<Option<String>> {
+        self.faults.storage()?;
+        if let Some(permission) = self.cache.permission(tool_name) {
+            return Ok(permission);
+        }
+        let conn = self.conn()?;
 
         let permission = conn
             .query_row(
@@ -329,64 +1428,1063 @@ impl SqliteStorage {
                 |row| row.get(0),
             )
             .optional()
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+        if conn.conn.tx_owner.is_none() {
+            self.cache.fill_permission(tool_name, permission.clone());
+        }
+        Ok(permission)
+    }
+
+    pub fn append_log(
+        &self,
+        level: &str,
+        message: &str,
+        session_id: Option<&str>,
+    ) -> CoreResult<i64> {
+        let now = self.clock.timestamp();
+        let conn = self.conn()?;
+
+        conn.execute(
+            "INSERT INTO logs (level, message, session_id, created_at) VALUES (?1, ?2, ?3, ?4)",
+            params![level, message, session_id, now],
+        )
+        .map_err(|e| CoreError::Storage(e.to_string()))?;
+
+        Ok(conn.last_insert_rowid())
+    }
+
+    pub fn list_logs(&self, limit: u32) -> CoreResult<Vec<LogEntry>> {
+        let conn = self.conn()?;
+
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, level, message, session_id, created_at
+                 FROM logs ORDER BY id DESC LIMIT ?1",
+            )
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+
+        let logs = stmt
+            .query_map(params![limit], |row| {
+                Ok(LogEntry {
+                    id: row.get(0)?,
+                    level: row.get(1)?,
+                    message: row.get(2)?,
+                    session_id: row.get(3)?,
+                    created_at: row.get(4)?,
+                })
+            })
             .map_err(|e| CoreError::Storage(e.to_string()))?
-            .unwrap_or_else(|| default_permission_for_tool(tool_name).to_owned());
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
 
-        Ok(permission)
+        Ok(logs)
+    }
+
+    /// Record `event` in the event history, keeping only the newest `keep`
+    /// rows. Payloads are sealed like message content while encryption is on.
+    pub fn append_event(&self, event: &CoreEvent, keep: u32) -> CoreResult<()> {
+        let payload = seal_content(
+            self.sealing_cipher()?.as_deref(),
+            &event.payload,
+            &event_aad(&event.kind, event.timestamp),
+        )?;
+        let conn = self.conn()?;
+
+        conn.execute(
+            "INSERT INTO event_history (kind, payload, timestamp) VALUES (?1, ?2, ?3)",
+            params![event.kind, payload, event.timestamp],
+        )
+        .map_err(|e| CoreError::Storage(e.to_string()))?;
+        conn.execute(
+            "DELETE FROM event_history WHERE id <= ?1",
+            params![conn.last_insert_rowid() - i64::from(keep)],
+        )
+        .map_err(|e| CoreError::Storage(e.to_string()))?;
+
+        Ok(())
+    }
+
+    /// Recorded events with `timestamp >= since`, oldest first. When more
+    /// match than `filter.limit` (default 500), the newest are returned.
+    pub fn list_events(&self, since: i64, filter: &EventFilter) -> CoreResult<Vec<CoreEvent>> {
+        let kinds =
+            (!filter.kinds.is_empty()).then(|| Value::from(filter.kinds.clone()).to_string());
+        let conn = self.conn()?;
+
+        let mut stmt = conn
+            .prepare(
+                "SELECT kind, payload, timestamp FROM (
+                     SELECT id, kind, payload, timestamp FROM event_history
+                     WHERE timestamp >= ?1
+                       AND (?2 IS NULL OR kind IN (SELECT value FROM json_each(?2)))
+                     ORDER BY id DESC
+                     LIMIT ?3
+                 ) ORDER BY id ASC",
+            )
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+
+        let events = stmt
+            .query_map(params![since, kinds, filter.limit.unwrap_or(500)], |row| {
+                Ok(CoreEvent {
+                    kind: row.get(0)?,
+                    payload: row.get(1)?,
+                    timestamp: row.get(2)?,
+                })
+            })
+            .map_err(|e| CoreError::Storage(e.to_string()))?
+            .collect::<Result<Vec<CoreEvent>, _>>()
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+        drop(stmt);
+        drop(conn);
+
+        events
+            .into_iter()
+            .map(|mut event| {
+                if ContentCipher::is_sealed(&event.payload) {
+                    event.payload = self
+                        .opening_cipher()?
+                        .open(&event.payload, &event_aad(&event.kind, event.timestamp))?;
+                }
+                Ok(event)
+            })
+            .collect()
+    }
+
+    pub fn append_task_trace(
+        &self,
+        task_id: &str,
+        session_id: &str,
+        kind: &str,
+        data: &str,
+    ) -> CoreResult<()> {
+        let now = self.clock.timestamp();
+        let data = seal_content(self.sealing_cipher()?.as_deref(), data, &trace_aad(task_id))?;
+        let conn = self.conn()?;
+
+        conn.execute(
+            "INSERT INTO task_traces (task_id, session_id, seq, kind, data, created_at)
+             VALUES (?1, ?2,
+                     (SELECT COALESCE(MAX(seq), 0) + 1 FROM task_traces WHERE task_id = ?1),
+                     ?3, ?4, ?5)",
+            params![task_id, session_id, kind, data, now],
+        )
+        .map_err(|e| CoreError::Storage(e.to_string()))?;
+        Ok(())
+    }
+
+    pub fn get_task_trace(&self, task_id: &str) -> CoreResult<Vec<TaskTraceEntry>> {
+        self.query_task_traces("task_id", task_id)
+    }
+
+    /// Traces of every task of a session, grouped by task in run order.
+    pub fn list_session_traces(&self, session_id: &str) -> CoreResult<Vec<TaskTraceEntry>> {
+        self.query_task_traces("session_id", session_id)
+    }
+
+    fn query_task_traces(&self, column: &str, value: &str) -> CoreResult<Vec<TaskTraceEntry>> {
+        let conn = self.conn()?;
+
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT task_id, seq, kind, data, created_at FROM task_traces
+                 WHERE {column} = ?1
+                 ORDER BY id ASC"
+            ))
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+
+        let entries = stmt
+            .query_map(params![value], |row| {
+                Ok(TaskTraceEntry {
+                    task_id: row.get(0)?,
+                    seq: row.get(1)?,
+                    kind: row.get(2)?,
+                    data: row.get(3)?,
+                    created_at: row.get(4)?,
+                })
+            })
+            .map_err(|e| CoreError::Storage(e.to_string()))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+
+        entries
+            .into_iter()
+            .map(|mut entry: TaskTraceEntry| {
+                entry.data = self.open_column(entry.data, &trace_aad(&entry.task_id))?;
+                Ok(entry)
+            })
+            .collect()
+    }
+
+    pub fn record_safety_incident(
+        &self,
+        session_id: &str,
+        task_id: Option<&str>,
+        rule_name: &str,
+        category: &str,
+        severity: &str,
+        context: &str,
+    ) -> CoreResult<()> {
+        let now = self.clock.timestamp();
+        let context = seal_content(
+            self.sealing_cipher()?.as_deref(),
+            context,
+            &incident_aad(session_id),
+        )?;
+        let conn = self.conn()?;
+
+        conn.execute(
+            "INSERT INTO safety_incidents
+                 (session_id, task_id, rule_name, category, severity, context, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![session_id, task_id, rule_name, category, severity, context, now],
+        )
+        .map_err(|e| CoreError::Storage(e.to_string()))?;
+        Ok(())
+    }
+
+    /// Newest first. Without a `limit` at most 500 incidents are returned.
+    pub fn list_safety_incidents(
+        &self,
+        filter: &SafetyIncidentFilter,
+    ) -> CoreResult<Vec<SafetyIncident>> {
+        let conn = self.conn()?;
+
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, session_id, task_id, rule_name, category, severity, context, created_at
+                 FROM safety_incidents
+                 WHERE (?1 IS NULL OR session_id = ?1)
+                   AND (?2 IS NULL OR category = ?2)
+                   AND (?3 IS NULL OR severity = ?3)
+                   AND (?4 IS NULL OR created_at >= ?4)
+                   AND (?5 IS NULL OR created_at <= ?5)
+                 ORDER BY created_at DESC, id DESC
+                 LIMIT ?6",
+            )
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+
+        let incidents = stmt
+            .query_map(
+                params![
+                    filter.session_id,
+                    filter.category,
+                    filter.severity,
+                    filter.since,
+                    filter.until,
+                    filter.limit.unwrap_or(500)
+                ],
+                |row| {
+                    Ok(SafetyIncident {
+                        id: row.get(0)?,
+                        session_id: row.get(1)?,
+                        task_id: row.get(2)?,
+                        rule_name: row.get(3)?,
+                        category: row.get(4)?,
+                        severity: row.get(5)?,
+                        context: row.get(6)?,
+                        created_at: row.get(7)?,
+                    })
+                },
+            )
+            .map_err(|e| CoreError::Storage(e.to_string()))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+
+        incidents
+            .into_iter()
+            .map(|mut incident: SafetyIncident| {
+                incident.context =
+                    self.open_column(incident.context, &incident_aad(&incident.session_id))?;
+                Ok(incident)
+            })
+            .collect()
+    }
+
+    /// Up to `limit` items of the activity feed of `session_id`, starting
+    /// after `after`.
+    pub fn session_activity(
+        &self,
+        session_id: &str,
+        after: Option<ActivityCursor>,
+        limit: u32,
+    ) -> CoreResult<ActivityPage> {
+        let bound = |source| after.map_or((i64::MIN, i64::MIN), |after| after.lower_bound(source));
+        // One more than a page, to tell whether another follows.
+        let fetch = i64::from(limit) + 1;
+        let mut items = Vec::new();
+        {
+            let conn = self.conn()?;
+            let query = |sql: &str,
+                         source: ActivitySource,
+                         map: &dyn Fn(&rusqlite::Row<'_>) -> rusqlite::Result<ActivityItem>|
+             -> CoreResult<Vec<(ActivityCursor, ActivityItem)>> {
+                let (created_at, row) = bound(source);
+                let mut stmt = conn
+                    .prepare(sql)
+                    .map_err(|e| CoreError::Storage(e.to_string()))?;
+                let rows = stmt
+                    .query_map(params![session_id, created_at, row, fetch], |row| {
+                        let cursor = ActivityCursor {
+                            created_at: row.get("created_at")?,
+                            source,
+                            row: row.get("rowid")?,
+                        };
+                        Ok((cursor, map(row)?))
+                    })
+                    .map_err(|e| CoreError::Storage(e.to_string()))?
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| CoreError::Storage(e.to_string()));
+                rows
+            };
+
+            items.extend(query(
+                "SELECT rowid, id, session_id, role, content, phase, tool_calls, created_at,
+                        reply_to_message_id
+                 FROM messages
+                 WHERE session_id = ?1 AND (created_at, rowid) > (?2, ?3)
+                 ORDER BY created_at, rowid LIMIT ?4",
+                ActivitySource::Message,
+                &|row| {
+                    let message = Message {
+                        id: row.get("id")?,
+                        session_id: row.get("session_id")?,
+                        role: row.get("role")?,
+                        content: row.get("content")?,
+                        phase: row.get("phase")?,
+                        tool_calls: row.get("tool_calls")?,
+                        created_at: row.get("created_at")?,
+                        reply_to_message_id: row.get("reply_to_message_id")?,
+                    };
+                    Ok(ActivityItem {
+                        id: format!("message:{}", message.id),
+                        kind: ActivityKind::Message,
+                        created_at: message.created_at,
+                        task_id: None,
+                        message: Some(message),
+                        data: "{}".to_owned(),
+                    })
+                },
+            )?);
+            items.extend(query(
+                &format!(
+                    "SELECT id AS rowid, task_id, kind, data, created_at
+                     FROM task_traces
+                     WHERE session_id = ?1 AND (created_at, id) > (?2, ?3)
+                       AND kind IN ({})
+                     ORDER BY created_at, id LIMIT ?4",
+                    TRACE_ACTIVITY_KINDS
+                        .iter()
+                        .map(|kind| format!("'{kind}'"))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+                ActivitySource::Trace,
+                &|row| {
+                    let kind: String = row.get("kind")?;
+                    Ok(ActivityItem {
+                        id: format!("trace:{}", row.get::<_, i64>("rowid")?),
+                        kind: ActivityKind::from_trace_kind(&kind).unwrap_or(ActivityKind::Phase),
+                        created_at: row.get("created_at")?,
+                        task_id: row.get("task_id")?,
+                        message: None,
+                        data: row.get("data")?,
+                    })
+                },
+            )?);
+            items.extend(query(
+                "SELECT id AS rowid, task_id, rule_name, category, severity, context, created_at
+                 FROM safety_incidents
+                 WHERE session_id = ?1 AND (created_at, id) > (?2, ?3)
+                 ORDER BY created_at, id LIMIT ?4",
+                ActivitySource::Incident,
+                &|row| {
+                    let data = serde_json::json!({
+                        "rule_name": row.get::<_, String>("rule_name")?,
+                        "category": row.get::<_, String>("category")?,
+                        "severity": row.get::<_, String>("severity")?,
+                        "context": row.get::<_, String>("context")?,
+                    });
+                    Ok(ActivityItem {
+                        id: format!("incident:{}", row.get::<_, i64>("rowid")?),
+                        kind: ActivityKind::SafetyIncident,
+                        created_at: row.get("created_at")?,
+                        task_id: row.get("task_id")?,
+                        message: None,
+                        data: data.to_string(),
+                    })
+                },
+            )?);
+            items.extend(query(
+                "SELECT id AS rowid, status, created_at
+                 FROM session_status_changes
+                 WHERE session_id = ?1 AND (created_at, id) > (?2, ?3)
+                 ORDER BY created_at, id LIMIT ?4",
+                ActivitySource::Status,
+                &|row| {
+                    let data = serde_json::json!({ "status": row.get::<_, String>("status")? });
+                    Ok(ActivityItem {
+                        id: format!("status:{}", row.get::<_, i64>("rowid")?),
+                        kind: ActivityKind::StatusChange,
+                        created_at: row.get("created_at")?,
+                        task_id: None,
+                        message: None,
+                        data: data.to_string(),
+                    })
+                },
+            )?);
+        }
+
+        items.sort_by_key(|(cursor, _)| *cursor);
+        let next_cursor =
+            (items.len() > limit as usize).then(|| items[limit as usize - 1].0.encode());
+        items.truncate(limit as usize);
+        let items = items
+            .into_iter()
+            .map(|(_, mut item)| {
+                item.message = item
+                    .message
+                    .map(|message| self.open_message(message))
+                    .transpose()?;
+                match item.kind {
+                    ActivityKind::SafetyIncident => {
+                        let mut data: Value = serde_json::from_str(&item.data).unwrap_or_default();
+                        if let Some(Value::String(context)) = data.get_mut("context") {
+                            *context = self
+                                .open_column(std::mem::take(context), &incident_aad(session_id))?;
+                        }
+                        item.data = data.to_string();
+                    }
+                    ActivityKind::ToolCall | ActivityKind::ToolSkipped | ActivityKind::Phase => {
+                        let task_id = item.task_id.as_deref().unwrap_or_default();
+                        item.data = self.open_column(item.data, &trace_aad(task_id))?;
+                    }
+                    ActivityKind::Message | ActivityKind::StatusChange => {}
+                }
+                Ok(item)
+            })
+            .collect::<CoreResult<_>>()?;
+        Ok(ActivityPage { items, next_cursor })
+    }
+
+    pub fn increment_counter(&self, name: &str, delta: i64) -> CoreResult<()> {
+        let now = self.clock.timestamp();
+        let conn = self.conn()?;
+
+        conn.execute(
+            "INSERT INTO analytics_counters (name, value, updated_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT(name) DO UPDATE SET value = value + excluded.value,
+                                             updated_at = excluded.updated_at",
+            params![name, delta, now],
+        )
+        .map_err(|e| CoreError::Storage(e.to_string()))?;
+        Ok(())
+    }
+
+    pub fn list_counters(&self) -> CoreResult<Vec<(String, i64)>> {
+        let conn = self.conn()?;
+
+        let mut stmt = conn
+            .prepare("SELECT name, value FROM analytics_counters ORDER BY name ASC")
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+
+        let counters = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(|e| CoreError::Storage(e.to_string()))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+
+        Ok(counters)
+    }
+
+    /// Sessions last touched before `cutoff`, excluding archived ones.
+    /// Returns `(session_id, message_count)` pairs.
+    pub fn list_expired_sessions(&self, cutoff: i64) -> CoreResult<Vec<(String, u32)>> {
+        let conn = self.conn()?;
+
+        let mut stmt = conn
+            .prepare(
+                "SELECT s.id, (SELECT COUNT(*) FROM messages m WHERE m.session_id = s.id)
+                 FROM sessions s
+                 WHERE s.updated_at < ?1 AND s.status != 'archived' AND s.pinned = 0
+                 ORDER BY s.updated_at ASC",
+            )
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+
+        let sessions = stmt
+            .query_map(params![cutoff], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(|e| CoreError::Storage(e.to_string()))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+
+        Ok(sessions)
+    }
+
+    pub fn count_logs_before(&self, cutoff: i64) -> CoreResult<u32> {
+        let conn = self.conn()?;
+
+        conn.query_row(
+            "SELECT COUNT(*) FROM logs WHERE created_at < ?1",
+            params![cutoff],
+            |row| row.get(0),
+        )
+        .map_err(|e| CoreError::Storage(e.to_string()))
+    }
+
+    /// Move messages, attachments, logs, task traces, safety incidents and
+    /// status changes of `source_id` to `target_id`, apply `intake_writes`
+    /// (`None` deletes the key), drop the source's intake settings and
+    /// archive it, all in one transaction. Indexed case text and session
+    /// settings move only where the target has none of their own.
+    pub fn move_session_records(
+        &self,
+        source_id: &str,
+        target_id: &str,
+        intake_writes: &[(String, Option<String>)],
+    ) -> CoreResult<MovedRecords> {
+        let mut conn = self.conn()?;
+        let tx = conn
+            .savepoint()
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+
+        let moved = |table: &str| {
+            tx.execute(
+                &format!("UPDATE {table} SET session_id = ?1 WHERE session_id = ?2"),
+                params![target_id, source_id],
+            )
+            .map(|count| count as u32)
+            .map_err(|e| CoreError::Storage(e.to_string()))
+        };
+        let records = MovedRecords {
+            messages: moved("messages")?,
+            attachments: moved("attachments")?,
+            logs: moved("logs")?,
+        };
+        moved("task_traces")?;
+        moved("safety_incidents")?;
+        moved("session_status_changes")?;
+
+        tx.execute(
+            "UPDATE case_index SET session_id = ?1
+             WHERE session_id = ?2
+               AND kind NOT IN (SELECT kind FROM case_index WHERE session_id = ?1)",
+            params![target_id, source_id],
+        )
+        .map_err(|e| CoreError::Storage(e.to_string()))?;
+        tx.execute(
+            "DELETE FROM case_index WHERE session_id = ?1",
+            params![source_id],
+        )
+        .map_err(|e| CoreError::Storage(e.to_string()))?;
+        tx.execute(
+            "UPDATE sessions SET updated_at = ?1 WHERE id = ?2",
+            params![self.clock.timestamp(), target_id],
+        )
+        .map_err(|e| CoreError::Storage(e.to_string()))?;
+
+        for (key, value) in intake_writes {
+            match value {
+                Some(value) => tx.execute(
+                    "INSERT INTO settings (key, value) VALUES (?1, ?2)
+                     ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+                    params![key, value],
+                ),
+                None => tx.execute("DELETE FROM settings WHERE key = ?1", params![key]),
+            }
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+        }
+        let source_prefix = format!("session:{source_id}:");
+        let source_pattern = format!("{}%", escape_like(&source_prefix));
+        // Sealed values are bound to their key and cannot move.
+        tx.execute(
+            "INSERT OR IGNORE INTO settings (key, value)
+             SELECT ?1 || substr(key, ?2), value FROM settings
+             WHERE key LIKE ?3 ESCAPE '\\' AND value NOT LIKE ?4",
+            params![
+                format!("session:{target_id}:"),
+                source_prefix.len() as i64 + 1,
+                source_pattern,
+                format!("{SEALED_PREFIX}%")
+            ],
+        )
+        .map_err(|e| CoreError::Storage(e.to_string()))?;
+        for prefix in [format!("intake:{source_id}:"), source_prefix.clone()] {
+            tx.execute(
+                "DELETE FROM settings WHERE key LIKE ?1 ESCAPE '\\'",
+                params![format!("{}%", escape_like(&prefix))],
+            )
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+        }
+        tx.execute(
+            "INSERT INTO session_status_changes (session_id, status, created_at)
+             SELECT id, 'archived', ?1 FROM sessions WHERE id = ?2 AND status != 'archived'",
+            params![self.clock.timestamp(), source_id],
+        )
+        .map_err(|e| CoreError::Storage(e.to_string()))?;
+        let archived = tx
+            .execute(
+                "UPDATE sessions SET status = 'archived', updated_at = ?1 WHERE id = ?2",
+                params![self.clock.timestamp(), source_id],
+            )
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+        if archived == 0 {
+            return Err(CoreError::NotFound(format!("session {source_id}")));
+        }
+
+        tx.commit().map_err(|e| CoreError::Storage(e.to_string()))?;
+        self.cache.invalidate_prefix(&source_prefix);
+        self.cache
+            .invalidate_prefix(&format!("session:{target_id}:"));
+        Ok(records)
+    }
+
+    /// Move the messages `message_ids` of `source_id` to `target_id`, in one
+    /// transaction; ids of other sessions are skipped. Returns how many
+    /// moved.
+    pub fn move_messages(
+        &self,
+        source_id: &str,
+        target_id: &str,
+        message_ids: &[String],
+    ) -> CoreResult<u32> {
+        let mut conn = self.conn()?;
+        let tx = conn
+            .savepoint()
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+
+        let mut moved = 0;
+        {
+            let mut stmt = tx
+                .prepare("UPDATE messages SET session_id = ?1 WHERE id = ?2 AND session_id = ?3")
+                .map_err(|e| CoreError::Storage(e.to_string()))?;
+            for message_id in message_ids {
+                moved +=
+                    stmt.execute(params![target_id, message_id, source_id])
+                        .map_err(|e| CoreError::Storage(e.to_string()))? as u32;
+            }
+        }
+        let now = self.clock.timestamp();
+        for session_id in [source_id, target_id] {
+            tx.execute(
+                "UPDATE sessions SET updated_at = ?1 WHERE id = ?2",
+                params![now, session_id],
+            )
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+        }
+
+        tx.commit().map_err(|e| CoreError::Storage(e.to_string()))?;
+        Ok(moved)
+    }
+
+    /// Delete the given sessions (messages cascade) together with their
+    /// `intake:{id}:*` and `session:{id}:*` settings, in one transaction.
+    pub fn purge_sessions(&self, session_ids: &[String]) -> CoreResult<u32> {
+        let mut conn = self.conn()?;
+        let tx = conn
+            .savepoint()
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+
+        let mut deleted = 0;
+        for session_id in session_ids {
+            deleted += tx
+                .execute("DELETE FROM sessions WHERE id = ?1", params![session_id])
+                .map_err(|e| CoreError::Storage(e.to_string()))? as u32;
+            for prefix in ["intake", "session"] {
+                tx.execute(
+                    "DELETE FROM settings WHERE key LIKE ?1 ESCAPE '\\'",
+                    params![format!("{prefix}:{}:%", escape_like(session_id))],
+                )
+                .map_err(|e| CoreError::Storage(e.to_string()))?;
+            }
+        }
+
+        tx.commit().map_err(|e| CoreError::Storage(e.to_string()))?;
+        for session_id in session_ids {
+            self.cache
+                .invalidate_prefix(&format!("session:{session_id}:"));
+        }
+        Ok(deleted)
+    }
+
+    /// Row counts of the data `erase_user_data` deletes; `file_count` is
+    /// left at 0.
+    pub fn count_user_data(&self) -> CoreResult<ErasureSummary> {
+        let conn = self.conn()?;
+        let count = |sql: &str| -> CoreResult<u32> {
+            conn.query_row(sql, [], |row| row.get(0))
+                .map_err(|e| CoreError::Storage(e.to_string()))
+        };
+
+        Ok(ErasureSummary {
+            session_count: count("SELECT COUNT(*) FROM sessions")?,
+            message_count: count("SELECT COUNT(*) FROM messages")?,
+            attachment_count: count("SELECT COUNT(*) FROM attachments")?,
+            fact_count: count("SELECT COUNT(*) FROM settings WHERE key LIKE 'intake:%:answer:%'")?,
+            log_count: count("SELECT COUNT(*) FROM logs")?,
+            trace_count: count("SELECT COUNT(*) FROM task_traces")?,
+            record_count: count(
+                "SELECT (SELECT COUNT(*) FROM query_log)
+                      + (SELECT COUNT(*) FROM safety_incidents)
+                      + (SELECT COUNT(*) FROM session_status_changes)
+                      + (SELECT COUNT(*) FROM event_history)
+                      + (SELECT COUNT(*) FROM kb_usage)
+                      + (SELECT COUNT(*) FROM analytics_counters)",
+            )?,
+            file_count: 0,
+        })
+    }
+
+    /// Delete every session of every profile (messages, attachments and
+    /// the case index with them), all `intake:*` and `session:*` settings,
+    /// and the logs, traces and usage records, in one transaction. Returns
+    /// the counts of what was deleted.
+    pub fn erase_user_data(&self) -> CoreResult<ErasureSummary> {
+        self.with_tx(|| {
+            let summary = self.count_user_data()?;
+            self.conn()?
+                .execute_batch(
+                    "DELETE FROM sessions;
+                     DELETE FROM messages;
+                     DELETE FROM attachments;
+                     DELETE FROM case_index;
+                     DELETE FROM task_traces;
+                     DELETE FROM settings WHERE key LIKE 'intake:%' OR key LIKE 'session:%';
+                     DELETE FROM logs;
+                     DELETE FROM query_log;
+                     DELETE FROM safety_incidents;
+                     DELETE FROM session_status_changes;
+                     DELETE FROM event_history;
+                     DELETE FROM kb_usage;
+                     DELETE FROM analytics_counters;",
+                )
+                .map_err(|e| CoreError::Storage(e.to_string()))?;
+            self.cache.invalidate_prefix("session:");
+            Ok(summary)
+        })
+    }
+
+    /// `PRAGMA user_version`: `SCHEMA_VERSION` once migrated, `0` for a
+    /// database from before versioning that a read-only core opened.
+    pub fn schema_version(&self) -> CoreResult<u32> {
+        self.conn()?
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .map_err(|e| CoreError::Storage(e.to_string()))
+    }
+
+    /// Whether a write transaction can be opened right now.
+    pub fn probe_writable(&self) -> CoreResult<()> {
+        self.with_tx(|| Ok(()))
+    }
+
+    /// Rebuild the database file, so deleted rows do not linger in free
+    /// pages.
+    pub fn vacuum(&self) -> CoreResult<()> {
+        self.conn()?
+            .execute_batch("VACUUM")
+            .map_err(|e| CoreError::Storage(e.to_string()))
+    }
+
+    /// Replace the searchable `kind` text of a session (one entry per kind).
+    /// Nothing is indexed while content encryption is on.
+    pub fn index_case_text(&self, session_id: &str, kind: &str, content: &str) -> CoreResult<()> {
+        if self.content_encryption() {
+            return Ok(());
+        }
+        let mut conn = self.conn()?;
+        let tx = conn
+            .savepoint()
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+
+        tx.execute(
+            "DELETE FROM case_index WHERE session_id = ?1 AND kind = ?2",
+            params![session_id, kind],
+        )
+        .map_err(|e| CoreError::Storage(e.to_string()))?;
+        tx.execute(
+            "INSERT INTO case_index (session_id, kind, content, updated_at)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![session_id, kind, content, self.clock.timestamp()],
+        )
+        .map_err(|e| CoreError::Storage(e.to_string()))?;
+
+        tx.commit().map_err(|e| CoreError::Storage(e.to_string()))
+    }
+
+    /// Search indexed reports and facts of `profile_id`'s sessions, best
+    /// match first. The trigram index needs at least three characters;
+    /// shorter queries fall back to a substring scan.
+    pub fn search_case_index(
+        &self,
+        query: &str,
+        limit: u32,
+        profile_id: &str,
+    ) -> CoreResult<Vec<SessionSearchHit>> {
+        let query = query.trim();
+        if query.is_empty() {
+            return Ok(Vec::new());
+        }
+        if self.content_encryption() {
+            return Err(CoreError::InvalidState(
+                "session search is off while message content is encrypted".to_owned(),
+            ));
+        }
+
+        let conn = self.conn()?;
+
+        let map_row = |row: &rusqlite::Row<'_>| {
+            Ok(SessionSearchHit {
+                session_id: row.get(0)?,
+                session_title: row.get(1)?,
+                kind: row.get(2)?,
+                snippet: row.get(3)?,
+                updated_at: row.get(4)?,
+            })
+        };
+
+        let hits = if query.chars().count() >= 3 {
+            let mut stmt = conn
+                .prepare(
+                    "SELECT c.session_id, s.title, c.kind,
+                            snippet(case_index, 2, '**', '**', '…', 24), c.updated_at
+                     FROM case_index c JOIN sessions s ON s.id = c.session_id
+                     WHERE case_index MATCH ?1 AND s.profile_id = ?3
+                     ORDER BY rank LIMIT ?2",
+                )
+                .map_err(|e| CoreError::Storage(e.to_string()))?;
+            let phrase = format!("\"{}\"", query.replace('"', "\"\""));
+            let rows = stmt
+                .query_map(params![phrase, limit, profile_id], map_row)
+                .map_err(|e| CoreError::Storage(e.to_string()))?;
+            rows.collect::<Result<Vec<_>, _>>()
+                .map_err(|e| CoreError::Storage(e.to_string()))?
+        } else {
+            let mut stmt = conn
+                .prepare(
+                    "SELECT c.session_id, s.title, c.kind, c.content, c.updated_at
+                     FROM case_index c JOIN sessions s ON s.id = c.session_id
+                     WHERE c.content LIKE ?1 ESCAPE '\\' AND s.profile_id = ?3
+                     ORDER BY c.updated_at DESC LIMIT ?2",
+                )
+                .map_err(|e| CoreError::Storage(e.to_string()))?;
+            let pattern = format!("%{}%", escape_like(query));
+            let rows = stmt
+                .query_map(params![pattern, limit, profile_id], map_row)
+                .map_err(|e| CoreError::Storage(e.to_string()))?;
+            rows.map(|row| {
+                row.map(|mut hit| {
+                    hit.snippet = snippet_around(&hit.snippet, query);
+                    hit
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| CoreError::Storage(e.to_string()))?
+        };
+
+        Ok(hits)
+    }
+
+    pub fn purge_logs_before(&self, cutoff: i64) -> CoreResult<u32> {
+        let conn = self.conn()?;
+
+        let deleted = conn
+            .execute("DELETE FROM logs WHERE created_at < ?1", params![cutoff])
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+        Ok(deleted as u32)
+    }
+
+    /// Count task traces older than `cutoff` outside archived and pinned
+    /// sessions.
+    pub fn count_task_traces_before(&self, cutoff: i64) -> CoreResult<u32> {
+        let conn = self.conn()?;
+
+        conn.query_row(
+            &format!("SELECT COUNT(*) FROM task_traces WHERE {EXPIRED_TRACE_FILTER}"),
+            params![cutoff],
+            |row| row.get(0),
+        )
+        .map_err(|e| CoreError::Storage(e.to_string()))
+    }
+
+    pub fn purge_task_traces_before(&self, cutoff: i64) -> CoreResult<u32> {
+        let conn = self.conn()?;
+
+        let deleted = conn
+            .execute(
+                &format!("DELETE FROM task_traces WHERE {EXPIRED_TRACE_FILTER}"),
+                params![cutoff],
+            )
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+        Ok(deleted as u32)
+    }
+
+    pub fn reset_counters(&self) -> CoreResult<()> {
+        let conn = self.conn()?;
+
+        conn.execute("DELETE FROM analytics_counters", [])
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+        Ok(())
+    }
+
+    /// Count one retrieval hit per chunk of `hits` and one citation per
+    /// chunk of `cited`, in one transaction.
+    pub fn record_kb_usage(&self, hits: &[String], cited: &[String]) -> CoreResult<()> {
+        let now = self.clock.timestamp();
+        let mut conn = self.conn()?;
+        let tx = conn
+            .savepoint()
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+
+        for (chunk_ids, hit, citation) in [(hits, 1, 0), (cited, 0, 1)] {
+            for chunk_id in chunk_ids {
+                tx.execute(
+                    "INSERT INTO kb_usage (chunk_id, hits, citations, updated_at)
+                     VALUES (?1, ?2, ?3, ?4)
+                     ON CONFLICT(chunk_id) DO UPDATE SET hits = hits + excluded.hits,
+                                                         citations = citations + excluded.citations,
+                                                         updated_at = excluded.updated_at",
+                    params![chunk_id, hit, citation, now],
+                )
+                .map_err(|e| CoreError::Storage(e.to_string()))?;
+            }
+        }
+
+        tx.commit().map_err(|e| CoreError::Storage(e.to_string()))
+    }
+
+    /// `(chunk_id, hits, citations)` of every chunk used so far.
+    pub fn list_kb_usage(&self) -> CoreResult<Vec<(String, i64, i64)>> {
+        let conn = self.conn()?;
+
+        let mut stmt = conn
+            .prepare("SELECT chunk_id, hits, citations FROM kb_usage ORDER BY chunk_id ASC")
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+
+        let usage = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .map_err(|e| CoreError::Storage(e.to_string()))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+
+        Ok(usage)
+    }
+
+    pub fn reset_kb_usage(&self) -> CoreResult<()> {
+        let conn = self.conn()?;
+
+        conn.execute("DELETE FROM kb_usage", [])
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+        Ok(())
+    }
+
+    /// Append a query log entry and return its id.
+    pub fn append_query_log(
+        &self,
+        query_hash: &str,
+        query_text: Option<&str>,
+        scenario: &str,
+        result_count: u32,
+        used_chunk_ids: &[String],
+    ) -> CoreResult<i64> {
+        let now = self.clock.timestamp();
+        let used = Value::from(used_chunk_ids.to_vec()).to_string();
+        let cipher = self.sealing_cipher()?;
+        let query_text = query_text
+            .map(|text| seal_content(cipher.as_deref(), text, &query_log_aad(query_hash)))
+            .transpose()?;
+        let conn = self.conn()?;
+
+        conn.execute(
+            "INSERT INTO query_log (query_hash, query_text, scenario, result_count, used_chunk_ids, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![query_hash, query_text, scenario, result_count, used, now],
+        )
+        .map_err(|e| CoreError::Storage(e.to_string()))?;
+
+        Ok(conn.last_insert_rowid())
+    }
+
+    /// Drop query log entries older than `max_age_secs` and all but the
+    /// newest `keep`.
+    pub fn trim_query_log(&self, keep: u32, max_age_secs: i64) -> CoreResult<()> {
+        let now = self.clock.timestamp();
+        let conn = self.conn()?;
+
+        conn.execute(
+            "DELETE FROM query_log
+             WHERE created_at < ?1
+                OR id <= (SELECT MAX(id) FROM query_log) - ?2",
+            params![now - max_age_secs, keep],
+        )
+        .map_err(|e| CoreError::Storage(e.to_string()))?;
+        Ok(())
     }
 
-    pub fn append_log(
-        &self,
-        level: &str,
-        message: &str,
-        session_id: Option<&str>,
-    ) -> CoreResult<i64> {
-        let now = Utc::now().timestamp();
-        let conn = self
-            .conn
-            .lock()
-            .map_err(|_| CoreError::Storage("storage lock poisoned".to_owned()))?;
+    /// Add `chunk_id` to the used chunks of entry `id` unless already there.
+    pub fn mark_query_log_used(&self, id: i64, chunk_id: &str) -> CoreResult<()> {
+        let conn = self.conn()?;
 
         conn.execute(
-            "INSERT INTO logs (level, message, session_id, created_at) VALUES (?1, ?2, ?3, ?4)",
-            params![level, message, session_id, now],
+            "UPDATE query_log SET used_chunk_ids = json_insert(used_chunk_ids, '$[#]', ?2)
+             WHERE id = ?1
+               AND NOT EXISTS (SELECT 1 FROM json_each(used_chunk_ids) WHERE value = ?2)",
+            params![id, chunk_id],
         )
         .map_err(|e| CoreError::Storage(e.to_string()))?;
-
-        Ok(conn.last_insert_rowid())
+        Ok(())
     }
 
-    pub fn list_logs(&self, limit: u32) -> CoreResult<Vec<LogEntry>> {
-        let conn = self
-            .conn
-            .lock()
-            .map_err(|_| CoreError::Storage("storage lock poisoned".to_owned()))?;
+    /// Query log entries with `created_at >= since`, oldest first.
+    pub fn list_query_log(&self, since: i64) -> CoreResult<Vec<QueryLogEntry>> {
+        let conn = self.conn()?;
 
         let mut stmt = conn
             .prepare(
-                "SELECT id, level, message, session_id, created_at
-                 FROM logs ORDER BY id DESC LIMIT ?1",
+                "SELECT id, query_hash, query_text, scenario, result_count, used_chunk_ids, created_at
+                 FROM query_log WHERE created_at >= ?1 ORDER BY id ASC",
             )
             .map_err(|e| CoreError::Storage(e.to_string()))?;
 
-        let logs = stmt
-            .query_map(params![limit], |row| {
-                Ok(LogEntry {
+        let entries = stmt
+            .query_map(params![since], |row| {
+                let used: String = row.get(5)?;
+                Ok(QueryLogEntry {
                     id: row.get(0)?,
-                    level: row.get(1)?,
-                    message: row.get(2)?,
-                    session_id: row.get(3)?,
-                    created_at: row.get(4)?,
+                    query_hash: row.get(1)?,
+                    query_text: row.get(2)?,
+                    scenario: row.get(3)?,
+                    result_count: row.get(4)?,
+                    used_chunk_ids: serde_json::from_str(&used).unwrap_or_default(),
+                    created_at: row.get(6)?,
                 })
             })
             .map_err(|e| CoreError::Storage(e.to_string()))?
             .collect::<Result<Vec<_>, _>>()
             .map_err(|e| CoreError::Storage(e.to_string()))?;
 
-        Ok(logs)
+        entries
+            .into_iter()
+            .map(|mut entry: QueryLogEntry| {
+                entry.query_text = entry
+                    .query_text
+                    .map(|text| self.open_column(text, &query_log_aad(&entry.query_hash)))
+                    .transpose()?;
+                Ok(entry)
+            })
+            .collect()
+    }
+
+    pub fn clear_query_log(&self) -> CoreResult<()> {
+        let conn = self.conn()?;
+
+        conn.execute("DELETE FROM query_log", [])
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+        Ok(())
     }
 }
 
+/// Version `migrate` brings a database to, kept in `PRAGMA user_version`.
+/// Bump it with every schema change.
+pub const SCHEMA_VERSION: u32 = 2;
+
 fn migrate(conn: &Connection) -> CoreResult<()> {
     conn.execute_batch(
         r#"
@@ -396,9 +2494,22 @@ fn migrate(conn: &Connection) -> CoreResult<()> {
             scenario TEXT NOT NULL DEFAULT 'labor',
             created_at INTEGER NOT NULL,
             updated_at INTEGER NOT NULL,
-            status TEXT NOT NULL DEFAULT 'active'
+            status TEXT NOT NULL DEFAULT 'active',
+            pinned INTEGER NOT NULL DEFAULT 0,
+            opposing_party TEXT,
+            claim_amount TEXT,
+            dispute_started_on TEXT,
+            jurisdiction TEXT
+        );
+
+        CREATE TABLE IF NOT EXISTS profiles (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            created_at INTEGER NOT NULL
         );
 
+        INSERT OR IGNORE INTO profiles (id, name, created_at) VALUES ('default', '默认', 0);
+
         CREATE TABLE IF NOT EXISTS messages (
             id TEXT PRIMARY KEY,
             session_id TEXT NOT NULL,
@@ -407,6 +2518,18 @@ fn migrate(conn: &Connection) -> CoreResult<()> {
             phase TEXT,
             tool_calls TEXT,
             created_at INTEGER NOT NULL,
+            reply_to_message_id TEXT,
+            FOREIGN KEY (session_id) REFERENCES sessions(id) ON DELETE CASCADE
+        );
+
+        CREATE TABLE IF NOT EXISTS attachments (
+            id TEXT PRIMARY KEY,
+            session_id TEXT NOT NULL,
+            file_name TEXT NOT NULL,
+            mime_type TEXT NOT NULL,
+            extracted_text TEXT NOT NULL,
+            summary TEXT,
+            created_at INTEGER NOT NULL,
             FOREIGN KEY (session_id) REFERENCES sessions(id) ON DELETE CASCADE
         );
 
@@ -428,32 +2551,245 @@ fn migrate(conn: &Connection) -> CoreResult<()> {
             created_at INTEGER NOT NULL
         );
 
+        CREATE TABLE IF NOT EXISTS analytics_counters (
+            name TEXT PRIMARY KEY,
+            value INTEGER NOT NULL DEFAULT 0,
+            updated_at INTEGER NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS kb_usage (
+            chunk_id TEXT PRIMARY KEY,
+            hits INTEGER NOT NULL DEFAULT 0,
+            citations INTEGER NOT NULL DEFAULT 0,
+            updated_at INTEGER NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS query_log (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            query_hash TEXT NOT NULL,
+            query_text TEXT,
+            scenario TEXT NOT NULL,
+            result_count INTEGER NOT NULL,
+            used_chunk_ids TEXT NOT NULL DEFAULT '[]',
+            created_at INTEGER NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS task_traces (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            task_id TEXT NOT NULL,
+            session_id TEXT NOT NULL,
+            seq INTEGER NOT NULL,
+            kind TEXT NOT NULL,
+            data TEXT NOT NULL,
+            created_at INTEGER NOT NULL
+        );
+
+        CREATE TRIGGER IF NOT EXISTS task_traces_session_deleted
+        AFTER DELETE ON sessions BEGIN
+            DELETE FROM task_traces WHERE session_id = OLD.id;
+        END;
+
+        CREATE TABLE IF NOT EXISTS safety_incidents (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            session_id TEXT NOT NULL,
+            task_id TEXT,
+            rule_name TEXT NOT NULL,
+            category TEXT NOT NULL,
+            severity TEXT NOT NULL,
+            context TEXT NOT NULL,
+            created_at INTEGER NOT NULL
+        );
+
+        CREATE TRIGGER IF NOT EXISTS safety_incidents_session_deleted
+        AFTER DELETE ON sessions BEGIN
+            DELETE FROM safety_incidents WHERE session_id = OLD.id;
+        END;
+
+        CREATE TABLE IF NOT EXISTS session_status_changes (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            session_id TEXT NOT NULL,
+            status TEXT NOT NULL,
+            created_at INTEGER NOT NULL
+        );
+
+        CREATE TRIGGER IF NOT EXISTS session_status_changes_session_deleted
+        AFTER DELETE ON sessions BEGIN
+            DELETE FROM session_status_changes WHERE session_id = OLD.id;
+        END;
+
+        CREATE TABLE IF NOT EXISTS event_history (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            kind TEXT NOT NULL,
+            payload TEXT NOT NULL,
+            timestamp INTEGER NOT NULL
+        );
+
+        CREATE VIRTUAL TABLE IF NOT EXISTS case_index USING fts5(
+            session_id UNINDEXED,
+            kind UNINDEXED,
+            content,
+            updated_at UNINDEXED,
+            tokenize = 'trigram'
+        );
+
+        CREATE TRIGGER IF NOT EXISTS case_index_session_deleted
+        AFTER DELETE ON sessions BEGIN
+            DELETE FROM case_index WHERE session_id = OLD.id;
+        END;
+
         CREATE INDEX IF NOT EXISTS idx_messages_session ON messages(session_id);
+        CREATE INDEX IF NOT EXISTS idx_attachments_session ON attachments(session_id);
         CREATE INDEX IF NOT EXISTS idx_logs_created ON logs(created_at);
+        CREATE INDEX IF NOT EXISTS idx_task_traces_task ON task_traces(task_id);
+        CREATE INDEX IF NOT EXISTS idx_task_traces_session ON task_traces(session_id);
+        CREATE INDEX IF NOT EXISTS idx_safety_incidents_created ON safety_incidents(created_at);
+        CREATE INDEX IF NOT EXISTS idx_session_status_changes_session
+            ON session_status_changes(session_id);
+        CREATE INDEX IF NOT EXISTS idx_event_history_timestamp ON event_history(timestamp);
+        CREATE INDEX IF NOT EXISTS idx_query_log_created ON query_log(created_at);
         "#,
     )
     .map_err(|e| CoreError::Storage(e.to_string()))?;
 
+    // Columns added after the first release; `CREATE TABLE IF NOT EXISTS`
+    // leaves older databases without them.
+    add_column_if_missing(conn, "sessions", "pinned", "INTEGER NOT NULL DEFAULT 0")?;
+    add_column_if_missing(
+        conn,
+        "sessions",
+        "profile_id",
+        "TEXT NOT NULL DEFAULT 'default'",
+    )?;
+    add_column_if_missing(conn, "messages", "reply_to_message_id", "TEXT")?;
+    for column in [
+        "opposing_party",
+        "claim_amount",
+        "dispute_started_on",
+        "jurisdiction",
+    ] {
+        add_column_if_missing(conn, "sessions", column, "TEXT")?;
+    }
+    conn.execute_batch("CREATE INDEX IF NOT EXISTS idx_sessions_profile ON sessions(profile_id);")
+        .map_err(|e| CoreError::Storage(e.to_string()))?;
+    conn.pragma_update(None, "user_version", SCHEMA_VERSION)
+        .map_err(|e| CoreError::Storage(e.to_string()))?;
+
+    Ok(())
+}
+
+fn add_column_if_missing(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> CoreResult<()> {
+    let exists = conn
+        .prepare(&format!("PRAGMA table_info({table})"))
+        .and_then(|mut stmt| {
+            stmt.query_map([], |row| row.get::<_, String>(1))?
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .map_err(|e| CoreError::Storage(e.to_string()))?
+        .iter()
+        .any(|name| name == column);
+
+    if !exists {
+        conn.execute_batch(&format!(
+            "ALTER TABLE {table} ADD COLUMN {column} {definition}"
+        ))
+        .map_err(|e| CoreError::Storage(e.to_string()))?;
+    }
     Ok(())
 }
 
-fn default_permission_for_tool(tool_name: &str) -> &'static str {
+fn row_to_profile(row: &rusqlite::Row<'_>) -> rusqlite::Result<Profile> {
+    Ok(Profile {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        created_at: row.get(2)?,
+    })
+}
+
+fn row_to_session(row: &rusqlite::Row<'_>) -> rusqlite::Result<Session> {
+    Ok(Session {
+        id: row.get(0)?,
+        title: row.get(1)?,
+        scenario: row.get(2)?,
+        created_at: row.get(3)?,
+        updated_at: row.get(4)?,
+        status: row.get(5)?,
+        pinned: row.get(6)?,
+        profile_id: row.get(7)?,
+        metadata: CaseMetadata {
+            opposing_party: row.get(8)?,
+            claim_amount: row.get(9)?,
+            dispute_started_on: row.get(10)?,
+            jurisdiction: row.get(11)?,
+        },
+    })
+}
+
+/// Cut `content` to a short window around the first occurrence of `query`,
+/// marked the same way as the FTS `snippet()` output.
+fn snippet_around(content: &str, query: &str) -> String {
+    const CONTEXT_CHARS: usize = 12;
+
+    let Some(start) = content.find(query) else {
+        return content.chars().take(CONTEXT_CHARS * 2).collect();
+    };
+    let end = start + query.len();
+    let before = content[..start].chars().collect::<Vec<_>>();
+    let after = content[end..].chars().collect::<Vec<_>>();
+
+    let mut snippet = String::new();
+    if before.len() > CONTEXT_CHARS {
+        snippet.push('…');
+    }
+    snippet.extend(&before[before.len().saturating_sub(CONTEXT_CHARS)..]);
+    snippet.push_str("**");
+    snippet.push_str(query);
+    snippet.push_str("**");
+    snippet.extend(after.iter().take(CONTEXT_CHARS));
+    if after.len() > CONTEXT_CHARS {
+        snippet.push('…');
+    }
+    snippet
+}
+
+fn escape_like(raw: &str) -> String {
+    raw.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Permission of `tool_name` until one is set.
+pub fn default_permission_for_tool(tool_name: &str) -> &'static str {
     match tool_name {
-        "cite" | "summarize_facts" | "check_safety" | "suggest_escalation" => "allow",
+        "cite" | "summarize_facts" | "evidence_summary" | "check_safety" | "suggest_escalation"
+        | "venue_lookup" | "glossary_lookup" => "allow",
         _ => "ask",
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
     use tempfile::TempDir;
 
-    use super::SqliteStorage;
+    use super::{
+        default_permission_for_tool, thread_messages, NewMessage, SafetyIncidentFilter,
+        SessionListOptions, SessionSort, Setting, SqliteStorage, DEFAULT_PROFILE_ID,
+    };
+    use crate::clock::{ManualClock, SystemClock};
+    use crate::error::CoreError;
+    use crate::storage::monitor::QueryLimits;
 
     fn make_storage() -> (TempDir, SqliteStorage) {
         let temp_dir = TempDir::new().expect("temp dir");
         let db_path = temp_dir.path().join("core.db");
-        let storage = SqliteStorage::new(db_path).expect("storage");
+        let storage = SqliteStorage::new(db_path, Arc::new(SystemClock)).expect("storage");
         (temp_dir, storage)
     }
 
@@ -462,9 +2798,11 @@ mod tests {
         let (_temp_dir, storage) = make_storage();
 
         let created = storage
-            .create_session("labor", Some("工资拖欠"))
+            .create_session("labor", Some("工资拖欠"), DEFAULT_PROFILE_ID)
             .expect("create session");
-        let listed = storage.list_sessions().expect("list sessions");
+        let listed = storage
+            .list_sessions(DEFAULT_PROFILE_ID, &SessionListOptions::default())
+            .expect("list sessions");
 
         assert_eq!(listed.len(), 1);
         assert_eq!(listed[0].id, created.id);
@@ -479,15 +2817,79 @@ mod tests {
         assert_eq!(updated.title.as_deref(), Some("新标题"));
 
         storage.delete_session(&created.id).expect("delete session");
-        let empty = storage.list_sessions().expect("list sessions");
+        let empty = storage
+            .list_sessions(DEFAULT_PROFILE_ID, &SessionListOptions::default())
+            .expect("list sessions");
         assert!(empty.is_empty());
     }
 
+    #[test]
+    fn list_sessions_sorts_pinned_first_and_filters() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let clock = Arc::new(ManualClock::at_timestamp(1_700_000_000));
+        let storage =
+            SqliteStorage::new(temp_dir.path().join("core.db"), clock.clone()).expect("storage");
+
+        let old = storage
+            .create_session("labor", Some("b 工资"), DEFAULT_PROFILE_ID)
+            .expect("session");
+        clock.advance(std::time::Duration::from_secs(60));
+        let rent = storage
+            .create_session("rental", Some("a 押金"), DEFAULT_PROFILE_ID)
+            .expect("session");
+        clock.advance(std::time::Duration::from_secs(60));
+        let recent = storage
+            .create_session("labor", None, DEFAULT_PROFILE_ID)
+            .expect("session");
+        storage.set_session_pinned(&old.id, true).expect("pin");
+
+        let ids = |options: SessionListOptions| {
+            storage
+                .list_sessions(DEFAULT_PROFILE_ID, &options)
+                .expect("list sessions")
+                .into_iter()
+                .map(|session| session.id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            ids(SessionListOptions::default()),
+            vec![old.id.clone(), recent.id.clone(), rent.id.clone()]
+        );
+        assert_eq!(
+            ids(SessionListOptions {
+                sort: SessionSort::CreatedAt,
+                ..Default::default()
+            }),
+            vec![recent.id.clone(), rent.id.clone(), old.id.clone()]
+        );
+        assert_eq!(
+            ids(SessionListOptions {
+                sort: SessionSort::Title,
+                ..Default::default()
+            }),
+            vec![rent.id.clone(), old.id.clone(), recent.id.clone()]
+        );
+        assert_eq!(
+            ids(SessionListOptions {
+                scenario: Some("labor".to_owned()),
+                status: Some("active".to_owned()),
+                ..Default::default()
+            }),
+            vec![old.id.clone(), recent.id]
+        );
+
+        storage.set_session_pinned(&old.id, false).expect("unpin");
+        let unpinned = storage.get_session(&old.id).expect("get").expect("session");
+        assert!(!unpinned.pinned);
+        assert_eq!(unpinned.updated_at, old.updated_at);
+        assert!(storage.set_session_pinned("missing", true).is_err());
+    }
+
     #[test]
     fn message_crud_works() {
         let (_temp_dir, storage) = make_storage();
         let session = storage
-            .create_session("labor", Some("测试"))
+            .create_session("labor", Some("测试"), DEFAULT_PROFILE_ID)
             .expect("create session");
 
         storage
@@ -500,6 +2902,96 @@ mod tests {
         assert_eq!(messages[0].phase.as_deref(), Some("plan"));
     }
 
+    #[test]
+    fn bulk_messages_are_stored_in_order_in_one_transaction() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let clock = Arc::new(ManualClock::at_timestamp(1_700_000_000));
+        let storage =
+            SqliteStorage::new(temp_dir.path().join("core.db"), clock.clone()).expect("storage");
+        let session = storage
+            .create_session("labor", None, DEFAULT_PROFILE_ID)
+            .expect("create session");
+        clock.advance(Duration::from_secs(60));
+        let message = |role: &str, content: &str, created_at: Option<i64>| NewMessage {
+            role: role.to_owned(),
+            content: content.to_owned(),
+            phase: None,
+            tool_calls: None,
+            created_at,
+        };
+        let batch = (0..1_000)
+            .map(|n| message("user", &format!("旧消息{n}"), Some(1_600_000_000 + n)))
+            .chain([message("assistant", "导入完成", None)])
+            .collect::<Vec<_>>();
+
+        let created = storage
+            .create_messages_bulk(&session.id, &batch)
+            .expect("bulk insert");
+        assert_eq!(created.len(), 1_001);
+        let stored = storage.get_messages(&session.id).expect("list messages");
+        assert_eq!(stored.len(), 1_001);
+        assert_eq!(
+            (stored[0].content.as_str(), stored[0].created_at),
+            ("旧消息0", 1_600_000_000)
+        );
+        assert_eq!(
+            (stored[1_000].content.as_str(), stored[1_000].created_at),
+            ("导入完成", 1_700_000_060)
+        );
+        let session = storage
+            .get_session(&session.id)
+            .expect("get")
+            .expect("session");
+        assert_eq!(session.updated_at, 1_700_000_060);
+
+        assert!(matches!(
+            storage.create_messages_bulk("missing", &batch),
+            Err(CoreError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn replies_are_threaded_under_their_user_message() {
+        let (_temp_dir, storage) = make_storage();
+        let session = storage
+            .create_session("labor", Some("测试"), DEFAULT_PROFILE_ID)
+            .expect("create session");
+
+        let first = storage
+            .create_message(&session.id, "user", "q1", None, None)
+            .expect("first question");
+        storage
+            .create_reply(&session.id, &first.id, "assistant", "a1", Some("draft"))
+            .expect("first answer");
+        let second = storage
+            .create_message(&session.id, "user", "q2", None, None)
+            .expect("second question");
+        storage
+            .create_reply(&session.id, &second.id, "assistant", "a2", Some("draft"))
+            .expect("second answer");
+        storage
+            .create_reply(
+                &session.id,
+                &first.id,
+                "assistant",
+                "a1 again",
+                Some("draft"),
+            )
+            .expect("late answer");
+
+        let messages = storage.get_messages(&session.id).expect("list messages");
+        assert_eq!(
+            messages[1].reply_to_message_id.as_deref(),
+            Some(first.id.as_str())
+        );
+        assert_eq!(messages[0].reply_to_message_id, None);
+        let contents = thread_messages(messages)
+            .into_iter()
+            .map(|message| message.content)
+            .collect::<Vec<_>>();
+        assert_eq!(contents, ["q1", "a1", "a1 again", "q2", "a2"]);
+    }
+
     #[test]
     fn settings_kv_works() {
         let (_temp_dir, storage) = make_storage();
@@ -513,33 +3005,457 @@ mod tests {
         assert!(missing.is_none());
     }
 
+    #[test]
+    fn settings_families_are_listed_and_deleted_by_prefix() {
+        let (_temp_dir, storage) = make_storage();
+        let setting = |key: &str, value: &str| Setting {
+            key: key.to_owned(),
+            value: value.to_owned(),
+        };
+        storage
+            .set_settings_bulk(&[
+                setting("intake:s1:index", "2"),
+                setting("intake:s1:answer:0", "深圳"),
+                setting("intake:s10:index", "1"),
+                setting("intake_s1", "unrelated"),
+            ])
+            .expect("bulk set");
+
+        let family = storage.list_settings("intake:s1:").expect("list");
+        assert_eq!(
+            family,
+            vec![
+                setting("intake:s1:answer:0", "深圳"),
+                setting("intake:s1:index", "2")
+            ]
+        );
+        assert_eq!(storage.list_settings("").expect("list all").len(), 4);
+
+        assert_eq!(storage.delete_settings("intake:s1:").expect("delete"), 2);
+        assert!(storage
+            .list_settings("intake:s1:")
+            .expect("list")
+            .is_empty());
+        assert_eq!(
+            storage.get_setting("intake_s1").expect("get").as_deref(),
+            Some("unrelated")
+        );
+        assert!(storage.delete_settings("").is_err());
+    }
+
+    #[test]
+    fn hot_settings_and_permissions_are_read_through_the_cache() {
+        let (_temp_dir, storage) = make_storage();
+        storage.set_setting("analytics:enabled", "1").expect("set");
+        for _ in 0..2 {
+            assert_eq!(
+                storage
+                    .get_setting("analytics:enabled")
+                    .expect("get")
+                    .as_deref(),
+                Some("1")
+            );
+        }
+        let stats = storage.read_cache_stats();
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+
+        storage.set_setting("analytics:enabled", "0").expect("set");
+        let rolled_back = storage.with_tx(|| {
+            storage.set_setting("analytics:enabled", "2")?;
+            assert_eq!(
+                storage.get_setting("analytics:enabled")?.as_deref(),
+                Some("2")
+            );
+            Err::<(), _>(CoreError::Cancelled)
+        });
+        assert!(rolled_back.is_err());
+        assert_eq!(
+            storage
+                .get_setting("analytics:enabled")
+                .expect("get")
+                .as_deref(),
+            Some("0")
+        );
+
+        assert_eq!(
+            storage.stored_tool_permission("kb_search").expect("get"),
+            None
+        );
+        storage
+            .set_tool_permission("kb_search", "allow")
+            .expect("set permission");
+        assert_eq!(
+            storage
+                .stored_tool_permission("kb_search")
+                .expect("get")
+                .as_deref(),
+            Some("allow")
+        );
+
+        // Written behind the storage's back: stale until invalidated.
+        storage
+            .conn()
+            .expect("conn")
+            .execute(
+                "UPDATE settings SET value = '9' WHERE key = 'analytics:enabled'",
+                [],
+            )
+            .expect("update");
+        assert_eq!(
+            storage
+                .get_setting("analytics:enabled")
+                .expect("get")
+                .as_deref(),
+            Some("0")
+        );
+        assert_eq!(
+            storage
+                .invalidate_cache(Some("analytics:"))
+                .expect("invalidate"),
+            1
+        );
+        assert_eq!(
+            storage
+                .get_setting("analytics:enabled")
+                .expect("get")
+                .as_deref(),
+            Some("9")
+        );
+    }
+
+    #[test]
+    fn failed_transactions_leave_no_writes_behind() {
+        let (_temp_dir, storage) = make_storage();
+        let session = storage
+            .create_session("labor", None, DEFAULT_PROFILE_ID)
+            .expect("session");
+
+        let failed = storage.with_tx(|| {
+            storage.set_setting("intake:s1:answer:0", "深圳")?;
+            storage.create_message(&session.id, "user", "你好", None, None)?;
+            storage.with_tx(|| {
+                storage.set_settings_bulk(&[Setting {
+                    key: "intake:s1:idx".to_owned(),
+                    value: "1".to_owned(),
+                }])
+            })?;
+            storage.create_message("missing", "user", "你好", None, None)
+        });
+        assert!(matches!(failed, Err(CoreError::NotFound(_))));
+        assert!(storage
+            .list_settings("intake:s1:")
+            .expect("list")
+            .is_empty());
+        assert!(storage
+            .get_messages(&session.id)
+            .expect("messages")
+            .is_empty());
+
+        storage
+            .with_tx(|| storage.set_setting("intake:s1:idx", "2"))
+            .expect("commit");
+        // Another thread reads only once the transaction is over.
+        let storage = Arc::new(storage);
+        let mut reader = None;
+        let rolled_back = storage.with_tx(|| {
+            storage.set_setting("intake:s1:idx", "3")?;
+            let storage = storage.clone();
+            reader = Some(std::thread::spawn(move || {
+                storage.get_setting("intake:s1:idx")
+            }));
+            std::thread::sleep(Duration::from_millis(50));
+            Err::<(), _>(CoreError::Cancelled)
+        });
+        assert!(rolled_back.is_err());
+        let reader = reader.expect("reader spawned");
+        assert_eq!(
+            reader.join().expect("reader").expect("get").as_deref(),
+            Some("2")
+        );
+    }
+
     #[test]
     fn tool_permission_default_is_ask() {
         let (_temp_dir, storage) = make_storage();
         let permission = storage
-            .get_tool_permission("kb_search")
-            .expect("get default permission");
-        assert_eq!(permission, "ask");
-
-        let allow_by_default = storage
-            .get_tool_permission("check_safety")
-            .expect("get allow default permission");
-        assert_eq!(allow_by_default, "allow");
+            .stored_tool_permission("kb_search")
+            .expect("get unset permission");
+        assert_eq!(permission, None);
+        assert_eq!(default_permission_for_tool("kb_search"), "ask");
+        assert_eq!(default_permission_for_tool("check_safety"), "allow");
 
         storage
             .set_tool_permission("kb_search", "allow")
             .expect("set permission");
         let updated = storage
-            .get_tool_permission("kb_search")
+            .stored_tool_permission("kb_search")
             .expect("get updated permission");
-        assert_eq!(updated, "allow");
+        assert_eq!(updated.as_deref(), Some("allow"));
+    }
+
+    #[test]
+    fn counters_accumulate_and_reset() {
+        let (_temp_dir, storage) = make_storage();
+        storage
+            .increment_counter("sessions_started", 1)
+            .expect("increment");
+        storage
+            .increment_counter("sessions_started", 2)
+            .expect("increment");
+        storage
+            .increment_counter("reports_generated", 1)
+            .expect("increment");
+
+        let counters = storage.list_counters().expect("list counters");
+        assert_eq!(
+            counters,
+            vec![
+                ("reports_generated".to_owned(), 1),
+                ("sessions_started".to_owned(), 3)
+            ]
+        );
+
+        storage.reset_counters().expect("reset");
+        assert!(storage.list_counters().expect("list counters").is_empty());
+    }
+
+    #[test]
+    fn case_index_search_and_cleanup() {
+        let (_temp_dir, storage) = make_storage();
+        let first = storage
+            .create_session("labor", Some("欠薪"), DEFAULT_PROFILE_ID)
+            .expect("session");
+        let second = storage
+            .create_session("labor", Some("合同"), DEFAULT_PROFILE_ID)
+            .expect("session");
+        storage
+            .index_case_text(
+                &first.id,
+                "report",
+                "公司拖欠工资三个月，建议申请劳动仲裁。",
+            )
+            .expect("index");
+        storage
+            .index_case_text(&first.id, "report", "公司拖欠工资两个月，建议先协商。")
+            .expect("reindex");
+        storage
+            .index_case_text(&second.id, "facts", "未签订书面劳动合同")
+            .expect("index");
+
+        let hits = storage
+            .search_case_index("拖欠工资", 10, DEFAULT_PROFILE_ID)
+            .expect("search");
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].session_id, first.id);
+        assert_eq!(hits[0].session_title.as_deref(), Some("欠薪"));
+        assert!(hits[0].snippet.contains("**拖欠工资**"));
+        assert!(hits[0].snippet.contains("两个月"));
+
+        let short = storage
+            .search_case_index("合同", 10, DEFAULT_PROFILE_ID)
+            .expect("search");
+        assert_eq!(short.len(), 1);
+        assert_eq!(short[0].kind, "facts");
+        assert!(short[0].snippet.contains("**合同**"));
+
+        storage.delete_session(&second.id).expect("delete");
+        assert!(storage
+            .search_case_index("合同", 10, DEFAULT_PROFILE_ID)
+            .expect("search")
+            .is_empty());
+    }
+
+    #[test]
+    fn task_trace_is_sequenced_and_removed_with_session() {
+        let (_temp_dir, storage) = make_storage();
+        let session = storage
+            .create_session("labor", None, DEFAULT_PROFILE_ID)
+            .expect("session");
+        storage
+            .append_task_trace("t1", &session.id, "phase", r#"{"phase":"planning"}"#)
+            .expect("trace");
+        storage
+            .append_task_trace("t1", &session.id, "tool_call", r#"{"tool":"kb_search"}"#)
+            .expect("trace");
+        storage
+            .append_task_trace("t2", &session.id, "phase", r#"{"phase":"planning"}"#)
+            .expect("trace");
+
+        let trace = storage.get_task_trace("t1").expect("trace");
+        assert_eq!(
+            trace.iter().map(|entry| entry.seq).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert_eq!(trace[1].kind, "tool_call");
+        assert_eq!(
+            storage
+                .list_session_traces(&session.id)
+                .expect("traces")
+                .len(),
+            3
+        );
+
+        storage.delete_session(&session.id).expect("delete");
+        assert!(storage.get_task_trace("t1").expect("trace").is_empty());
+    }
+
+    #[test]
+    fn event_payloads_are_sealed_while_encryption_is_on() {
+        use crate::storage::crypto::ContentCipher;
+        use crate::{CoreEvent, EventFilter};
+
+        let (temp_dir, storage) = make_storage();
+        let event = |payload: &str, timestamp| CoreEvent {
+            kind: "message".to_owned(),
+            payload: payload.to_owned(),
+            timestamp,
+        };
+        storage
+            .append_event(&event(r#"{"content":"拖欠工资"}"#, 1), 10)
+            .expect("plain event");
+
+        storage
+            .set_content_cipher(Some(
+                ContentCipher::from_secret("device-key-0123456789abcdef").expect("cipher"),
+            ))
+            .expect("cipher");
+        storage.set_content_encryption(true);
+        storage.seal_plaintext_content().expect("seal");
+        storage
+            .append_event(&event(r#"{"content":"违法解除"}"#, 2), 10)
+            .expect("sealed event");
+
+        let raw = rusqlite::Connection::open(temp_dir.path().join("core.db")).expect("raw db");
+        let stored = raw
+            .prepare("SELECT payload FROM event_history")
+            .and_then(|mut stmt| {
+                stmt.query_map([], |row| row.get::<_, String>(0))?
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .expect("raw events");
+        assert_eq!(stored.len(), 2);
+        assert!(stored
+            .iter()
+            .all(|payload| ContentCipher::is_sealed(payload)));
+
+        let payloads = storage
+            .list_events(0, &EventFilter::default())
+            .expect("events")
+            .into_iter()
+            .map(|event| event.payload)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            payloads,
+            [r#"{"content":"拖欠工资"}"#, r#"{"content":"违法解除"}"#]
+        );
+    }
+
+    #[test]
+    fn sealed_settings_are_sealed_while_encryption_is_on() {
+        use crate::storage::crypto::ContentCipher;
+
+        let (_temp_dir, storage) = make_storage();
+        let key = "intake:s1:pending_transcript";
+        storage.set_sealed_setting(key, "广东深圳").expect("plain");
+        assert_eq!(
+            storage.get_setting(key).expect("raw").as_deref(),
+            Some("广东深圳")
+        );
+
+        storage
+            .set_content_cipher(Some(
+                ContentCipher::from_secret("device-key-0123456789abcdef").expect("cipher"),
+            ))
+            .expect("cipher");
+        storage.set_content_encryption(true);
+        storage.set_sealed_setting(key, "广东深圳").expect("sealed");
+        assert!(ContentCipher::is_sealed(
+            &storage.get_setting(key).expect("raw").expect("stored")
+        ));
+        assert_eq!(
+            storage.get_sealed_setting(key).expect("open").as_deref(),
+            Some("广东深圳")
+        );
+    }
+
+    #[test]
+    fn safety_incidents_are_filtered() {
+        let (_temp_dir, storage) = make_storage();
+        storage
+            .record_safety_incident(
+                "s1",
+                Some("t1"),
+                "must_win",
+                "outcome_promise",
+                "critical",
+                "这个案子包赢",
+            )
+            .expect("record");
+        storage
+            .record_safety_incident(
+                "s2",
+                None,
+                "legal_effect",
+                "legal_effect",
+                "warning",
+                "具有法律效力",
+            )
+            .expect("record");
+
+        let all = storage
+            .list_safety_incidents(&SafetyIncidentFilter::default())
+            .expect("list");
+        assert_eq!(all.len(), 2);
+
+        let critical = storage
+            .list_safety_incidents(&SafetyIncidentFilter {
+                severity: Some("critical".to_owned()),
+                ..Default::default()
+            })
+            .expect("list");
+        assert_eq!(critical.len(), 1);
+        assert_eq!(critical[0].session_id, "s1");
+        assert_eq!(critical[0].task_id.as_deref(), Some("t1"));
+
+        let none = storage
+            .list_safety_incidents(&SafetyIncidentFilter {
+                session_id: Some("s2".to_owned()),
+                category: Some("outcome_promise".to_owned()),
+                ..Default::default()
+            })
+            .expect("list");
+        assert!(none.is_empty());
+    }
+
+    #[test]
+    fn safety_incidents_go_with_their_session() {
+        let (_temp_dir, storage) = make_storage();
+        let session = storage
+            .create_session("labor", Some("事故"), DEFAULT_PROFILE_ID)
+            .expect("session");
+        storage
+            .record_safety_incident(
+                &session.id,
+                None,
+                "must_win",
+                "outcome_promise",
+                "critical",
+                "这个案子包赢",
+            )
+            .expect("record");
+
+        storage.delete_session(&session.id).expect("delete");
+        let left = storage
+            .list_safety_incidents(&SafetyIncidentFilter::default())
+            .expect("list");
+        assert!(left.is_empty());
     }
 
     #[test]
     fn cascade_delete_messages() {
         let (_temp_dir, storage) = make_storage();
         let session = storage
-            .create_session("labor", Some("删除测试"))
+            .create_session("labor", Some("删除测试"), DEFAULT_PROFILE_ID)
             .expect("create session");
         storage
             .create_message(&session.id, "user", "test", None, None)
@@ -549,4 +3465,37 @@ mod tests {
         let messages = storage.get_messages(&session.id).expect("list messages");
         assert!(messages.is_empty());
     }
+
+    #[test]
+    fn runaway_query_is_interrupted_and_logged() {
+        let (_temp_dir, storage) = make_storage();
+        storage
+            .set_query_limits(QueryLimits {
+                timeout: Duration::from_millis(200),
+                slow_threshold: Duration::from_millis(50),
+            })
+            .expect("limits");
+
+        let result: rusqlite::Result<i64> = storage.conn().expect("conn").query_row(
+            "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i > 0)
+             SELECT count(*) FROM n WHERE i = 'x'",
+            [],
+            |row| row.get(0),
+        );
+        assert!(result.is_err());
+
+        // The connection is usable again once the call returns.
+        storage
+            .create_session("labor", None, DEFAULT_PROFILE_ID)
+            .expect("session");
+
+        let stats = storage.slow_query_stats();
+        assert_eq!(stats.interrupted, 1);
+        assert_eq!(stats.slow_queries, 1);
+        assert!(stats.slowest_ms >= 200);
+        assert_eq!(
+            stats.slowest_fingerprint.as_deref(),
+            Some("WITH RECURSIVE n(i) AS (SELECT ? UNION ALL SELECT i + ? FROM n WHERE i > ?) SELECT count(*) FROM n WHERE i = ?")
+        );
+    }
 }
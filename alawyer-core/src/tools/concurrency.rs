@@ -0,0 +1,139 @@
+//! Per-tool limits on parallel runs. Tools that keep state outside their
+//! arguments, such as an index rebuild, may not be safe to run twice at
+//! once; their runs queue here, in arrival order, until a slot is free.
+
+use std::collections::HashMap;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// How many runs of one tool may execute at the same time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, uniffi::Enum)]
+pub enum ToolConcurrency {
+    Unlimited,
+    /// One run at a time.
+    Serialized,
+    /// At most `max_parallel` runs at a time; 0 is read as 1.
+    Limited {
+        max_parallel: u32,
+    },
+}
+
+impl ToolConcurrency {
+    fn slots(self) -> Option<usize> {
+        match self {
+            Self::Unlimited => None,
+            Self::Serialized => Some(1),
+            Self::Limited { max_parallel } => Some(max_parallel.max(1) as usize),
+        }
+    }
+}
+
+#[derive(Default)]
+struct ToolSlots {
+    running: usize,
+    next_seq: u64,
+    /// Sequence numbers of queued runs, oldest first.
+    waiting: Vec<u64>,
+}
+
+/// Slots of every limited tool. Unlimited tools never touch the lock.
+#[derive(Default)]
+pub struct ToolLimiter {
+    state: Mutex<HashMap<String, ToolSlots>>,
+    turn: Condvar,
+}
+
+impl ToolLimiter {
+    /// Wait for a slot to run `tool_name` under `policy`. The permit also
+    /// says how long the run was queued.
+    pub fn acquire(&self, tool_name: &str, policy: ToolConcurrency) -> ToolPermit<'_> {
+        let Some(slots) = policy.slots() else {
+            return ToolPermit {
+                limiter: None,
+                tool_name: String::new(),
+                waited: Duration::ZERO,
+            };
+        };
+        let started = Instant::now();
+        let mut state = self.lock();
+        let tool = state.entry(tool_name.to_owned()).or_default();
+        let seq = tool.next_seq;
+        tool.next_seq += 1;
+        tool.waiting.push(seq);
+        let mut queued = false;
+        loop {
+            let tool = state.get_mut(tool_name).expect("tool slots");
+            if tool.waiting.first() == Some(&seq) && tool.running < slots {
+                tool.waiting.remove(0);
+                tool.running += 1;
+                break;
+            }
+            state = self
+                .turn
+                .wait(state)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            queued = true;
+        }
+        drop(state);
+        // The next queued run may fit in a slot that is still free.
+        self.turn.notify_all();
+        ToolPermit {
+            limiter: Some(self),
+            tool_name: tool_name.to_owned(),
+            waited: if queued {
+                started.elapsed()
+            } else {
+                Duration::ZERO
+            },
+        }
+    }
+
+    /// Runs of `tool_name` queued for a slot.
+    pub fn waiting(&self, tool_name: &str) -> usize {
+        self.lock()
+            .get(tool_name)
+            .map_or(0, |tool| tool.waiting.len())
+    }
+
+    fn release(&self, tool_name: &str) {
+        {
+            let mut state = self.lock();
+            if let Some(tool) = state.get_mut(tool_name) {
+                tool.running -= 1;
+                if tool.running == 0 && tool.waiting.is_empty() {
+                    state.remove(tool_name);
+                }
+            }
+        }
+        self.turn.notify_all();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, ToolSlots>> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// A granted run slot; dropping it lets the next queued run start.
+pub struct ToolPermit<'a> {
+    limiter: Option<&'a ToolLimiter>,
+    tool_name: String,
+    waited: Duration,
+}
+
+impl ToolPermit<'_> {
+    /// Time spent queued; zero when a slot was free or the tool is
+    /// unlimited.
+    pub fn waited(&self) -> Duration {
+        self.waited
+    }
+}
+
+impl Drop for ToolPermit<'_> {
+    fn drop(&mut self) {
+        if let Some(limiter) = self.limiter {
+            limiter.release(&self.tool_name);
+        }
+    }
+}
@@ -0,0 +1,161 @@
+//! Record and replay of tool outputs, so UI flows can be developed and
+//! demoed without a KB or model. Configured through `debug:*` settings,
+//! which are not scoped to a profile:
+//!
+//! - `debug:tool_fixtures`: `record` stores the output of every successful
+//!   tool run in the fixture file, `replay` serves outputs from it instead
+//!   of running tools; anything else is off
+//! - `debug:tool_fixtures_path`: the fixture file, JSON
+//!
+//! Replay prefers a fixture recorded with the same arguments, then the
+//! latest one of the tool. Post-processing runs on replayed outputs as on
+//! real ones.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::{CoreError, CoreResult};
+use crate::storage::SqliteStorage;
+
+use super::{Tool, ToolContext};
+
+/// Settings namespace of developer switches. Not scoped to a profile.
+pub const DEBUG_SETTING_PREFIX: &str = "debug:";
+pub const TOOL_FIXTURES_KEY: &str = "debug:tool_fixtures";
+pub const TOOL_FIXTURES_PATH_KEY: &str = "debug:tool_fixtures_path";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct FixtureFile {
+    runs: Vec<Fixture>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Fixture {
+    tool: String,
+    args: Value,
+    output: Value,
+}
+
+enum Mode {
+    Off,
+    Record(PathBuf),
+    Replay(PathBuf),
+}
+
+/// The fixture file last read or written, by path.
+#[derive(Default)]
+pub(crate) struct ToolFixtures {
+    loaded: Mutex<Option<(PathBuf, FixtureFile)>>,
+}
+
+impl ToolFixtures {
+    /// Run `tool`, record its output or replay one, as the settings say.
+    pub(crate) fn run(&self, tool: &dyn Tool, args: Value, ctx: &ToolContext) -> CoreResult<Value> {
+        match mode(&ctx.storage)? {
+            Mode::Off => tool.run(args, ctx),
+            Mode::Replay(path) => self.replay(&path, tool.name(), &args),
+            Mode::Record(path) => {
+                let output = tool.run(args.clone(), ctx)?;
+                let fixture = Fixture {
+                    tool: tool.name().to_owned(),
+                    args,
+                    output: output.clone(),
+                };
+                // A fixture that cannot be written must not fail the run.
+                if let Err(err) = self.record(&path, fixture) {
+                    tracing::warn!("tool fixture not recorded: {err}");
+                }
+                Ok(output)
+            }
+        }
+    }
+
+    fn replay(&self, path: &Path, tool_name: &str, args: &Value) -> CoreResult<Value> {
+        let loaded = self.load(path, false)?;
+        let (_, file) = loaded.as_ref().expect("fixtures loaded");
+        let runs = file
+            .runs
+            .iter()
+            .rev()
+            .filter(|fixture| fixture.tool == tool_name);
+        runs.clone()
+            .find(|fixture| fixture.args == *args)
+            .or_else(|| runs.clone().next())
+            .map(|fixture| fixture.output.clone())
+            .ok_or_else(|| {
+                CoreError::NotFound(format!(
+                    "no fixture for tool {tool_name} in {}",
+                    path.display()
+                ))
+            })
+    }
+
+    fn record(&self, path: &Path, fixture: Fixture) -> CoreResult<()> {
+        let mut loaded = self.load(path, true)?;
+        let (_, file) = loaded.as_mut().expect("fixtures loaded");
+        file.runs.push(fixture);
+        let encoded = serde_json::to_string_pretty(file)
+            .map_err(|e| CoreError::Unknown(format!("serialize tool fixtures failed: {e}")))?;
+        fs::write(path, encoded).map_err(|e| {
+            CoreError::Storage(format!(
+                "write tool fixtures {} failed: {e}",
+                path.display()
+            ))
+        })
+    }
+
+    /// The fixtures of `path`, read unless already loaded. A missing file
+    /// is empty when `create` is set.
+    fn load(
+        &self,
+        path: &Path,
+        create: bool,
+    ) -> CoreResult<MutexGuard<'_, Option<(PathBuf, FixtureFile)>>> {
+        let mut loaded = self
+            .loaded
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if loaded.as_ref().is_some_and(|(loaded, _)| loaded == path) {
+            return Ok(loaded);
+        }
+        let file = match fs::read_to_string(path) {
+            Ok(raw) => serde_json::from_str(&raw).map_err(|e| {
+                CoreError::Config(format!("invalid tool fixtures {}: {e}", path.display()))
+            })?,
+            Err(err) if create && err.kind() == std::io::ErrorKind::NotFound => {
+                FixtureFile::default()
+            }
+            Err(err) => {
+                return Err(CoreError::Config(format!(
+                    "read tool fixtures {} failed: {err}",
+                    path.display()
+                )))
+            }
+        };
+        *loaded = Some((path.to_owned(), file));
+        Ok(loaded)
+    }
+}
+
+fn mode(storage: &SqliteStorage) -> CoreResult<Mode> {
+    let mode = storage.get_setting(TOOL_FIXTURES_KEY)?;
+    let record = match mode.as_deref() {
+        Some("record") => true,
+        Some("replay") => false,
+        _ => return Ok(Mode::Off),
+    };
+    let path = storage
+        .get_setting(TOOL_FIXTURES_PATH_KEY)?
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .ok_or_else(|| CoreError::Config(format!("{TOOL_FIXTURES_PATH_KEY} is not set")))?;
+    Ok(if record {
+        Mode::Record(path)
+    } else {
+        Mode::Replay(path)
+    })
+}
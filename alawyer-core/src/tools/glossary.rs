@@ -0,0 +1,98 @@
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+/// Heading of the report section explaining the legal terms it uses.
+pub const GLOSSARY_HEADING: &str = "【名词解释】";
+
+/// Terms explained per report; the first ones in the text win.
+const MAX_EXPLAINED_TERMS: usize = 8;
+
+/// A legal term with a plain-language explanation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GlossaryEntry {
+    pub term: String,
+    /// Other ways the term is written, e.g. `经济补偿` for `经济补偿金`.
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    pub explanation: String,
+}
+
+static GLOSSARY: Lazy<Vec<GlossaryEntry>> = Lazy::new(|| {
+    serde_json::from_str(include_str!("../../data/glossary.json")).expect("valid glossary")
+});
+
+/// The entry for `term`, matched against terms and aliases.
+pub fn lookup(term: &str) -> Option<&'static GlossaryEntry> {
+    let term = term.trim();
+    GLOSSARY
+        .iter()
+        .find(|entry| entry.term == term || entry.aliases.iter().any(|alias| alias == term))
+}
+
+/// Entries whose term or an alias appears in `text`, in order of first
+/// appearance.
+pub fn detect_terms(text: &str) -> Vec<&'static GlossaryEntry> {
+    let mut found = GLOSSARY
+        .iter()
+        .filter_map(|entry| {
+            std::iter::once(&entry.term)
+                .chain(&entry.aliases)
+                .filter_map(|key| text.find(key.as_str()))
+                .min()
+                .map(|at| (at, entry))
+        })
+        .collect::<Vec<_>>();
+    found.sort_by_key(|(at, _)| *at);
+    found
+        .into_iter()
+        .take(MAX_EXPLAINED_TERMS)
+        .map(|(_, entry)| entry)
+        .collect()
+}
+
+/// `report` with a 【名词解释】 section explaining `entries`; unchanged when
+/// there are none or the report already has the section.
+pub fn append_glossary(report: String, entries: &[GlossaryEntry]) -> String {
+    if entries.is_empty() || report.contains(GLOSSARY_HEADING) {
+        return report;
+    }
+    let lines = entries
+        .iter()
+        .map(|entry| format!("- {}：{}", entry.term, entry.explanation))
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!("{}\n\n{GLOSSARY_HEADING}\n{lines}", report.trim_end())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{append_glossary, detect_terms, lookup, GLOSSARY_HEADING};
+
+    #[test]
+    fn explains_terms_in_order_of_appearance() {
+        assert_eq!(
+            lookup("经济补偿").map(|entry| entry.term.as_str()),
+            Some("经济补偿金")
+        );
+        assert!(lookup("不存在的术语").is_none());
+
+        let terms = detect_terms("注意仲裁时效。可主张经济补偿，并申请劳动仲裁；时效从离职起算")
+            .into_iter()
+            .map(|entry| entry.term.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(terms, ["仲裁时效", "经济补偿金", "劳动仲裁"]);
+
+        let report = "【结论】\n可申请劳动仲裁。\n".to_owned();
+        let entries = detect_terms(&report)
+            .into_iter()
+            .cloned()
+            .collect::<Vec<_>>();
+        let report = append_glossary(report, &entries);
+        assert!(report.ends_with(&format!(
+            "。\n\n{GLOSSARY_HEADING}\n- 劳动仲裁：{}",
+            entries[0].explanation
+        )));
+        assert_eq!(append_glossary(report.clone(), &entries), report);
+        assert_eq!(append_glossary("无相关术语".to_owned(), &[]), "无相关术语");
+    }
+}
@@ -1,17 +1,271 @@
+pub mod concurrency;
+pub mod fixtures;
+pub mod glossary;
+pub mod permissions;
+pub mod postprocess;
+pub mod stats;
+pub mod venue;
+
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
 
+use once_cell::sync::Lazy;
+use regex::Regex;
 use serde_json::{json, Value};
 
+use crate::agent::{run_what_if, FactOverride};
 use crate::error::{CoreError, CoreResult};
-use crate::retrieval::RetrievalEngine;
+use crate::region::DeploymentRegion;
+use crate::retrieval::temporal::validity_window;
+use crate::retrieval::{merge_query_results, CaseDates, RetrievalEngine};
 use crate::safety::SafetyEngine;
+use crate::storage::SqliteStorage;
+
+use fixtures::ToolFixtures;
+
+pub use concurrency::{ToolConcurrency, ToolLimiter};
+pub use fixtures::DEBUG_SETTING_PREFIX;
+pub use permissions::{
+    resolve_permission, save_seeded_policy, validate_permission, PermissionPolicy,
+};
+pub use postprocess::PostProcessStep;
+pub use stats::{ToolStats, ToolStatsCollector};
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, uniffi::Record)]
 pub struct IntakeQuestion {
     pub id: u32,
+    /// Stable key of the fact this question collects, e.g. `hire_date`.
+    pub field: String,
+    /// Short label for fact cards.
+    pub label: String,
     pub question: String,
     pub required: bool,
+    /// Format the answer should have; `None` accepts anything.
+    #[serde(default)]
+    pub validator: Option<AnswerValidator>,
+}
+
+/// Answers that mean "I don't know" or "skip" are accepted by every
+/// validator; the intake prompt tells users they may answer this way.
+const UNKNOWN_ANSWERS: [&str; 4] = ["暂不清楚", "不清楚", "不确定", "暂无"];
+/// English counterparts of `UNKNOWN_ANSWERS`, compared case-insensitively.
+const UNKNOWN_ANSWERS_EN: [&str; 7] = [
+    "not sure",
+    "don't know",
+    "dont know",
+    "i don't know",
+    "unknown",
+    "no idea",
+    "skip",
+];
+
+/// English wording of the built-in intake questions, by field: label, then
+/// question.
+const QUESTION_TRANSLATIONS_EN: [(&str, &str, &str); 6] = [
+    (
+        "region",
+        "Place of work",
+        "First, where do you mainly work (province/city)? Rules differ between regions.",
+    ),
+    (
+        "hire_date",
+        "Start date and contract",
+        "When did you start the job, roughly? Did you sign a labor contract (an electronic one counts)?",
+    ),
+    (
+        "job_salary",
+        "Job and monthly pay",
+        "What is your job, and roughly how much is your monthly pay (before or after tax)?",
+    ),
+    (
+        "arrears",
+        "Unpaid wages",
+        "For how long have wages gone unpaid, and roughly how much in total? An estimate is fine.",
+    ),
+    (
+        "goal",
+        "Desired outcome",
+        "What outcome do you want most? For example back pay, compensation or a separation certificate.",
+    ),
+    (
+        "evidence",
+        "Evidence",
+        "What materials do you have now? For example a contract, attendance records, pay slips, chat logs or recordings.",
+    ),
+];
+
+static DATE_HINT: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)\d{4}\s*[年./-]|\d{1,2}\s*月|[一二三四五六七八九十]+月|去年|今年|前年|上个?月|年初|年中|年底|年前|年后|\b(?:19|20)\d{2}\b|\b(?:jan|feb|mar|apr|may|jun|jul|aug|sep|oct|nov|dec)[a-z]*\b|\b(?:last|this) (?:year|month)\b|\b(?:years?|months?) ago\b")
+        .expect("valid regex")
+});
+
+static MONEY_HINT: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"[0-9０-９]|[一二两三四五六七八九十]+\s*[百千万]").expect("valid regex")
+});
+
+static DATE_VALUE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(\d{4})\s*[年./-]\s*(?:(\d{1,2})\s*月?)?").expect("valid regex"));
+
+static MONEY_VALUE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(\d+(?:\.\d+)?)\s*(万|千|[kK])?").expect("valid regex"));
+
+/// Words that make a line of evidence worth quoting in a digest.
+const EVIDENCE_KEYWORDS: [&str; 12] = [
+    "未", "拖欠", "欠", "扣", "工资", "加班", "合同", "解除", "辞退", "社保", "赔偿", "入账",
+];
+
+/// Segments quoted per attachment, and characters kept per segment.
+const EVIDENCE_DIGEST_SEGMENTS: usize = 2;
+const EVIDENCE_SEGMENT_CHARS: usize = 60;
+
+/// Per-question answer check used by intake to re-ask on garbage input.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize, uniffi::Enum)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AnswerValidator {
+    /// Mentions a point in time: a year, a month or a relative expression.
+    Date,
+    /// Mentions an amount, in digits or Chinese numerals.
+    Money,
+    /// Contains one of `options`.
+    OneOf { options: Vec<String> },
+    /// At least `chars` non-whitespace characters.
+    MinLength { chars: u32 },
+}
+
+/// Whether `answer` says "I don't know" or asks to skip the question.
+pub fn is_unknown_answer(answer: &str) -> bool {
+    let answer = answer.trim();
+    let bare = answer.trim_matches(|ch: char| ch.is_ascii_punctuation() || "。，！？".contains(ch));
+    UNKNOWN_ANSWERS.contains(&bare)
+        || UNKNOWN_ANSWERS_EN.contains(&bare.to_lowercase().as_str())
+        || answer.contains("跳过")
+}
+
+/// Label and question of the built-in question for `field` in `locale`,
+/// when a translation exists.
+fn question_translation(field: &str, locale: &str) -> Option<(&'static str, &'static str)> {
+    if locale != "en" {
+        return None;
+    }
+    QUESTION_TRANSLATIONS_EN
+        .iter()
+        .find(|(translated, _, _)| *translated == field)
+        .map(|(_, label, question)| (*label, *question))
+}
+
+impl IntakeQuestion {
+    /// The question in `locale`, or as written when it has no translation.
+    pub fn question_in(&self, locale: &str) -> String {
+        question_translation(&self.field, locale).map_or_else(
+            || self.question.clone(),
+            |(_, question)| question.to_owned(),
+        )
+    }
+
+    pub fn label_in(&self, locale: &str) -> String {
+        question_translation(&self.field, locale)
+            .map_or_else(|| self.label.clone(), |(label, _)| label.to_owned())
+    }
+}
+
+impl AnswerValidator {
+    pub fn accepts(&self, answer: &str) -> bool {
+        if is_unknown_answer(answer) {
+            return true;
+        }
+        let answer = answer.trim();
+
+        match self {
+            Self::Date => DATE_HINT.is_match(answer),
+            Self::Money => MONEY_HINT.is_match(answer),
+            Self::OneOf { options } => options
+                .iter()
+                .any(|option| answer.contains(option.as_str())),
+            Self::MinLength { chars } => {
+                answer.chars().filter(|ch| !ch.is_whitespace()).count() >= *chars as usize
+            }
+        }
+    }
+
+    /// Machine-readable form of an answer: `YYYY-MM` (or `YYYY`) for dates,
+    /// yuan for amounts written with digits, the matched option for
+    /// `OneOf`. `None` when nothing can be extracted.
+    pub fn normalize(&self, answer: &str) -> Option<String> {
+        match self {
+            Self::Date => {
+                let captures = DATE_VALUE.captures(answer)?;
+                let year = &captures[1];
+                Some(match captures.get(2) {
+                    Some(month) => format!("{year}-{:0>2}", month.as_str()),
+                    None => year.to_owned(),
+                })
+            }
+            Self::Money => {
+                let captures = MONEY_VALUE.captures(answer)?;
+                let zeros = match captures.get(2).map(|unit| unit.as_str()) {
+                    Some("万") => 4,
+                    Some("千") | Some("k") | Some("K") => 3,
+                    _ => 0,
+                };
+                Some(scale_decimal(&captures[1], zeros))
+            }
+            Self::OneOf { options } => options
+                .iter()
+                .find(|option| answer.contains(option.as_str()))
+                .cloned(),
+            Self::MinLength { .. } => None,
+        }
+    }
+
+    /// Gentle format hint shown when an answer is re-asked, in English for
+    /// `en` and in Chinese otherwise.
+    pub fn hint(&self, locale: &str) -> String {
+        if locale == "en" {
+            return match self {
+                Self::Date => {
+                    "This one needs a rough time, like \"March 2023\" or \"late last year\"."
+                        .to_owned()
+                }
+                Self::Money => {
+                    "This one needs a rough amount, like \"8000 yuan\" or \"about 10,000\"."
+                        .to_owned()
+                }
+                Self::OneOf { options } => {
+                    format!("You can pick one of these: {}.", options.join(", "))
+                }
+                Self::MinLength { .. } => {
+                    "Could you say a bit more? A few more words make the analysis more accurate."
+                        .to_owned()
+                }
+            };
+        }
+        match self {
+            Self::Date => "这题需要一个大概的时间，比如“2023年3月”或“去年年底”。".to_owned(),
+            Self::Money => "这题需要一个大概的金额，比如“8000元”或“一万左右”。".to_owned(),
+            Self::OneOf { options } => format!("这题可以从这些选项里选：{}。", options.join("、")),
+            Self::MinLength { .. } => "可以再具体一点吗？多写几个字能让分析更准确。".to_owned(),
+        }
+    }
+}
+
+/// `number` (digits with an optional fraction) times `10^zeros`, computed on
+/// the digits so that e.g. "2.01万" gives exactly "20100".
+fn scale_decimal(number: &str, zeros: usize) -> String {
+    let (integer, fraction) = number.split_once('.').unwrap_or((number, ""));
+    let shifted = fraction.len().min(zeros);
+    let mut integer = format!("{integer}{}", &fraction[..shifted]);
+    integer.push_str(&"0".repeat(zeros - shifted));
+    let fraction = fraction[shifted..].trim_end_matches('0');
+    let integer = match integer.trim_start_matches('0') {
+        "" => "0",
+        trimmed => trimmed,
+    };
+    if fraction.is_empty() {
+        integer.to_owned()
+    } else {
+        format!("{integer}.{fraction}")
+    }
 }
 
 pub fn intake_questions_for_scenario(scenario: &str) -> Vec<IntakeQuestion> {
@@ -19,36 +273,54 @@ pub fn intake_questions_for_scenario(scenario: &str) -> Vec<IntakeQuestion> {
         "labor" => vec![
             IntakeQuestion {
                 id: 1,
+                field: "region".to_owned(),
+                label: "工作地区".to_owned(),
                 question: "先确认一下，您主要工作地在什么地区（省/市）？不同地区处理口径会有差异。"
                     .to_owned(),
                 required: true,
+                validator: Some(AnswerValidator::MinLength { chars: 2 }),
             },
             IntakeQuestion {
                 id: 2,
+                field: "hire_date".to_owned(),
+                label: "入职时间与合同".to_owned(),
                 question: "您大概什么时候入职的？有没有签劳动合同（电子版也算）？".to_owned(),
                 required: true,
+                validator: Some(AnswerValidator::Date),
             },
             IntakeQuestion {
                 id: 3,
+                field: "job_salary".to_owned(),
+                label: "岗位与月薪".to_owned(),
                 question: "您主要做什么工作？月工资大约多少（税前税后都可以）？".to_owned(),
                 required: true,
+                validator: Some(AnswerValidator::Money),
             },
             IntakeQuestion {
                 id: 4,
+                field: "arrears".to_owned(),
+                label: "欠薪情况".to_owned(),
                 question: "被拖欠工资大概持续多久、总额大约多少？不确定可以先给估算。".to_owned(),
                 required: false,
+                validator: Some(AnswerValidator::Money),
             },
             IntakeQuestion {
                 id: 5,
+                field: "goal".to_owned(),
+                label: "期望结果".to_owned(),
                 question: "您最希望达成的结果是什么？比如补发工资、经济补偿、出具离职证明等。"
                     .to_owned(),
                 required: true,
+                validator: Some(AnswerValidator::MinLength { chars: 2 }),
             },
             IntakeQuestion {
                 id: 6,
+                field: "evidence".to_owned(),
+                label: "证据材料".to_owned(),
                 question: "目前手里有哪些材料？例如合同、考勤、工资流水、聊天记录、录音等。"
                     .to_owned(),
                 required: false,
+                validator: None,
             },
         ],
         _ => vec![],
@@ -59,31 +331,59 @@ pub fn intake_questions_for_scenario(scenario: &str) -> Vec<IntakeQuestion> {
 pub struct ToolContext {
     pub retrieval: Arc<RetrievalEngine>,
     pub safety: Arc<SafetyEngine>,
+    pub storage: Arc<SqliteStorage>,
+    pub region: DeploymentRegion,
+    /// Profile of the session the tool runs for; cross-session lookups stay
+    /// inside it.
+    pub profile_id: String,
 }
 
 pub trait Tool: Send + Sync {
     fn name(&self) -> &'static str;
     fn run(&self, args: Value, ctx: &ToolContext) -> CoreResult<Value>;
+
+    /// Parallel runs the tool tolerates; stateful tools narrow this.
+    fn concurrency(&self) -> ToolConcurrency {
+        ToolConcurrency::Unlimited
+    }
 }
 
 #[derive(Clone)]
 pub struct ToolRegistry {
     tools: HashMap<String, Arc<dyn Tool>>,
+    /// Host overrides of `postprocess::default_chain`, keyed by tool name.
+    post_processing: Arc<RwLock<HashMap<String, Vec<PostProcessStep>>>>,
+    stats: Arc<ToolStatsCollector>,
+    /// Host overrides of `Tool::concurrency`, keyed by tool name.
+    concurrency: Arc<RwLock<HashMap<String, ToolConcurrency>>>,
+    limiter: Arc<ToolLimiter>,
+    fixtures: Arc<ToolFixtures>,
 }
 
 impl ToolRegistry {
     pub fn with_builtins() -> Self {
         let mut registry = Self {
             tools: HashMap::new(),
+            post_processing: Arc::new(RwLock::new(HashMap::new())),
+            stats: Arc::new(ToolStatsCollector::default()),
+            concurrency: Arc::new(RwLock::new(HashMap::new())),
+            limiter: Arc::new(ToolLimiter::default()),
+            fixtures: Arc::new(ToolFixtures::default()),
         };
 
         registry.register(KbSearchTool);
         registry.register(KbReadTool);
+        registry.register(KbListTool);
+        registry.register(SessionSearchTool);
+        registry.register(WhatIfTool);
         registry.register(AskUserTool);
         registry.register(CiteTool);
         registry.register(SummarizeFactsTool);
+        registry.register(EvidenceSummaryTool);
         registry.register(CheckSafetyTool);
         registry.register(SuggestEscalationTool);
+        registry.register(VenueLookupTool);
+        registry.register(GlossaryLookupTool);
         registry
     }
 
@@ -96,7 +396,98 @@ impl ToolRegistry {
             .tools
             .get(tool_name)
             .ok_or_else(|| CoreError::NotFound(format!("tool {tool_name}")))?;
-        tool.run(args, ctx)
+        let permit = self
+            .limiter
+            .acquire(tool_name, self.concurrency(tool_name)?);
+        if !permit.waited().is_zero() {
+            self.stats.record_queued(tool_name, permit.waited());
+        }
+        let started = Instant::now();
+        let output = self.fixtures.run(tool.as_ref(), args, ctx);
+        drop(permit);
+        self.stats
+            .record_run(tool_name, started.elapsed(), output.is_ok());
+        self.stats.persist_if_due(&ctx.storage);
+        let output = output?;
+
+        let steps = self.post_processing(tool_name)?;
+        Ok(postprocess::apply_chain(
+            &steps,
+            tool_name,
+            output,
+            &ctx.safety,
+        ))
+    }
+
+    pub fn stats(&self) -> &ToolStatsCollector {
+        &self.stats
+    }
+
+    /// `ToolStatsCollector::snapshot` with the runs queued right now.
+    pub fn stats_snapshot(&self) -> Vec<ToolStats> {
+        let mut snapshot = self.stats.snapshot();
+        for stats in &mut snapshot {
+            stats.queued_now = self.limiter.waiting(&stats.tool_name) as u32;
+        }
+        snapshot
+    }
+
+    pub fn concurrency(&self, tool_name: &str) -> CoreResult<ToolConcurrency> {
+        let tool = self
+            .tools
+            .get(tool_name)
+            .ok_or_else(|| CoreError::NotFound(format!("tool {tool_name}")))?;
+        let overrides = self
+            .concurrency
+            .read()
+            .map_err(|_| CoreError::InvalidState("concurrency lock poisoned".to_owned()))?;
+        Ok(overrides
+            .get(tool_name)
+            .copied()
+            .unwrap_or_else(|| tool.concurrency()))
+    }
+
+    /// Replace the concurrency policy `tool_name` declares. Runs already
+    /// queued keep the limit they were queued under.
+    pub fn set_concurrency(&self, tool_name: &str, policy: ToolConcurrency) -> CoreResult<()> {
+        if !self.tools.contains_key(tool_name) {
+            return Err(CoreError::NotFound(format!("tool {tool_name}")));
+        }
+        let mut overrides = self
+            .concurrency
+            .write()
+            .map_err(|_| CoreError::InvalidState("concurrency lock poisoned".to_owned()))?;
+        overrides.insert(tool_name.to_owned(), policy);
+        Ok(())
+    }
+
+    pub fn post_processing(&self, tool_name: &str) -> CoreResult<Vec<PostProcessStep>> {
+        let overrides = self
+            .post_processing
+            .read()
+            .map_err(|_| CoreError::InvalidState("post_processing lock poisoned".to_owned()))?;
+        Ok(overrides
+            .get(tool_name)
+            .cloned()
+            .unwrap_or_else(|| postprocess::default_chain(tool_name)))
+    }
+
+    /// Replace the post-processing chain of `tool_name`; an empty list turns
+    /// post-processing off for that tool.
+    pub fn set_post_processing(
+        &self,
+        tool_name: &str,
+        steps: Vec<PostProcessStep>,
+    ) -> CoreResult<()> {
+        if !self.tools.contains_key(tool_name) {
+            return Err(CoreError::NotFound(format!("tool {tool_name}")));
+        }
+        let mut overrides = self
+            .post_processing
+            .write()
+            .map_err(|_| CoreError::InvalidState("post_processing lock poisoned".to_owned()))?;
+        overrides.insert(tool_name.to_owned(), steps);
+        Ok(())
     }
 
     pub fn list_tools(&self) -> Vec<String> {
@@ -106,6 +497,10 @@ impl ToolRegistry {
     }
 }
 
+/// Takes one `query`, or several `queries` whose results are merged (see
+/// `merge_query_results`) into `top_k` in all. With `case_dates`
+/// (`{"earliest": "YYYY-MM-DD", "latest": "YYYY-MM-DD"}`), laws not in force
+/// during the case rank lower; see `RetrievalEngine::search_for_case`.
 struct KbSearchTool;
 impl Tool for KbSearchTool {
     fn name(&self) -> &'static str {
@@ -113,17 +508,47 @@ impl Tool for KbSearchTool {
     }
 
     fn run(&self, args: Value, ctx: &ToolContext) -> CoreResult<Value> {
-        let query = args
-            .get("query")
-            .and_then(Value::as_str)
-            .ok_or_else(|| CoreError::Tool("kb_search missing query".to_owned()))?;
+        let queries = match args.get("queries").and_then(Value::as_array) {
+            Some(queries) => queries
+                .iter()
+                .filter_map(Value::as_str)
+                .filter(|query| !query.trim().is_empty())
+                .collect::<Vec<_>>(),
+            None => args
+                .get("query")
+                .and_then(Value::as_str)
+                .into_iter()
+                .collect(),
+        };
+        if queries.is_empty() {
+            return Err(CoreError::Tool("kb_search missing query".to_owned()));
+        }
         let scenario = args
             .get("scenario")
             .and_then(Value::as_str)
             .unwrap_or("labor");
         let top_k = args.get("top_k").and_then(Value::as_u64).unwrap_or(5) as usize;
+        let case = match args.get("case_dates") {
+            Some(Value::Null) | None => None,
+            Some(dates) => Some(
+                serde_json::from_value::<CaseDates>(dates.clone())
+                    .map_err(|e| CoreError::Tool(format!("kb_search invalid case_dates: {e}")))?,
+            ),
+        };
+        let search = |query: &str| {
+            ctx.retrieval
+                .search_for_case(query, scenario, top_k, case.as_ref())
+        };
 
-        let results = ctx.retrieval.search(query, scenario, top_k)?;
+        let results = if let [query] = queries[..] {
+            search(query)?
+        } else {
+            let batches = queries
+                .iter()
+                .map(|query| search(query))
+                .collect::<CoreResult<Vec<_>>>()?;
+            merge_query_results(batches, top_k)
+        };
         serde_json::to_value(results)
             .map_err(|e| CoreError::Unknown(format!("serialize kb_search result failed: {e}")))
     }
@@ -146,6 +571,86 @@ impl Tool for KbReadTool {
     }
 }
 
+struct KbListTool;
+impl Tool for KbListTool {
+    fn name(&self) -> &'static str {
+        "kb_list"
+    }
+
+    fn run(&self, args: Value, ctx: &ToolContext) -> CoreResult<Value> {
+        let scenario = args.get("scenario").and_then(Value::as_str);
+        let subdir = args.get("subdir").and_then(Value::as_str);
+
+        let files = ctx.retrieval.list_files(scenario, subdir)?;
+        serde_json::to_value(files)
+            .map_err(|e| CoreError::Unknown(format!("serialize kb_list result failed: {e}")))
+    }
+}
+
+/// Look up earlier consultations so the agent can reuse prior reports/facts.
+struct SessionSearchTool;
+impl Tool for SessionSearchTool {
+    fn name(&self) -> &'static str {
+        "session_search"
+    }
+
+    fn run(&self, args: Value, ctx: &ToolContext) -> CoreResult<Value> {
+        let query = args
+            .get("query")
+            .and_then(Value::as_str)
+            .ok_or_else(|| CoreError::Tool("session_search requires query".to_owned()))?;
+        let limit = args.get("limit").and_then(Value::as_u64).unwrap_or(5) as u32;
+
+        let hits = ctx
+            .storage
+            .search_case_index(query, limit, &ctx.profile_id)?;
+        Ok(Value::Array(
+            hits.into_iter()
+                .map(|hit| {
+                    json!({
+                        "session_id": hit.session_id,
+                        "title": hit.session_title,
+                        "kind": hit.kind,
+                        "snippet": hit.snippet
+                    })
+                })
+                .collect(),
+        ))
+    }
+}
+
+/// Hypothetical re-analysis with some facts swapped; nothing is stored.
+struct WhatIfTool;
+impl Tool for WhatIfTool {
+    fn name(&self) -> &'static str {
+        "what_if"
+    }
+
+    fn run(&self, args: Value, ctx: &ToolContext) -> CoreResult<Value> {
+        let session_id = args
+            .get("session_id")
+            .and_then(Value::as_str)
+            .ok_or_else(|| CoreError::Tool("what_if requires session_id".to_owned()))?;
+        let scenario = args
+            .get("scenario")
+            .and_then(Value::as_str)
+            .unwrap_or("labor");
+        let overrides: Vec<FactOverride> =
+            serde_json::from_value(args.get("overrides").cloned().unwrap_or(Value::Null))
+                .map_err(|e| CoreError::Tool(format!("what_if overrides invalid: {e}")))?;
+
+        let answer = run_what_if(
+            &ctx.storage,
+            &ctx.retrieval,
+            session_id,
+            scenario,
+            &overrides,
+            ctx.region,
+        )?;
+        Ok(json!({ "hypothetical": true, "answer": answer }))
+    }
+}
+
 struct AskUserTool;
 impl Tool for AskUserTool {
     fn name(&self) -> &'static str {
@@ -158,13 +663,17 @@ impl Tool for AskUserTool {
             .and_then(Value::as_str)
             .unwrap_or("labor");
         let index = args.get("index").and_then(Value::as_u64).unwrap_or(0) as usize;
+        let locale = args
+            .get("locale")
+            .and_then(Value::as_str)
+            .unwrap_or("zh-CN");
         let questions = intake_questions_for_scenario(scenario);
 
         if let Some(question) = questions.get(index) {
             Ok(json!({
                 "done": false,
                 "id": question.id,
-                "question": question.question,
+                "question": question.question_in(locale),
                 "required": question.required,
                 "current": index + 1,
                 "total": questions.len()
@@ -175,6 +684,8 @@ impl Tool for AskUserTool {
     }
 }
 
+/// Sources with an `effective_date` or `repeal_date` are listed with their
+/// validity window.
 struct CiteTool;
 impl Tool for CiteTool {
     fn name(&self) -> &'static str {
@@ -183,6 +694,7 @@ impl Tool for CiteTool {
 
     fn run(&self, args: Value, _ctx: &ToolContext) -> CoreResult<Value> {
         let mut lines = Vec::new();
+        let mut links = Vec::new();
         if let Some(sources) = args.get("sources").and_then(Value::as_array) {
             for source in sources {
                 let file_path = source
@@ -197,11 +709,26 @@ impl Tool for CiteTool {
                     .get("line_end")
                     .and_then(Value::as_u64)
                     .unwrap_or_default();
-                lines.push(format!("- {}:{}-{}", file_path, line_start, line_end));
+                let window = validity_window(
+                    source.get("effective_date").and_then(Value::as_str),
+                    source.get("repeal_date").and_then(Value::as_str),
+                );
+                lines.push(format!(
+                    "- {}:{}-{}{}",
+                    file_path, line_start, line_end, window
+                ));
+                // Deep link for the UI to open the passage with
+                // `get_knowledge_chunk` and scroll to the heading.
+                if let Some(chunk_id) = source.get("chunk_id").and_then(Value::as_str) {
+                    links.push(json!({
+                        "chunk_id": chunk_id,
+                        "anchor": source.get("anchor").cloned().unwrap_or(Value::Null)
+                    }));
+                }
             }
         }
 
-        Ok(json!({ "citations": lines.join("\n") }))
+        Ok(json!({ "citations": lines.join("\n"), "links": links }))
     }
 }
 
@@ -229,6 +756,91 @@ impl Tool for SummarizeFactsTool {
     }
 }
 
+/// Offline digest of an attachment's extracted text: the lines that mention
+/// dates, amounts or dispute keywords, in document order.
+pub fn summarize_evidence_text(text: &str) -> String {
+    let segments = text
+        .split(['\n', '。', '；', ';'])
+        .map(str::trim)
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>();
+    if segments.is_empty() {
+        return "未识别到文字内容".to_owned();
+    }
+
+    let relevance = |segment: &str| {
+        usize::from(DATE_HINT.is_match(segment))
+            + usize::from(segment.contains('元') || segment.contains('¥'))
+            + usize::from(EVIDENCE_KEYWORDS.iter().any(|word| segment.contains(word)))
+    };
+    let mut ranked = segments
+        .iter()
+        .enumerate()
+        .map(|(index, segment)| (relevance(segment), index))
+        .filter(|(score, _)| *score > 0)
+        .collect::<Vec<_>>();
+    ranked.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    let mut picked = ranked
+        .into_iter()
+        .take(EVIDENCE_DIGEST_SEGMENTS)
+        .map(|(_, index)| index)
+        .collect::<Vec<_>>();
+    if picked.is_empty() {
+        picked.push(0);
+    }
+    picked.sort_unstable();
+
+    picked
+        .into_iter()
+        .map(|index| truncate_chars(segments[index], EVIDENCE_SEGMENT_CHARS))
+        .collect::<Vec<_>>()
+        .join("；")
+}
+
+fn truncate_chars(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((cut, _)) => format!("{}…", &text[..cut]),
+        None => text.to_owned(),
+    }
+}
+
+/// Fills in a summary for every attachment of the session that has none and
+/// returns all digests. Summaries written earlier (e.g. by the model) are
+/// kept as they are.
+struct EvidenceSummaryTool;
+impl Tool for EvidenceSummaryTool {
+    fn name(&self) -> &'static str {
+        "evidence_summary"
+    }
+
+    fn run(&self, args: Value, ctx: &ToolContext) -> CoreResult<Value> {
+        let session_id = args
+            .get("session_id")
+            .and_then(Value::as_str)
+            .ok_or_else(|| CoreError::Tool("evidence_summary requires session_id".to_owned()))?;
+
+        let mut digests = Vec::new();
+        for attachment in ctx.storage.list_attachments(session_id)? {
+            let summary = match attachment.summary {
+                Some(summary) => summary,
+                None => {
+                    let summary = summarize_evidence_text(&attachment.extracted_text);
+                    ctx.storage
+                        .set_attachment_summary(&attachment.id, &summary)?;
+                    summary
+                }
+            };
+            digests.push(json!({
+                "attachment_id": attachment.id,
+                "file_name": attachment.file_name,
+                "summary": summary
+            }));
+        }
+
+        Ok(Value::Array(digests))
+    }
+}
+
 struct CheckSafetyTool;
 impl Tool for CheckSafetyTool {
     fn name(&self) -> &'static str {
@@ -240,8 +852,16 @@ impl Tool for CheckSafetyTool {
             .get("content")
             .and_then(Value::as_str)
             .ok_or_else(|| CoreError::Tool("check_safety missing content".to_owned()))?;
+        // What the user wrote; quotations of it keep their wording.
+        let user_text = args
+            .get("user_text")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .collect::<Vec<_>>();
 
-        let result = ctx.safety.check(content);
+        let result = ctx.safety.check_quoting(content, &user_text);
         serde_json::to_value(result)
             .map_err(|e| CoreError::Unknown(format!("serialize safety result failed: {e}")))
     }
@@ -253,7 +873,7 @@ impl Tool for SuggestEscalationTool {
         "suggest_escalation"
     }
 
-    fn run(&self, args: Value, _ctx: &ToolContext) -> CoreResult<Value> {
+    fn run(&self, args: Value, ctx: &ToolContext) -> CoreResult<Value> {
         let content = args
             .get("content")
             .and_then(Value::as_str)
@@ -264,30 +884,100 @@ impl Tool for SuggestEscalationTool {
             .iter()
             .any(|keyword| content.contains(keyword));
 
-        let message = if need_escalation {
+        let advice = if need_escalation {
             "这个场景风险较高，建议尽快和执业律师一对一确认关键细节。"
         } else {
             "以上建议仅供参考；如果争议金额较大或事实复杂，建议再请执业律师把关。"
         };
+        let resources = ctx.region.escalation_resources();
 
         Ok(json!({
             "need_escalation": need_escalation,
-            "message": message
+            "message": format!("{advice}{resources}"),
+            "resources": resources
         }))
     }
 }
 
+/// Arbitration commission for the region the user gave at intake, from the
+/// dataset bundled with the KB. The dataset only lists labor arbitration
+/// commissions; other scenarios get their general venue advice.
+struct VenueLookupTool;
+impl Tool for VenueLookupTool {
+    fn name(&self) -> &'static str {
+        "venue_lookup"
+    }
+
+    fn run(&self, args: Value, ctx: &ToolContext) -> CoreResult<Value> {
+        let region = args
+            .get("region")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+
+        let scenario = args
+            .get("scenario")
+            .and_then(Value::as_str)
+            .unwrap_or(venue::VENUE_SCENARIO);
+
+        let venues = if scenario == venue::VENUE_SCENARIO {
+            venue::load_venues(ctx.retrieval.kb_root())?
+        } else {
+            Vec::new()
+        };
+        let Some(matched) = venue::match_venue(&venues, region) else {
+            return Ok(json!({
+                "matched": false,
+                "venue": Value::Null,
+                "message": venue::venue_fallback(scenario)
+            }));
+        };
+        Ok(json!({
+            "matched": true,
+            "venue": matched,
+            "message": matched.describe()
+        }))
+    }
+}
+
+/// Plain-language explanations of legal terms from the bundled glossary:
+/// one `term`, or every known term found in `text`.
+struct GlossaryLookupTool;
+impl Tool for GlossaryLookupTool {
+    fn name(&self) -> &'static str {
+        "glossary_lookup"
+    }
+
+    fn run(&self, args: Value, _ctx: &ToolContext) -> CoreResult<Value> {
+        if let Some(text) = args.get("text").and_then(Value::as_str) {
+            return Ok(json!({ "terms": glossary::detect_terms(text) }));
+        }
+        let term = args
+            .get("term")
+            .and_then(Value::as_str)
+            .ok_or_else(|| CoreError::Tool("glossary_lookup needs term or text".to_owned()))?;
+        Ok(json!({ "entry": glossary::lookup(term) }))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::fs;
+    use std::sync::atomic::{AtomicUsize, Ordering};
     use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
 
     use serde_json::{json, Value};
     use tempfile::TempDir;
 
-    use super::{ToolContext, ToolRegistry};
-    use crate::retrieval::RetrievalEngine;
+    use super::fixtures::{TOOL_FIXTURES_KEY, TOOL_FIXTURES_PATH_KEY};
+    use super::{AnswerValidator, Tool, ToolConcurrency, ToolContext, ToolRegistry};
+    use crate::clock::SystemClock;
+    use crate::error::{CoreError, CoreResult};
+    use crate::region::DeploymentRegion;
+    use crate::retrieval::{RetrievalConfig, RetrievalEngine};
     use crate::safety::SafetyEngine;
+    use crate::storage::{SqliteStorage, DEFAULT_PROFILE_ID};
 
     fn make_context() -> (TempDir, ToolContext) {
         let dir = TempDir::new().expect("temp dir");
@@ -302,12 +992,141 @@ mod tests {
         .expect("write file");
 
         let ctx = ToolContext {
-            retrieval: Arc::new(RetrievalEngine::new(root)),
+            retrieval: Arc::new(RetrievalEngine::new(&root, RetrievalConfig::default())),
             safety: Arc::new(SafetyEngine::default()),
+            storage: Arc::new(
+                SqliteStorage::new(root.join("core.db"), Arc::new(SystemClock)).expect("storage"),
+            ),
+            region: DeploymentRegion::default(),
+            profile_id: DEFAULT_PROFILE_ID.to_owned(),
         };
         (dir, ctx)
     }
 
+    /// Records how many of its runs overlap.
+    struct OverlapTool {
+        running: Arc<AtomicUsize>,
+        most: Arc<AtomicUsize>,
+    }
+
+    impl Tool for OverlapTool {
+        fn name(&self) -> &'static str {
+            "rebuild_index"
+        }
+
+        fn run(&self, _args: Value, _ctx: &ToolContext) -> CoreResult<Value> {
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.most.fetch_max(running, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(30));
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            Ok(json!({"rebuilt": true}))
+        }
+
+        fn concurrency(&self) -> ToolConcurrency {
+            ToolConcurrency::Serialized
+        }
+    }
+
+    /// Counts its runs and returns the count.
+    struct CountingTool(Arc<AtomicUsize>);
+
+    impl Tool for CountingTool {
+        fn name(&self) -> &'static str {
+            "count_runs"
+        }
+
+        fn run(&self, _args: Value, _ctx: &ToolContext) -> CoreResult<Value> {
+            Ok(json!({"runs": self.0.fetch_add(1, Ordering::SeqCst) + 1}))
+        }
+    }
+
+    #[test]
+    fn recorded_tool_outputs_are_replayed_without_running_tools() {
+        let (dir, ctx) = make_context();
+        let runs = Arc::new(AtomicUsize::new(0));
+        let mut registry = ToolRegistry::with_builtins();
+        registry.register(CountingTool(runs.clone()));
+        let fixtures = dir.path().join("fixtures.json");
+        let set = |key: &str, value: &str| ctx.storage.set_setting(key, value).expect("set");
+        set(TOOL_FIXTURES_PATH_KEY, &fixtures.to_string_lossy());
+        let run = |args: Value| registry.run("count_runs", args, &ctx);
+
+        set(TOOL_FIXTURES_KEY, "record");
+        let first = run(json!({"q": "a"})).expect("first");
+        let second = run(json!({"q": "b"})).expect("second");
+        assert!(fixtures.exists());
+
+        set(TOOL_FIXTURES_KEY, "replay");
+        assert_eq!(run(json!({"q": "a"})).expect("replay a"), first);
+        assert_eq!(run(json!({"q": "c"})).expect("replay latest"), second);
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        assert!(matches!(
+            registry.run("kb_list", json!({}), &ctx),
+            Err(CoreError::NotFound(_))
+        ));
+
+        set(TOOL_FIXTURES_KEY, "off");
+        assert_eq!(run(json!({"q": "a"})).expect("real run")["runs"], 3);
+    }
+
+    #[test]
+    fn serialized_tool_runs_one_at_a_time_and_reports_queueing() {
+        let (_dir, ctx) = make_context();
+        let most = Arc::new(AtomicUsize::new(0));
+        let mut registry = ToolRegistry::with_builtins();
+        registry.register(OverlapTool {
+            running: Arc::new(AtomicUsize::new(0)),
+            most: most.clone(),
+        });
+        assert_eq!(
+            registry.concurrency("rebuild_index").expect("policy"),
+            ToolConcurrency::Serialized
+        );
+
+        let handles = (0..3)
+            .map(|_| {
+                let registry = registry.clone();
+                let ctx = ctx.clone();
+                thread::spawn(move || registry.run("rebuild_index", json!({}), &ctx))
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().expect("join").expect("run");
+        }
+        assert_eq!(most.load(Ordering::SeqCst), 1);
+        let stats = registry
+            .stats_snapshot()
+            .into_iter()
+            .find(|stats| stats.tool_name == "rebuild_index")
+            .expect("stats");
+        assert_eq!(stats.invocations, 3);
+        assert_eq!((stats.queued_runs, stats.queued_now), (2, 0));
+        assert!(stats.wait_p95_ms >= 20);
+
+        // A host override lets the runs overlap again.
+        registry
+            .set_concurrency(
+                "rebuild_index",
+                ToolConcurrency::Limited { max_parallel: 3 },
+            )
+            .expect("override");
+        most.store(0, Ordering::SeqCst);
+        let handles = (0..3)
+            .map(|_| {
+                let registry = registry.clone();
+                let ctx = ctx.clone();
+                thread::spawn(move || registry.run("rebuild_index", json!({}), &ctx))
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().expect("join").expect("run");
+        }
+        assert!(most.load(Ordering::SeqCst) > 1);
+        assert!(registry
+            .set_concurrency("missing", ToolConcurrency::Serialized)
+            .is_err());
+    }
+
     #[test]
     fn registry_runs_kb_search() {
         let (_dir, ctx) = make_context();
@@ -339,4 +1158,15 @@ mod tests {
             .unwrap_or_default();
         assert!(modified.contains("结果不确定"));
     }
+
+    #[test]
+    fn money_is_normalized_without_rounding_errors() {
+        let normalize = |answer| AnswerValidator::Money.normalize(answer);
+        assert_eq!(normalize("2.01万").as_deref(), Some("20100"));
+        assert_eq!(normalize("1.5k").as_deref(), Some("1500"));
+        assert_eq!(normalize("大约8000元").as_deref(), Some("8000"));
+        assert_eq!(normalize("3.50元").as_deref(), Some("3.5"));
+        assert_eq!(normalize("0.123456万").as_deref(), Some("1234.56"));
+        assert_eq!(normalize("没有"), None);
+    }
 }
@@ -0,0 +1,169 @@
+//! Default tool permissions a deployment sets in a policy file, optionally
+//! per scenario:
+//!
+//! ```json
+//! {
+//!   "defaults": {"kb_read": "ask"},
+//!   "scenarios": {"labor": {"kb_read": "allow"}}
+//! }
+//! ```
+//!
+//! A tool's permission is, first match wins:
+//! 1. the one set with `set_tool_permission`
+//! 2. the policy's entry for the session's scenario
+//! 3. the policy's `defaults`
+//! 4. the defaults seeded by `initialize_first_run`
+//! 5. the built-in default
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{CoreError, CoreResult};
+use crate::storage::{default_permission_for_tool, SqliteStorage};
+
+const PERMISSIONS: [&str; 3] = ["allow", "ask", "deny"];
+
+/// Settings key of the defaults seeded at first run, stored as a policy
+/// without scenario entries. Not scoped to a profile.
+pub const SEEDED_POLICY_KEY: &str = "permissions:seeded";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PermissionPolicy {
+    /// Permission by tool name, for every scenario.
+    #[serde(default)]
+    defaults: HashMap<String, String>,
+    /// Permission by tool name, by scenario.
+    #[serde(default)]
+    scenarios: HashMap<String, HashMap<String, String>>,
+}
+
+impl PermissionPolicy {
+    pub fn load(path: &Path) -> CoreResult<Self> {
+        let raw = fs::read_to_string(path).map_err(|e| {
+            CoreError::Config(format!(
+                "read permission policy {} failed: {e}",
+                path.display()
+            ))
+        })?;
+        let policy = serde_json::from_str::<Self>(&raw)
+            .map_err(|e| CoreError::Config(format!("invalid permission policy: {e}")))?;
+        let entries = policy
+            .defaults
+            .iter()
+            .chain(policy.scenarios.values().flatten());
+        for (tool_name, permission) in entries {
+            validate_permission(tool_name, permission)
+                .map_err(|e| CoreError::Config(format!("invalid permission policy: {e}")))?;
+        }
+        Ok(policy)
+    }
+
+    /// A policy of `defaults` alone, by tool name.
+    pub fn from_defaults(defaults: HashMap<String, String>) -> Self {
+        Self {
+            defaults,
+            scenarios: HashMap::new(),
+        }
+    }
+
+    /// The policy's permission for `tool_name` in `scenario`, if it sets one.
+    pub fn default_for(&self, tool_name: &str, scenario: Option<&str>) -> Option<&str> {
+        scenario
+            .and_then(|scenario| self.scenarios.get(scenario))
+            .and_then(|tools| tools.get(tool_name))
+            .or_else(|| self.defaults.get(tool_name))
+            .map(String::as_str)
+    }
+}
+
+pub fn validate_permission(tool_name: &str, permission: &str) -> CoreResult<()> {
+    if !PERMISSIONS.contains(&permission) {
+        return Err(CoreError::Config(format!(
+            "{tool_name} is {permission}, expected allow, ask or deny"
+        )));
+    }
+    Ok(())
+}
+
+/// Permission of `tool_name` for a session in `scenario`, or outside any
+/// session when `None`.
+pub fn resolve_permission(
+    storage: &SqliteStorage,
+    policy: &PermissionPolicy,
+    tool_name: &str,
+    scenario: Option<&str>,
+) -> CoreResult<String> {
+    if let Some(permission) = storage.stored_tool_permission(tool_name)? {
+        return Ok(permission);
+    }
+    if let Some(permission) = policy.default_for(tool_name, scenario) {
+        return Ok(permission.to_owned());
+    }
+    Ok(seeded_policy(storage)?
+        .default_for(tool_name, scenario)
+        .unwrap_or_else(|| default_permission_for_tool(tool_name))
+        .to_owned())
+}
+
+/// The defaults `initialize_first_run` seeded; empty before.
+pub fn seeded_policy(storage: &SqliteStorage) -> CoreResult<PermissionPolicy> {
+    Ok(storage
+        .get_setting(SEEDED_POLICY_KEY)?
+        .and_then(|raw| serde_json::from_str(&raw).ok())
+        .unwrap_or_default())
+}
+
+pub fn save_seeded_policy(storage: &SqliteStorage, policy: &PermissionPolicy) -> CoreResult<()> {
+    let raw = serde_json::to_string(policy)
+        .map_err(|e| CoreError::Unknown(format!("serialize permission policy failed: {e}")))?;
+    storage.set_setting(SEEDED_POLICY_KEY, &raw)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tempfile::TempDir;
+
+    use super::{resolve_permission, PermissionPolicy};
+    use crate::clock::SystemClock;
+    use crate::storage::SqliteStorage;
+
+    #[test]
+    fn scenario_profile_beats_defaults_and_user_choice_beats_both() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let path = temp_dir.path().join("policy.json");
+        std::fs::write(
+            &path,
+            r#"{
+                "defaults": {"kb_read": "ask", "cite": "deny"},
+                "scenarios": {"labor": {"kb_read": "allow"}}
+            }"#,
+        )
+        .expect("write policy");
+        let policy = PermissionPolicy::load(&path).expect("policy");
+        let storage = SqliteStorage::new(temp_dir.path().join("core.db"), Arc::new(SystemClock))
+            .expect("storage");
+        let resolve = |tool_name: &str, scenario: Option<&str>| {
+            resolve_permission(&storage, &policy, tool_name, scenario).expect("resolve")
+        };
+
+        assert_eq!(resolve("kb_read", Some("labor")), "allow");
+        assert_eq!(resolve("kb_read", Some("rental")), "ask");
+        assert_eq!(resolve("kb_read", None), "ask");
+        assert_eq!(resolve("cite", Some("labor")), "deny");
+        assert_eq!(resolve("check_safety", Some("labor")), "allow");
+
+        storage
+            .set_tool_permission("kb_read", "deny")
+            .expect("set permission");
+        assert_eq!(resolve("kb_read", Some("labor")), "deny");
+
+        std::fs::write(&path, r#"{"defaults": {"kb_read": "sometimes"}}"#).expect("write");
+        assert!(PermissionPolicy::load(&path).is_err());
+    }
+}
@@ -0,0 +1,156 @@
+use serde_json::{Map, Value};
+
+use crate::model::estimate_tokens;
+use crate::safety::SafetyEngine;
+
+/// Object keys whose string values are treated as tool-produced text.
+const TEXT_FIELDS: [&str; 3] = ["content", "snippet", "text"];
+
+/// One step of a tool's output post-processing chain. Steps run in order
+/// inside `ToolRegistry::run`, before the result reaches the agent or events.
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Enum)]
+pub enum PostProcessStep {
+    /// Rewrite text that trips the safety rules and flag it with
+    /// `"safety_adjusted": true`.
+    SafetyScan,
+    /// Cut text to `max_chars` characters and flag it with `"truncated": true`.
+    LengthTrim { max_chars: u32 },
+    /// Add `source_tool` and, for text, an estimated `token_count`.
+    Metadata,
+}
+
+/// Chains applied when the host has not configured a tool explicitly.
+pub fn default_chain(tool_name: &str) -> Vec<PostProcessStep> {
+    match tool_name {
+        "kb_read" => vec![
+            PostProcessStep::SafetyScan,
+            PostProcessStep::LengthTrim { max_chars: 8_000 },
+            PostProcessStep::Metadata,
+        ],
+        "kb_search" | "session_search" => vec![PostProcessStep::SafetyScan],
+        _ => Vec::new(),
+    }
+}
+
+pub fn apply_chain(
+    steps: &[PostProcessStep],
+    tool_name: &str,
+    mut output: Value,
+    safety: &SafetyEngine,
+) -> Value {
+    for step in steps {
+        output = match output {
+            Value::Array(items) => Value::Array(
+                items
+                    .into_iter()
+                    .map(|item| apply_step(step, tool_name, item, safety))
+                    .collect(),
+            ),
+            other => apply_step(step, tool_name, other, safety),
+        };
+    }
+    output
+}
+
+fn apply_step(
+    step: &PostProcessStep,
+    tool_name: &str,
+    value: Value,
+    safety: &SafetyEngine,
+) -> Value {
+    let Value::Object(mut object) = value else {
+        return value;
+    };
+
+    match step {
+        PostProcessStep::SafetyScan => {
+            let mut adjusted = false;
+            for_each_text(&mut object, |text| {
+                let result = safety.check(text);
+                if !result.issues.is_empty() {
+                    *text = result.modified_content;
+                    adjusted = true;
+                }
+            });
+            if adjusted {
+                object.insert("safety_adjusted".to_owned(), Value::Bool(true));
+            }
+        }
+        PostProcessStep::LengthTrim { max_chars } => {
+            let max_chars = *max_chars as usize;
+            let mut truncated = false;
+            for_each_text(&mut object, |text| {
+                if let Some((cut, _)) = text.char_indices().nth(max_chars) {
+                    text.truncate(cut);
+                    truncated = true;
+                }
+            });
+            if truncated {
+                object.insert("truncated".to_owned(), Value::Bool(true));
+            }
+        }
+        PostProcessStep::Metadata => {
+            let mut tokens = None;
+            for_each_text(&mut object, |text| {
+                *tokens.get_or_insert(0) += estimate_tokens(text);
+            });
+            object.insert(
+                "source_tool".to_owned(),
+                Value::String(tool_name.to_owned()),
+            );
+            if let Some(tokens) = tokens {
+                object
+                    .entry("token_count")
+                    .or_insert_with(|| Value::from(tokens));
+            }
+        }
+    }
+
+    Value::Object(object)
+}
+
+fn for_each_text(object: &mut Map<String, Value>, mut f: impl FnMut(&mut String)) {
+    for key in TEXT_FIELDS {
+        if let Some(Value::String(text)) = object.get_mut(key) {
+            f(text);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{apply_chain, default_chain, PostProcessStep};
+    use crate::safety::SafetyEngine;
+
+    #[test]
+    fn kb_read_chain_scans_trims_and_annotates() {
+        let safety = SafetyEngine::default();
+        let output = json!({
+            "file_path": "labor/law.md",
+            "content": format!("这个案子包赢。{}", "条文".repeat(10))
+        });
+
+        let processed = apply_chain(
+            &[
+                PostProcessStep::SafetyScan,
+                PostProcessStep::LengthTrim { max_chars: 10 },
+                PostProcessStep::Metadata,
+            ],
+            "kb_read",
+            output,
+            &safety,
+        );
+
+        let content = processed["content"].as_str().expect("content");
+        assert!(!content.contains("包赢"));
+        assert_eq!(content.chars().count(), 10);
+        assert_eq!(processed["safety_adjusted"], json!(true));
+        assert_eq!(processed["truncated"], json!(true));
+        assert_eq!(processed["source_tool"], json!("kb_read"));
+        assert!(processed["token_count"].as_u64().is_some());
+
+        assert!(default_chain("cite").is_empty());
+    }
+}
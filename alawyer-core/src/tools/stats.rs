@@ -0,0 +1,227 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::storage::SqliteStorage;
+
+/// Settings key holding the persisted counters.
+pub const TOOL_STATS_KEY: &str = "tool_stats";
+
+/// Durations kept per tool for the percentiles; older runs age out.
+const DURATION_SAMPLES: usize = 256;
+
+/// Minimum spacing between writes of the counters to storage.
+const PERSIST_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Execution counters of one tool since stats were first recorded.
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct ToolStats {
+    pub tool_name: String,
+    /// Runs that started, successful or not. Denied calls never start.
+    pub invocations: u64,
+    pub failures: u64,
+    /// Calls refused by a `deny` permission or by the user.
+    pub denials: u64,
+    /// Over the most recent runs; 0 before the first one.
+    pub p50_ms: u64,
+    pub p95_ms: u64,
+    /// Runs that waited for a slot under the tool's concurrency limit.
+    pub queued_runs: u64,
+    /// Over the most recent queued runs; 0 before the first one.
+    pub wait_p95_ms: u64,
+    /// Runs waiting for a slot right now.
+    pub queued_now: u32,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct ToolCounters {
+    invocations: u64,
+    failures: u64,
+    denials: u64,
+    durations_ms: VecDeque<u64>,
+    #[serde(default)]
+    queued_runs: u64,
+    #[serde(default)]
+    waits_ms: VecDeque<u64>,
+}
+
+fn push_sample(samples: &mut VecDeque<u64>, duration: Duration) {
+    if samples.len() == DURATION_SAMPLES {
+        samples.pop_front();
+    }
+    samples.push_back(duration.as_millis() as u64);
+}
+
+fn percentile(samples: &VecDeque<u64>, percent: usize) -> u64 {
+    let mut sorted = samples.iter().copied().collect::<Vec<_>>();
+    if sorted.is_empty() {
+        return 0;
+    }
+    sorted.sort_unstable();
+    // Nearest rank.
+    let rank = (sorted.len() * percent).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+#[derive(Default)]
+struct StatsState {
+    tools: BTreeMap<String, ToolCounters>,
+    dirty: bool,
+    last_persist: Option<Instant>,
+}
+
+/// In-memory per-tool counters, written to storage at most once per
+/// `PERSIST_INTERVAL` so recording stays off the hot path.
+#[derive(Default)]
+pub struct ToolStatsCollector {
+    state: Mutex<StatsState>,
+}
+
+impl ToolStatsCollector {
+    /// Seed the counters from a previous run. Unreadable stats are logged and
+    /// dropped; they are diagnostics, not data worth failing startup over.
+    pub fn load(&self, storage: &SqliteStorage) {
+        let raw = match storage.get_setting(TOOL_STATS_KEY) {
+            Ok(Some(raw)) => raw,
+            Ok(None) => return,
+            Err(err) => {
+                tracing::warn!("failed to load tool stats: {err}");
+                return;
+            }
+        };
+        match serde_json::from_str(&raw) {
+            Ok(tools) => self.lock().tools = tools,
+            Err(err) => tracing::warn!("discarding unreadable tool stats: {err}"),
+        }
+    }
+
+    pub fn record_run(&self, tool_name: &str, duration: Duration, succeeded: bool) {
+        let mut state = self.lock();
+        let counters = state.tools.entry(tool_name.to_owned()).or_default();
+        counters.invocations += 1;
+        if !succeeded {
+            counters.failures += 1;
+        }
+        push_sample(&mut counters.durations_ms, duration);
+        state.dirty = true;
+    }
+
+    /// A run of `tool_name` that waited `waited` for a concurrency slot.
+    pub fn record_queued(&self, tool_name: &str, waited: Duration) {
+        let mut state = self.lock();
+        let counters = state.tools.entry(tool_name.to_owned()).or_default();
+        counters.queued_runs += 1;
+        push_sample(&mut counters.waits_ms, waited);
+        state.dirty = true;
+    }
+
+    pub fn record_denial(&self, tool_name: &str) {
+        let mut state = self.lock();
+        state.tools.entry(tool_name.to_owned()).or_default().denials += 1;
+        state.dirty = true;
+    }
+
+    /// Write the counters if they changed and the last write is older than
+    /// `PERSIST_INTERVAL`.
+    pub fn persist_if_due(&self, storage: &SqliteStorage) {
+        let due = {
+            let state = self.lock();
+            state.dirty
+                && state
+                    .last_persist
+                    .is_none_or(|last| last.elapsed() >= PERSIST_INTERVAL)
+        };
+        if due {
+            self.persist(storage);
+        }
+    }
+
+    pub fn persist(&self, storage: &SqliteStorage) {
+        let raw = {
+            let mut state = self.lock();
+            state.dirty = false;
+            state.last_persist = Some(Instant::now());
+            serde_json::to_string(&state.tools)
+        };
+        let result = match raw {
+            Ok(raw) => storage.set_setting(TOOL_STATS_KEY, &raw),
+            Err(err) => {
+                tracing::warn!("failed to serialize tool stats: {err}");
+                return;
+            }
+        };
+        if let Err(err) = result {
+            self.lock().dirty = true;
+            tracing::warn!("failed to persist tool stats: {err}");
+        }
+    }
+
+    /// Counters of every tool that has run or been denied, by name.
+    /// `queued_now` is left at 0; the registry fills it in.
+    pub fn snapshot(&self) -> Vec<ToolStats> {
+        self.lock()
+            .tools
+            .iter()
+            .map(|(tool_name, counters)| ToolStats {
+                tool_name: tool_name.clone(),
+                invocations: counters.invocations,
+                failures: counters.failures,
+                denials: counters.denials,
+                p50_ms: percentile(&counters.durations_ms, 50),
+                p95_ms: percentile(&counters.durations_ms, 95),
+                queued_runs: counters.queued_runs,
+                wait_p95_ms: percentile(&counters.waits_ms, 95),
+                queued_now: 0,
+            })
+            .collect()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, StatsState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use tempfile::TempDir;
+
+    use super::ToolStatsCollector;
+    use crate::clock::SystemClock;
+    use crate::storage::SqliteStorage;
+
+    #[test]
+    fn counts_runs_and_denials_and_survives_reload() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let storage = SqliteStorage::new(temp_dir.path().join("core.db"), Arc::new(SystemClock))
+            .expect("storage");
+
+        let stats = ToolStatsCollector::default();
+        for ms in 1..=20 {
+            stats.record_run("kb_search", Duration::from_millis(ms), ms != 7);
+        }
+        stats.record_denial("kb_search");
+        stats.record_denial("suggest_escalation");
+        stats.persist_if_due(&storage);
+
+        let reloaded = ToolStatsCollector::default();
+        reloaded.load(&storage);
+        let snapshot = reloaded.snapshot();
+        assert_eq!(snapshot.len(), 2);
+        let search = &snapshot[0];
+        assert_eq!(search.tool_name, "kb_search");
+        assert_eq!(
+            (search.invocations, search.failures, search.denials),
+            (20, 1, 1)
+        );
+        assert_eq!((search.p50_ms, search.p95_ms), (10, 19));
+        assert_eq!(snapshot[1].tool_name, "suggest_escalation");
+        assert_eq!((snapshot[1].invocations, snapshot[1].p95_ms), (0, 0));
+    }
+}
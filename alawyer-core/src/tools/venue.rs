@@ -0,0 +1,146 @@
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{CoreError, CoreResult};
+
+/// Venue dataset, relative to the KB root.
+pub const VENUES_FILE: &str = "data/venues.json";
+
+/// Scenario whose disputes the dataset's commissions take.
+pub const VENUE_SCENARIO: &str = "labor";
+
+/// Shown under 【办理地点】 when the region is unknown or not in the dataset,
+/// and for scenarios the dataset does not cover.
+pub fn venue_fallback(scenario: &str) -> &'static str {
+    match scenario {
+        "labor" => "暂未匹配到你所在地区的仲裁委。一般可向用人单位所在地或劳动合同履行地的劳动人事争议仲裁委员会申请仲裁，具体可拨打 12333 咨询。",
+        "rental" => "租赁纠纷一般可先向房屋所在地的人民调解委员会申请调解，调解不成可向房屋所在地的基层人民法院起诉。",
+        "consumer" => "消费纠纷一般可拨打 12315 或通过全国 12315 平台投诉，也可向经营者所在地或消费行为发生地的基层人民法院起诉。",
+        "family" => "婚姻家庭纠纷一般可向当地人民调解委员会或妇联申请调解，也可向被告住所地的基层人民法院起诉。",
+        "traffic" => "交通事故一般由事故发生地的公安机关交通管理部门处理；赔偿争议可申请调解，或向事故发生地、被告住所地的基层人民法院起诉。",
+        _ => "办理机构因事项而异，可拨打 12348 公共法律服务热线咨询当地应向哪个部门申请。",
+    }
+}
+
+/// Administrative suffixes dropped from dataset keys, so "深圳市" also
+/// matches answers like "在深圳上班".
+const REGION_SUFFIXES: [&str; 7] = ["特别行政区", "自治区", "自治州", "省", "市", "区", "县"];
+
+/// One labor dispute arbitration commission of the dataset.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Venue {
+    pub region: String,
+    /// Other names the region goes by, e.g. districts it covers.
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    pub name: String,
+    pub address: String,
+    pub phone: String,
+    /// Which disputes the commission takes, in plain words.
+    pub jurisdiction: String,
+}
+
+impl Venue {
+    pub fn describe(&self) -> String {
+        format!(
+            "- 机构：{}\n- 地址：{}\n- 电话：{}\n- 管辖：{}",
+            self.name, self.address, self.phone, self.jurisdiction
+        )
+    }
+}
+
+/// Venues bundled with the KB at `kb_root`; none when the KB ships without
+/// a dataset.
+pub fn load_venues(kb_root: &Path) -> CoreResult<Vec<Venue>> {
+    let raw = match fs::read_to_string(kb_root.join(VENUES_FILE)) {
+        Ok(raw) => raw,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => {
+            return Err(CoreError::Storage(format!(
+                "read venue dataset failed: {err}"
+            )))
+        }
+    };
+    serde_json::from_str(&raw).map_err(|e| CoreError::Config(format!("invalid venue dataset: {e}")))
+}
+
+/// The venue whose region or alias appears in `answer`. Addresses run from
+/// province down to district, so the match furthest into the answer wins,
+/// then the longest one.
+pub fn match_venue<'a>(venues: &'a [Venue], answer: &str) -> Option<&'a Venue> {
+    let answer = answer.split_whitespace().collect::<String>();
+    venues
+        .iter()
+        .filter_map(|venue| {
+            std::iter::once(&venue.region)
+                .chain(&venue.aliases)
+                .map(|key| strip_region_suffix(key.trim()))
+                .filter(|key| key.chars().count() >= 2)
+                .filter_map(|key| answer.rfind(key).map(|at| (at, key.len())))
+                .max()
+                .map(|rank| (rank, venue))
+        })
+        .max_by_key(|(rank, _)| *rank)
+        .map(|(_, venue)| venue)
+}
+
+fn strip_region_suffix(key: &str) -> &str {
+    REGION_SUFFIXES
+        .iter()
+        .find_map(|suffix| key.strip_suffix(suffix))
+        .filter(|stripped| !stripped.is_empty())
+        .unwrap_or(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    use super::{load_venues, match_venue, venue_fallback, VENUES_FILE};
+
+    #[test]
+    fn matches_most_specific_region_in_answer() {
+        let dir = TempDir::new().expect("temp dir");
+        assert!(load_venues(dir.path()).expect("no dataset").is_empty());
+
+        let venue = |region: &str, aliases: &[&str]| {
+            json!({
+                "region": region,
+                "aliases": aliases,
+                "name": format!("{region}劳动人事争议仲裁委员会"),
+                "address": "以当地人社部门公布为准",
+                "phone": "12333",
+                "jurisdiction": "用人单位所在地或劳动合同履行地"
+            })
+        };
+        let dataset = json!([
+            venue("广东省", &[]),
+            venue("深圳市", &["鹏城"]),
+            venue("深圳市南山区", &["南山"]),
+        ]);
+        fs::create_dir_all(dir.path().join("data")).expect("data dir");
+        fs::write(dir.path().join(VENUES_FILE), dataset.to_string()).expect("write");
+        let venues = load_venues(dir.path()).expect("load");
+
+        let region = |answer: &str| match_venue(&venues, answer).map(|venue| venue.region.as_str());
+        assert_eq!(region("广东 深圳"), Some("深圳市"));
+        assert_eq!(region("广东省深圳市南山区科技园"), Some("深圳市南山区"));
+        assert_eq!(region("在鹏城上班"), Some("深圳市"));
+        assert_eq!(region("广州"), None);
+        assert_eq!(region("广东"), Some("广东省"));
+        assert_eq!(region(""), None);
+    }
+
+    #[test]
+    fn fallback_advice_follows_the_scenario() {
+        assert!(venue_fallback("labor").contains("仲裁委"));
+        assert!(venue_fallback("consumer").contains("12315"));
+        assert!(!venue_fallback("traffic").contains("仲裁委"));
+        assert!(venue_fallback("unknown").contains("12348"));
+    }
+}
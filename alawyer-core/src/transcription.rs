@@ -0,0 +1,69 @@
+use crate::error::{CoreError, CoreResult};
+use crate::storage::SqliteStorage;
+
+/// Recorded speech handed to the host for transcription.
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Enum)]
+pub enum AudioInput {
+    /// Audio in memory, e.g. straight from the recorder.
+    Bytes { data: Vec<u8>, mime_type: String },
+    /// An audio file the host can read.
+    File { path: String },
+}
+
+/// Host-side speech recognition (SFSpeechRecognizer, Android
+/// SpeechRecognizer, ...). The core never processes audio itself.
+#[uniffi::export(callback_interface)]
+pub trait Transcriber: Send + Sync {
+    /// Text spoken in `audio`, recognized for `locale` (e.g. `zh-CN`);
+    /// `None` when nothing could be recognized.
+    fn transcribe(&self, audio: AudioInput, locale: String) -> Option<String>;
+}
+
+/// A transcribed intake answer waiting for the user to confirm it.
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct IntakeTranscript {
+    pub session_id: String,
+    pub text: String,
+    /// The intake question the answer is for; `None` before intake starts.
+    pub question: Option<String>,
+}
+
+fn pending_transcript_key(session_id: &str) -> String {
+    format!("intake:{session_id}:pending_transcript")
+}
+
+pub fn transcribe(
+    transcriber: &dyn Transcriber,
+    audio: AudioInput,
+    locale: &str,
+) -> CoreResult<String> {
+    if let AudioInput::Bytes { data, .. } = &audio {
+        if data.is_empty() {
+            return Err(CoreError::Config("audio is empty".to_owned()));
+        }
+    }
+    transcriber
+        .transcribe(audio, locale.to_owned())
+        .map(|text| text.trim().to_owned())
+        .filter(|text| !text.is_empty())
+        .ok_or_else(|| CoreError::InvalidState("no speech recognized".to_owned()))
+}
+
+/// Keep `text` until the user confirms or re-records it; one per session.
+/// It is sealed while message content encryption is on, like the answer it
+/// becomes.
+pub fn save_pending_transcript(
+    storage: &SqliteStorage,
+    session_id: &str,
+    text: &str,
+) -> CoreResult<()> {
+    storage.set_sealed_setting(&pending_transcript_key(session_id), text)
+}
+
+pub fn pending_transcript(storage: &SqliteStorage, session_id: &str) -> CoreResult<Option<String>> {
+    storage.get_sealed_setting(&pending_transcript_key(session_id))
+}
+
+pub fn clear_pending_transcript(storage: &SqliteStorage, session_id: &str) -> CoreResult<()> {
+    storage.delete_setting(&pending_transcript_key(session_id))
+}